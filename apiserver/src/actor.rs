@@ -5,25 +5,93 @@
 // GNU General Public License version 2 or any later version.
 
 use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
 
-use actix::{Actor, Addr, Context, Handler, Message, Syn};
+use actix::{Actor, Addr, Context, Handler, Message, ResponseFuture, Syn};
 use actix::dev::Request;
+use actix_web::HttpResponse;
+use bytes::Bytes;
 use failure::{err_msg, Error, Result};
-use futures::{Future, IntoFuture};
+use futures::{future, Future, Stream};
+use futures::sync::mpsc;
+use futures_ext::{BoxFuture, FutureExt};
 use slog::Logger;
 
 use blobrepo::BlobRepo;
-use mercurial_types::RepositoryId;
+use mercurial_types::{Changeset as HgChangeset, HgChangesetId, HgNodeHash, MPath, RepoPath,
+                      RepositoryId};
+use mercurial_types::manifest::Content;
 use metaconfig::repoconfig::{RepoConfig, RepoConfigs};
 use metaconfig::repoconfig::RepoType::{BlobManifold, BlobRocks};
+use mononoke_types::Sha256;
+use repoinfo::RepoGenCache;
+use revset::Revset;
+use skiplist::SkiplistIndex;
+
+use errors::ErrorKind;
+use lfs::{BatchRequest, BatchResponse, Operation, ResponseObject, CONTENT_TYPE};
+use model::{Bookmark, Changeset, Entry, HistoryEntry};
+
+/// The largest number of changesets a revset query can return
+const MAX_REVSET_RESULTS: usize = 1000;
+const SKIPLIST_CACHE_SIZE: usize = 100 * 1024 * 1024;
 
 #[derive(Debug)]
 pub enum MononokeRepoQuery {
+    GetRawFile { changeset: String, path: String },
     GetBlobContent { hash: String },
+    ListDirectory { changeset: String, path: String },
+    GetChangeset { hash: String },
+    ListBookmarks,
+    GetFileHistory { path: String },
+    QueryRevset { revset: String },
+    /// `url` is where the LFS endpoints of the repo are served
+    LfsBatch { request: BatchRequest, url: String },
+    LfsDownload { oid: String },
+    /// `content` streams the body of the upload request
+    LfsUpload {
+        oid: String,
+        content: mpsc::Receiver<Result<Bytes>>,
+    },
 }
 
 impl Message for MononokeRepoQuery {
-    type Result = Result<String>;
+    type Result = Result<MononokeRepoResponse>;
+}
+
+pub enum MononokeRepoResponse {
+    GetRawFile { content: Bytes },
+    GetBlobContent { content: Bytes },
+    ListDirectory { files: Vec<Entry> },
+    GetChangeset { changeset: Changeset },
+    ListBookmarks { bookmarks: Vec<Bookmark> },
+    GetFileHistory { history: Vec<HistoryEntry> },
+    QueryRevset { changesets: Vec<String> },
+    LfsBatch { response: BatchResponse },
+    LfsDownload { content: Bytes },
+    LfsUpload,
+}
+
+impl MononokeRepoResponse {
+    pub fn into_http_response(self) -> HttpResponse {
+        use MononokeRepoResponse::*;
+
+        match self {
+            GetRawFile { content }
+            | GetBlobContent { content }
+            | LfsDownload { content } => HttpResponse::Ok()
+                .content_type("application/octet-stream")
+                .body(content),
+            ListDirectory { files } => HttpResponse::Ok().json(files),
+            GetChangeset { changeset } => HttpResponse::Ok().json(changeset),
+            ListBookmarks { bookmarks } => HttpResponse::Ok().json(bookmarks),
+            GetFileHistory { history } => HttpResponse::Ok().json(history),
+            QueryRevset { changesets } => HttpResponse::Ok().json(changesets),
+            LfsBatch { response } => HttpResponse::Ok().content_type(CONTENT_TYPE).json(response),
+            LfsUpload => HttpResponse::Ok().finish(),
+        }
+    }
 }
 
 pub struct MononokeQuery {
@@ -36,12 +104,15 @@ impl Message for MononokeQuery {
 }
 
 pub struct MononokeRepoActor {
-    pub repo: BlobRepo,
+    pub repo: Arc<BlobRepo>,
+    pub repo_generation: RepoGenCache,
+    pub skiplist_index: SkiplistIndex,
 }
 
 impl MononokeRepoActor {
     fn new(logger: Logger, config: RepoConfig) -> Result<Self> {
         let repoid = RepositoryId::new(config.repoid);
+        let repo_generation = RepoGenCache::new(config.generation_cache_size);
         let repo = match config.repotype {
             BlobRocks(ref path) => BlobRepo::new_rocksdb(logger, &path, repoid),
             BlobManifold {
@@ -69,7 +140,252 @@ impl MononokeRepoActor {
             _ => Err(err_msg("Unsupported repo type.")),
         };
 
-        repo.map(|repo| Self { repo })
+        repo.map(|repo| {
+            let repo = Arc::new(repo);
+            Self {
+                skiplist_index: SkiplistIndex::new(repo.clone(), SKIPLIST_CACHE_SIZE),
+                repo,
+                repo_generation,
+            }
+        })
+    }
+
+    fn get_raw_file(
+        &self,
+        changeset: String,
+        path: String,
+    ) -> BoxFuture<MononokeRepoResponse, Error> {
+        self.get_content(changeset, path.clone())
+            .and_then(move |content| match content {
+                Content::File(contents)
+                | Content::Executable(contents)
+                | Content::Symlink(contents) => Ok(MononokeRepoResponse::GetRawFile {
+                    content: contents.into_bytes(),
+                }),
+                Content::Tree(_) => {
+                    Err(ErrorKind::InvalidInput(format!("{} is a directory", path)).into())
+                }
+            })
+            .boxify()
+    }
+
+    fn get_blob_content(&self, hash: String) -> BoxFuture<MononokeRepoResponse, Error> {
+        let hash = try_boxfuture!(parse_hash(&hash));
+
+        self.repo
+            .get_file_content(&hash)
+            .map(|contents| MononokeRepoResponse::GetBlobContent {
+                content: contents.into_bytes(),
+            })
+            .boxify()
+    }
+
+    fn list_directory(
+        &self,
+        changeset: String,
+        path: String,
+    ) -> BoxFuture<MononokeRepoResponse, Error> {
+        self.get_content(changeset, path.clone())
+            .and_then(move |content| match content {
+                Content::Tree(manifest) => Ok(MononokeRepoResponse::ListDirectory {
+                    files: manifest.list().map(|entry| Entry::new(&*entry)).collect(),
+                }),
+                _ => Err(ErrorKind::InvalidInput(format!("{} is not a directory", path)).into()),
+            })
+            .boxify()
+    }
+
+    fn get_changeset(&self, hash: String) -> BoxFuture<MononokeRepoResponse, Error> {
+        let changesetid = try_boxfuture!(parse_changeset_id(&hash));
+
+        self.repo
+            .get_changeset_by_changesetid(&changesetid)
+            .map(move |changeset| MononokeRepoResponse::GetChangeset {
+                changeset: Changeset::new(changesetid, &changeset),
+            })
+            .boxify()
+    }
+
+    fn list_bookmarks(&self) -> BoxFuture<MononokeRepoResponse, Error> {
+        self.repo
+            .get_bookmarks()
+            .map(|(bookmark, changesetid)| Bookmark::new(bookmark, changesetid))
+            .collect()
+            .map(|bookmarks| MononokeRepoResponse::ListBookmarks { bookmarks })
+            .boxify()
+    }
+
+    fn get_file_history(&self, path: String) -> BoxFuture<MononokeRepoResponse, Error> {
+        let mpath = match try_boxfuture!(parse_path(&path)) {
+            Some(mpath) => mpath,
+            None => {
+                return future::err(ErrorKind::InvalidInput("path is required".into()).into())
+                    .boxify()
+            }
+        };
+
+        self.repo
+            .get_all_filenodes(RepoPath::FilePath(mpath))
+            .and_then(move |filenodes| {
+                if filenodes.is_empty() {
+                    Err(ErrorKind::NotFound(format!("history of {}", path)).into())
+                } else {
+                    Ok(MononokeRepoResponse::GetFileHistory {
+                        history: filenodes.into_iter().map(HistoryEntry::from).collect(),
+                    })
+                }
+            })
+            .boxify()
+    }
+
+    fn query_revset(&self, revset: String) -> BoxFuture<MononokeRepoResponse, Error> {
+        let revset = try_boxfuture!(Revset::parse(&revset));
+        if revset.scans_all_changesets() {
+            return future::err(
+                ErrorKind::InvalidInput(
+                    "not x and author(string) must be intersected with another set".to_string(),
+                ).into(),
+            ).boxify();
+        }
+
+        revset
+            .evaluate(&self.repo, self.repo_generation.clone(), &self.skiplist_index)
+            .map(|node| node.to_string())
+            .take(MAX_REVSET_RESULTS as u64 + 1)
+            .collect()
+            .and_then(|changesets| {
+                if changesets.len() > MAX_REVSET_RESULTS {
+                    Err(ErrorKind::InvalidInput(format!(
+                        "revset matches more than {} changesets",
+                        MAX_REVSET_RESULTS
+                    )).into())
+                } else {
+                    Ok(MononokeRepoResponse::QueryRevset { changesets })
+                }
+            })
+            .boxify()
+    }
+
+    /// Answers a Git-LFS batch request: objects that are stored can be downloaded, objects that
+    /// are not stored yet can be uploaded
+    fn lfs_batch(
+        &self,
+        request: BatchRequest,
+        url: String,
+    ) -> BoxFuture<MononokeRepoResponse, Error> {
+        let operation = request.operation;
+        let objects: Vec<_> = request
+            .objects
+            .into_iter()
+            .map(|object| {
+                let sha256 = match Sha256::from_str(&object.oid) {
+                    Ok(sha256) => sha256,
+                    Err(_) => {
+                        let message = format!("invalid oid {}", object.oid);
+                        return future::ok(ResponseObject::error(object, 422, message)).boxify();
+                    }
+                };
+                let href = format!("{}/{}/{}", url, operation_path(operation), sha256);
+
+                self.repo
+                    .lfs_content_exists(&sha256)
+                    .map(move |exists| match (operation, exists) {
+                        (Operation::Download, true) => ResponseObject::download(object, href),
+                        (Operation::Download, false) => {
+                            let message = format!("object {} not found", object.oid);
+                            ResponseObject::error(object, 404, message)
+                        }
+                        (Operation::Upload, true) => ResponseObject::no_action(object),
+                        (Operation::Upload, false) => ResponseObject::upload(object, href),
+                    })
+                    .boxify()
+            })
+            .collect();
+
+        future::join_all(objects)
+            .map(|objects| MononokeRepoResponse::LfsBatch {
+                response: BatchResponse::new(objects),
+            })
+            .boxify()
+    }
+
+    fn lfs_download(&self, oid: String) -> BoxFuture<MononokeRepoResponse, Error> {
+        let sha256 = try_boxfuture!(parse_oid(&oid));
+
+        self.repo
+            .get_lfs_content(&sha256)
+            .and_then(move |content| match content {
+                Some(content) => Ok(MononokeRepoResponse::LfsDownload { content }),
+                None => Err(ErrorKind::NotFound(format!("LFS object {}", oid)).into()),
+            })
+            .boxify()
+    }
+
+    /// Stores the contents `content` streams, which must hash to `oid`
+    fn lfs_upload(
+        &self,
+        oid: String,
+        content: mpsc::Receiver<Result<Bytes>>,
+    ) -> BoxFuture<MononokeRepoResponse, Error> {
+        let sha256 = try_boxfuture!(parse_oid(&oid));
+        let content = content
+            .map_err(|()| err_msg("LFS upload request went away"))
+            .and_then(|chunk| chunk);
+
+        self.repo
+            .upload_lfs_content_stream(sha256, content)
+            .map(|_| MononokeRepoResponse::LfsUpload)
+            .boxify()
+    }
+
+    /// Looks up the file or directory at `path` in the manifest of `changeset`
+    fn get_content(&self, changeset: String, path: String) -> BoxFuture<Content, Error> {
+        let changesetid = try_boxfuture!(parse_changeset_id(&changeset));
+        let mpath = try_boxfuture!(parse_path(&path));
+        let repo = self.repo.clone();
+
+        self.repo
+            .get_changeset_by_changesetid(&changesetid)
+            .and_then(move |changeset| {
+                repo.find_path_in_manifest(mpath, changeset.manifestid().into_nodehash())
+            })
+            .and_then(move |content| {
+                content.ok_or_else(|| ErrorKind::NotFound(format!("path {}", path)).into())
+            })
+            .boxify()
+    }
+}
+
+fn parse_hash(hash: &str) -> Result<HgNodeHash> {
+    HgNodeHash::from_str(hash)
+        .map_err(|_| ErrorKind::InvalidInput(format!("invalid hash {}", hash)).into())
+}
+
+fn parse_changeset_id(hash: &str) -> Result<HgChangesetId> {
+    parse_hash(hash).map(HgChangesetId::new)
+}
+
+fn parse_oid(oid: &str) -> Result<Sha256> {
+    Sha256::from_str(oid)
+        .map_err(|_| ErrorKind::InvalidInput(format!("invalid oid {}", oid)).into())
+}
+
+/// The path of the endpoint for `operation`, under the LFS endpoints of the repo
+fn operation_path(operation: Operation) -> &'static str {
+    match operation {
+        Operation::Download => "download",
+        Operation::Upload => "upload",
+    }
+}
+
+/// An empty path refers to the root directory
+fn parse_path(path: &str) -> Result<Option<MPath>> {
+    if path.is_empty() {
+        Ok(None)
+    } else {
+        MPath::new(path)
+            .map(Some)
+            .map_err(|_| ErrorKind::InvalidInput(format!("invalid path {}", path)).into())
     }
 }
 
@@ -78,14 +394,23 @@ impl Actor for MononokeRepoActor {
 }
 
 impl Handler<MononokeRepoQuery> for MononokeRepoActor {
-    type Result = Result<String>;
+    type Result = ResponseFuture<MononokeRepoResponse, Error>;
 
     fn handle(&mut self, msg: MononokeRepoQuery, _ctx: &mut Context<Self>) -> Self::Result {
         use MononokeRepoQuery::*;
 
-        match msg {
-            GetBlobContent { hash: _hash } => Ok("success!".to_string()),
-        }
+        Box::new(match msg {
+            GetRawFile { changeset, path } => self.get_raw_file(changeset, path),
+            GetBlobContent { hash } => self.get_blob_content(hash),
+            ListDirectory { changeset, path } => self.list_directory(changeset, path),
+            GetChangeset { hash } => self.get_changeset(hash),
+            ListBookmarks => self.list_bookmarks(),
+            GetFileHistory { path } => self.get_file_history(path),
+            QueryRevset { revset } => self.query_revset(revset),
+            LfsBatch { request, url } => self.lfs_batch(request, url),
+            LfsDownload { oid } => self.lfs_download(oid),
+            LfsUpload { oid, content } => self.lfs_upload(oid, content),
+        })
     }
 }
 
@@ -124,19 +449,18 @@ impl Handler<MononokeQuery> for MononokeActor {
     fn handle(&mut self, msg: MononokeQuery, _ctx: &mut Context<Self>) -> Self::Result {
         match self.repos.get(&msg.repo) {
             Some(repo) => Ok(repo.send(msg.kind)),
-            None => Err(err_msg("repo not found!")),
+            None => Err(ErrorKind::NotFound(format!("repo {}", msg.repo)).into()),
         }
     }
 }
 
 pub fn unwrap_request(
     request: Request<Syn, MononokeActor, MononokeQuery>,
-) -> impl Future<Item = String, Error = Error> {
-    request.map_err(From::from).and_then(|result| {
-        result.map_err(From::from).into_future().and_then(|result| {
-            result
-                .map_err(From::from)
-                .and_then(|result| result.map_err(From::from).into_future())
-        })
-    })
+) -> impl Future<Item = MononokeRepoResponse, Error = ErrorKind> {
+    request
+        .map_err(Error::from)
+        .and_then(|result| result)
+        .and_then(|request| request.map_err(Error::from))
+        .and_then(|result| result)
+        .map_err(ErrorKind::from)
 }
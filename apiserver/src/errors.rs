@@ -0,0 +1,83 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use actix_web::HttpResponse;
+use actix_web::error::ResponseError;
+use actix_web::http::StatusCode;
+use failure::Error;
+
+use blobrepo::ErrorKind as BlobRepoError;
+use revset::ErrorKind as RevsetError;
+
+#[derive(Debug, Fail)]
+pub enum ErrorKind {
+    #[fail(display = "{} not found", _0)]
+    NotFound(String),
+    #[fail(display = "invalid input: {}", _0)]
+    InvalidInput(String),
+    #[fail(display = "internal server error: {}", _0)]
+    InternalError(Error),
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+impl ResponseError for ErrorKind {
+    fn error_response(&self) -> HttpResponse {
+        let status = match *self {
+            ErrorKind::NotFound(_) => StatusCode::NOT_FOUND,
+            ErrorKind::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            ErrorKind::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        HttpResponse::build(status).json(ErrorResponse {
+            message: self.to_string(),
+        })
+    }
+}
+
+impl From<Error> for ErrorKind {
+    fn from(e: Error) -> ErrorKind {
+        let e = match e.downcast::<ErrorKind>() {
+            Ok(e) => return e,
+            Err(e) => e,
+        };
+
+        let e = match e.downcast::<RevsetError>() {
+            Ok(RevsetError::RevsetParseError(msg)) => return ErrorKind::InvalidInput(msg),
+            Ok(RevsetError::UnknownRevision(rev)) => {
+                return ErrorKind::NotFound(format!("revision {}", rev))
+            }
+            Ok(RevsetError::AmbiguousRevision(rev)) => {
+                return ErrorKind::InvalidInput(format!("ambiguous revision {}", rev))
+            }
+            Ok(RevsetError::UnknownBookmark(name)) => {
+                return ErrorKind::NotFound(format!("bookmark {}", name))
+            }
+            Ok(e) => return ErrorKind::InternalError(e.into()),
+            Err(e) => e,
+        };
+
+        match e.downcast::<BlobRepoError>() {
+            Ok(BlobRepoError::ChangesetMissing(id)) => {
+                ErrorKind::NotFound(format!("changeset {}", id))
+            }
+            Ok(BlobRepoError::ManifestMissing(id)) => {
+                ErrorKind::NotFound(format!("manifest {}", id))
+            }
+            Ok(BlobRepoError::HgContentMissing(id, _)) => {
+                ErrorKind::NotFound(format!("blob {}", id))
+            }
+            Ok(BlobRepoError::LfsHashMismatch(oid, _)) => {
+                ErrorKind::InvalidInput(format!("content doesn't match oid {}", oid))
+            }
+            Ok(e) => ErrorKind::InternalError(e.into()),
+            Err(e) => ErrorKind::InternalError(e),
+        }
+    }
+}
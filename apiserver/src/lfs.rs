@@ -0,0 +1,122 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! The JSON representations of the Git-LFS batch API.
+//!
+//! See https://github.com/git-lfs/git-lfs/blob/master/docs/api/batch.md for the protocol. Only
+//! the `basic` transfer adapter is supported, whose actions point at the download and upload
+//! endpoints of the API server.
+
+/// The content type of the requests and the responses of the batch API
+pub const CONTENT_TYPE: &str = "application/vnd.git-lfs+json";
+
+/// The only transfer adapter that is supported
+const BASIC_TRANSFER: &str = "basic";
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Download,
+    Upload,
+}
+
+/// A batch request. The transfer adapters listed by the client are ignored, as `basic` is
+/// always used.
+#[derive(Debug, Deserialize)]
+pub struct BatchRequest {
+    pub operation: Operation,
+    pub objects: Vec<RequestObject>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RequestObject {
+    pub oid: String,
+    pub size: u64,
+}
+
+#[derive(Serialize)]
+pub struct BatchResponse {
+    pub transfer: &'static str,
+    pub objects: Vec<ResponseObject>,
+}
+
+impl BatchResponse {
+    pub fn new(objects: Vec<ResponseObject>) -> Self {
+        BatchResponse {
+            transfer: BASIC_TRANSFER,
+            objects,
+        }
+    }
+}
+
+/// The answer for a single object. An object without actions and without an error needs no
+/// transfer, e.g. because it was uploaded before.
+#[derive(Serialize)]
+pub struct ResponseObject {
+    pub oid: String,
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actions: Option<Actions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ObjectError>,
+}
+
+impl ResponseObject {
+    pub fn no_action(object: RequestObject) -> Self {
+        ResponseObject {
+            oid: object.oid,
+            size: object.size,
+            actions: None,
+            error: None,
+        }
+    }
+
+    pub fn download(object: RequestObject, href: String) -> Self {
+        ResponseObject {
+            actions: Some(Actions {
+                download: Some(Action { href }),
+                upload: None,
+            }),
+            ..Self::no_action(object)
+        }
+    }
+
+    pub fn upload(object: RequestObject, href: String) -> Self {
+        ResponseObject {
+            actions: Some(Actions {
+                download: None,
+                upload: Some(Action { href }),
+            }),
+            ..Self::no_action(object)
+        }
+    }
+
+    pub fn error(object: RequestObject, code: u16, message: String) -> Self {
+        ResponseObject {
+            error: Some(ObjectError { code, message }),
+            ..Self::no_action(object)
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Actions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download: Option<Action>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload: Option<Action>,
+}
+
+#[derive(Serialize)]
+pub struct Action {
+    pub href: String,
+}
+
+#[derive(Serialize)]
+pub struct ObjectError {
+    pub code: u16,
+    pub message: String,
+}
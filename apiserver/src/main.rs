@@ -10,11 +10,23 @@ extern crate actix;
 extern crate actix_web;
 extern crate blobrepo;
 extern crate bookmarks;
+extern crate bytes;
 extern crate clap;
+#[macro_use]
 extern crate failure_ext as failure;
+extern crate filenodes;
 extern crate futures;
+#[macro_use]
+extern crate futures_ext;
 extern crate mercurial_types;
 extern crate metaconfig;
+extern crate mononoke_types;
+extern crate repoinfo;
+extern crate revset;
+extern crate skiplist;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 #[macro_use]
 extern crate slog;
 extern crate slog_glog_fmt;
@@ -24,19 +36,22 @@ extern crate slog_term;
 extern crate time_ext;
 
 mod actor;
+mod errors;
+mod lfs;
 mod middleware;
+mod model;
 
 use std::path::Path;
 use std::str::FromStr;
 
 use actix::{Actor, Addr, Syn};
-use actix_web::{http, server, App, HttpRequest, HttpResponse};
-use actix_web::error::ResponseError;
+use actix_web::{http, server, App, HttpMessage, HttpRequest, HttpResponse};
 use blobrepo::BlobRepo;
 use bookmarks::Bookmark;
 use clap::Arg;
-use failure::{err_msg, Result};
-use futures::Future;
+use failure::{err_msg, Error, Result};
+use futures::{Future, Sink, Stream};
+use futures::sync::mpsc;
 use slog::{Drain, Level, Logger};
 use slog_glog_fmt::{kv_categorizer, kv_defaults, GlogFormat};
 use slog_logview::LogViewDrain;
@@ -45,38 +60,164 @@ use mercurial_types::RepositoryId;
 use mercurial_types::nodehash::HgChangesetId;
 use metaconfig::RepoConfigs;
 
-use actor::{unwrap_request, MononokeActor, MononokeQuery, MononokeRepoQuery};
+use actor::{unwrap_request, MononokeActor, MononokeQuery, MononokeRepoQuery,
+            MononokeRepoResponse};
+use errors::ErrorKind;
+use lfs::BatchRequest;
+
+/// The largest LFS object that can be uploaded in one request
+const MAX_LFS_OBJECT_SIZE: usize = 1024 * 1024 * 1024;
+/// How many chunks of an LFS upload are buffered while the previous ones are stored
+const LFS_UPLOAD_BUFFER: usize = 16;
 
 mod parameters {
     pub const REPO: &str = "repo";
     pub const HASH: &str = "hash";
+    pub const CHANGESET: &str = "changeset";
+    pub const PATH: &str = "path";
+    pub const REVSET: &str = "revset";
+    pub const OID: &str = "oid";
+}
+
+fn get_param(req: &HttpRequest<HttpServerState>, name: &str) -> String {
+    req.match_info()
+        .get(name)
+        .unwrap_or_else(|| panic!("{} is required", name))
+        .to_string()
+}
+
+fn query_repo(
+    req: &HttpRequest<HttpServerState>,
+    kind: MononokeRepoQuery,
+) -> impl Future<Item = HttpResponse, Error = ErrorKind> {
+    let request = req.state().mononoke.send(MononokeQuery {
+        repo: get_param(req, parameters::REPO),
+        kind,
+    });
+
+    unwrap_request(request).map(MononokeRepoResponse::into_http_response)
+}
+
+fn get_raw_file(
+    req: HttpRequest<HttpServerState>,
+) -> impl Future<Item = HttpResponse, Error = ErrorKind> {
+    let kind = MononokeRepoQuery::GetRawFile {
+        changeset: get_param(&req, parameters::CHANGESET),
+        path: get_param(&req, parameters::PATH),
+    };
+    query_repo(&req, kind)
 }
 
 fn get_blob_content(
     req: HttpRequest<HttpServerState>,
-) -> impl Future<Item = HttpResponse, Error = impl ResponseError> {
-    let matches = req.match_info();
-    let repo = matches
-        .get(parameters::REPO)
-        .expect("repo is required")
-        .to_string();
-    let hash = matches
-        .get(parameters::HASH)
-        .expect("hash is required")
-        .to_string();
-
-    let state = req.state();
-
-    let mononoke = &state.mononoke;
-
-    let request = mononoke.send(MononokeQuery {
-        repo: repo,
-        kind: MononokeRepoQuery::GetBlobContent { hash: hash },
-    });
+) -> impl Future<Item = HttpResponse, Error = ErrorKind> {
+    let kind = MononokeRepoQuery::GetBlobContent {
+        hash: get_param(&req, parameters::HASH),
+    };
+    query_repo(&req, kind)
+}
+
+fn list_directory(
+    req: HttpRequest<HttpServerState>,
+) -> impl Future<Item = HttpResponse, Error = ErrorKind> {
+    let kind = MononokeRepoQuery::ListDirectory {
+        changeset: get_param(&req, parameters::CHANGESET),
+        path: get_param(&req, parameters::PATH),
+    };
+    query_repo(&req, kind)
+}
 
-    unwrap_request(request)
-        .map(|response| HttpResponse::Ok().content_type("text/plain").body(response))
-        .map_err(|e| e.compat())
+fn get_changeset(
+    req: HttpRequest<HttpServerState>,
+) -> impl Future<Item = HttpResponse, Error = ErrorKind> {
+    let kind = MononokeRepoQuery::GetChangeset {
+        hash: get_param(&req, parameters::HASH),
+    };
+    query_repo(&req, kind)
+}
+
+fn list_bookmarks(
+    req: HttpRequest<HttpServerState>,
+) -> impl Future<Item = HttpResponse, Error = ErrorKind> {
+    query_repo(&req, MononokeRepoQuery::ListBookmarks)
+}
+
+fn get_file_history(
+    req: HttpRequest<HttpServerState>,
+) -> impl Future<Item = HttpResponse, Error = ErrorKind> {
+    let kind = MononokeRepoQuery::GetFileHistory {
+        path: get_param(&req, parameters::PATH),
+    };
+    query_repo(&req, kind)
+}
+
+fn query_revset(
+    req: HttpRequest<HttpServerState>,
+) -> impl Future<Item = HttpResponse, Error = ErrorKind> {
+    let kind = MononokeRepoQuery::QueryRevset {
+        revset: get_param(&req, parameters::REVSET),
+    };
+    query_repo(&req, kind)
+}
+
+fn lfs_batch(
+    req: HttpRequest<HttpServerState>,
+) -> impl Future<Item = HttpResponse, Error = ErrorKind> {
+    let url = {
+        let info = req.connection_info();
+        format!(
+            "{}://{}/{}/lfs",
+            info.scheme(),
+            info.host(),
+            get_param(&req, parameters::REPO)
+        )
+    };
+
+    req.clone()
+        .json::<BatchRequest>()
+        .map_err(|err| ErrorKind::InvalidInput(err.to_string()))
+        .and_then(move |request| query_repo(&req, MononokeRepoQuery::LfsBatch { request, url }))
+}
+
+fn lfs_download(
+    req: HttpRequest<HttpServerState>,
+) -> impl Future<Item = HttpResponse, Error = ErrorKind> {
+    let kind = MononokeRepoQuery::LfsDownload {
+        oid: get_param(&req, parameters::OID),
+    };
+    query_repo(&req, kind)
+}
+
+fn lfs_upload(
+    req: HttpRequest<HttpServerState>,
+) -> impl Future<Item = HttpResponse, Error = ErrorKind> {
+    let oid = get_param(&req, parameters::OID);
+    let (sender, content) = mpsc::channel(LFS_UPLOAD_BUFFER);
+
+    // The body is passed on to the repo actor as it arrives, so that it is stored without being
+    // held in memory. Errors are passed on too, and fail the upload.
+    let mut size = 0;
+    let body = req.clone()
+        .map_err(|err| ErrorKind::InvalidInput(err.to_string()))
+        .and_then(move |chunk| {
+            size += chunk.len();
+            if size > MAX_LFS_OBJECT_SIZE {
+                Err(ErrorKind::InvalidInput(format!(
+                    "LFS objects are limited to {} bytes",
+                    MAX_LFS_OBJECT_SIZE
+                )))
+            } else {
+                Ok(chunk)
+            }
+        })
+        .then(|chunk| Ok::<_, ()>(chunk.map_err(Error::from)))
+        .forward(sender.sink_map_err(|_| ()))
+        // The upload failed if the actor stopped reading the body, and it reports why
+        .then(|_| Ok::<_, ErrorKind>(()));
+
+    query_repo(&req, MononokeRepoQuery::LfsUpload { oid, content })
+        .join(body)
+        .map(|(response, ())| response)
 }
 
 fn setup_logger(debug: bool) -> Logger {
@@ -159,23 +300,30 @@ fn main() -> Result<()> {
             Arg::with_name("config-path")
                 .long("config-path")
                 .value_name("PATH")
-                .required(true)
+                .required_unless("config-dir")
                 .help("directory of the config repository"),
         )
         .arg(
             Arg::with_name("config-bookmark")
                 .long("config-bookmark")
                 .value_name("BOOKMARK")
-                .required_unless("config-commit")
+                .required_unless_one(&["config-commit", "config-dir"])
                 .help("bookmark of the config repository"),
         )
         .arg(
             Arg::with_name("config-commit")
                 .long("config-commit")
                 .value_name("HASH")
-                .required_unless("config-bookmark")
+                .required_unless_one(&["config-bookmark", "config-dir"])
                 .help("commit hash of the config repository"),
         )
+        .arg(
+            Arg::with_name("config-dir")
+                .long("config-dir")
+                .value_name("PATH")
+                .conflicts_with("config-path")
+                .help("directory laid out like the config repository, used instead of it"),
+        )
         .get_matches();
 
     let host = matches.value_of("http-host").unwrap_or("127.0.0.1");
@@ -187,14 +335,17 @@ fn main() -> Result<()> {
 
     let sys = actix::System::new("mononoke-apiserver");
 
-    let repo_configs = create_config(
-        &root_logger,
-        matches
-            .value_of("config-path")
-            .expect("must set config-path"),
-        matches.value_of("config-bookmark"),
-        matches.value_of("config-commit"),
-    )?;
+    let repo_configs = match matches.value_of("config-dir") {
+        Some(config_dir) => RepoConfigs::read_config_dir(config_dir).wait()?,
+        None => create_config(
+            &root_logger,
+            matches
+                .value_of("config-path")
+                .expect("must set config-path"),
+            matches.value_of("config-bookmark"),
+            matches.value_of("config-commit"),
+        )?,
+    };
 
     let addr =
         MononokeActor::create(move |_| MononokeActor::new(mononoke_logger.clone(), repo_configs));
@@ -207,9 +358,36 @@ fn main() -> Result<()> {
         App::with_state(state.clone())
             .middleware(middleware::SLogger::new(actix_logger.clone()))
             .scope("/{repo}", |repo| {
-                repo.resource("/blob/{hash}", |r| {
-                    r.method(http::Method::GET).a(get_blob_content)
-                })
+                repo.resource("/raw/{changeset}/{path:.*}", |r| {
+                        r.method(http::Method::GET).a(get_raw_file)
+                    })
+                    .resource("/blob/{hash}", |r| {
+                        r.method(http::Method::GET).a(get_blob_content)
+                    })
+                    .resource("/tree/{changeset}/{path:.*}", |r| {
+                        r.method(http::Method::GET).a(list_directory)
+                    })
+                    .resource("/changeset/{hash}", |r| {
+                        r.method(http::Method::GET).a(get_changeset)
+                    })
+                    .resource("/bookmarks", |r| {
+                        r.method(http::Method::GET).a(list_bookmarks)
+                    })
+                    .resource("/history/{path:.*}", |r| {
+                        r.method(http::Method::GET).a(get_file_history)
+                    })
+                    .resource("/revset/{revset:.*}", |r| {
+                        r.method(http::Method::GET).a(query_revset)
+                    })
+                    .resource("/lfs/objects/batch", |r| {
+                        r.method(http::Method::POST).a(lfs_batch)
+                    })
+                    .resource("/lfs/download/{oid}", |r| {
+                        r.method(http::Method::GET).a(lfs_download)
+                    })
+                    .resource("/lfs/upload/{oid}", |r| {
+                        r.method(http::Method::PUT).a(lfs_upload)
+                    })
             })
     }).bind(format!("{}:{}", host, port))?;
     let address = server.addrs()[0];
@@ -0,0 +1,121 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! The JSON representations of the objects served by the API server.
+
+use blobrepo::BlobChangeset;
+use bookmarks::Bookmark as RepoBookmark;
+use filenodes::FilenodeInfo;
+use mercurial_types::{Changeset as HgChangeset, Entry as HgEntry, FileType, HgChangesetId, Type};
+
+#[derive(Serialize)]
+pub struct Entry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ttype: &'static str,
+    pub hash: String,
+}
+
+impl Entry {
+    pub fn new(entry: &HgEntry) -> Entry {
+        let name = entry
+            .get_name()
+            .map(|name| String::from_utf8_lossy(name.as_bytes()).into_owned())
+            .unwrap_or_default();
+        let ttype = match entry.get_type() {
+            Type::Tree => "tree",
+            Type::File(FileType::Regular) => "file",
+            Type::File(FileType::Executable) => "executable",
+            Type::File(FileType::Symlink) => "symlink",
+        };
+
+        Entry {
+            name,
+            ttype,
+            hash: entry.get_hash().to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Changeset {
+    pub hash: String,
+    pub parents: Vec<String>,
+    pub manifest: String,
+    pub author: String,
+    pub timestamp: i64,
+    pub tz_offset: i32,
+    pub comment: String,
+    pub files: Vec<String>,
+}
+
+impl Changeset {
+    pub fn new(hash: HgChangesetId, changeset: &BlobChangeset) -> Changeset {
+        Changeset {
+            hash: hash.to_string(),
+            parents: changeset
+                .parents()
+                .into_iter()
+                .map(|parent| parent.to_string())
+                .collect(),
+            manifest: changeset.manifestid().to_string(),
+            author: String::from_utf8_lossy(changeset.user()).into_owned(),
+            timestamp: changeset.time().timestamp_secs(),
+            tz_offset: changeset.time().tz_offset_secs(),
+            comment: String::from_utf8_lossy(changeset.comments()).into_owned(),
+            files: changeset
+                .files()
+                .iter()
+                .map(|path| path.to_string())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Bookmark {
+    pub name: String,
+    pub changeset: String,
+}
+
+impl Bookmark {
+    pub fn new(bookmark: RepoBookmark, changeset: HgChangesetId) -> Bookmark {
+        Bookmark {
+            name: bookmark.to_string(),
+            changeset: changeset.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct CopyFrom {
+    pub path: String,
+    pub filenode: String,
+}
+
+#[derive(Serialize)]
+pub struct HistoryEntry {
+    pub filenode: String,
+    pub p1: Option<String>,
+    pub p2: Option<String>,
+    pub linknode: String,
+    pub copyfrom: Option<CopyFrom>,
+}
+
+impl From<FilenodeInfo> for HistoryEntry {
+    fn from(info: FilenodeInfo) -> HistoryEntry {
+        HistoryEntry {
+            filenode: info.filenode.to_string(),
+            p1: info.p1.map(|p| p.to_string()),
+            p2: info.p2.map(|p| p.to_string()),
+            linknode: info.linknode.to_string(),
+            copyfrom: info.copyfrom.map(|(path, filenode)| CopyFrom {
+                path: path.to_string(),
+                filenode: filenode.to_string(),
+            }),
+        }
+    }
+}
@@ -13,7 +13,7 @@ use bzip2::bufread::BzDecoder;
 use flate2::bufread::GzDecoder;
 use tokio_io::AsyncRead;
 
-use raw::RawDecoder;
+use raw::{RawDecoder, ZstdDecoder};
 
 pub struct Decompressor<'a, R>
 where
@@ -40,9 +40,7 @@ where
             inner: match dt {
                 DecompressorType::Bzip2 => Box::new(BzDecoder::new(r)),
                 DecompressorType::Gzip => Box::new(GzDecoder::new(r)),
-                // TODO: The zstd crate is not safe for decompressing Read input, because it is
-                // overconsuming it
-                DecompressorType::Zstd => unimplemented!(),
+                DecompressorType::Zstd => Box::new(ZstdDecoder::new(r)),
             },
         }
     }
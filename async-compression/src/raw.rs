@@ -17,6 +17,7 @@ use bzip2::write::BzEncoder;
 use flate2::bufread::GzDecoder;
 use flate2::write::GzEncoder;
 use zstd::Encoder as ZstdEncoder;
+use zstd::stream::raw::{Decoder as RawZstdDecoder, InBuffer, Operation, OutBuffer};
 
 pub trait RawDecoder<R: BufRead>: Read {
     fn get_ref(&self) -> &R;
@@ -58,6 +59,81 @@ impl<R: BufRead> RawDecoder<R> for GzDecoder<R> {
     }
 }
 
+/// A streaming zstd decoder that reads exactly one frame from the underlying reader.
+///
+/// The decoders provided by the zstd crate read ahead into their own buffer, so the data that
+/// follows the frame is lost. This one feeds the frame to zstd straight from the buffer of the
+/// underlying reader and only consumes the bytes that zstd actually used, so the reader is left
+/// positioned right after the end of the frame.
+pub struct ZstdDecoder<R: BufRead> {
+    inner: R,
+    decoder: RawZstdDecoder,
+    finished: bool,
+}
+
+impl<R: BufRead> ZstdDecoder<R> {
+    pub fn new(inner: R) -> Self {
+        ZstdDecoder {
+            inner,
+            // RawZstdDecoder::new() should only fail on OOM, see AsyncZstdEncoder::new
+            decoder: RawZstdDecoder::new().unwrap(),
+            finished: false,
+        }
+    }
+}
+
+impl<R: BufRead> Read for ZstdDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.finished || buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            let (consumed, written, remaining, eof) = {
+                let input = self.inner.fill_buf()?;
+                let mut src = InBuffer::around(input);
+                let mut dst = OutBuffer::around(&mut *buf);
+                // The return value is a hint of how much input is still needed, it's 0 once the
+                // frame is completely decoded and flushed
+                let remaining = self.decoder.run(&mut src, &mut dst)?;
+                (src.pos, dst.pos, remaining, input.is_empty())
+            };
+            self.inner.consume(consumed);
+
+            if remaining == 0 {
+                self.finished = true;
+                return Ok(written);
+            }
+            if written > 0 {
+                return Ok(written);
+            }
+            if eof {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "zstd stream ended in the middle of a frame",
+                ));
+            }
+        }
+    }
+}
+
+impl<R: BufRead> RawDecoder<R> for ZstdDecoder<R> {
+    #[inline]
+    fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    #[inline]
+    fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    #[inline]
+    fn into_inner(self: Box<Self>) -> R {
+        self.inner
+    }
+}
+
 pub trait RawEncoder<W>: AsyncWrite
 where
     W: AsyncWrite + Send,
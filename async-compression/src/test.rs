@@ -17,7 +17,7 @@ use tokio_io::io::read_to_end;
 use retry::retry_write;
 
 use compressor::{Compressor, CompressorType};
-use decompressor::Decompressor;
+use decompressor::{Decompressor, DecompressorType};
 use membuf::MemBuf;
 use metered::{MeteredRead, MeteredWrite};
 
@@ -30,6 +30,10 @@ quickcheck! {
         roundtrip(CompressorType::Gzip(cmprs.0), &input)
     }
 
+    fn test_zstd_roundtrip(cmprs: ZstdCompression, input: Vec<u8>) -> TestResult {
+        roundtrip(CompressorType::Zstd { level: cmprs.0 }, &input)
+    }
+
     fn test_bzip_overreading(
         cmprs: BzipCompression,
         compressable_input: Vec<u8>,
@@ -53,6 +57,18 @@ quickcheck! {
             extra_input.as_slice(),
         )
     }
+
+    fn test_zstd_overreading(
+        cmprs: ZstdCompression,
+        compressable_input: Vec<u8>,
+        extra_input: Vec<u8>
+    ) -> TestResult {
+        check_overreading(
+            CompressorType::Zstd { level: cmprs.0 },
+            compressable_input.as_slice(),
+            extra_input.as_slice(),
+        )
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -85,6 +101,14 @@ impl Arbitrary for GzipCompression {
     }
 }
 
+#[derive(Debug, Clone)]
+struct ZstdCompression(i32);
+impl Arbitrary for ZstdCompression {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        ZstdCompression(*g.choose(&[1, 3, 19]).unwrap())
+    }
+}
+
 fn roundtrip(ct: CompressorType, input: &[u8]) -> TestResult {
     let compressed_buf = MeteredWrite::new(Cursor::new(Vec::with_capacity(32 * 1024)));
     let mut compressor = MeteredWrite::new(Compressor::new(compressed_buf, ct));
@@ -229,3 +253,23 @@ fn check_overreading(c_type: CompressorType, data: &[u8], extra: &[u8]) -> TestR
 
     TestResult::passed()
 }
+
+#[test]
+fn test_zstd_truncated() {
+    let mut compressor = Compressor::new(
+        Cursor::new(Vec::new()),
+        CompressorType::Zstd { level: 1 },
+    );
+    compressor.write_all(b"some data to compress").unwrap();
+    let mut compressed = compressor.try_finish().unwrap().into_inner();
+    let truncated_len = compressed.len() - 1;
+    compressed.truncate(truncated_len);
+
+    let mut decompressor = Decompressor::new(
+        BufReader::new(Cursor::new(compressed)),
+        DecompressorType::Zstd,
+    );
+    let mut buf = Vec::new();
+    let err = decompressor.read_to_end(&mut buf).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
@@ -146,6 +146,23 @@ impl BlobChangeset {
         self.changesetid
     }
 
+    /// Parse a changeset out of the envelope it is stored in. The changeset ID is taken from
+    /// the envelope as is; use `compute_hash` to check it.
+    pub fn from_envelope(envelope: HgChangesetEnvelope) -> Result<Self> {
+        let changesetid = HgChangesetId::new(*envelope.node_id());
+        let revlogcs = RevlogChangeset::from_envelope(envelope)?;
+        Ok(Self::new_with_id(
+            &changesetid,
+            ChangesetContent::from_revlogcs(revlogcs),
+        ))
+    }
+
+    /// Recompute the changeset hash from its contents. For a changeset loaded from the
+    /// blobstore this should match `get_changeset_id`.
+    pub fn compute_hash(&self) -> Result<HgChangesetId> {
+        self.content.compute_hash()
+    }
+
     pub fn load(
         blobstore: &RepoBlobstore,
         changesetid: &HgChangesetId,
@@ -172,12 +189,7 @@ impl BlobChangeset {
                                 envelope.node_id()
                             );
                         }
-                        let revlogcs = RevlogChangeset::from_envelope(envelope)?;
-                        let cs = BlobChangeset::new_with_id(
-                            &changesetid,
-                            ChangesetContent::from_revlogcs(revlogcs),
-                        );
-                        Ok(Some(cs))
+                        Ok(Some(BlobChangeset::from_envelope(envelope)?))
                     }
                 })
                 .with_context(|_| ErrorKind::ChangesetDeserializeFailed(key))
@@ -0,0 +1,108 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Contents of huge files, which are stored as a list of chunks
+//!
+//! The contents of files larger than the chunking threshold of a repo are split into chunks,
+//! each of which is stored as a separate blob. The file has no content blob, and its
+//! `ExternalContents` only hold the index of the chunks, so the contents can be streamed without
+//! holding all of them in memory.
+
+use bytes::{Bytes, BytesMut};
+use futures::future::Future;
+use futures::stream::{self, Stream};
+use futures_ext::{BoxStream, StreamExt};
+
+use blobstore::Blobstore;
+use mononoke_types::{BlobstoreValue, ChunkPointer, ChunkedContents, ContentChunk, ContentChunkId,
+                     MononokeId};
+
+use errors::*;
+use repo::RepoBlobstore;
+
+/// How many chunks are fetched ahead of the one that is being read
+const MAX_CONCURRENT_CHUNK_FETCHES: usize = 4;
+/// How many chunks of a file are uploaded at once
+pub const MAX_CONCURRENT_CHUNK_UPLOADS: usize = 4;
+
+/// Fetch the chunk `pointer` points to from `blobstore_key`. That's the key of its id, except
+/// for the chunks of LFS contents.
+pub fn fetch_chunk(
+    blobstore: &RepoBlobstore,
+    blobstore_key: String,
+    pointer: ChunkPointer,
+) -> impl Future<Item = Bytes, Error = Error> {
+    let chunk_id = *pointer.chunk_id();
+    blobstore
+        .get(blobstore_key.clone())
+        .context("While fetching content chunk")
+        .map_err(Error::from)
+        .and_then(move |bytes| {
+            let blobstore_bytes = match bytes {
+                Some(bytes) => bytes,
+                None => bail_err!(ErrorKind::ContentChunkMissing(chunk_id)),
+            };
+            let chunk = ContentChunk::from_blob(blobstore_bytes.into())?.into_bytes();
+            let size = chunk.len() as u64;
+            if size != pointer.size() {
+                bail_err!(ErrorKind::ContentChunkSizeMismatch(
+                    chunk_id,
+                    size,
+                    pointer.size()
+                ));
+            }
+            Ok(chunk)
+        })
+        .with_context(move |_| format!("While fetching content chunk {}", blobstore_key))
+        .from_err()
+}
+
+/// Stream the chunks of `chunked` in order, fetching only a few of them ahead
+pub fn stream_chunked_contents(
+    blobstore: &RepoBlobstore,
+    chunked: ChunkedContents,
+) -> BoxStream<Bytes, Error> {
+    stream_chunks(blobstore, chunked, |chunk_id| chunk_id.blobstore_key())
+}
+
+/// Stream the chunks of `chunked` in order from the keys `chunk_key` gives for their ids,
+/// fetching only a few of them ahead
+pub fn stream_chunks<K>(
+    blobstore: &RepoBlobstore,
+    chunked: ChunkedContents,
+    chunk_key: K,
+) -> BoxStream<Bytes, Error>
+where
+    K: Fn(&ContentChunkId) -> String + Send + 'static,
+{
+    let blobstore = blobstore.clone();
+    stream::iter_ok(chunked.into_chunks())
+        .map(move |pointer| fetch_chunk(&blobstore, chunk_key(pointer.chunk_id()), pointer))
+        .buffered(MAX_CONCURRENT_CHUNK_FETCHES)
+        .boxify()
+}
+
+/// Fetch all the chunks of `chunked` and concatenate them
+pub fn fetch_chunked_contents(
+    blobstore: &RepoBlobstore,
+    chunked: ChunkedContents,
+) -> impl Future<Item = Bytes, Error = Error> {
+    let size = chunked.size();
+    concat_chunks(stream_chunked_contents(blobstore, chunked), size)
+}
+
+/// Concatenate the stream of the `size` bytes of some contents
+pub fn concat_chunks<S>(chunks: S, size: u64) -> impl Future<Item = Bytes, Error = Error>
+where
+    S: Stream<Item = Bytes, Error = Error>,
+{
+    chunks
+        .fold(BytesMut::with_capacity(size as usize), |mut contents, chunk| {
+            contents.extend_from_slice(&chunk);
+            Ok::<_, Error>(contents)
+        })
+        .map(BytesMut::freeze)
+}
@@ -13,7 +13,7 @@ pub use failure::prelude::*;
 
 use mercurial_types::{HgBlob, HgBlobHash, HgChangesetId, HgFileNodeId, HgNodeHash, HgParents,
                       MPath, RepoPath, Type};
-use mononoke_types::ContentId;
+use mononoke_types::{ChangesetId, ContentChunkId, ContentId, Sha256};
 
 use BlobChangeset;
 
@@ -21,9 +21,12 @@ use BlobChangeset;
 pub enum StateOpenError {
     Heads,
     Bookmarks,
+    ScratchBookmarks,
     Blobstore,
     Changesets,
     Filenodes,
+    BonsaiHgMapping,
+    LfsBlobstore,
 }
 
 impl fmt::Display for StateOpenError {
@@ -33,9 +36,12 @@ impl fmt::Display for StateOpenError {
         match *self {
             Heads => write!(f, "heads"),
             Bookmarks => write!(f, "bookmarks"),
+            ScratchBookmarks => write!(f, "scratch bookmarks"),
             Blobstore => write!(f, "blob store"),
             Changesets => write!(f, "changesets"),
             Filenodes => write!(f, "filenodes"),
+            BonsaiHgMapping => write!(f, "bonsai_hg_mapping"),
+            LfsBlobstore => write!(f, "LFS blob store"),
         }
     }
 }
@@ -95,4 +101,18 @@ pub enum ErrorKind {
     #[fail(display = "Path not found: {}", _0)] PathNotFound(MPath),
     #[fail(display = "Remove called on non-directory")] NotADirectory,
     #[fail(display = "Empty file path")] EmptyFilePath,
+    #[fail(display = "Bonsai changeset not found for hg changeset {}", _0)]
+    BonsaiMappingNotFound(HgChangesetId),
+    #[fail(display = "Hg changeset not found for bonsai changeset {}", _0)]
+    HgMappingNotFound(ChangesetId),
+    #[fail(display = "Copy source {} not found in bonsai changeset {}", _0, _1)]
+    CopySourceNotFound(MPath, ChangesetId),
+    #[fail(display = "LFS content missing for sha256: {}", _0)] LfsContentMissing(Sha256),
+    #[fail(display = "LFS content {} has size {}, expected {}", _0, _1, _2)]
+    LfsSizeMismatch(Sha256, u64, u64),
+    #[fail(display = "LFS content uploaded as {} has sha256 {}", _0, _1)]
+    LfsHashMismatch(Sha256, Sha256),
+    #[fail(display = "Content chunk missing for id: {}", _0)] ContentChunkMissing(ContentChunkId),
+    #[fail(display = "Content chunk {} has size {}, expected {}", _0, _1, _2)]
+    ContentChunkSizeMismatch(ContentChunkId, u64, u64),
 }
@@ -0,0 +1,51 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Where contents that have no content blob are stored
+//!
+//! Contents that are stored in the LFS blobstore or as chunks are still identified by the
+//! `ContentId` of their bytes, but have no content blob. Instead, their `ExternalContents` are
+//! stored under a key derived from that `ContentId`.
+
+use futures::future::Future;
+
+use blobstore::Blobstore;
+use mononoke_types::{ContentId, ExternalContents};
+
+use errors::*;
+use repo::RepoBlobstore;
+
+/// Fetch where the contents with id `content_id` are stored, or None if they have a content blob
+/// or are missing
+pub fn fetch_external_contents_opt(
+    blobstore: &RepoBlobstore,
+    content_id: ContentId,
+) -> impl Future<Item = Option<ExternalContents>, Error = Error> {
+    let blobstore_key = ExternalContents::blobstore_key(&content_id);
+    blobstore
+        .get(blobstore_key.clone())
+        .context("While fetching external contents")
+        .map_err(Error::from)
+        .and_then(|bytes| match bytes {
+            Some(bytes) => Ok(Some(ExternalContents::from_blobstore_bytes(&bytes)?)),
+            None => Ok(None),
+        })
+        .with_context(move |_| format!("While fetching external contents {}", blobstore_key))
+        .from_err()
+}
+
+/// Store where the contents with id `content_id` are stored. This must only be done once the
+/// contents themselves are stored, so that the contents never look present when they are not.
+pub fn store_external_contents(
+    blobstore: &RepoBlobstore,
+    content_id: ContentId,
+    external: ExternalContents,
+) -> impl Future<Item = (), Error = Error> {
+    blobstore.put(
+        ExternalContents::blobstore_key(&content_id),
+        external.into_blobstore_bytes(),
+    )
+}
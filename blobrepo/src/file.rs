@@ -6,20 +6,26 @@
 
 //! Plain files, symlinks
 
+use bytes::Bytes;
 use futures::future::{self, Future};
-use futures_ext::{BoxFuture, FutureExt};
+use futures::stream;
+use futures_ext::{BoxFuture, BoxStream, FutureExt, StreamExt};
 
 use mercurial::file;
 use mercurial_types::{FileType, HgBlob, HgFileEnvelope, HgFileNodeId, HgManifestId, HgNodeHash,
                       HgParents, MPath, MPathElement};
 use mercurial_types::manifest::{Content, Entry, Manifest, Type};
 use mercurial_types::nodehash::HgEntryId;
-use mononoke_types::{BlobstoreValue, ContentId, FileContents, MononokeId};
+use mononoke_types::{BlobstoreValue, ContentId, ExternalContents, FileContents, LfsPointer,
+                     MononokeId};
 
 use blobstore::Blobstore;
 
 use errors::*;
 
+use chunk::{fetch_chunked_contents, stream_chunked_contents};
+use external_contents::fetch_external_contents_opt;
+use lfs::{fetch_lfs_content, stream_lfs_content};
 use manifest::{fetch_manifest_envelope, fetch_raw_manifest_bytes, BlobManifest};
 
 use repo::RepoBlobstore;
@@ -90,11 +96,7 @@ pub fn fetch_file_content_and_renames_from_blobstore(
                     envelope.p1.as_ref(),
                     envelope.p2.as_ref(),
                 );
-                let copy_from = match f.copied_from() {
-                    Ok(copy_from) => copy_from,
-                    // XXX error out if copy-from information couldn't be read?
-                    Err(_err) => None,
-                };
+                let copy_from = get_copy_from(&f);
 
                 file_contents_fut.map(move |contents| (contents, copy_from))
             }
@@ -102,6 +104,15 @@ pub fn fetch_file_content_and_renames_from_blobstore(
         .boxify()
 }
 
+/// The path and filenode `f` was copied from, if any
+pub fn get_copy_from(f: &file::File) -> Option<(MPath, HgNodeHash)> {
+    match f.copied_from() {
+        Ok(copy_from) => copy_from,
+        // XXX error out if copy-from information couldn't be read?
+        Err(_err) => None,
+    }
+}
+
 pub fn fetch_file_envelope(
     blobstore: &RepoBlobstore,
     node_id: HgNodeHash,
@@ -145,27 +156,122 @@ pub fn fetch_file_envelope_opt(
         .from_err()
 }
 
-pub fn fetch_file_contents(
+/// Fetch the content blob of a file, or None if there is none because the contents are stored
+/// out of band or chunked
+fn fetch_content_blob_opt(
     blobstore: &RepoBlobstore,
     content_id: ContentId,
-) -> impl Future<Item = FileContents, Error = Error> {
+) -> impl Future<Item = Option<FileContents>, Error = Error> {
     let blobstore_key = content_id.blobstore_key();
     blobstore
         .get(blobstore_key.clone())
         .context("While fetching content blob")
         .map_err(Error::from)
-        .and_then(move |bytes| {
-            let blobstore_bytes = match bytes {
-                Some(bytes) => bytes,
-                None => bail_err!(ErrorKind::ContentBlobMissing(content_id)),
-            };
-            let file_contents = FileContents::from_blob(blobstore_bytes.into())?;
-            Ok(file_contents)
+        .and_then(|bytes| match bytes {
+            Some(bytes) => Ok(Some(FileContents::from_blob(bytes.into())?)),
+            None => Ok(None),
         })
         .with_context(|_| ErrorKind::FileContentsDeserializeFailed(blobstore_key))
         .from_err()
 }
 
+/// How the contents of a file are stored
+enum StoredContents {
+    Blob(FileContents),
+    External(ExternalContents),
+}
+
+fn fetch_stored_contents(
+    blobstore: &RepoBlobstore,
+    content_id: ContentId,
+) -> impl Future<Item = StoredContents, Error = Error> {
+    fetch_content_blob_opt(blobstore, content_id).and_then({
+        let blobstore = blobstore.clone();
+        move |file_contents| match file_contents {
+            Some(file_contents) => future::ok(StoredContents::Blob(file_contents)).boxify(),
+            None => fetch_external_contents_opt(&blobstore, content_id)
+                .and_then(move |external| {
+                    let external =
+                        external.ok_or(ErrorKind::ContentBlobMissing(content_id))?;
+                    Ok(StoredContents::External(external))
+                })
+                .boxify(),
+        }
+    })
+}
+
+/// Fetch the contents of a file. Contents that are stored out of band or chunked are fetched as
+/// well.
+pub fn fetch_file_contents(
+    blobstore: &RepoBlobstore,
+    content_id: ContentId,
+) -> impl Future<Item = FileContents, Error = Error> {
+    fetch_stored_contents(blobstore, content_id).and_then({
+        let blobstore = blobstore.clone();
+        move |stored| match stored {
+            StoredContents::Blob(file_contents) => future::ok(file_contents).boxify(),
+            StoredContents::External(ExternalContents::Lfs(pointer)) => {
+                fetch_lfs_content(&blobstore, pointer)
+                    .map(FileContents::Bytes)
+                    .boxify()
+            }
+            StoredContents::External(ExternalContents::Chunked(chunked)) => {
+                fetch_chunked_contents(&blobstore, chunked)
+                    .map(FileContents::Bytes)
+                    .boxify()
+            }
+        }
+    })
+}
+
+/// Stream the contents of a file. Chunked and LFS contents are fetched one chunk at a time, so
+/// they never have to be held in memory at once.
+pub fn fetch_file_contents_stream(
+    blobstore: &RepoBlobstore,
+    content_id: ContentId,
+) -> BoxStream<Bytes, Error> {
+    let blobstore = blobstore.clone();
+    fetch_stored_contents(&blobstore, content_id)
+        .map(move |stored| match stored {
+            StoredContents::Blob(FileContents::Bytes(bytes)) => stream::once(Ok(bytes)).boxify(),
+            StoredContents::External(ExternalContents::Lfs(pointer)) => {
+                stream_lfs_content(&blobstore, pointer)
+            }
+            StoredContents::External(ExternalContents::Chunked(chunked)) => {
+                stream_chunked_contents(&blobstore, chunked)
+            }
+        })
+        .flatten_stream()
+        .boxify()
+}
+
+/// Fetch the pointer to the contents of a file if they are stored in the LFS blobstore, along
+/// with the path and filenode the file was copied from, if any
+pub fn fetch_file_lfs_pointer(
+    blobstore: &RepoBlobstore,
+    node_id: HgNodeHash,
+) -> impl Future<Item = Option<(LfsPointer, Option<(MPath, HgNodeHash)>)>, Error = Error> {
+    fetch_file_envelope(blobstore, node_id).and_then({
+        let blobstore = blobstore.clone();
+        move |envelope| {
+            let envelope = envelope.into_mut();
+            let f = file::File::new(
+                envelope.metadata,
+                envelope.p1.as_ref(),
+                envelope.p2.as_ref(),
+            );
+            let copy_from = get_copy_from(&f);
+
+            fetch_external_contents_opt(&blobstore, envelope.content_id).map(
+                move |external| match external {
+                    Some(ExternalContents::Lfs(pointer)) => Some((pointer, copy_from)),
+                    _ => None,
+                },
+            )
+        }
+    })
+}
+
 impl HgBlobEntry {
     pub fn new(blobstore: RepoBlobstore, name: MPathElement, nodeid: HgNodeHash, ty: Type) -> Self {
         Self {
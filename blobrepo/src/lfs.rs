@@ -0,0 +1,291 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Large file contents, which are stored out of band
+//!
+//! The contents of files larger than the LFS threshold of a repo are stored by their SHA-256
+//! hash in the LFS blobstore. They have no content blob, and their `ExternalContents` only hold
+//! an `LfsPointer` to them. The LFS blobstore is separate from the blobstore of the repo, but
+//! the `RepoBlobstore` reaches both, as it sends the keys of LFS contents to the LFS blobstore.
+//!
+//! LFS contents are split into chunks, which are stored in the LFS blobstore along with the
+//! index of the chunks, so that they can be uploaded and fetched as streams. The index is only
+//! stored once all the chunks are, so contents are present as soon as their index is.
+
+use std::str::{self, FromStr};
+
+use bytes::{Bytes, BytesMut};
+use futures::future::{self, Future};
+use futures::stream::{self, Stream};
+use futures_ext::{BoxStream, FutureExt, StreamExt};
+
+use blobstore::Blobstore;
+use mercurial_types::{HgNodeHash, MPath, RepositoryId};
+use mononoke_types::{BlobstoreValue, ChunkPointer, ChunkedContents, Chunker, ContentChunk,
+                     ContentChunkBlob, ContentChunkId, LfsPointer, MononokeId, Sha256,
+                     Sha256Context};
+
+use chunk::{concat_chunks, stream_chunks, MAX_CONCURRENT_CHUNK_UPLOADS};
+use errors::*;
+use repo::RepoBlobstore;
+
+/// The keys of LFS contents start with this, after the repo prefix
+const LFS_KEY_PREFIX: &str = "lfs.";
+/// LFS contents are split into chunks of this many bytes
+const LFS_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Mercurial's lfs extension keeps the copy info of a file in the pointer to its contents
+const HG_COPY_KEY: &str = "x-hg-copy";
+const HG_COPYREV_KEY: &str = "x-hg-copyrev";
+
+/// The prefix of the keys that the `RepoBlobstore` of `repoid` sends to the LFS blobstore
+pub fn lfs_key_prefix(repoid: RepositoryId) -> String {
+    format!("{}{}", repoid.prefix(), LFS_KEY_PREFIX)
+}
+
+/// The key of the index of the contents with the SHA-256 hash `sha256` in the `RepoBlobstore`
+pub fn lfs_blobstore_key(sha256: &Sha256) -> String {
+    format!("{}sha256.{}", LFS_KEY_PREFIX, sha256)
+}
+
+/// The key of a chunk of LFS contents in the `RepoBlobstore`
+pub fn lfs_chunk_key(chunk_id: &ContentChunkId) -> String {
+    format!("{}{}", LFS_KEY_PREFIX, chunk_id.blobstore_key())
+}
+
+/// Fetch the index of the contents with the SHA-256 hash `sha256`, or None if they are not
+/// stored
+fn fetch_lfs_index_opt(
+    blobstore: &RepoBlobstore,
+    sha256: &Sha256,
+) -> impl Future<Item = Option<ChunkedContents>, Error = Error> {
+    let blobstore_key = lfs_blobstore_key(sha256);
+    blobstore
+        .get(blobstore_key.clone())
+        .and_then(|bytes| match bytes {
+            Some(bytes) => Ok(Some(ChunkedContents::from_blobstore_bytes(&bytes)?)),
+            None => Ok(None),
+        })
+        .with_context(move |_| format!("While fetching LFS content {}", blobstore_key))
+        .from_err()
+}
+
+/// Stream the contents `pointer` points to, one chunk at a time
+pub fn stream_lfs_content(
+    blobstore: &RepoBlobstore,
+    pointer: LfsPointer,
+) -> BoxStream<Bytes, Error> {
+    let blobstore = blobstore.clone();
+    fetch_lfs_index_opt(&blobstore, pointer.sha256())
+        .and_then(move |chunked| {
+            let chunked =
+                chunked.ok_or_else(|| ErrorKind::LfsContentMissing(*pointer.sha256()))?;
+            let size = chunked.size();
+            if size != pointer.size() {
+                bail_err!(ErrorKind::LfsSizeMismatch(
+                    *pointer.sha256(),
+                    size,
+                    pointer.size()
+                ));
+            }
+            Ok(stream_chunks(&blobstore, chunked, lfs_chunk_key))
+        })
+        .flatten_stream()
+        .boxify()
+}
+
+/// Fetch the contents `pointer` points to
+pub fn fetch_lfs_content(
+    blobstore: &RepoBlobstore,
+    pointer: LfsPointer,
+) -> impl Future<Item = Bytes, Error = Error> {
+    concat_chunks(stream_lfs_content(blobstore, pointer), pointer.size())
+}
+
+/// Fetch the contents with the SHA-256 hash `sha256`, or None if they are not stored
+pub fn fetch_lfs_content_opt(
+    blobstore: &RepoBlobstore,
+    sha256: &Sha256,
+) -> impl Future<Item = Option<Bytes>, Error = Error> {
+    let blobstore = blobstore.clone();
+    fetch_lfs_index_opt(&blobstore, sha256).and_then(move |chunked| match chunked {
+        Some(chunked) => {
+            let size = chunked.size();
+            concat_chunks(stream_chunks(&blobstore, chunked, lfs_chunk_key), size)
+                .map(Some)
+                .boxify()
+        }
+        None => future::ok(None).boxify(),
+    })
+}
+
+pub fn lfs_content_exists(
+    blobstore: &RepoBlobstore,
+    sha256: &Sha256,
+) -> impl Future<Item = bool, Error = Error> {
+    blobstore.is_present(lfs_blobstore_key(sha256))
+}
+
+/// Store `content`, which `pointer` was constructed from, in the LFS blobstore
+pub fn store_lfs_content(
+    blobstore: &RepoBlobstore,
+    pointer: &LfsPointer,
+    content: Bytes,
+) -> impl Future<Item = (), Error = Error> {
+    let (chunked, chunks) = Chunker::FixedSize(LFS_CHUNK_SIZE).chunk(content);
+    let blobstore_key = lfs_blobstore_key(pointer.sha256());
+    let blobstore = blobstore.clone();
+    store_lfs_chunks(&blobstore, chunks)
+        .and_then(move |()| blobstore.put(blobstore_key, chunked.into_blobstore_bytes()))
+}
+
+/// Store the contents `content` streams in the LFS blobstore, and return the pointer to them.
+/// The pieces they are streamed in can have any size, as they are split into chunks as they
+/// arrive, so at most about a chunk of them is held in memory at once. The contents are only
+/// stored if their SHA-256 hash is `sha256`.
+pub fn store_lfs_content_stream<S>(
+    blobstore: &RepoBlobstore,
+    sha256: Sha256,
+    content: S,
+) -> impl Future<Item = LfsPointer, Error = Error>
+where
+    S: Stream<Item = Bytes, Error = Error> + Send + 'static,
+{
+    let blobstore = blobstore.clone();
+    content
+        .map(Some)
+        // None marks the end of the contents, so that the last chunk is stored as well
+        .chain(stream::once(Ok(None)))
+        .fold(LfsUpload::new(), {
+            let blobstore = blobstore.clone();
+            move |mut upload, piece| {
+                let chunks = match piece {
+                    Some(piece) => upload.add(piece),
+                    None => upload.last_chunk().into_iter().collect(),
+                };
+                store_lfs_chunks(&blobstore, chunks).map(move |()| upload)
+            }
+        })
+        .and_then(move |upload| {
+            let (pointer, chunked) = upload.finish();
+            if *pointer.sha256() != sha256 {
+                let err = ErrorKind::LfsHashMismatch(sha256, *pointer.sha256());
+                return future::err(Error::from(err)).boxify();
+            }
+            blobstore
+                .put(lfs_blobstore_key(&sha256), chunked.into_blobstore_bytes())
+                .map(move |()| pointer)
+                .boxify()
+        })
+}
+
+fn store_lfs_chunks(
+    blobstore: &RepoBlobstore,
+    chunks: Vec<ContentChunkBlob>,
+) -> impl Future<Item = (), Error = Error> {
+    let blobstore = blobstore.clone();
+    stream::iter_ok(chunks)
+        .map(move |chunk| blobstore.put(lfs_chunk_key(chunk.id()), chunk.into()))
+        .buffer_unordered(MAX_CONCURRENT_CHUNK_UPLOADS)
+        .for_each(|()| Ok(()))
+}
+
+/// LFS contents that are being uploaded, as they are streamed in pieces of any size
+struct LfsUpload {
+    context: Sha256Context,
+    size: u64,
+    // The bytes that don't make a whole chunk yet
+    pending: BytesMut,
+    chunks: Vec<ChunkPointer>,
+}
+
+impl LfsUpload {
+    fn new() -> Self {
+        LfsUpload {
+            context: Sha256Context::new(),
+            size: 0,
+            pending: BytesMut::new(),
+            chunks: vec![],
+        }
+    }
+
+    /// Add the next piece of the contents, and return the chunks it completes
+    fn add(&mut self, piece: Bytes) -> Vec<ContentChunkBlob> {
+        self.context.update(&piece);
+        self.size += piece.len() as u64;
+        self.pending.extend_from_slice(&piece);
+
+        let mut chunks = vec![];
+        while self.pending.len() >= LFS_CHUNK_SIZE {
+            let chunk = self.pending.split_to(LFS_CHUNK_SIZE).freeze();
+            chunks.push(self.chunk(chunk));
+        }
+        chunks
+    }
+
+    /// The chunk of what is left at the end of the contents, if anything is
+    fn last_chunk(&mut self) -> Option<ContentChunkBlob> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let chunk = self.pending.take().freeze();
+        Some(self.chunk(chunk))
+    }
+
+    fn chunk(&mut self, bytes: Bytes) -> ContentChunkBlob {
+        let size = bytes.len() as u64;
+        let blob = ContentChunk::new(bytes).into_blob();
+        self.chunks.push(ChunkPointer::new(*blob.id(), size));
+        blob
+    }
+
+    fn finish(self) -> (LfsPointer, ChunkedContents) {
+        let pointer = LfsPointer::new(self.context.finish(), self.size);
+        (pointer, ChunkedContents::new(self.chunks))
+    }
+}
+
+/// Parse the pointer file that Mercurial's lfs extension stores instead of the contents of a
+/// file, along with the path and filenode the file was copied from, if any. Returns None if
+/// `bytes` are not a pointer file.
+pub fn parse_hg_lfs_pointer(bytes: &[u8]) -> Option<(LfsPointer, Option<(MPath, HgNodeHash)>)> {
+    let pointer = LfsPointer::from_pointer_file(bytes).ok()?;
+    // Pointer files are always valid utf-8.
+    let text = str::from_utf8(bytes).ok()?;
+
+    let mut copy = None;
+    let mut copyrev = None;
+    for line in text.lines() {
+        let mut parts = line.splitn(2, ' ');
+        match (parts.next(), parts.next()) {
+            (Some(HG_COPY_KEY), Some(path)) => copy = Some(path),
+            (Some(HG_COPYREV_KEY), Some(rev)) => copyrev = Some(rev),
+            _ => {}
+        }
+    }
+    let copy_from = match (copy, copyrev) {
+        (Some(path), Some(rev)) => Some((MPath::new(path).ok()?, HgNodeHash::from_str(rev).ok()?)),
+        _ => None,
+    };
+    Some((pointer, copy_from))
+}
+
+/// The pointer file that Mercurial's lfs extension stores instead of the contents `pointer`
+/// points to, for a file copied from `copy_from`
+pub fn hg_lfs_pointer_file(
+    pointer: &LfsPointer,
+    copy_from: Option<&(MPath, HgNodeHash)>,
+) -> Bytes {
+    let mut bytes = BytesMut::from(&pointer.to_pointer_file()[..]);
+    if let Some(&(ref path, ref node)) = copy_from {
+        // The keys are sorted, and the copy info keys go after the ones of the pointer.
+        bytes.extend_from_slice(HG_COPY_KEY.as_bytes());
+        bytes.extend_from_slice(b" ");
+        bytes.extend_from_slice(&path.to_vec());
+        bytes.extend_from_slice(format!("\n{} {}\n", HG_COPYREV_KEY, node).as_bytes());
+    }
+    bytes.freeze()
+}
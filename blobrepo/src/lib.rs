@@ -32,6 +32,8 @@ extern crate futures_stats;
 
 extern crate ascii;
 extern crate blobstore;
+extern crate bonsai_hg_mapping;
+extern crate bonsai_utils;
 extern crate bookmarks;
 extern crate changesets;
 extern crate dbbookmarks;
@@ -58,8 +60,11 @@ extern crate many_files_dirs;
 extern crate mercurial_types_mocks;
 
 mod changeset;
+mod chunk;
 mod errors;
+mod external_contents;
 mod file;
+mod lfs;
 mod manifest;
 mod memory_manifest;
 mod repo;
@@ -70,6 +75,7 @@ pub use errors::*;
 
 pub use changeset::BlobChangeset;
 pub use file::HgBlobEntry;
+pub use lfs::{lfs_blobstore_key, lfs_chunk_key};
 pub use manifest::BlobManifest;
 pub use repo::{BlobRepo, ContentBlobInfo, ContentBlobMeta, CreateChangeset, UploadHgFileContents,
                UploadHgFileEntry, UploadHgNodeHash, UploadHgTreeEntry};
@@ -518,6 +518,60 @@ impl MemoryManifestEntry {
         }.boxify()
     }
 
+    /// Drop the second parent of this tree and of all the trees below it that were created by a
+    /// merge, leaving a tree whose history only follows the first parent
+    fn without_p2(self) -> Self {
+        match self {
+            MemoryManifestEntry::MemTree {
+                base_manifest_id,
+                p1,
+                changes,
+                ..
+            } => {
+                let changes = {
+                    let changes = changes.lock().expect("lock poisoned");
+                    changes
+                        .iter()
+                        .map(|(name, entry)| (name.clone(), entry.clone().map(Self::without_p2)))
+                        .collect()
+                };
+                MemoryManifestEntry::MemTree {
+                    base_manifest_id,
+                    p1,
+                    p2: None,
+                    changes: Arc::new(Mutex::new(changes)),
+                }
+            }
+            other => other,
+        }
+    }
+
+    /// Replace every conflict below this tree with one of its entries, as chosen by `pick` from
+    /// the path of the conflict
+    fn resolve_conflicts_with<F>(&self, path: Option<&MPath>, pick: &F)
+    where
+        F: Fn(&MPath) -> usize,
+    {
+        if let MemoryManifestEntry::MemTree { changes, .. } = self {
+            let mut changes = changes.lock().expect("lock poisoned");
+            for (name, entry) in changes.iter_mut() {
+                let entry_path = MPath::join_opt(path, name).expect("entry has an empty path");
+                let picked = match entry {
+                    Some(MemoryManifestEntry::Conflict(conflicts)) => {
+                        Some(conflicts.get(pick(&entry_path)).cloned())
+                    }
+                    _ => None,
+                };
+                match picked {
+                    Some(picked) => *entry = picked,
+                    None => if let Some(entry) = entry {
+                        entry.resolve_conflicts_with(Some(&entry_path), pick)
+                    },
+                }
+            }
+        }
+    }
+
     // Only for use in find_mut_helper
     fn conflict_to_memtree(&mut self) -> Self {
         let new = if let MemoryManifestEntry::Conflict(conflicts) = self {
@@ -841,6 +895,23 @@ impl MemoryRootManifest {
         }
     }
 
+    /// Create an in-memory manifest for moving a changeset with the manifest `manifest` on top
+    /// of a changeset with the manifest `onto`. The two manifests are merged, with conflicts
+    /// wherever they differ, but unlike a merge the trees only have the trees of `onto` as
+    /// parents.
+    pub fn new_rebased(
+        repo: BlobRepo,
+        onto: &HgNodeHash,
+        manifest: &HgNodeHash,
+    ) -> BoxFuture<Self, Error> {
+        Self::create_conflict(
+            repo,
+            MemoryManifestEntry::convert_treenode(onto),
+            MemoryManifestEntry::convert_treenode(manifest),
+        ).map(|rebased| Self::create(rebased.repo, rebased.root_entry.without_p2()))
+            .boxify()
+    }
+
     /// Save this manifest to the blobstore, recursing down to ensure that
     /// all child entries are saved and that there are no conflicts.
     /// Note that child entries can be saved even if a parallel tree has conflicts. E.g. if the
@@ -893,6 +964,16 @@ impl MemoryRootManifest {
         self.root_entry.resolve_trivial_conflicts(self.repo.clone())
     }
 
+    /// Resolve all the conflicts by keeping one of the conflicting entries. `pick` gets the path
+    /// of each conflict and returns the index of the entry to keep, in the order of the merged
+    /// manifests; a conflict is removed if there's no entry with that index.
+    pub fn resolve_conflicts_with<F>(&self, pick: F)
+    where
+        F: Fn(&MPath) -> usize,
+    {
+        self.root_entry.resolve_conflicts_with(None, &pick)
+    }
+
     pub fn unittest_root(&self) -> &MemoryManifestEntry {
         &self.root_entry
     }
@@ -12,7 +12,7 @@ use std::thread;
 use std::time::Duration;
 use std::usize;
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use db::{get_connection_params, InstanceRequirement, ProxyRequirement};
 use futures::{Async, IntoFuture, Poll};
 use futures::future::{self, Either, Future};
@@ -26,42 +26,60 @@ use stats::Timeseries;
 use time_ext::DurationExt;
 use uuid::Uuid;
 
-use blobstore::{Blobstore, EagerMemblob, MemcacheBlobstore, MemoizedBlobstore, PrefixBlobstore};
-use bookmarks::{self, Bookmark, BookmarkPrefix, Bookmarks};
+use blobstore::{Blobstore, EagerMemblob, MemcacheBlobstore, MemoizedBlobstore, PrefixBlobstore,
+                SplitBlobstore};
+use bonsai_hg_mapping::{BonsaiHgMapping, BonsaiHgMappingEntry, MysqlBonsaiHgMapping,
+                        SqliteBonsaiHgMapping};
+use bookmarks::{self, Bookmark, BookmarkPrefix, BookmarkUpdateLogEntry, BookmarkUpdateReason,
+                Bookmarks};
 use changesets::{CachingChangests, ChangesetInsert, Changesets, MysqlChangesets, SqliteChangesets};
-use dbbookmarks::{MysqlDbBookmarks, SqliteDbBookmarks};
+use dbbookmarks::{MysqlDbBookmarks, MysqlDbScratchBookmarks, SqliteDbBookmarks,
+                  SqliteDbScratchBookmarks};
 use delayblob::DelayBlob;
 use dieselfilenodes::{MysqlFilenodes, SqliteFilenodes, DEFAULT_INSERT_CHUNK_SIZE};
 use fileblob::Fileblob;
 use filenodes::{CachingFilenodes, FilenodeInfo, Filenodes};
 use manifoldblob::ManifoldBlob;
-use mercurial::file::File;
-use mercurial_types::{Changeset, Entry, HgBlob, HgBlobNode, HgChangesetId, HgFileEnvelopeMut,
-                      HgFileNodeId, HgManifestEnvelopeMut, HgManifestId, HgNodeHash, HgParents,
-                      Manifest, RepoPath, RepositoryId, Type};
+use mercurial::file::{File, META_SZ};
+use mercurial_types::{Changeset, Entry, HgBlob, HgBlobNode, HgChangesetId, HgChangesetIdPrefix,
+                      HgFileEnvelopeMut, HgFileNodeId, HgManifestEnvelopeMut, HgManifestId,
+                      HgNodeHash, HgNodeHasher, HgParents, Manifest, RepoPath, RepositoryId,
+                      Type};
 use mercurial_types::manifest::Content;
-use mononoke_types::{Blob, BlobstoreValue, BonsaiChangeset, ContentId, DateTime, FileChange,
-                     FileContents, FileType, MPath, MPathElement, MononokeId};
+use mononoke_types::{Blob, BlobstoreValue, BonsaiChangeset, ChangesetId, Chunker, ContentId,
+                     ContentIdHasher, DateTime, ExternalContents, FileChange, FileContents,
+                     FileType, LfsPointer, MPath, MPathElement, MononokeId, Sha256};
 use rocksblob::Rocksblob;
 use rocksdb;
 use tokio_core::reactor::Core;
 
 use BlobChangeset;
 use BlobManifest;
+use chunk::MAX_CONCURRENT_CHUNK_UPLOADS;
 use errors::*;
-use file::{fetch_file_content_and_renames_from_blobstore, fetch_raw_filenode_bytes, HgBlobEntry};
+use external_contents::store_external_contents;
+use file::{fetch_file_content_and_renames_from_blobstore, fetch_file_contents_stream,
+           fetch_file_envelope, fetch_file_lfs_pointer, fetch_raw_filenode_bytes, get_copy_from,
+           HgBlobEntry};
+use lfs::{fetch_lfs_content_opt, hg_lfs_pointer_file, lfs_content_exists, lfs_key_prefix,
+          parse_hg_lfs_pointer, store_lfs_content, store_lfs_content_stream, stream_lfs_content};
 use memory_manifest::MemoryRootManifest;
 use repo_commit::*;
 
 define_stats! {
     prefix = "mononoke.blobrepo";
     get_file_content: timeseries(RATE, SUM),
+    get_file_content_stream: timeseries(RATE, SUM),
+    get_file_size: timeseries(RATE, SUM),
     get_raw_hg_content: timeseries(RATE, SUM),
+    get_raw_hg_content_stream: timeseries(RATE, SUM),
+    get_hg_lfs_pointer: timeseries(RATE, SUM),
     get_parents: timeseries(RATE, SUM),
     get_file_copy: timeseries(RATE, SUM),
     get_changesets: timeseries(RATE, SUM),
     get_heads: timeseries(RATE, SUM),
     changeset_exists: timeseries(RATE, SUM),
+    get_changesets_by_prefix: timeseries(RATE, SUM),
     get_changeset_parents: timeseries(RATE, SUM),
     get_changeset_by_changesetid: timeseries(RATE, SUM),
     get_manifest_by_nodeid: timeseries(RATE, SUM),
@@ -69,9 +87,21 @@ define_stats! {
     get_bookmark: timeseries(RATE, SUM),
     get_bookmarks: timeseries(RATE, SUM),
     update_bookmark_transaction: timeseries(RATE, SUM),
+    read_bookmark_log: timeseries(RATE, SUM),
+    get_scratch_bookmark: timeseries(RATE, SUM),
+    get_scratch_bookmarks_by_prefix: timeseries(RATE, SUM),
+    update_scratch_bookmark_transaction: timeseries(RATE, SUM),
     get_linknode: timeseries(RATE, SUM),
     get_all_filenodes: timeseries(RATE, SUM),
     get_generation_number: timeseries(RATE, SUM),
+    get_bonsai_changeset: timeseries(RATE, SUM),
+    get_bonsai_from_hg: timeseries(RATE, SUM),
+    get_hg_from_bonsai: timeseries(RATE, SUM),
+    get_lfs_content: timeseries(RATE, SUM),
+    lfs_content_exists: timeseries(RATE, SUM),
+    upload_lfs_content: timeseries(RATE, SUM),
+    upload_lfs_content_stream: timeseries(RATE, SUM),
+    upload_hg_lfs_pointer: timeseries(RATE, SUM),
     upload_blob: timeseries(RATE, SUM),
     upload_hg_file_entry: timeseries(RATE, SUM),
     upload_hg_tree_entry: timeseries(RATE, SUM),
@@ -79,20 +109,30 @@ define_stats! {
     create_changeset_compute_cf: timeseries("create_changeset.compute_changed_files"; RATE, SUM),
     create_changeset_expected_cf: timeseries("create_changeset.expected_changed_files"; RATE, SUM),
     create_changeset_cf_count: timeseries("create_changeset.changed_files_count"; AVG, SUM),
+    create_changeset_without_bonsai: timeseries("create_changeset.without_bonsai"; RATE, SUM),
 }
 
 /// Making PrefixBlobstore part of every blobstore does two things:
 /// 1. It ensures that the prefix applies first, which is important for shared caches like
 ///    memcache.
 /// 2. It ensures that all possible blobrepos use a prefix.
+/// Under the prefix, the keys of large file contents are sent to the separate LFS blobstore.
 pub type RepoBlobstore = PrefixBlobstore<Arc<Blobstore>>;
 
 pub struct BlobRepo {
     logger: Logger,
     blobstore: RepoBlobstore,
+    // Contents of files larger than this are stored in the LFS blobstore. If it's None, all
+    // contents are stored in the blobstore.
+    lfs_threshold: Option<u64>,
+    // Contents of files larger than the threshold are split into chunks with the chunker. If
+    // it's None, no contents are chunked.
+    chunking: Option<(u64, Chunker)>,
     bookmarks: Arc<Bookmarks>,
+    scratch_bookmarks: Arc<Bookmarks>,
     filenodes: Arc<Filenodes>,
     changesets: Arc<Changesets>,
+    bonsai_hg_mapping: Arc<BonsaiHgMapping>,
     repoid: RepositoryId,
 }
 
@@ -100,17 +140,25 @@ impl BlobRepo {
     pub fn new(
         logger: Logger,
         bookmarks: Arc<Bookmarks>,
+        scratch_bookmarks: Arc<Bookmarks>,
         blobstore: Arc<Blobstore>,
+        lfs_blobstore: Arc<Blobstore>,
         filenodes: Arc<Filenodes>,
         changesets: Arc<Changesets>,
+        bonsai_hg_mapping: Arc<BonsaiHgMapping>,
         repoid: RepositoryId,
     ) -> Self {
+        let blobstore = SplitBlobstore::new(blobstore, lfs_key_prefix(repoid), lfs_blobstore);
         BlobRepo {
             logger,
             bookmarks,
-            blobstore: PrefixBlobstore::new(blobstore, repoid.prefix()),
+            scratch_bookmarks,
+            blobstore: PrefixBlobstore::new(Arc::new(blobstore), repoid.prefix()),
+            lfs_threshold: None,
+            chunking: None,
             filenodes,
             changesets,
+            bonsai_hg_mapping,
             repoid,
         }
     }
@@ -120,16 +168,25 @@ impl BlobRepo {
     pub fn new_files(logger: Logger, path: &Path, repoid: RepositoryId) -> Result<Self> {
         let blobstore = Fileblob::create(path.join("blobs"))
             .context(ErrorKind::StateOpen(StateOpenError::Blobstore))?;
+        let lfs_blobstore = Fileblob::create(path.join("lfs"))
+            .context(ErrorKind::StateOpen(StateOpenError::LfsBlobstore))?;
 
-        Self::new_local(logger, path, Arc::new(blobstore), repoid)
+        Self::new_local(
+            logger,
+            path,
+            Arc::new(blobstore),
+            Arc::new(lfs_blobstore),
+            repoid,
+        )
     }
 
     pub fn new_rocksdb(logger: Logger, path: &Path, repoid: RepositoryId) -> Result<Self> {
         let options = rocksdb::Options::new().create_if_missing(true);
         let blobstore = Rocksblob::open_with_options(path.join("blobs"), options)
             .context(ErrorKind::StateOpen(StateOpenError::Blobstore))?;
+        let lfs_blobstore = Self::open_rocksdb_lfs_blobstore(path)?;
 
-        Self::new_local(logger, path, Arc::new(blobstore), repoid)
+        Self::new_local(logger, path, Arc::new(blobstore), lfs_blobstore, repoid)
     }
 
     pub fn new_rocksdb_delayed<F>(
@@ -156,8 +213,16 @@ impl BlobRepo {
             is_present_roundtrips,
             assert_present_roundtrips,
         );
+        let lfs_blobstore = Self::open_rocksdb_lfs_blobstore(path)?;
 
-        Self::new_local(logger, path, Arc::new(blobstore), repoid)
+        Self::new_local(logger, path, Arc::new(blobstore), lfs_blobstore, repoid)
+    }
+
+    fn open_rocksdb_lfs_blobstore(path: &Path) -> Result<Arc<Blobstore>> {
+        let options = rocksdb::Options::new().create_if_missing(true);
+        let lfs_blobstore = Rocksblob::open_with_options(path.join("lfs"), options)
+            .context(ErrorKind::StateOpen(StateOpenError::LfsBlobstore))?;
+        Ok(Arc::new(lfs_blobstore))
     }
 
     /// Create a new BlobRepo with purely local state.
@@ -165,10 +230,14 @@ impl BlobRepo {
         logger: Logger,
         path: &Path,
         blobstore: Arc<Blobstore>,
+        lfs_blobstore: Arc<Blobstore>,
         repoid: RepositoryId,
     ) -> Result<Self> {
         let bookmarks = SqliteDbBookmarks::open_or_create(path.join("books").to_string_lossy())
             .context(ErrorKind::StateOpen(StateOpenError::Bookmarks))?;
+        let scratch_bookmarks = SqliteDbScratchBookmarks::open_or_create(
+            path.join("scratch_books").to_string_lossy(),
+        ).context(ErrorKind::StateOpen(StateOpenError::ScratchBookmarks))?;
         let filenodes = SqliteFilenodes::open_or_create(
             path.join("filenodes").to_string_lossy(),
             DEFAULT_INSERT_CHUNK_SIZE,
@@ -176,13 +245,19 @@ impl BlobRepo {
         let changesets = SqliteChangesets::open_or_create(
             path.join("changesets").to_string_lossy(),
         ).context(ErrorKind::StateOpen(StateOpenError::Changesets))?;
+        let bonsai_hg_mapping = SqliteBonsaiHgMapping::open_or_create(
+            path.join("bonsai_hg_mapping").to_string_lossy(),
+        ).context(ErrorKind::StateOpen(StateOpenError::BonsaiHgMapping))?;
 
         Ok(Self::new(
             logger,
             Arc::new(bookmarks),
+            Arc::new(scratch_bookmarks),
             blobstore,
+            lfs_blobstore,
             Arc::new(filenodes),
             Arc::new(changesets),
+            Arc::new(bonsai_hg_mapping),
             repoid,
         ))
     }
@@ -196,11 +271,16 @@ impl BlobRepo {
         Ok(Self::new(
             logger.unwrap_or(Logger::root(Discard {}.ignore_res(), o!())),
             Arc::new(SqliteDbBookmarks::in_memory()?),
+            Arc::new(SqliteDbScratchBookmarks::in_memory()
+                .context(ErrorKind::StateOpen(StateOpenError::ScratchBookmarks))?),
             blobstore.unwrap_or_else(|| Arc::new(EagerMemblob::new())),
+            Arc::new(EagerMemblob::new()),
             Arc::new(SqliteFilenodes::in_memory()
                 .context(ErrorKind::StateOpen(StateOpenError::Filenodes))?),
             Arc::new(SqliteChangesets::in_memory()
                 .context(ErrorKind::StateOpen(StateOpenError::Changesets))?),
+            Arc::new(SqliteBonsaiHgMapping::in_memory()
+                .context(ErrorKind::StateOpen(StateOpenError::BonsaiHgMapping))?),
             RepositoryId::new(0),
         ))
     }
@@ -226,6 +306,8 @@ impl BlobRepo {
         )?;
         let bookmarks = MysqlDbBookmarks::open(&connection_params)
             .context(ErrorKind::StateOpen(StateOpenError::Bookmarks))?;
+        let scratch_bookmarks = MysqlDbScratchBookmarks::open(&connection_params)
+            .context(ErrorKind::StateOpen(StateOpenError::ScratchBookmarks))?;
 
         let mut io_remotes = vec![];
         for i in 0..thread_num {
@@ -256,6 +338,13 @@ impl BlobRepo {
         );
         let blobstore = MemcacheBlobstore::new(blobstore, "manifold", bucket.as_ref())?;
         let blobstore = MemoizedBlobstore::new(blobstore, usize::MAX, blobstore_cache_size);
+        // Large file contents would crowd everything else out of the caches, so they bypass them
+        let lfs_blobstore = ManifoldBlob::new_with_prefix(
+            bucket.to_string(),
+            prefix,
+            io_remotes.iter().collect(),
+            max_concurrent_requests_per_io_thread,
+        );
 
         let filenodes = MysqlFilenodes::open(db_address, DEFAULT_INSERT_CHUNK_SIZE)
             .context(ErrorKind::StateOpen(StateOpenError::Filenodes))?;
@@ -266,12 +355,18 @@ impl BlobRepo {
 
         let changesets = CachingChangests::new(Arc::new(changesets), changesets_cache_size);
 
+        let bonsai_hg_mapping = MysqlBonsaiHgMapping::open(db_address)
+            .context(ErrorKind::StateOpen(StateOpenError::BonsaiHgMapping))?;
+
         Ok(Self::new(
             logger,
             Arc::new(bookmarks),
+            Arc::new(scratch_bookmarks),
             Arc::new(blobstore),
+            Arc::new(lfs_blobstore),
             Arc::new(filenodes),
             Arc::new(changesets),
+            Arc::new(bonsai_hg_mapping),
             repoid,
         ))
     }
@@ -325,6 +420,25 @@ impl BlobRepo {
             .boxify()
     }
 
+    /// Streams the contents of the file, which unlike `get_file_content` doesn't hold all of
+    /// them in memory if they are chunked
+    pub fn get_file_content_stream(&self, key: &HgNodeHash) -> BoxStream<Bytes, Error> {
+        STATS::get_file_content_stream.add_value(1);
+        let blobstore = self.blobstore.clone();
+        fetch_file_envelope(&self.blobstore, *key)
+            .map(move |envelope| fetch_file_contents_stream(&blobstore, *envelope.content_id()))
+            .flatten_stream()
+            .boxify()
+    }
+
+    /// The size of the contents of the file, without the copy metadata
+    pub fn get_file_size(&self, key: &HgNodeHash) -> BoxFuture<u64, Error> {
+        STATS::get_file_size.add_value(1);
+        fetch_file_envelope(&self.blobstore, *key)
+            .map(|envelope| envelope.content_size())
+            .boxify()
+    }
+
     // TODO: (rain1) T30456231 It should be possible in principle to make the return type a wrapper
     // around a Chain, but it isn't because of API deficiencies in bytes::Buf. See D8412210.
 
@@ -335,6 +449,32 @@ impl BlobRepo {
         fetch_raw_filenode_bytes(&self.blobstore, *key)
     }
 
+    /// Streams the raw filenode content, the copy metadata followed by the contents, without
+    /// holding all of the contents in memory if they are chunked
+    pub fn get_raw_hg_content_stream(&self, key: &HgNodeHash) -> BoxStream<Bytes, Error> {
+        STATS::get_raw_hg_content_stream.add_value(1);
+        let blobstore = self.blobstore.clone();
+        fetch_file_envelope(&self.blobstore, *key)
+            .map(move |envelope| {
+                let metadata = stream::once(Ok(envelope.metadata().clone()));
+                metadata.chain(fetch_file_contents_stream(&blobstore, *envelope.content_id()))
+            })
+            .flatten_stream()
+            .boxify()
+    }
+
+    /// The pointer file that Mercurial's lfs extension stores instead of the contents of the
+    /// file, if they are stored in the LFS blobstore. Clients with the extension fetch the
+    /// contents the pointer points to themselves.
+    pub fn get_hg_lfs_pointer(&self, key: &HgNodeHash) -> BoxFuture<Option<Bytes>, Error> {
+        STATS::get_hg_lfs_pointer.add_value(1);
+        fetch_file_lfs_pointer(&self.blobstore, *key)
+            .map(|lfs| {
+                lfs.map(|(pointer, copy_from)| hg_lfs_pointer_file(&pointer, copy_from.as_ref()))
+            })
+            .boxify()
+    }
+
     pub fn get_parents(&self, path: &RepoPath, node: &HgNodeHash) -> BoxFuture<HgParents, Error> {
         STATS::get_parents.add_value(1);
         let path = path.clone();
@@ -404,6 +544,16 @@ impl BlobRepo {
             .boxify()
     }
 
+    /// Returns at most `limit` changesets whose hashes start with `prefix`, in ascending order
+    pub fn get_changesets_by_prefix(
+        &self,
+        prefix: HgChangesetIdPrefix,
+        limit: usize,
+    ) -> BoxFuture<Vec<HgChangesetId>, Error> {
+        STATS::get_changesets_by_prefix.add_value(1);
+        self.changesets.get_many_by_prefix(self.repoid, prefix, limit)
+    }
+
     pub fn get_changeset_parents(
         &self,
         changesetid: &HgChangesetId,
@@ -458,9 +608,44 @@ impl BlobRepo {
             .list_by_prefix(&BookmarkPrefix::empty(), &self.repoid)
     }
 
-    pub fn update_bookmark_transaction(&self) -> Box<bookmarks::Transaction> {
+    /// `session` identifies who moves the bookmarks in their update logs
+    pub fn update_bookmark_transaction(
+        &self,
+        reason: BookmarkUpdateReason,
+        session: &str,
+    ) -> Box<bookmarks::Transaction> {
         STATS::update_bookmark_transaction.add_value(1);
-        self.bookmarks.create_transaction(&self.repoid)
+        self.bookmarks.create_transaction(&self.repoid, reason, session)
+    }
+
+    /// Returns the moves of a bookmark, the most recent first
+    pub fn read_bookmark_log(&self, name: &Bookmark) -> BoxStream<BookmarkUpdateLogEntry, Error> {
+        STATS::read_bookmark_log.add_value(1);
+        self.bookmarks.read_log(name, &self.repoid)
+    }
+
+    /// Scratch bookmarks are created by infinitepush pushes. They are kept apart from the
+    /// public bookmarks, so they are never returned by `get_bookmarks` or `get_heads`.
+    pub fn get_scratch_bookmark(&self, name: &Bookmark) -> BoxFuture<Option<HgChangesetId>, Error> {
+        STATS::get_scratch_bookmark.add_value(1);
+        self.scratch_bookmarks.get(name, &self.repoid)
+    }
+
+    pub fn get_scratch_bookmarks_by_prefix(
+        &self,
+        prefix: &BookmarkPrefix,
+    ) -> BoxStream<(Bookmark, HgChangesetId), Error> {
+        STATS::get_scratch_bookmarks_by_prefix.add_value(1);
+        self.scratch_bookmarks.list_by_prefix(prefix, &self.repoid)
+    }
+
+    pub fn update_scratch_bookmark_transaction(
+        &self,
+        reason: BookmarkUpdateReason,
+        session: &str,
+    ) -> Box<bookmarks::Transaction> {
+        STATS::update_scratch_bookmark_transaction.add_value(1);
+        self.scratch_bookmarks.create_transaction(&self.repoid, reason, session)
     }
 
     pub fn get_linknode(&self, path: RepoPath, node: &HgNodeHash) -> BoxFuture<HgNodeHash, Error> {
@@ -491,6 +676,136 @@ impl BlobRepo {
             .boxify()
     }
 
+    pub fn get_bonsai_changeset(&self, bcs_id: &ChangesetId) -> BoxFuture<BonsaiChangeset, Error> {
+        STATS::get_bonsai_changeset.add_value(1);
+        self.fetch(bcs_id).boxify()
+    }
+
+    /// Returns the id of the bonsai changeset this Mercurial changeset was derived from
+    pub fn get_bonsai_from_hg(
+        &self,
+        hg_cs_id: &HgChangesetId,
+    ) -> BoxFuture<Option<ChangesetId>, Error> {
+        STATS::get_bonsai_from_hg.add_value(1);
+        self.bonsai_hg_mapping.get_bonsai_from_hg(self.repoid, *hg_cs_id)
+    }
+
+    /// Returns the id of the Mercurial changeset derived from this bonsai changeset
+    pub fn get_hg_from_bonsai(
+        &self,
+        bcs_id: &ChangesetId,
+    ) -> BoxFuture<Option<HgChangesetId>, Error> {
+        STATS::get_hg_from_bonsai.add_value(1);
+        self.bonsai_hg_mapping.get_hg_from_bonsai(self.repoid, *bcs_id)
+    }
+
+    /// Store the bonsai changeset `bcs`, and record that the Mercurial changeset `hg_cs_id` was
+    /// derived from it
+    pub fn store_bonsai_changeset(
+        &self,
+        hg_cs_id: HgChangesetId,
+        bcs: BonsaiChangeset,
+    ) -> BoxFuture<ChangesetId, Error> {
+        let bonsai_hg_mapping = self.bonsai_hg_mapping.clone();
+        let repo_id = self.repoid;
+        self.upload_blob(bcs.into_blob())
+            .and_then(move |bcs_id| {
+                bonsai_hg_mapping
+                    .add(BonsaiHgMappingEntry {
+                        repo_id,
+                        hg_cs_id,
+                        bcs_id,
+                    })
+                    .map(move |_| bcs_id)
+            })
+            .boxify()
+    }
+
+    /// Derive and store the bonsai changesets of `head` and of all its ancestors that have none.
+    /// This walks the whole history of repos imported before bonsai changesets were stored, so
+    /// it's meant for offline backfills.
+    pub fn backfill_bonsai_changesets(&self, head: HgChangesetId) -> BoxFuture<(), Error> {
+        backfill_bonsai_changesets(self.clone(), head)
+    }
+
+    /// Store the contents of files larger than `threshold` bytes in the LFS blobstore when they
+    /// are uploaded. If it's None, all contents are stored in the blobstore.
+    pub fn set_lfs_threshold(&mut self, threshold: Option<u64>) {
+        self.lfs_threshold = threshold;
+    }
+
+    /// Split the contents of files larger than the threshold into chunks with the chunker when
+    /// they are uploaded, unless they go to the LFS blobstore. If it's None, no contents are
+    /// chunked.
+    pub fn set_chunking(&mut self, chunking: Option<(u64, Chunker)>) {
+        self.chunking = chunking;
+    }
+
+    /// Returns the contents with this SHA-256 hash from the LFS blobstore, if they are stored
+    pub fn get_lfs_content(&self, sha256: &Sha256) -> BoxFuture<Option<Bytes>, Error> {
+        STATS::get_lfs_content.add_value(1);
+        fetch_lfs_content_opt(&self.blobstore, sha256).boxify()
+    }
+
+    pub fn lfs_content_exists(&self, sha256: &Sha256) -> BoxFuture<bool, Error> {
+        STATS::lfs_content_exists.add_value(1);
+        lfs_content_exists(&self.blobstore, sha256).boxify()
+    }
+
+    /// Store `content` in the LFS blobstore, and return the pointer to it
+    pub fn upload_lfs_content(&self, content: Bytes) -> BoxFuture<LfsPointer, Error> {
+        STATS::upload_lfs_content.add_value(1);
+        let pointer = LfsPointer::from_content(&content);
+        store_lfs_content(&self.blobstore, &pointer, content)
+            .map(move |()| pointer)
+            .boxify()
+    }
+
+    /// Store the contents `content` streams in the LFS blobstore without holding all of them in
+    /// memory, and return the pointer to them. They are only stored if their SHA-256 hash is
+    /// `sha256`, and the upload fails with `ErrorKind::LfsHashMismatch` otherwise.
+    pub fn upload_lfs_content_stream<S>(
+        &self,
+        sha256: Sha256,
+        content: S,
+    ) -> BoxFuture<LfsPointer, Error>
+    where
+        S: Stream<Item = Bytes, Error = Error> + Send + 'static,
+    {
+        STATS::upload_lfs_content_stream.add_value(1);
+        store_lfs_content_stream(&self.blobstore, sha256, content).boxify()
+    }
+
+    /// If LFS is enabled and `raw_content` is the pointer file that Mercurial's lfs extension
+    /// stores instead of the contents of a file, returns the future that records where the
+    /// contents it points to are stored and returns their meta, to upload the file with
+    /// `UploadHgFileContents::ContentUploaded`. The contents must already be in the LFS
+    /// blobstore, and are hashed as they are streamed from there.
+    pub fn upload_hg_lfs_pointer(
+        &self,
+        raw_content: &[u8],
+    ) -> Option<BoxFuture<ContentBlobMeta, Error>> {
+        if self.lfs_threshold.is_none() {
+            return None;
+        }
+        let (pointer, copy_from) = parse_hg_lfs_pointer(raw_content)?;
+        STATS::upload_hg_lfs_pointer.add_value(1);
+
+        let blobstore = self.blobstore.clone();
+        let upload = stream_lfs_content(&self.blobstore, pointer)
+            .fold(ContentIdHasher::new(pointer.size()), |mut hasher, chunk| {
+                hasher.update(chunk);
+                Ok::<_, Error>(hasher)
+            })
+            .and_then(move |hasher| {
+                let id = hasher.finish();
+                store_external_contents(&blobstore, id, ExternalContents::Lfs(pointer))
+                    .map(move |()| ContentBlobMeta { id, copy_from })
+            })
+            .boxify();
+        Some(upload)
+    }
+
     pub fn upload_blob<Id>(&self, blob: Blob<Id>) -> impl Future<Item = Id, Error = Error> + Send
     where
         Id: MononokeId,
@@ -542,27 +857,60 @@ impl BlobRepo {
         match change {
             None => Either::A(future::ok(None)),
             Some(change) => {
-                let upload_entry = UploadHgFileEntry {
-                    upload_node_id: UploadHgNodeHash::Generate,
-                    contents: UploadHgFileContents::ContentUploaded(ContentBlobMeta {
-                        id: *change.content_id(),
-                        // FIXME: need external {ChangesetID -> HgNodeHash} mapping
-                        copy_from: None,
-                    }),
-                    file_type: change.file_type(),
-                    p1,
-                    p2,
-                    path: path.clone(),
-                };
-                let upload_fut = match upload_entry.upload(self) {
-                    Ok((_, upload_fut)) => upload_fut,
-                    Err(err) => return Either::A(future::err(err)),
+                let copy_from = match change.copy_from() {
+                    None => Either::A(future::ok(None)),
+                    Some(&(ref copy_path, bcs_id)) => {
+                        Either::B(self.find_copy_source(copy_path.clone(), bcs_id).map(Some))
+                    }
                 };
-                Either::B(upload_fut.map(|(entry, _)| Some(entry)))
+                let repo = self.clone();
+                let change = change.clone();
+                let path = path.clone();
+                let upload = copy_from.and_then(move |copy_from| {
+                    let upload_entry = UploadHgFileEntry {
+                        upload_node_id: UploadHgNodeHash::Generate,
+                        contents: UploadHgFileContents::ContentUploaded(ContentBlobMeta {
+                            id: *change.content_id(),
+                            copy_from,
+                        }),
+                        file_type: change.file_type(),
+                        p1,
+                        p2,
+                        path,
+                    };
+                    let (_, upload_fut) = try_boxfuture!(upload_entry.upload(&repo));
+                    upload_fut.map(|(entry, _)| Some(entry)).boxify()
+                });
+                Either::B(upload)
             }
         }
     }
 
+    /// Bonsai copy info refers to a changeset, while Mercurial copy info refers to a filenode,
+    /// so find the filenode of `path` in the Mercurial changeset derived from `bcs_id`.
+    fn find_copy_source(
+        &self,
+        path: MPath,
+        bcs_id: ChangesetId,
+    ) -> impl Future<Item = (MPath, HgNodeHash), Error = Error> + Send {
+        let repo = self.clone();
+        self.get_hg_from_bonsai(&bcs_id)
+            .and_then(move |hg_cs_id| {
+                hg_cs_id.ok_or_else(|| ErrorKind::HgMappingNotFound(bcs_id).into())
+            })
+            .and_then({
+                let repo = repo.clone();
+                move |hg_cs_id| repo.get_changeset_by_changesetid(&hg_cs_id)
+            })
+            .and_then(move |cs| {
+                repo.find_file_in_manifest(&path, cs.manifestid().into_nodehash())
+                    .and_then(move |node| match node {
+                        Some(node) => Ok((path, node)),
+                        None => Err(ErrorKind::CopySourceNotFound(path, bcs_id).into()),
+                    })
+            })
+    }
+
     pub fn find_path_in_manifest(
         &self,
         path: Option<MPath>,
@@ -845,40 +1193,79 @@ impl UploadHgFileContents {
                 let f = File::new(raw_content, p1.as_ref(), p2.as_ref());
                 let metadata = f.metadata();
 
-                let copy_from = match f.copied_from() {
-                    Ok(copy_from) => copy_from,
-                    // XXX error out if copy-from information couldn't be read?
-                    Err(_err) => None,
-                };
+                let copy_from = get_copy_from(&f);
                 // Upload the contents separately (they'll be used for bonsai changesets as well).
                 let contents = f.file_contents();
                 let size = contents.size() as u64;
-                let contents_blob = contents.into_blob();
+                // Contents are always keyed by their bytes, no matter how they are stored.
+                let content_id = contents.content_id();
                 let cbinfo = ContentBlobInfo {
                     path: path.clone(),
                     meta: ContentBlobMeta {
-                        id: *contents_blob.id(),
+                        id: content_id,
                         copy_from,
                     },
                 };
 
-                let upload_fut = repo.upload_blob(contents_blob)
-                    .map(|_content_id| ())
-                    .timed({
-                        let logger = repo.logger.clone();
-                        move |stats, result| {
-                            if result.is_ok() {
-                                UploadHgFileEntry::log_stats(
-                                    logger,
-                                    path,
-                                    node_id,
-                                    "content_uploaded",
-                                    stats,
-                                );
-                            }
-                            Ok(())
+                // Large contents go to the LFS blobstore, and huge contents are split into
+                // chunks. Either way, they get no content blob, and where they are stored is
+                // recorded separately once they are.
+                let chunker = match repo.chunking {
+                    Some((threshold, chunker)) if size > threshold => Some(chunker),
+                    _ => None,
+                };
+                let upload_fut = match (repo.lfs_threshold, chunker) {
+                    (Some(threshold), _) if size > threshold => {
+                        let content = contents.into_bytes();
+                        let pointer = LfsPointer::from_content(&content);
+                        let blobstore = repo.blobstore.clone();
+                        store_lfs_content(&repo.blobstore, &pointer, content)
+                            .and_then(move |()| {
+                                store_external_contents(
+                                    &blobstore,
+                                    content_id,
+                                    ExternalContents::Lfs(pointer),
+                                )
+                            })
+                            .boxify()
+                    }
+                    (_, Some(chunker)) => {
+                        let (chunked, chunks) = chunker.chunk(contents.into_bytes());
+                        let blobstore = repo.blobstore.clone();
+                        stream::iter_ok(chunks)
+                            .map({
+                                let repo = repo.clone();
+                                move |chunk| repo.upload_blob(chunk)
+                            })
+                            .buffer_unordered(MAX_CONCURRENT_CHUNK_UPLOADS)
+                            .for_each(|_| Ok(()))
+                            .and_then(move |()| {
+                                store_external_contents(
+                                    &blobstore,
+                                    content_id,
+                                    ExternalContents::Chunked(chunked),
+                                )
+                            })
+                            .boxify()
+                    }
+                    _ => repo.upload_blob(contents.into_blob()).map(|_| ()).boxify(),
+                };
+
+                let upload_fut = upload_fut.timed({
+                    let logger = repo.logger.clone();
+                    move |stats, result| {
+                        if result.is_ok() {
+                            UploadHgFileEntry::log_stats(
+                                logger,
+                                path,
+                                node_id,
+                                "content_uploaded",
+                                stats,
+                            );
                         }
-                    });
+                        Ok(())
+                    }
+                });
                 let compute_fut = future::ok((node_id, metadata, size));
 
                 (cbinfo, Either::B(upload_fut), Either::B(compute_fut))
@@ -892,21 +1279,12 @@ impl UploadHgFileContents {
         p1: Option<HgNodeHash>,
         p2: Option<HgNodeHash>,
     ) -> impl Future<Item = (HgNodeHash, Bytes, u64), Error = Error> {
-        // Computing the file node hash requires fetching the blob and gluing it together with the
-        // metadata.
-        repo.fetch(&cbmeta.id).map(move |file_contents| {
-            let size = file_contents.size() as u64;
-            let mut metadata = Vec::new();
-            File::generate_metadata(cbmeta.copy_from.as_ref(), &file_contents, &mut metadata)
-                .expect("Vec::write_all should never fail");
-
-            let file_bytes = file_contents.into_bytes();
-
-            // XXX this is just a hash computation, so it shouldn't require a copy
-            let raw_content = [&metadata[..], &file_bytes[..]].concat();
-            let node_id = Self::node_id(raw_content, p1.as_ref(), p2.as_ref());
-            (node_id, Bytes::from(metadata), size)
-        })
+        // Computing the file node hash requires the contents together with the metadata. They
+        // are hashed as they are streamed, so that huge contents are never held in memory.
+        let hasher = FileNodeHasher::new(cbmeta.copy_from, p1, p2);
+        fetch_file_contents_stream(&repo.blobstore, cbmeta.id)
+            .fold(hasher, |hasher, chunk| Ok::<_, Error>(hasher.update(chunk)))
+            .map(FileNodeHasher::finish)
     }
 
     #[inline]
@@ -922,6 +1300,76 @@ impl UploadHgFileContents {
     }
 }
 
+/// Computes the node hash of a file from the stream of its contents. The metadata that goes
+/// before the contents depends on their first bytes, so those are held back until there are
+/// enough of them to generate it.
+struct FileNodeHasher {
+    copy_from: Option<(MPath, HgNodeHash)>,
+    p1: Option<HgNodeHash>,
+    p2: Option<HgNodeHash>,
+    head: BytesMut,
+    hasher: Option<HgNodeHasher>,
+    metadata: Bytes,
+    size: u64,
+}
+
+impl FileNodeHasher {
+    fn new(
+        copy_from: Option<(MPath, HgNodeHash)>,
+        p1: Option<HgNodeHash>,
+        p2: Option<HgNodeHash>,
+    ) -> Self {
+        Self {
+            copy_from,
+            p1,
+            p2,
+            head: BytesMut::new(),
+            hasher: None,
+            metadata: Bytes::new(),
+            size: 0,
+        }
+    }
+
+    fn update(mut self, chunk: Bytes) -> Self {
+        self.size += chunk.len() as u64;
+        match self.hasher {
+            Some(ref mut hasher) => hasher.update(&chunk),
+            None => {
+                self.head.extend_from_slice(&chunk);
+                if self.head.len() >= META_SZ {
+                    self.start_hashing();
+                }
+            }
+        }
+        self
+    }
+
+    fn start_hashing(&mut self) {
+        let head = self.head.take().freeze();
+        let mut metadata = Vec::new();
+        File::generate_metadata(
+            self.copy_from.as_ref(),
+            &FileContents::new_bytes(head.clone()),
+            &mut metadata,
+        ).expect("Vec::write_all should never fail");
+
+        let mut hasher = HgNodeHasher::new(self.p1.as_ref(), self.p2.as_ref());
+        hasher.update(&metadata);
+        hasher.update(&head);
+        self.hasher = Some(hasher);
+        self.metadata = Bytes::from(metadata);
+    }
+
+    fn finish(mut self) -> (HgNodeHash, Bytes, u64) {
+        if self.hasher.is_none() {
+            // The contents are shorter than what the metadata depends on
+            self.start_hashing();
+        }
+        let hasher = self.hasher.expect("hashing must have started");
+        (hasher.finish(), self.metadata, self.size)
+    }
+}
+
 /// Context for uploading a Mercurial file entry.
 pub struct UploadHgFileEntry {
     pub upload_node_id: UploadHgNodeHash,
@@ -1061,6 +1509,9 @@ pub struct CreateChangeset {
     pub time: DateTime,
     pub extra: BTreeMap<Vec<u8>, Vec<u8>>,
     pub comments: String,
+    /// The bonsai changeset this changeset is derived from. If it's None, the bonsai changeset
+    /// is derived from this changeset instead.
+    pub bonsai: Option<BonsaiChangeset>,
 }
 
 impl CreateChangeset {
@@ -1215,13 +1666,36 @@ impl CreateChangeset {
                 }
             });
 
+        let bonsai = self.bonsai;
         let complete_changesets = repo.changesets.clone();
         let repo_id = repo.repoid;
+        let repo = repo.clone();
         ChangesetHandle::new_pending(
             can_be_parent.shared(),
             changeset
                 .join(parents_complete)
                 .and_then(move |(cs, _)| {
+                    // The bonsai changeset and its mapping are stored before the changeset is
+                    // marked as complete, so that children can always find their parents' bonsai
+                    // counterparts. Changesets whose parents have no bonsai counterparts get none
+                    // either, until backfill_bonsai_changesets derives them.
+                    let bcs = match bonsai {
+                        Some(bcs) => future::ok(Some(bcs)).boxify(),
+                        None => make_bonsai_changeset(repo.clone(), cs.clone()),
+                    };
+                    let hg_cs_id = cs.get_changeset_id();
+                    bcs.and_then(move |bcs| match bcs {
+                        Some(bcs) => repo.store_bonsai_changeset(hg_cs_id, bcs)
+                            .map(|_| ())
+                            .boxify(),
+                        None => {
+                            STATS::create_changeset_without_bonsai.add_value(1);
+                            future::ok(()).boxify()
+                        }
+                    }).context("While creating bonsai changeset")
+                        .map(move |()| cs)
+                })
+                .and_then(move |cs| {
                     let completion_record = ChangesetInsert {
                         repo_id: repo_id,
                         cs_id: cs.get_changeset_id(),
@@ -1263,9 +1737,13 @@ impl Clone for BlobRepo {
         Self {
             logger: self.logger.clone(),
             bookmarks: self.bookmarks.clone(),
+            scratch_bookmarks: self.scratch_bookmarks.clone(),
             blobstore: self.blobstore.clone(),
+            lfs_threshold: self.lfs_threshold,
+            chunking: self.chunking,
             filenodes: self.filenodes.clone(),
             changesets: self.changesets.clone(),
+            bonsai_hg_mapping: self.bonsai_hg_mapping.clone(),
             repoid: self.repoid.clone(),
         }
     }
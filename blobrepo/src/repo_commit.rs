@@ -10,7 +10,7 @@ use std::sync::{Arc, Mutex};
 
 use failure::{err_msg, Compat, FutureFailureErrorExt};
 use futures::IntoFuture;
-use futures::future::{self, Future, Shared, SharedError, SharedItem};
+use futures::future::{self, Either, Future, Loop, Shared, SharedError, SharedItem};
 use futures::stream::{self, Stream};
 use futures::sync::oneshot;
 use futures_ext::{BoxFuture, BoxStream, FutureExt, StreamExt};
@@ -19,6 +19,7 @@ use scuba_ext::{ScubaSampleBuilder, ScubaSampleBuilderExt};
 use stats::Timeseries;
 
 use blobstore::Blobstore;
+use bonsai_utils::{bonsai_diff, BonsaiDiffResult};
 use filenodes::{FilenodeInfo, Filenodes};
 use mercurial::file;
 use mercurial_types::{Changeset, Entry, HgChangesetId, HgEntryId, HgNodeHash, HgNodeKey,
@@ -26,13 +27,14 @@ use mercurial_types::{Changeset, Entry, HgChangesetId, HgEntryId, HgNodeHash, Hg
 use mercurial_types::manifest::{self, Content};
 use mercurial_types::manifest_utils::{changed_entry_stream, EntryStatus};
 use mercurial_types::nodehash::{HgFileNodeId, HgManifestId};
-use mononoke_types::DateTime;
+use mononoke_types::{BonsaiChangeset, ChangesetId, DateTime, FileChange, FileType};
+use mononoke_types::bonsai_changeset::BonsaiChangesetMut;
 
 use BlobChangeset;
 use BlobRepo;
 use changeset::ChangesetContent;
 use errors::*;
-use file::HgBlobEntry;
+use file::{fetch_file_envelope, get_copy_from, HgBlobEntry};
 use repo::RepoBlobstore;
 
 define_stats! {
@@ -678,6 +680,200 @@ pub fn make_new_changeset(
     BlobChangeset::new(changeset)
 }
 
+/// Derive the bonsai form of a newly created Mercurial changeset. Resolves to None if any of its
+/// parents has no bonsai counterpart, because it was imported before bonsai changesets were
+/// stored and hasn't been backfilled yet.
+pub fn make_bonsai_changeset(
+    repo: BlobRepo,
+    cs: BlobChangeset,
+) -> BoxFuture<Option<BonsaiChangeset>, Error> {
+    let parents = cs.parents()
+        .into_iter()
+        .map(|p| HgChangesetId::new(p))
+        .map({
+            let repo = repo.clone();
+            move |p| {
+                let bcs_id = repo.get_bonsai_from_hg(&p);
+                let manifest_id = repo.get_changeset_by_changesetid(&p)
+                    .map(|cs| *cs.manifestid());
+                bcs_id.join(manifest_id)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    future::join_all(parents)
+        .and_then(move |parents| {
+            let parents = match parents
+                .into_iter()
+                .map(|(bcs_id, mf)| bcs_id.map(|bcs_id| (bcs_id, mf)))
+                .collect::<Option<Vec<_>>>()
+            {
+                Some(parents) => parents,
+                None => return future::ok(None).boxify(),
+            };
+            make_bonsai_with_parents(repo, cs, parents)
+                .map(Some)
+                .boxify()
+        })
+        .boxify()
+}
+
+fn make_bonsai_with_parents(
+    repo: BlobRepo,
+    cs: BlobChangeset,
+    parents: Vec<(ChangesetId, HgManifestId)>,
+) -> impl Future<Item = BonsaiChangeset, Error = Error> + Send {
+    let root_entry = repo.get_root_entry(cs.manifestid());
+    let p1_entry = parents.get(0).map(|(_, mf)| repo.get_root_entry(mf));
+    let p2_entry = parents.get(1).map(|(_, mf)| repo.get_root_entry(mf));
+
+    bonsai_diff(root_entry, p1_entry, p2_entry)
+        .map({
+            let repo = repo.clone();
+            let parents = parents.clone();
+            move |diff| match diff {
+                BonsaiDiffResult::Changed(path, file_type, entry_id) => {
+                    let file_change = make_file_change(
+                        repo.clone(),
+                        parents.clone(),
+                        file_type,
+                        entry_id.into_nodehash(),
+                    );
+                    Either::A(file_change.map(move |fc| (path, Some(fc))))
+                }
+                BonsaiDiffResult::Deleted(path) => Either::B(future::ok((path, None))),
+            }
+        })
+        .buffer_unordered(100)
+        .collect()
+        .and_then(move |file_changes| {
+            BonsaiChangesetMut {
+                parents: parents.into_iter().map(|(bcs_id, _)| bcs_id).collect(),
+                author: String::from_utf8_lossy(cs.user()).into_owned(),
+                author_date: *cs.time(),
+                committer: None,
+                committer_date: None,
+                message: String::from_utf8_lossy(cs.comments()).into_owned(),
+                extra: cs.extra()
+                    .iter()
+                    .map(|(key, value)| {
+                        (
+                            String::from_utf8_lossy(key).into_owned(),
+                            String::from_utf8_lossy(value).into_owned(),
+                        )
+                    })
+                    .collect(),
+                file_changes: file_changes.into_iter().collect(),
+            }.freeze()
+        })
+}
+
+/// Derive and store the bonsai counterparts of `head` and of all its ancestors that have none.
+/// They are derived parents first, so that each of them finds the bonsai counterparts of its
+/// parents. This walks the history down to the changesets that have bonsai counterparts, which
+/// for repos imported before bonsai changesets were stored is the whole history, so it's meant
+/// to be run offline rather than when changesets are created.
+pub fn backfill_bonsai_changesets(repo: BlobRepo, head: HgChangesetId) -> BoxFuture<(), Error> {
+    let find_missing = future::loop_fn(
+        (vec![head], HashSet::new(), Vec::new()),
+        {
+            let repo = repo.clone();
+            move |(mut queue, mut seen, mut missing): (Vec<_>, HashSet<_>, Vec<_>)| {
+                let cs_id = match queue.pop() {
+                    Some(cs_id) => cs_id,
+                    None => return future::ok(Loop::Break(missing)).boxify(),
+                };
+                if !seen.insert(cs_id) {
+                    return future::ok(Loop::Continue((queue, seen, missing))).boxify();
+                }
+                repo.get_bonsai_from_hg(&cs_id)
+                    .join(repo.get_changeset_parents(&cs_id))
+                    .map(move |(bcs_id, parents)| {
+                        if bcs_id.is_none() {
+                            missing.push(cs_id);
+                            queue.extend(parents);
+                        }
+                        Loop::Continue((queue, seen, missing))
+                    })
+                    .boxify()
+            }
+        },
+    );
+
+    find_missing
+        .and_then({
+            let repo = repo.clone();
+            move |missing| {
+                stream::iter_ok(missing)
+                    .map(move |cs_id| {
+                        repo.get_generation_number(&cs_id).and_then(move |generation| {
+                            let generation =
+                                generation.ok_or(ErrorKind::ChangesetMissing(cs_id))?;
+                            Ok((generation, cs_id))
+                        })
+                    })
+                    .buffered(100)
+                    .collect()
+            }
+        })
+        .and_then(move |mut missing| {
+            // Parents have lower generation numbers than their children
+            missing.sort();
+            stream::iter_ok(missing).for_each(move |(_, cs_id)| {
+                let repo = repo.clone();
+                repo.get_changeset_by_changesetid(&cs_id)
+                    .and_then({
+                        let repo = repo.clone();
+                        move |cs| make_bonsai_changeset(repo, cs)
+                    })
+                    .and_then(move |bcs| {
+                        // The parents were derived before
+                        let bcs = bcs.ok_or(ErrorKind::BonsaiMappingNotFound(cs_id))?;
+                        Ok(repo.store_bonsai_changeset(cs_id, bcs))
+                    })
+                    .flatten()
+                    .map(|_| ())
+            })
+        })
+        .boxify()
+}
+
+fn make_file_change(
+    repo: BlobRepo,
+    parents: Vec<(ChangesetId, HgManifestId)>,
+    file_type: FileType,
+    node_id: HgNodeHash,
+) -> impl Future<Item = FileChange, Error = Error> + Send {
+    fetch_file_envelope(&repo.get_blobstore(), node_id).and_then(move |envelope| {
+        let envelope = envelope.into_mut();
+        let f = file::File::new(envelope.metadata, envelope.p1.as_ref(), envelope.p2.as_ref());
+        let copy_from = match get_copy_from(&f) {
+            None => Either::A(future::ok(None)),
+            Some((path, copy_node)) => {
+                // Bonsai copy info refers to a parent changeset rather than a filenode, so
+                // find the parent that has the copy source.
+                let checks = parents.into_iter().map(move |(bcs_id, mf)| {
+                    repo.find_file_in_manifest(&path, mf.into_nodehash())
+                        .map({
+                            let path = path.clone();
+                            move |node| match node {
+                                Some(node) if node == copy_node => Some((path, bcs_id)),
+                                _ => None,
+                            }
+                        })
+                });
+                let copy_from = future::join_all(checks)
+                    .map(|found| found.into_iter().filter_map(|copy_from| copy_from).next());
+                Either::B(copy_from)
+            }
+        };
+
+        let content_id = envelope.content_id;
+        let size = envelope.content_size;
+        copy_from.map(move |copy_from| FileChange::new(content_id, file_type, size, copy_from))
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
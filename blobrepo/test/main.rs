@@ -26,17 +26,22 @@ extern crate mercurial_types_mocks;
 extern crate merge_uneven;
 extern crate mononoke_types;
 
+use bytes::Bytes;
 use failure::Error;
-use futures::Future;
+use futures::{future, stream, Future, Stream};
 use futures_ext::{BoxFuture, FutureExt};
 use quickcheck::{quickcheck, Arbitrary, Gen, TestResult, Testable};
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::marker::PhantomData;
 
-use blobrepo::{compute_changed_files, BlobRepo, ErrorKind};
+use blobrepo::{compute_changed_files, BlobRepo, ErrorKind, HgBlobEntry, UploadHgFileContents,
+               UploadHgFileEntry, UploadHgNodeHash};
+use blobstore::Blobstore;
+use mercurial::file::File;
 use mercurial_types::{manifest, Changeset, Entry, FileType, HgChangesetId, HgEntryId,
                       HgManifestId, HgNodeHash, HgParents, MPath, MPathElement, RepoPath};
-use mononoke_types::{BonsaiChangeset, ChangesetId, ContentId, DateTime, FileChange, FileContents,
+use mononoke_types::{BlobstoreValue, BonsaiChangeset, ChangesetId, Chunker, ContentId,
+                     DateTime, ExternalContents, FileChange, FileContents, LfsPointer,
                      MononokeId};
 use mononoke_types::bonsai_changeset::BonsaiChangesetMut;
 
@@ -124,6 +129,134 @@ test_both_repotypes!(
     upload_blob_one_parent_eager
 );
 
+/// Contents that are stored out of band or chunked keep the id of their bytes, but have no
+/// content blob
+fn assert_stored_externally(repo: &BlobRepo, contents: &'static str) {
+    let content_id = *FileContents::new_bytes(contents).into_blob().id();
+    let blobstore = repo.get_blobstore();
+    assert!(!run_future(blobstore.is_present(content_id.blobstore_key())).unwrap());
+    let external_key = ExternalContents::blobstore_key(&content_id);
+    assert!(run_future(blobstore.is_present(external_key)).unwrap());
+}
+
+fn upload_lfs_blob(mut repo: BlobRepo) {
+    // The hash is the same as if the contents were stored in the blobstore
+    let expected_hash = string_to_nodehash("c3127cdbf2eae0f09653f9237d85c8436425b246");
+    let fake_path = RepoPath::file("fake/file").expect("Can't generate fake RepoPath");
+    let pointer = LfsPointer::from_content("blob");
+
+    repo.set_lfs_threshold(Some(2));
+    assert!(!run_future(repo.lfs_content_exists(pointer.sha256())).unwrap());
+
+    let (hash, future) = upload_file_no_parents(&repo, "blob", &fake_path);
+    assert!(hash == expected_hash);
+    let (entry, _) = run_future(future).unwrap();
+
+    // The contents were stored out of band...
+    assert!(run_future(repo.lfs_content_exists(pointer.sha256())).unwrap());
+    let lfs_content = run_future(repo.get_lfs_content(pointer.sha256())).unwrap();
+    assert_eq!(lfs_content.expect("LFS content is missing").as_ref(), &b"blob"[..]);
+
+    // ...but they are read back transparently
+    let content = run_future(entry.get_content()).unwrap();
+    match content {
+        manifest::Content::File(FileContents::Bytes(f)) => assert_eq!(f.as_ref(), &b"blob"[..]),
+        _ => panic!(),
+    };
+    let bytes = run_future(repo.get_file_content(&expected_hash)).unwrap();
+    assert!(&bytes.into_bytes() == &b"blob"[..]);
+
+    assert_stored_externally(&repo, "blob");
+}
+
+test_both_repotypes!(upload_lfs_blob, upload_lfs_blob_lazy, upload_lfs_blob_eager);
+
+fn upload_lfs_pointer(mut repo: BlobRepo) {
+    // The hash is the same as if the contents themselves were uploaded
+    let expected_hash = string_to_nodehash("c3127cdbf2eae0f09653f9237d85c8436425b246");
+    let pointer = LfsPointer::from_content("blob");
+    let pointer_file = pointer.to_pointer_file();
+
+    // Pointers are regular contents if LFS is disabled
+    assert!(repo.upload_hg_lfs_pointer(&pointer_file).is_none());
+    repo.set_lfs_threshold(Some(2));
+
+    // The contents are uploaded first, and only if they match the hash they are uploaded as
+    let wrong = LfsPointer::from_content("wrong");
+    let content = stream::iter_ok(vec![Bytes::from("bl"), Bytes::from("ob")]);
+    let err = run_future(repo.upload_lfs_content_stream(*wrong.sha256(), content))
+        .expect_err("unexpected OK - hash mismatch");
+    match err.downcast::<ErrorKind>() {
+        Ok(ErrorKind::LfsHashMismatch(..)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert!(!run_future(repo.lfs_content_exists(wrong.sha256())).unwrap());
+
+    let content = stream::iter_ok(vec![Bytes::from("bl"), Bytes::from("ob")]);
+    let uploaded = run_future(repo.upload_lfs_content_stream(*pointer.sha256(), content)).unwrap();
+    assert_eq!(uploaded, pointer);
+
+    let meta = run_future(repo.upload_hg_lfs_pointer(&pointer_file).expect("not a pointer"))
+        .unwrap();
+    assert_eq!(meta.id, FileContents::new_bytes("blob").content_id());
+    assert_eq!(meta.copy_from, None);
+
+    let upload = UploadHgFileEntry {
+        upload_node_id: UploadHgNodeHash::Checked(expected_hash),
+        contents: UploadHgFileContents::ContentUploaded(meta),
+        file_type: FileType::Regular,
+        p1: None,
+        p2: None,
+        path: MPath::new("fake/file").unwrap(),
+    };
+    let (_, future) = upload.upload(&repo).unwrap();
+    run_future(future).unwrap();
+
+    let bytes = run_future(repo.get_file_content(&expected_hash)).unwrap();
+    assert!(&bytes.into_bytes() == &b"blob"[..]);
+    assert_stored_externally(&repo, "blob");
+
+    // The same pointer is sent back to clients with the lfs extension
+    let hg_pointer = run_future(repo.get_hg_lfs_pointer(&expected_hash)).unwrap();
+    assert_eq!(hg_pointer, Some(pointer_file));
+}
+
+test_both_repotypes!(
+    upload_lfs_pointer,
+    upload_lfs_pointer_lazy,
+    upload_lfs_pointer_eager
+);
+
+fn upload_chunked_blob(mut repo: BlobRepo) {
+    // The hash is the same as if the contents were stored as a single blob
+    let expected_hash = string_to_nodehash("c3127cdbf2eae0f09653f9237d85c8436425b246");
+    let fake_path = RepoPath::file("fake/file").expect("Can't generate fake RepoPath");
+
+    repo.set_chunking(Some((2, Chunker::FixedSize(3))));
+
+    let (hash, future) = upload_file_no_parents(&repo, "blob", &fake_path);
+    assert!(hash == expected_hash);
+    run_future(future).unwrap();
+
+    let bytes = run_future(repo.get_file_content(&expected_hash)).unwrap();
+    assert!(&bytes.into_bytes() == &b"blob"[..]);
+
+    let chunks = run_future(repo.get_file_content_stream(&expected_hash).collect()).unwrap();
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(chunks.concat(), b"blob".to_vec());
+
+    let size = run_future(repo.get_file_size(&expected_hash)).unwrap();
+    assert_eq!(size, 4);
+
+    assert_stored_externally(&repo, "blob");
+}
+
+test_both_repotypes!(
+    upload_chunked_blob,
+    upload_chunked_blob_lazy,
+    upload_chunked_blob_eager
+);
+
 fn create_one_changeset(repo: BlobRepo) {
     let fake_file_path = RepoPath::file("file").expect("Can't generate fake RepoPath");
     let fake_dir_path = RepoPath::dir("dir").expect("Can't generate fake RepoPath");
@@ -239,6 +372,128 @@ test_both_repotypes!(
     create_two_changesets_eager
 );
 
+fn create_bonsai_changesets(repo: BlobRepo) {
+    let fake_file_path = RepoPath::file("file").expect("Can't generate fake RepoPath");
+    let fake_dir_path = RepoPath::file("dir").expect("Can't generate fake RepoPath");
+
+    let (filehash, file_future) = upload_file_no_parents(&repo, "blob", &fake_file_path);
+
+    let (dirhash, manifest_dir_future) =
+        upload_manifest_no_parents(&repo, format!("file\0{}\n", filehash), &fake_dir_path);
+
+    let (roothash, root_manifest_future) =
+        upload_manifest_no_parents(&repo, format!("dir\0{}t\n", dirhash), &RepoPath::root());
+
+    let commit1 = create_changeset_no_parents(
+        &repo,
+        root_manifest_future.map(Some).boxify(),
+        vec![file_future, manifest_dir_future],
+    );
+
+    let (_, root_manifest_future) = upload_manifest_one_parent(
+        &repo,
+        format!("file\0{}\n", filehash),
+        &RepoPath::root(),
+        roothash,
+    );
+
+    let commit2 = create_changeset_one_parent(
+        &repo,
+        root_manifest_future.map(Some).boxify(),
+        vec![],
+        commit1.clone(),
+    );
+
+    let (commit1, commit2) = run_future(
+        commit1
+            .get_completed_changeset()
+            .join(commit2.get_completed_changeset()),
+    ).unwrap();
+
+    let bcs1_id = run_future(repo.get_bonsai_from_hg(&commit1.get_changeset_id()))
+        .unwrap()
+        .expect("bonsai changeset is missing for commit1");
+    let bcs2_id = run_future(repo.get_bonsai_from_hg(&commit2.get_changeset_id()))
+        .unwrap()
+        .expect("bonsai changeset is missing for commit2");
+    assert_eq!(
+        run_future(repo.get_hg_from_bonsai(&bcs2_id)).unwrap(),
+        Some(commit2.get_changeset_id())
+    );
+
+    let content_id = *FileContents::new_bytes("blob").into_blob().id();
+    let file_change = FileChange::new(content_id, FileType::Regular, 4, None);
+
+    let bcs1 = run_future(repo.get_bonsai_changeset(&bcs1_id)).unwrap();
+    assert_eq!(bcs1.parents().collect::<Vec<_>>(), Vec::<&ChangesetId>::new());
+    assert_eq!(bcs1.author().as_bytes(), commit1.user());
+    assert_eq!(
+        bcs1.file_changes().collect::<Vec<_>>(),
+        vec![(&MPath::new("dir/file").unwrap(), Some(&file_change))]
+    );
+
+    let bcs2 = run_future(repo.get_bonsai_changeset(&bcs2_id)).unwrap();
+    assert_eq!(bcs2.parents().collect::<Vec<_>>(), vec![&bcs1_id]);
+    assert_eq!(
+        bcs2.file_changes().collect::<Vec<_>>(),
+        vec![
+            (&MPath::new("dir/file").unwrap(), None),
+            (&MPath::new("file").unwrap(), Some(&file_change)),
+        ]
+    );
+}
+
+test_both_repotypes!(
+    create_bonsai_changesets,
+    create_bonsai_changesets_lazy,
+    create_bonsai_changesets_eager
+);
+
+fn get_manifest_from_bonsai_with_copy(repo: BlobRepo) {
+    let fake_file_path = RepoPath::file("file").expect("Can't generate fake RepoPath");
+
+    let (filehash, file_future) = upload_file_no_parents(&repo, "blob", &fake_file_path);
+
+    let (roothash, root_manifest_future) =
+        upload_manifest_no_parents(&repo, format!("file\0{}\n", filehash), &RepoPath::root());
+
+    let commit = create_changeset_no_parents(
+        &repo,
+        root_manifest_future.map(Some).boxify(),
+        vec![file_future],
+    );
+    let commit = run_future(commit.get_completed_changeset()).unwrap();
+    let bcs_id = run_future(repo.get_bonsai_from_hg(&commit.get_changeset_id()))
+        .unwrap()
+        .expect("bonsai changeset is missing");
+
+    let content_id = run_future(repo.unittest_store(FileContents::new_bytes("blob"))).unwrap();
+    let copy_from = Some((MPath::new("file").unwrap(), bcs_id));
+    let file_change = FileChange::new(content_id, FileType::Regular, 4, copy_from);
+    let bcs = make_bonsai_changeset(Some(bcs_id), None, vec![("copy", Some(file_change))]);
+    let ms_hash = run_future(repo.get_manifest_from_bonsai(bcs, Some(&roothash), None))
+        .expect("copying a file should not fail");
+
+    // The copy source is recorded in the filenode the way Mercurial expects it
+    let manifest = run_future(repo.get_manifest_by_nodeid(&ms_hash)).unwrap();
+    let copy = manifest
+        .lookup(&MPathElement::new(b"copy".to_vec()).unwrap())
+        .expect("copy should be in the manifest");
+    let blob = run_future(copy.get_raw_content()).unwrap();
+    let parents = run_future(copy.get_parents()).unwrap();
+    let (p1, p2) = parents.get_nodes();
+    assert_eq!(
+        File::new(blob, p1, p2).copied_from().unwrap(),
+        Some((MPath::new("file").unwrap(), filehash))
+    );
+}
+
+test_both_repotypes!(
+    get_manifest_from_bonsai_with_copy,
+    get_manifest_from_bonsai_with_copy_lazy,
+    get_manifest_from_bonsai_with_copy_eager
+);
+
 fn create_bad_changeset(repo: BlobRepo) {
     let dirhash = string_to_nodehash("c2d60b35a8e7e034042a9467783bbdac88a0d219");
 
@@ -419,6 +674,59 @@ test_both_repotypes!(
     store_fetch_mononoke_types_eager
 );
 
+#[test]
+fn create_changeset_on_history_without_bonsai() {
+    async_unit::tokio_unit_test(|| {
+        // The fixture repos were imported without bonsai changesets
+        let repo = merge_uneven::getrepo(None);
+        let parent_id = HgChangesetId::new(string_to_nodehash(
+            "16839021e338500b3cf7c9b871c8a07351697d68",
+        ));
+        let root_id = HgChangesetId::new(string_to_nodehash(
+            "15c40d0abc36d47fb51c8eaec51ac7aad31f669c",
+        ));
+        assert!(
+            run_future(repo.get_bonsai_from_hg(&parent_id))
+                .unwrap()
+                .is_none()
+        );
+
+        let parent = run_future(repo.get_changeset_by_changesetid(&parent_id)).unwrap();
+        let root_manifest = HgBlobEntry::new_root(repo.get_blobstore(), *parent.manifestid());
+        let commit = create_changeset_one_parent(
+            &repo,
+            future::ok(Some((root_manifest, RepoPath::root()))).boxify(),
+            vec![],
+            parent.into(),
+        );
+        let commit = run_future(commit.get_completed_changeset()).unwrap();
+
+        // The ancestors aren't walked when creating changesets, so the new commit has no bonsai
+        // changeset until the history is backfilled
+        assert!(
+            run_future(repo.get_bonsai_from_hg(&commit.get_changeset_id()))
+                .unwrap()
+                .is_none()
+        );
+        run_future(repo.backfill_bonsai_changesets(commit.get_changeset_id())).unwrap();
+
+        // The bonsai changesets of the commit and of all its ancestors were derived
+        let bcs_id = run_future(repo.get_bonsai_from_hg(&commit.get_changeset_id()))
+            .unwrap()
+            .expect("bonsai changeset of the new commit is missing");
+        let parent_bcs_id = run_future(repo.get_bonsai_from_hg(&parent_id))
+            .unwrap()
+            .expect("bonsai changeset of the parent is missing");
+        let bcs = run_future(repo.get_bonsai_changeset(&bcs_id)).unwrap();
+        assert_eq!(bcs.parents().collect::<Vec<_>>(), vec![&parent_bcs_id]);
+        assert!(
+            run_future(repo.get_bonsai_from_hg(&root_id))
+                .unwrap()
+                .is_some()
+        );
+    })
+}
+
 #[test]
 fn test_compute_changed_files_no_parents() {
     async_unit::tokio_unit_test(|| {
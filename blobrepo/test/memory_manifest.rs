@@ -9,10 +9,11 @@ use futures::future::Future;
 use std::collections::BTreeMap;
 use std::sync::{Arc, Mutex};
 
-use blobrepo::HgBlobEntry;
+use blobrepo::{BlobManifest, HgBlobEntry};
 use blobrepo::internal::{MemoryManifestEntry, MemoryRootManifest};
 use many_files_dirs;
-use mercurial_types::{Entry, FileType, HgNodeHash, MPath, MPathElement, Type, nodehash::HgEntryId};
+use mercurial_types::{Entry, FileType, HgManifestId, HgNodeHash, MPath, MPathElement, Type,
+                      nodehash::HgEntryId};
 use mercurial_types_mocks::nodehash;
 use mononoke_types::RepoPath;
 
@@ -455,3 +456,89 @@ fn merge_manifests() {
         }
     })
 }
+
+#[test]
+fn rebase_manifest() {
+    async_unit::tokio_unit_test(|| {
+        let repo = many_files_dirs::getrepo(None);
+        let blobstore = repo.get_blobstore();
+
+        let manifest_id = HgNodeHash::from_static_str("b267a6869fcc39b37741408b5823cc044233201d")
+            .expect("Could not get nodehash");
+        let dir2 = MPathElement::new(b"dir2".to_vec()).expect("Can't create MPathElement dir2");
+        let file = MPath::new(b"dir2/file_1_in_dir2").expect("Can't create MPath");
+        let new_file = MPathElement::new(b"file_1_in_dir2".to_vec())
+            .expect("Can't create MPathElement file_1_in_dir2");
+        let new_entry = HgBlobEntry::new(
+            blobstore.clone(),
+            new_file.clone(),
+            nodehash::ONES_HASH,
+            Type::File(FileType::Regular),
+        );
+
+        // Make a manifest that differs from the loaded one in a single file
+        let memory_manifest = MemoryRootManifest::new(repo.clone(), Some(&manifest_id), None)
+            .wait()
+            .expect("Could not load manifest");
+        memory_manifest
+            .change_entry(&file, Some(new_entry.clone()))
+            .wait()
+            .expect("Failed to set");
+        let changed_id = memory_manifest
+            .save()
+            .wait()
+            .expect("Could not save manifest")
+            .get_hash()
+            .into_nodehash();
+
+        // Rebase the changed manifest onto the original one
+        let rebased = MemoryRootManifest::new_rebased(repo.clone(), &manifest_id, &changed_id)
+            .wait()
+            .expect("Could not rebase manifest");
+
+        if let MemoryManifestEntry::MemTree {
+            p1,
+            p2,
+            changes,
+            ..
+        } = rebased.unittest_root()
+        {
+            assert_eq!(*p1, Some(manifest_id), "Rebased manifest had wrong p1");
+            assert!(p2.is_none(), "Rebased manifest had p2");
+            let changes = changes.lock().expect("lock poisoned");
+            match changes.get(&dir2) {
+                Some(Some(MemoryManifestEntry::MemTree { p2, changes, .. })) => {
+                    assert!(p2.is_none(), "Rebased dir2 had p2");
+                    let changes = changes.lock().expect("lock poisoned");
+                    match changes.get(&new_file) {
+                        Some(Some(MemoryManifestEntry::Conflict(conflicts))) => {
+                            assert_eq!(conflicts.len(), 2, "Should have two conflicts")
+                        }
+                        _ => panic!("changed file did not create a conflict"),
+                    }
+                }
+                _ => panic!("dir2 is not a tree"),
+            }
+        } else {
+            panic!("Rebased manifest is not a MemTree");
+        }
+
+        // Resolve the conflict and check that the saved manifest only has one parent
+        rebased
+            .change_entry(&file, Some(new_entry))
+            .wait()
+            .expect("Failed to set");
+        let rebased_id = rebased
+            .save()
+            .wait()
+            .expect("Could not save manifest")
+            .get_hash()
+            .into_nodehash();
+        let saved = BlobManifest::load(&blobstore, &HgManifestId::new(rebased_id))
+            .wait()
+            .expect("Could not load saved manifest")
+            .expect("Saved manifest is missing");
+        assert_eq!(saved.p1(), Some(&manifest_id));
+        assert_eq!(saved.p2(), None);
+    })
+}
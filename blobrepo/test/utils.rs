@@ -187,6 +187,7 @@ pub fn create_changeset_no_parents(
         time: DateTime::from_timestamp(0, 0).expect("valid timestamp"),
         extra: BTreeMap::new(),
         comments: "Test commit".into(),
+        bonsai: None,
     };
     create_changeset.create(repo, ScubaSampleBuilder::with_discard())
 }
@@ -208,6 +209,7 @@ pub fn create_changeset_one_parent(
         time: DateTime::from_timestamp(1234, 0).expect("valid timestamp"),
         extra: BTreeMap::new(),
         comments: "Child commit".into(),
+        bonsai: None,
     };
     create_changeset.create(repo, ScubaSampleBuilder::with_discard())
 }
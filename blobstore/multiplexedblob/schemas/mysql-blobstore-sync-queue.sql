@@ -0,0 +1,6 @@
+CREATE TABLE blobstore_sync_queue (
+  id BIGINT UNSIGNED PRIMARY KEY AUTO_INCREMENT NOT NULL,
+  blobstore_key VARCHAR(255) NOT NULL,
+  add_timestamp BIGINT NOT NULL,
+  INDEX (add_timestamp)
+);
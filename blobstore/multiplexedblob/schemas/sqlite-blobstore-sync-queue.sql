@@ -0,0 +1,8 @@
+CREATE TABLE blobstore_sync_queue (
+  -- Sqlite doesn't support autoincrement UNSIGNED BIGINT
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  blobstore_key VARCHAR(255) NOT NULL,
+  add_timestamp BIGINT NOT NULL
+);
+
+CREATE INDEX blobstore_sync_queue_add_timestamp ON blobstore_sync_queue (add_timestamp);
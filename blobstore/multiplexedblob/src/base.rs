@@ -0,0 +1,205 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+
+use futures::{future, Async, Future, Poll, Stream};
+use futures::future::Loop;
+use futures::stream::FuturesUnordered;
+use futures_ext::{BoxFuture, FutureExt};
+
+use blobstore::Blobstore;
+use mononoke_types::BlobstoreBytes;
+
+use errors::*;
+use queue::{current_timestamp, BlobstoreSyncQueue};
+
+/// Identifies one of the blobstores of a multiplexed blobstore in errors
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct BlobstoreId(u64);
+
+impl BlobstoreId {
+    pub fn new(id: u64) -> Self {
+        BlobstoreId(id)
+    }
+}
+
+impl fmt::Display for BlobstoreId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A blobstore that writes every blob to all of its blobstores, and reads from whichever of
+/// them has the blob.
+///
+/// A `put` succeeds once `write_quorum` blobstores have acknowledged it. If some blobstores
+/// haven't acknowledged the write by then, the key is recorded in the sync queue, and the
+/// `Healer` will copy the blob to them later.
+#[derive(Clone)]
+pub struct MultiplexedBlobstore {
+    blobstores: Arc<Vec<(BlobstoreId, Arc<Blobstore>)>>,
+    write_quorum: usize,
+    queue: Arc<BlobstoreSyncQueue>,
+}
+
+impl MultiplexedBlobstore {
+    pub fn new(
+        blobstores: Vec<(BlobstoreId, Arc<Blobstore>)>,
+        write_quorum: usize,
+        queue: Arc<BlobstoreSyncQueue>,
+    ) -> Result<Self> {
+        if write_quorum == 0 || write_quorum > blobstores.len() {
+            bail_err!(ErrorKind::InvalidWriteQuorum(write_quorum, blobstores.len()));
+        }
+        Ok(Self {
+            blobstores: Arc::new(blobstores),
+            write_quorum,
+            queue,
+        })
+    }
+}
+
+impl Blobstore for MultiplexedBlobstore {
+    fn get(&self, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+        let gets = self.blobstores
+            .iter()
+            .map(|&(id, ref blobstore)| {
+                blobstore
+                    .get(key.clone())
+                    .map_err(move |err| (id, err))
+                    .boxify()
+            })
+            .collect();
+        first_hit(key, gets)
+    }
+
+    fn put(&self, key: String, value: BlobstoreBytes) -> BoxFuture<(), Error> {
+        let total = self.blobstores.len();
+        let puts = self.blobstores
+            .iter()
+            .map(|&(id, ref blobstore)| {
+                blobstore
+                    .put(key.clone(), value.clone())
+                    .map(move |()| id)
+                    .map_err(move |err| (id, err))
+                    .boxify()
+            })
+            .collect();
+        let queue = self.queue.clone();
+
+        QuorumPut::new(key.clone(), puts, self.write_quorum)
+            .and_then(move |(acked, remaining)| {
+                if acked == total {
+                    return future::ok(()).boxify();
+                }
+                // The puts that are still in flight are driven by the returned future, as there
+                // might be no runtime to spawn them on. Whether they succeed or not, the healer
+                // will make sure that all blobstores get the blob.
+                let remaining = remaining.then(|_| Ok(())).for_each(|()| Ok(()));
+                queue
+                    .add(key, current_timestamp())
+                    .join(remaining)
+                    .map(|((), ())| ())
+                    .boxify()
+            })
+            .boxify()
+    }
+
+    fn is_present(&self, key: String) -> BoxFuture<bool, Error> {
+        let checks = self.blobstores
+            .iter()
+            .map(|&(id, ref blobstore)| {
+                blobstore
+                    .is_present(key.clone())
+                    .map(|present| if present { Some(()) } else { None })
+                    .map_err(move |err| (id, err))
+                    .boxify()
+            })
+            .collect();
+        first_hit(key, checks).map(|hit| hit.is_some()).boxify()
+    }
+}
+
+/// Returns the first `Some` produced by one of the futures. If none of them produces a value,
+/// the result is `None` unless some of them failed, because the value might have been stored
+/// in one of the failed blobstores.
+fn first_hit<T: Send + 'static>(
+    key: String,
+    futs: FuturesUnordered<BoxFuture<Option<T>, (BlobstoreId, Error)>>,
+) -> BoxFuture<Option<T>, Error> {
+    future::loop_fn((futs, Vec::new()), move |(futs, mut errors)| {
+        let key = key.clone();
+        futs.into_future().then(move |res| match res {
+            Ok((Some(Some(value)), _)) => Ok(Loop::Break(Some(value))),
+            Ok((Some(None), futs)) => Ok(Loop::Continue((futs, errors))),
+            Ok((None, _)) => if errors.is_empty() {
+                Ok(Loop::Break(None))
+            } else {
+                Err(ErrorKind::SomeBlobstoresFailed(key, errors).into())
+            },
+            Err(((id, err), futs)) => {
+                errors.push((id, err));
+                Ok(Loop::Continue((futs, errors)))
+            }
+        })
+    }).boxify()
+}
+
+/// Waits until `write_quorum` of the puts succeeded. Resolves to the number of puts that
+/// succeeded so far and the puts that are still in flight.
+struct QuorumPut {
+    key: String,
+    puts: FuturesUnordered<BoxFuture<BlobstoreId, (BlobstoreId, Error)>>,
+    write_quorum: usize,
+    acked: usize,
+    errors: Vec<(BlobstoreId, Error)>,
+}
+
+impl QuorumPut {
+    fn new(
+        key: String,
+        puts: FuturesUnordered<BoxFuture<BlobstoreId, (BlobstoreId, Error)>>,
+        write_quorum: usize,
+    ) -> Self {
+        Self {
+            key,
+            puts,
+            write_quorum,
+            acked: 0,
+            errors: Vec::new(),
+        }
+    }
+}
+
+impl Future for QuorumPut {
+    type Item = (usize, FuturesUnordered<BoxFuture<BlobstoreId, (BlobstoreId, Error)>>);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            if self.acked >= self.write_quorum {
+                let remaining = mem::replace(&mut self.puts, FuturesUnordered::new());
+                return Ok(Async::Ready((self.acked, remaining)));
+            }
+            match self.puts.poll() {
+                Ok(Async::Ready(Some(_))) => self.acked += 1,
+                Ok(Async::Ready(None)) => {
+                    let errors = mem::replace(&mut self.errors, Vec::new());
+                    return Err(ErrorKind::WriteQuorumNotReached(
+                        self.key.clone(),
+                        self.write_quorum,
+                        errors,
+                    ).into());
+                }
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(err) => self.errors.push(err),
+            }
+        }
+    }
+}
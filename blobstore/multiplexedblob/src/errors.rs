@@ -0,0 +1,21 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+pub use failure::{Error, Result};
+
+use base::BlobstoreId;
+
+#[derive(Debug, Fail)]
+pub enum ErrorKind {
+    #[fail(display = "Write quorum {} is invalid for {} blobstores", _0, _1)]
+    InvalidWriteQuorum(usize, usize),
+    #[fail(display = "Blob {} was written to less than {} blobstores: {:?}", _0, _1, _2)]
+    WriteQuorumNotReached(String, usize, Vec<(BlobstoreId, Error)>),
+    #[fail(display = "Blob {} is missing from all blobstores", _0)]
+    BlobMissingFromAllBlobstores(String),
+    #[fail(display = "Some blobstores failed while looking for blob {}: {:?}", _0, _1)]
+    SomeBlobstoresFailed(String, Vec<(BlobstoreId, Error)>),
+}
@@ -0,0 +1,145 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use futures::{future, Future};
+use futures_ext::{BoxFuture, FutureExt};
+
+use blobstore::Blobstore;
+
+use base::BlobstoreId;
+use errors::*;
+use queue::{current_timestamp, BlobstoreSyncQueue};
+
+/// Copies the blobs recorded in the sync queue to the blobstores of a multiplexed blobstore
+/// that are missing them, and removes them from the queue once every blobstore has them.
+pub struct Healer {
+    blobstores: Arc<Vec<(BlobstoreId, Arc<Blobstore>)>>,
+    queue: Arc<BlobstoreSyncQueue>,
+    min_age_secs: i64,
+}
+
+impl Healer {
+    /// `blobstores` must be the blobstores of the multiplexed blobstore that writes to `queue`.
+    /// Entries younger than `min_age_secs` are skipped, because the writes that added them
+    /// might still be in flight.
+    pub fn new(
+        blobstores: Vec<(BlobstoreId, Arc<Blobstore>)>,
+        queue: Arc<BlobstoreSyncQueue>,
+        min_age_secs: i64,
+    ) -> Self {
+        Self {
+            blobstores: Arc::new(blobstores),
+            queue,
+            min_age_secs,
+        }
+    }
+
+    /// Processes at most `limit` entries of the queue and returns how many of them were healed.
+    /// Entries that couldn't be healed, for example because a blobstore is unavailable, are kept
+    /// in the queue so that they are retried by the next call.
+    pub fn heal(&self, limit: usize) -> BoxFuture<usize, Error> {
+        let blobstores = self.blobstores.clone();
+        let queue = self.queue.clone();
+
+        self.queue
+            .get_older_than(current_timestamp() - self.min_age_secs, limit)
+            .and_then(move |entries| {
+                // The same key might have been written several times
+                let mut ids_by_key: HashMap<String, Vec<i64>> = HashMap::new();
+                for entry in entries {
+                    ids_by_key
+                        .entry(entry.blobstore_key)
+                        .or_insert_with(Vec::new)
+                        .push(entry.id);
+                }
+
+                future::join_all(ids_by_key.into_iter().map(move |(key, ids)| {
+                    heal_key(blobstores.clone(), key).then(move |res| match res {
+                        Ok(()) => Ok::<_, Error>(ids),
+                        Err(_) => Ok(vec![]),
+                    })
+                }))
+            })
+            .and_then(move |healed_ids| {
+                let healed_ids: Vec<_> = healed_ids.into_iter().flat_map(|ids| ids).collect();
+                let count = healed_ids.len();
+                if healed_ids.is_empty() {
+                    future::ok(count).boxify()
+                } else {
+                    queue.del(healed_ids).map(move |()| count).boxify()
+                }
+            })
+            .boxify()
+    }
+}
+
+/// Makes sure that every blobstore has the blob
+fn heal_key(
+    blobstores: Arc<Vec<(BlobstoreId, Arc<Blobstore>)>>,
+    key: String,
+) -> BoxFuture<(), Error> {
+    let checks: Vec<_> = blobstores
+        .iter()
+        .map(|&(id, ref blobstore)| {
+            blobstore
+                .is_present(key.clone())
+                .map(move |present| (id, present))
+        })
+        .collect();
+
+    future::join_all(checks)
+        .and_then(move |presence| {
+            let source = presence
+                .iter()
+                .find(|&&(_, present)| present)
+                .map(|&(id, _)| id);
+            let source = match source {
+                Some(source) => source,
+                None => {
+                    return future::err(ErrorKind::BlobMissingFromAllBlobstores(key).into())
+                        .boxify()
+                }
+            };
+            let missing: Vec<_> = presence
+                .into_iter()
+                .filter(|&(_, present)| !present)
+                .map(|(id, _)| id)
+                .collect();
+            if missing.is_empty() {
+                return future::ok(()).boxify();
+            }
+
+            let source = get_blobstore(&blobstores, source);
+            source
+                .get(key.clone())
+                .and_then({
+                    let key = key.clone();
+                    move |value| value.ok_or(ErrorKind::BlobMissingFromAllBlobstores(key).into())
+                })
+                .and_then(move |value| {
+                    future::join_all(missing.into_iter().map(move |id| {
+                        get_blobstore(&blobstores, id).put(key.clone(), value.clone())
+                    }))
+                })
+                .map(|_| ())
+                .boxify()
+        })
+        .boxify()
+}
+
+fn get_blobstore(
+    blobstores: &[(BlobstoreId, Arc<Blobstore>)],
+    id: BlobstoreId,
+) -> Arc<Blobstore> {
+    blobstores
+        .iter()
+        .find(|&&(blobstore_id, _)| blobstore_id == id)
+        .map(|&(_, ref blobstore)| blobstore.clone())
+        .expect("blobstore ids come from the same list")
+}
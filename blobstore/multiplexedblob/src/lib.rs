@@ -0,0 +1,47 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! A blobstore that replicates blobs to several underlying blobstores.
+//!
+//! `MultiplexedBlobstore` writes every blob to all of its blobstores and reports success once a
+//! quorum of them has acknowledged the write. Keys that were not written everywhere are recorded
+//! in a `BlobstoreSyncQueue`, and the `Healer` later copies them to the blobstores that are
+//! missing them.
+
+#![deny(warnings)]
+#![feature(never_type)]
+
+#[macro_use]
+extern crate diesel;
+#[macro_use]
+extern crate failure_ext as failure;
+extern crate futures;
+extern crate tokio;
+
+extern crate blobstore;
+extern crate db;
+extern crate futures_ext;
+extern crate mononoke_types;
+
+#[cfg(test)]
+extern crate bytes;
+#[cfg(test)]
+extern crate delayblob;
+
+mod base;
+mod errors;
+mod healer;
+mod models;
+mod queue;
+mod schema;
+#[cfg(test)]
+mod test;
+
+pub use base::{BlobstoreId, MultiplexedBlobstore};
+pub use errors::*;
+pub use healer::Healer;
+pub use queue::{BlobstoreSyncQueue, BlobstoreSyncQueueEntry, MysqlBlobstoreSyncQueue,
+                SqliteBlobstoreSyncQueue};
@@ -0,0 +1,15 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use schema::blobstore_sync_queue;
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Insertable)]
+#[table_name = "blobstore_sync_queue"]
+pub(crate) struct BlobstoreSyncQueueInsertRow {
+    pub blobstore_key: String,
+    pub add_timestamp: i64,
+}
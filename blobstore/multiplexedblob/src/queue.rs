@@ -0,0 +1,221 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::result;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use diesel::{delete, insert_into, Connection, MysqlConnection, SqliteConnection};
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+
+use db::ConnectionParams;
+use futures_ext::{asynchronize, BoxFuture};
+
+use errors::*;
+use models::BlobstoreSyncQueueInsertRow;
+use schema::blobstore_sync_queue;
+
+/// A key that might be missing from some of the blobstores of a multiplexed blobstore
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Queryable)]
+pub struct BlobstoreSyncQueueEntry {
+    pub id: i64,
+    pub blobstore_key: String,
+    /// Seconds since the epoch at which the entry was added
+    pub add_timestamp: i64,
+}
+
+/// Durable storage of the keys that still have to be copied between blobstores
+pub trait BlobstoreSyncQueue: Send + Sync {
+    /// Record that `blobstore_key` might be missing from some of the blobstores
+    fn add(&self, blobstore_key: String, add_timestamp: i64) -> BoxFuture<(), Error>;
+
+    /// Retrieve at most `limit` entries added before `older_than`, oldest first
+    fn get_older_than(
+        &self,
+        older_than: i64,
+        limit: usize,
+    ) -> BoxFuture<Vec<BlobstoreSyncQueueEntry>, Error>;
+
+    /// Remove the entries with these ids from the queue
+    fn del(&self, ids: Vec<i64>) -> BoxFuture<(), Error>;
+}
+
+impl BlobstoreSyncQueue for Arc<BlobstoreSyncQueue> {
+    fn add(&self, blobstore_key: String, add_timestamp: i64) -> BoxFuture<(), Error> {
+        (**self).add(blobstore_key, add_timestamp)
+    }
+
+    fn get_older_than(
+        &self,
+        older_than: i64,
+        limit: usize,
+    ) -> BoxFuture<Vec<BlobstoreSyncQueueEntry>, Error> {
+        (**self).get_older_than(older_than, limit)
+    }
+
+    fn del(&self, ids: Vec<i64>) -> BoxFuture<(), Error> {
+        (**self).del(ids)
+    }
+}
+
+/// Current time in seconds since the epoch, as stored in the queue
+pub(crate) fn current_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time is before the epoch")
+        .as_secs() as i64
+}
+
+#[derive(Clone)]
+pub struct SqliteBlobstoreSyncQueue {
+    connection: Arc<Mutex<SqliteConnection>>,
+}
+
+impl SqliteBlobstoreSyncQueue {
+    /// Open a SQLite database. This is synchronous because the SQLite backend hits local
+    /// disk or memory.
+    pub fn open<P: AsRef<str>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let conn = SqliteConnection::establish(path)?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    fn create_tables(&mut self) -> Result<()> {
+        let up_query = include_str!("../schemas/sqlite-blobstore-sync-queue.sql");
+
+        self.connection
+            .lock()
+            .expect("lock poisoned")
+            .batch_execute(&up_query)?;
+
+        Ok(())
+    }
+
+    /// Create a new SQLite database.
+    pub fn create<P: AsRef<str>>(path: P) -> Result<Self> {
+        let mut queue = Self::open(path)?;
+
+        queue.create_tables()?;
+
+        Ok(queue)
+    }
+
+    /// Open a SQLite database, and create the tables if they are missing
+    pub fn open_or_create<P: AsRef<str>>(path: P) -> Result<Self> {
+        let mut queue = Self::open(path)?;
+
+        let _ = queue.create_tables();
+
+        Ok(queue)
+    }
+
+    /// Create a new in-memory empty database. Great for tests.
+    pub fn in_memory() -> Result<Self> {
+        Self::create(":memory:")
+    }
+
+    fn get_conn(&self) -> result::Result<MutexGuard<SqliteConnection>, !> {
+        Ok(self.connection.lock().expect("lock poisoned"))
+    }
+}
+
+#[derive(Clone)]
+pub struct MysqlBlobstoreSyncQueue {
+    pool: Pool<ConnectionManager<MysqlConnection>>,
+}
+
+impl MysqlBlobstoreSyncQueue {
+    pub fn open(params: &ConnectionParams) -> Result<Self> {
+        let url = params.to_diesel_url()?;
+        let manager = ConnectionManager::new(url);
+        let pool = Pool::builder()
+            .max_size(10)
+            .min_idle(Some(1))
+            .build(manager)?;
+        Ok(Self { pool })
+    }
+
+    pub fn create_test_db<P: AsRef<str>>(prefix: P) -> Result<Self> {
+        let params = db::create_test_db(prefix)?;
+        Self::create(&params)
+    }
+
+    fn create(params: &ConnectionParams) -> Result<Self> {
+        let queue = Self::open(params)?;
+
+        let up_query = include_str!("../schemas/mysql-blobstore-sync-queue.sql");
+        queue.pool.get()?.batch_execute(&up_query)?;
+
+        Ok(queue)
+    }
+
+    fn get_conn(&self) -> Result<PooledConnection<ConnectionManager<MysqlConnection>>> {
+        self.pool.get().map_err(Error::from)
+    }
+}
+
+/// Using a macro here is unfortunate, but it appears to be the only way to share this code
+/// between SQLite and MySQL.
+/// See https://github.com/diesel-rs/diesel/issues/882#issuecomment-300257476
+macro_rules! impl_blobstore_sync_queue {
+    ($struct: ty) => {
+        impl BlobstoreSyncQueue for $struct {
+            fn add(&self, blobstore_key: String, add_timestamp: i64) -> BoxFuture<(), Error> {
+                let db = self.clone();
+
+                asynchronize(move || {
+                    let connection = db.get_conn()?;
+                    let row = BlobstoreSyncQueueInsertRow {
+                        blobstore_key,
+                        add_timestamp,
+                    };
+                    insert_into(blobstore_sync_queue::table)
+                        .values(&row)
+                        .execute(&*connection)?;
+                    Ok(())
+                })
+            }
+
+            fn get_older_than(
+                &self,
+                older_than: i64,
+                limit: usize,
+            ) -> BoxFuture<Vec<BlobstoreSyncQueueEntry>, Error> {
+                let db = self.clone();
+
+                asynchronize(move || {
+                    let connection = db.get_conn()?;
+                    blobstore_sync_queue::table
+                        .filter(blobstore_sync_queue::add_timestamp.lt(older_than))
+                        .order(blobstore_sync_queue::id.asc())
+                        .limit(limit as i64)
+                        .load::<BlobstoreSyncQueueEntry>(&*connection)
+                        .map_err(Error::from)
+                })
+            }
+
+            fn del(&self, ids: Vec<i64>) -> BoxFuture<(), Error> {
+                let db = self.clone();
+
+                asynchronize(move || {
+                    let connection = db.get_conn()?;
+                    let query = blobstore_sync_queue::table
+                        .filter(blobstore_sync_queue::id.eq_any(ids));
+                    delete(query).execute(&*connection)?;
+                    Ok(())
+                })
+            }
+        }
+    }
+}
+
+impl_blobstore_sync_queue!(MysqlBlobstoreSyncQueue);
+impl_blobstore_sync_queue!(SqliteBlobstoreSyncQueue);
@@ -0,0 +1,293 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::{future, Future};
+use futures_ext::{BoxFuture, FutureExt};
+use tokio::runtime::Runtime;
+
+use blobstore::{Blobstore, EagerMemblob};
+use delayblob::DelayBlob;
+use mononoke_types::BlobstoreBytes;
+
+use base::{BlobstoreId, MultiplexedBlobstore};
+use errors::*;
+use healer::Healer;
+use queue::{current_timestamp, BlobstoreSyncQueue, SqliteBlobstoreSyncQueue};
+
+/// A blobstore that is always unavailable
+struct FailingBlobstore;
+
+impl Blobstore for FailingBlobstore {
+    fn get(&self, _key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+        future::err(format_err!("blobstore is unavailable")).boxify()
+    }
+
+    fn put(&self, _key: String, _value: BlobstoreBytes) -> BoxFuture<(), Error> {
+        future::err(format_err!("blobstore is unavailable")).boxify()
+    }
+}
+
+fn new_queue() -> Arc<SqliteBlobstoreSyncQueue> {
+    Arc::new(SqliteBlobstoreSyncQueue::in_memory().expect("Creating the queue failed"))
+}
+
+fn with_ids(blobstores: Vec<Arc<Blobstore>>) -> Vec<(BlobstoreId, Arc<Blobstore>)> {
+    blobstores
+        .into_iter()
+        .enumerate()
+        .map(|(idx, blobstore)| (BlobstoreId::new(idx as u64), blobstore))
+        .collect()
+}
+
+fn queued_keys(runtime: &mut Runtime, queue: &SqliteBlobstoreSyncQueue) -> Vec<String> {
+    runtime
+        .block_on(queue.get_older_than(::std::i64::MAX, 100))
+        .expect("Reading the queue failed")
+        .into_iter()
+        .map(|entry| entry.blobstore_key)
+        .collect()
+}
+
+fn has_blob(runtime: &mut Runtime, blobstore: &Blobstore, key: &str) -> bool {
+    runtime
+        .block_on(blobstore.is_present(key.to_string()))
+        .expect("is_present failed")
+}
+
+fn value() -> BlobstoreBytes {
+    BlobstoreBytes::from_bytes(&b"value"[..])
+}
+
+#[test]
+fn test_invalid_write_quorum() {
+    let blobstores = with_ids(vec![Arc::new(EagerMemblob::new())]);
+    assert!(MultiplexedBlobstore::new(blobstores.clone(), 0, new_queue()).is_err());
+    assert!(MultiplexedBlobstore::new(blobstores, 2, new_queue()).is_err());
+}
+
+#[test]
+fn test_put_everywhere() {
+    let mut runtime = Runtime::new().unwrap();
+    let inner: Vec<_> = (0..3).map(|_| EagerMemblob::new()).collect();
+    let queue = new_queue();
+    let multiplexed = MultiplexedBlobstore::new(
+        with_ids(inner.iter().map(|b| Arc::new(b.clone()) as Arc<Blobstore>).collect()),
+        2,
+        queue.clone(),
+    ).unwrap();
+
+    runtime
+        .block_on(multiplexed.put("key".into(), value()))
+        .expect("put failed");
+
+    for blobstore in &inner {
+        assert!(has_blob(&mut runtime, blobstore, "key"));
+    }
+    assert!(queued_keys(&mut runtime, &queue).is_empty());
+}
+
+#[test]
+fn test_put_without_runtime() {
+    let inner: Vec<_> = (0..2).map(|_| EagerMemblob::new()).collect();
+    let multiplexed = MultiplexedBlobstore::new(
+        with_ids(inner.iter().map(|b| Arc::new(b.clone()) as Arc<Blobstore>).collect()),
+        1,
+        new_queue(),
+    ).unwrap();
+
+    // The put that is still in flight once the quorum is reached is not spawned anywhere
+    multiplexed
+        .put("key".into(), value())
+        .wait()
+        .expect("put failed");
+
+    for blobstore in &inner {
+        let present = blobstore
+            .is_present("key".into())
+            .wait()
+            .expect("is_present failed");
+        assert!(present);
+    }
+}
+
+#[test]
+fn test_put_quorum_with_failure() {
+    let mut runtime = Runtime::new().unwrap();
+    let queue = new_queue();
+    let multiplexed = MultiplexedBlobstore::new(
+        with_ids(vec![
+            Arc::new(EagerMemblob::new()),
+            Arc::new(FailingBlobstore),
+            Arc::new(EagerMemblob::new()),
+        ]),
+        2,
+        queue.clone(),
+    ).unwrap();
+
+    runtime
+        .block_on(multiplexed.put("key".into(), value()))
+        .expect("put failed");
+    assert_eq!(queued_keys(&mut runtime, &queue), vec!["key".to_string()]);
+
+    let out = runtime
+        .block_on(multiplexed.get("key".into()))
+        .expect("get failed")
+        .expect("blob is missing");
+    assert_eq!(out.into_bytes(), Bytes::from_static(b"value"));
+}
+
+#[test]
+fn test_put_quorum_not_reached() {
+    let mut runtime = Runtime::new().unwrap();
+    let queue = new_queue();
+    let multiplexed = MultiplexedBlobstore::new(
+        with_ids(vec![
+            Arc::new(EagerMemblob::new()),
+            Arc::new(FailingBlobstore),
+            Arc::new(FailingBlobstore),
+        ]),
+        2,
+        queue.clone(),
+    ).unwrap();
+
+    let err = runtime
+        .block_on(multiplexed.put("key".into(), value()))
+        .expect_err("put should fail");
+    match err.downcast::<ErrorKind>() {
+        Ok(ErrorKind::WriteQuorumNotReached(key, 2, errors)) => {
+            assert_eq!(key, "key");
+            let mut failed: Vec<_> = errors.into_iter().map(|(id, _)| id).collect();
+            failed.sort();
+            assert_eq!(failed, vec![BlobstoreId::new(1), BlobstoreId::new(2)]);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(queued_keys(&mut runtime, &queue).is_empty());
+}
+
+#[test]
+fn test_put_slow_blobstore() {
+    let mut runtime = Runtime::new().unwrap();
+    let queue = new_queue();
+    let slow_inner = EagerMemblob::new();
+    let slow = DelayBlob::new(
+        Box::new(slow_inner.clone()),
+        |()| Duration::from_secs(3600),
+        1,
+        1,
+        1,
+        1,
+    );
+    let multiplexed = MultiplexedBlobstore::new(
+        with_ids(vec![
+            Arc::new(EagerMemblob::new()),
+            Arc::new(slow),
+            Arc::new(EagerMemblob::new()),
+        ]),
+        2,
+        queue.clone(),
+    ).unwrap();
+
+    // The put returns without waiting for the slow blobstore
+    runtime
+        .block_on(multiplexed.put("key".into(), value()))
+        .expect("put failed");
+    assert!(!has_blob(&mut runtime, &slow_inner, "key"));
+    assert_eq!(queued_keys(&mut runtime, &queue), vec!["key".to_string()]);
+}
+
+#[test]
+fn test_get() {
+    let mut runtime = Runtime::new().unwrap();
+    let inner: Vec<_> = (0..3).map(|_| EagerMemblob::new()).collect();
+    let multiplexed = MultiplexedBlobstore::new(
+        with_ids(inner.iter().map(|b| Arc::new(b.clone()) as Arc<Blobstore>).collect()),
+        1,
+        new_queue(),
+    ).unwrap();
+
+    runtime
+        .block_on(inner[2].put("key".into(), value()))
+        .expect("put failed");
+
+    let out = runtime
+        .block_on(multiplexed.get("key".into()))
+        .expect("get failed")
+        .expect("blob is missing");
+    assert_eq!(out.into_bytes(), Bytes::from_static(b"value"));
+    assert!(has_blob(&mut runtime, &multiplexed, "key"));
+
+    let out = runtime
+        .block_on(multiplexed.get("missing".into()))
+        .expect("get failed");
+    assert!(out.is_none());
+    assert!(!has_blob(&mut runtime, &multiplexed, "missing"));
+}
+
+#[test]
+fn test_get_with_failure() {
+    let mut runtime = Runtime::new().unwrap();
+    let blobstore = EagerMemblob::new();
+    let multiplexed = MultiplexedBlobstore::new(
+        with_ids(vec![Arc::new(blobstore.clone()), Arc::new(FailingBlobstore)]),
+        1,
+        new_queue(),
+    ).unwrap();
+
+    runtime
+        .block_on(blobstore.put("key".into(), value()))
+        .expect("put failed");
+    assert!(has_blob(&mut runtime, &multiplexed, "key"));
+
+    // The failed blobstore might have the blob, so its absence can't be confirmed
+    runtime
+        .block_on(multiplexed.get("missing".into()))
+        .expect_err("get should fail");
+}
+
+#[test]
+fn test_healer() {
+    let mut runtime = Runtime::new().unwrap();
+    let inner: Vec<_> = (0..3).map(|_| EagerMemblob::new()).collect();
+    let queue = new_queue();
+    let healer = Healer::new(
+        with_ids(inner.iter().map(|b| Arc::new(b.clone()) as Arc<Blobstore>).collect()),
+        queue.clone(),
+        60,
+    );
+
+    runtime
+        .block_on(inner[1].put("key".into(), value()))
+        .expect("put failed");
+    runtime
+        .block_on(queue.add("key".into(), current_timestamp() - 120))
+        .expect("adding to the queue failed");
+    runtime
+        .block_on(queue.add("key".into(), current_timestamp() - 120))
+        .expect("adding to the queue failed");
+    // Too recent to be healed
+    runtime
+        .block_on(queue.add("recent".into(), current_timestamp()))
+        .expect("adding to the queue failed");
+    // Can't be healed, because none of the blobstores has it
+    runtime
+        .block_on(queue.add("lost".into(), current_timestamp() - 120))
+        .expect("adding to the queue failed");
+
+    let healed = runtime.block_on(healer.heal(100)).expect("heal failed");
+    assert_eq!(healed, 2);
+
+    for blobstore in &inner {
+        assert!(has_blob(&mut runtime, blobstore, "key"));
+    }
+    let mut remaining = queued_keys(&mut runtime, &queue);
+    remaining.sort();
+    assert_eq!(remaining, vec!["lost".to_string(), "recent".to_string()]);
+}
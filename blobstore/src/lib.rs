@@ -49,6 +49,9 @@ pub use memcache_cache::{MemcacheBlobstore, MemcacheBlobstoreExt};
 mod prefix;
 pub use prefix::PrefixBlobstore;
 
+mod split;
+pub use split::SplitBlobstore;
+
 mod errors;
 pub use errors::*;
 
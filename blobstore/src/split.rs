@@ -0,0 +1,129 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use failure::Error;
+use futures_ext::BoxFuture;
+
+use mononoke_types::BlobstoreBytes;
+
+use Blobstore;
+
+/// A layer over two blobstores that sends the keys starting with a fixed string to the second
+/// one, and every other key to the first one. Keys are passed on unchanged.
+#[derive(Clone)]
+pub struct SplitBlobstore<T: Blobstore + Clone, U: Blobstore + Clone> {
+    prefix: String,
+    blobstore: T,
+    prefixed_blobstore: U,
+}
+
+impl<T: Blobstore + Clone, U: Blobstore + Clone> SplitBlobstore<T, U> {
+    pub fn new<S: Into<String>>(blobstore: T, prefix: S, prefixed_blobstore: U) -> Self {
+        let prefix = prefix.into();
+        Self {
+            prefix,
+            blobstore,
+            prefixed_blobstore,
+        }
+    }
+}
+
+impl<T: Blobstore + Clone, U: Blobstore + Clone> Blobstore for SplitBlobstore<T, U> {
+    #[inline]
+    fn get(&self, key: String) -> BoxFuture<Option<BlobstoreBytes>, Error> {
+        if key.starts_with(&self.prefix) {
+            self.prefixed_blobstore.get(key)
+        } else {
+            self.blobstore.get(key)
+        }
+    }
+
+    #[inline]
+    fn put(&self, key: String, value: BlobstoreBytes) -> BoxFuture<(), Error> {
+        if key.starts_with(&self.prefix) {
+            self.prefixed_blobstore.put(key, value)
+        } else {
+            self.blobstore.put(key, value)
+        }
+    }
+
+    #[inline]
+    fn is_present(&self, key: String) -> BoxFuture<bool, Error> {
+        if key.starts_with(&self.prefix) {
+            self.prefixed_blobstore.is_present(key)
+        } else {
+            self.blobstore.is_present(key)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bytes::Bytes;
+    use futures::Future;
+
+    use memblob::EagerMemblob;
+
+    #[test]
+    fn test_split() {
+        let base = EagerMemblob::new();
+        let other = EagerMemblob::new();
+        let split = SplitBlobstore::new(base.clone(), "other-", other.clone());
+
+        // This is EagerMemblob (immediate future completion) so calling wait() is fine.
+        for key in vec!["foobar", "other-foobar"] {
+            split
+                .put(key.to_string(), BlobstoreBytes::from_bytes(key))
+                .wait()
+                .expect("put should succeed");
+        }
+
+        // Test that each key went to exactly one of the stores.
+        assert_eq!(
+            base.get("foobar".to_string())
+                .wait()
+                .expect("get should succeed")
+                .expect("value should be present")
+                .into_bytes(),
+            Bytes::from("foobar"),
+        );
+        assert_eq!(
+            other
+                .get("other-foobar".to_string())
+                .wait()
+                .expect("get should succeed")
+                .expect("value should be present")
+                .into_bytes(),
+            Bytes::from("other-foobar"),
+        );
+        assert!(
+            !base.is_present("other-foobar".to_string())
+                .wait()
+                .expect("is_present should succeed")
+        );
+        assert!(
+            !other
+                .is_present("foobar".to_string())
+                .wait()
+                .expect("is_present should succeed")
+        );
+
+        // Test that the split store finds both keys.
+        for key in vec!["foobar", "other-foobar"] {
+            assert_eq!(
+                split
+                    .get(key.to_string())
+                    .wait()
+                    .expect("get should succeed")
+                    .expect("value should be present")
+                    .into_bytes(),
+                Bytes::from(key),
+            );
+        }
+    }
+}
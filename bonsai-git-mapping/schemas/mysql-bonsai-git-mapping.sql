@@ -0,0 +1,7 @@
+CREATE TABLE bonsai_git_mapping (
+  repo_id INTEGER NOT NULL,
+  git_sha1 BINARY(20) NOT NULL,
+  bcs_id BINARY(32) NOT NULL,
+  PRIMARY KEY (repo_id, git_sha1),
+  UNIQUE (repo_id, bcs_id)
+);
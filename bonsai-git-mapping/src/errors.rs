@@ -0,0 +1,16 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+pub use failure::{Error, Result};
+
+use BonsaiGitMappingEntry;
+
+#[derive(Debug, Eq, Fail, PartialEq)]
+pub enum ErrorKind {
+    #[fail(display = "Conflicting entries: stored:{:?} current:{:?}", _0, _1)]
+    ConflictingEntries(BonsaiGitMappingEntry, BonsaiGitMappingEntry),
+    #[fail(display = "invalid git sha1 input: {}", _0)] InvalidGitSha1Input(String),
+}
@@ -0,0 +1,128 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::fmt::{self, Debug, Display};
+use std::io::Write;
+use std::str::FromStr;
+
+use diesel::backend::Backend;
+use diesel::deserialize::{self, FromSql};
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::Binary;
+
+use errors::*;
+
+const HEX_CHARS: &[u8] = b"0123456789abcdef";
+
+/// The SHA-1 hash that identifies a git object, such as a commit.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(FromSqlRow, AsExpression)]
+#[sql_type = "GitSha1Sql"]
+pub struct GitSha1([u8; 20]);
+
+impl GitSha1 {
+    /// Construct a `GitSha1` from the 20 bytes of a hash.
+    pub fn from_bytes<B: AsRef<[u8]>>(bytes: B) -> Result<Self> {
+        let bytes = bytes.as_ref();
+        if bytes.len() != 20 {
+            bail_err!(ErrorKind::InvalidGitSha1Input(
+                "need exactly 20 bytes".into()
+            ));
+        }
+        let mut ret = GitSha1([0; 20]);
+        ret.0.copy_from_slice(bytes);
+        Ok(ret)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0[..]
+    }
+
+    pub fn to_hex(&self) -> String {
+        let mut v = Vec::with_capacity(40);
+        for &byte in self.as_bytes() {
+            v.push(HEX_CHARS[(byte >> 4) as usize]);
+            v.push(HEX_CHARS[(byte & 0xf) as usize]);
+        }
+        String::from_utf8(v).expect("hex digits are valid utf-8")
+    }
+}
+
+impl FromStr for GitSha1 {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.len() != 40 || !s.is_ascii() {
+            bail_err!(ErrorKind::InvalidGitSha1Input(
+                "need exactly 40 hex digits".into()
+            ));
+        }
+
+        let mut ret = GitSha1([0; 20]);
+        for idx in 0..ret.0.len() {
+            ret.0[idx] = match u8::from_str_radix(&s[(idx * 2)..(idx * 2 + 2)], 16) {
+                Ok(v) => v,
+                Err(_) => bail_err!(ErrorKind::InvalidGitSha1Input("bad digit".into())),
+            };
+        }
+        Ok(ret)
+    }
+}
+
+impl Display for GitSha1 {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(&self.to_hex())
+    }
+}
+
+/// Custom `Debug` output for `GitSha1` so it prints in hex.
+impl Debug for GitSha1 {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "GitSha1({})", self)
+    }
+}
+
+#[derive(QueryId, SqlType)]
+#[mysql_type = "Blob"]
+#[sqlite_type = "Binary"]
+pub struct GitSha1Sql;
+
+impl<DB: Backend> ToSql<GitSha1Sql, DB> for GitSha1 {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, DB>) -> serialize::Result {
+        out.write_all(self.as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl<DB: Backend> FromSql<GitSha1Sql, DB> for GitSha1
+where
+    *const [u8]: FromSql<Binary, DB>,
+{
+    fn from_sql(bytes: Option<&DB::RawValue>) -> deserialize::Result<Self> {
+        // Using unsafe here saves on a heap allocation. See https://goo.gl/K6hapb.
+        let raw_bytes: *const [u8] = FromSql::<Binary, DB>::from_sql(bytes)?;
+        let raw_bytes: &[u8] = unsafe { &*raw_bytes };
+        let sha1 = GitSha1::from_bytes(raw_bytes).compat()?;
+        Ok(sha1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hex_roundtrip() {
+        let hex = "0123456789abcdef0123456789abcdef01234567";
+        let sha1 = GitSha1::from_str(hex).expect("valid sha1");
+        assert_eq!(sha1.as_bytes()[..3], [0x01, 0x23, 0x45]);
+        assert_eq!(sha1.to_string(), hex);
+
+        assert!(GitSha1::from_str(&hex[1..]).is_err());
+        assert!(GitSha1::from_str("z123456789abcdef0123456789abcdef01234567").is_err());
+        assert!(GitSha1::from_bytes([0; 19]).is_err());
+    }
+}
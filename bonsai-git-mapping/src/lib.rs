@@ -0,0 +1,287 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Mapping between bonsai changesets and the git commits they were imported from.
+
+#![deny(warnings)]
+
+#[macro_use]
+extern crate diesel;
+#[macro_use]
+extern crate failure_ext as failure;
+extern crate futures;
+extern crate tokio;
+
+extern crate bonsai_mapping_utils;
+extern crate futures_ext;
+#[macro_use]
+extern crate lazy_static;
+extern crate mercurial_types;
+extern crate mononoke_types;
+#[macro_use]
+extern crate stats;
+
+use diesel::{insert_into, MysqlConnection, SqliteConnection};
+use diesel::prelude::*;
+use futures::Future;
+
+use bonsai_mapping_utils::{add_entry, get_entry, MysqlMappingDb, SqliteMappingDb};
+use futures_ext::{asynchronize, BoxFuture, FutureExt};
+use mercurial_types::RepositoryId;
+use mononoke_types::ChangesetId;
+use stats::Timeseries;
+
+mod errors;
+mod git_sha1;
+mod models;
+mod schema;
+mod wrappers;
+
+pub use errors::*;
+pub use git_sha1::{GitSha1, GitSha1Sql};
+use models::BonsaiGitMappingRow;
+use schema::bonsai_git_mapping;
+
+define_stats! {
+    prefix = "mononoke.bonsai_git_mapping";
+    gets: timeseries(RATE, SUM),
+    gets_master: timeseries(RATE, SUM),
+    adds: timeseries(RATE, SUM),
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct BonsaiGitMappingEntry {
+    pub repo_id: RepositoryId,
+    pub git_sha1: GitSha1,
+    pub bcs_id: ChangesetId,
+}
+
+impl From<BonsaiGitMappingRow> for BonsaiGitMappingEntry {
+    fn from(row: BonsaiGitMappingRow) -> Self {
+        BonsaiGitMappingEntry {
+            repo_id: row.repo_id,
+            git_sha1: row.git_sha1,
+            bcs_id: row.bcs_id,
+        }
+    }
+}
+
+impl From<BonsaiGitMappingEntry> for BonsaiGitMappingRow {
+    fn from(entry: BonsaiGitMappingEntry) -> Self {
+        BonsaiGitMappingRow {
+            repo_id: entry.repo_id,
+            git_sha1: entry.git_sha1,
+            bcs_id: entry.bcs_id,
+        }
+    }
+}
+
+/// A changeset id in either of the two forms stored in the mapping.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BonsaiOrGitSha1 {
+    Bonsai(ChangesetId),
+    GitSha1(GitSha1),
+}
+
+impl From<ChangesetId> for BonsaiOrGitSha1 {
+    fn from(cs_id: ChangesetId) -> Self {
+        BonsaiOrGitSha1::Bonsai(cs_id)
+    }
+}
+
+impl From<GitSha1> for BonsaiOrGitSha1 {
+    fn from(sha1: GitSha1) -> Self {
+        BonsaiOrGitSha1::GitSha1(sha1)
+    }
+}
+
+/// Interface to storage of the mapping between bonsai changesets and git commits.
+pub trait BonsaiGitMapping: Send + Sync {
+    /// Add a new entry to the mapping. Returns true if the entry was inserted, returns false if
+    /// the same entry already existed. Adding an entry that conflicts with an existing one is an
+    /// error.
+    fn add(&self, entry: BonsaiGitMappingEntry) -> BoxFuture<bool, Error>;
+
+    /// Retrieve the entry for this changeset, looked up by either of its ids.
+    fn get(
+        &self,
+        repo_id: RepositoryId,
+        cs_id: BonsaiOrGitSha1,
+    ) -> BoxFuture<Option<BonsaiGitMappingEntry>, Error>;
+
+    /// Retrieve the git commit this bonsai changeset was imported from, if any.
+    fn get_git_sha1_from_bonsai(
+        &self,
+        repo_id: RepositoryId,
+        cs_id: ChangesetId,
+    ) -> BoxFuture<Option<GitSha1>, Error> {
+        self.get(repo_id, cs_id.into())
+            .map(|entry| entry.map(|entry| entry.git_sha1))
+            .boxify()
+    }
+
+    /// Retrieve the bonsai changeset this git commit was imported as, if any.
+    fn get_bonsai_from_git_sha1(
+        &self,
+        repo_id: RepositoryId,
+        sha1: GitSha1,
+    ) -> BoxFuture<Option<ChangesetId>, Error> {
+        self.get(repo_id, sha1.into())
+            .map(|entry| entry.map(|entry| entry.bcs_id))
+            .boxify()
+    }
+}
+
+const SQLITE_SCHEMA: &str = include_str!("../schemas/sqlite-bonsai-git-mapping.sql");
+const MYSQL_SCHEMA: &str = include_str!("../schemas/mysql-bonsai-git-mapping.sql");
+
+#[derive(Clone)]
+pub struct SqliteBonsaiGitMapping {
+    db: SqliteMappingDb,
+}
+
+impl SqliteBonsaiGitMapping {
+    /// Open a SQLite database. This is synchronous because the SQLite backend hits local
+    /// disk or memory.
+    pub fn open<P: AsRef<str>>(path: P) -> Result<Self> {
+        let db = SqliteMappingDb::open(path)?;
+        Ok(Self { db })
+    }
+
+    /// Create a new SQLite database.
+    pub fn create<P: AsRef<str>>(path: P) -> Result<Self> {
+        let mapping = Self::open(path)?;
+
+        mapping.db.create_tables(SQLITE_SCHEMA)?;
+
+        Ok(mapping)
+    }
+
+    /// Open a SQLite database, and create the tables if they are missing
+    pub fn open_or_create<P: AsRef<str>>(path: P) -> Result<Self> {
+        let mapping = Self::open(path)?;
+
+        let _ = mapping.db.create_tables(SQLITE_SCHEMA);
+
+        Ok(mapping)
+    }
+
+    /// Create a new in-memory empty database. Great for tests.
+    pub fn in_memory() -> Result<Self> {
+        Self::create(":memory:")
+    }
+}
+
+#[derive(Clone)]
+pub struct MysqlBonsaiGitMapping {
+    db: MysqlMappingDb,
+}
+
+impl MysqlBonsaiGitMapping {
+    pub fn open(db_address: &str) -> Result<Self> {
+        let db = MysqlMappingDb::open(db_address)?;
+        Ok(Self { db })
+    }
+
+    pub fn create_test_db<P: AsRef<str>>(prefix: P) -> Result<Self> {
+        let db = MysqlMappingDb::create_test_db(prefix, MYSQL_SCHEMA)?;
+        Ok(Self { db })
+    }
+}
+
+/// Using a macro here is unfortunate, but it appears to be the only way to share this code
+/// between SQLite and MySQL.
+/// See https://github.com/diesel-rs/diesel/issues/882#issuecomment-300257476
+macro_rules! impl_bonsai_git_mapping {
+    ($struct: ty, $connection: ty) => {
+        impl BonsaiGitMapping for $struct {
+            fn add(&self, entry: BonsaiGitMappingEntry) -> BoxFuture<bool, Error> {
+                STATS::adds.add_value(1);
+                let db = self.db.clone();
+
+                asynchronize(move || {
+                    let connection = db.get_master_conn()?;
+                    add_entry(
+                        entry,
+                        |entry| {
+                            insert_into(bonsai_git_mapping::table)
+                                .values(&BonsaiGitMappingRow::from(entry.clone()))
+                                .execute(&*connection)
+                        },
+                        |entry| {
+                            let stored = Self::actual_get(
+                                &connection,
+                                entry.repo_id,
+                                BonsaiOrGitSha1::GitSha1(entry.git_sha1),
+                            )?;
+                            match stored {
+                                Some(stored) => Ok(Some(stored)),
+                                None => Self::actual_get(
+                                    &connection,
+                                    entry.repo_id,
+                                    BonsaiOrGitSha1::Bonsai(entry.bcs_id),
+                                ),
+                            }
+                        },
+                        |stored, entry| ErrorKind::ConflictingEntries(stored, entry).into(),
+                    )
+                })
+            }
+
+            fn get(
+                &self,
+                repo_id: RepositoryId,
+                cs_id: BonsaiOrGitSha1,
+            ) -> BoxFuture<Option<BonsaiGitMappingEntry>, Error> {
+                STATS::gets.add_value(1);
+                let db = self.db.clone();
+
+                asynchronize(move || {
+                    get_entry(
+                        || {
+                            let connection = db.get_conn()?;
+                            Self::actual_get(&connection, repo_id, cs_id)
+                        },
+                        || {
+                            STATS::gets_master.add_value(1);
+                            let connection = db.get_master_conn()?;
+                            Self::actual_get(&connection, repo_id, cs_id)
+                        },
+                    )
+                })
+            }
+        }
+
+        impl $struct {
+            fn actual_get(
+                connection: &$connection,
+                repo_id: RepositoryId,
+                cs_id: BonsaiOrGitSha1,
+            ) -> Result<Option<BonsaiGitMappingEntry>> {
+                let query = bonsai_git_mapping::table
+                    .filter(bonsai_git_mapping::repo_id.eq(repo_id))
+                    .into_boxed();
+                let query = match cs_id {
+                    BonsaiOrGitSha1::Bonsai(id) => {
+                        query.filter(bonsai_git_mapping::bcs_id.eq(id))
+                    }
+                    BonsaiOrGitSha1::GitSha1(id) => {
+                        query.filter(bonsai_git_mapping::git_sha1.eq(id))
+                    }
+                };
+
+                query
+                    .first::<BonsaiGitMappingRow>(connection)
+                    .optional()
+                    .map(|row| row.map(BonsaiGitMappingEntry::from))
+                    .map_err(failure::Error::from)
+            }
+        }
+    }
+}
+
+impl_bonsai_git_mapping!(MysqlBonsaiGitMapping, MysqlConnection);
+impl_bonsai_git_mapping!(SqliteBonsaiGitMapping, SqliteConnection);
@@ -0,0 +1,20 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use mercurial_types::RepositoryId;
+use mononoke_types::ChangesetId;
+
+use git_sha1::GitSha1;
+use schema::bonsai_git_mapping;
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Queryable, Insertable)]
+#[table_name = "bonsai_git_mapping"]
+pub(crate) struct BonsaiGitMappingRow {
+    pub repo_id: RepositoryId,
+    pub git_sha1: GitSha1,
+    pub bcs_id: ChangesetId,
+}
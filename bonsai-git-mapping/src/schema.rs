@@ -0,0 +1,22 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! The `table!` macros in this module describe the schemas for these tables in SQL storage
+//! (MySQL or SQLite). These descriptions are *not* the source of truth, so if the schema ever
+//! changes it will need to be updated here as well.
+
+table! {
+    use diesel::sql_types::Integer;
+
+    use git_sha1::GitSha1Sql;
+    use mononoke_types::sql_types::ChangesetIdSql;
+
+    bonsai_git_mapping (repo_id, git_sha1) {
+        repo_id -> Integer,
+        git_sha1 -> GitSha1Sql,
+        bcs_id -> ChangesetIdSql,
+    }
+}
@@ -0,0 +1,285 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Mapping between bonsai changesets and the Mercurial changesets derived from them.
+
+#![deny(warnings)]
+
+#[macro_use]
+extern crate diesel;
+#[macro_use]
+extern crate failure_ext as failure;
+extern crate futures;
+extern crate tokio;
+
+extern crate bonsai_mapping_utils;
+extern crate futures_ext;
+#[macro_use]
+extern crate lazy_static;
+extern crate mercurial_types;
+extern crate mononoke_types;
+#[macro_use]
+extern crate stats;
+
+use diesel::{insert_into, MysqlConnection, SqliteConnection};
+use diesel::prelude::*;
+use futures::Future;
+
+use bonsai_mapping_utils::{add_entry, get_entry, MysqlMappingDb, SqliteMappingDb};
+use futures_ext::{asynchronize, BoxFuture, FutureExt};
+use mercurial_types::{HgChangesetId, RepositoryId};
+use mononoke_types::ChangesetId;
+use stats::Timeseries;
+
+mod errors;
+mod models;
+mod schema;
+mod wrappers;
+
+pub use errors::*;
+use models::BonsaiHgMappingRow;
+use schema::bonsai_hg_mapping;
+
+define_stats! {
+    prefix = "mononoke.bonsai_hg_mapping";
+    gets: timeseries(RATE, SUM),
+    gets_master: timeseries(RATE, SUM),
+    adds: timeseries(RATE, SUM),
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct BonsaiHgMappingEntry {
+    pub repo_id: RepositoryId,
+    pub hg_cs_id: HgChangesetId,
+    pub bcs_id: ChangesetId,
+}
+
+impl From<BonsaiHgMappingRow> for BonsaiHgMappingEntry {
+    fn from(row: BonsaiHgMappingRow) -> Self {
+        BonsaiHgMappingEntry {
+            repo_id: row.repo_id,
+            hg_cs_id: row.hg_cs_id,
+            bcs_id: row.bcs_id,
+        }
+    }
+}
+
+impl From<BonsaiHgMappingEntry> for BonsaiHgMappingRow {
+    fn from(entry: BonsaiHgMappingEntry) -> Self {
+        BonsaiHgMappingRow {
+            repo_id: entry.repo_id,
+            hg_cs_id: entry.hg_cs_id,
+            bcs_id: entry.bcs_id,
+        }
+    }
+}
+
+/// A changeset id in either of the two forms stored in the mapping.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BonsaiOrHgChangesetId {
+    Bonsai(ChangesetId),
+    Hg(HgChangesetId),
+}
+
+impl From<ChangesetId> for BonsaiOrHgChangesetId {
+    fn from(cs_id: ChangesetId) -> Self {
+        BonsaiOrHgChangesetId::Bonsai(cs_id)
+    }
+}
+
+impl From<HgChangesetId> for BonsaiOrHgChangesetId {
+    fn from(cs_id: HgChangesetId) -> Self {
+        BonsaiOrHgChangesetId::Hg(cs_id)
+    }
+}
+
+/// Interface to storage of the mapping between bonsai changesets and Mercurial changesets.
+pub trait BonsaiHgMapping: Send + Sync {
+    /// Add a new entry to the mapping. Returns true if the entry was inserted, returns false if
+    /// the same entry already existed. Adding an entry that conflicts with an existing one is an
+    /// error.
+    fn add(&self, entry: BonsaiHgMappingEntry) -> BoxFuture<bool, Error>;
+
+    /// Retrieve the entry for this changeset, looked up by either of its ids.
+    fn get(
+        &self,
+        repo_id: RepositoryId,
+        cs_id: BonsaiOrHgChangesetId,
+    ) -> BoxFuture<Option<BonsaiHgMappingEntry>, Error>;
+
+    /// Retrieve the Mercurial changeset derived from this bonsai changeset, if any.
+    fn get_hg_from_bonsai(
+        &self,
+        repo_id: RepositoryId,
+        cs_id: ChangesetId,
+    ) -> BoxFuture<Option<HgChangesetId>, Error> {
+        self.get(repo_id, cs_id.into())
+            .map(|entry| entry.map(|entry| entry.hg_cs_id))
+            .boxify()
+    }
+
+    /// Retrieve the bonsai changeset this Mercurial changeset is derived from, if any.
+    fn get_bonsai_from_hg(
+        &self,
+        repo_id: RepositoryId,
+        cs_id: HgChangesetId,
+    ) -> BoxFuture<Option<ChangesetId>, Error> {
+        self.get(repo_id, cs_id.into())
+            .map(|entry| entry.map(|entry| entry.bcs_id))
+            .boxify()
+    }
+}
+
+const SQLITE_SCHEMA: &str = include_str!("../schemas/sqlite-bonsai-hg-mapping.sql");
+const MYSQL_SCHEMA: &str = include_str!("../schemas/mysql-bonsai-hg-mapping.sql");
+
+#[derive(Clone)]
+pub struct SqliteBonsaiHgMapping {
+    db: SqliteMappingDb,
+}
+
+impl SqliteBonsaiHgMapping {
+    /// Open a SQLite database. This is synchronous because the SQLite backend hits local
+    /// disk or memory.
+    pub fn open<P: AsRef<str>>(path: P) -> Result<Self> {
+        let db = SqliteMappingDb::open(path)?;
+        Ok(Self { db })
+    }
+
+    /// Create a new SQLite database.
+    pub fn create<P: AsRef<str>>(path: P) -> Result<Self> {
+        let mapping = Self::open(path)?;
+
+        mapping.db.create_tables(SQLITE_SCHEMA)?;
+
+        Ok(mapping)
+    }
+
+    /// Open a SQLite database, and create the tables if they are missing
+    pub fn open_or_create<P: AsRef<str>>(path: P) -> Result<Self> {
+        let mapping = Self::open(path)?;
+
+        let _ = mapping.db.create_tables(SQLITE_SCHEMA);
+
+        Ok(mapping)
+    }
+
+    /// Create a new in-memory empty database. Great for tests.
+    pub fn in_memory() -> Result<Self> {
+        Self::create(":memory:")
+    }
+}
+
+#[derive(Clone)]
+pub struct MysqlBonsaiHgMapping {
+    db: MysqlMappingDb,
+}
+
+impl MysqlBonsaiHgMapping {
+    pub fn open(db_address: &str) -> Result<Self> {
+        let db = MysqlMappingDb::open(db_address)?;
+        Ok(Self { db })
+    }
+
+    pub fn create_test_db<P: AsRef<str>>(prefix: P) -> Result<Self> {
+        let db = MysqlMappingDb::create_test_db(prefix, MYSQL_SCHEMA)?;
+        Ok(Self { db })
+    }
+}
+
+/// Using a macro here is unfortunate, but it appears to be the only way to share this code
+/// between SQLite and MySQL.
+/// See https://github.com/diesel-rs/diesel/issues/882#issuecomment-300257476
+macro_rules! impl_bonsai_hg_mapping {
+    ($struct: ty, $connection: ty) => {
+        impl BonsaiHgMapping for $struct {
+            fn add(&self, entry: BonsaiHgMappingEntry) -> BoxFuture<bool, Error> {
+                STATS::adds.add_value(1);
+                let db = self.db.clone();
+
+                asynchronize(move || {
+                    let connection = db.get_master_conn()?;
+                    add_entry(
+                        entry,
+                        |entry| {
+                            insert_into(bonsai_hg_mapping::table)
+                                .values(&BonsaiHgMappingRow::from(entry.clone()))
+                                .execute(&*connection)
+                        },
+                        |entry| {
+                            let stored = Self::actual_get(
+                                &connection,
+                                entry.repo_id,
+                                BonsaiOrHgChangesetId::Hg(entry.hg_cs_id),
+                            )?;
+                            match stored {
+                                Some(stored) => Ok(Some(stored)),
+                                None => Self::actual_get(
+                                    &connection,
+                                    entry.repo_id,
+                                    BonsaiOrHgChangesetId::Bonsai(entry.bcs_id),
+                                ),
+                            }
+                        },
+                        |stored, entry| ErrorKind::ConflictingEntries(stored, entry).into(),
+                    )
+                })
+            }
+
+            fn get(
+                &self,
+                repo_id: RepositoryId,
+                cs_id: BonsaiOrHgChangesetId,
+            ) -> BoxFuture<Option<BonsaiHgMappingEntry>, Error> {
+                STATS::gets.add_value(1);
+                let db = self.db.clone();
+
+                asynchronize(move || {
+                    get_entry(
+                        || {
+                            let connection = db.get_conn()?;
+                            Self::actual_get(&connection, repo_id, cs_id)
+                        },
+                        || {
+                            STATS::gets_master.add_value(1);
+                            let connection = db.get_master_conn()?;
+                            Self::actual_get(&connection, repo_id, cs_id)
+                        },
+                    )
+                })
+            }
+        }
+
+        impl $struct {
+            fn actual_get(
+                connection: &$connection,
+                repo_id: RepositoryId,
+                cs_id: BonsaiOrHgChangesetId,
+            ) -> Result<Option<BonsaiHgMappingEntry>> {
+                let query = bonsai_hg_mapping::table
+                    .filter(bonsai_hg_mapping::repo_id.eq(repo_id))
+                    .into_boxed();
+                let query = match cs_id {
+                    BonsaiOrHgChangesetId::Bonsai(id) => {
+                        query.filter(bonsai_hg_mapping::bcs_id.eq(id))
+                    }
+                    BonsaiOrHgChangesetId::Hg(id) => {
+                        query.filter(bonsai_hg_mapping::hg_cs_id.eq(id))
+                    }
+                };
+
+                query
+                    .first::<BonsaiHgMappingRow>(connection)
+                    .optional()
+                    .map(|row| row.map(BonsaiHgMappingEntry::from))
+                    .map_err(failure::Error::from)
+            }
+        }
+    }
+}
+
+impl_bonsai_hg_mapping!(MysqlBonsaiHgMapping, MysqlConnection);
+impl_bonsai_hg_mapping!(SqliteBonsaiHgMapping, SqliteConnection);
@@ -0,0 +1,29 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Implementations for wrappers that enable dynamic dispatch. Add more as necessary.
+
+use std::sync::Arc;
+
+use futures_ext::BoxFuture;
+use mercurial_types::RepositoryId;
+
+use {BonsaiHgMapping, BonsaiHgMappingEntry, BonsaiOrHgChangesetId};
+use errors::*;
+
+impl BonsaiHgMapping for Arc<BonsaiHgMapping> {
+    fn add(&self, entry: BonsaiHgMappingEntry) -> BoxFuture<bool, Error> {
+        (**self).add(entry)
+    }
+
+    fn get(
+        &self,
+        repo_id: RepositoryId,
+        cs_id: BonsaiOrHgChangesetId,
+    ) -> BoxFuture<Option<BonsaiHgMappingEntry>, Error> {
+        (**self).get(repo_id, cs_id)
+    }
+}
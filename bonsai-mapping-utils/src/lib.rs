@@ -0,0 +1,164 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Storage code shared by the mappings between bonsai changesets and the changesets of other
+//! version control systems. The queries depend on the table of each mapping, so they are left to
+//! the mappings themselves.
+
+#![deny(warnings)]
+#![feature(never_type)]
+
+extern crate diesel;
+extern crate failure_ext as failure;
+
+extern crate db;
+
+use std::result;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use diesel::{Connection, MysqlConnection, SqliteConnection};
+use diesel::connection::SimpleConnection;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use diesel::result::{DatabaseErrorKind, Error as DieselError, QueryResult};
+use failure::{Error, Result};
+
+use db::{get_connection_params, ConnectionParams, InstanceRequirement, ProxyRequirement};
+
+/// The SQLite database of a mapping
+#[derive(Clone)]
+pub struct SqliteMappingDb {
+    connection: Arc<Mutex<SqliteConnection>>,
+}
+
+impl SqliteMappingDb {
+    /// Open a SQLite database. This is synchronous because the SQLite backend hits local
+    /// disk or memory.
+    pub fn open<P: AsRef<str>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let conn = SqliteConnection::establish(path)?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Create the tables of the mapping, as described by `up_query`
+    pub fn create_tables(&self, up_query: &str) -> Result<()> {
+        self.connection
+            .lock()
+            .expect("lock poisoned")
+            .batch_execute(up_query)?;
+
+        Ok(())
+    }
+
+    pub fn get_conn(&self) -> result::Result<MutexGuard<SqliteConnection>, !> {
+        Ok(self.connection.lock().expect("lock poisoned"))
+    }
+
+    pub fn get_master_conn(&self) -> result::Result<MutexGuard<SqliteConnection>, !> {
+        Ok(self.connection.lock().expect("lock poisoned"))
+    }
+}
+
+/// The MySQL database of a mapping. Reads go to the closest replica, writes go to the master.
+#[derive(Clone)]
+pub struct MysqlMappingDb {
+    pool: Pool<ConnectionManager<MysqlConnection>>,
+    master_pool: Pool<ConnectionManager<MysqlConnection>>,
+}
+
+impl MysqlMappingDb {
+    pub fn open(db_address: &str) -> Result<Self> {
+        let local_connection_params = get_connection_params(
+            db_address.to_string(),
+            InstanceRequirement::Closest,
+            None,
+            Some(ProxyRequirement::Forbidden),
+        )?;
+
+        let master_connection_params = get_connection_params(
+            db_address.to_string(),
+            InstanceRequirement::Master,
+            None,
+            Some(ProxyRequirement::Forbidden),
+        )?;
+
+        Self::open_with_params(&local_connection_params, &master_connection_params)
+    }
+
+    fn open_with_params(
+        local_connection_params: &ConnectionParams,
+        master_connection_params: &ConnectionParams,
+    ) -> Result<Self> {
+        let local_url = local_connection_params.to_diesel_url()?;
+        let master_url = master_connection_params.to_diesel_url()?;
+
+        let pool = Pool::builder()
+            .max_size(10)
+            .min_idle(Some(1))
+            .build(ConnectionManager::new(local_url.clone()))?;
+        let master_pool = Pool::builder()
+            .max_size(1)
+            .min_idle(Some(1))
+            .build(ConnectionManager::new(master_url.clone()))?;
+        Ok(Self { pool, master_pool })
+    }
+
+    /// Create a test database with the tables described by `up_query`
+    pub fn create_test_db<P: AsRef<str>>(prefix: P, up_query: &str) -> Result<Self> {
+        let params = db::create_test_db(prefix)?;
+        let db = Self::open_with_params(&params, &params)?;
+        db.master_pool.get()?.batch_execute(up_query)?;
+
+        Ok(db)
+    }
+
+    pub fn get_conn(&self) -> Result<PooledConnection<ConnectionManager<MysqlConnection>>> {
+        self.pool.get().map_err(Error::from)
+    }
+
+    pub fn get_master_conn(&self) -> Result<PooledConnection<ConnectionManager<MysqlConnection>>> {
+        self.master_pool.get().map_err(Error::from)
+    }
+}
+
+/// Add `entry` to a mapping by running `insert`. Returns true if the entry was inserted.
+///
+/// Either side of the mapping might already be taken, in which case `get_stored` fetches the
+/// stored entry that shares an id with `entry`. It's only fine if that is exactly the entry
+/// being added, which returns false. Otherwise the error is made by `conflict`.
+pub fn add_entry<E, I, G, C>(entry: E, insert: I, get_stored: G, conflict: C) -> Result<bool>
+where
+    E: PartialEq,
+    I: FnOnce(&E) -> QueryResult<usize>,
+    G: FnOnce(&E) -> Result<Option<E>>,
+    C: FnOnce(E, E) -> Error,
+{
+    match insert(&entry) {
+        Ok(_rows) => Ok(true),
+        Err(err @ DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            match get_stored(&entry)? {
+                Some(ref stored) if stored == &entry => Ok(false),
+                Some(stored) => Err(conflict(stored, entry)),
+                None => Err(err.into()),
+            }
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Get an entry of a mapping with `get`, and with `get_master` if it's not found, as the entry
+/// might not have been replicated yet.
+pub fn get_entry<E, G, M>(get: G, get_master: M) -> Result<Option<E>>
+where
+    G: FnOnce() -> Result<Option<E>>,
+    M: FnOnce() -> Result<Option<E>>,
+{
+    match get()? {
+        Some(entry) => Ok(Some(entry)),
+        None => get_master(),
+    }
+}
@@ -4,3 +4,15 @@ CREATE TABLE bookmarks (
   changeset_id VARBINARY(32) NOT NULL,
   PRIMARY KEY (repo_id, name)
 );
+
+CREATE TABLE bookmarks_update_log (
+  id BIGINT UNSIGNED PRIMARY KEY AUTO_INCREMENT NOT NULL,
+  repo_id INT UNSIGNED NOT NULL,
+  name VARCHAR(512) NOT NULL,
+  from_changeset_id VARBINARY(32),
+  to_changeset_id VARBINARY(32),
+  reason VARCHAR(32) NOT NULL,
+  session VARCHAR(255) NOT NULL,
+  timestamp BIGINT NOT NULL,
+  INDEX (repo_id, name)
+);
@@ -4,3 +4,17 @@ CREATE TABLE bookmarks (
   changeset_id VARBINARY(32) NOT NULL,
   PRIMARY KEY (repo_id, name)
 );
+
+CREATE TABLE bookmarks_update_log (
+  -- Sqlite doesn't support autoincrement UNSIGNED BIGINT
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  repo_id INT UNSIGNED NOT NULL,
+  name VARCHAR(512) NOT NULL,
+  from_changeset_id VARBINARY(32),
+  to_changeset_id VARBINARY(32),
+  reason VARCHAR(32) NOT NULL,
+  session VARCHAR(255) NOT NULL,
+  timestamp BIGINT NOT NULL
+);
+
+CREATE INDEX bookmarks_update_log_repo_id_name ON bookmarks_update_log (repo_id, name);
@@ -25,7 +25,8 @@ extern crate storage_types;
 mod schema;
 mod models;
 
-use bookmarks::{Bookmark, BookmarkPrefix, Bookmarks, Transaction};
+use bookmarks::{Bookmark, BookmarkPrefix, BookmarkUpdateLogEntry, BookmarkUpdateReason,
+                Bookmarks, Transaction};
 use diesel::{delete, insert_into, replace_into, update, MysqlConnection, SqliteConnection};
 use diesel::connection::SimpleConnection;
 use diesel::prelude::*;
@@ -39,99 +40,123 @@ use mercurial_types::{HgChangesetId, RepositoryId};
 use std::collections::{HashMap, HashSet};
 use std::result;
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Clone)]
-pub struct SqliteDbBookmarks {
-    connection: Arc<Mutex<SqliteConnection>>,
-}
+macro_rules! impl_sqlite_bookmarks {
+    ($struct: ident, $schema: expr) => {
+        #[derive(Clone)]
+        pub struct $struct {
+            connection: Arc<Mutex<SqliteConnection>>,
+        }
 
-impl SqliteDbBookmarks {
-    /// Open a SQLite database. This is synchronous because the SQLite backend hits local
-    /// disk or memory.
-    pub fn open<P: AsRef<str>>(path: P) -> Result<Self> {
-        let path = path.as_ref();
-        let conn = SqliteConnection::establish(path)?;
-        Ok(Self {
-            connection: Arc::new(Mutex::new(conn)),
-        })
-    }
+        impl $struct {
+            /// Open a SQLite database. This is synchronous because the SQLite backend hits local
+            /// disk or memory.
+            pub fn open<P: AsRef<str>>(path: P) -> Result<Self> {
+                let path = path.as_ref();
+                let conn = SqliteConnection::establish(path)?;
+                Ok(Self {
+                    connection: Arc::new(Mutex::new(conn)),
+                })
+            }
 
-    fn create_tables(&mut self) -> Result<()> {
-        let up_query = include_str!("../schemas/sqlite-bookmarks.sql");
+            fn create_tables(&mut self) -> Result<()> {
+                let up_query = include_str!($schema);
 
-        self.connection
-            .lock()
-            .expect("lock poisoned")
-            .batch_execute(&up_query)?;
+                self.connection
+                    .lock()
+                    .expect("lock poisoned")
+                    .batch_execute(&up_query)?;
 
-        Ok(())
-    }
+                Ok(())
+            }
 
-    /// Create a new SQLite database.
-    pub fn create<P: AsRef<str>>(path: P) -> Result<Self> {
-        let mut bookmarks = Self::open(path)?;
+            /// Create a new SQLite database.
+            pub fn create<P: AsRef<str>>(path: P) -> Result<Self> {
+                let mut bookmarks = Self::open(path)?;
 
-        bookmarks.create_tables()?;
+                bookmarks.create_tables()?;
 
-        Ok(bookmarks)
-    }
+                Ok(bookmarks)
+            }
 
-    /// Open a SQLite database, and create the tables if they are missing
-    pub fn open_or_create<P: AsRef<str>>(path: P) -> Result<Self> {
-        let mut bookmarks = Self::open(path)?;
+            /// Open a SQLite database, and create the tables if they are missing
+            pub fn open_or_create<P: AsRef<str>>(path: P) -> Result<Self> {
+                let mut bookmarks = Self::open(path)?;
 
-        let _ = bookmarks.create_tables();
+                let _ = bookmarks.create_tables();
 
-        Ok(bookmarks)
-    }
+                Ok(bookmarks)
+            }
 
-    /// Create a new in-memory empty database. Great for tests.
-    pub fn in_memory() -> Result<Self> {
-        Self::create(":memory:")
-    }
+            /// Create a new in-memory empty database. Great for tests.
+            pub fn in_memory() -> Result<Self> {
+                Self::create(":memory:")
+            }
 
-    fn get_conn(&self) -> result::Result<MutexGuard<SqliteConnection>, !> {
-        Ok(self.connection.lock().expect("lock poisoned"))
+            fn get_conn(&self) -> result::Result<MutexGuard<SqliteConnection>, !> {
+                Ok(self.connection.lock().expect("lock poisoned"))
+            }
+        }
     }
 }
 
-#[derive(Clone)]
-pub struct MysqlDbBookmarks {
-    pool: Pool<ConnectionManager<MysqlConnection>>,
-}
+macro_rules! impl_mysql_bookmarks {
+    ($struct: ident, $schema: expr) => {
+        #[derive(Clone)]
+        pub struct $struct {
+            pool: Pool<ConnectionManager<MysqlConnection>>,
+        }
 
-impl MysqlDbBookmarks {
-    pub fn open(params: &ConnectionParams) -> Result<Self> {
-        let url = params.to_diesel_url()?;
-        let manager = ConnectionManager::new(url);
-        let pool = Pool::builder()
-            .max_size(10)
-            .min_idle(Some(1))
-            .build(manager)?;
-        Ok(Self { pool })
-    }
+        impl $struct {
+            pub fn open(params: &ConnectionParams) -> Result<Self> {
+                let url = params.to_diesel_url()?;
+                let manager = ConnectionManager::new(url);
+                let pool = Pool::builder()
+                    .max_size(10)
+                    .min_idle(Some(1))
+                    .build(manager)?;
+                Ok(Self { pool })
+            }
 
-    pub fn create_test_db<P: AsRef<str>>(prefix: P) -> Result<Self> {
-        let params = db::create_test_db(prefix)?;
-        Self::create(&params)
-    }
+            pub fn create_test_db<P: AsRef<str>>(prefix: P) -> Result<Self> {
+                let params = db::create_test_db(prefix)?;
+                Self::create(&params)
+            }
 
-    fn create(params: &ConnectionParams) -> Result<Self> {
-        let changesets = Self::open(params)?;
+            fn create(params: &ConnectionParams) -> Result<Self> {
+                let bookmarks = Self::open(params)?;
 
-        let up_query = include_str!("../schemas/mysql-bookmarks.sql");
-        changesets.pool.get()?.batch_execute(&up_query)?;
+                let up_query = include_str!($schema);
+                bookmarks.pool.get()?.batch_execute(&up_query)?;
 
-        Ok(changesets)
-    }
+                Ok(bookmarks)
+            }
 
-    fn get_conn(&self) -> Result<PooledConnection<ConnectionManager<MysqlConnection>>> {
-        self.pool.get().map_err(Error::from)
+            fn get_conn(&self) -> Result<PooledConnection<ConnectionManager<MysqlConnection>>> {
+                self.pool.get().map_err(Error::from)
+            }
+        }
     }
 }
 
+impl_sqlite_bookmarks!(SqliteDbBookmarks, "../schemas/sqlite-bookmarks.sql");
+impl_mysql_bookmarks!(MysqlDbBookmarks, "../schemas/mysql-bookmarks.sql");
+
+// Scratch bookmarks are the bookmarks created by infinitepush pushes. They live in their own
+// table so that they never show up as public heads of the repo.
+impl_sqlite_bookmarks!(SqliteDbScratchBookmarks, "../schemas/sqlite-scratch-bookmarks.sql");
+impl_mysql_bookmarks!(MysqlDbScratchBookmarks, "../schemas/mysql-scratch-bookmarks.sql");
+
 macro_rules! impl_bookmarks {
-    ($struct: ty, $transaction_struct: ident) => {
+    (
+        $struct: ty,
+        $transaction_struct: ident,
+        $table: ident,
+        $row: ident,
+        $log_table: ident,
+        $log_row: ident
+    ) => {
         impl Bookmarks for $struct {
             fn get(
                 &self,
@@ -141,10 +166,10 @@ macro_rules! impl_bookmarks {
                 #[allow(unreachable_code, unreachable_patterns)] // sqlite can't fail
                 let connection = try_boxfuture!(self.get_conn());
 
-                schema::bookmarks::table
-                    .filter(schema::bookmarks::repo_id.eq(repo_id))
-                    .filter(schema::bookmarks::name.eq(name.to_string()))
-                    .select(schema::bookmarks::changeset_id)
+                schema::$table::table
+                    .filter(schema::$table::repo_id.eq(repo_id))
+                    .filter(schema::$table::name.eq(name.to_string()))
+                    .select(schema::$table::changeset_id)
                     .first::<HgChangesetId>(&*connection)
                     .optional()
                     .into_future()
@@ -165,12 +190,12 @@ macro_rules! impl_bookmarks {
                     },
                 };
 
-                let query = schema::bookmarks::table
-                    .filter(schema::bookmarks::repo_id.eq(repo_id))
-                    .filter(schema::bookmarks::name.like(format!("{}%", prefix.to_string())));
+                let query = schema::$table::table
+                    .filter(schema::$table::repo_id.eq(repo_id))
+                    .filter(schema::$table::name.like(format!("{}%", prefix.to_string())));
 
                 query
-                    .get_results::<models::BookmarkRow>(&*connection)
+                    .get_results::<models::$row>(&*connection)
                     .into_future()
                     .and_then(|bookmarks| {
                         let bookmarks = bookmarks
@@ -184,12 +209,56 @@ macro_rules! impl_bookmarks {
                     .boxify()
             }
 
-            fn create_transaction(&self, repoid: &RepositoryId) -> Box<Transaction> {
+            fn create_transaction(
+                &self,
+                repoid: &RepositoryId,
+                reason: BookmarkUpdateReason,
+                session: &str,
+            ) -> Box<Transaction> {
                 Box::new($transaction_struct::new(
                     self.clone(),
                     repoid,
+                    reason,
+                    session,
                 ))
             }
+
+            fn read_log(
+                &self,
+                name: &Bookmark,
+                repo_id: &RepositoryId,
+            ) -> BoxStream<BookmarkUpdateLogEntry, Error> {
+                #[allow(unreachable_code, unreachable_patterns)] // sqlite can't fail
+                let connection = match self.get_conn() {
+                    Ok(conn) => conn,
+                    Err(err) => {
+                        return stream::once(Err(err)).boxify();
+                    },
+                };
+
+                let name = name.clone();
+                schema::$log_table::table
+                    .filter(schema::$log_table::repo_id.eq(repo_id))
+                    .filter(schema::$log_table::name.eq(name.to_string()))
+                    .order(schema::$log_table::id.desc())
+                    .get_results::<models::BookmarkUpdateLogRow>(&*connection)
+                    .into_future()
+                    .from_err()
+                    .map(|rows| stream::iter_ok::<_, Error>(rows))
+                    .flatten_stream()
+                    .and_then(move |row| {
+                        Ok(BookmarkUpdateLogEntry {
+                            id: row.id as u64,
+                            name: name.clone(),
+                            from_changeset_id: row.from_changeset_id,
+                            to_changeset_id: row.to_changeset_id,
+                            reason: row.reason.parse()?,
+                            session: row.session,
+                            timestamp: row.timestamp,
+                        })
+                    })
+                    .boxify()
+            }
         }
 
         struct $transaction_struct {
@@ -200,10 +269,17 @@ macro_rules! impl_bookmarks {
             force_deletes: HashSet<Bookmark>,
             deletes: HashMap<Bookmark, HgChangesetId>,
             repo_id: RepositoryId,
+            reason: BookmarkUpdateReason,
+            session: String,
         }
 
         impl $transaction_struct {
-            fn new(db: $struct, repo_id: &RepositoryId) -> Self {
+            fn new(
+                db: $struct,
+                repo_id: &RepositoryId,
+                reason: BookmarkUpdateReason,
+                session: &str,
+            ) -> Self {
                 Self {
                     db,
                     force_sets: HashMap::new(),
@@ -212,6 +288,8 @@ macro_rules! impl_bookmarks {
                     force_deletes: HashSet::new(),
                     deletes: HashMap::new(),
                     repo_id: *repo_id,
+                    reason,
+                    session: session.to_string(),
                 }
             }
 
@@ -224,6 +302,34 @@ macro_rules! impl_bookmarks {
                 }
                 Ok(())
             }
+
+            fn create_rows(&self, map: &HashMap<Bookmark, HgChangesetId>) -> Vec<models::$row> {
+                map.iter()
+                    .map(|(name, changeset_id)| models::$row {
+                        repo_id: self.repo_id,
+                        name: name.to_string(),
+                        changeset_id: *changeset_id,
+                    })
+                    .collect()
+            }
+
+            fn create_log_row(
+                &self,
+                name: &Bookmark,
+                from_changeset_id: Option<HgChangesetId>,
+                to_changeset_id: Option<HgChangesetId>,
+                timestamp: i64,
+            ) -> models::$log_row {
+                models::$log_row {
+                    repo_id: self.repo_id,
+                    name: name.to_string(),
+                    from_changeset_id,
+                    to_changeset_id,
+                    reason: self.reason.to_string(),
+                    session: self.session.clone(),
+                    timestamp,
+                }
+            }
         }
 
         impl Transaction for $transaction_struct {
@@ -271,24 +377,47 @@ macro_rules! impl_bookmarks {
             fn commit(&self) -> BoxFuture<(), Error> {
                 #[allow(unreachable_code, unreachable_patterns)] // sqlite can't fail
                 let connection = try_boxfuture!(self.db.get_conn());
+                let timestamp = current_timestamp();
 
                 let txnres = connection.transaction::<_, Error, _>(|| {
-                    replace_into(schema::bookmarks::table)
-                        .values(&create_bookmarks_rows(self.repo_id, &self.force_sets))
+                    // The previous values of the bookmarks that are set or deleted unconditionally
+                    // are only known in the transaction
+                    let get_current = |key: &Bookmark| {
+                        schema::$table::table
+                            .filter(schema::$table::repo_id.eq(self.repo_id))
+                            .filter(schema::$table::name.eq(key.to_string()))
+                            .select(schema::$table::changeset_id)
+                            .first::<HgChangesetId>(&*connection)
+                            .optional()
+                    };
+                    let mut log_rows = vec![];
+
+                    for (key, new_cs) in self.force_sets.iter() {
+                        let old_cs = get_current(key)?;
+                        log_rows.push(self.create_log_row(key, old_cs, Some(*new_cs), timestamp));
+                    }
+                    for key in self.force_deletes.iter() {
+                        if let Some(old_cs) = get_current(key)? {
+                            log_rows.push(self.create_log_row(key, Some(old_cs), None, timestamp));
+                        }
+                    }
+
+                    replace_into(schema::$table::table)
+                        .values(&self.create_rows(&self.force_sets))
                         .execute(&*connection)?;
 
-                    insert_into(schema::bookmarks::table)
-                        .values(&create_bookmarks_rows(self.repo_id, &self.creates))
+                    insert_into(schema::$table::table)
+                        .values(&self.create_rows(&self.creates))
                         .execute(&*connection)?;
 
                     for (key, &BookmarkSetData { new_cs, old_cs }) in self.sets.iter() {
                         let key = key.to_string();
                         let num_affected_rows = update(
-                            schema::bookmarks::table
-                                .filter(schema::bookmarks::repo_id.eq(self.repo_id))
-                                .filter(schema::bookmarks::name.eq(key.clone()))
-                                .filter(schema::bookmarks::changeset_id.eq(old_cs)),
-                        ).set(schema::bookmarks::changeset_id.eq(new_cs))
+                            schema::$table::table
+                                .filter(schema::$table::repo_id.eq(self.repo_id))
+                                .filter(schema::$table::name.eq(key.clone()))
+                                .filter(schema::$table::changeset_id.eq(old_cs)),
+                        ).set(schema::$table::changeset_id.eq(new_cs))
                             .execute(&*connection)?;
                         if num_affected_rows != 1 {
                             bail_msg!("cannot update bookmark {}", key);
@@ -297,9 +426,9 @@ macro_rules! impl_bookmarks {
 
                     for key in self.force_deletes.iter() {
                         let key = key.to_string();
-                        delete(schema::bookmarks::table
-                                .filter(schema::bookmarks::repo_id.eq(self.repo_id))
-                                .filter(schema::bookmarks::name.eq(key))
+                        delete(schema::$table::table
+                                .filter(schema::$table::repo_id.eq(self.repo_id))
+                                .filter(schema::$table::name.eq(key))
                             )
                             .execute(&*connection)?;
                     }
@@ -307,15 +436,30 @@ macro_rules! impl_bookmarks {
                     for (key, old_cs) in self.deletes.iter() {
                         let key = key.to_string();
                         let num_deleted_rows = delete(
-                            schema::bookmarks::table
-                                .filter(schema::bookmarks::repo_id.eq(self.repo_id))
-                                .filter(schema::bookmarks::name.eq(key.clone()))
-                                .filter(schema::bookmarks::changeset_id.eq(old_cs)),
+                            schema::$table::table
+                                .filter(schema::$table::repo_id.eq(self.repo_id))
+                                .filter(schema::$table::name.eq(key.clone()))
+                                .filter(schema::$table::changeset_id.eq(old_cs)),
                         ).execute(&*connection)?;
                         if num_deleted_rows != 1 {
                             bail_msg!("cannot delete bookmark {}", key);
                         }
                     }
+
+                    for (key, new_cs) in self.creates.iter() {
+                        log_rows.push(self.create_log_row(key, None, Some(*new_cs), timestamp));
+                    }
+                    for (key, data) in self.sets.iter() {
+                        let (old_cs, new_cs) = (Some(data.old_cs), Some(data.new_cs));
+                        log_rows.push(self.create_log_row(key, old_cs, new_cs, timestamp));
+                    }
+                    for (key, old_cs) in self.deletes.iter() {
+                        log_rows.push(self.create_log_row(key, Some(*old_cs), None, timestamp));
+                    }
+
+                    insert_into(schema::$log_table::table)
+                        .values(&log_rows)
+                        .execute(&*connection)?;
                     Ok(())
                 });
                 future::result(txnres).from_err().boxify()
@@ -324,23 +468,48 @@ macro_rules! impl_bookmarks {
     }
 }
 
-impl_bookmarks!(SqliteDbBookmarks, SqliteBookmarksTransaction);
-impl_bookmarks!(MysqlDbBookmarks, MysqlBookmarksTransaction);
+impl_bookmarks!(
+    SqliteDbBookmarks,
+    SqliteBookmarksTransaction,
+    bookmarks,
+    BookmarkRow,
+    bookmarks_update_log,
+    NewBookmarkUpdateLogRow
+);
+impl_bookmarks!(
+    MysqlDbBookmarks,
+    MysqlBookmarksTransaction,
+    bookmarks,
+    BookmarkRow,
+    bookmarks_update_log,
+    NewBookmarkUpdateLogRow
+);
+impl_bookmarks!(
+    SqliteDbScratchBookmarks,
+    SqliteScratchBookmarksTransaction,
+    scratch_bookmarks,
+    ScratchBookmarkRow,
+    scratch_bookmarks_update_log,
+    NewScratchBookmarkUpdateLogRow
+);
+impl_bookmarks!(
+    MysqlDbScratchBookmarks,
+    MysqlScratchBookmarksTransaction,
+    scratch_bookmarks,
+    ScratchBookmarkRow,
+    scratch_bookmarks_update_log,
+    NewScratchBookmarkUpdateLogRow
+);
+
+/// Current time in seconds since the epoch, as stored in the bookmark update logs
+fn current_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time is before the epoch")
+        .as_secs() as i64
+}
 
 struct BookmarkSetData {
     new_cs: HgChangesetId,
     old_cs: HgChangesetId,
 }
-
-fn create_bookmarks_rows(
-    repo_id: RepositoryId,
-    map: &HashMap<Bookmark, HgChangesetId>,
-) -> Vec<models::BookmarkRow> {
-    map.iter()
-        .map(|(name, changeset_id)| models::BookmarkRow {
-            repo_id,
-            name: name.to_string(),
-            changeset_id: *changeset_id,
-        })
-        .collect()
-}
@@ -6,7 +6,7 @@
 
 use mercurial_types::{HgChangesetId, RepositoryId};
 
-use schema::bookmarks;
+use schema::{bookmarks, bookmarks_update_log, scratch_bookmarks, scratch_bookmarks_update_log};
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 #[derive(Queryable, Insertable)]
@@ -17,3 +17,52 @@ pub(crate) struct BookmarkRow {
     pub name: String,
     pub changeset_id: HgChangesetId,
 }
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Queryable, Insertable)]
+#[table_name = "scratch_bookmarks"]
+pub(crate) struct ScratchBookmarkRow {
+    pub repo_id: RepositoryId,
+    pub name: String,
+    pub changeset_id: HgChangesetId,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Queryable)]
+pub(crate) struct BookmarkUpdateLogRow {
+    pub id: i64,
+    pub repo_id: RepositoryId,
+    pub name: String,
+    pub from_changeset_id: Option<HgChangesetId>,
+    pub to_changeset_id: Option<HgChangesetId>,
+    pub reason: String,
+    pub session: String,
+    pub timestamp: i64,
+}
+
+/// The ids of the entries are assigned by the database
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Insertable)]
+#[table_name = "bookmarks_update_log"]
+pub(crate) struct NewBookmarkUpdateLogRow {
+    pub repo_id: RepositoryId,
+    pub name: String,
+    pub from_changeset_id: Option<HgChangesetId>,
+    pub to_changeset_id: Option<HgChangesetId>,
+    pub reason: String,
+    pub session: String,
+    pub timestamp: i64,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Insertable)]
+#[table_name = "scratch_bookmarks_update_log"]
+pub(crate) struct NewScratchBookmarkUpdateLogRow {
+    pub repo_id: RepositoryId,
+    pub name: String,
+    pub from_changeset_id: Option<HgChangesetId>,
+    pub to_changeset_id: Option<HgChangesetId>,
+    pub reason: String,
+    pub session: String,
+    pub timestamp: i64,
+}
@@ -19,3 +19,49 @@ table! {
         changeset_id -> HgChangesetIdSql,
     }
 }
+
+table! {
+    use diesel::sql_types::{Integer, Text};
+
+    use mercurial_types::sql_types::HgChangesetIdSql;
+
+    scratch_bookmarks (repo_id, name) {
+        repo_id -> Integer,
+        name -> Text,
+        changeset_id -> HgChangesetIdSql,
+    }
+}
+
+table! {
+    use diesel::sql_types::{BigInt, Integer, Nullable, Text};
+
+    use mercurial_types::sql_types::HgChangesetIdSql;
+
+    bookmarks_update_log (id) {
+        id -> BigInt,
+        repo_id -> Integer,
+        name -> Text,
+        from_changeset_id -> Nullable<HgChangesetIdSql>,
+        to_changeset_id -> Nullable<HgChangesetIdSql>,
+        reason -> Text,
+        session -> Text,
+        timestamp -> BigInt,
+    }
+}
+
+table! {
+    use diesel::sql_types::{BigInt, Integer, Nullable, Text};
+
+    use mercurial_types::sql_types::HgChangesetIdSql;
+
+    scratch_bookmarks_update_log (id) {
+        id -> BigInt,
+        repo_id -> Integer,
+        name -> Text,
+        from_changeset_id -> Nullable<HgChangesetIdSql>,
+        to_changeset_id -> Nullable<HgChangesetIdSql>,
+        reason -> Text,
+        session -> Text,
+        timestamp -> BigInt,
+    }
+}
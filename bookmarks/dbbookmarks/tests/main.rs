@@ -20,11 +20,15 @@ extern crate mercurial_types;
 extern crate mercurial_types_mocks;
 extern crate tokio;
 
-use bookmarks::{Bookmark, BookmarkPrefix};
-use dbbookmarks::{MysqlDbBookmarks, SqliteDbBookmarks};
+use bookmarks::{Bookmark, BookmarkPrefix, BookmarkUpdateReason};
+use dbbookmarks::{MysqlDbBookmarks, MysqlDbScratchBookmarks, SqliteDbBookmarks,
+                  SqliteDbScratchBookmarks};
 use mercurial_types_mocks::nodehash::{ONES_CSID, TWOS_CSID};
 use mercurial_types_mocks::repo::{REPO_ONE, REPO_ZERO};
 
+const REASON: BookmarkUpdateReason = BookmarkUpdateReason::TestMove;
+const SESSION: &str = "test session";
+
 fn create_bookmark(book: &str) -> Bookmark {
     Bookmark::new(book.to_string()).unwrap()
 }
@@ -50,7 +54,7 @@ macro_rules! bookmarks_test_impl {
                 let name_correct = create_bookmark("book");
                 let name_incorrect = create_bookmark("book2");
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.force_set(&name_correct, &ONES_CSID).unwrap();
                 txn.commit().wait().unwrap();
 
@@ -70,7 +74,7 @@ macro_rules! bookmarks_test_impl {
                 let name_1 = create_bookmark("book");
                 let name_2 = create_bookmark("book2");
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.force_set(&name_1, &ONES_CSID).unwrap();
                 txn.force_set(&name_2, &TWOS_CSID).unwrap();
                 txn.commit().wait().unwrap();
@@ -90,11 +94,11 @@ macro_rules! bookmarks_test_impl {
                 let bookmarks = $new_cb();
                 let name_1 = create_bookmark("book");
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.force_set(&name_1, &ONES_CSID).unwrap();
                 txn.commit().wait().unwrap();
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.force_set(&name_1, &ONES_CSID).unwrap();
                 txn.commit().wait().unwrap();
 
@@ -109,7 +113,7 @@ macro_rules! bookmarks_test_impl {
                 let bookmarks = $new_cb();
                 let name_1 = create_bookmark("book");
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.create(&name_1, &ONES_CSID).unwrap();
                 txn.commit().wait().unwrap();
 
@@ -124,11 +128,11 @@ macro_rules! bookmarks_test_impl {
                 let bookmarks = $new_cb();
                 let name_1 = create_bookmark("book");
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.create(&name_1, &ONES_CSID).unwrap();
                 txn.commit().wait().unwrap();
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.create(&name_1, &ONES_CSID).unwrap();
                 assert!(txn.commit().wait().is_err());
             }
@@ -138,35 +142,35 @@ macro_rules! bookmarks_test_impl {
                 let bookmarks = $new_cb();
                 let name_1 = create_bookmark("book");
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.create(&name_1, &ONES_CSID).unwrap();
                 assert!(txn.force_set(&name_1, &ONES_CSID).is_err());
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.force_set(&name_1, &ONES_CSID).unwrap();
                 assert!(txn.create(&name_1, &ONES_CSID).is_err());
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.force_set(&name_1, &ONES_CSID).unwrap();
                 assert!(txn.update(&name_1, &TWOS_CSID, &ONES_CSID).is_err());
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.update(&name_1, &TWOS_CSID, &ONES_CSID).unwrap();
                 assert!(txn.force_set(&name_1, &ONES_CSID).is_err());
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.update(&name_1, &TWOS_CSID, &ONES_CSID).unwrap();
                 assert!(txn.force_delete(&name_1).is_err());
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.force_delete(&name_1).unwrap();
                 assert!(txn.update(&name_1, &TWOS_CSID, &ONES_CSID).is_err());
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.delete(&name_1, &ONES_CSID).unwrap();
                 assert!(txn.update(&name_1, &TWOS_CSID, &ONES_CSID).is_err());
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.update(&name_1, &TWOS_CSID, &ONES_CSID).unwrap();
                 assert!(txn.delete(&name_1, &ONES_CSID).is_err());
             }
@@ -176,11 +180,11 @@ macro_rules! bookmarks_test_impl {
                 let bookmarks = $new_cb();
                 let name_1 = create_bookmark("book");
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.create(&name_1, &ONES_CSID).unwrap();
                 txn.commit().wait().unwrap();
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.update(&name_1, &TWOS_CSID, &ONES_CSID).unwrap();
                 txn.commit().wait().unwrap();
 
@@ -195,7 +199,7 @@ macro_rules! bookmarks_test_impl {
                 let bookmarks = $new_cb();
                 let name_1 = create_bookmark("book");
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.update(&name_1, &TWOS_CSID, &ONES_CSID).unwrap();
                 assert!(txn.commit().wait().is_err());
             }
@@ -205,11 +209,11 @@ macro_rules! bookmarks_test_impl {
                 let bookmarks = $new_cb();
                 let name_1 = create_bookmark("book");
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.create(&name_1, &ONES_CSID).unwrap();
                 txn.commit().wait().unwrap();
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.update(&name_1, &ONES_CSID, &TWOS_CSID).unwrap();
                 assert!(txn.commit().wait().is_err());
             }
@@ -219,18 +223,18 @@ macro_rules! bookmarks_test_impl {
                 let bookmarks = $new_cb();
                 let name_1 = create_bookmark("book");
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.force_delete(&name_1).unwrap();
                 txn.commit().wait().unwrap();
 
                 assert_eq!(bookmarks.get(&name_1, &REPO_ZERO).wait().unwrap(), None);
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.create(&name_1, &ONES_CSID).unwrap();
                 txn.commit().wait().unwrap();
                 assert!(bookmarks.get(&name_1, &REPO_ZERO).wait().unwrap().is_some());
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.force_delete(&name_1).unwrap();
                 txn.commit().wait().unwrap();
 
@@ -242,16 +246,16 @@ macro_rules! bookmarks_test_impl {
                 let bookmarks = $new_cb();
                 let name_1 = create_bookmark("book");
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.delete(&name_1, &ONES_CSID).unwrap();
                 assert!(txn.commit().wait().is_err());
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.create(&name_1, &ONES_CSID).unwrap();
                 txn.commit().wait().unwrap();
                 assert!(bookmarks.get(&name_1, &REPO_ZERO).wait().unwrap().is_some());
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.delete(&name_1, &ONES_CSID).unwrap();
                 txn.commit().wait().unwrap();
             }
@@ -261,12 +265,12 @@ macro_rules! bookmarks_test_impl {
                 let bookmarks = $new_cb();
                 let name_1 = create_bookmark("book");
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.create(&name_1, &ONES_CSID).unwrap();
                 txn.commit().wait().unwrap();
                 assert!(bookmarks.get(&name_1, &REPO_ZERO).wait().unwrap().is_some());
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.delete(&name_1, &TWOS_CSID).unwrap();
                 assert!(txn.commit().wait().is_err());
             }
@@ -277,7 +281,7 @@ macro_rules! bookmarks_test_impl {
                 let name_1 = create_bookmark("book1");
                 let name_2 = create_bookmark("book2");
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.create(&name_1, &ONES_CSID).unwrap();
                 txn.create(&name_2, &ONES_CSID).unwrap();
                 txn.commit().wait().unwrap();
@@ -318,17 +322,17 @@ macro_rules! bookmarks_test_impl {
                 let bookmarks = $new_cb();
                 let name_1 = create_bookmark("book");
 
-                let mut txn = bookmarks.create_transaction(&REPO_ZERO);
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
                 txn.force_set(&name_1, &ONES_CSID).unwrap();
                 assert!(txn.commit().wait().is_ok());
 
                 // Updating value from another repo, should fail
-                let mut txn = bookmarks.create_transaction(&REPO_ONE);
+                let mut txn = bookmarks.create_transaction(&REPO_ONE, REASON, SESSION);
                 txn.update(&name_1, &TWOS_CSID, &ONES_CSID).unwrap();
                 assert!(txn.commit().wait().is_err());
 
                 // Creating value should succeed
-                let mut txn = bookmarks.create_transaction(&REPO_ONE);
+                let mut txn = bookmarks.create_transaction(&REPO_ONE, REASON, SESSION);
                 txn.create(&name_1, &TWOS_CSID).unwrap();
                 assert!(txn.commit().wait().is_ok());
 
@@ -336,16 +340,81 @@ macro_rules! bookmarks_test_impl {
                 assert_eq!(bookmarks.get(&name_1, &REPO_ONE).wait().unwrap(), Some(TWOS_CSID));
 
                 // Force deleting should delete only from one repo
-                let mut txn = bookmarks.create_transaction(&REPO_ONE);
+                let mut txn = bookmarks.create_transaction(&REPO_ONE, REASON, SESSION);
                 txn.force_delete(&name_1).unwrap();
                 assert!(txn.commit().wait().is_ok());
                 assert_eq!(bookmarks.get(&name_1, &REPO_ZERO).wait().unwrap(), Some(ONES_CSID));
 
                 // delete should fail for another repo
-                let mut txn = bookmarks.create_transaction(&REPO_ONE);
+                let mut txn = bookmarks.create_transaction(&REPO_ONE, REASON, SESSION);
                 txn.delete(&name_1, &ONES_CSID).unwrap();
                 assert!(txn.commit().wait().is_err());
             }
+
+            #[test]
+            fn test_update_log() {
+                let bookmarks = $new_cb();
+                let name_1 = create_bookmark("book1");
+                let name_2 = create_bookmark("book2");
+                let read_log = |name: &Bookmark| {
+                    bookmarks
+                        .read_log(name, &REPO_ZERO)
+                        .map(|entry| {
+                            (
+                                entry.from_changeset_id,
+                                entry.to_changeset_id,
+                                entry.reason,
+                                entry.session,
+                            )
+                        })
+                        .collect()
+                        .wait()
+                        .unwrap()
+                };
+
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
+                txn.create(&name_1, &ONES_CSID).unwrap();
+                txn.commit().wait().unwrap();
+
+                let mut txn =
+                    bookmarks.create_transaction(&REPO_ZERO, BookmarkUpdateReason::Push, "pusher");
+                txn.update(&name_1, &TWOS_CSID, &ONES_CSID).unwrap();
+                txn.force_set(&name_2, &ONES_CSID).unwrap();
+                txn.commit().wait().unwrap();
+
+                // Failed transactions are not logged
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
+                txn.force_set(&name_2, &TWOS_CSID).unwrap();
+                txn.update(&name_1, &ONES_CSID, &ONES_CSID).unwrap();
+                assert!(txn.commit().wait().is_err());
+
+                let mut txn = bookmarks.create_transaction(&REPO_ZERO, REASON, SESSION);
+                txn.force_delete(&name_1).unwrap();
+                txn.commit().wait().unwrap();
+
+                assert_eq!(
+                    read_log(&name_1),
+                    vec![
+                        (Some(TWOS_CSID), None, REASON, SESSION.to_string()),
+                        (
+                            Some(ONES_CSID),
+                            Some(TWOS_CSID),
+                            BookmarkUpdateReason::Push,
+                            "pusher".to_string(),
+                        ),
+                        (None, Some(ONES_CSID), REASON, SESSION.to_string()),
+                    ]
+                );
+                assert_eq!(
+                    read_log(&name_2),
+                    vec![
+                        (None, Some(ONES_CSID), BookmarkUpdateReason::Push, "pusher".to_string()),
+                    ]
+                );
+
+                let log = bookmarks.read_log(&name_1, &REPO_ONE).collect().wait().unwrap();
+                assert!(log.is_empty());
+            }
         }
     }
 }
@@ -358,6 +427,14 @@ bookmarks_test_impl!(mysql_tests => {
      new: create_mysql,
  });
 
+bookmarks_test_impl!(sqlite_scratch_tests => {
+     new: create_sqlite_scratch,
+ });
+
+bookmarks_test_impl!(mysql_scratch_tests => {
+     new: create_mysql_scratch,
+ });
+
 fn create_sqlite() -> SqliteDbBookmarks {
     SqliteDbBookmarks::in_memory().unwrap()
 }
@@ -365,3 +442,11 @@ fn create_sqlite() -> SqliteDbBookmarks {
 fn create_mysql() -> MysqlDbBookmarks {
     MysqlDbBookmarks::create_test_db("mononokefilenodestest").unwrap()
 }
+
+fn create_sqlite_scratch() -> SqliteDbScratchBookmarks {
+    SqliteDbScratchBookmarks::in_memory().unwrap()
+}
+
+fn create_mysql_scratch() -> MysqlDbScratchBookmarks {
+    MysqlDbScratchBookmarks::create_test_db("mononokescratchbookmarkstest").unwrap()
+}
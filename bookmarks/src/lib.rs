@@ -13,6 +13,7 @@ extern crate futures_ext;
 extern crate mercurial_types;
 
 use std::fmt;
+use std::str::FromStr;
 
 use ascii::AsciiString;
 use failure::{Error, Result};
@@ -89,6 +90,72 @@ impl BookmarkPrefix {
     }
 }
 
+/// Why a transaction moves bookmarks, recorded in the update log of the bookmarks it moves.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BookmarkUpdateReason {
+    Push,
+    Pushrebase,
+    Blobimport,
+    Gitimport,
+    /// A bookmark moved by hand, e.g. with the admin tool
+    ManualMove,
+    /// A bookmark moved back to the value it had after an earlier entry of its update log
+    Rollback,
+    TestMove,
+}
+
+impl fmt::Display for BookmarkUpdateReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use BookmarkUpdateReason::*;
+
+        let reason = match *self {
+            Push => "push",
+            Pushrebase => "pushrebase",
+            Blobimport => "blobimport",
+            Gitimport => "gitimport",
+            ManualMove => "manualmove",
+            Rollback => "rollback",
+            TestMove => "testmove",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+impl FromStr for BookmarkUpdateReason {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        use BookmarkUpdateReason::*;
+
+        match s {
+            "push" => Ok(Push),
+            "pushrebase" => Ok(Pushrebase),
+            "blobimport" => Ok(Blobimport),
+            "gitimport" => Ok(Gitimport),
+            "manualmove" => Ok(ManualMove),
+            "rollback" => Ok(Rollback),
+            "testmove" => Ok(TestMove),
+            _ => bail_msg!("unknown bookmark update reason: {}", s),
+        }
+    }
+}
+
+/// An entry of the update log of a bookmark. `from_changeset_id` is None if the bookmark was
+/// created, `to_changeset_id` is None if it was deleted.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BookmarkUpdateLogEntry {
+    /// Increases with every entry of the log of the repo
+    pub id: u64,
+    pub name: Bookmark,
+    pub from_changeset_id: Option<HgChangesetId>,
+    pub to_changeset_id: Option<HgChangesetId>,
+    pub reason: BookmarkUpdateReason,
+    /// Identity of the session that moved the bookmark, e.g. the user and host of a push
+    pub session: String,
+    /// Unix timestamp of the commit of the transaction, in seconds
+    pub timestamp: i64,
+}
+
 pub trait Bookmarks: Send + Sync + 'static {
     /// Returns Some(HgChangesetId) if bookmark exists, returns None if doesn't
     fn get(&self, name: &Bookmark, repoid: &RepositoryId)
@@ -104,8 +171,21 @@ pub trait Bookmarks: Send + Sync + 'static {
         repoid: &RepositoryId,
     ) -> BoxStream<(Bookmark, HgChangesetId), Error>;
 
-    /// Creates a transaction that will be used for write operations.
-    fn create_transaction(&self, repoid: &RepositoryId) -> Box<Transaction>;
+    /// Creates a transaction that will be used for write operations. Committing it appends an
+    /// entry with `reason` and `session` to the update log of every bookmark it moves.
+    fn create_transaction(
+        &self,
+        repoid: &RepositoryId,
+        reason: BookmarkUpdateReason,
+        session: &str,
+    ) -> Box<Transaction>;
+
+    /// Lists the entries of the update log of a bookmark, the most recent first.
+    fn read_log(
+        &self,
+        name: &Bookmark,
+        repoid: &RepositoryId,
+    ) -> BoxStream<BookmarkUpdateLogEntry, Error>;
 }
 
 pub trait Transaction: Send + Sync + 'static {
@@ -0,0 +1,270 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Restrictions on how pushes may move the bookmarks of a repository. Bookmarks without a policy
+//! may be moved by anyone in any way.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use futures::{future, Future};
+
+use blobrepo::BlobRepo;
+use bookmarks::Bookmark;
+use futures_ext::{BoxFuture, FutureExt};
+use mercurial_types::HgChangesetId;
+use repoinfo::RepoGenCache;
+use revset::is_ancestor_indexed;
+use skiplist::SkiplistIndex;
+
+use errors::*;
+
+/// How a push may move a single bookmark
+#[derive(Clone, Debug)]
+pub struct BookmarkPolicy {
+    /// The bookmark may only be moved to a descendant of where it points
+    pub only_fast_forward: bool,
+    /// The bookmark may be deleted
+    pub allow_delete: bool,
+    /// If set, only these users may move the bookmark
+    pub allowed_users: Option<Vec<String>>,
+}
+
+#[derive(Clone)]
+pub struct BookmarkPolicies {
+    repo: Arc<BlobRepo>,
+    repo_generation: RepoGenCache,
+    index: SkiplistIndex,
+    policies: HashMap<Bookmark, BookmarkPolicy>,
+}
+
+impl BookmarkPolicies {
+    pub fn new(
+        repo: Arc<BlobRepo>,
+        repo_generation: RepoGenCache,
+        index: SkiplistIndex,
+        policies: HashMap<Bookmark, BookmarkPolicy>,
+    ) -> Self {
+        BookmarkPolicies {
+            repo,
+            repo_generation,
+            index,
+            policies,
+        }
+    }
+
+    /// Checks that `user` may move `bookmark` from `old` to `new`, `None` meaning that the
+    /// bookmark does not exist before or after the move.
+    pub fn check_move(
+        &self,
+        bookmark: &Bookmark,
+        user: Option<&str>,
+        old: Option<HgChangesetId>,
+        new: Option<HgChangesetId>,
+    ) -> BoxFuture<(), Error> {
+        let policy = match self.policies.get(bookmark) {
+            Some(policy) => policy,
+            None => return future::ok(()).boxify(),
+        };
+        try_boxfuture!(check_user(bookmark, policy, user));
+
+        match (old, new) {
+            (Some(_), None) if !policy.allow_delete => {
+                future::err(ErrorKind::BookmarkDeleteNotAllowed(bookmark.clone()).into()).boxify()
+            }
+            (Some(old), Some(new)) if policy.only_fast_forward && old != new => {
+                let bookmark = bookmark.clone();
+                is_ancestor_indexed(
+                    &self.index,
+                    &self.repo,
+                    self.repo_generation.clone(),
+                    *old.as_nodehash(),
+                    *new.as_nodehash(),
+                ).from_err()
+                    .and_then(move |is_ancestor| {
+                        if is_ancestor {
+                            Ok(())
+                        } else {
+                            Err(ErrorKind::BookmarkNotFastForward(bookmark, old, new).into())
+                        }
+                    })
+                    .boxify()
+            }
+            _ => future::ok(()).boxify(),
+        }
+    }
+
+    /// Checks that `user` may pushrebase onto `bookmark`. A pushrebase only moves the bookmark
+    /// to a descendant of where it points, so this can be checked before rebasing anything.
+    pub fn check_pushrebase(&self, bookmark: &Bookmark, user: Option<&str>) -> Result<()> {
+        match self.policies.get(bookmark) {
+            Some(policy) => check_user(bookmark, policy, user),
+            None => Ok(()),
+        }
+    }
+}
+
+fn check_user(bookmark: &Bookmark, policy: &BookmarkPolicy, user: Option<&str>) -> Result<()> {
+    if let Some(ref allowed_users) = policy.allowed_users {
+        if !user.map_or(false, |user| allowed_users.iter().any(|allowed| allowed == user)) {
+            let user = user.unwrap_or("unknown user").to_string();
+            bail_err!(ErrorKind::BookmarkMoverNotAllowed(bookmark.clone(), user));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use async_unit;
+    use linear;
+    use mercurial_types::HgNodeHash;
+
+    // Ancestor and descendant in the linear fixture repo
+    const OLD: &str = "d0a361e9022d226ae52f689667bd7d212a19cfe0";
+    const NEW: &str = "a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157";
+
+    fn cs_id(hash: &str) -> Option<HgChangesetId> {
+        Some(HgChangesetId::new(HgNodeHash::from_str(hash).unwrap()))
+    }
+
+    fn make_policies(policy: BookmarkPolicy) -> (BookmarkPolicies, Bookmark) {
+        let bookmark = Bookmark::new("master").unwrap();
+        let repo = Arc::new(linear::getrepo(None));
+        let policies = BookmarkPolicies::new(
+            repo.clone(),
+            RepoGenCache::new(10),
+            SkiplistIndex::new(repo, 100000),
+            hashmap! { bookmark.clone() => policy },
+        );
+        (policies, bookmark)
+    }
+
+    fn permissive() -> BookmarkPolicy {
+        BookmarkPolicy {
+            only_fast_forward: false,
+            allow_delete: true,
+            allowed_users: None,
+        }
+    }
+
+    fn check_error(result: Result<()>) -> ErrorKind {
+        result
+            .expect_err("unexpected OK")
+            .downcast::<ErrorKind>()
+            .expect("unexpected error kind")
+    }
+
+    #[test]
+    fn no_policy() {
+        async_unit::tokio_unit_test(|| {
+            let (policies, _) = make_policies(BookmarkPolicy {
+                only_fast_forward: true,
+                allow_delete: false,
+                allowed_users: Some(vec![]),
+            });
+            let other = Bookmark::new("other").unwrap();
+            policies
+                .check_move(&other, None, cs_id(NEW), None)
+                .wait()
+                .expect("bookmarks without a policy can be moved in any way");
+        })
+    }
+
+    #[test]
+    fn allowed_users() {
+        async_unit::tokio_unit_test(|| {
+            let (policies, bookmark) = make_policies(BookmarkPolicy {
+                allowed_users: Some(vec!["alice".to_string()]),
+                ..permissive()
+            });
+            policies
+                .check_move(&bookmark, Some("alice"), cs_id(OLD), cs_id(NEW))
+                .wait()
+                .expect("alice is allowed to move the bookmark");
+
+            policies
+                .check_pushrebase(&bookmark, Some("alice"))
+                .expect("alice is allowed to pushrebase onto the bookmark");
+
+            for user in vec![Some("mallory"), None] {
+                let result = policies
+                    .check_move(&bookmark, user, cs_id(OLD), cs_id(NEW))
+                    .wait();
+                match check_error(result) {
+                    ErrorKind::BookmarkMoverNotAllowed(..) => {}
+                    err => panic!("unexpected error: {}", err),
+                }
+                match check_error(policies.check_pushrebase(&bookmark, user)) {
+                    ErrorKind::BookmarkMoverNotAllowed(..) => {}
+                    err => panic!("unexpected error: {}", err),
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn fast_forward() {
+        async_unit::tokio_unit_test(|| {
+            let (policies, bookmark) = make_policies(BookmarkPolicy {
+                only_fast_forward: true,
+                ..permissive()
+            });
+            policies
+                .check_move(&bookmark, None, cs_id(OLD), cs_id(NEW))
+                .wait()
+                .expect("moving to a descendant is a fast-forward");
+            policies
+                .check_move(&bookmark, None, None, cs_id(NEW))
+                .wait()
+                .expect("creating the bookmark is allowed");
+
+            let result = policies
+                .check_move(&bookmark, None, cs_id(NEW), cs_id(OLD))
+                .wait();
+            match check_error(result) {
+                ErrorKind::BookmarkNotFastForward(..) => {}
+                err => panic!("unexpected error: {}", err),
+            }
+        })
+    }
+
+    #[test]
+    fn non_fast_forward() {
+        async_unit::tokio_unit_test(|| {
+            let (policies, bookmark) = make_policies(permissive());
+            policies
+                .check_move(&bookmark, None, cs_id(NEW), cs_id(OLD))
+                .wait()
+                .expect("the bookmark can be moved backwards");
+        })
+    }
+
+    #[test]
+    fn delete() {
+        async_unit::tokio_unit_test(|| {
+            let (policies, bookmark) = make_policies(permissive());
+            policies
+                .check_move(&bookmark, None, cs_id(NEW), None)
+                .wait()
+                .expect("the bookmark can be deleted");
+
+            let (policies, bookmark) = make_policies(BookmarkPolicy {
+                allow_delete: false,
+                ..permissive()
+            });
+            let result = policies.check_move(&bookmark, None, cs_id(NEW), None).wait();
+            match check_error(result) {
+                ErrorKind::BookmarkDeleteNotAllowed(..) => {}
+                err => panic!("unexpected error: {}", err),
+            }
+        })
+    }
+}
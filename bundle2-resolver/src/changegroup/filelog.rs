@@ -10,16 +10,17 @@ use std::sync::Arc;
 
 use bytes::Bytes;
 use failure::Compat;
-use futures::{Future, IntoFuture, Stream};
+use futures::{future, Future, IntoFuture, Stream};
 use futures::future::Shared;
 use futures_ext::{BoxFuture, BoxStream, FutureExt, StreamExt};
 use heapsize::HeapSizeOf;
 use quickcheck::{Arbitrary, Gen};
 
-use blobrepo::{BlobRepo, ContentBlobInfo, HgBlobEntry, UploadHgFileContents, UploadHgFileEntry,
-               UploadHgNodeHash};
+use blobrepo::{BlobRepo, ContentBlobInfo, ContentBlobMeta, HgBlobEntry, UploadHgFileContents,
+               UploadHgFileEntry, UploadHgNodeHash};
 use mercurial_bundles::changegroup::CgDeltaChunk;
-use mercurial_types::{delta, Delta, FileType, HgNodeHash, HgNodeKey, MPath, RepoPath, NULL_HASH};
+use mercurial_types::{delta, Delta, FileType, HgBlobNode, HgNodeHash, HgNodeKey, MPath, RepoPath,
+                      NULL_HASH};
 
 use errors::*;
 use stats::*;
@@ -38,6 +39,9 @@ pub struct Filelog {
     pub p2: Option<HgNodeHash>,
     pub linknode: HgNodeHash,
     pub data: Bytes,
+    // If the data is the pointer that Mercurial's lfs extension stores instead of large contents,
+    // the meta of the contents it points to, which the file is uploaded with instead
+    pub lfs_content: Option<ContentBlobMeta>,
 }
 
 impl UploadableHgBlob for Filelog {
@@ -59,7 +63,10 @@ impl UploadableHgBlob for Filelog {
         };
         let upload = UploadHgFileEntry {
             upload_node_id: UploadHgNodeHash::Checked(node_key.hash),
-            contents: UploadHgFileContents::RawBytes(self.data),
+            contents: match self.lfs_content {
+                Some(meta) => UploadHgFileContents::ContentUploaded(meta),
+                None => UploadHgFileContents::RawBytes(self.data),
+            },
             // XXX should this really be Regular?
             file_type: FileType::Regular,
             p1: self.p1,
@@ -79,7 +86,7 @@ pub fn convert_to_revlog_filelog<S>(repo: Arc<BlobRepo>, deltaed: S) -> BoxStrea
 where
     S: Stream<Item = FilelogDeltaed, Error = Error> + Send + 'static,
 {
-    let mut delta_cache = DeltaCache::new(repo);
+    let mut delta_cache = DeltaCache::new(repo.clone());
     deltaed
         .and_then(move |FilelogDeltaed { path, chunk }| {
             let CgDeltaChunk {
@@ -94,18 +101,39 @@ where
             delta_cache
                 .decode(node.clone(), base.into_option(), delta)
                 .and_then({
+                    let repo = repo.clone();
                     let node = node.clone();
                     let path = path.clone();
                     move |data| {
-                        Ok(Filelog {
+                        let p1 = p1.into_option();
+                        let p2 = p2.into_option();
+                        let lfs_content = repo.upload_hg_lfs_pointer(&data).and_then(|upload| {
+                            // Files whose contents merely look like a pointer are hashed with
+                            // them, while the hash of the files the pointer stands for is
+                            // that of the contents it points to.
+                            let node_id = HgBlobNode::new(data.clone(), p1.as_ref(), p2.as_ref())
+                                .nodeid();
+                            if node_id == Some(node) {
+                                None
+                            } else {
+                                Some(upload)
+                            }
+                        });
+                        let lfs_content = match lfs_content {
+                            Some(upload) => upload.map(Some).boxify(),
+                            None => future::ok(None).boxify(),
+                        };
+
+                        lfs_content.map(move |lfs_content| Filelog {
                             node_key: HgNodeKey {
                                 path: RepoPath::FilePath(path),
                                 hash: node,
                             },
-                            p1: p1.into_option(),
-                            p2: p2.into_option(),
+                            p1,
+                            p2,
                             linknode,
                             data,
+                            lfs_content,
                         })
                     }
                 })
@@ -222,6 +250,7 @@ impl Arbitrary for Filelog {
             p2: HgNodeHash::arbitrary(g).into_option(),
             linknode: HgNodeHash::arbitrary(g),
             data: Bytes::from(Vec::<u8>::arbitrary(g)),
+            lfs_content: None,
         }
     }
 
@@ -280,6 +309,7 @@ mod tests {
     use mercurial_types::NULL_HASH;
     use mercurial_types::delta::Fragment;
     use mercurial_types_mocks::nodehash::*;
+    use mononoke_types::FileContents;
 
     struct NodeHashGen {
         bytes: Vec<u8>,
@@ -393,6 +423,7 @@ mod tests {
             p2: Some(THREES_HASH),
             linknode: FOURS_HASH,
             data: Bytes::from("test file content"),
+            lfs_content: None,
         };
 
         let f2 = Filelog {
@@ -404,6 +435,7 @@ mod tests {
             p2: Some(SEVENS_HASH),
             linknode: EIGHTS_HASH,
             data: Bytes::from("test2 file content"),
+            lfs_content: None,
         };
 
         check_conversion(
@@ -422,6 +454,7 @@ mod tests {
             p2: Some(THREES_HASH),
             linknode: FOURS_HASH,
             data: Bytes::from("test file content"),
+            lfs_content: None,
         };
 
         let f2 = Filelog {
@@ -433,6 +466,7 @@ mod tests {
             p2: Some(SEVENS_HASH),
             linknode: EIGHTS_HASH,
             data: Bytes::from("test2 file content"),
+            lfs_content: None,
         };
 
         let f1_deltaed = filelog_to_deltaed(&f1);
@@ -475,6 +509,54 @@ mod tests {
         files_check_order(false);
     }
 
+    #[test]
+    fn lfs_pointer() {
+        let mut repo = BlobRepo::new_memblob_empty(None, None).unwrap();
+        repo.set_lfs_threshold(Some(2));
+        let repo = Arc::new(repo);
+        let pointer = repo.upload_lfs_content(Bytes::from("blob")).wait().unwrap();
+        let pointer_file = pointer.to_pointer_file();
+
+        // Mercurial's lfs extension sends the pointer with the hash of the contents
+        let node = HgBlobNode::new(Bytes::from("blob"), None, None)
+            .nodeid()
+            .unwrap();
+        let f = Filelog {
+            node_key: HgNodeKey {
+                path: RepoPath::FilePath(MPath::new(b"test").unwrap()),
+                hash: node,
+            },
+            p1: None,
+            p2: None,
+            linknode: FOURS_HASH,
+            data: pointer_file.clone(),
+            lfs_content: None,
+        };
+
+        let result = convert_to_revlog_filelog(repo.clone(), iter_ok(vec![filelog_to_deltaed(&f)]))
+            .collect()
+            .wait()
+            .unwrap();
+        let meta = result[0].lfs_content.clone().expect("pointer wasn't recognised");
+        assert_eq!(meta.id, FileContents::new_bytes("blob").content_id());
+
+        let (_, (_, upload)) = result[0].clone().upload(&repo).unwrap();
+        upload.wait().unwrap();
+        let content = repo.get_file_content(&node).wait().unwrap();
+        assert_eq!(content.into_bytes(), Bytes::from("blob"));
+
+        // A file whose contents merely look like a pointer keeps them
+        let mut f = f.clone();
+        f.node_key.hash = HgBlobNode::new(pointer_file, None, None)
+            .nodeid()
+            .unwrap();
+        let result = convert_to_revlog_filelog(repo, iter_ok(vec![filelog_to_deltaed(&f)]))
+            .collect()
+            .wait()
+            .unwrap();
+        assert_equal(result, vec![f]);
+    }
+
     quickcheck! {
         fn sanitycheck_delta_computation(b1: Vec<u8>, b2: Vec<u8>) -> bool {
             assert_equal(&b2, &delta::apply(&b1, &compute_delta(&b1, &b2)).unwrap());
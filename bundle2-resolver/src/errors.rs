@@ -6,11 +6,26 @@
 
 pub use failure::prelude::*;
 
-use mercurial_types::HgNodeHash;
+use bookmarks::Bookmark;
+use mercurial_types::{HgChangesetId, HgNodeHash, MPath};
 
 #[derive(Debug, Fail)]
 pub enum ErrorKind {
     #[fail(display = "Malformed treemanifest part: {}", _0)] MalformedTreemanifestPart(String),
     #[fail(display = "Error while uploading data for changesets, hashes: {:?}", _0)]
     WhileUploadingData(Vec<HgNodeHash>),
+    #[fail(display = "Bookmark {} to pushrebase onto does not exist", _0)]
+    PushrebaseBookmarkNotFound(Bookmark),
+    #[fail(display = "Pushed changesets can't be pushrebased: {}", _0)]
+    PushrebaseInvalidStack(String),
+    #[fail(display = "Base of the pushed stack {} is not an ancestor of {}", _0, _1)]
+    PushrebaseBaseNotAncestor(HgChangesetId, HgChangesetId),
+    #[fail(display = "Pushrebase conflicts, files modified on the server: {:?}", _0)]
+    PushrebaseConflicts(Vec<MPath>),
+    #[fail(display = "{} is not allowed to move bookmark {}", _1, _0)]
+    BookmarkMoverNotAllowed(Bookmark, String),
+    #[fail(display = "Bookmark {} can't be deleted", _0)] BookmarkDeleteNotAllowed(Bookmark),
+    #[fail(display = "Bookmark {} can only be fast-forwarded, {} is not an ancestor of {}", _0,
+           _1, _2)]
+    BookmarkNotFastForward(Bookmark, HgChangesetId, HgChangesetId),
 }
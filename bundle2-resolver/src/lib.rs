@@ -7,6 +7,8 @@
 #![deny(warnings)]
 
 extern crate ascii;
+#[cfg(test)]
+extern crate async_unit;
 extern crate bytes;
 #[macro_use]
 extern crate failure_ext as failure;
@@ -28,6 +30,7 @@ extern crate quickcheck;
 #[macro_use]
 extern crate quickcheck;
 extern crate scuba_ext;
+extern crate serde_json;
 #[macro_use]
 extern crate slog;
 #[macro_use]
@@ -36,18 +39,28 @@ extern crate tokio_io;
 
 extern crate blobrepo;
 extern crate bookmarks;
+extern crate hooks;
+#[cfg(test)]
+extern crate linear;
 extern crate mercurial;
 extern crate mercurial_bundles;
 extern crate mercurial_types;
 #[cfg(test)]
 extern crate mercurial_types_mocks;
 extern crate mononoke_types;
+extern crate phases;
+extern crate repoinfo;
+extern crate revset;
+extern crate skiplist;
 
+mod bookmark_policy;
 mod changegroup;
 pub mod errors;
+mod pushrebase;
 mod resolver;
 mod stats;
 mod wirepackparser;
 mod upload_blobs;
 
+pub use bookmark_policy::{BookmarkPolicies, BookmarkPolicy};
 pub use resolver::resolve;
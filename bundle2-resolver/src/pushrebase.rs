@@ -0,0 +1,648 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Pushrebase recreates a pushed stack of changesets on top of the current position of a
+//! bookmark, so that pushes don't fail just because the bookmark moved after the client pulled.
+//!
+//! The pushed changesets have to form a linear stack whose base is an ancestor of the bookmark.
+//! The push is rejected if the pushed changesets touch files that were modified on the bookmark
+//! since the base of the stack. Otherwise the manifest of each pushed changeset is merged with
+//! the manifest of its new parent, taking the files it modified from the pushed changeset and the
+//! files modified on the bookmark from the new parent, without any content merging.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use blobrepo::{BlobChangeset, BlobRepo, ChangesetHandle, CreateChangeset, HgBlobEntry};
+use blobrepo::ErrorKind as BlobRepoErrorKind;
+use blobrepo::internal::MemoryRootManifest;
+use bookmarks::Bookmark;
+use failure::FutureFailureErrorExt;
+use futures::{Future, Stream};
+use futures::future::{self, Loop};
+use futures::stream;
+use futures_ext::{BoxFuture, FutureExt, StreamExt};
+use mercurial_types::{Changeset, HgChangesetId, HgManifestId, HgNodeHash, MPath, RepoPath};
+use mercurial_types::manifest::Content;
+use scuba_ext::ScubaSampleBuilder;
+
+use errors::*;
+
+/// Outcome of a successful pushrebase
+pub struct PushrebaseSuccess {
+    /// Position of the bookmark before the pushrebase
+    pub old_head: HgChangesetId,
+    /// Position the bookmark should be moved to
+    pub new_head: HgChangesetId,
+    /// Pushed changesets and the changesets they were rebased to, in the order they were pushed.
+    /// Empty if the pushed stack was already based on the current position of the bookmark.
+    pub rebased_changesets: Vec<(HgChangesetId, HgChangesetId)>,
+}
+
+/// Rebases the pushed changesets (which must be already uploaded) onto the bookmark. Note that
+/// the bookmark itself is not moved, that's the responsibility of the caller.
+pub fn do_pushrebase(
+    repo: Arc<BlobRepo>,
+    scuba_logger: ScubaSampleBuilder,
+    onto: Bookmark,
+    pushed: Vec<HgChangesetId>,
+) -> BoxFuture<PushrebaseSuccess, Error> {
+    if pushed.is_empty() {
+        let err = ErrorKind::PushrebaseInvalidStack("no changesets were pushed".into());
+        return future::err(err.into()).boxify();
+    }
+
+    let fetch_pushed = future::join_all(pushed.clone().into_iter().map({
+        let repo = repo.clone();
+        move |changeset_id| repo.get_changeset_by_changesetid(&changeset_id)
+    }));
+    let fetch_head = repo.get_bookmark(&onto).and_then({
+        let onto = onto.clone();
+        move |head| head.ok_or(ErrorKind::PushrebaseBookmarkNotFound(onto).into())
+    });
+
+    fetch_pushed
+        .join(fetch_head)
+        .and_then(move |(changesets, old_head)| {
+            let base = try_boxfuture!(find_stack_base(&pushed, &changesets));
+            if base == old_head {
+                let new_head = *pushed.last().expect("pushed changesets are not empty");
+                return future::ok(PushrebaseSuccess {
+                    old_head,
+                    new_head,
+                    rebased_changesets: vec![],
+                }).boxify();
+            }
+
+            find_changed_files(repo.clone(), old_head, base)
+                .and_then(move |server_files| {
+                    check_conflicts(&changesets, &server_files)?;
+                    Ok((changesets, server_files))
+                })
+                .and_then(move |(changesets, server_files)| {
+                    rebase_changesets(repo, scuba_logger, old_head, changesets, server_files)
+                })
+                .map(move |rebased_changesets| {
+                    let new_head = rebased_changesets
+                        .last()
+                        .expect("pushed changesets are not empty")
+                        .1;
+                    PushrebaseSuccess {
+                        old_head,
+                        new_head,
+                        rebased_changesets,
+                    }
+                })
+                .boxify()
+        })
+        .with_context(move |_| format!("While pushrebasing onto {}", onto))
+        .from_err()
+        .boxify()
+}
+
+/// Checks that the pushed changesets form a linear stack and returns the parent of its root
+fn find_stack_base(
+    pushed: &[HgChangesetId],
+    changesets: &[BlobChangeset],
+) -> Result<HgChangesetId> {
+    let mut base = None;
+    for (idx, cs) in changesets.iter().enumerate() {
+        if cs.p2().is_some() {
+            bail_err!(ErrorKind::PushrebaseInvalidStack(format!(
+                "merge changeset {} can't be pushrebased",
+                pushed[idx]
+            )));
+        }
+        let p1 = cs.p1().map(|p1| HgChangesetId::new(*p1));
+        if idx == 0 {
+            base = p1;
+        } else if p1 != Some(pushed[idx - 1]) {
+            bail_err!(ErrorKind::PushrebaseInvalidStack(format!(
+                "changeset {} is not a child of {}",
+                pushed[idx],
+                pushed[idx - 1]
+            )));
+        }
+    }
+    base.ok_or_else(|| {
+        ErrorKind::PushrebaseInvalidStack(format!("root changeset {} has no parent", pushed[0]))
+            .into()
+    })
+}
+
+/// Collects the files modified between `base` and `head`. Only the first parents of the
+/// changesets are followed, so `base` has to be a first parent ancestor of `head`.
+fn find_changed_files(
+    repo: Arc<BlobRepo>,
+    head: HgChangesetId,
+    base: HgChangesetId,
+) -> BoxFuture<HashSet<MPath>, Error> {
+    future::loop_fn((head, HashSet::new()), move |(current, mut files)| {
+        if current == base {
+            return future::ok(Loop::Break(files)).boxify();
+        }
+        repo.get_changeset_by_changesetid(&current)
+            .and_then(move |cs| {
+                files.extend(cs.files().iter().cloned());
+                match cs.p1() {
+                    Some(p1) => Ok(Loop::Continue((HgChangesetId::new(*p1), files))),
+                    None => Err(ErrorKind::PushrebaseBaseNotAncestor(base, head).into()),
+                }
+            })
+            .boxify()
+    }).boxify()
+}
+
+/// Fails if any of the pushed changesets modifies a file (or a directory containing a file)
+/// that was modified on the server
+fn check_conflicts(changesets: &[BlobChangeset], server_files: &HashSet<MPath>) -> Result<()> {
+    let mut conflicts: Vec<_> = changesets
+        .iter()
+        .flat_map(|cs| cs.files().iter())
+        .filter(|path| {
+            server_files
+                .iter()
+                .any(|server| server.is_prefix_of(*path) || path.is_prefix_of(server))
+        })
+        .cloned()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    if conflicts.is_empty() {
+        Ok(())
+    } else {
+        conflicts.sort();
+        Err(ErrorKind::PushrebaseConflicts(conflicts).into())
+    }
+}
+
+/// Creates the changesets one by one on top of `onto`. Returns pairs of (original, rebased)
+/// changeset ids.
+fn rebase_changesets(
+    repo: Arc<BlobRepo>,
+    scuba_logger: ScubaSampleBuilder,
+    onto: HgChangesetId,
+    changesets: Vec<BlobChangeset>,
+    server_files: HashSet<MPath>,
+) -> BoxFuture<Vec<(HgChangesetId, HgChangesetId)>, Error> {
+    let server_files = Arc::new(server_files);
+    repo.get_changeset_by_changesetid(&onto)
+        .and_then(move |onto_cs| {
+            stream::iter_ok(changesets)
+                .fold(
+                    (onto_cs, Vec::new()),
+                    move |(parent, mut rebased), cs| {
+                        rebase_changeset(
+                            repo.clone(),
+                            scuba_logger.clone(),
+                            server_files.clone(),
+                            parent,
+                            cs,
+                        ).map(move |(original, new_cs)| {
+                            rebased.push((original, new_cs.get_changeset_id()));
+                            (new_cs, rebased)
+                        })
+                    },
+                )
+                .map(|(_, rebased)| rebased)
+        })
+        .boxify()
+}
+
+/// Creates a changeset with the changes of `cs` on top of `parent`, where `server_files` are
+/// the files modified on the bookmark since the base of the pushed stack.
+fn rebase_changeset(
+    repo: Arc<BlobRepo>,
+    scuba_logger: ScubaSampleBuilder,
+    server_files: Arc<HashSet<MPath>>,
+    parent: BlobChangeset,
+    cs: BlobChangeset,
+) -> BoxFuture<(HgChangesetId, BlobChangeset), Error> {
+    let original = cs.get_changeset_id();
+    let files: Vec<_> = cs.files().into();
+
+    rebase_manifest(
+        repo.clone(),
+        parent.manifestid().into_nodehash(),
+        cs.manifestid().into_nodehash(),
+        files.clone(),
+        server_files,
+    ).and_then(move |manifest| {
+        let mut extra = cs.extra().clone();
+        extra.insert(b"rebase_source".to_vec(), format!("{}", original).into_bytes());
+
+        let root_manifest =
+            HgBlobEntry::new_root(repo.get_blobstore(), HgManifestId::new(manifest));
+        let create_changeset = CreateChangeset {
+            expected_nodeid: None,
+            expected_files: Some(files),
+            p1: Some(ChangesetHandle::from(parent)),
+            p2: None,
+            root_manifest: future::ok(Some((root_manifest, RepoPath::root()))).boxify(),
+            // All the entries of the manifest are already in the blobstore
+            sub_entries: stream::empty().boxify(),
+            user: String::from_utf8(cs.user().into())?,
+            time: cs.time().clone(),
+            extra,
+            comments: String::from_utf8(cs.comments().into())?,
+            bonsai: None,
+        };
+        Ok(create_changeset.create(&repo, scuba_logger))
+    })
+        .and_then(|handle| {
+            handle
+                .get_completed_changeset()
+                .map_err(Error::from)
+                .map(|cs| (*cs).clone())
+        })
+        .map(move |new_cs| (original, new_cs))
+        .with_context(move |_| format!("While rebasing changeset {}", original))
+        .from_err()
+        .boxify()
+}
+
+/// Merges `manifest` into `onto`. The two manifests differ only in the `files` modified by the
+/// pushed changeset and in the `server_files` modified on the bookmark, and those never overlap,
+/// so the conflicts are resolved by taking `files` from `manifest` and everything else from
+/// `onto`. The files removed on one side, which the merge brings back from the other side, are
+/// then removed again.
+fn rebase_manifest(
+    repo: Arc<BlobRepo>,
+    onto: HgNodeHash,
+    manifest: HgNodeHash,
+    files: Vec<MPath>,
+    server_files: Arc<HashSet<MPath>>,
+) -> BoxFuture<HgNodeHash, Error> {
+    let removed = future::join_all(
+        files
+            .iter()
+            .map(|path| (path.clone(), manifest, onto))
+            .chain(server_files.iter().map(|path| (path.clone(), onto, manifest)))
+            .map({
+                let repo = repo.clone();
+                move |(path, source, other)| {
+                    path_exists(&repo, &path, source)
+                        .join(path_exists(&repo, &path, other))
+                        .map(move |exists| match exists {
+                            (false, true) => Some(path),
+                            _ => None,
+                        })
+                }
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    MemoryRootManifest::new_rebased((*repo).clone(), &onto, &manifest)
+        .join(removed)
+        .and_then(move |(memory_manifest, removed)| {
+            let memory_manifest = Arc::new(memory_manifest);
+            memory_manifest
+                .resolve_trivial_conflicts()
+                .and_then({
+                    let memory_manifest = memory_manifest.clone();
+                    move |()| {
+                        // A conflict between a file and a directory belongs to the side whose
+                        // changes touched the files in that directory
+                        memory_manifest.resolve_conflicts_with(|path| {
+                            if files.iter().any(|file| path.is_prefix_of(file)) {
+                                1
+                            } else {
+                                0
+                            }
+                        });
+                        stream::iter_ok(removed.into_iter().filter_map(|path| path)).for_each(
+                            move |path| {
+                                memory_manifest.change_entry(&path, None).or_else(|err| {
+                                    match err.downcast::<BlobRepoErrorKind>() {
+                                        // The file was in a directory that the merge
+                                        // replaced with a file, so it's already gone
+                                        Ok(BlobRepoErrorKind::PathNotFound(_)) => Ok(()),
+                                        Ok(kind) => Err(kind.into()),
+                                        Err(err) => Err(err),
+                                    }
+                                })
+                            },
+                        )
+                    }
+                })
+                .and_then(move |()| memory_manifest.save())
+                .map(|entry| entry.get_hash().into_nodehash())
+        })
+        .boxify()
+}
+
+/// Whether there's a file or a directory at `path` in `manifest`
+fn path_exists(repo: &BlobRepo, path: &MPath, manifest: HgNodeHash) -> BoxFuture<bool, Error> {
+    let (dirname, basename) = path.split_dirname();
+    let basename = basename.clone();
+    repo.find_path_in_manifest(dirname, manifest)
+        .map(move |content| match content {
+            Some(Content::Tree(manifest)) => manifest.lookup(&basename).is_some(),
+            _ => false,
+        })
+        .boxify()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+
+    use async_unit;
+    use blobrepo::{UploadHgFileContents, UploadHgFileEntry, UploadHgNodeHash};
+    use bookmarks::BookmarkUpdateReason;
+    use bytes::Bytes;
+    use linear;
+    use mercurial_types::{FileType, MPathElement};
+    use mercurial_types::nodehash::HgEntryId;
+    use mononoke_types::DateTime;
+
+    // Changesets of the linear fixture repo, each of them adds a file named after its position
+    // (counting from 1) and modifies the file `files`
+    const ROOT: &str = "2d7d4ba9ce0a6ffd222de7785b249ead9c51c536";
+    const REV6: &str = "0ed509bf086fadcb8a8a5384dc3b550729b0fc17";
+    const REV7: &str = "a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157";
+    const REV8: &str = "3c15267ebf11807f3d772eb891272b911ec68759";
+    const HEAD: &str = "a5ffa77602a066db7d5cfb9fb5823a0895717c5a";
+
+    fn cs_id(hash: &str) -> HgChangesetId {
+        HgChangesetId::from_str(hash).unwrap()
+    }
+
+    fn path(path: &str) -> MPath {
+        MPath::new(path).unwrap()
+    }
+
+    fn fetch(repo: &BlobRepo, hashes: &[&str]) -> (Vec<HgChangesetId>, Vec<BlobChangeset>) {
+        let ids: Vec<_> = hashes.iter().map(|hash| cs_id(hash)).collect();
+        let changesets = future::join_all(
+            ids.iter()
+                .map(|id| repo.get_changeset_by_changesetid(id))
+                .collect::<Vec<_>>(),
+        ).wait()
+            .unwrap();
+        (ids, changesets)
+    }
+
+    /// The linear repo with the bookmark `master` at `head`
+    fn repo_with_master(head: &str) -> (Arc<BlobRepo>, Bookmark) {
+        let repo = Arc::new(linear::getrepo(None));
+        let master = Bookmark::new("master").unwrap();
+
+        let mut txn = repo.update_bookmark_transaction(BookmarkUpdateReason::TestMove, "test");
+        txn.create(&master, &cs_id(head)).unwrap();
+        txn.commit().wait().expect("Bookmark creation failed");
+
+        (repo, master)
+    }
+
+    /// Creates a changeset on top of `parent` which adds the file `file`
+    fn create_changeset(
+        repo: &BlobRepo,
+        parent: HgChangesetId,
+        file: &str,
+        content: &'static str,
+    ) -> HgChangesetId {
+        let parent = repo.get_changeset_by_changesetid(&parent).wait().unwrap();
+        let memory_manifest =
+            MemoryRootManifest::new(repo.clone(), Some(&parent.manifestid().into_nodehash()), None)
+                .wait()
+                .unwrap();
+
+        let upload = UploadHgFileEntry {
+            upload_node_id: UploadHgNodeHash::Generate,
+            contents: UploadHgFileContents::RawBytes(Bytes::from(content)),
+            file_type: FileType::Regular,
+            p1: None,
+            p2: None,
+            path: path(file),
+        };
+        let (_, upload) = upload.upload(repo).unwrap();
+        let (entry, _) = upload.wait().unwrap();
+        memory_manifest
+            .change_entry(&path(file), Some(entry))
+            .wait()
+            .unwrap();
+        let root_manifest = memory_manifest.save().wait().unwrap();
+
+        let create_changeset = CreateChangeset {
+            expected_nodeid: None,
+            expected_files: None,
+            p1: Some(ChangesetHandle::from(parent)),
+            p2: None,
+            root_manifest: future::ok(Some((root_manifest, RepoPath::root()))).boxify(),
+            sub_entries: stream::empty().boxify(),
+            user: "author <author@fb.com>".into(),
+            time: DateTime::from_timestamp(0, 0).unwrap(),
+            extra: BTreeMap::new(),
+            comments: format!("Add {}", file),
+            bonsai: None,
+        };
+        create_changeset
+            .create(repo, ScubaSampleBuilder::with_discard())
+            .get_completed_changeset()
+            .map_err(Error::from)
+            .wait()
+            .unwrap()
+            .get_changeset_id()
+    }
+
+    /// The top level entries of the manifest of `cs`
+    fn manifest_entries(repo: &BlobRepo, cs: HgChangesetId) -> BTreeMap<MPathElement, HgEntryId> {
+        let cs = repo.get_changeset_by_changesetid(&cs).wait().unwrap();
+        repo.get_manifest_by_nodeid(&cs.manifestid().into_nodehash())
+            .wait()
+            .unwrap()
+            .list()
+            .map(|entry| (entry.get_name().unwrap().clone(), entry.get_hash().clone()))
+            .collect()
+    }
+
+    /// The pushrebase error `result` failed with, which might be wrapped in some context
+    fn error_kind<T>(result: &Result<T>) -> &ErrorKind {
+        result
+            .as_ref()
+            .err()
+            .expect("unexpected OK")
+            .causes()
+            .filter_map(|cause| cause.downcast_ref::<ErrorKind>())
+            .next()
+            .expect("unexpected error kind")
+    }
+
+    #[test]
+    fn stack_base() {
+        async_unit::tokio_unit_test(|| {
+            let repo = linear::getrepo(None);
+            let (pushed, changesets) = fetch(&repo, &[REV7, REV8, HEAD]);
+            assert_eq!(find_stack_base(&pushed, &changesets).unwrap(), cs_id(REV6));
+        })
+    }
+
+    #[test]
+    fn stack_base_not_linear() {
+        async_unit::tokio_unit_test(|| {
+            let repo = linear::getrepo(None);
+            let (pushed, changesets) = fetch(&repo, &[REV7, HEAD]);
+            match error_kind(&find_stack_base(&pushed, &changesets)) {
+                &ErrorKind::PushrebaseInvalidStack(..) => {}
+                err => panic!("unexpected error: {}", err),
+            }
+        })
+    }
+
+    #[test]
+    fn stack_base_root() {
+        async_unit::tokio_unit_test(|| {
+            let repo = linear::getrepo(None);
+            let (pushed, changesets) = fetch(&repo, &[ROOT]);
+            match error_kind(&find_stack_base(&pushed, &changesets)) {
+                &ErrorKind::PushrebaseInvalidStack(..) => {}
+                err => panic!("unexpected error: {}", err),
+            }
+        })
+    }
+
+    #[test]
+    fn conflicts() {
+        async_unit::tokio_unit_test(|| {
+            let repo = linear::getrepo(None);
+            let (_, changesets) = fetch(&repo, &[REV8, HEAD]);
+
+            let unrelated = hashset! { path("1"), path("dir/2") };
+            check_conflicts(&changesets, &unrelated).expect("no files in common");
+
+            let server_files = hashset! { path("1"), path("files") };
+            match error_kind(&check_conflicts(&changesets, &server_files)) {
+                &ErrorKind::PushrebaseConflicts(ref conflicts) => {
+                    assert_eq!(*conflicts, vec![path("files")])
+                }
+                err => panic!("unexpected error: {}", err),
+            }
+
+            // A file that became a directory, or the other way around
+            let server_files = hashset! { path("9/file"), path("10") };
+            match error_kind(&check_conflicts(&changesets, &server_files)) {
+                &ErrorKind::PushrebaseConflicts(ref conflicts) => {
+                    assert_eq!(*conflicts, vec![path("10"), path("9")])
+                }
+                err => panic!("unexpected error: {}", err),
+            }
+        })
+    }
+
+    #[test]
+    fn pushrebase_nothing_pushed() {
+        async_unit::tokio_unit_test(|| {
+            let (repo, master) = repo_with_master(HEAD);
+            let result = do_pushrebase(repo, ScubaSampleBuilder::with_discard(), master, vec![])
+                .wait();
+            match error_kind(&result) {
+                &ErrorKind::PushrebaseInvalidStack(..) => {}
+                err => panic!("unexpected error: {}", err),
+            }
+        })
+    }
+
+    #[test]
+    fn pushrebase_missing_bookmark() {
+        async_unit::tokio_unit_test(|| {
+            let (repo, _) = repo_with_master(REV8);
+            let other = Bookmark::new("other").unwrap();
+            let result =
+                do_pushrebase(repo, ScubaSampleBuilder::with_discard(), other, vec![cs_id(HEAD)])
+                    .wait();
+            match error_kind(&result) {
+                &ErrorKind::PushrebaseBookmarkNotFound(..) => {}
+                err => panic!("unexpected error: {}", err),
+            }
+        })
+    }
+
+    #[test]
+    fn pushrebase_already_based_on_bookmark() {
+        async_unit::tokio_unit_test(|| {
+            let (repo, master) = repo_with_master(REV8);
+            let success =
+                do_pushrebase(repo, ScubaSampleBuilder::with_discard(), master, vec![cs_id(HEAD)])
+                    .wait()
+                    .expect("pushrebase should succeed");
+            assert_eq!(success.old_head, cs_id(REV8));
+            assert_eq!(success.new_head, cs_id(HEAD));
+            assert!(success.rebased_changesets.is_empty());
+        })
+    }
+
+    #[test]
+    fn pushrebase_conflicts() {
+        async_unit::tokio_unit_test(|| {
+            // The stack based on REV7 modifies `9` and `files`, and so did the changesets
+            // between REV7 and the bookmark
+            let (repo, master) = repo_with_master(HEAD);
+            let result =
+                do_pushrebase(repo, ScubaSampleBuilder::with_discard(), master, vec![cs_id(REV8)])
+                    .wait();
+            match error_kind(&result) {
+                &ErrorKind::PushrebaseConflicts(ref conflicts) => {
+                    assert_eq!(*conflicts, vec![path("9"), path("files")])
+                }
+                err => panic!("unexpected error: {}", err),
+            }
+        })
+    }
+
+    #[test]
+    fn pushrebase_stack() {
+        async_unit::tokio_unit_test(|| {
+            // A stack based on REV6 that only adds new files, while the bookmark moved to HEAD
+            let (repo, master) = repo_with_master(HEAD);
+            let first = create_changeset(&repo, cs_id(REV6), "pushed", "pushed\n");
+            let second = create_changeset(&repo, first, "dir/pushed", "pushed to dir\n");
+
+            let success = do_pushrebase(
+                repo.clone(),
+                ScubaSampleBuilder::with_discard(),
+                master,
+                vec![first, second],
+            ).wait()
+                .expect("pushrebase should succeed");
+            assert_eq!(success.old_head, cs_id(HEAD));
+            assert_eq!(success.rebased_changesets.len(), 2);
+            assert_eq!(success.rebased_changesets[0].0, first);
+            assert_eq!(success.rebased_changesets[1].0, second);
+            assert_eq!(success.new_head, success.rebased_changesets[1].1);
+
+            let mut parent = cs_id(HEAD);
+            for &(original, rebased) in &success.rebased_changesets {
+                let cs = repo.get_changeset_by_changesetid(&rebased).wait().unwrap();
+                assert_eq!(cs.p1(), Some(parent.as_nodehash()));
+                assert_eq!(cs.p2(), None);
+                assert_eq!(
+                    cs.extra().get(&b"rebase_source"[..]),
+                    Some(&format!("{}", original).into_bytes())
+                );
+                parent = rebased;
+            }
+
+            // The rebased changesets have all the files of the bookmark, plus the pushed ones
+            let head_entries = manifest_entries(&repo, cs_id(HEAD));
+            let mut expected = head_entries.clone();
+            let pushed = MPathElement::new(b"pushed".to_vec()).unwrap();
+            expected.insert(pushed.clone(), manifest_entries(&repo, first)[&pushed].clone());
+            assert_eq!(
+                manifest_entries(&repo, success.rebased_changesets[0].1),
+                expected
+            );
+
+            let dir = MPathElement::new(b"dir".to_vec()).unwrap();
+            expected.insert(dir.clone(), manifest_entries(&repo, second)[&dir].clone());
+            assert_eq!(manifest_entries(&repo, success.new_head), expected);
+            assert_eq!(head_entries.get(&pushed), None);
+        })
+    }
+}
@@ -7,28 +7,34 @@
 use std::collections::HashMap;
 use std::io::Cursor;
 use std::ops::AddAssign;
+use std::str::FromStr;
 use std::sync::Arc;
 
 use ascii::AsciiString;
 use blobrepo::{BlobRepo, ChangesetHandle, ContentBlobInfo, CreateChangeset, HgBlobEntry};
-use bookmarks;
-use bytes::Bytes;
+use bookmarks::{self, BookmarkUpdateReason};
+use bytes::{Bytes, BytesMut};
 use failure::{Compat, FutureFailureErrorExt, StreamFailureErrorExt};
 use futures::{Future, IntoFuture, Stream};
 use futures::future::{self, err, ok, Shared};
 use futures::stream;
 use futures_ext::{BoxFuture, BoxStream, FutureExt, StreamExt};
-use mercurial::changeset::RevlogChangeset;
+use hooks::{HookExecution, HookManager, HookRejectionInfo};
+use mercurial::changeset::{serialize_cs, RevlogChangeset};
 use mercurial::manifest::{Details, ManifestContent};
 use mercurial_bundles::{parts, Bundle2EncodeBuilder, Bundle2Item};
-use mercurial_types::{HgChangesetId, HgManifestId, HgNodeHash, HgNodeKey, MPath, RepoPath,
-                      NULL_HASH};
+use mercurial_types::{Changeset, HgBlobNode, HgChangesetId, HgManifestId, HgNodeHash, HgNodeKey,
+                      MPath, RepoPath, NULL_HASH};
+use phases::{Phase, RepoPhases};
 use scuba_ext::{ScubaSampleBuilder, ScubaSampleBuilderExt};
+use serde_json;
 use slog::Logger;
 use stats::*;
 
+use bookmark_policy::BookmarkPolicies;
 use changegroup::{convert_to_revlog_changesets, convert_to_revlog_filelog, split_changegroup};
 use errors::*;
+use pushrebase::{do_pushrebase, PushrebaseSuccess};
 use upload_blobs::{upload_hg_blobs, UploadBlobsType, UploadableHgBlob};
 use wirepackparser::{TreemanifestBundle2Parser, TreemanifestEntry};
 
@@ -38,20 +44,41 @@ type Filelogs = HashMap<HgNodeKey, Shared<BoxFuture<(HgBlobEntry, RepoPath), Com
 type ContentBlobs = HashMap<HgNodeKey, ContentBlobInfo>;
 type Manifests = HashMap<HgNodeKey, <TreemanifestEntry as UploadableHgBlob>::Value>;
 type UploadedChangesets = HashMap<HgNodeHash, ChangesetHandle>;
+/// Scratch bookmarks to move, `None` means that the bookmark should be deleted
+type ScratchBookmarks = HashMap<bookmarks::Bookmark, Option<HgChangesetId>>;
 
 /// The resolve function takes a bundle2, interprets it's content as Changesets, Filelogs and
 /// Manifests and uploades all of them to the provided BlobRepo in the correct order.
+/// Before any bookmark is moved the pushed changesets are checked by the hooks that are
+/// configured for that bookmark. If any of the hooks rejects the push the bookmarks are left
+/// untouched and the response contains an error part with the reason of the rejection.
 /// It returns a Future that contains the response that should be send back to the requester.
+/// The bookmark moves are recorded in the bookmark update logs as made by `session`, and are
+/// rejected if they break the policies of the bookmarks for `user`.
 pub fn resolve(
     repo: Arc<BlobRepo>,
     logger: Logger,
     scuba_logger: ScubaSampleBuilder,
+    hook_manager: Arc<HookManager>,
+    phases: RepoPhases,
+    bookmark_policies: BookmarkPolicies,
+    session: String,
+    user: Option<String>,
     heads: Vec<String>,
     bundle2: BoxStream<Bundle2Item, Error>,
 ) -> BoxFuture<Bytes, Error> {
     info!(logger, "unbundle heads {:?}", heads);
 
-    let resolver = Bundle2Resolver::new(repo, logger, scuba_logger);
+    let resolver = Bundle2Resolver::new(
+        repo,
+        logger,
+        scuba_logger,
+        hook_manager,
+        phases,
+        bookmark_policies,
+        session,
+        user,
+    );
 
     let bundle2 = resolver.resolve_start_and_replycaps(bundle2);
 
@@ -63,85 +90,182 @@ pub fn resolve(
                 resolver
                     .resolve_multiple_parts(bundle2, Bundle2Resolver::maybe_resolve_pushkey)
                     .map(move |(pushkeys, bundle2)| {
-                        let bookmark_push: Vec<_> = pushkeys
-                            .into_iter()
-                            .filter_map(|pushkey| match pushkey {
-                                Pushkey::Phases => None,
-                                Pushkey::BookmarkPush(bp) => Some(bp),
-                            })
-                            .collect();
+                        let mut bookmark_push = vec![];
+                        let mut phase_push = vec![];
+                        for pushkey in pushkeys {
+                            match pushkey {
+                                Pushkey::BookmarkPush(bp) => bookmark_push.push(bp),
+                                Pushkey::PhasePush(pp) => phase_push.push(pp),
+                            }
+                        }
 
                         STATS::bookmark_pushkeys_count.add_value(bookmark_push.len() as i64);
+                        STATS::phase_pushkeys_count.add_value(phase_push.len() as i64);
 
-                        (cg_push, bookmark_push, bundle2)
+                        (cg_push, bookmark_push, phase_push, bundle2)
                     })
             }
         })
         .and_then({
             let resolver = resolver.clone();
-            move |(cg_push, bookmark_push, bundle2)| {
+            move |(cg_push, bookmark_push, phase_push, bundle2)| {
                 if let Some(cg_push) = cg_push {
                     resolver
                         .resolve_b2xtreegroup2(bundle2)
                         .map(|(manifests, bundle2)| {
-                            (Some((cg_push, manifests)), bookmark_push, bundle2)
+                            (Some((cg_push, manifests)), bookmark_push, phase_push, bundle2)
                         })
                         .boxify()
                 } else {
-                    ok((None, bookmark_push, bundle2)).boxify()
+                    ok((None, bookmark_push, phase_push, bundle2)).boxify()
                 }
             }
         })
         .and_then({
             let resolver = resolver.clone();
-            move |(cg_and_manifests, bookmark_push, bundle2)| {
+            move |(cg_and_manifests, bookmark_push, phase_push, bundle2)| {
                 if let Some((cg_push, manifests)) = cg_and_manifests {
-                    let changegroup_id = Some(cg_push.part_id);
+                    let uploaded = UploadedChangegroup {
+                        part_id: cg_push.part_id,
+                        changeset_ids: cg_push
+                            .changesets
+                            .iter()
+                            .map(|(node, _)| HgChangesetId::new(*node))
+                            .collect(),
+                        pushrebase_onto: cg_push.pushrebase_onto.clone(),
+                        infinitepush_bookmark: cg_push.infinitepush_bookmark.clone(),
+                    };
                     resolver
                         .upload_changesets(cg_push, manifests)
-                        .map(move |()| (changegroup_id, bookmark_push, bundle2))
+                        .map(move |()| (Some(uploaded), bookmark_push, phase_push, bundle2))
                         .boxify()
                 } else {
-                    ok((None, bookmark_push, bundle2)).boxify()
+                    ok((None, bookmark_push, phase_push, bundle2)).boxify()
                 }
             }
         })
         .and_then({
             let resolver = resolver.clone();
-            move |(changegroup_id, bookmark_push, bundle2)| {
+            move |(uploaded, bookmark_push, phase_push, bundle2)| {
                 resolver
                     .maybe_resolve_infinitepush_bookmarks(bundle2)
-                    .map(move |((), bundle2)| (changegroup_id, bookmark_push, bundle2))
+                    .and_then({
+                        let resolver = resolver.clone();
+                        move |(scratch_bookmarks, bundle2)| {
+                            resolver
+                                .update_scratch_bookmarks(uploaded.as_ref(), scratch_bookmarks)
+                                .map(move |()| (uploaded, bookmark_push, phase_push, bundle2))
+                        }
+                    })
             }
         })
         .and_then({
             let resolver = resolver.clone();
-            move |(changegroup_id, bookmark_push, bundle2)| {
+            move |(uploaded, bookmark_push, phase_push, bundle2)| {
                 resolver
                     .ensure_stream_finished(bundle2)
-                    .map(move |()| (changegroup_id, bookmark_push))
+                    .map(move |()| (uploaded, bookmark_push, phase_push))
             }
         })
         .and_then({
             let resolver = resolver.clone();
-            move |(changegroup_id, bookmark_push)| {
-                (move || {
-                    let bookmark_ids: Vec<_> = bookmark_push.iter().map(|bp| bp.part_id).collect();
+            move |(uploaded, bookmark_push, phase_push)| {
+                let mut bookmarks: Vec<_> = bookmark_push
+                    .iter()
+                    .filter(|bp| bp.new.is_some())
+                    .map(|bp| bp.name.clone())
+                    .collect();
+                let changeset_ids = match uploaded {
+                    Some(ref uploaded) => {
+                        bookmarks.extend(uploaded.pushrebase_onto.clone());
+                        uploaded.changeset_ids.clone()
+                    }
+                    None => vec![],
+                };
+                resolver
+                    .run_hooks(changeset_ids, bookmarks)
+                    .map(move |hook_failure| (uploaded, bookmark_push, phase_push, hook_failure))
+            }
+        })
+        .and_then({
+            let resolver = resolver.clone();
+            move |(uploaded, bookmark_push, phase_push, hook_failure)| {
+                if let Some(hook_failure) = hook_failure {
+                    return ok(Err(hook_failure)).boxify();
+                }
+
+                // Check the policies before pushrebasing, so that rejected pushes don't create
+                // any rebased changesets
+                let mut policy_checks: Vec<_> = bookmark_push
+                    .iter()
+                    .map(|bp| resolver.check_bookmark_move(&bp.name, bp.old, bp.new))
+                    .collect();
+                if let Some(onto) = uploaded.as_ref().and_then(|u| u.pushrebase_onto.as_ref()) {
+                    policy_checks.push(resolver.check_pushrebase(onto).into_future().boxify());
+                }
 
-                    let mut txn = resolver.repo.update_bookmark_transaction();
+                future::join_all(policy_checks)
+                    .context("While checking bookmark policies")
+                    .from_err()
+                    .and_then({
+                        let resolver = resolver.clone();
+                        move |_| {
+                            resolver.maybe_pushrebase(uploaded.as_ref()).map(move |pushrebased| {
+                                Ok((uploaded, bookmark_push, phase_push, pushrebased))
+                            })
+                        }
+                    })
+                    .boxify()
+            }
+        })
+        .and_then({
+            let resolver = resolver.clone();
+            move |result| {
+                let (uploaded, bookmark_push, phase_push, pushrebased) = match result {
+                    Ok(result) => result,
+                    Err(hook_failure) => return ok(Err(hook_failure)).boxify(),
+                };
+
+                (move || {
+                    let changegroup_id = uploaded.map(|uploaded| uploaded.part_id);
+                    let pushkey_ids: Vec<_> = bookmark_push
+                        .iter()
+                        .map(|bp| bp.part_id)
+                        .chain(phase_push.iter().map(|pp| pp.part_id))
+                        .collect();
+
+                    let reason = if pushrebased.is_some() {
+                        BookmarkUpdateReason::Pushrebase
+                    } else {
+                        BookmarkUpdateReason::Push
+                    };
+                    let mut txn =
+                        resolver.repo.update_bookmark_transaction(reason, &resolver.session);
                     for bp in bookmark_push {
                         try_boxfuture!(add_bookmark_to_transaction(&mut txn, bp));
                     }
+                    let rebased_changesets = match pushrebased {
+                        Some((onto, success)) => {
+                            try_boxfuture!(txn.update(&onto, &success.new_head, &success.old_head));
+                            success.rebased_changesets
+                        }
+                        None => vec![],
+                    };
                     txn.commit()
-                        .map(move |()| (changegroup_id, bookmark_ids))
+                        .and_then(move |()| resolver.update_phases(phase_push))
+                        .map(move |()| Ok((changegroup_id, pushkey_ids, rebased_changesets)))
                         .boxify()
                 })()
                     .context("While updating Bookmarks")
                     .from_err()
+                    .boxify()
             }
         })
-        .and_then(move |(changegroup_id, bookmark_ids)| {
-            resolver.prepare_response(changegroup_id, bookmark_ids)
+        .and_then(move |result| match result {
+            Ok((changegroup_id, pushkey_ids, rebased_changesets)) => {
+                resolver.prepare_response(changegroup_id, pushkey_ids, rebased_changesets)
+            }
+            Err(hook_failure) => resolver.prepare_hook_failure_response(hook_failure),
         })
         .context("bundle2-resolver error")
         .from_err()
@@ -159,11 +283,23 @@ struct ChangegroupPush {
     changesets: Changesets,
     filelogs: Filelogs,
     content_blobs: ContentBlobs,
+    /// Set if the changesets should be pushrebased onto this bookmark
+    pushrebase_onto: Option<bookmarks::Bookmark>,
+    /// Set if this is an infinitepush that moves this scratch bookmark to the pushed head
+    infinitepush_bookmark: Option<bookmarks::Bookmark>,
+}
+
+/// What is left of a ChangegroupPush once its changesets are uploaded
+struct UploadedChangegroup {
+    part_id: PartId,
+    changeset_ids: Vec<HgChangesetId>,
+    pushrebase_onto: Option<bookmarks::Bookmark>,
+    infinitepush_bookmark: Option<bookmarks::Bookmark>,
 }
 
 enum Pushkey {
     BookmarkPush(BookmarkPush),
-    Phases,
+    PhasePush(PhasePush),
 }
 
 struct BookmarkPush {
@@ -173,23 +309,71 @@ struct BookmarkPush {
     new: Option<HgChangesetId>,
 }
 
+struct PhasePush {
+    part_id: PartId,
+    changeset_id: HgChangesetId,
+    phase: Phase,
+}
+
+/// A hook that rejected one of the pushed changesets
+struct HookFailure {
+    hook_name: String,
+    bookmark: bookmarks::Bookmark,
+    changeset_id: HgChangesetId,
+    info: HookRejectionInfo,
+}
+
 /// Holds repo and logger for convienience access from it's methods
 #[derive(Clone)]
 struct Bundle2Resolver {
     repo: Arc<BlobRepo>,
     logger: Logger,
     scuba_logger: ScubaSampleBuilder,
+    hook_manager: Arc<HookManager>,
+    phases: RepoPhases,
+    bookmark_policies: BookmarkPolicies,
+    session: String,
+    user: Option<String>,
 }
 
 impl Bundle2Resolver {
-    fn new(repo: Arc<BlobRepo>, logger: Logger, scuba_logger: ScubaSampleBuilder) -> Self {
+    fn new(
+        repo: Arc<BlobRepo>,
+        logger: Logger,
+        scuba_logger: ScubaSampleBuilder,
+        hook_manager: Arc<HookManager>,
+        phases: RepoPhases,
+        bookmark_policies: BookmarkPolicies,
+        session: String,
+        user: Option<String>,
+    ) -> Self {
         Self {
             repo,
             logger,
             scuba_logger,
+            hook_manager,
+            phases,
+            bookmark_policies,
+            session,
+            user,
         }
     }
 
+    fn check_bookmark_move(
+        &self,
+        bookmark: &bookmarks::Bookmark,
+        old: Option<HgChangesetId>,
+        new: Option<HgChangesetId>,
+    ) -> BoxFuture<(), Error> {
+        let user = self.user.as_ref().map(String::as_str);
+        self.bookmark_policies.check_move(bookmark, user, old, new)
+    }
+
+    fn check_pushrebase(&self, onto: &bookmarks::Bookmark) -> Result<()> {
+        let user = self.user.as_ref().map(String::as_str);
+        self.bookmark_policies.check_pushrebase(onto, user)
+    }
+
     /// Parse Start and Replycaps and ignore their content
     fn resolve_start_and_replycaps(
         &self,
@@ -209,6 +393,8 @@ impl Bundle2Resolver {
     }
 
     /// Parse changegroup.
+    /// A b2x:rebase part is parsed as a changegroup as well, with the changesets marked to be
+    /// pushrebased onto the bookmark from its `onto` parameter.
     /// The ChangegroupId will be used in the last step for preparing response
     /// The Changesets should be parsed as RevlogChangesets and used for uploading changesets
     /// The Filelogs should be scheduled for uploading to BlobRepo and the Future resolving in
@@ -220,43 +406,67 @@ impl Bundle2Resolver {
         let repo = self.repo.clone();
 
         next_item(bundle2)
-            .and_then(move |(changegroup, bundle2)| match changegroup {
-                Some(Bundle2Item::Changegroup(header, parts))
-                | Some(Bundle2Item::B2xInfinitepush(header, parts)) => {
-                    let part_id = header.part_id();
-                    let (c, f) = split_changegroup(parts);
-                    convert_to_revlog_changesets(c)
-                        .collect()
-                        .and_then(|changesets| {
-                            upload_hg_blobs(
-                                repo.clone(),
-                                convert_to_revlog_filelog(repo, f),
-                                UploadBlobsType::EnsureNoDuplicates,
-                            ).map(move |upload_map| {
-                                let mut filelogs = HashMap::new();
-                                let mut content_blobs = HashMap::new();
-                                for (node_key, (cbinfo, file_upload)) in upload_map {
-                                    filelogs.insert(node_key.clone(), file_upload);
-                                    content_blobs.insert(node_key, cbinfo);
-                                }
-                                (changesets, filelogs, content_blobs)
-                            })
-                                .context("While uploading File Blobs")
-                                .from_err()
-                        })
-                        .map(move |(changesets, filelogs, content_blobs)| {
-                            let cg_push = ChangegroupPush {
-                                part_id,
-                                changesets,
-                                filelogs,
-                                content_blobs,
-                            };
-                            (Some(cg_push), bundle2)
+            .and_then(move |(changegroup, bundle2)| {
+                let (header, parts, pushrebase_onto, infinitepush_bookmark) = match changegroup {
+                    Some(Bundle2Item::Changegroup(header, parts)) => (header, parts, None, None),
+                    Some(Bundle2Item::B2xInfinitepush(header, parts)) => {
+                        // Infinitepush sends the scratch bookmark as an advisory param
+                        let bookmark = match header.aparams().get("bookmark") {
+                            Some(_) => {
+                                try_boxfuture!(get_ascii_param(header.aparams(), "bookmark"))
+                            }
+                            None => AsciiString::new(),
+                        };
+                        let bookmark = if bookmark.is_empty() {
+                            None
+                        } else {
+                            Some(bookmarks::Bookmark::new_ascii(bookmark))
+                        };
+                        (header, parts, None, bookmark)
+                    }
+                    Some(Bundle2Item::B2xRebase(header, parts)) => {
+                        let onto = try_boxfuture!(get_ascii_param(header.mparams(), "onto"));
+                        (header, parts, Some(bookmarks::Bookmark::new_ascii(onto)), None)
+                    }
+                    Some(part) => {
+                        return ok((None, stream::once(Ok(part)).chain(bundle2).boxify())).boxify()
+                    }
+                    _ => return err(format_err!("Unexpected Bundle2 stream end")).boxify(),
+                };
+
+                let part_id = header.part_id();
+                let (c, f) = split_changegroup(parts);
+                convert_to_revlog_changesets(c)
+                    .collect()
+                    .and_then(|changesets| {
+                        upload_hg_blobs(
+                            repo.clone(),
+                            convert_to_revlog_filelog(repo, f),
+                            UploadBlobsType::EnsureNoDuplicates,
+                        ).map(move |upload_map| {
+                            let mut filelogs = HashMap::new();
+                            let mut content_blobs = HashMap::new();
+                            for (node_key, (cbinfo, file_upload)) in upload_map {
+                                filelogs.insert(node_key.clone(), file_upload);
+                                content_blobs.insert(node_key, cbinfo);
+                            }
+                            (changesets, filelogs, content_blobs)
                         })
-                        .boxify()
-                }
-                Some(part) => ok((None, stream::once(Ok(part)).chain(bundle2).boxify())).boxify(),
-                _ => err(format_err!("Unexpected Bundle2 stream end")).boxify(),
+                            .context("While uploading File Blobs")
+                            .from_err()
+                    })
+                    .map(move |(changesets, filelogs, content_blobs)| {
+                        let cg_push = ChangegroupPush {
+                            part_id,
+                            changesets,
+                            filelogs,
+                            content_blobs,
+                            pushrebase_onto,
+                            infinitepush_bookmark,
+                        };
+                        (Some(cg_push), bundle2)
+                    })
+                    .boxify()
             })
             .context("While resolving Changegroup")
             .from_err()
@@ -280,7 +490,19 @@ impl Bundle2Resolver {
                     );
 
                     let pushkey = match &namespace[..] {
-                        b"phases" => Pushkey::Phases,
+                        b"phases" => {
+                            let part_id = header.part_id();
+                            let mparams = header.mparams();
+                            let key = try_boxfuture!(get_ascii_param(mparams, "key"));
+                            let changeset_id = try_boxfuture!(HgChangesetId::from_ascii_str(&key));
+                            let phase = try_boxfuture!(get_phase_param(mparams, "new"));
+
+                            Pushkey::PhasePush(PhasePush {
+                                part_id,
+                                changeset_id,
+                                phase,
+                            })
+                        }
                         b"bookmarks" => {
                             let part_id = header.part_id();
                             let mparams = header.mparams();
@@ -314,7 +536,7 @@ impl Bundle2Resolver {
             .boxify()
     }
 
-    /// Parse b2xtreegroup2.
+    /// Parse b2xtreegroup2 (or b2x:rebasepackpart, which has the same content).
     /// The Manifests should be scheduled for uploading to BlobRepo and the Future resolving in
     /// their upload as well as their parsed content should be used for uploading changesets.
     fn resolve_b2xtreegroup2(
@@ -325,7 +547,8 @@ impl Bundle2Resolver {
 
         next_item(bundle2)
             .and_then(move |(b2xtreegroup2, bundle2)| match b2xtreegroup2 {
-                Some(Bundle2Item::B2xTreegroup2(_, parts)) => {
+                Some(Bundle2Item::B2xTreegroup2(_, parts))
+                | Some(Bundle2Item::B2xRebasePack(_, parts)) => {
                     upload_hg_blobs(
                         repo,
                         TreemanifestBundle2Parser::new(parts),
@@ -343,18 +566,23 @@ impl Bundle2Resolver {
     }
 
     /// Parse b2xinfinitepushscratchbookmarks.
-    /// This part is ignored, so just parse it and forget it
+    /// Returns the scratch bookmarks that the part asks to move
     fn maybe_resolve_infinitepush_bookmarks(
         &self,
         bundle2: BoxStream<Bundle2Item, Error>,
-    ) -> BoxFuture<((), BoxStream<Bundle2Item, Error>), Error> {
+    ) -> BoxFuture<(ScratchBookmarks, BoxStream<Bundle2Item, Error>), Error> {
         next_item(bundle2)
             .and_then(
                 move |(infinitepushbookmarks, bundle2)| match infinitepushbookmarks {
-                    Some(Bundle2Item::B2xInfinitepushBookmarks(_, bookmarks)) => {
-                        bookmarks.collect().map(|_| ((), bundle2)).boxify()
-                    }
-                    None => Ok(((), bundle2)).into_future().boxify(),
+                    Some(Bundle2Item::B2xInfinitepushBookmarks(_, bookmarks)) => bookmarks
+                        .fold(BytesMut::new(), |mut payload, chunk| {
+                            payload.extend_from_slice(&chunk);
+                            Ok::<_, Error>(payload)
+                        })
+                        .and_then(|payload| decode_scratch_bookmarks(&payload))
+                        .map(move |scratch_bookmarks| (scratch_bookmarks, bundle2))
+                        .boxify(),
+                    None => Ok((HashMap::new(), bundle2)).into_future().boxify(),
                     _ => err(format_err!(
                         "Expected B2xInfinitepushBookmarks or end of the stream"
                     )).boxify(),
@@ -365,6 +593,41 @@ impl Bundle2Resolver {
             .boxify()
     }
 
+    /// Moves the scratch bookmarks of an infinitepush push: the one from the `bookmark` param
+    /// of b2x:infinitepush, which points to the pushed head, and the ones from
+    /// b2x:infinitepushscratchbookmarks. Scratch bookmarks are not checked by hooks.
+    fn update_scratch_bookmarks(
+        &self,
+        uploaded: Option<&UploadedChangegroup>,
+        mut scratch_bookmarks: ScratchBookmarks,
+    ) -> BoxFuture<(), Error> {
+        if let Some(uploaded) = uploaded {
+            if let (Some(name), Some(head)) = (
+                uploaded.infinitepush_bookmark.clone(),
+                uploaded.changeset_ids.last(),
+            ) {
+                scratch_bookmarks.insert(name, Some(*head));
+            }
+        }
+
+        if scratch_bookmarks.is_empty() {
+            return ok(()).boxify();
+        }
+
+        let mut txn = self.repo
+            .update_scratch_bookmark_transaction(BookmarkUpdateReason::Push, &self.session);
+        for (name, changeset_id) in scratch_bookmarks {
+            try_boxfuture!(match changeset_id {
+                Some(changeset_id) => txn.force_set(&name, &changeset_id),
+                None => txn.force_delete(&name),
+            });
+        }
+        txn.commit()
+            .context("While updating scratch bookmarks")
+            .from_err()
+            .boxify()
+    }
+
     /// Takes parsed Changesets and scheduled for upload Filelogs and Manifests. The content of
     /// Manifests is used to figure out DAG of dependencies between a given Changeset and the
     /// Manifests and Filelogs it adds.
@@ -435,6 +698,7 @@ impl Bundle2Resolver {
                         time: revlog_cs.time().clone(),
                         extra: revlog_cs.extra().clone(),
                         comments: String::from_utf8(revlog_cs.comments().into())?,
+                        bonsai: None,
                     };
                     let scheduled_uploading = create_changeset.create(&repo, scuba_logger);
 
@@ -496,34 +760,195 @@ impl Bundle2Resolver {
             .boxify()
     }
 
+    /// Runs the hooks configured for every bookmark that is being created or moved on all of
+    /// the pushed changesets. Resolves to the first rejection, if any.
+    fn run_hooks(
+        &self,
+        changeset_ids: Vec<HgChangesetId>,
+        bookmarks: Vec<bookmarks::Bookmark>,
+    ) -> BoxFuture<Option<HookFailure>, Error> {
+        let mut runs = Vec::new();
+        for bookmark in bookmarks {
+            for changeset_id in &changeset_ids {
+                let changeset_id = *changeset_id;
+                let bookmark = bookmark.clone();
+                runs.push(
+                    self.hook_manager
+                        .run_hooks_for_bookmark(changeset_id, &bookmark)
+                        .map(move |executions| (bookmark, changeset_id, executions)),
+                );
+            }
+        }
+
+        let logger = self.logger.clone();
+        future::join_all(runs)
+            .map(move |runs| {
+                let hook_failure = runs.into_iter()
+                    .flat_map(|(bookmark, changeset_id, executions)| {
+                        executions
+                            .into_iter()
+                            .map(move |(hook_name, execution)| {
+                                (bookmark.clone(), changeset_id, hook_name, execution)
+                            })
+                    })
+                    .filter_map(
+                        |(bookmark, changeset_id, hook_name, execution)| match execution {
+                            HookExecution::Accepted => None,
+                            HookExecution::Rejected(info) => Some(HookFailure {
+                                hook_name,
+                                bookmark,
+                                changeset_id,
+                                info,
+                            }),
+                        },
+                    )
+                    .next();
+
+                if let Some(ref hook_failure) = hook_failure {
+                    STATS::hook_rejections.add_value(1);
+                    info!(
+                        logger,
+                        "hook {} rejected changeset {} for bookmark {}: {}",
+                        hook_failure.hook_name,
+                        hook_failure.changeset_id,
+                        hook_failure.bookmark,
+                        hook_failure.info.description
+                    );
+                }
+                hook_failure
+            })
+            .context("While running hooks")
+            .from_err()
+            .boxify()
+    }
+
+    /// If the changegroup was sent as b2x:rebase, rebases the uploaded changesets onto the
+    /// bookmark. Resolves to the bookmark and the result of the pushrebase, the bookmark still
+    /// has to be moved by the caller.
+    fn maybe_pushrebase(
+        &self,
+        uploaded: Option<&UploadedChangegroup>,
+    ) -> BoxFuture<Option<(bookmarks::Bookmark, PushrebaseSuccess)>, Error> {
+        let (onto, changeset_ids) = match uploaded {
+            Some(&UploadedChangegroup {
+                pushrebase_onto: Some(ref onto),
+                ref changeset_ids,
+                ..
+            }) => (onto.clone(), changeset_ids.clone()),
+            _ => return ok(None).boxify(),
+        };
+
+        let logger = self.logger.clone();
+        do_pushrebase(
+            self.repo.clone(),
+            self.scuba_logger.clone(),
+            onto.clone(),
+            changeset_ids,
+        ).map(move |success| {
+            STATS::pushrebase_changesets_count.add_value(success.rebased_changesets.len() as i64);
+            info!(
+                logger,
+                "pushrebased {} changesets onto {}, moving it from {} to {}",
+                success.rebased_changesets.len(),
+                onto,
+                success.old_head,
+                success.new_head
+            );
+            Some((onto, success))
+        })
+            .boxify()
+    }
+
+    /// Marks the changesets that the client pushed as public. Moving changesets back to draft
+    /// is not supported, just like in Mercurial, so such pushes are ignored.
+    fn update_phases(&self, phase_push: Vec<PhasePush>) -> BoxFuture<(), Error> {
+        let updates = phase_push
+            .into_iter()
+            .filter(|pp| pp.phase == Phase::Public)
+            .map({
+                let phases = self.phases.clone();
+                move |pp| phases.mark_public(pp.changeset_id)
+            });
+
+        future::join_all(updates)
+            .map(|_| ())
+            .context("While updating Phases")
+            .from_err()
+            .boxify()
+    }
+
     /// Takes a changegroup id and prepares a Bytes response containing Bundle2 with reply to
-    /// changegroup part saying that the push was successful
+    /// changegroup part saying that the push was successful. If the changesets were
+    /// pushrebased the response also contains a changegroup with the rebased changesets, which
+    /// tells the client their new hashes.
     fn prepare_response(
         &self,
         changegroup_id: Option<PartId>,
-        bookmark_ids: Vec<PartId>,
+        pushkey_ids: Vec<PartId>,
+        rebased_changesets: Vec<(HgChangesetId, HgChangesetId)>,
     ) -> BoxFuture<Bytes, Error> {
-        let writer = Cursor::new(Vec::new());
-        let mut bundle = Bundle2EncodeBuilder::new(writer);
-        // Mercurial currently hangs while trying to read compressed bundles over the wire:
-        // https://bz.mercurial-scm.org/show_bug.cgi?id=5646
-        // TODO: possibly enable compression support once this is fixed.
-        bundle.set_compressor_type(None);
+        let mut bundle = new_response_bundle();
         if let Some(changegroup_id) = changegroup_id {
             bundle.add_part(try_boxfuture!(parts::replychangegroup_part(
                 parts::ChangegroupApplyResult::Success { heads_num_diff: 0 },
                 changegroup_id,
             )));
         }
-        for part_id in bookmark_ids {
+        for part_id in pushkey_ids {
             bundle.add_part(try_boxfuture!(parts::replypushkey_part(true, part_id)));
         }
-        bundle
-            .build()
-            .map(|cursor| Bytes::from(cursor.into_inner()))
-            .context("While preparing response")
-            .from_err()
-            .boxify()
+        if !rebased_changesets.is_empty() {
+            let repo = self.repo.clone();
+            let changelogentries = stream::iter_ok(rebased_changesets)
+                .and_then(move |(_, rebased)| {
+                    repo.get_changeset_by_changesetid(&rebased)
+                        .map(move |cs| (rebased.into_nodehash(), cs))
+                })
+                .and_then(|(node, cs)| {
+                    let revlogcs = RevlogChangeset::new_from_parts(
+                        cs.parents().clone(),
+                        cs.manifestid().clone(),
+                        cs.user().into(),
+                        cs.time().clone(),
+                        cs.extra().clone(),
+                        cs.files().into(),
+                        cs.comments().into(),
+                    );
+
+                    let mut v = Vec::new();
+                    serialize_cs(&revlogcs, &mut v)?;
+                    Ok((
+                        node,
+                        HgBlobNode::new(Bytes::from(v), revlogcs.p1(), revlogcs.p2()),
+                    ))
+                });
+            bundle.add_part(try_boxfuture!(parts::changegroup_part(changelogentries)));
+        }
+        encode_response(bundle)
+    }
+
+    /// Prepares a Bytes response containing Bundle2 with an error part that makes the client
+    /// abort the push, explaining which hook rejected it
+    fn prepare_hook_failure_response(&self, hook_failure: HookFailure) -> BoxFuture<Bytes, Error> {
+        let HookFailure {
+            hook_name,
+            bookmark,
+            changeset_id,
+            info,
+        } = hook_failure;
+        let message = format!(
+            "hook {} rejected changeset {} for bookmark {}: {}",
+            hook_name, changeset_id, bookmark, info.description
+        );
+        let hint = if info.long_description.is_empty() {
+            None
+        } else {
+            Some(info.long_description)
+        };
+
+        let mut bundle = new_response_bundle();
+        bundle.add_part(try_boxfuture!(parts::error_abort_part(message, hint)));
+        encode_response(bundle)
     }
 
     /// A method that can use any of the above maybe_resolve_* methods to return
@@ -555,6 +980,25 @@ impl Bundle2Resolver {
     }
 }
 
+fn new_response_bundle() -> Bundle2EncodeBuilder<Cursor<Vec<u8>>> {
+    let writer = Cursor::new(Vec::new());
+    let mut bundle = Bundle2EncodeBuilder::new(writer);
+    // Mercurial currently hangs while trying to read compressed bundles over the wire:
+    // https://bz.mercurial-scm.org/show_bug.cgi?id=5646
+    // TODO: possibly enable compression support once this is fixed.
+    bundle.set_compressor_type(None);
+    bundle
+}
+
+fn encode_response(bundle: Bundle2EncodeBuilder<Cursor<Vec<u8>>>) -> BoxFuture<Bytes, Error> {
+    bundle
+        .build()
+        .map(|cursor| Bytes::from(cursor.into_inner()))
+        .context("While preparing response")
+        .from_err()
+        .boxify()
+}
+
 fn add_bookmark_to_transaction(
     txn: &mut Box<bookmarks::Transaction>,
     bookmark_push: BookmarkPush,
@@ -795,6 +1239,24 @@ fn is_entry_present_in_parent(
     }
 }
 
+/// Decodes the payload of b2x:infinitepushscratchbookmarks: a JSON object that maps bookmark
+/// names to hex changeset hashes, where an empty hash means that the bookmark is deleted
+fn decode_scratch_bookmarks(payload: &[u8]) -> Result<ScratchBookmarks> {
+    let decoded: HashMap<String, String> = serde_json::from_slice(payload)?;
+    decoded
+        .into_iter()
+        .map(|(name, node)| -> Result<_> {
+            let name = bookmarks::Bookmark::new(name)?;
+            let changeset_id = if node.is_empty() {
+                None
+            } else {
+                Some(HgChangesetId::from_str(&node)?)
+            };
+            Ok((name, changeset_id))
+        })
+        .collect()
+}
+
 fn get_ascii_param(params: &HashMap<String, Bytes>, param: &str) -> Result<AsciiString> {
     let val = params
         .get(param)
@@ -803,6 +1265,13 @@ fn get_ascii_param(params: &HashMap<String, Bytes>, param: &str) -> Result<Ascii
         .map_err(|err| format_err!("`{}` parameter is not ascii: {}", param, err))
 }
 
+fn get_phase_param(params: &HashMap<String, Bytes>, param: &str) -> Result<Phase> {
+    let val = get_ascii_param(params, param)?;
+    let number = i32::from_str(val.as_str())
+        .map_err(|err| format_err!("`{}` parameter is not a phase number: {}", param, err))?;
+    Phase::from_hg_number(number)
+}
+
 fn get_optional_changeset_param(
     params: &HashMap<String, Bytes>,
     param: &str,
@@ -44,7 +44,7 @@ use failure::ResultExt;
 use db::{get_connection_params, ConnectionParams, InstanceRequirement, ProxyRequirement};
 use futures::Future;
 use futures_ext::{asynchronize, BoxFuture, FutureExt};
-use mercurial_types::{HgChangesetId, RepositoryId};
+use mercurial_types::{HgChangesetId, HgChangesetIdPrefix, RepositoryId};
 use mercurial_types::sql_types::HgChangesetIdSql;
 use stats::Timeseries;
 
@@ -61,6 +61,7 @@ define_stats! {
     prefix = "mononoke.changesets";
     gets: timeseries(RATE, SUM),
     gets_master: timeseries(RATE, SUM),
+    gets_many_by_prefix: timeseries(RATE, SUM),
     adds: timeseries(RATE, SUM),
 }
 
@@ -91,6 +92,15 @@ pub trait Changesets: Send + Sync {
         repo_id: RepositoryId,
         cs_id: HgChangesetId,
     ) -> BoxFuture<Option<ChangesetEntry>, Error>;
+
+    /// Retrieve the ids of at most `limit` changesets whose hashes start with `cs_prefix`,
+    /// in ascending order.
+    fn get_many_by_prefix(
+        &self,
+        repo_id: RepositoryId,
+        cs_prefix: HgChangesetIdPrefix,
+        limit: usize,
+    ) -> BoxFuture<Vec<HgChangesetId>, Error>;
 }
 
 pub struct CachingChangests {
@@ -128,6 +138,15 @@ impl Changesets for CachingChangests {
             })
             .boxify()
     }
+
+    fn get_many_by_prefix(
+        &self,
+        repo_id: RepositoryId,
+        cs_prefix: HgChangesetIdPrefix,
+        limit: usize,
+    ) -> BoxFuture<Vec<HgChangesetId>, Error> {
+        self.changesets.get_many_by_prefix(repo_id, cs_prefix, limit)
+    }
 }
 
 pub struct ChangesetsFiller {
@@ -318,6 +337,31 @@ macro_rules! impl_changesets {
                 })
             }
 
+            /// Retrieve the changesets whose ids start with this prefix. Only the replica is
+            /// queried, so very recent changesets might not be found yet.
+            fn get_many_by_prefix(
+                &self,
+                repo_id: RepositoryId,
+                cs_prefix: HgChangesetIdPrefix,
+                limit: usize,
+            ) -> BoxFuture<Vec<HgChangesetId>, Error> {
+                STATS::gets_many_by_prefix.add_value(1);
+                let db = self.clone();
+
+                asynchronize(move || {
+                    let connection = db.get_conn()?;
+                    changesets::table
+                        .filter(changesets::repo_id.eq(repo_id))
+                        .filter(changesets::cs_id.ge(cs_prefix.min_cs()))
+                        .filter(changesets::cs_id.le(cs_prefix.max_cs()))
+                        .order(changesets::cs_id.asc())
+                        .limit(limit as i64)
+                        .select(changesets::cs_id)
+                        .load::<HgChangesetId>(&*connection)
+                        .map_err(failure::Error::from)
+                })
+            }
+
             /// Insert a new changeset into this table. Checks that all parents are already in
             /// storage.
             fn add(&self, cs: ChangesetInsert) -> BoxFuture<bool, Error> {
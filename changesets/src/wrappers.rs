@@ -9,7 +9,7 @@
 use std::sync::Arc;
 
 use futures_ext::BoxFuture;
-use mercurial_types::{HgChangesetId, RepositoryId};
+use mercurial_types::{HgChangesetId, HgChangesetIdPrefix, RepositoryId};
 
 use {ChangesetEntry, ChangesetInsert, Changesets};
 use errors::*;
@@ -26,4 +26,13 @@ impl Changesets for Arc<Changesets> {
     ) -> BoxFuture<Option<ChangesetEntry>, Error> {
         (**self).get(repo_id, cs_id)
     }
+
+    fn get_many_by_prefix(
+        &self,
+        repo_id: RepositoryId,
+        cs_prefix: HgChangesetIdPrefix,
+        limit: usize,
+    ) -> BoxFuture<Vec<HgChangesetId>, Error> {
+        (**self).get_many_by_prefix(repo_id, cs_prefix, limit)
+    }
 }
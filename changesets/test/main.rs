@@ -16,14 +16,17 @@ extern crate failure_ext as failure;
 extern crate futures;
 
 extern crate changesets;
+extern crate mercurial_types;
 extern crate mercurial_types_mocks;
 
+use std::str::FromStr;
 use std::sync::Arc;
 
 use futures::Future;
 
 use changesets::{ChangesetEntry, ChangesetInsert, Changesets, ErrorKind, MysqlChangesets,
                  SqliteChangesets};
+use mercurial_types::{HgChangesetId, HgChangesetIdPrefix};
 use mercurial_types_mocks::nodehash::*;
 use mercurial_types_mocks::repo::*;
 
@@ -239,6 +242,45 @@ fn complex<C: Changesets>(changesets: C) {
     );
 }
 
+fn get_many_by_prefix<C: Changesets>(changesets: C) {
+    let cs_ids: Vec<_> = vec![
+        "1111111111111111111111111111111111111111",
+        "1112222222222222222222222222222222222222",
+        "1113333333333333333333333333333333333333",
+        "2222222222222222222222222222222222222222",
+    ].into_iter()
+        .map(|hash| HgChangesetId::from_str(hash).unwrap())
+        .collect();
+    for cs_id in &cs_ids {
+        let row = ChangesetInsert {
+            repo_id: REPO_ZERO,
+            cs_id: *cs_id,
+            parents: vec![],
+        };
+        changesets.add(row).wait().expect("Adding row failed");
+    }
+
+    let get = |prefix: &str, limit: usize| {
+        changesets
+            .get_many_by_prefix(REPO_ZERO, HgChangesetIdPrefix::from_str(prefix).unwrap(), limit)
+            .wait()
+            .expect("Get by prefix failed")
+    };
+
+    assert_eq!(get("111", 10), cs_ids[0..3].to_vec());
+    assert_eq!(get("111", 2), cs_ids[0..2].to_vec());
+    assert_eq!(get("1112", 10), vec![cs_ids[1]]);
+    assert_eq!(get("2", 10), vec![cs_ids[3]]);
+    assert!(get("3", 10).is_empty());
+
+    // Changesets from other repos are not returned
+    let result = changesets
+        .get_many_by_prefix(REPO_ONE, HgChangesetIdPrefix::from_str("111").unwrap(), 10)
+        .wait()
+        .expect("Get by prefix failed");
+    assert!(result.is_empty());
+}
+
 macro_rules! changesets_test_impl {
     ($mod_name: ident => {
         new: $new_cb: expr,
@@ -287,6 +329,13 @@ macro_rules! changesets_test_impl {
                     complex($new_cb());
                 });
             }
+
+            #[test]
+            fn test_get_many_by_prefix() {
+                async_unit::tokio_unit_test(|| {
+                    get_many_by_prefix($new_cb());
+                });
+            }
         }
     }
 }
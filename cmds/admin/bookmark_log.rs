@@ -0,0 +1,133 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Reads the update log of a bookmark, and rolls a bookmark back to the value it had after an
+//! entry of its log.
+
+use std::env;
+use std::sync::Arc;
+
+use failure::Error;
+use futures::{future, Future, Stream};
+use futures_ext::{BoxFuture, FutureExt};
+use slog::Logger;
+
+use blobrepo::BlobRepo;
+use bookmarks::{Bookmark, BookmarkUpdateLogEntry, BookmarkUpdateReason};
+use mercurial_types::HgChangesetId;
+use mononoke_types::DateTime;
+
+/// Prints the entries of the update log of a bookmark, the most recent first
+pub fn print_log(repo: Arc<BlobRepo>, name: &str) -> BoxFuture<(), Error> {
+    let name = try_boxfuture!(Bookmark::new(name));
+
+    repo.read_bookmark_log(&name)
+        .for_each(|entry| {
+            println!("{}", format_entry(&entry));
+            Ok(())
+        })
+        .boxify()
+}
+
+/// Moves a bookmark back to the value it had after the log entry `entry_id`. The move is a
+/// compare-and-swap against the current value of the bookmark, so it fails instead of clobbering
+/// a concurrent move, and it is logged as a rollback like any other move.
+pub fn rollback(
+    repo: Arc<BlobRepo>,
+    logger: Logger,
+    name: &str,
+    entry_id: u64,
+) -> BoxFuture<(), Error> {
+    let name = try_boxfuture!(Bookmark::new(name));
+
+    let entry = repo.read_bookmark_log(&name)
+        .filter(move |entry| entry.id == entry_id)
+        .into_future()
+        .map_err(|(err, _)| err)
+        .and_then({
+            let name = name.clone();
+            move |(entry, _)| {
+                entry.ok_or_else(|| format_err!("bookmark {} has no log entry {}", name, entry_id))
+            }
+        });
+
+    let target = entry.and_then({
+        let repo = repo.clone();
+        move |entry| match entry.to_changeset_id {
+            Some(target) => repo.changeset_exists(&target)
+                .and_then(move |exists| {
+                    if exists {
+                        Ok(Some(target))
+                    } else {
+                        Err(format_err!("changeset {} does not exist", target))
+                    }
+                })
+                .boxify(),
+            None => future::ok(None).boxify(),
+        }
+    });
+
+    target
+        .join(repo.get_bookmark(&name))
+        .and_then(move |(target, current)| {
+            if target == current {
+                info!(logger, "{} already points to {}", name, format_value(target));
+                return future::ok(()).boxify();
+            }
+
+            let session = env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+            let mut txn =
+                repo.update_bookmark_transaction(BookmarkUpdateReason::Rollback, &session);
+            try_boxfuture!(match (current, target) {
+                (Some(current), Some(target)) => txn.update(&name, &target, &current),
+                (None, Some(target)) => txn.create(&name, &target),
+                (Some(current), None) => txn.delete(&name, &current),
+                (None, None) => unreachable!(),
+            });
+            txn.commit()
+                .map_err({
+                    let name = name.clone();
+                    move |err| {
+                        err.context(format!("{} was moved meanwhile, or the update failed", name))
+                            .into()
+                    }
+                })
+                .map(move |()| {
+                    info!(
+                        logger,
+                        "moved {} from {} to {}",
+                        name,
+                        format_value(current),
+                        format_value(target)
+                    );
+                })
+                .boxify()
+        })
+        .boxify()
+}
+
+fn format_value(value: Option<HgChangesetId>) -> String {
+    match value {
+        Some(changeset_id) => changeset_id.to_string(),
+        None => "(none)".to_string(),
+    }
+}
+
+fn format_entry(entry: &BookmarkUpdateLogEntry) -> String {
+    let date = match DateTime::from_timestamp(entry.timestamp, 0) {
+        Ok(date) => date.to_string(),
+        Err(_) => entry.timestamp.to_string(),
+    };
+    format!(
+        "{} {} {} {} -> {} by {}",
+        entry.id,
+        date,
+        entry.reason,
+        format_value(entry.from_changeset_id),
+        format_value(entry.to_changeset_id),
+        entry.session
+    )
+}
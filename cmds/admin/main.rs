@@ -14,15 +14,32 @@ extern crate tokio_core;
 
 extern crate blobrepo;
 extern crate blobstore;
+extern crate bookmarks;
+extern crate bytes;
 #[macro_use]
 extern crate futures_ext;
 extern crate manifoldblob;
 extern crate mercurial_types;
 extern crate mononoke_types;
+extern crate repoinfo;
+extern crate revset;
+extern crate skiplist;
 #[macro_use]
 extern crate slog;
 extern crate slog_glog_fmt;
+extern crate streaming_clone;
 
+#[cfg(test)]
+extern crate async_unit;
+#[cfg(test)]
+extern crate linear;
+#[cfg(test)]
+extern crate tempdir;
+
+mod bookmark_log;
+mod scrub;
+
+use std::path::Path;
 use std::str::FromStr;
 use std::sync::Arc;
 
@@ -39,13 +56,28 @@ use futures_ext::{BoxFuture, FutureExt};
 use manifoldblob::ManifoldBlob;
 use mercurial_types::{Changeset, HgChangesetId, MPath, MPathElement, Manifest, RepositoryId};
 use mercurial_types::manifest::Content;
-use mononoke_types::{BlobstoreBytes, FileContents};
+use mononoke_types::BlobstoreBytes;
+use repoinfo::RepoGenCache;
+use revset::Revset;
+use skiplist::SkiplistIndex;
 use slog::{Drain, Level, Logger};
 use slog_glog_fmt::default_drain as glog_drain;
+use streaming_clone::StreamingClone;
+
+use scrub::{Checkpoint, Scrubber};
 
 const BLOBSTORE_FETCH: &'static str = "blobstore-fetch";
+const BONSAI_BACKFILL: &'static str = "bonsai-backfill";
+const BOOKMARKS: &'static str = "bookmarks";
+const BOOKMARKS_LOG: &'static str = "log";
+const BOOKMARKS_ROLLBACK: &'static str = "rollback";
 const CONTENT_FETCH: &'static str = "content-fetch";
+const REVSET: &'static str = "revset";
+const SCRUB: &'static str = "scrub";
+const SKIPLIST_BACKFILL: &'static str = "skiplist-backfill";
+const STREAMING_CLONE_UPDATE: &'static str = "streaming-clone-update";
 const MAX_CONCURRENT_REQUESTS_PER_IO_THREAD: usize = 4;
+const SKIPLIST_CACHE_SIZE: usize = 100 * 1024 * 1024;
 
 fn setup_app<'a, 'b>() -> App<'a, 'b> {
     let blobstore_fetch = SubCommand::with_name(BLOBSTORE_FETCH)
@@ -78,6 +110,30 @@ fn setup_app<'a, 'b>() -> App<'a, 'b> {
                 .help("Don't prepend a prefix based on the repo id to the key"),
         );
 
+    let bonsai_backfill = SubCommand::with_name(BONSAI_BACKFILL).about(
+        "derives the bonsai changesets of all the changesets that have none, which the servers \
+         don't do for changesets imported before bonsai changesets were stored",
+    );
+
+    let bookmarks = SubCommand::with_name(BOOKMARKS)
+        .about("inspects the update logs of bookmarks and undoes bookmark moves")
+        .subcommand(
+            SubCommand::with_name(BOOKMARKS_LOG)
+                .about("prints the update log of a bookmark, the most recent entry first")
+                .args_from_usage("<BOOKMARK>    'name of the bookmark'"),
+        )
+        .subcommand(
+            SubCommand::with_name(BOOKMARKS_ROLLBACK)
+                .about(
+                    "moves a bookmark back to the value it had after an entry of its log, if it \
+                     was not moved meanwhile",
+                )
+                .args_from_usage(
+                    "<BOOKMARK>    'name of the bookmark'
+                     <ENTRY_ID>    'id of the log entry to roll back to'",
+                ),
+        );
+
     let content_fetch = SubCommand::with_name(CONTENT_FETCH)
         .about("fetches content of the file or manifest from blobrepo")
         .args_from_usage(
@@ -85,6 +141,38 @@ fn setup_app<'a, 'b>() -> App<'a, 'b> {
              <PATH>            'path to fetch'",
         );
 
+    let revset = SubCommand::with_name(REVSET)
+        .about(
+            "prints the changesets of a revset, e.g. \"::master % release\", one per line in \
+             decreasing order of generation numbers",
+        )
+        .args_from_usage("<REVSET>    'revset to evaluate'");
+
+    let scrub = SubCommand::with_name(SCRUB)
+        .about(
+            "verifies that everything reachable from the bookmarks is in the blobstore and \
+             hashes correctly",
+        )
+        .arg(
+            Arg::with_name("checkpoint")
+                .long("checkpoint")
+                .takes_value(true)
+                .required(false)
+                .help("file recording verified changesets, used to resume an interrupted scrub"),
+        );
+
+    let skiplist_backfill = SubCommand::with_name(SKIPLIST_BACKFILL).about(
+        "indexes all the changesets in the skip-list index, which the servers only extend by a \
+         few changesets at a time",
+    );
+
+    let streaming_clone_update = SubCommand::with_name(STREAMING_CLONE_UPDATE)
+        .about(
+            "snapshots the changelog and manifests of a Mercurial repository for streaming \
+             clones, the repository must not be written to meanwhile",
+        )
+        .args_from_usage("<REPO_PATH>    'path to the .hg directory of the repository'");
+
     App::new("Mononoke admin command line tool")
         .version("0.0.0")
         .about("Poke at mononoke internals for debugging and investigating data structures.")
@@ -96,7 +184,13 @@ fn setup_app<'a, 'b>() -> App<'a, 'b> {
              -d, --debug                'print debug level output'",
         )
         .subcommand(blobstore_fetch)
+        .subcommand(bonsai_backfill)
+        .subcommand(bookmarks)
         .subcommand(content_fetch)
+        .subcommand(revset)
+        .subcommand(scrub)
+        .subcommand(skiplist_backfill)
+        .subcommand(streaming_clone_update)
 }
 
 struct ManifoldArgs<'a> {
@@ -293,6 +387,37 @@ fn main() {
             })
                 .boxify()
         }
+        (BONSAI_BACKFILL, Some(_)) => {
+            let repo = create_blobrepo(&logger, manifold_args);
+            repo.get_heads()
+                .for_each(move |head| repo.backfill_bonsai_changesets(HgChangesetId::new(head)))
+                .boxify()
+        }
+        (BOOKMARKS, Some(sub_m)) => {
+            let repo = Arc::new(create_blobrepo(&logger, manifold_args));
+            match sub_m.subcommand() {
+                (BOOKMARKS_LOG, Some(sub_m)) => {
+                    bookmark_log::print_log(repo, sub_m.value_of("BOOKMARK").unwrap())
+                }
+                (BOOKMARKS_ROLLBACK, Some(sub_m)) => {
+                    let entry_id = sub_m
+                        .value_of("ENTRY_ID")
+                        .unwrap()
+                        .parse::<u64>()
+                        .expect("expected the entry id to be a u64");
+                    bookmark_log::rollback(
+                        repo,
+                        logger.clone(),
+                        sub_m.value_of("BOOKMARK").unwrap(),
+                        entry_id,
+                    )
+                }
+                _ => {
+                    println!("{}", sub_m.usage());
+                    ::std::process::exit(1);
+                }
+            }
+        }
         (CONTENT_FETCH, Some(sub_m)) => {
             let rev = sub_m.value_of("CHANGESET_ID").unwrap();
             let path = sub_m.value_of("PATH").unwrap();
@@ -304,13 +429,11 @@ fn main() {
                         Content::Executable(_) => {
                             println!("Binary file");
                         }
-                        Content::File(contents) | Content::Symlink(contents) => match contents {
-                            FileContents::Bytes(bytes) => {
-                                let content = String::from_utf8(bytes.to_vec())
-                                    .expect("non-utf8 file content");
-                                println!("{}", content);
-                            }
-                        },
+                        Content::File(contents) | Content::Symlink(contents) => {
+                            let content = String::from_utf8(contents.into_bytes().to_vec())
+                                .expect("non-utf8 file content");
+                            println!("{}", content);
+                        }
                         Content::Tree(mf) => {
                             let entries: Vec<_> = mf.list().collect();
                             let mut longest_len = 0;
@@ -341,6 +464,70 @@ fn main() {
                 })
                 .boxify()
         }
+        (REVSET, Some(sub_m)) => {
+            let revset = sub_m.value_of("REVSET").unwrap();
+
+            let repo = Arc::new(create_blobrepo(&logger, manifold_args));
+            let index = SkiplistIndex::new(repo.clone(), SKIPLIST_CACHE_SIZE);
+            future::result(Revset::parse(revset))
+                .and_then(move |revset| {
+                    revset
+                        .evaluate(&repo, RepoGenCache::new(1000000), &index)
+                        .for_each(|node| {
+                            println!("{}", node);
+                            Ok(())
+                        })
+                })
+                .boxify()
+        }
+        (SCRUB, Some(sub_m)) => {
+            let checkpoint = match sub_m.value_of("checkpoint") {
+                Some(path) => Checkpoint::open(Path::new(path)).expect("cannot open checkpoint"),
+                None => Checkpoint::none(),
+            };
+
+            let repo = create_blobrepo(&logger, manifold_args);
+            Scrubber::new(repo, logger.clone())
+                .scrub(checkpoint)
+                .and_then(|stats| {
+                    println!("{:#?}", stats);
+                    if stats.problems() == 0 {
+                        Ok(())
+                    } else {
+                        Err(format_err!("scrub found {} problems", stats.problems()))
+                    }
+                })
+                .boxify()
+        }
+        (SKIPLIST_BACKFILL, Some(_)) => {
+            let repo = Arc::new(create_blobrepo(&logger, manifold_args));
+            repo.get_heads()
+                .collect()
+                .and_then(move |heads| SkiplistIndex::backfill(repo, SKIPLIST_CACHE_SIZE, heads))
+                .boxify()
+        }
+        (STREAMING_CLONE_UPDATE, Some(sub_m)) => {
+            let repo_path = sub_m.value_of("REPO_PATH").unwrap();
+
+            let repo = create_blobrepo(&logger, manifold_args);
+            StreamingClone::new(Arc::new(repo.get_blobstore()))
+                .update_snapshot(repo_path)
+                .map({
+                    let logger = logger.clone();
+                    move |snapshot| {
+                        for file in snapshot.files {
+                            info!(
+                                logger,
+                                "{}: {} bytes in {} chunks",
+                                file.name,
+                                file.size,
+                                file.chunks.len()
+                            );
+                        }
+                    }
+                })
+                .boxify()
+        }
         _ => {
             println!("{}", matches.usage());
             ::std::process::exit(1);
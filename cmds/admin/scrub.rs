@@ -0,0 +1,835 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Walks everything that is reachable from the bookmarks of a repo: changesets, manifests,
+//! filenodes and file contents, and checks that each of them is present in the blobstore and
+//! hashes to the key it is stored under.
+
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use bytes::{Bytes, BytesMut};
+use failure::{err_msg, Error, Result, ResultExt};
+use futures::{future, Future, Stream};
+use futures::future::Loop;
+use futures::stream::iter_ok;
+use futures_ext::{BoxFuture, FutureExt};
+use slog::Logger;
+
+use blobrepo::{lfs_blobstore_key, lfs_chunk_key, BlobChangeset, BlobManifest, BlobRepo,
+               ErrorKind};
+use blobstore::Blobstore;
+use mercurial_types::{Changeset, HgBlobNode, HgChangesetEnvelope, HgChangesetId, HgFileEnvelope,
+                      HgFileNodeId, HgManifestEnvelope, HgManifestId, HgNodeHash, MPath,
+                      Manifest, RepoPath, Type, NULL_HASH};
+use mononoke_types::{BlobstoreValue, ChunkPointer, ChunkedContents, ContentBlob, ContentChunk,
+                     ContentChunkBlob, ContentChunkId, ContentId, ExternalContents, FileContents,
+                     LfsPointer, MononokeId};
+
+/// How many manifest entries of a single tree are verified concurrently
+const MAX_CONCURRENT_ENTRIES: usize = 100;
+
+/// Something that is wrong with the data of a repo
+pub enum Problem {
+    /// A blob that is referenced from somewhere is missing from the blobstore
+    MissingKey(String),
+    /// The hash of a blob doesn't match the key it is stored under
+    HashMismatch { key: String, computed: String },
+    /// A blob exists, but can't be decoded
+    Corrupt { key: String, error: Error },
+    /// A file that is referenced from a manifest has no filenode row
+    MissingFilenode { path: RepoPath, filenode: HgFileNodeId },
+    /// A filenode row refers to a filenode or a linknode that doesn't exist
+    DanglingFilenode {
+        path: RepoPath,
+        filenode: HgFileNodeId,
+        reason: String,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Problem::MissingKey(ref key) => write!(f, "missing key {}", key),
+            Problem::HashMismatch {
+                ref key,
+                ref computed,
+            } => write!(f, "hash mismatch for {}: computed {}", key, computed),
+            Problem::Corrupt { ref key, ref error } => {
+                write!(f, "corrupt blob {}: {}", key, error)
+            }
+            Problem::MissingFilenode {
+                ref path,
+                ref filenode,
+            } => write!(f, "no filenode row for {} {}", path, filenode),
+            Problem::DanglingFilenode {
+                ref path,
+                ref filenode,
+                ref reason,
+            } => write!(f, "dangling filenode row {} {}: {}", path, filenode, reason),
+        }
+    }
+}
+
+/// Counts of the items verified by the scrubber and of the problems it found
+#[derive(Clone, Debug, Default)]
+pub struct ScrubStats {
+    pub changesets: usize,
+    pub skipped_changesets: usize,
+    pub manifests: usize,
+    pub files: usize,
+    pub filenode_rows: usize,
+    pub missing_keys: usize,
+    pub hash_mismatches: usize,
+    pub corrupt_blobs: usize,
+    pub missing_filenodes: usize,
+    pub dangling_filenodes: usize,
+}
+
+impl ScrubStats {
+    pub fn problems(&self) -> usize {
+        self.missing_keys + self.hash_mismatches + self.corrupt_blobs + self.missing_filenodes
+            + self.dangling_filenodes
+    }
+}
+
+/// The changesets that were completely verified by previous runs and had no problems, so that an
+/// interrupted scrub can be resumed. Changesets with problems are left out, so that resuming
+/// reports their problems again. They are stored one hash per line.
+pub struct Checkpoint {
+    done: HashSet<HgChangesetId>,
+    file: Option<File>,
+}
+
+impl Checkpoint {
+    /// A checkpoint that isn't persisted anywhere
+    pub fn none() -> Self {
+        Self {
+            done: HashSet::new(),
+            file: None,
+        }
+    }
+
+    /// Loads the checkpoint from `path`, creating the file if it doesn't exist yet
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut done = HashSet::new();
+        if path.exists() {
+            let reader = BufReader::new(File::open(path)?);
+            for line in reader.lines() {
+                let line = line?;
+                let line = line.trim();
+                if !line.is_empty() {
+                    let cs_id = HgChangesetId::from_str(line)
+                        .with_context(|_| format!("invalid checkpoint entry {:?}", line))?;
+                    done.insert(cs_id);
+                }
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            done,
+            file: Some(file),
+        })
+    }
+
+    fn is_done(&self, cs_id: &HgChangesetId) -> bool {
+        self.done.contains(cs_id)
+    }
+
+    fn mark_done(&mut self, cs_id: HgChangesetId) -> Result<()> {
+        if let Some(ref mut file) = self.file {
+            writeln!(file, "{}", cs_id)?;
+            file.flush()?;
+        }
+        self.done.insert(cs_id);
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct ScrubState {
+    seen_manifests: HashSet<HgNodeHash>,
+    seen_files: HashSet<HgNodeHash>,
+    seen_paths: HashSet<RepoPath>,
+    stats: ScrubStats,
+}
+
+/// Verifies the data that is reachable from the bookmarks of a repo. Problems are logged as
+/// they are found. Errors from the blobstore or the databases abort the scrub, and it can be
+/// resumed from the checkpoint once they are resolved.
+#[derive(Clone)]
+pub struct Scrubber {
+    repo: BlobRepo,
+    logger: Logger,
+    state: Arc<Mutex<ScrubState>>,
+}
+
+impl Scrubber {
+    pub fn new(repo: BlobRepo, logger: Logger) -> Self {
+        Self {
+            repo,
+            logger,
+            state: Arc::new(Mutex::new(ScrubState::default())),
+        }
+    }
+
+    /// Walks all changesets that are reachable from the bookmarks. Changesets that are in the
+    /// checkpoint are only used to find their parents, and every changeset that is verified
+    /// without problems is added to it.
+    pub fn scrub(&self, checkpoint: Checkpoint) -> BoxFuture<ScrubStats, Error> {
+        let this = self.clone();
+
+        self.repo
+            .get_bookmarks()
+            .map(|(_, cs_id)| cs_id)
+            .collect()
+            .and_then(move |heads| {
+                let mut seen = HashSet::new();
+                let mut queue = VecDeque::new();
+                for head in heads {
+                    if seen.insert(head) {
+                        queue.push_back(head);
+                    }
+                }
+
+                future::loop_fn(
+                    (this, checkpoint, seen, queue),
+                    |(this, mut checkpoint, mut seen, mut queue)| {
+                        let cs_id = match queue.pop_front() {
+                            Some(cs_id) => cs_id,
+                            None => return future::ok(Loop::Break(this.stats())).boxify(),
+                        };
+
+                        let verify = if checkpoint.is_done(&cs_id) {
+                            this.with_stats(|stats| stats.skipped_changesets += 1);
+                            future::ok(false).boxify()
+                        } else {
+                            // Changesets are verified one at a time, so the problems found
+                            // meanwhile are all problems of this changeset
+                            let problems = this.stats().problems();
+                            this.scrub_changeset(cs_id)
+                                .map({
+                                    let this = this.clone();
+                                    move |()| this.stats().problems() == problems
+                                })
+                                .boxify()
+                        };
+
+                        verify
+                            .join(this.repo.get_changeset_parents(&cs_id))
+                            .and_then(move |(done, parents)| {
+                                if done {
+                                    checkpoint.mark_done(cs_id)?;
+                                }
+                                for parent in parents {
+                                    if seen.insert(parent) {
+                                        queue.push_back(parent);
+                                    }
+                                }
+                                Ok(Loop::Continue((this, checkpoint, seen, queue)))
+                            })
+                            .boxify()
+                    },
+                )
+            })
+            .boxify()
+    }
+
+    fn stats(&self) -> ScrubStats {
+        self.state.lock().expect("lock poisoned").stats.clone()
+    }
+
+    fn with_stats<F: FnOnce(&mut ScrubStats)>(&self, f: F) {
+        f(&mut self.state.lock().expect("lock poisoned").stats)
+    }
+
+    fn report(&self, problem: Problem) {
+        error!(self.logger, "{}", problem);
+        self.with_stats(|stats| match problem {
+            Problem::MissingKey(..) => stats.missing_keys += 1,
+            Problem::HashMismatch { .. } => stats.hash_mismatches += 1,
+            Problem::Corrupt { .. } => stats.corrupt_blobs += 1,
+            Problem::MissingFilenode { .. } => stats.missing_filenodes += 1,
+            Problem::DanglingFilenode { .. } => stats.dangling_filenodes += 1,
+        });
+    }
+
+    /// Checks the hash of a blob. Returns false if it doesn't match.
+    fn check_hash(&self, key: &str, expected: &HgNodeHash, blobnode: HgBlobNode) -> bool {
+        match blobnode.nodeid() {
+            Some(ref computed) if computed == expected => true,
+            computed => {
+                let computed = match computed {
+                    Some(computed) => computed.to_string(),
+                    None => "no hash".to_string(),
+                };
+                self.report(Problem::HashMismatch {
+                    key: key.to_string(),
+                    computed,
+                });
+                false
+            }
+        }
+    }
+
+    fn scrub_changeset(&self, cs_id: HgChangesetId) -> BoxFuture<(), Error> {
+        let this = self.clone();
+        let key = cs_id.blobstore_key();
+        debug!(self.logger, "scrubbing changeset {}", cs_id);
+
+        self.repo
+            .get_blobstore()
+            .get(key.clone())
+            .and_then(move |bytes| {
+                this.with_stats(|stats| stats.changesets += 1);
+                let bytes = match bytes {
+                    Some(bytes) => bytes,
+                    None => {
+                        this.report(Problem::MissingKey(key));
+                        return future::ok(()).boxify();
+                    }
+                };
+
+                let cs = HgChangesetEnvelope::from_blob(bytes.into()).and_then(|envelope| {
+                    let (p1, p2) = envelope.parents();
+                    let blobnode = HgBlobNode::new(envelope.contents().clone(), p1, p2);
+                    this.check_hash(&key, cs_id.as_nodehash(), blobnode);
+                    BlobChangeset::from_envelope(envelope)
+                });
+                let cs = match cs {
+                    Ok(cs) => cs,
+                    Err(error) => {
+                        this.report(Problem::Corrupt { key, error });
+                        return future::ok(()).boxify();
+                    }
+                };
+
+                // The envelope hash only covers the bytes as they are stored, make sure that
+                // they also parse into the same changeset
+                match cs.compute_hash() {
+                    Ok(ref computed) if *computed == cs_id => (),
+                    Ok(computed) => this.report(Problem::HashMismatch {
+                        key,
+                        computed: computed.to_string(),
+                    }),
+                    Err(error) => this.report(Problem::Corrupt { key, error }),
+                }
+
+                this.scrub_manifest(None, *cs.manifestid())
+            })
+            .boxify()
+    }
+
+    fn scrub_manifest(
+        &self,
+        path: Option<MPath>,
+        manifest_id: HgManifestId,
+    ) -> BoxFuture<(), Error> {
+        let node_id = manifest_id.into_nodehash();
+        if node_id == NULL_HASH || !self.first_visit(|state| &mut state.seen_manifests, node_id) {
+            return future::ok(()).boxify();
+        }
+
+        let this = self.clone();
+        let key = manifest_id.blobstore_key();
+        let blobstore = self.repo.get_blobstore();
+
+        blobstore
+            .get(key.clone())
+            .and_then(move |bytes| {
+                this.with_stats(|stats| stats.manifests += 1);
+                let bytes = match bytes {
+                    Some(bytes) => bytes,
+                    None => {
+                        this.report(Problem::MissingKey(key));
+                        return future::ok(()).boxify();
+                    }
+                };
+
+                let manifest = HgManifestEnvelope::from_blob(bytes.into()).and_then(|envelope| {
+                    if envelope.node_id() != &node_id {
+                        this.report(Problem::HashMismatch {
+                            key: key.clone(),
+                            computed: envelope.node_id().to_string(),
+                        });
+                    } else {
+                        // Root manifests are the only ones that are allowed to have a node id
+                        // that differs from the hash of their contents
+                        let expected = if path.is_some() {
+                            &node_id
+                        } else {
+                            envelope.computed_node_id()
+                        };
+                        let (p1, p2) = envelope.parents();
+                        let blobnode = HgBlobNode::new(envelope.contents().clone(), p1, p2);
+                        this.check_hash(&key, expected, blobnode);
+                    }
+                    BlobManifest::parse(this.repo.get_blobstore(), envelope)
+                });
+                let manifest = match manifest {
+                    Ok(manifest) => manifest,
+                    Err(error) => {
+                        this.report(Problem::Corrupt { key, error });
+                        return future::ok(()).boxify();
+                    }
+                };
+
+                let entries: Vec<_> = manifest
+                    .list()
+                    .map(|entry| {
+                        let path = MPath::join_element_opt(path.as_ref(), entry.get_name())
+                            .expect("manifest entries have names");
+                        let node_id = entry.get_hash().into_nodehash();
+                        (path, node_id, entry.get_type())
+                    })
+                    .collect();
+
+                iter_ok(entries)
+                    .map(move |(path, node_id, ty)| match ty {
+                        Type::Tree => this.scrub_manifest(Some(path), HgManifestId::new(node_id)),
+                        Type::File(_) => this.scrub_file(path, node_id),
+                    })
+                    .buffer_unordered(MAX_CONCURRENT_ENTRIES)
+                    .for_each(|()| Ok(()))
+                    .boxify()
+            })
+            .boxify()
+    }
+
+    fn scrub_file(&self, path: MPath, node_id: HgNodeHash) -> BoxFuture<(), Error> {
+        let repo_path = RepoPath::FilePath(path);
+        let filenode_rows = if self.first_visit(|state| &mut state.seen_paths, repo_path.clone()) {
+            self.scrub_filenode_rows(repo_path.clone())
+        } else {
+            future::ok(()).boxify()
+        };
+        if !self.first_visit(|state| &mut state.seen_files, node_id) {
+            return filenode_rows;
+        }
+
+        let this = self.clone();
+        let key = HgFileNodeId::new(node_id).blobstore_key();
+
+        let envelope = self.repo.get_blobstore().get(key.clone()).and_then({
+            let this = this.clone();
+            move |bytes| {
+                this.with_stats(|stats| stats.files += 1);
+                let bytes = match bytes {
+                    Some(bytes) => bytes,
+                    None => {
+                        this.report(Problem::MissingKey(key));
+                        return Ok(None);
+                    }
+                };
+                match HgFileEnvelope::from_blob(bytes.into()) {
+                    Ok(envelope) => Ok(Some((key, envelope))),
+                    Err(error) => {
+                        this.report(Problem::Corrupt { key, error });
+                        Ok(None)
+                    }
+                }
+            }
+        });
+
+        let contents = envelope.and_then({
+            let this = this.clone();
+            move |envelope| match envelope {
+                Some((key, envelope)) => this.scrub_file_contents(key, node_id, envelope),
+                None => future::ok(()).boxify(),
+            }
+        });
+
+        let filenode_row = this.repo
+            .get_linknode(repo_path, &node_id)
+            .then(move |res| match res {
+                Ok(_) => Ok(()),
+                Err(err) => match err.downcast::<ErrorKind>() {
+                    Ok(ErrorKind::MissingFilenode(path, filenode)) => {
+                        this.report(Problem::MissingFilenode { path, filenode });
+                        Ok(())
+                    }
+                    Ok(err) => Err(err.into()),
+                    Err(err) => Err(err),
+                },
+            });
+
+        contents
+            .join3(filenode_row, filenode_rows)
+            .map(|_| ())
+            .boxify()
+    }
+
+    /// Checks the hash of the contents blob, and the hash of the filenode, which covers the copy
+    /// metadata and the contents. Contents that are stored out of band are fetched and checked
+    /// against their pointer first.
+    fn scrub_file_contents(
+        &self,
+        key: String,
+        node_id: HgNodeHash,
+        envelope: HgFileEnvelope,
+    ) -> BoxFuture<(), Error> {
+        let this = self.clone();
+        let content_id = *envelope.content_id();
+        let content_key = content_id.blobstore_key();
+
+        self.repo
+            .get_blobstore()
+            .get(content_key.clone())
+            .and_then({
+                let this = this.clone();
+                move |bytes| {
+                    let bytes = match bytes {
+                        Some(bytes) => bytes,
+                        None => return this.scrub_external_contents(content_id),
+                    };
+
+                    let blob: ContentBlob = bytes.into();
+                    if *blob.id() != content_id {
+                        this.report(Problem::HashMismatch {
+                            key: content_key,
+                            computed: blob.id().to_string(),
+                        });
+                        return future::ok(None).boxify();
+                    }
+                    match FileContents::from_blob(blob) {
+                        Ok(contents) => future::ok(Some(contents.into_bytes())).boxify(),
+                        Err(error) => {
+                            this.report(Problem::Corrupt {
+                                key: content_key,
+                                error,
+                            });
+                            future::ok(None).boxify()
+                        }
+                    }
+                }
+            })
+            .map(move |contents| {
+                if let Some(contents) = contents {
+                    let mut data = envelope.metadata().to_vec();
+                    data.extend_from_slice(contents.as_ref());
+                    let (p1, p2) = envelope.parents();
+                    this.check_hash(&key, &node_id, HgBlobNode::new(Bytes::from(data), p1, p2));
+                }
+            })
+            .boxify()
+    }
+
+    /// Fetches the contents with id `content_id` that have no content blob, because they are
+    /// stored out of band or chunked, and checks that they hash to `content_id`. Returns None if
+    /// they are missing or don't match.
+    fn scrub_external_contents(&self, content_id: ContentId) -> BoxFuture<Option<Bytes>, Error> {
+        let this = self.clone();
+        let key = ExternalContents::blobstore_key(&content_id);
+
+        self.repo
+            .get_blobstore()
+            .get(key.clone())
+            .and_then({
+                let this = this.clone();
+                let key = key.clone();
+                move |bytes| {
+                    let bytes = match bytes {
+                        Some(bytes) => bytes,
+                        None => {
+                            this.report(Problem::MissingKey(content_id.blobstore_key()));
+                            return future::ok(None).boxify();
+                        }
+                    };
+
+                    match ExternalContents::from_blobstore_bytes(&bytes) {
+                        Ok(ExternalContents::Lfs(pointer)) => this.scrub_lfs_content(pointer),
+                        Ok(ExternalContents::Chunked(chunked)) => {
+                            this.scrub_chunked_contents(chunked, |chunk_id| {
+                                chunk_id.blobstore_key()
+                            })
+                        }
+                        Err(error) => {
+                            this.report(Problem::Corrupt { key, error });
+                            future::ok(None).boxify()
+                        }
+                    }
+                }
+            })
+            .map(move |contents| {
+                let contents = contents?;
+                let blob = FileContents::new_bytes(contents.clone()).into_blob();
+                if *blob.id() != content_id {
+                    this.report(Problem::HashMismatch {
+                        key,
+                        computed: blob.id().to_string(),
+                    });
+                    return None;
+                }
+                Some(contents)
+            })
+            .boxify()
+    }
+
+    /// Fetches the contents `pointer` points to from the LFS blobstore and checks their hash and
+    /// size. Returns None if they are missing or don't match.
+    fn scrub_lfs_content(&self, pointer: LfsPointer) -> BoxFuture<Option<Bytes>, Error> {
+        let this = self.clone();
+        let key = lfs_blobstore_key(pointer.sha256());
+
+        self.repo
+            .get_blobstore()
+            .get(key.clone())
+            .and_then({
+                let this = this.clone();
+                let key = key.clone();
+                move |bytes| {
+                    let bytes = match bytes {
+                        Some(bytes) => bytes,
+                        None => {
+                            this.report(Problem::MissingKey(key));
+                            return future::ok(None).boxify();
+                        }
+                    };
+
+                    match ChunkedContents::from_blobstore_bytes(&bytes) {
+                        Ok(chunked) => this.scrub_chunked_contents(chunked, lfs_chunk_key),
+                        Err(error) => {
+                            this.report(Problem::Corrupt { key, error });
+                            future::ok(None).boxify()
+                        }
+                    }
+                }
+            })
+            .map(move |contents| {
+                let contents = contents?;
+                let computed = LfsPointer::from_content(&contents);
+                if computed != pointer {
+                    this.report(Problem::HashMismatch {
+                        key,
+                        computed: format!("{} of size {}", computed.sha256(), computed.size()),
+                    });
+                    return None;
+                }
+                Some(contents)
+            })
+            .boxify()
+    }
+
+    /// Fetches the chunks of `chunked` from the keys `chunk_key` gives for their ids, and checks
+    /// their hashes and sizes. Returns the concatenated contents, or None if a chunk is missing
+    /// or doesn't match.
+    fn scrub_chunked_contents(
+        &self,
+        chunked: ChunkedContents,
+        chunk_key: fn(&ContentChunkId) -> String,
+    ) -> BoxFuture<Option<Bytes>, Error> {
+        let this = self.clone();
+
+        iter_ok(chunked.into_chunks())
+            .map(move |pointer| this.scrub_content_chunk(pointer, chunk_key))
+            .buffered(MAX_CONCURRENT_ENTRIES)
+            .fold(Some(BytesMut::new()), |contents, chunk| {
+                let contents = match (contents, chunk) {
+                    (Some(mut contents), Some(chunk)) => {
+                        contents.extend_from_slice(&chunk);
+                        Some(contents)
+                    }
+                    _ => None,
+                };
+                Ok::<_, Error>(contents)
+            })
+            .map(|contents| contents.map(BytesMut::freeze))
+            .boxify()
+    }
+
+    fn scrub_content_chunk(
+        &self,
+        pointer: ChunkPointer,
+        chunk_key: fn(&ContentChunkId) -> String,
+    ) -> BoxFuture<Option<Bytes>, Error> {
+        let this = self.clone();
+        let chunk_id = *pointer.chunk_id();
+        let key = chunk_key(&chunk_id);
+
+        self.repo
+            .get_blobstore()
+            .get(key.clone())
+            .map(move |bytes| {
+                let bytes = match bytes {
+                    Some(bytes) => bytes,
+                    None => {
+                        this.report(Problem::MissingKey(key));
+                        return None;
+                    }
+                };
+
+                let blob: ContentChunkBlob = bytes.into();
+                if *blob.id() != chunk_id {
+                    this.report(Problem::HashMismatch {
+                        key,
+                        computed: blob.id().to_string(),
+                    });
+                    return None;
+                }
+                let chunk = match ContentChunk::from_blob(blob) {
+                    Ok(chunk) => chunk.into_bytes(),
+                    Err(error) => {
+                        this.report(Problem::Corrupt { key, error });
+                        return None;
+                    }
+                };
+                if chunk.len() as u64 != pointer.size() {
+                    let error = err_msg(format!(
+                        "chunk has size {}, expected {}",
+                        chunk.len(),
+                        pointer.size()
+                    ));
+                    this.report(Problem::Corrupt { key, error });
+                    return None;
+                }
+                Some(chunk)
+            })
+            .boxify()
+    }
+
+    /// Checks that every filenode row of the path refers to an existing filenode and linknode
+    fn scrub_filenode_rows(&self, path: RepoPath) -> BoxFuture<(), Error> {
+        let this = self.clone();
+
+        self.repo
+            .get_all_filenodes(path)
+            .and_then(move |rows| {
+                this.with_stats(|stats| stats.filenode_rows += rows.len());
+                let checks: Vec<_> = rows.into_iter()
+                    .map(|row| {
+                        let this = this.clone();
+                        let filenode_present = this.repo
+                            .get_blobstore()
+                            .is_present(row.filenode.blobstore_key());
+                        let linknode_present = this.repo.changeset_exists(&row.linknode);
+                        filenode_present.join(linknode_present).map(
+                            move |(filenode_present, linknode_present)| {
+                                let reason = if !filenode_present {
+                                    "filenode is missing from the blobstore".to_string()
+                                } else if !linknode_present {
+                                    format!("linknode {} doesn't exist", row.linknode)
+                                } else {
+                                    return;
+                                };
+                                this.report(Problem::DanglingFilenode {
+                                    path: row.path,
+                                    filenode: row.filenode,
+                                    reason,
+                                });
+                            },
+                        )
+                    })
+                    .collect();
+
+                iter_ok(checks)
+                    .buffer_unordered(MAX_CONCURRENT_ENTRIES)
+                    .for_each(|()| Ok(()))
+            })
+            .boxify()
+    }
+
+    /// Records that `item` was visited. Returns false if it was visited before.
+    fn first_visit<T, F>(&self, seen: F, item: T) -> bool
+    where
+        T: ::std::hash::Hash + Eq,
+        F: FnOnce(&mut ScrubState) -> &mut HashSet<T>,
+    {
+        let mut state = self.state.lock().expect("lock poisoned");
+        seen(&mut state).insert(item)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use async_unit;
+    use linear;
+    use mononoke_types::BlobstoreBytes;
+    use slog::Discard;
+    use tempdir::TempDir;
+
+    const HEAD: &str = "a5ffa77602a066db7d5cfb9fb5823a0895717c5a";
+    const HEAD_PARENT: &str = "3c15267ebf11807f3d772eb891272b911ec68759";
+
+    fn scrubber(repo: BlobRepo) -> Scrubber {
+        Scrubber::new(repo, Logger::root(Discard, o!()))
+    }
+
+    #[test]
+    fn test_checkpoint_roundtrip() {
+        let dir = TempDir::new("scrub").expect("cannot create temp dir");
+        let path = dir.path().join("checkpoint");
+        let head = HgChangesetId::from_str(HEAD).unwrap();
+
+        let mut checkpoint = Checkpoint::open(&path).expect("cannot create checkpoint");
+        assert!(!checkpoint.is_done(&head));
+        checkpoint.mark_done(head).unwrap();
+        assert!(checkpoint.is_done(&head));
+
+        let checkpoint = Checkpoint::open(&path).expect("cannot reopen checkpoint");
+        assert!(checkpoint.is_done(&head));
+    }
+
+    #[test]
+    fn test_scrub_and_resume() {
+        async_unit::tokio_unit_test(|| {
+            let dir = TempDir::new("scrub").expect("cannot create temp dir");
+            let path = dir.path().join("checkpoint");
+            let repo = linear::getrepo(None);
+
+            let checkpoint = Checkpoint::open(&path).unwrap();
+            let stats = scrubber(repo.clone()).scrub(checkpoint).wait().unwrap();
+            assert_eq!(stats.problems(), 0);
+            assert_eq!(stats.changesets, 10);
+            assert_eq!(stats.skipped_changesets, 0);
+
+            let checkpoint = Checkpoint::open(&path).unwrap();
+            let stats = scrubber(repo).scrub(checkpoint).wait().unwrap();
+            assert_eq!(stats.changesets, 0);
+            assert_eq!(stats.skipped_changesets, 10);
+        });
+    }
+
+    #[test]
+    fn test_problems_are_not_checkpointed() {
+        async_unit::tokio_unit_test(|| {
+            let dir = TempDir::new("scrub").expect("cannot create temp dir");
+            let path = dir.path().join("checkpoint");
+            let repo = linear::getrepo(None);
+            let head = HgChangesetId::from_str(HEAD).unwrap();
+            let head_parent = HgChangesetId::from_str(HEAD_PARENT).unwrap();
+
+            repo.get_blobstore()
+                .put(
+                    head.blobstore_key(),
+                    BlobstoreBytes::from_bytes(&b"corrupt"[..]),
+                )
+                .wait()
+                .unwrap();
+
+            let checkpoint = Checkpoint::open(&path).unwrap();
+            let stats = scrubber(repo.clone()).scrub(checkpoint).wait().unwrap();
+            assert_eq!(stats.corrupt_blobs, 1);
+            assert_eq!(stats.problems(), 1);
+
+            let checkpoint = Checkpoint::open(&path).unwrap();
+            assert!(!checkpoint.is_done(&head));
+            assert!(checkpoint.is_done(&head_parent));
+
+            // Resuming verifies the changeset with problems again, and reports them again
+            let stats = scrubber(repo).scrub(checkpoint).wait().unwrap();
+            assert_eq!(stats.changesets, 1);
+            assert_eq!(stats.skipped_changesets, 9);
+            assert_eq!(stats.corrupt_blobs, 1);
+        });
+    }
+}
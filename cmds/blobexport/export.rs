@@ -0,0 +1,550 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Writing of the history of a blobrepo into the revlogs of a new Mercurial repo
+//!
+//! The manifests are written both as flat manifests into `00manifest`, which is where Mercurial
+//! reads them from, and as tree manifests into `00manifesttree` and `meta/`, which is where
+//! `RevlogRepo` and the treemanifest extension read them from. A flat manifest has the same id as
+//! the root tree manifest, like in the repos that blobimport reads.
+//!
+//! Files larger than `STREAM_THRESHOLD` are streamed into their filelogs, so that their contents
+//! never have to be held in memory.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use failure::{Error, Result, ResultExt};
+use futures::{future, Future, Stream};
+use futures::future::Loop;
+use futures_ext::{BoxFuture, FutureExt};
+use slog::Logger;
+
+use blobrepo::BlobRepo;
+use mercurial::RevlogChangeset;
+use mercurial::changeset::serialize_cs;
+use mercurial::manifest::{Details, ManifestContent};
+use mercurial::revlog::{RevIdx, RevisionWriter, RevlogWriter};
+use mercurial_types::{fncache_fsencode, Changeset, Entry, HgChangesetId, HgManifestId,
+                      HgNodeHash, MPath, RepoPath, Type};
+use mercurial_types::manifest::Content;
+use mercurial_types::manifest_utils::{changed_entry_stream, recursive_entry_stream, EntryStatus};
+
+/// Requirements of the exported repo
+const REQUIREMENTS: &[&str] = &["dotencode", "fncache", "generaldelta", "revlogv1", "store"];
+
+/// Files larger than this are streamed into their filelogs, and so are never stored as deltas
+const STREAM_THRESHOLD: u64 = 10 * 1024 * 1024;
+
+/// The revlogs of the repo being written
+struct Revlogs {
+    store: PathBuf,
+    changelog: RevlogWriter,
+    manifest: RevlogWriter,                    // flat manifests
+    flat: Option<(HgNodeHash, FlatManifest)>, // the flat manifest that was written last
+    paths: HashMap<RepoPath, RevlogWriter>,    // tree manifests and filelogs
+    fncache: Vec<Vec<u8>>,
+}
+
+type FlatManifest = BTreeMap<MPath, Details>;
+
+impl Revlogs {
+    fn create(store: PathBuf) -> Result<Self> {
+        let changelog =
+            RevlogWriter::create(store.join("00changelog.i"), store.join("00changelog.d"))?;
+        let manifest =
+            RevlogWriter::create(store.join("00manifest.i"), store.join("00manifest.d"))?;
+        Ok(Revlogs {
+            store,
+            changelog,
+            manifest,
+            flat: None,
+            paths: HashMap::new(),
+            fncache: Vec::new(),
+        })
+    }
+
+    fn contains(&self, path: &RepoPath, nodeid: &HgNodeHash) -> bool {
+        self.paths
+            .get(path)
+            .map_or(false, |revlog| revlog.contains(nodeid))
+    }
+
+    fn add_entry(
+        &mut self,
+        path: &RepoPath,
+        nodeid: &HgNodeHash,
+        (p1, p2): (Option<&HgNodeHash>, Option<&HgNodeHash>),
+        linknode: &HgNodeHash,
+        linkrev: RevIdx,
+        text: &[u8],
+    ) -> Result<()> {
+        let linkrev = self.linkrev(linknode, linkrev);
+        self.path_revlog(path)?
+            .add_revision(nodeid, p1, p2, linkrev, text)
+            .with_context(|_| format!("While writing the revlog of {:?}", path))?;
+        Ok(())
+    }
+
+    /// Start writing an entry whose text is streamed, see `RevlogWriter::start_revision`
+    fn start_entry(
+        &mut self,
+        path: &RepoPath,
+        nodeid: &HgNodeHash,
+        (p1, p2): (Option<&HgNodeHash>, Option<&HgNodeHash>),
+        linknode: &HgNodeHash,
+        linkrev: RevIdx,
+    ) -> Result<RevisionWriter> {
+        let linkrev = self.linkrev(linknode, linkrev);
+        let revision = self.path_revlog(path)?
+            .start_revision(nodeid, p1, p2, linkrev)
+            .with_context(|_| format!("While writing the revlog of {:?}", path))?;
+        Ok(revision)
+    }
+
+    fn finish_entry(&mut self, path: &RepoPath, revision: RevisionWriter) -> Result<()> {
+        self.path_revlog(path)?
+            .finish_revision(revision)
+            .with_context(|_| format!("While writing the revlog of {:?}", path))?;
+        Ok(())
+    }
+
+    // The entry links to the changeset that introduced it, unless that one is exported later
+    fn linkrev(&self, linknode: &HgNodeHash, linkrev: RevIdx) -> RevIdx {
+        self.changelog
+            .get_idx_by_nodeid(linknode)
+            .unwrap_or(linkrev)
+    }
+
+    fn path_revlog(&mut self, path: &RepoPath) -> Result<&mut RevlogWriter> {
+        if !self.paths.contains_key(path) {
+            let revlog = self.create_path_revlog(path)?;
+            self.paths.insert(path.clone(), revlog);
+        }
+        Ok(self.paths.get_mut(path).expect("revlog was just inserted"))
+    }
+
+    fn create_path_revlog(&mut self, path: &RepoPath) -> Result<RevlogWriter> {
+        let revlog_path = match *path {
+            // .hg/store/00manifesttree
+            RepoPath::RootPath => MPath::new("00manifesttree")?,
+            // .hg/store/meta/<path>/00manifest
+            RepoPath::DirectoryPath(_) => MPath::new("meta")?
+                .join(MPath::iter_opt(path.mpath()))
+                .join(&MPath::new("00manifest")?),
+            // .hg/store/data/<path>
+            RepoPath::FilePath(_) => MPath::new("data")?.join(MPath::iter_opt(path.mpath())),
+        };
+
+        let mut files = vec![];
+        for extension in &[".i", ".d"] {
+            let mut file = revlog_path.to_vec();
+            file.extend_from_slice(extension.as_bytes());
+            let file = MPath::new(file)?;
+            let elements: Vec<_> = file.clone().into_iter().collect();
+            files.push(self.store.join(fncache_fsencode(&elements, true)));
+            if *path != RepoPath::RootPath {
+                self.fncache.push(file.to_vec());
+            }
+        }
+        RevlogWriter::create(&files[0], &files[1])
+    }
+
+    fn write_fncache(&self) -> Result<()> {
+        let mut fncache = File::create(self.store.join("fncache"))?;
+        for file in &self.fncache {
+            fncache.write_all(file)?;
+            fncache.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// Write every changeset reachable from the bookmarks of `blobrepo`, and the bookmarks, into a
+/// new Mercurial repo at `output`.
+pub fn export(logger: Logger, blobrepo: Arc<BlobRepo>, output: &Path) -> BoxFuture<(), Error> {
+    let dothg = output.join(".hg");
+    if dothg.exists() {
+        return future::err(format_err!("{:?} already exists", dothg)).boxify();
+    }
+    let store = dothg.join("store");
+    try_boxfuture!(
+        fs::create_dir_all(&store).with_context(|_| format!("Can't create {:?}", store))
+    );
+    try_boxfuture!(write_lines(dothg.join("requires"), REQUIREMENTS.iter()));
+    let revlogs = Arc::new(Mutex::new(try_boxfuture!(Revlogs::create(store))));
+
+    blobrepo
+        .get_heads()
+        .map(HgChangesetId::new)
+        .collect()
+        .and_then({
+            let logger = logger.clone();
+            let blobrepo = blobrepo.clone();
+            let revlogs = revlogs.clone();
+            move |heads| export_changesets(logger, blobrepo, revlogs, heads)
+        })
+        .and_then(move |cs_count| {
+            info!(logger, "exported {} commits, now doing bookmarks", cs_count);
+            blobrepo.get_bookmarks().collect()
+        })
+        .and_then(move |bookmarks| {
+            let mut lines: Vec<_> = bookmarks
+                .into_iter()
+                .map(|(bookmark, csid)| format!("{} {}", csid, bookmark.to_string()))
+                .collect();
+            lines.sort();
+            write_lines(dothg.join("bookmarks"), lines.iter())?;
+            revlogs.lock().expect("lock poisoned").write_fncache()
+        })
+        .boxify()
+}
+
+/// Write the changesets reachable from `heads` and return how many were written. The history is
+/// walked depth first, and a changeset is written as soon as its parents are.
+fn export_changesets(
+    logger: Logger,
+    blobrepo: Arc<BlobRepo>,
+    revlogs: Arc<Mutex<Revlogs>>,
+    heads: Vec<HgChangesetId>,
+) -> BoxFuture<usize, Error> {
+    // Changesets to write, with their parents once they are known
+    let stack: Vec<(HgChangesetId, Option<Vec<HgChangesetId>>)> =
+        heads.into_iter().rev().map(|csid| (csid, None)).collect();
+
+    future::loop_fn((stack, 0), move |(mut stack, cs_count): (Vec<_>, usize)| {
+        let (csid, parents) = match stack.pop() {
+            Some(next) => next,
+            None => return future::ok(Loop::Break(cs_count)).boxify(),
+        };
+        if revlogs
+            .lock()
+            .expect("lock poisoned")
+            .changelog
+            .contains(csid.as_nodehash())
+        {
+            return future::ok(Loop::Continue((stack, cs_count))).boxify();
+        }
+
+        let parents = match parents {
+            Some(parents) => future::ok(parents).boxify(),
+            None => blobrepo.get_changeset_parents(&csid),
+        };
+        parents
+            .and_then({
+                let logger = logger.clone();
+                let blobrepo = blobrepo.clone();
+                let revlogs = revlogs.clone();
+                move |parents| {
+                    let missing: Vec<_> = {
+                        let revlogs = revlogs.lock().expect("lock poisoned");
+                        parents
+                            .iter()
+                            .filter(|p| !revlogs.changelog.contains(p.as_nodehash()))
+                            .cloned()
+                            .collect()
+                    };
+                    if !missing.is_empty() {
+                        stack.push((csid, Some(parents)));
+                        stack.extend(missing.into_iter().rev().map(|p| (p, None)));
+                        return future::ok(Loop::Continue((stack, cs_count))).boxify();
+                    }
+
+                    export_changeset(blobrepo, revlogs, csid)
+                        .map(move |csid| {
+                            debug!(logger, "exported: {}", csid);
+                            let cs_count = cs_count + 1;
+                            if cs_count % 5000 == 0 {
+                                info!(logger, "exported commits # {}", cs_count);
+                            }
+                            Loop::Continue((stack, cs_count))
+                        })
+                        .boxify()
+                }
+            })
+            .boxify()
+    }).boxify()
+}
+
+/// Write the changeset `csid` and the manifests and files it introduces
+fn export_changeset(
+    blobrepo: Arc<BlobRepo>,
+    revlogs: Arc<Mutex<Revlogs>>,
+    csid: HgChangesetId,
+) -> BoxFuture<HgChangesetId, Error> {
+    // Changesets are written one after the other, so this is the index the changeset will have
+    let linkrev = RevIdx::from(revlogs.lock().expect("lock poisoned").changelog.len());
+
+    blobrepo
+        .get_changeset_by_changesetid(&csid)
+        .and_then(move |cs| {
+            let manifestid = *cs.manifestid();
+            let root = blobrepo.get_root_entry(&manifestid);
+            let trees_and_files =
+                export_entry(blobrepo.clone(), revlogs.clone(), RepoPath::RootPath, root, linkrev);
+            let flat_manifest =
+                export_flat_manifest(blobrepo, revlogs.clone(), manifestid, linkrev);
+
+            trees_and_files
+                .and_then(move |()| flat_manifest)
+                .and_then(move |()| {
+                    let revlogcs = RevlogChangeset::new_from_parts(
+                        cs.parents().clone(),
+                        cs.manifestid().clone(),
+                        cs.user().into(),
+                        cs.time().clone(),
+                        cs.extra().clone(),
+                        cs.files().into(),
+                        cs.comments().into(),
+                    );
+                    let mut text = Vec::new();
+                    serialize_cs(&revlogcs, &mut text)?;
+
+                    revlogs
+                        .lock()
+                        .expect("lock poisoned")
+                        .changelog
+                        .add_revision(
+                            csid.as_nodehash(),
+                            revlogcs.p1(),
+                            revlogcs.p2(),
+                            linkrev,
+                            &text,
+                        )
+                        .with_context(|_| format!("While writing changeset {}", csid))?;
+                    Ok(csid)
+                })
+        })
+        .boxify()
+}
+
+/// Write the flat manifest of the root tree manifest `manifestid` into `00manifest`
+///
+/// Along linear history the flat manifest is computed from the one written last, by applying the
+/// changes against the first parent, so that the whole tree only has to be read after merges and
+/// branch points.
+fn export_flat_manifest(
+    blobrepo: Arc<BlobRepo>,
+    revlogs: Arc<Mutex<Revlogs>>,
+    manifestid: HgManifestId,
+    linkrev: RevIdx,
+) -> BoxFuture<(), Error> {
+    let nodeid = manifestid.into_nodehash();
+    if revlogs
+        .lock()
+        .expect("lock poisoned")
+        .manifest
+        .contains(&nodeid)
+    {
+        return future::ok(()).boxify();
+    }
+
+    let root = blobrepo.get_root_entry(&manifestid);
+    root.get_parents()
+        .and_then(move |parents| {
+            let last = revlogs.lock().expect("lock poisoned").flat.take();
+            let files = match (last, parents.get_nodes().0) {
+                (Some((last, files)), Some(p1)) => if last == *p1 {
+                    update_flat_manifest(blobrepo, files, nodeid, *p1)
+                } else {
+                    list_flat_manifest(root)
+                },
+                _ => list_flat_manifest(root),
+            };
+
+            files.and_then(move |files| {
+                let content = ManifestContent { files };
+                let mut text = Vec::new();
+                content.generate(&mut text)?;
+                let (p1, p2) = parents.get_nodes();
+
+                let mut revlogs = revlogs.lock().expect("lock poisoned");
+                revlogs
+                    .manifest
+                    .add_revision(&nodeid, p1, p2, linkrev, &text)
+                    .context("While writing 00manifest")?;
+                revlogs.flat = Some((nodeid, content.files));
+                Ok(())
+            })
+        })
+        .boxify()
+}
+
+/// The flat manifest of the tree manifest `to`, given the one of `from`
+fn update_flat_manifest(
+    blobrepo: Arc<BlobRepo>,
+    files: FlatManifest,
+    to: HgNodeHash,
+    from: HgNodeHash,
+) -> BoxFuture<FlatManifest, Error> {
+    blobrepo
+        .get_manifest_by_nodeid(&to)
+        .join(blobrepo.get_manifest_by_nodeid(&from))
+        .map(|(to, from)| changed_entry_stream(&to, &from, None))
+        .flatten_stream()
+        .fold(files, |mut files, changed| {
+            let path = match changed.get_full_path() {
+                Some(path) => path,
+                None => return Ok(files),
+            };
+            match changed.status {
+                EntryStatus::Added(entry)
+                | EntryStatus::Modified {
+                    to_entry: entry, ..
+                } => if let Type::File(_) = entry.get_type() {
+                    files.insert(path, Details::new(*entry.get_hash(), entry.get_type()));
+                },
+                EntryStatus::Deleted(entry) => if let Type::File(_) = entry.get_type() {
+                    files.remove(&path);
+                },
+            }
+            Ok::<_, Error>(files)
+        })
+        .boxify()
+}
+
+/// The flat manifest of the tree manifest `root`
+fn list_flat_manifest(root: Box<Entry + Sync>) -> BoxFuture<FlatManifest, Error> {
+    recursive_entry_stream(None, root)
+        .fold(FlatManifest::new(), |mut files, (dirname, entry)| {
+            if let Type::File(_) = entry.get_type() {
+                let path = MPath::join_element_opt(dirname.as_ref(), entry.get_name())
+                    .ok_or_else(|| format_err!("Entry in {:?} has no name", dirname))?;
+                files.insert(path, Details::new(*entry.get_hash(), entry.get_type()));
+            }
+            Ok::<_, Error>(files)
+        })
+        .boxify()
+}
+
+/// Write `entry` and, if it is a tree, the entries below it that are not written yet
+fn export_entry(
+    blobrepo: Arc<BlobRepo>,
+    revlogs: Arc<Mutex<Revlogs>>,
+    path: RepoPath,
+    entry: Box<Entry + Sync>,
+    linkrev: RevIdx,
+) -> BoxFuture<(), Error> {
+    let nodeid = entry.get_hash().into_nodehash();
+    if revlogs
+        .lock()
+        .expect("lock poisoned")
+        .contains(&path, &nodeid)
+    {
+        // Everything below an entry that was written already was written along with it
+        return future::ok(()).boxify();
+    }
+
+    let subentries = match entry.get_type() {
+        Type::File(_) => future::ok(()).boxify(),
+        Type::Tree => entry
+            .get_content()
+            .and_then({
+                let blobrepo = blobrepo.clone();
+                let revlogs = revlogs.clone();
+                let path = path.clone();
+                move |content| {
+                    let manifest = match content {
+                        Content::Tree(manifest) => manifest,
+                        _ => bail_msg!("Content of {:?} is not a tree", path),
+                    };
+                    let mut subentries = vec![];
+                    for subentry in manifest.list() {
+                        let subpath =
+                            MPath::join_element_opt(path.mpath(), subentry.get_name())
+                                .ok_or_else(|| format_err!("Entry in {:?} has no name", path))?;
+                        let subpath = match subentry.get_type() {
+                            Type::Tree => RepoPath::DirectoryPath(subpath),
+                            Type::File(_) => RepoPath::FilePath(subpath),
+                        };
+                        subentries.push(export_entry(
+                            blobrepo.clone(),
+                            revlogs.clone(),
+                            subpath,
+                            subentry,
+                            linkrev,
+                        ));
+                    }
+                    Ok(future::join_all(subentries).map(|_| ()))
+                }
+            })
+            .flatten()
+            .boxify(),
+    };
+
+    let streamed = match entry.get_type() {
+        Type::File(_) => blobrepo
+            .get_file_size(&nodeid)
+            .map(|size| size > STREAM_THRESHOLD)
+            .boxify(),
+        Type::Tree => future::ok(false).boxify(),
+    };
+
+    streamed
+        .join3(
+            entry.get_parents(),
+            blobrepo.get_linknode(path.clone(), &nodeid),
+        )
+        .and_then(move |(streamed, parents, linknode)| {
+            if !streamed {
+                return entry
+                    .get_raw_content()
+                    .and_then(move |blob| {
+                        let text = blob.as_slice().ok_or_else(|| {
+                            format_err!("Content of {:?} {} missing", path, nodeid)
+                        })?;
+                        revlogs.lock().expect("lock poisoned").add_entry(
+                            &path,
+                            &nodeid,
+                            parents.get_nodes(),
+                            &linknode,
+                            linkrev,
+                            text,
+                        )
+                    })
+                    .boxify();
+            }
+
+            let revision = try_boxfuture!(revlogs.lock().expect("lock poisoned").start_entry(
+                &path,
+                &nodeid,
+                parents.get_nodes(),
+                &linknode,
+                linkrev,
+            ));
+            blobrepo
+                .get_raw_hg_content_stream(&nodeid)
+                .fold(revision, |mut revision, bytes| {
+                    revision.write(&bytes).map(|()| revision)
+                })
+                .and_then(move |revision| {
+                    revlogs
+                        .lock()
+                        .expect("lock poisoned")
+                        .finish_entry(&path, revision)
+                })
+                .boxify()
+        })
+        .and_then(move |()| subentries)
+        .boxify()
+}
+
+fn write_lines<P, I, L>(path: P, lines: I) -> Result<()>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = L>,
+    L: AsRef<str>,
+{
+    let path = path.as_ref();
+    let mut file = File::create(path).with_context(|_| format!("Can't create {:?}", path))?;
+    for line in lines {
+        writeln!(file, "{}", line.as_ref())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,152 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+#![deny(warnings)]
+
+extern crate blobrepo;
+extern crate clap;
+#[macro_use]
+extern crate failure_ext as failure;
+extern crate futures;
+#[macro_use]
+extern crate futures_ext;
+extern crate mercurial;
+extern crate mercurial_types;
+#[macro_use]
+extern crate slog;
+extern crate slog_glog_fmt;
+extern crate tokio_core;
+
+mod export;
+
+use std::path::Path;
+use std::sync::Arc;
+
+use clap::{App, Arg, ArgMatches};
+use slog::{Drain, Level, Logger};
+use slog_glog_fmt::default_drain as glog_drain;
+use tokio_core::reactor::Core;
+
+use blobrepo::BlobRepo;
+use mercurial_types::RepositoryId;
+
+fn setup_app<'a, 'b>() -> App<'a, 'b> {
+    App::new("blob to revlog exporter")
+        .version("0.0.0")
+        .about("Export a repo from Mononoke blobstore into a new revlog-backed Mercurial repo.")
+        .args_from_usage(
+            r#"
+            <OUTPUT>                        'directory of the new revlog repo'
+            --debug                         'print debug logs'
+            --repo_id <repo_id>             'ID of the exported repo'
+            --manifold-bucket [BUCKET]      'manifold bucket'
+            --manifold-prefix [PREFIX]      'manifold prefix Default: new_blobimport_test'
+            --db-address [address]          'address of a db. Used only for manifold blobstore'
+            --blobstore-cache-size [SIZE]   'size of the blobstore cache'
+            --changesets-cache-size [SIZE]  'size of the changesets cache'
+            --filenodes-cache-size [SIZE]   'size of the filenodes cache'
+            --io-thread-num [NUM]           'num of the io threads to use'
+            --max-concurrent-request-per-io-thread [NUM] 'max requests per io thread'
+            [INPUT]                         'Blobstore input'
+        "#,
+        )
+        .arg(
+            Arg::with_name("blobstore")
+                .long("blobstore")
+                .takes_value(true)
+                .possible_values(&["files", "rocksdb", "manifold"])
+                .required(true)
+                .help("blobstore type"),
+        )
+}
+
+fn get_usize<'a>(matches: &ArgMatches<'a>, key: &str, default: usize) -> usize {
+    matches
+        .value_of(key)
+        .map(|val| {
+            val.parse::<usize>()
+                .expect(&format!("{} must be integer", key))
+        })
+        .unwrap_or(default)
+}
+
+fn open_blobrepo<'a>(logger: &Logger, matches: &ArgMatches<'a>) -> BlobRepo {
+    let repo_id = RepositoryId::new(matches.value_of("repo_id").unwrap().parse().unwrap());
+
+    match matches.value_of("blobstore").unwrap() {
+        "files" => {
+            let input = matches.value_of("INPUT").expect("input is not specified");
+            let input = Path::new(input)
+                .canonicalize()
+                .expect("Failed to read input path");
+
+            BlobRepo::new_files(
+                logger.new(o!["BlobRepo:Files" => input.to_string_lossy().into_owned()]),
+                &input,
+                repo_id,
+            ).expect("failed to open file blobrepo")
+        }
+        "rocksdb" => {
+            let input = matches.value_of("INPUT").expect("input is not specified");
+            let input = Path::new(input)
+                .canonicalize()
+                .expect("Failed to read input path");
+
+            BlobRepo::new_rocksdb(
+                logger.new(o!["BlobRepo:Rocksdb" => input.to_string_lossy().into_owned()]),
+                &input,
+                repo_id,
+            ).expect("failed to open rocksdb blobrepo")
+        }
+        "manifold" => {
+            let manifold_bucket = matches
+                .value_of("manifold-bucket")
+                .expect("manifold bucket is not specified");
+
+            BlobRepo::new_manifold(
+                logger.new(o!["BlobRepo:TestManifold" => manifold_bucket.to_owned()]),
+                manifold_bucket,
+                matches
+                    .value_of("manifold-prefix")
+                    .unwrap_or("new_blobimport_test"),
+                repo_id,
+                matches
+                    .value_of("db-address")
+                    .expect("--db-address is not specified"),
+                get_usize(&matches, "blobstore-cache-size", 100_000_000),
+                get_usize(&matches, "changesets-cache-size", 100_000_000),
+                get_usize(&matches, "filenodes-cache-size", 100_000_000),
+                get_usize(&matches, "io-thread-num", 5),
+                get_usize(&matches, "max-concurrent-request-per-io-thread", 5),
+            ).expect("failed to open manifold blobrepo")
+        }
+        bad => panic!("unexpected blobstore type: {}", bad),
+    }
+}
+
+fn main() {
+    let matches = setup_app().get_matches();
+
+    let output = matches.value_of("OUTPUT").expect("output is not specified");
+
+    let mut core = Core::new().expect("cannot create tokio core");
+
+    let logger = {
+        let level = if matches.is_present("debug") {
+            Level::Debug
+        } else {
+            Level::Info
+        };
+
+        let drain = glog_drain().filter_level(level).fuse();
+        slog::Logger::root(drain, o![])
+    };
+
+    let blobrepo = Arc::new(open_blobrepo(&logger, &matches));
+
+    core.run(export::export(logger, blobrepo, Path::new(output)))
+        .expect("main stream failed");
+}
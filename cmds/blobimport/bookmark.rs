@@ -4,74 +4,143 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2 or any later version.
 
+use std::collections::HashMap;
+use std::env;
 use std::sync::Arc;
 
 use ascii::AsciiString;
 use failure::prelude::*;
 use futures::prelude::*;
+use futures::stream;
 use futures_ext::{BoxFuture, FutureExt};
 use slog::Logger;
 
 use blobrepo::BlobRepo;
-use bookmarks::Bookmark;
+use bookmarks::{Bookmark, BookmarkUpdateReason};
 use mercurial::RevlogRepo;
+use mercurial_types::HgChangesetId;
 
-pub fn upload_bookmarks(
+enum BookmarkChange {
+    Set(Bookmark, HgChangesetId),
+    Delete(Bookmark),
+}
+
+/// Makes the bookmarks of the blobrepo match the ones of the revlog repo. Bookmarks that point
+/// to changesets that were not imported yet are left untouched. Bookmarks that are missing from
+/// the revlog repo are only deleted if `delete` is set, otherwise they are just logged. Every
+/// change is logged.
+pub fn sync_bookmarks(
     logger: &Logger,
-    revlogrepo: RevlogRepo,
+    revlogrepo: &RevlogRepo,
     blobrepo: Arc<BlobRepo>,
+    delete: bool,
 ) -> BoxFuture<(), Error> {
     let logger = logger.clone();
     let bookmarks = Arc::new(try_boxfuture!(revlogrepo.get_bookmarks()));
+    // The update logs of the bookmarks record who ran the import
+    let session = env::var("USER").unwrap_or_else(|_| "unknown".to_string());
 
-    (*bookmarks).keys().map({
-        let bookmarks = bookmarks.clone();
-        let blobrepo = blobrepo.clone();
-        move |key| {
+    let revlog_bookmarks = (*bookmarks)
+        .keys()
+        .and_then({
+            let bookmarks = bookmarks.clone();
+            move |key| {
+                (*bookmarks).get(&key).and_then(move |v| {
+                    let (cs_id, _) =
+                        v.ok_or_else(|| format_err!("Bookmark value missing: {:?}", key))?;
+                    let key = Bookmark::new_ascii(AsciiString::from_ascii(key)?);
+                    Ok((key, cs_id))
+                })
+            }
+        })
+        .collect();
+
+    revlog_bookmarks
+        .join(blobrepo.get_bookmarks().collect())
+        .map({
             let blobrepo = blobrepo.clone();
-            (*bookmarks).get(&key).and_then(move |v| {
-                v.ok_or_else(|| format_err!("Bookmark value missing: {:?}", key))
-                    .into_future()
-                    .and_then(move |(cs_id, _)| {
-                        blobrepo.changeset_exists(&cs_id)
-                            .map(move |exists| (key, cs_id, exists))
+            let logger = logger.clone();
+            move |(revlog_bookmarks, blob_bookmarks)| {
+                let mut blob_bookmarks: HashMap<_, _> = blob_bookmarks.into_iter().collect();
+                let moved: Vec<_> = revlog_bookmarks
+                    .into_iter()
+                    .filter_map(|(key, cs_id)| match blob_bookmarks.remove(&key) {
+                        Some(old_cs_id) if old_cs_id == cs_id => None,
+                        old_cs_id => Some((key, cs_id, old_cs_id)),
+                    })
+                    .collect();
+                let deleted: Vec<_> = blob_bookmarks
+                    .into_iter()
+                    .filter_map(|(key, cs_id)| {
+                        if delete {
+                            info!(logger, "deleting bookmark {:?}, was {:?}", key, cs_id);
+                            Some(BookmarkChange::Delete(key))
+                        } else {
+                            warn!(
+                                logger,
+                                "bookmark {:?} is not in the revlog repo, but is not deleted \
+                                 without --delete-bookmarks",
+                                key,
+                            );
+                            None
+                        }
                     })
-            })
-        }
-    })
-    .buffer_unordered(100)
-    .filter_map({
-        let logger = logger.clone();
-        move |(key, cs_id, exists)| {
-            if exists {
-                Some((key, cs_id))
-            } else {
-                info!(
-                    logger,
-                    "did not update bookmark {:?}, because cs {:?} was not imported yet",
-                    key,
-                    cs_id,
-                );
-                None
-           }
-        }
-    })
-    .chunks(100) // send 100 bookmarks in a single transaction
-    .and_then({
-        let blobrepo = blobrepo.clone();
-        move |vec| {
-            let count = vec.len();
-            let mut transaction = blobrepo.update_bookmark_transaction();
+                    .collect();
 
-            for (key, value) in vec {
-                let key = Bookmark::new_ascii(try_boxfuture!(AsciiString::from_ascii(key)));
-                try_boxfuture!(transaction.force_set(&key, &value))
+                stream::iter_ok(moved)
+                    .map(move |(key, cs_id, old_cs_id)| {
+                        blobrepo
+                            .changeset_exists(&cs_id)
+                            .map(move |exists| (key, cs_id, old_cs_id, exists))
+                    })
+                    .buffer_unordered(100)
+                    .filter_map(move |(key, cs_id, old_cs_id, exists)| {
+                        if exists {
+                            info!(
+                                logger,
+                                "setting bookmark {:?} to {:?}, was {:?}", key, cs_id, old_cs_id
+                            );
+                            Some(BookmarkChange::Set(key, cs_id))
+                        } else {
+                            info!(
+                                logger,
+                                "did not update bookmark {:?}, because cs {:?} was not \
+                                 imported yet",
+                                key,
+                                cs_id,
+                            );
+                            None
+                        }
+                    })
+                    .chain(stream::iter_ok(deleted))
             }
+        })
+        .flatten_stream()
+        .chunks(100) // send 100 bookmarks in a single transaction
+        .and_then({
+            let blobrepo = blobrepo.clone();
+            move |vec| {
+                let count = vec.len();
+                let mut transaction = blobrepo
+                    .update_bookmark_transaction(BookmarkUpdateReason::Blobimport, &session);
 
-            transaction.commit().map(move |()| count).boxify()
-        }
-    }).for_each(move |count| {
-        info!(logger, "uploaded chunk of {:?} bookmarks", count);
-        Ok(())
-    }).boxify()
+                for change in vec {
+                    match change {
+                        BookmarkChange::Set(key, value) => {
+                            try_boxfuture!(transaction.force_set(&key, &value))
+                        }
+                        BookmarkChange::Delete(key) => {
+                            try_boxfuture!(transaction.force_delete(&key))
+                        }
+                    }
+                }
+
+                transaction.commit().map(move |()| count).boxify()
+            }
+        })
+        .for_each(move |count| {
+            info!(logger, "synced chunk of {:?} bookmarks", count);
+            Ok(())
+        })
+        .boxify()
 }
@@ -22,10 +22,11 @@ use scuba_ext::ScubaSampleBuilder;
 use blobrepo::{BlobChangeset, BlobRepo, ChangesetHandle, CreateChangeset, HgBlobEntry,
                UploadHgFileContents, UploadHgFileEntry, UploadHgNodeHash, UploadHgTreeEntry};
 use mercurial::{manifest, RevlogChangeset, RevlogEntry, RevlogRepo};
+use mercurial::revlog::RevIdx;
 use mercurial_types::{HgBlob, HgChangesetId, HgManifestId, HgNodeHash, MPath, RepoPath, Type,
                       NULL_HASH};
 
-use super::get_usize;
+use super::{get_usize, is_incremental};
 
 struct ParseChangeset {
     revlogcs: BoxFuture<SharedItem<RevlogChangeset>, Error>,
@@ -206,16 +207,50 @@ fn upload_entry(
         .boxify()
 }
 
+// Finds the revision following the last imported changeset by walking back from the tip
+fn first_not_imported(
+    revlogrepo: &RevlogRepo,
+    blobrepo: &Arc<BlobRepo>,
+) -> BoxFuture<RevIdx, Error> {
+    let blobrepo = blobrepo.clone();
+    revlogrepo
+        .changesets_reversed()
+        .and_then(move |(idx, csid)| {
+            blobrepo
+                .changeset_exists(&HgChangesetId::new(csid))
+                .map(move |exists| (idx, exists))
+        })
+        .skip_while(|&(_, exists)| Ok(!exists))
+        .into_future()
+        .map(|(imported, _)| match imported {
+            Some((idx, _)) => idx.succ(),
+            None => RevIdx::zero(),
+        })
+        .map_err(|(err, _)| err)
+        .boxify()
+}
+
 pub fn upload_changesets<'a>(
     matches: &ArgMatches<'a>,
     revlogrepo: RevlogRepo,
     blobrepo: Arc<BlobRepo>,
     cpupool_size: usize,
 ) -> BoxStream<BoxFuture<SharedItem<BlobChangeset>, Error>, Error> {
+    let incremental = is_incremental(matches);
     let changesets = if let Some(hash) = matches.value_of("changeset") {
         future::result(HgNodeHash::from_str(hash))
             .into_stream()
             .boxify()
+    } else if incremental && !matches.is_present("skip") {
+        // Start after the last imported changeset, so that every incremental run only looks at
+        // the new changesets instead of the whole history
+        first_not_imported(&revlogrepo, &blobrepo)
+            .map({
+                let revlogrepo = revlogrepo.clone();
+                move |idx| revlogrepo.changesets_from(idx)
+            })
+            .flatten_stream()
+            .boxify()
     } else {
         revlogrepo.changesets().boxify()
     };
@@ -234,7 +269,28 @@ pub fn upload_changesets<'a>(
         changesets.take(limit as u64).boxify()
     };
 
-    let is_import_from_beggining = !matches.is_present("changeset") && !matches.is_present("skip");
+    let changesets = if !incremental {
+        changesets
+    } else {
+        // A previous run might have been interrupted after importing some of the changesets
+        // following the last imported one. The changesets come in topological order, which the
+        // filtering keeps
+        changesets
+            .map({
+                let blobrepo = blobrepo.clone();
+                move |csid| {
+                    blobrepo
+                        .changeset_exists(&HgChangesetId::new(csid))
+                        .map(move |exists| (csid, exists))
+                }
+            })
+            .buffered(100)
+            .filter_map(|(csid, exists)| if exists { None } else { Some(csid) })
+            .boxify()
+    };
+
+    let is_import_from_beggining =
+        !incremental && !matches.is_present("changeset") && !matches.is_present("skip");
     let mut parent_changeset_handles: HashMap<HgNodeHash, ChangesetHandle> = HashMap::new();
 
     changesets
@@ -327,6 +383,7 @@ pub fn upload_changesets<'a>(
                 extra: cs.extra().clone(),
                 comments: String::from_utf8(Vec::from(cs.comments()))
                     .expect(&format!("non-utf8 comments for {}", csid)),
+                bonsai: None,
             };
             let cshandle = create_changeset.create(&blobrepo, ScubaSampleBuilder::with_discard());
             parent_changeset_handles.insert(csid, cshandle.clone());
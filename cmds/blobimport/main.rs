@@ -23,6 +23,7 @@ extern crate scuba_ext;
 #[macro_use]
 extern crate slog;
 extern crate slog_glog_fmt;
+extern crate streaming_clone;
 extern crate tokio_core;
 
 mod bookmark;
@@ -31,11 +32,13 @@ mod changeset;
 use std::fs;
 use std::path::Path;
 use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
 use clap::{App, Arg, ArgMatches};
-use failure::{err_msg, Result, ResultExt};
+use failure::{err_msg, Error, Result, ResultExt};
 use futures::{future, Future, Stream};
-use futures_ext::FutureExt;
+use futures_ext::{BoxFuture, FutureExt};
 use slog::{Drain, Level, Logger};
 use slog_glog_fmt::default_drain as glog_drain;
 use tokio_core::reactor::Core;
@@ -43,6 +46,7 @@ use tokio_core::reactor::Core;
 use blobrepo::BlobRepo;
 use mercurial::RevlogRepo;
 use mercurial_types::RepositoryId;
+use streaming_clone::StreamingClone;
 
 fn setup_app<'a, 'b>() -> App<'a, 'b> {
     App::new("revlog to blob importer")
@@ -64,6 +68,9 @@ fn setup_app<'a, 'b>() -> App<'a, 'b> {
             --parsing-cpupool-size [NUM]    'size of cpupool for parsing revlogs'
             --changeset [HASH]              'if provided, the only changeset to be imported'
             --no-bookmark                   'if provided won't update bookmarks'
+            --incremental                   'import only the changesets that are not imported yet'
+            --delete-bookmarks              'delete the bookmarks that are not in the revlog repo'
+            --streaming-clone               'also snapshot the revlogs for streaming clones'
             [OUTPUT]                        'Blobstore output'
         "#,
         )
@@ -84,6 +91,16 @@ fn setup_app<'a, 'b>() -> App<'a, 'b> {
                 "--commits-limit [LIMIT] 'import only LIMIT first commits from revlog repo'",
             ).conflicts_with("changeset"),
         )
+        .arg(
+            Arg::from_usage(
+                "--tail [SECONDS] 'keep importing the new changesets and bookmarks every SECONDS'",
+            ).conflicts_with_all(&["changeset", "skip", "commits-limit"]),
+        )
+}
+
+/// Whether the changesets that are already in the blobrepo should be skipped
+fn is_incremental<'a>(matches: &ArgMatches<'a>) -> bool {
+    matches.is_present("incremental") || matches.is_present("tail")
 }
 
 fn get_usize<'a>(matches: &ArgMatches<'a>, key: &str, default: usize) -> usize {
@@ -96,7 +113,7 @@ fn get_usize<'a>(matches: &ArgMatches<'a>, key: &str, default: usize) -> usize {
         .unwrap_or(default)
 }
 
-fn setup_local_state(output: &Path) -> Result<()> {
+fn setup_local_state(output: &Path, allow_existing: bool) -> Result<()> {
     if !output.is_dir() {
         bail_msg!("{:?} does not exist or is not a directory", output);
     }
@@ -109,7 +126,7 @@ fn setup_local_state(output: &Path) -> Result<()> {
             }
 
             let content: Vec<_> = subdir.read_dir()?.collect();
-            if !content.is_empty() {
+            if !content.is_empty() && !allow_existing {
                 bail_msg!(
                     "{:?} already exists and is not empty: {:?}",
                     subdir,
@@ -133,7 +150,8 @@ fn open_blobrepo<'a>(logger: &Logger, matches: &ArgMatches<'a>) -> BlobRepo {
             let output = Path::new(output)
                 .canonicalize()
                 .expect("Failed to read output path");
-            setup_local_state(&output).expect("Setting up file blobrepo failed");
+            setup_local_state(&output, is_incremental(matches))
+                .expect("Setting up file blobrepo failed");
 
             BlobRepo::new_files(
                 logger.new(o!["BlobRepo:Files" => output.to_string_lossy().into_owned()]),
@@ -146,7 +164,8 @@ fn open_blobrepo<'a>(logger: &Logger, matches: &ArgMatches<'a>) -> BlobRepo {
             let output = Path::new(output)
                 .canonicalize()
                 .expect("Failed to read output path");
-            setup_local_state(&output).expect("Setting up rocksdb blobrepo failed");
+            setup_local_state(&output, is_incremental(matches))
+                .expect("Setting up rocksdb blobrepo failed");
 
             BlobRepo::new_rocksdb(
                 logger.new(o!["BlobRepo:Rocksdb" => output.to_string_lossy().into_owned()]),
@@ -180,34 +199,19 @@ fn open_blobrepo<'a>(logger: &Logger, matches: &ArgMatches<'a>) -> BlobRepo {
     }
 }
 
-fn main() {
-    let matches = setup_app().get_matches();
-
-    let revlogrepo = {
-        let input = matches.value_of("INPUT").expect("input is not specified");
-        RevlogRepo::open(input).expect("cannot open revlogrepo")
-    };
-
-    let mut core = Core::new().expect("cannot create tokio core");
-
-    let logger = {
-        let level = if matches.is_present("debug") {
-            Level::Debug
-        } else {
-            Level::Info
-        };
-
-        let drain = glog_drain().filter_level(level).fuse();
-        slog::Logger::root(drain, o![])
-    };
-
-    let blobrepo = Arc::new(open_blobrepo(&logger, &matches));
-
+/// Imports the changesets of the revlog repo, then syncs its bookmarks and, if asked to, updates
+/// the streaming clone snapshot
+fn import<'a>(
+    logger: &Logger,
+    matches: &ArgMatches<'a>,
+    revlogrepo: RevlogRepo,
+    blobrepo: Arc<BlobRepo>,
+) -> BoxFuture<(), Error> {
     let upload_changesets = changeset::upload_changesets(
-        &matches,
+        matches,
         revlogrepo.clone(),
         blobrepo.clone(),
-        get_usize(&matches, "parsing-cpupool-size", 8),
+        get_usize(matches, "parsing-cpupool-size", 8),
     ).buffer_unordered(100)
         .map({
             let mut cs_count = 0;
@@ -221,16 +225,19 @@ fn main() {
                 ()
             }
         })
-        .map_err(|err| {
-            error!(logger, "failed to blobimport: {}", err);
+        .map_err({
+            let logger = logger.clone();
+            move |err| {
+                error!(logger, "failed to blobimport: {}", err);
 
-            for cause in err.causes() {
-                info!(logger, "cause: {}", cause);
-            }
-            info!(logger, "root cause: {:?}", err.root_cause());
+                for cause in err.causes() {
+                    info!(logger, "cause: {}", cause);
+                }
+                info!(logger, "root cause: {:?}", err.root_cause());
 
-            let msg = format!("failed to blobimport: {}", err);
-            err_msg(msg)
+                let msg = format!("failed to blobimport: {}", err);
+                err_msg(msg)
+            }
         })
         .for_each(|()| Ok(()));
 
@@ -244,11 +251,76 @@ fn main() {
             Ok(())
         }).boxify()
     } else {
-        bookmark::upload_bookmarks(&logger, revlogrepo, blobrepo)
+        bookmark::sync_bookmarks(
+            logger,
+            &revlogrepo,
+            blobrepo.clone(),
+            matches.is_present("delete-bookmarks"),
+        )
+    };
+
+    let update_snapshot = if matches.is_present("streaming-clone") {
+        let input = matches.value_of("INPUT").expect("input is not specified");
+        let logger = logger.clone();
+        StreamingClone::new(Arc::new(blobrepo.get_blobstore()))
+            .update_snapshot(input)
+            .map(move |snapshot| {
+                info!(
+                    logger,
+                    "updated the streaming clone snapshot, {} bytes",
+                    snapshot.bytecount()
+                )
+            })
+            .boxify()
+    } else {
+        future::ok(()).boxify()
     };
 
-    core.run(upload_changesets.and_then(|()| {
-        info!(logger, "finished uploading changesets, now doing bookmarks");
-        upload_bookmarks
-    })).expect("main stream failed");
+    let logger = logger.clone();
+    upload_changesets
+        .and_then(move |()| {
+            info!(logger, "finished uploading changesets, now doing bookmarks");
+            upload_bookmarks
+        })
+        .and_then(move |()| update_snapshot)
+        .boxify()
+}
+
+fn main() {
+    let matches = setup_app().get_matches();
+
+    let input = matches.value_of("INPUT").expect("input is not specified");
+    let revlogrepo = RevlogRepo::open(input).expect("cannot open revlogrepo");
+
+    let mut core = Core::new().expect("cannot create tokio core");
+
+    let logger = {
+        let level = if matches.is_present("debug") {
+            Level::Debug
+        } else {
+            Level::Info
+        };
+
+        let drain = glog_drain().filter_level(level).fuse();
+        slog::Logger::root(drain, o![])
+    };
+
+    let blobrepo = Arc::new(open_blobrepo(&logger, &matches));
+
+    core.run(import(&logger, &matches, revlogrepo, blobrepo.clone()))
+        .expect("main stream failed");
+
+    if let Some(interval) = matches.value_of("tail") {
+        let interval = Duration::from_secs(interval.parse().expect("--tail must be integer"));
+        loop {
+            thread::sleep(interval);
+            // The revlog repo is opened again to see the changesets added since the last import
+            let imported = RevlogRepo::open(input).and_then(|revlogrepo| {
+                core.run(import(&logger, &matches, revlogrepo, blobrepo.clone()))
+            });
+            if let Err(err) = imported {
+                error!(logger, "failed to import new changesets, retrying later: {}", err);
+            }
+        }
+    }
 }
@@ -0,0 +1,530 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Conversion of the commits of a git repo into bonsai changesets
+//!
+//! Every commit becomes a bonsai changeset with the same parents, and the Mercurial changeset
+//! derived from it is created right away, so that the imported repo can be served to Mercurial
+//! clients. The bonsai changeset of every imported commit is recorded in the git mapping, which
+//! is also how the parents of a commit are found and how a commit that was imported already is
+//! recognized on the next import.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use failure::{Error, FutureFailureErrorExt, Result, ResultExt};
+use futures::{future, stream, Future, IntoFuture, Stream};
+use futures_ext::{BoxFuture, FutureExt, StreamExt};
+use git2::{BranchType, Delta, Diff, DiffFindOptions, ErrorCode, Oid, Repository, Sort, Time};
+use scuba_ext::ScubaSampleBuilder;
+use slog::Logger;
+
+use blobrepo::{BlobChangeset, BlobRepo, ChangesetHandle, CreateChangeset, HgBlobEntry};
+use bonsai_git_mapping::{BonsaiGitMapping, BonsaiGitMappingEntry, GitSha1};
+use bookmarks::{Bookmark, BookmarkUpdateReason};
+use mercurial_types::{Changeset, Entry, HgChangesetId, HgManifestId, MPath, RepoPath,
+                      RepositoryId, Type};
+use mercurial_types::manifest_utils::new_entry_intersection_stream;
+use mononoke_types::{BlobstoreValue, BonsaiChangeset, ChangesetId, ContentId, DateTime,
+                     FileChange, FileContents, FileType};
+use mononoke_types::bonsai_changeset::BonsaiChangesetMut;
+
+// File modes of the entries of git trees
+const MODE_REGULAR: i32 = 0o100644;
+const MODE_EXECUTABLE: i32 = 0o100755;
+const MODE_SYMLINK: i32 = 0o120000;
+const MODE_GITLINK: i32 = 0o160000;
+
+/// A commit read from the git repo, with the contents of the files it changes
+struct GitCommit {
+    sha1: GitSha1,
+    parents: Vec<GitSha1>,
+    author: String,
+    author_date: DateTime,
+    committer: String,
+    committer_date: DateTime,
+    message: String,
+    changes: BTreeMap<MPath, Option<GitFileChange>>,
+}
+
+struct GitFileChange {
+    content: Bytes,
+    file_type: FileType,
+    // Path the file was copied or renamed from in the first parent
+    copy_from: Option<MPath>,
+}
+
+/// Import the commits reachable from the branches of `gitrepo` that are not imported yet, and
+/// point a bookmark with the name of every branch to the branch's commit if `set_bookmarks`.
+pub fn import(
+    logger: Logger,
+    gitrepo: Repository,
+    blobrepo: Arc<BlobRepo>,
+    mapping: Arc<BonsaiGitMapping>,
+    repo_id: RepositoryId,
+    set_bookmarks: bool,
+) -> BoxFuture<(), Error> {
+    let (commits, branches) = try_boxfuture!(list_commits(&gitrepo));
+    let gitrepo = Arc::new(Mutex::new(gitrepo));
+
+    stream::iter_ok(commits)
+        .map({
+            let mapping = mapping.clone();
+            move |oid| {
+                let sha1 = GitSha1::from_bytes(oid.as_bytes());
+                sha1.into_future()
+                    .and_then({
+                        let mapping = mapping.clone();
+                        move |sha1| mapping.get_bonsai_from_git_sha1(repo_id, sha1)
+                    })
+                    .map(move |imported| (oid, imported.is_some()))
+            }
+        })
+        .buffered(100)
+        .filter_map(|(oid, imported)| if imported { None } else { Some(oid) })
+        // The commits are imported one after the other, as every commit needs the changesets of
+        // its parents
+        .and_then({
+            let blobrepo = blobrepo.clone();
+            let mapping = mapping.clone();
+            move |oid| {
+                let commit = try_boxfuture!(read_commit(
+                    &gitrepo.lock().expect("lock poisoned"),
+                    oid
+                ));
+                import_commit(blobrepo.clone(), mapping.clone(), repo_id, commit)
+            }
+        })
+        .fold(0, {
+            let logger = logger.clone();
+            move |cs_count, (sha1, hg_cs_id)| {
+                debug!(logger, "imported: {} as {}", sha1, hg_cs_id);
+                let cs_count = cs_count + 1;
+                if cs_count % 5000 == 0 {
+                    info!(logger, "imported commits # {}", cs_count);
+                }
+                Ok::<_, Error>(cs_count)
+            }
+        })
+        .and_then(move |cs_count| {
+            if set_bookmarks {
+                info!(logger, "imported {} commits, now doing bookmarks", cs_count);
+                sync_bookmarks(blobrepo, mapping, repo_id, branches)
+            } else {
+                info!(logger, "imported {} commits", cs_count);
+                future::ok(()).boxify()
+            }
+        })
+        .boxify()
+}
+
+/// Return the commits reachable from the local branches, parents before children, and the
+/// commits the branches point to.
+fn list_commits(gitrepo: &Repository) -> Result<(Vec<Oid>, Vec<(String, Oid)>)> {
+    let mut branches = Vec::new();
+    for branch in gitrepo.branches(Some(BranchType::Local))? {
+        let (branch, _) = branch?;
+        let name = branch
+            .name()?
+            .ok_or_else(|| format_err!("branch name is not utf-8"))?
+            .to_string();
+        let oid = branch
+            .get()
+            .target()
+            .ok_or_else(|| format_err!("branch {} is not a direct reference", name))?;
+        branches.push((name, oid));
+    }
+
+    let mut revwalk = gitrepo.revwalk()?;
+    revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE);
+    for &(_, oid) in &branches {
+        revwalk.push(oid)?;
+    }
+    let mut commits = Vec::new();
+    for oid in revwalk {
+        commits.push(oid?);
+    }
+    Ok((commits, branches))
+}
+
+fn read_commit(gitrepo: &Repository, oid: Oid) -> Result<GitCommit> {
+    let commit = gitrepo
+        .find_commit(oid)
+        .with_context(|_| format!("While reading commit {}", oid))?;
+    let tree = commit.tree()?;
+
+    // Every path that differs from one of the parents is recorded, which is what makes the
+    // changes of a merge complete
+    let mut changed = BTreeSet::new();
+    let mut copies = HashMap::new();
+    if commit.parent_count() == 0 {
+        let diff = gitrepo.diff_tree_to_tree(None, Some(&tree), None)?;
+        collect_changed_paths(&diff, &mut changed, None)?;
+    }
+    for (idx, parent) in commit.parents().enumerate() {
+        let mut diff = gitrepo.diff_tree_to_tree(Some(&parent.tree()?), Some(&tree), None)?;
+        if idx == 0 {
+            // Bonsai copy info refers to one parent, so only copies from p1 are looked for
+            diff.find_similar(Some(DiffFindOptions::new().renames(true).copies(true)))?;
+            collect_changed_paths(&diff, &mut changed, Some(&mut copies))?;
+        } else {
+            collect_changed_paths(&diff, &mut changed, None)?;
+        }
+    }
+
+    let mut changes = BTreeMap::new();
+    for path in changed {
+        let entry = match tree.get_path(Path::new(OsStr::from_bytes(&path.to_vec()))) {
+            Ok(entry) => Some(entry),
+            Err(ref err) if err.code() == ErrorCode::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+        let file_type = match entry.as_ref().map(|entry| entry.filemode()) {
+            None => None,
+            Some(MODE_REGULAR) => Some(FileType::Regular),
+            Some(MODE_EXECUTABLE) => Some(FileType::Executable),
+            Some(MODE_SYMLINK) => Some(FileType::Symlink),
+            // Submodules have no counterpart in Mononoke, so they are left out
+            Some(MODE_GITLINK) => continue,
+            // The path became a directory, so the file was removed
+            Some(_) => None,
+        };
+        let change = match (entry, file_type) {
+            (Some(entry), Some(file_type)) => {
+                let blob = gitrepo.find_blob(entry.id())?;
+                Some(GitFileChange {
+                    content: Bytes::from(blob.content()),
+                    file_type,
+                    copy_from: copies.remove(&path),
+                })
+            }
+            _ => None,
+        };
+        changes.insert(path, change);
+    }
+
+    // A file that is replaced by a directory, or the other way around, is removed implicitly.
+    // Bonsai changesets don't allow the removal to be listed next to the other change.
+    let conflicting: Vec<_> = changes
+        .iter()
+        .filter(|&(path, change)| {
+            change.is_none() && changes.keys().any(|other| {
+                other != path && (path.is_prefix_of(other) || other.is_prefix_of(path))
+            })
+        })
+        .map(|(path, _)| path.clone())
+        .collect();
+    for path in conflicting {
+        changes.remove(&path);
+    }
+
+    let (author, author_date) = convert_signature(&commit.author())?;
+    let (committer, committer_date) = convert_signature(&commit.committer())?;
+    let mut parents = Vec::new();
+    for parent in commit.parent_ids() {
+        parents.push(GitSha1::from_bytes(parent.as_bytes())?);
+    }
+
+    Ok(GitCommit {
+        sha1: GitSha1::from_bytes(oid.as_bytes())?,
+        parents,
+        author,
+        author_date,
+        committer,
+        committer_date,
+        message: String::from_utf8_lossy(commit.message_bytes()).into_owned(),
+        changes,
+    })
+}
+
+fn collect_changed_paths(
+    diff: &Diff,
+    changed: &mut BTreeSet<MPath>,
+    mut copies: Option<&mut HashMap<MPath, MPath>>,
+) -> Result<()> {
+    for delta in diff.deltas() {
+        let old_path = delta.old_file().path_bytes().map(MPath::new);
+        let new_path = delta.new_file().path_bytes().map(MPath::new);
+        let (old_path, new_path) = match (old_path, new_path) {
+            (Some(old_path), Some(new_path)) => (old_path?, new_path?),
+            _ => bail_msg!("diff of {:?} has no path", delta.status()),
+        };
+        match (delta.status(), copies.as_mut()) {
+            (Delta::Renamed, Some(copies)) | (Delta::Copied, Some(copies)) => {
+                copies.insert(new_path.clone(), old_path.clone());
+            }
+            _ => {}
+        }
+        changed.insert(old_path);
+        changed.insert(new_path);
+    }
+    Ok(())
+}
+
+fn convert_signature(signature: &::git2::Signature) -> Result<(String, DateTime)> {
+    let user = format!(
+        "{} <{}>",
+        String::from_utf8_lossy(signature.name_bytes()),
+        String::from_utf8_lossy(signature.email_bytes())
+    );
+    Ok((user, convert_time(signature.when())?))
+}
+
+fn convert_time(time: Time) -> Result<DateTime> {
+    // Git records the offset east of UTC, Mononoke the one west of it
+    DateTime::from_timestamp(time.seconds(), -time.offset_minutes() * 60)
+}
+
+/// Find the bonsai changeset a git commit was imported as, and the Mercurial changeset derived
+/// from it
+fn imported_changeset(
+    blobrepo: Arc<BlobRepo>,
+    mapping: Arc<BonsaiGitMapping>,
+    repo_id: RepositoryId,
+    sha1: GitSha1,
+) -> BoxFuture<(ChangesetId, HgChangesetId), Error> {
+    mapping
+        .get_bonsai_from_git_sha1(repo_id, sha1)
+        .and_then(move |bcs_id| {
+            bcs_id.ok_or_else(|| format_err!("git commit {} is not imported", sha1))
+        })
+        .and_then(move |bcs_id| {
+            blobrepo
+                .get_hg_from_bonsai(&bcs_id)
+                .and_then(move |hg_cs_id| {
+                    hg_cs_id.ok_or_else(|| format_err!("no hg changeset for {}", bcs_id))
+                })
+                .map(move |hg_cs_id| (bcs_id, hg_cs_id))
+        })
+        .boxify()
+}
+
+fn import_commit(
+    blobrepo: Arc<BlobRepo>,
+    mapping: Arc<BonsaiGitMapping>,
+    repo_id: RepositoryId,
+    commit: GitCommit,
+) -> BoxFuture<(GitSha1, HgChangesetId), Error> {
+    let GitCommit {
+        sha1,
+        parents,
+        author,
+        author_date,
+        committer,
+        committer_date,
+        message,
+        changes,
+    } = commit;
+
+    let parents = parents.into_iter().map({
+        let blobrepo = blobrepo.clone();
+        let mapping = mapping.clone();
+        move |parent| {
+            imported_changeset(blobrepo.clone(), mapping.clone(), repo_id, parent).and_then({
+                let blobrepo = blobrepo.clone();
+                move |(bcs_id, hg_cs_id)| {
+                    blobrepo
+                        .get_changeset_by_changesetid(&hg_cs_id)
+                        .map(move |cs| (bcs_id, cs))
+                }
+            })
+        }
+    });
+
+    let contents = changes.into_iter().map({
+        let blobrepo = blobrepo.clone();
+        move |(path, change)| match change {
+            None => future::ok((path, None)).boxify(),
+            Some(change) => upload_content(&blobrepo, change.content)
+                .map(move |(content_id, size)| {
+                    (
+                        path,
+                        Some((content_id, change.file_type, size, change.copy_from)),
+                    )
+                })
+                .boxify(),
+        }
+    });
+
+    future::join_all(parents)
+        .join(future::join_all(contents))
+        .and_then(move |(parents, contents)| {
+            let p1 = parents.first().map(|&(bcs_id, _)| bcs_id);
+            let file_changes = contents
+                .into_iter()
+                .map(|(path, change)| {
+                    let change = change.map(|(content_id, file_type, size, copy_from)| {
+                        let copy_from = copy_from.and_then(|from| p1.map(|p1| (from, p1)));
+                        FileChange::new(content_id, file_type, size, copy_from)
+                    });
+                    (path, change)
+                })
+                .collect();
+            // The git commit is recorded like Mercurial's convert extension records it
+            let mut extra = BTreeMap::new();
+            extra.insert("convert_revision".to_string(), sha1.to_string());
+
+            let bcs = BonsaiChangesetMut {
+                parents: parents.iter().map(|&(bcs_id, _)| bcs_id).collect(),
+                author,
+                author_date,
+                committer: Some(committer),
+                committer_date: Some(committer_date),
+                message,
+                extra,
+                file_changes,
+            }.freeze()
+                .with_context(|_| format!("While converting git commit {}", sha1))?;
+
+            let parents = parents.into_iter().map(|(_, cs)| cs).collect();
+            Ok(create_hg_changeset(blobrepo, bcs, parents))
+        })
+        .flatten()
+        .and_then(move |(bcs_id, hg_cs_id)| {
+            let entry = BonsaiGitMappingEntry {
+                repo_id,
+                git_sha1: sha1,
+                bcs_id,
+            };
+            mapping.add(entry).map(move |_| (sha1, hg_cs_id))
+        })
+        .with_context(move |_| format!("While importing git commit {}", sha1))
+        .from_err()
+        .boxify()
+}
+
+fn upload_content(blobrepo: &BlobRepo, content: Bytes) -> BoxFuture<(ContentId, u64), Error> {
+    let size = content.len() as u64;
+    blobrepo
+        .upload_blob(FileContents::Bytes(content).into_blob())
+        .map(move |content_id| (content_id, size))
+        .boxify()
+}
+
+/// Create the Mercurial changeset derived from `bcs`, which becomes the bonsai changeset of
+/// the Mercurial changeset in the bonsai mapping. `parents` are the Mercurial changesets of the
+/// parents of `bcs`.
+fn create_hg_changeset(
+    blobrepo: Arc<BlobRepo>,
+    bcs: BonsaiChangeset,
+    parents: Vec<BlobChangeset>,
+) -> BoxFuture<(ChangesetId, HgChangesetId), Error> {
+    let bcs_id = *bcs.clone().into_blob().id();
+    let manifest_p1 = parents.get(0).map(|cs| cs.manifestid().into_nodehash());
+    let manifest_p2 = parents.get(1).map(|cs| cs.manifestid().into_nodehash());
+
+    blobrepo
+        .get_manifest_from_bonsai(bcs.clone(), manifest_p1.as_ref(), manifest_p2.as_ref())
+        .and_then({
+            let blobrepo = blobrepo.clone();
+            move |root| {
+                let p1 = manifest_p1.map(|p1| blobrepo.get_manifest_by_nodeid(&p1));
+                let p2 = manifest_p2.map(|p2| blobrepo.get_manifest_by_nodeid(&p2));
+                blobrepo
+                    .get_manifest_by_nodeid(&root)
+                    .join3(p1, p2)
+                    .map(move |(rootmf, p1, p2)| (root, rootmf, p1, p2))
+            }
+        })
+        .and_then(move |(root, rootmf, p1_manifest, p2_manifest)| {
+            // The filenodes of the entries that are new in this changeset are only recorded if
+            // the entries are passed to CreateChangeset
+            let blobstore = blobrepo.get_blobstore();
+            let sub_entries =
+                new_entry_intersection_stream(&rootmf, p1_manifest.as_ref(), p2_manifest.as_ref())
+                    .and_then({
+                        let blobstore = blobstore.clone();
+                        move |(dirname, entry)| {
+                            let name = entry
+                                .get_name()
+                                .cloned()
+                                .ok_or_else(|| format_err!("Entry in {:?} has no name", dirname))?;
+                            let path = MPath::join_opt_element(dirname.as_ref(), &name);
+                            let path = match entry.get_type() {
+                                Type::File(_) => RepoPath::FilePath(path),
+                                Type::Tree => RepoPath::DirectoryPath(path),
+                            };
+                            let entry = HgBlobEntry::new(
+                                blobstore.clone(),
+                                name,
+                                entry.get_hash().into_nodehash(),
+                                entry.get_type(),
+                            );
+                            Ok((entry, path))
+                        }
+                    })
+                    .boxify();
+            let root_manifest = HgBlobEntry::new_root(blobstore, HgManifestId::new(root));
+
+            let mut parents = parents.into_iter().map(ChangesetHandle::from);
+            let create_changeset = CreateChangeset {
+                expected_nodeid: None,
+                expected_files: None,
+                p1: parents.next(),
+                p2: parents.next(),
+                root_manifest: future::ok(Some((root_manifest, RepoPath::root()))).boxify(),
+                sub_entries,
+                user: bcs.author().to_string(),
+                time: *bcs.author_date(),
+                extra: bcs.extra()
+                    .map(|(key, value)| (key.as_bytes().to_vec(), value.as_bytes().to_vec()))
+                    .collect(),
+                // Mercurial strips the trailing whitespace of commit messages
+                comments: bcs.message().trim_right().to_string(),
+                bonsai: Some(bcs),
+            };
+            create_changeset
+                .create(&blobrepo, ScubaSampleBuilder::with_discard())
+                .get_completed_changeset()
+                .map_err(Error::from)
+        })
+        .map(move |cs| (bcs_id, cs.get_changeset_id()))
+        .boxify()
+}
+
+/// Point a bookmark with the name of every branch to the changeset of the branch's commit
+fn sync_bookmarks(
+    blobrepo: Arc<BlobRepo>,
+    mapping: Arc<BonsaiGitMapping>,
+    repo_id: RepositoryId,
+    branches: Vec<(String, Oid)>,
+) -> BoxFuture<(), Error> {
+    // The update logs of the bookmarks record who ran the import
+    let session = env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+
+    let bookmarks = branches.into_iter().map({
+        let blobrepo = blobrepo.clone();
+        move |(name, oid)| {
+            let bookmark = Bookmark::new(&name);
+            let sha1 = GitSha1::from_bytes(oid.as_bytes());
+            let blobrepo = blobrepo.clone();
+            let mapping = mapping.clone();
+            bookmark
+                .and_then(|bookmark| sha1.map(|sha1| (bookmark, sha1)))
+                .into_future()
+                .and_then(move |(bookmark, sha1)| {
+                    imported_changeset(blobrepo, mapping, repo_id, sha1)
+                        .map(move |(_, hg_cs_id)| (bookmark, hg_cs_id))
+                })
+        }
+    });
+
+    future::join_all(bookmarks)
+        .and_then(move |bookmarks| {
+            let mut transaction =
+                blobrepo.update_bookmark_transaction(BookmarkUpdateReason::Gitimport, &session);
+            for (bookmark, hg_cs_id) in bookmarks {
+                try_boxfuture!(transaction.force_set(&bookmark, &hg_cs_id));
+            }
+            transaction.commit()
+        })
+        .boxify()
+}
@@ -0,0 +1,194 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+#![deny(warnings)]
+
+extern crate blobrepo;
+extern crate bonsai_git_mapping;
+extern crate bookmarks;
+extern crate bytes;
+extern crate clap;
+#[macro_use]
+extern crate failure_ext as failure;
+extern crate futures;
+#[macro_use]
+extern crate futures_ext;
+extern crate git2;
+extern crate mercurial_types;
+extern crate mononoke_types;
+extern crate scuba_ext;
+#[macro_use]
+extern crate slog;
+extern crate slog_glog_fmt;
+extern crate tokio_core;
+
+mod import;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use clap::{App, Arg, ArgMatches};
+use git2::Repository;
+use slog::{Drain, Level, Logger};
+use slog_glog_fmt::default_drain as glog_drain;
+use tokio_core::reactor::Core;
+
+use blobrepo::BlobRepo;
+use bonsai_git_mapping::{BonsaiGitMapping, MysqlBonsaiGitMapping, SqliteBonsaiGitMapping};
+use mercurial_types::RepositoryId;
+
+fn setup_app<'a, 'b>() -> App<'a, 'b> {
+    App::new("git to blob importer")
+        .version("0.0.0")
+        .about("Import a git repo into Mononoke blobstore.")
+        .args_from_usage(
+            r#"
+            <INPUT>                         'input bare git repo'
+            --debug                         'print debug logs'
+            --repo_id <repo_id>             'ID of the newly imported repo'
+            --manifold-bucket [BUCKET]      'manifold bucket'
+            --manifold-prefix [PREFIX]      'manifold prefix Default: new_blobimport_test'
+            --db-address [address]          'address of a db. Used only for manifold blobstore'
+            --blobstore-cache-size [SIZE]   'size of the blobstore cache'
+            --changesets-cache-size [SIZE]  'size of the changesets cache'
+            --filenodes-cache-size [SIZE]   'size of the filenodes cache'
+            --io-thread-num [NUM]           'num of the io threads to use'
+            --max-concurrent-request-per-io-thread [NUM] 'max requests per io thread'
+            --no-bookmark                   'if provided won't update bookmarks'
+            [OUTPUT]                        'Blobstore output'
+        "#,
+        )
+        .arg(
+            Arg::with_name("blobstore")
+                .long("blobstore")
+                .takes_value(true)
+                .possible_values(&["files", "rocksdb", "manifold"])
+                .required(true)
+                .help("blobstore type"),
+        )
+}
+
+fn get_usize<'a>(matches: &ArgMatches<'a>, key: &str, default: usize) -> usize {
+    matches
+        .value_of(key)
+        .map(|val| {
+            val.parse::<usize>()
+                .expect(&format!("{} must be integer", key))
+        })
+        .unwrap_or(default)
+}
+
+fn get_output<'a>(matches: &ArgMatches<'a>) -> PathBuf {
+    let output = matches.value_of("OUTPUT").expect("output is not specified");
+    let output = Path::new(output)
+        .canonicalize()
+        .expect("Failed to read output path");
+    if !output.is_dir() {
+        panic!("{:?} is not a directory", output);
+    }
+    output
+}
+
+fn open_blobrepo<'a>(logger: &Logger, matches: &ArgMatches<'a>) -> BlobRepo {
+    let repo_id = RepositoryId::new(matches.value_of("repo_id").unwrap().parse().unwrap());
+
+    match matches.value_of("blobstore").unwrap() {
+        "files" => {
+            let output = get_output(matches);
+            BlobRepo::new_files(
+                logger.new(o!["BlobRepo:Files" => output.to_string_lossy().into_owned()]),
+                &output,
+                repo_id,
+            ).expect("failed to create file blobrepo")
+        }
+        "rocksdb" => {
+            let output = get_output(matches);
+            BlobRepo::new_rocksdb(
+                logger.new(o!["BlobRepo:Rocksdb" => output.to_string_lossy().into_owned()]),
+                &output,
+                repo_id,
+            ).expect("failed to create rocksdb blobrepo")
+        }
+        "manifold" => {
+            let manifold_bucket = matches
+                .value_of("manifold-bucket")
+                .expect("manifold bucket is not specified");
+
+            BlobRepo::new_manifold(
+                logger.new(o!["BlobRepo:TestManifold" => manifold_bucket.to_owned()]),
+                manifold_bucket,
+                matches
+                    .value_of("manifold-prefix")
+                    .unwrap_or("new_blobimport_test"),
+                repo_id,
+                matches
+                    .value_of("db-address")
+                    .expect("--db-address is not specified"),
+                get_usize(&matches, "blobstore-cache-size", 100_000_000),
+                get_usize(&matches, "changesets-cache-size", 100_000_000),
+                get_usize(&matches, "filenodes-cache-size", 100_000_000),
+                get_usize(&matches, "io-thread-num", 5),
+                get_usize(&matches, "max-concurrent-request-per-io-thread", 5),
+            ).expect("failed to create manifold blobrepo")
+        }
+        bad => panic!("unexpected blobstore type: {}", bad),
+    }
+}
+
+/// The git mapping is stored next to the other tables of the repo
+fn open_git_mapping<'a>(matches: &ArgMatches<'a>) -> Arc<BonsaiGitMapping> {
+    match matches.value_of("blobstore").unwrap() {
+        "files" | "rocksdb" => {
+            let path = get_output(matches).join("bonsai_git_mapping");
+            Arc::new(
+                SqliteBonsaiGitMapping::open_or_create(path.to_string_lossy())
+                    .expect("failed to open the git mapping"),
+            )
+        }
+        "manifold" => {
+            let db_address = matches
+                .value_of("db-address")
+                .expect("--db-address is not specified");
+            Arc::new(
+                MysqlBonsaiGitMapping::open(db_address).expect("failed to open the git mapping"),
+            )
+        }
+        bad => panic!("unexpected blobstore type: {}", bad),
+    }
+}
+
+fn main() {
+    let matches = setup_app().get_matches();
+
+    let input = matches.value_of("INPUT").expect("input is not specified");
+    let gitrepo = Repository::open_bare(input).expect("cannot open git repo");
+
+    let mut core = Core::new().expect("cannot create tokio core");
+
+    let logger = {
+        let level = if matches.is_present("debug") {
+            Level::Debug
+        } else {
+            Level::Info
+        };
+
+        let drain = glog_drain().filter_level(level).fuse();
+        slog::Logger::root(drain, o![])
+    };
+
+    let repo_id = RepositoryId::new(matches.value_of("repo_id").unwrap().parse().unwrap());
+    let blobrepo = Arc::new(open_blobrepo(&logger, &matches));
+    let mapping = open_git_mapping(&matches);
+
+    core.run(import::import(
+        logger,
+        gitrepo,
+        blobrepo,
+        mapping,
+        repo_id,
+        !matches.is_present("no-bookmark"),
+    )).expect("main stream failed");
+}
@@ -33,8 +33,13 @@ pub enum ErrorKind {
     #[fail(display = "Bad LZ4: {}", _0)] BadLZ4(String),
     #[fail(display = "Failed to init LZ4 context")] LZ4CompressInitFailed,
     #[fail(display = "Compression failed")] LZ4CompressFailed,
+    #[fail(display = "Input of {} bytes is too large for LZ4", _0)] InputTooLarge(usize),
 }
 
+// The largest input LZ4 can compress, LZ4_MAX_INPUT_SIZE in lz4.h. This also bounds the size of
+// the uncompressed blocks of `uncompressed_header`, as they have to be decompressible.
+pub const MAX_INPUT_SIZE: usize = 0x7E000000;
+
 // Wrapper for the lz4 library decompress context
 struct DecompressContext(*mut LZ4StreamDecode);
 impl DecompressContext {
@@ -144,6 +149,31 @@ pub fn compress(input_data: &[u8]) -> Result<Vec<u8>, Error> {
     Ok(compressed)
 }
 
+// Returns the header of an uncompressed LZ4 block that stores `size` bytes as literals, so
+// that the block is the header followed by the data itself. Unlike `compress`, this doesn't
+// need the data in memory, so it can be used to stream huge inputs.
+pub fn uncompressed_header(size: usize) -> Result<Vec<u8>, Error> {
+    if size > MAX_INPUT_SIZE {
+        bail_err!(ErrorKind::InputTooLarge(size));
+    }
+    // The block has a single sequence, whose token has the literals length in the high 4 bits.
+    // Lengths of at least 15 continue in bytes of 255 and a byte with the remainder.
+    let mut header = Vec::with_capacity(4 + 1 + size / 255 + 1);
+    header.write_u32::<LittleEndian>(size as u32)?;
+    if size < 15 {
+        header.push((size as u8) << 4);
+    } else {
+        header.push(0xF0);
+        let mut remaining = size - 15;
+        while remaining >= 255 {
+            header.push(255);
+            remaining -= 255;
+        }
+        header.push(remaining as u8);
+    }
+    Ok(header)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -156,4 +186,21 @@ mod tests {
         assert!(remains.is_empty());
         assert_eq!(data, res.as_slice());
     }
+
+    #[test]
+    fn uncompressed_header_decompress() {
+        for size in vec![0, 1, 14, 15, 16, 269, 270, 300, 1000, 100000] {
+            let data: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+            let mut block = uncompressed_header(size).unwrap();
+            block.extend_from_slice(&data);
+            let (res, remains) = decompress(&block).unwrap();
+            assert!(remains.is_empty());
+            assert_eq!(data, res);
+        }
+    }
+
+    #[test]
+    fn uncompressed_header_too_large() {
+        uncompressed_header(MAX_INPUT_SIZE + 1).expect_err("unexpected OK - too large");
+    }
 }
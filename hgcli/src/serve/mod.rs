@@ -4,6 +4,8 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2 or any later version.
 
+use std::env;
+use std::ffi::CStr;
 use std::net::SocketAddr;
 
 use bytes::Bytes;
@@ -16,6 +18,7 @@ use tokio_io::codec::{FramedRead, FramedWrite};
 use tokio::net::TcpStream;
 
 use clap::ArgMatches;
+use nix::libc;
 
 use errors::*;
 
@@ -57,7 +60,14 @@ fn ssh_relay<P: AsRef<str>>(path: P, repo: &str) -> Result<()> {
     let rx = FramedRead::new(socket_read, SshDecoder::new());
     let tx = FramedWrite::new(socket_write, SshEncoder::new());
 
-    let preamble = Preamble::new(String::from(repo));
+    let mut preamble = Preamble::new(String::from(repo));
+    // Tell the server who is connecting, e.g. for the update logs of the bookmarks they move and
+    // for the users allowed to move them. The user is the account this runs as, which sshd
+    // authenticated, rather than $USER, which the client can set to anything.
+    preamble.misc.insert("user".to_string(), authenticated_user()?);
+    if let Ok(ssh_client) = env::var("SSH_CLIENT") {
+        preamble.misc.insert("ssh_client".to_string(), ssh_client);
+    }
     let preamble = stream::once(Ok(SshMsg::new(SshStream::Preamble(preamble), Bytes::new())));
 
     // Start a task to copy from stdin to the socket
@@ -90,3 +100,13 @@ fn ssh_relay<P: AsRef<str>>(path: P, repo: &str) -> Result<()> {
         Err((e, _)) => Err(e),
     }
 }
+
+/// Name of the account this process runs as
+fn authenticated_user() -> Result<String> {
+    let passwd = unsafe { libc::getpwuid(libc::getuid()) };
+    if passwd.is_null() {
+        bail_msg!("No passwd entry for the current user");
+    }
+    let name = unsafe { CStr::from_ptr((*passwd).pw_name) };
+    Ok(name.to_str()?.to_string())
+}
@@ -136,6 +136,18 @@ impl<H: HgCommands + Send + 'static> HgCommandHandler<H> {
                     .boxify(),
                 ok(instream).boxify(),
             ),
+            SingleRequest::ListKeysPatterns {
+                namespace,
+                patterns,
+            } => (
+                hgcmds
+                    .listkeyspatterns(namespace, patterns)
+                    .map(SingleResponse::Listkeys)
+                    .map_err(self::Error::into)
+                    .into_stream()
+                    .boxify(),
+                ok(instream).boxify(),
+            ),
             SingleRequest::Lookup { key } => (
                 hgcmds
                     .lookup(key)
@@ -216,6 +228,14 @@ impl<H: HgCommands + Send + 'static> HgCommandHandler<H> {
                     instream,
                 )
             }
+            SingleRequest::StreamOut => (
+                hgcmds
+                    .stream_out()
+                    .map(SingleResponse::StreamOut)
+                    .map_err(self::Error::into)
+                    .boxify(),
+                ok(instream).boxify(),
+            ),
         }
     }
 
@@ -458,9 +478,7 @@ pub trait HgCommands {
 
     // @wireprotocommand('branchmap')
     fn branchmap(&self) -> HgCommandRes<HashMap<String, HashSet<HgNodeHash>>> {
-        // We have no plans to support mercurial branches and hence no plans for branchmap,
-        // so just return fake response.
-        future::ok(HashMap::new()).boxify()
+        unimplemented("branchmap")
     }
 
     // @wireprotocommand('capabilities')
@@ -489,6 +507,15 @@ pub trait HgCommands {
         unimplemented("listkeys")
     }
 
+    // @wireprotocommand('listkeyspatterns', 'namespace patterns')
+    fn listkeyspatterns(
+        &self,
+        _namespace: String,
+        _patterns: Vec<String>,
+    ) -> HgCommandRes<HashMap<Vec<u8>, Vec<u8>>> {
+        unimplemented("listkeyspatterns")
+    }
+
     // @wireprotocommand('lookup', 'key')
     fn lookup(&self, _key: String) -> HgCommandRes<Bytes> {
         unimplemented("lookup")
@@ -517,6 +544,11 @@ pub trait HgCommands {
     fn getfiles(&self, _params: BoxStream<(HgNodeHash, MPath), Error>) -> BoxStream<Bytes, Error> {
         once(Err(ErrorKind::Unimplemented("getfiles".into()).into())).boxify()
     }
+
+    // @wireprotocommand('stream_out')
+    fn stream_out(&self) -> BoxStream<Bytes, Error> {
+        once(Err(ErrorKind::Unimplemented("stream_out".into()).into())).boxify()
+    }
 }
 
 #[cfg(test)]
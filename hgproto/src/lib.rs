@@ -97,6 +97,10 @@ pub enum SingleRequest {
     Listkeys {
         namespace: String,
     },
+    ListKeysPatterns {
+        namespace: String,
+        patterns: Vec<String>,
+    },
     Lookup {
         key: String,
     },
@@ -108,6 +112,7 @@ pub enum SingleRequest {
     },
     Gettreepack(GettreepackArgs),
     Getfiles,
+    StreamOut,
 }
 
 impl SingleRequest {
@@ -121,11 +126,13 @@ impl SingleRequest {
             &SingleRequest::Heads => "heads",
             &SingleRequest::Hello => "hello",
             &SingleRequest::Listkeys { .. } => "listkeys",
+            &SingleRequest::ListKeysPatterns { .. } => "listkeyspatterns",
             &SingleRequest::Lookup { .. } => "lookup",
             &SingleRequest::Known { .. } => "known",
             &SingleRequest::Unbundle { .. } => "unbundle",
             &SingleRequest::Gettreepack(_) => "gettreepack",
             &SingleRequest::Getfiles => "getfiles",
+            &SingleRequest::StreamOut => "stream_out",
         }
     }
 }
@@ -138,6 +145,8 @@ pub struct GetbundleArgs {
     pub common: Vec<HgNodeHash>,
     pub bundlecaps: Vec<Vec<u8>>,
     pub listkeys: Vec<Vec<u8>>,
+    /// Whether the client asked for a streaming clone instead of a changegroup.
+    pub stream: bool,
 }
 
 impl Debug for GetbundleArgs {
@@ -157,6 +166,7 @@ impl Debug for GetbundleArgs {
             .field("common", &common)
             .field("bundlecaps", &bcaps)
             .field("listkeys", &listkeys)
+            .field("stream", &self.stream)
             .finish()
     }
 }
@@ -199,10 +209,12 @@ pub enum SingleResponse {
     Unbundle(Bytes),
     Gettreepack(Bytes),
     Getfiles(Bytes),
+    StreamOut(Bytes),
 }
 
 impl SingleResponse {
     /// Whether this represents a streaming response. Streaming responses don't have any framing.
+    /// Getfiles responses frame each file themselves, so that a file can be sent in pieces.
     pub fn is_stream(&self) -> bool {
         use SingleResponse::*;
 
@@ -211,6 +223,8 @@ impl SingleResponse {
             &ReadyForStream => true,
             &Unbundle(_) => true,
             &Gettreepack(_) => true,
+            &Getfiles(_) => true,
+            &StreamOut(_) => true,
             _ => false,
         }
     }
@@ -19,6 +19,8 @@ use errors;
 use errors::*;
 
 const BAD_UTF8_ERR_CODE: u32 = 111;
+const BAD_HEX_ERR_CODE: u32 = 112;
+const BAD_BOOL_ERR_CODE: u32 = 113;
 
 /// Parse an unsigned decimal integer. If it reaches the end of input, it returns Incomplete,
 /// as there may be more digits following
@@ -200,6 +202,30 @@ named!(
     )
 );
 
+/// A space-separated list of hex-encoded utf8 strings, as produced by Mercurial's
+/// `wireproto.encodelist`. The input is assumed to be complete and exact.
+fn hexstringlist(input: &[u8]) -> IResult<&[u8], Vec<String>> {
+    if input.len() == 0 {
+        return IResult::Done(b"", vec![]);
+    }
+
+    let mut res = vec![];
+    for item in input.split(|c| *c == b' ') {
+        if item.len() % 2 != 0 {
+            return IResult::Error(ErrorKind::Custom(BAD_HEX_ERR_CODE));
+        }
+        let decoded: Option<Vec<u8>> = item.chunks(2)
+            .map(|c| str::from_utf8(c).ok().and_then(|c| u8::from_str_radix(c, 16).ok()))
+            .collect();
+        match decoded.map(String::from_utf8) {
+            Some(Ok(s)) => res.push(s),
+            Some(Err(_)) => return IResult::Error(ErrorKind::Custom(BAD_UTF8_ERR_CODE)),
+            None => return IResult::Error(ErrorKind::Custom(BAD_HEX_ERR_CODE)),
+        }
+    }
+    IResult::Done(b"", res)
+}
+
 /// A comma-separated list of arbitrary values. The input is assumed to be
 /// complete and exact.
 fn commavalues(input: &[u8]) -> IResult<&[u8], Vec<Vec<u8>>> {
@@ -218,6 +244,18 @@ fn commavalues(input: &[u8]) -> IResult<&[u8], Vec<Vec<u8>>> {
     }
 }
 
+/// A boolean, encoded as "1" or "0" by Mercurial's `wireproto`. The input is assumed to be
+/// complete and exact.
+fn boolean(input: &[u8]) -> IResult<&[u8], bool> {
+    if input == b"1" {
+        IResult::Done(b"", true)
+    } else if input == b"0" {
+        IResult::Done(b"", false)
+    } else {
+        IResult::Error(ErrorKind::Custom(BAD_BOOL_ERR_CODE))
+    }
+}
+
 fn notsemi(b: u8) -> bool {
     b != b';'
 }
@@ -465,19 +503,24 @@ fn parse_with_params(
             |kv| Ok(Getbundle(GetbundleArgs {
                 // Some params are currently ignored, like:
                 // - obsmarkers
-                // - cg
+                // - cg (clients only turn it off when they ask for a stream)
                 // - cbattempted
                 // If those params are needed, they should be parsed here.
                 heads: parseval_default(&kv, "heads", hashlist)?,
                 common: parseval_default(&kv, "common", hashlist)?,
                 bundlecaps: parseval_default(&kv, "bundlecaps", commavalues)?,
                 listkeys: parseval_default(&kv, "listkeys", commavalues)?,
+                stream: parseval_default(&kv, "stream", boolean)?,
             })))
         | command!("heads", Heads, parse_params, {})
         | command!("hello", Hello, parse_params, {})
         | command!("listkeys", Listkeys, parse_params, {
               namespace => ident_string,
           })
+        | command!("listkeyspatterns", ListKeysPatterns, parse_params, {
+              namespace => ident_string,
+              patterns => hexstringlist,
+          })
         | command!("lookup", Lookup, parse_params, {
               key => utf8_string_complete,
           })
@@ -495,6 +538,19 @@ fn parse_with_params(
                 directories: parseval(&kv, "directories", gettreepack_directories)?,
             })))
         | command!("getfiles", Getfiles, parse_params, {})
+        | command!("stream_out", StreamOut, parse_params, {})
+        // remotefilelog's variant for shallow clients takes include and exclude patterns. The
+        // stream can't be narrowed, so clients that ask for it are rejected rather than sent
+        // everything.
+        | call!(parse_command, "stream_out_shallow", parse_params, 0+1,
+            |kv: HashMap<Vec<u8>, Vec<u8>>| {
+                for key in &["includepattern", "excludepattern"] {
+                    if kv.get(key.as_bytes()).map_or(false, |patterns| !patterns.is_empty()) {
+                        bail_msg!("stream_out_shallow with {} is not supported", key);
+                    }
+                }
+                Ok(StreamOut)
+            })
     )
 }
 
@@ -1155,6 +1211,7 @@ mod test_parse {
                 common: vec![],
                 bundlecaps: vec![],
                 listkeys: vec![],
+                stream: false,
             })),
         );
 
@@ -1179,6 +1236,25 @@ mod test_parse {
                 common: vec![hash_twos(), hash_threes()],
                 bundlecaps: vec![b"cap1".to_vec(), b"CAP2".to_vec(), b"cap3".to_vec()],
                 listkeys: vec![b"key1".to_vec(), b"key2".to_vec()],
+                stream: false,
+            })),
+        );
+
+        // streaming clone
+        let inp = "getbundle\n\
+                   * 2\n\
+                   cg 1\n\
+                   0\
+                   stream 1\n\
+                   1";
+        test_parse(
+            inp,
+            Request::Single(SingleRequest::Getbundle(GetbundleArgs {
+                heads: vec![],
+                common: vec![],
+                bundlecaps: vec![],
+                listkeys: vec![],
+                stream: true,
             })),
         );
     }
@@ -1211,6 +1287,24 @@ mod test_parse {
         );
     }
 
+    #[test]
+    fn test_parse_listkeyspatterns() {
+        // patterns are "scratch/*" and "book" hex encoded
+        let inp = "listkeyspatterns\n\
+                   namespace 9\n\
+                   bookmarks\
+                   patterns 27\n\
+                   736372617463682f2a 626f6f6b";
+
+        test_parse(
+            inp,
+            Request::Single(SingleRequest::ListKeysPatterns {
+                namespace: "bookmarks".to_string(),
+                patterns: vec!["scratch/*".to_string(), "book".to_string()],
+            }),
+        );
+    }
+
     #[test]
     fn test_parse_lookup() {
         let inp = "lookup\n\
@@ -1307,6 +1401,32 @@ mod test_parse {
         test_parse(inp, Request::Single(SingleRequest::Known { nodes: vec![] }));
     }
 
+    #[test]
+    fn test_parse_stream_out() {
+        let inp = "stream_out\n";
+
+        test_parse(inp, Request::Single(SingleRequest::StreamOut));
+    }
+
+    #[test]
+    fn test_parse_stream_out_shallow() {
+        let inp = "stream_out_shallow\n\
+                   * 0\n";
+
+        test_parse(inp, Request::Single(SingleRequest::StreamOut));
+    }
+
+    #[test]
+    fn test_parse_stream_out_shallow_patterns() {
+        let inp = "stream_out_shallow\n\
+                   * 1\n\
+                   includepattern 4\n\
+                   dir1";
+
+        let mut buf = BytesMut::from(inp);
+        parse_request(&mut buf).expect_err("patterns are not supported");
+    }
+
     fn test_parse_unbundle_with(bundle: &[u8]) {
         let inp = b"unbundle\n\
                     heads 10\n\
@@ -4,12 +4,14 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2 or any later version.
 
+use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::io::{self, Write};
 
 use bytes::{BufMut, Bytes, BytesMut};
 use futures::stream;
 use futures_ext::StreamExt;
+use mercurial_types::percent_encode;
 
 use {batch, Response, SingleResponse};
 use handler::OutputStream;
@@ -118,6 +120,8 @@ fn encode_cmd(response: &SingleResponse) -> Bytes {
 
         &Getfiles(ref res) => res.clone(),
 
+        &StreamOut(ref res) => res.clone(),
+
         &Lookup(ref res) => res.clone(),
 
         &Listkeys(ref res) => {
@@ -131,12 +135,70 @@ fn encode_cmd(response: &SingleResponse) -> Bytes {
             bytes.freeze()
         }
 
-        &Branchmap(ref _res) => {
-            // We have no plans to support mercurial branches and hence no plans for branchmap,
-            // so just return fake response.
-            Bytes::new()
+        &Branchmap(ref branchmap) => {
+            let mut out = Vec::new();
+
+            // Sorted so that the response doesn't depend on the HashMap order
+            let branches: BTreeMap<_, _> = branchmap
+                .iter()
+                .map(|(branch, heads)| {
+                    let mut heads: Vec<_> = heads.iter().collect();
+                    heads.sort();
+                    (percent_encode(branch), heads)
+                })
+                .collect();
+            // One line per branch: its name and its heads, separated by spaces
+            for (branch, heads) in branches {
+                write!(out, "{} ", branch).expect("write to vec failed");
+                separated(&mut out, heads, " ").expect("write to vec failed");
+            }
+
+            Bytes::from(out)
         }
 
         r => panic!("Response for {:?} unimplemented", r),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use mercurial_types::HgNodeHash;
+    use mercurial_types_mocks::nodehash::{ONES_HASH, THREES_HASH, TWOS_HASH};
+
+    fn encode_branchmap(branches: Vec<(&str, Vec<HgNodeHash>)>) -> Bytes {
+        let branchmap = branches
+            .into_iter()
+            .map(|(branch, heads)| (branch.to_string(), heads.into_iter().collect()))
+            .collect();
+        encode_cmd(&SingleResponse::Branchmap(branchmap))
+    }
+
+    #[test]
+    fn branchmap_no_branches() {
+        assert_eq!(encode_branchmap(vec![]), Bytes::new());
+    }
+
+    #[test]
+    fn branchmap_one_branch() {
+        assert_eq!(
+            encode_branchmap(vec![("default", vec![TWOS_HASH, ONES_HASH])]),
+            Bytes::from(format!("default {} {}\n", ONES_HASH, TWOS_HASH))
+        );
+    }
+
+    #[test]
+    fn branchmap_many_branches() {
+        let encoded = encode_branchmap(vec![
+            ("stable", vec![THREES_HASH]),
+            ("default", vec![ONES_HASH, TWOS_HASH]),
+            ("release 1,0", vec![TWOS_HASH]),
+        ]);
+        let expected = format!(
+            "default {} {}\nrelease%201%2C0 {}\nstable {}\n",
+            ONES_HASH, TWOS_HASH, TWOS_HASH, THREES_HASH
+        );
+        assert_eq!(encoded, Bytes::from(expected));
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright (c) 2004-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! This sub module contains functions to load hooks for the server from the repo config
+
+use super::{ErrorKind, HookManager};
+use bookmarks::Bookmark;
+use failure::Error;
+use lua_hook::LuaHook;
+use metaconfig::repoconfig::RepoConfig;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Install the hooks of the repo config into the hook manager and register them for the
+/// bookmarks that reference them
+pub fn load_hooks(hook_manager: &mut HookManager, config: RepoConfig) -> Result<(), Error> {
+    let mut hook_names = HashSet::new();
+    for hook in config.hooks.unwrap_or_default() {
+        let code = match hook.code {
+            Some(code) => code,
+            None => bail_err!(ErrorKind::MissingHookCode(hook.name)),
+        };
+        let lua_hook = LuaHook {
+            name: hook.name.clone(),
+            code,
+        };
+        hook_manager.install_hook(&hook.name, Arc::new(lua_hook));
+        hook_names.insert(hook.name);
+    }
+
+    for bookmark_params in config.bookmarks.unwrap_or_default() {
+        let bookmark = Bookmark::new(&bookmark_params.name)?;
+        let hooks = bookmark_params.hooks.unwrap_or_default();
+        let missing: Vec<_> = hooks
+            .iter()
+            .filter(|hook_name| !hook_names.contains(*hook_name))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            bail_err!(ErrorKind::NoSuchHook(bookmark, missing));
+        }
+        hook_manager.set_hooks_for_bookmark(bookmark, hooks);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::InMemoryChangesetStore;
+    use async_unit;
+    use metaconfig::repoconfig::{BookmarkParams, HookParams, RepoType};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_load_hooks() {
+        async_unit::tokio_unit_test(|| {
+            let mut config = default_config();
+            config.bookmarks = Some(vec![
+                BookmarkParams {
+                    name: "bm1".into(),
+                    hooks: Some(vec!["hook1".into(), "hook2".into()]),
+                    publishing: false,
+                    only_fast_forward: false,
+                    allow_delete: true,
+                    allowed_users: None,
+                },
+                BookmarkParams {
+                    name: "bm2".into(),
+                    hooks: Some(vec!["hook2".into()]),
+                    publishing: false,
+                    only_fast_forward: false,
+                    allow_delete: true,
+                    allowed_users: None,
+                },
+            ]);
+            config.hooks = Some(vec![
+                HookParams {
+                    name: "hook1".into(),
+                    path: "hooks/hook1.lua".into(),
+                    code: Some("hook1 code".into()),
+                },
+                HookParams {
+                    name: "hook2".into(),
+                    path: "hooks/hook2.lua".into(),
+                    code: Some("hook2 code".into()),
+                },
+            ]);
+
+            let mut hook_manager = hook_manager();
+            load_hooks(&mut hook_manager, config).expect("Can load hooks");
+
+            let mut hooks: Vec<_> = hook_manager.iter().map(|(name, _)| name).collect();
+            hooks.sort();
+            assert_eq!(hooks, vec!["hook1".to_string(), "hook2".to_string()]);
+            assert_eq!(
+                hook_manager.hooks_for_bookmark(&Bookmark::new("bm1").unwrap()),
+                &["hook1".to_string(), "hook2".to_string()]
+            );
+            assert_eq!(
+                hook_manager.hooks_for_bookmark(&Bookmark::new("bm2").unwrap()),
+                &["hook2".to_string()]
+            );
+        });
+    }
+
+    #[test]
+    fn test_load_hooks_no_such_hook() {
+        async_unit::tokio_unit_test(|| {
+            let mut config = default_config();
+            config.bookmarks = Some(vec![
+                BookmarkParams {
+                    name: "bm1".into(),
+                    hooks: Some(vec!["hook1".into()]),
+                    publishing: false,
+                    only_fast_forward: false,
+                    allow_delete: true,
+                    allowed_users: None,
+                },
+            ]);
+
+            let mut hook_manager = hook_manager();
+            assert_matches!(
+                load_hooks(&mut hook_manager, config)
+                    .unwrap_err()
+                    .downcast::<ErrorKind>(),
+                Ok(ErrorKind::NoSuchHook(ref bookmark, ref hooks))
+                    if bookmark == &Bookmark::new("bm1").unwrap() && hooks == &vec!["hook1".to_string()]
+            );
+        });
+    }
+
+    #[test]
+    fn test_load_hooks_missing_code() {
+        async_unit::tokio_unit_test(|| {
+            let mut config = default_config();
+            config.hooks = Some(vec![
+                HookParams {
+                    name: "hook1".into(),
+                    path: "hooks/hook1.lua".into(),
+                    code: None,
+                },
+            ]);
+
+            let mut hook_manager = hook_manager();
+            assert_matches!(
+                load_hooks(&mut hook_manager, config)
+                    .unwrap_err()
+                    .downcast::<ErrorKind>(),
+                Ok(ErrorKind::MissingHookCode(ref hook)) if hook == "hook1"
+            );
+        });
+    }
+
+    fn hook_manager() -> HookManager {
+        let store = InMemoryChangesetStore::new();
+        HookManager::new("some_repo".into(), Box::new(store), 1024, 1024 * 1024)
+    }
+
+    fn default_config() -> RepoConfig {
+        RepoConfig {
+            repotype: RepoType::Revlog(PathBuf::from("/some/path")),
+            generation_cache_size: 1,
+            repoid: 1,
+            scuba_table: None,
+            cache_warmup: None,
+            lfs: None,
+            chunking: None,
+            bookmarks: None,
+            hooks: None,
+            hook_manager_params: None,
+        }
+    }
+}
@@ -23,6 +23,7 @@ extern crate assert_matches;
 extern crate async_unit;
 extern crate asyncmemo;
 extern crate blobrepo;
+extern crate bookmarks;
 #[macro_use]
 extern crate failure_ext as failure;
 extern crate futures;
@@ -34,14 +35,17 @@ extern crate linear;
 #[macro_use]
 extern crate maplit;
 extern crate mercurial_types;
+extern crate metaconfig;
 #[cfg(test)]
 extern crate tokio_core;
 
+pub mod hook_loader;
 pub mod lua_hook;
 pub mod rust_hook;
 
 use asyncmemo::{Asyncmemo, Filler, Weight};
 use blobrepo::{BlobChangeset, BlobRepo};
+use bookmarks::Bookmark;
 use failure::Error;
 use futures::{failed, finished, Future};
 use futures_ext::{BoxFuture, FutureExt};
@@ -56,9 +60,11 @@ type Hooks = Arc<Mutex<HashMap<String, Arc<Hook>>>>;
 
 /// Manages hooks and allows them to be installed and uninstalled given a name
 /// Knows how to run hooks
+/// Knows which hooks are active for which bookmarks
 pub struct HookManager {
     cache: Asyncmemo<HookCacheFiller>,
     hooks: Hooks,
+    bookmark_hooks: HashMap<Bookmark, Vec<String>>,
 }
 
 /// Represents the status of a (non error) hook run
@@ -138,6 +144,9 @@ impl ChangesetStore for InMemoryChangesetStore {
 #[derive(Debug, Fail)]
 pub enum ErrorKind {
     #[fail(display = "No changeset with id '{}'", _0)] NoSuchChangeset(String),
+    #[fail(display = "No code was loaded for hook '{}'", _0)] MissingHookCode(String),
+    #[fail(display = "Hook(s) referenced in bookmark {} do not exist: {:?}", _0, _1)]
+    NoSuchHook(Bookmark, Vec<String>),
 }
 
 impl InMemoryChangesetStore {
@@ -217,7 +226,11 @@ impl HookManager {
             repo_name,
         };
         let cache = Asyncmemo::with_limits(filler, entrylimit, weightlimit);
-        HookManager { cache, hooks }
+        HookManager {
+            cache,
+            hooks,
+            bookmark_hooks: HashMap::new(),
+        }
     }
 
     pub fn install_hook(&mut self, hook_name: &str, hook: Arc<Hook>) {
@@ -230,6 +243,18 @@ impl HookManager {
         hooks.remove(hook_name);
     }
 
+    /// Set the names of the hooks that must pass before the given bookmark can be moved
+    pub fn set_hooks_for_bookmark(&mut self, bookmark: Bookmark, hooks: Vec<String>) {
+        self.bookmark_hooks.insert(bookmark, hooks);
+    }
+
+    pub fn hooks_for_bookmark(&self, bookmark: &Bookmark) -> &[String] {
+        match self.bookmark_hooks.get(bookmark) {
+            Some(hooks) => hooks,
+            None => &[],
+        }
+    }
+
     pub fn iter(&self) -> IntoIter<String, Arc<Hook>> {
         let hooks = self.hooks.lock().unwrap();
         let cloned = hooks.clone();
@@ -256,6 +281,40 @@ impl HookManager {
             .boxify()
     }
 
+    /// Run the hooks active for the given bookmark on a changeset. The results are returned in
+    /// the order the hooks were configured in for the bookmark.
+    pub fn run_hooks_for_bookmark(
+        &self,
+        changeset_id: HgChangesetId,
+        bookmark: &Bookmark,
+    ) -> BoxFuture<Vec<(String, HookExecution)>, Error> {
+        let hook_names = self.hooks_for_bookmark(bookmark);
+        let missing: Vec<_> = {
+            let hooks = self.hooks.lock().unwrap();
+            hook_names
+                .iter()
+                .filter(|hook_name| !hooks.contains_key(*hook_name))
+                .cloned()
+                .collect()
+        };
+        if !missing.is_empty() {
+            return failed(ErrorKind::NoSuchHook(bookmark.clone(), missing).into())
+                .boxify();
+        }
+
+        let v: Vec<BoxFuture<HookExecutionHolder, _>> = hook_names
+            .iter()
+            .map(|hook_name| self.run_hook(hook_name.clone(), changeset_id.clone()))
+            .collect();
+        futures::future::join_all(v)
+            .map(|v| {
+                v.into_iter()
+                    .map(|heh| (heh.hook_name, heh.hook_execution))
+                    .collect()
+            })
+            .boxify()
+    }
+
     fn run_hook(
         &self,
         hook_name: String,
@@ -505,6 +564,76 @@ mod test {
         });
     }
 
+    #[test]
+    fn test_run_hooks_for_bookmark() {
+        async_unit::tokio_unit_test(|| {
+            let mut hook_manager = hook_manager();
+            hook_manager.install_hook(
+                "testhook1",
+                Arc::new(TestHook {
+                    expected_execution: HookExecution::Accepted,
+                }),
+            );
+            let rejection = HookExecution::Rejected(HookRejectionInfo::new(
+                "d1".into(),
+                "d2".into(),
+            ));
+            hook_manager.install_hook(
+                "testhook2",
+                Arc::new(TestHook {
+                    expected_execution: rejection.clone(),
+                }),
+            );
+            let bookmark = Bookmark::new("master").unwrap();
+            hook_manager.set_hooks_for_bookmark(
+                bookmark.clone(),
+                vec!["testhook2".into(), "testhook1".into()],
+            );
+
+            let change_set_id =
+                HgChangesetId::from_str("a5ffa77602a066db7d5cfb9fb5823a0895717c5a").unwrap();
+            let res = hook_manager
+                .run_hooks_for_bookmark(change_set_id, &bookmark)
+                .wait()
+                .unwrap();
+            assert_eq!(
+                res,
+                vec![
+                    ("testhook2".to_string(), rejection),
+                    ("testhook1".to_string(), HookExecution::Accepted),
+                ]
+            );
+
+            let other = Bookmark::new("other").unwrap();
+            let res = hook_manager
+                .run_hooks_for_bookmark(change_set_id, &other)
+                .wait()
+                .unwrap();
+            assert!(res.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_run_hooks_for_bookmark_missing_hook() {
+        async_unit::tokio_unit_test(|| {
+            let mut hook_manager = hook_manager();
+            let bookmark = Bookmark::new("master").unwrap();
+            hook_manager.set_hooks_for_bookmark(bookmark.clone(), vec!["nosuchhook".into()]);
+
+            let change_set_id =
+                HgChangesetId::from_str("a5ffa77602a066db7d5cfb9fb5823a0895717c5a").unwrap();
+            assert_matches!(
+                hook_manager
+                    .run_hooks_for_bookmark(change_set_id, &bookmark)
+                    .wait()
+                    .unwrap_err()
+                    .downcast::<ErrorKind>(),
+                Ok(ErrorKind::NoSuchHook(_, ref missing))
+                    if missing == &vec!["nosuchhook".to_string()]
+            );
+        });
+    }
+
     fn hook_manager() -> HookManager {
         hook_manager_inmem()
     }
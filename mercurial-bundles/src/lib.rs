@@ -97,6 +97,8 @@ pub enum Bundle2Item {
     Changegroup(PartHeader, BoxStream<changegroup::Part, Error>),
     B2xInfinitepush(PartHeader, BoxStream<changegroup::Part, Error>),
     B2xTreegroup2(PartHeader, BoxStream<wirepack::Part, Error>),
+    B2xRebase(PartHeader, BoxStream<changegroup::Part, Error>),
+    B2xRebasePack(PartHeader, BoxStream<wirepack::Part, Error>),
     // B2xInfinitepushBookmarks returns Bytes because this part is not going to be used.
     B2xInfinitepushBookmarks(PartHeader, BoxStream<bytes::Bytes, Error>),
     Replycaps(PartHeader, BoxFuture<capabilities::Capabilities, Error>),
@@ -137,6 +139,10 @@ impl fmt::Debug for Bundle2Item {
             &B2xTreegroup2(ref header, _) => {
                 write!(f, "Bundle2Item::B2xTreegroup2({:?}, ...)", header)
             }
+            &B2xRebase(ref header, _) => write!(f, "Bundle2Item::B2xRebase({:?}, ...)", header),
+            &B2xRebasePack(ref header, _) => {
+                write!(f, "Bundle2Item::B2xRebasePack({:?}, ...)", header)
+            }
             &Replycaps(ref header, _) => write!(f, "Bundle2Item::Replycaps({:?}, ...)", header),
             &Pushkey(ref header, _) => write!(f, "Bundle2Item::Pushkey({:?}, ...)", header),
         }
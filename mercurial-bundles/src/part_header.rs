@@ -44,13 +44,23 @@ pub enum PartHeaderType {
     Pushkey,
     /// Respond to a corresponding pushkey part
     ReplyPushkey,
+    /// Contains changegroup for commits that should be rebased by the server on top of the
+    /// bookmark given in the `onto` parameter (pushrebase).
+    B2xRebase,
+    /// Contains wirepacks that are encoded TreeManifests of commits that are pushrebased.
+    B2xRebasePack,
+    /// Tells the client that the server aborted processing of the bundle2. Carries the reason of
+    /// the abort and an optional hint.
+    ErrorAbort,
+    /// Contains the revlogs of the repository for a streaming clone, to be written as they are
+    /// by the client.
+    Stream2,
     // RemoteChangegroup,       // We don't wish to support this functionality
     // CheckBookmarks,          // TODO Do we want to support this?
     // CheckHeads,              // TODO Do we want to support this?
     // CheckUpdatedHeads,       // TODO Do we want to support this?
     // CheckPhases,             // TODO Do we want to support this?
     // Output,                  // TODO Do we want to support this?
-    // ErrorAbort,              // TODO Do we want to support this?
     // ErrorPushkey,            // TODO Do we want to support this?
     // ErrorUnsupportedContent, // TODO Do we want to support this?
     // ErrorPushRaced,          // TODO Do we want to support this?
@@ -78,6 +88,10 @@ impl PartHeaderType {
             "check:heads" => Ok(CheckHeads),
             "pushkey" => Ok(Pushkey),
             "reply:pushkey" => Ok(ReplyPushkey),
+            "error:abort" => Ok(ErrorAbort),
+            "b2x:rebase" => Ok(B2xRebase),
+            "b2x:rebasepackpart" => Ok(B2xRebasePack),
+            "stream2" => Ok(Stream2),
             bad => bail_msg!("unknown header type {}", bad),
         }
     }
@@ -95,6 +109,10 @@ impl PartHeaderType {
             CheckHeads => "check:heads",
             Pushkey => "pushkey",
             ReplyPushkey => "reply:pushkey",
+            ErrorAbort => "error:abort",
+            B2xRebase => "b2x:rebase",
+            B2xRebasePack => "b2x:rebasepackpart",
+            Stream2 => "stream2",
         }
     }
 }
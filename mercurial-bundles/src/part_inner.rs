@@ -43,6 +43,9 @@ lazy_static! {
             "pushbackbookmarks", "cgversion", "bookmark", "bookprevnode", "create", "force"});
         m.insert(PartHeaderType::B2xInfinitepushBookmarks, hashset!{});
         m.insert(PartHeaderType::B2xTreegroup2, hashset!{"version", "cache", "category"});
+        m.insert(PartHeaderType::B2xRebase, hashset!{
+            "onto", "newhead", "obsmarkerversions", "cgversion"});
+        m.insert(PartHeaderType::B2xRebasePack, hashset!{"version", "cache", "category"});
         m.insert(PartHeaderType::Replycaps, hashset!{});
         m.insert(PartHeaderType::Pushkey, hashset!{ "namespace", "key", "old", "new" });
         m
@@ -100,6 +103,12 @@ pub fn inner_stream<R: AsyncRead + BufRead + 'static + Send>(
             ));
             Bundle2Item::B2xInfinitepush(header, Box::new(cg2_stream))
         }
+        &PartHeaderType::B2xRebase => {
+            let cg2_stream = wrapped_stream.decode(changegroup::unpacker::Cg2Unpacker::new(
+                logger.new(o!("stream" => "cg2")),
+            ));
+            Bundle2Item::B2xRebase(header, Box::new(cg2_stream))
+        }
         &PartHeaderType::B2xInfinitepushBookmarks => {
             let bookmarks_stream =
                 wrapped_stream.decode(infinitepush::InfinitepushBookmarksUnpacker::new());
@@ -114,6 +123,13 @@ pub fn inner_stream<R: AsyncRead + BufRead + 'static + Send>(
             ));
             Bundle2Item::B2xTreegroup2(header, Box::new(wirepack_stream))
         }
+        &PartHeaderType::B2xRebasePack => {
+            let wirepack_stream = wrapped_stream.decode(wirepack::unpacker::new(
+                logger.new(o!("stream" => "wirepack")),
+                wirepack::Kind::Tree,
+            ));
+            Bundle2Item::B2xRebasePack(header, Box::new(wirepack_stream))
+        }
         &PartHeaderType::Replycaps => {
             let caps = wrapped_stream
                 .decode(capabilities::CapabilitiesUnpacker)
@@ -9,17 +9,20 @@ use std::fmt;
 use bytes::Bytes;
 use futures::{Future, Stream};
 use futures::stream::{iter_ok, once};
-use futures_ext::BoxFuture;
+use futures_ext::{BoxFuture, BoxStream};
 
 use super::changegroup::{CgDeltaChunk, Part, Section};
 use super::changegroup::packer::Cg2Packer;
 use super::wirepack;
 use super::wirepack::packer::WirePackPacker;
 
+use chunk::Chunk;
 use errors::*;
-use mercurial_types::{Delta, HgBlobNode, HgNodeHash, MPath, MPathElement, RepoPath, NULL_HASH};
+use mercurial_types::{percent_encode, Delta, HgBlobNode, HgNodeHash, MPath, MPathElement,
+                      RepoPath, NULL_HASH};
 use part_encode::PartEncodeBuilder;
 use part_header::PartHeaderType;
+use utils::encode_uvarint;
 
 pub fn listkey_part<N, S, K, V>(namespace: N, items: S) -> Result<PartEncodeBuilder>
 where
@@ -157,6 +160,39 @@ where
     Ok(builder)
 }
 
+/// Builds a stream2 part for a streaming clone. `files` are the names of the files relative to
+/// the store, their sizes and their contents, and the client writes them as they are. The
+/// `requirements` are the ones that the client needs to support to read the files.
+pub fn stream2_part<S>(
+    requirements: &[String],
+    filecount: usize,
+    bytecount: u64,
+    files: S,
+) -> Result<PartEncodeBuilder>
+where
+    S: Stream<Item = (String, u64, BoxStream<Bytes, Error>), Error = Error> + Send + 'static,
+{
+    let mut builder = PartEncodeBuilder::mandatory(PartHeaderType::Stream2)?;
+    builder.add_mparam("requirements", percent_encode(&requirements.join(",")))?;
+    builder.add_mparam("filecount", format!("{}", filecount))?;
+    builder.add_mparam("bytecount", format!("{}", bytecount))?;
+
+    let data = files
+        .map(|(name, size, content)| {
+            // 's' tells the client that the file belongs to the store, as opposed to the cache
+            let mut header = vec![b's'];
+            encode_uvarint(name.len() as u64, &mut header);
+            encode_uvarint(size, &mut header);
+            header.extend_from_slice(name.as_bytes());
+            once(Ok(Bytes::from(header))).chain(content)
+        })
+        .flatten()
+        .and_then(Chunk::new);
+    builder.set_data_generated(data);
+
+    Ok(builder)
+}
+
 pub enum ChangegroupApplyResult {
     Success { heads_num_diff: i64 },
     Error,
@@ -219,3 +255,31 @@ pub fn replypushkey_part(res: bool, in_reply_to: u32) -> Result<PartEncodeBuilde
 
     Ok(builder)
 }
+
+/// Builds an error:abort part, which makes the client abort with the given message. Mercurial
+/// limits the size of part parameters, so both the message and the hint are truncated to fit.
+pub fn error_abort_part<M, H>(message: M, hint: Option<H>) -> Result<PartEncodeBuilder>
+where
+    M: AsRef<str>,
+    H: AsRef<str>,
+{
+    let mut builder = PartEncodeBuilder::mandatory(PartHeaderType::ErrorAbort)?;
+    builder.add_mparam("message", truncate_param(message.as_ref()))?;
+    if let Some(hint) = hint {
+        builder.add_aparam("hint", truncate_param(hint.as_ref()))?;
+    }
+
+    Ok(builder)
+}
+
+fn truncate_param(value: &str) -> String {
+    let max_len = u8::max_value() as usize;
+    if value.len() <= max_len {
+        return value.to_string();
+    }
+    let mut end = max_len;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    value[..end].to_string()
+}
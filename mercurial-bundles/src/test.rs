@@ -40,6 +40,7 @@ const BZIP2_BUNDLE2: &[u8] = include_bytes!("fixtures/bzip2.bin");
 const UNCOMP_BUNDLE2: &[u8] = include_bytes!("fixtures/uncompressed.bin");
 const UNKNOWN_COMPRESSION_BUNDLE2: &[u8] = include_bytes!("fixtures/unknown-compression.bin");
 const WIREPACK_BUNDLE2: &[u8] = include_bytes!("fixtures/wirepack.bin");
+const ZSTD_BUNDLE2: &[u8] = include_bytes!("fixtures/zstd.bin");
 
 const CHANGESET1_HASH_STR: &str = "b2040b24fd5cdfaf36e3164ddc357e834167b14a";
 const CHANGESET2_HASH_STR: &str = "415ab71954c98ea93dab4b8f61f04ca57bc5c33c";
@@ -59,6 +60,17 @@ fn parse_bzip2(read_ops: PartialWithErrors<GenWouldBlock>) {
     parse_bundle(BZIP2_BUNDLE2, Some("BZ"), read_ops);
 }
 
+#[test]
+fn test_parse_zstd() {
+    let rng = StdGen::new(rand::thread_rng(), 20);
+    let mut quickcheck = QuickCheck::new().gen(rng);
+    quickcheck.quickcheck(parse_zstd as fn(PartialWithErrors<GenWouldBlock>) -> ());
+}
+
+fn parse_zstd(read_ops: PartialWithErrors<GenWouldBlock>) {
+    parse_bundle(ZSTD_BUNDLE2, Some("ZS"), read_ops);
+}
+
 #[test]
 fn test_parse_uncompressed() {
     let rng = StdGen::new(rand::thread_rng(), 20);
@@ -89,6 +101,11 @@ fn test_empty_bundle_roundtrip_gzip() {
     empty_bundle_roundtrip(Some(CompressorType::Gzip(FlateCompression::best())));
 }
 
+#[test]
+fn test_empty_bundle_roundtrip_zstd() {
+    empty_bundle_roundtrip(Some(CompressorType::Zstd { level: 0 }));
+}
+
 #[test]
 fn test_empty_bundle_roundtrip_uncompressed() {
     empty_bundle_roundtrip(None);
@@ -148,6 +165,11 @@ fn test_unknown_part_gzip() {
     unknown_part(Some(CompressorType::Gzip(FlateCompression::best())));
 }
 
+#[test]
+fn test_unknown_part_zstd() {
+    unknown_part(Some(CompressorType::Zstd { level: 0 }));
+}
+
 #[test]
 fn test_unknown_part_uncompressed() {
     unknown_part(None);
@@ -150,6 +150,16 @@ pub fn get_compression_param(ct: &Option<CompressorType>) -> &'static str {
     }
 }
 
+/// Encode an unsigned integer the way Mercurial's `util.uvarintencode` does: 7 bits per byte,
+/// least significant first, with the high bit set on every byte but the last.
+pub fn encode_uvarint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
 pub fn capitalize_first(s: String) -> String {
     // Capitalize Unicode style, since capitalizing a single code point can
     // produce multiple code points.
@@ -177,4 +187,19 @@ mod test {
             "'123': first char '1' is not alphabetic"
         );
     }
+
+    #[test]
+    fn test_encode_uvarint() {
+        let f = |x: u64| {
+            let mut out = vec![];
+            encode_uvarint(x, &mut out);
+            out
+        };
+
+        assert_eq!(f(0), vec![0x00]);
+        assert_eq!(f(1), vec![0x01]);
+        assert_eq!(f(127), vec![0x7f]);
+        assert_eq!(f(1337), vec![0xb9, 0x0a]);
+        assert_eq!(f(65536), vec![0x80, 0x80, 0x04]);
+    }
 }
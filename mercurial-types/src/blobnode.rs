@@ -112,24 +112,51 @@ impl HgBlobNode {
     // sha1(p1 || p2 || sha1(content)), so we can't compute a filenode for
     // a blob we don't have
     pub fn nodeid(&self) -> Option<HgNodeHash> {
+        self.as_blob().as_slice().map(|data| {
+            let mut hasher = HgNodeHasher::from_parents(&self.parents);
+            hasher.update(data);
+            hasher.finish()
+        })
+    }
+}
+
+/// Computes the node hash of a blob incrementally, so that the blob doesn't have to be held in
+/// memory at once. Hashing all of a blob with the same parents gives the same result as
+/// `HgBlobNode::nodeid`.
+#[derive(Clone)]
+pub struct HgNodeHasher(Context);
+
+impl HgNodeHasher {
+    pub fn new(p1: Option<&HgNodeHash>, p2: Option<&HgNodeHash>) -> Self {
+        Self::from_parents(&HgParents::new(p1, p2))
+    }
+
+    fn from_parents(parents: &HgParents) -> Self {
         let null = hash::NULL;
 
-        let (h1, h2) = match &self.parents {
+        let (h1, h2) = match parents {
             &HgParents::None => (&null, &null),
             &HgParents::One(ref p1) => (&null, &p1.0),
             &HgParents::Two(ref p1, ref p2) if p1 > p2 => (&p2.0, &p1.0),
             &HgParents::Two(ref p1, ref p2) => (&p1.0, &p2.0),
         };
 
-        self.as_blob().as_slice().map(|data| {
-            let mut ctxt = Context::new();
+        let mut ctxt = Context::new();
+        ctxt.update(h1);
+        ctxt.update(h2);
+        HgNodeHasher(ctxt)
+    }
 
-            ctxt.update(h1);
-            ctxt.update(h2);
-            ctxt.update(data);
+    /// Hash the next part of the blob
+    pub fn update<T>(&mut self, data: T)
+    where
+        T: AsRef<[u8]>,
+    {
+        self.0.update(data)
+    }
 
-            HgNodeHash(ctxt.finish())
-        })
+    pub fn finish(self) -> HgNodeHash {
+        HgNodeHash(self.0.finish())
     }
 }
 
@@ -208,4 +235,31 @@ mod test {
         };
         assert_eq!(node1, node2);
     }
+
+    #[test]
+    fn test_hasher() {
+        let p1 = HgBlobNode::new(HgBlob::from(Bytes::from(&b"foo1"[..])), None, None)
+            .nodeid()
+            .expect("no nodeid 1");
+        let p2 = HgBlobNode::new(HgBlob::from(Bytes::from(&b"foo2"[..])), None, None)
+            .nodeid()
+            .expect("no nodeid 2");
+
+        for &(p1, p2) in &[
+            (None, None),
+            (Some(&p1), None),
+            (None, Some(&p2)),
+            (Some(&p1), Some(&p2)),
+            (Some(&p2), Some(&p1)),
+        ] {
+            let expected = HgBlobNode::new(HgBlob::from(Bytes::from(&b"foobar"[..])), p1, p2)
+                .nodeid()
+                .expect("no nodeid");
+
+            let mut hasher = HgNodeHasher::new(p1, p2);
+            hasher.update(b"foo");
+            hasher.update(b"bar");
+            assert_eq!(hasher.finish(), expected);
+        }
+    }
 }
@@ -134,6 +134,55 @@ impl FromStr for Sha1 {
     }
 }
 
+/// Range of `Sha1` hashes that start with the same hex digits. Used to resolve abbreviated
+/// hashes: every hash with this prefix lies between `min` and `max` inclusive.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Sha1Prefix(Sha1, Sha1);
+
+impl Sha1Prefix {
+    /// The smallest hash with this prefix
+    pub fn min(&self) -> Sha1 {
+        self.0
+    }
+
+    /// The largest hash with this prefix
+    pub fn max(&self) -> Sha1 {
+        self.1
+    }
+}
+
+impl FromStr for Sha1Prefix {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Sha1Prefix> {
+        if s.is_empty() || s.len() > 40 {
+            bail_err!(ErrorKind::InvalidSha1Input(
+                "need between 1 and 40 hex digits".into()
+            ));
+        }
+
+        let mut min = Sha1([0x00; 20]);
+        let mut max = Sha1([0xff; 20]);
+
+        for (idx, c) in s.chars().enumerate() {
+            let digit = match c.to_digit(16) {
+                Some(v) => v as u8,
+                None => bail_err!(ErrorKind::InvalidSha1Input("bad digit".into())),
+            };
+            let byte = idx / 2;
+            if idx % 2 == 0 {
+                min.0[byte] = digit << 4;
+                max.0[byte] = (digit << 4) | 0x0f;
+            } else {
+                min.0[byte] |= digit;
+                max.0[byte] = (max.0[byte] & 0xf0) | digit;
+            }
+        }
+
+        Ok(Sha1Prefix(min, max))
+    }
+}
+
 impl Display for Sha1 {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         Display::fmt(&self.to_hex(), fmt)
@@ -264,6 +313,31 @@ mod test {
         };
     }
 
+    #[test]
+    fn parse_prefix() {
+        let prefix = Sha1Prefix::from_str("da39A").unwrap();
+        assert_eq!(
+            prefix.min(),
+            Sha1::from_str("da39a00000000000000000000000000000000000").unwrap()
+        );
+        assert_eq!(
+            prefix.max(),
+            Sha1::from_str("da39afffffffffffffffffffffffffffffffffff").unwrap()
+        );
+
+        let prefix = Sha1Prefix::from_str("da39a3ee5e6b4b0d3255bfef95601890afd80709").unwrap();
+        assert_eq!(prefix.min(), NILHASH);
+        assert_eq!(prefix.max(), NILHASH);
+    }
+
+    #[test]
+    fn parse_prefix_bad() {
+        Sha1Prefix::from_str("").expect_err("unexpected OK - zero len");
+        Sha1Prefix::from_str("da39x").expect_err("unexpected OK - bad digit");
+        Sha1Prefix::from_str("da39a3ee5e6b4b0d3255bfef95601890afd807090")
+            .expect_err("unexpected OK - too long");
+    }
+
     #[test]
     fn parse_thrift() {
         let null_thrift = thrift::Sha1(vec![0; 20]);
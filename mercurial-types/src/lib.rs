@@ -98,7 +98,7 @@ mod node;
 mod envelope;
 
 pub use blob::{HgBlob, HgBlobHash};
-pub use blobnode::{HgBlobNode, HgParents};
+pub use blobnode::{HgBlobNode, HgNodeHasher, HgParents};
 pub use changeset::Changeset;
 pub use delta::Delta;
 pub use envelope::{HgChangesetEnvelope, HgChangesetEnvelopeMut, HgFileEnvelope, HgFileEnvelopeMut,
@@ -106,8 +106,8 @@ pub use envelope::{HgChangesetEnvelope, HgChangesetEnvelopeMut, HgFileEnvelope,
 pub use fsencode::{fncache_fsencode, simple_fsencode};
 pub use manifest::{Entry, Manifest, Type};
 pub use node::Node;
-pub use nodehash::{HgChangesetId, HgEntryId, HgFileNodeId, HgManifestId, HgNodeHash, HgNodeKey,
-                   NULL_HASH};
+pub use nodehash::{HgChangesetId, HgChangesetIdPrefix, HgEntryId, HgFileNodeId, HgManifestId,
+                   HgNodeHash, HgNodeKey, NULL_HASH};
 pub use repo::RepositoryId;
 pub use utils::percent_encode;
 
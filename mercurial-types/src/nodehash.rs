@@ -17,7 +17,7 @@ use thrift;
 
 use RepoPath;
 use errors::*;
-use hash::{self, Sha1};
+use hash::{self, Sha1, Sha1Prefix};
 use sql_types::{HgChangesetIdSql, HgFileNodeIdSql, HgManifestIdSql};
 
 pub const NULL_HASH: HgNodeHash = HgNodeHash(hash::NULL);
@@ -244,6 +244,32 @@ impl<'de> serde::de::Deserialize<'de> for HgChangesetId {
     }
 }
 
+/// An abbreviated hex representation of a changeset id, as accepted by `hg` commands
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Hash)]
+pub struct HgChangesetIdPrefix(Sha1Prefix);
+
+impl HgChangesetIdPrefix {
+    /// The smallest changeset id with this prefix
+    #[inline]
+    pub fn min_cs(&self) -> HgChangesetId {
+        HgChangesetId(HgNodeHash(self.0.min()))
+    }
+
+    /// The largest changeset id with this prefix
+    #[inline]
+    pub fn max_cs(&self) -> HgChangesetId {
+        HgChangesetId(HgNodeHash(self.0.max()))
+    }
+}
+
+impl FromStr for HgChangesetIdPrefix {
+    type Err = <Sha1Prefix as FromStr>::Err;
+
+    fn from_str(s: &str) -> result::Result<HgChangesetIdPrefix, Self::Err> {
+        Sha1Prefix::from_str(s).map(HgChangesetIdPrefix)
+    }
+}
+
 #[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug, Hash)]
 #[derive(HeapSizeOf, FromSqlRow, AsExpression)]
 #[sql_type = "HgManifestIdSql"]
@@ -24,7 +24,8 @@ pub struct File {
 const META_MARKER: &[u8] = b"\x01\n";
 const COPY_PATH_KEY: &[u8] = b"copy";
 const COPY_REV_KEY: &[u8] = b"copyrev";
-const META_SZ: usize = 2;
+/// How many bytes at the start of the contents `generate_metadata` looks at
+pub const META_SZ: usize = 2;
 
 impl File {
     pub fn new<B: Into<HgBlob>>(blob: B, p1: Option<&HgNodeHash>, p2: Option<&HgNodeHash>) -> Self {
@@ -42,6 +42,9 @@ extern crate quickcheck;
 
 extern crate serde;
 
+#[cfg(test)]
+extern crate tempdir;
+
 extern crate asyncmemo;
 extern crate mercurial_types;
 extern crate mercurial_types_mocks;
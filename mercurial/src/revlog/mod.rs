@@ -27,6 +27,7 @@ pub use mercurial_types::bdiff::{self, Delta};
 mod parser;
 mod revidx;
 mod lz4;
+mod writer;
 
 #[cfg(test)]
 mod test;
@@ -34,6 +35,7 @@ mod test;
 use self::parser::{Header, Version};
 pub use self::parser::Entry;
 pub use self::revidx::RevIdx;
+pub use self::writer::{RevisionWriter, RevlogWriter};
 
 #[derive(Debug)]
 enum Datafile {
@@ -241,6 +243,11 @@ impl Revlog {
     pub fn get_heads(&self) -> Result<HashSet<HgNodeHash>> {
         self.inner.get_heads()
     }
+
+    /// Return the index of the last revision, or `None` if the revlog is empty.
+    pub fn get_tip_idx(&self) -> Option<RevIdx> {
+        self.inner.idxoff.keys().next_back().cloned()
+    }
 }
 
 impl RevlogInner {
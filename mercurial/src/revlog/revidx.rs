@@ -59,6 +59,13 @@ impl From<usize> for RevIdx {
     }
 }
 
+// Convert a `RevIdx` back into a `u32`
+impl From<RevIdx> for u32 {
+    fn from(v: RevIdx) -> Self {
+        v.0
+    }
+}
+
 // Construct a `RevIdx` from a string (which may fail)
 impl FromStr for RevIdx {
     type Err = <u32 as FromStr>::Err;
@@ -0,0 +1,497 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use bytes::BufMut;
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+
+use errors::*;
+use mercurial_types::HgNodeHash;
+
+use super::parser::{self, Features, Version};
+use super::revidx::RevIdx;
+
+/// Texts shorter than this are not worth compressing, which is what Mercurial does as well
+const MIN_COMPRESS_LEN: usize = 44;
+/// Longest chain of deltas that has to be applied to get a text
+const MAX_CHAIN_LEN: usize = 1000;
+
+/// Deltas that have to be read to reconstruct a revision
+#[derive(Copy, Clone, Debug)]
+struct Chain {
+    len: usize,
+    size: usize,
+}
+
+/// How a revision was written to the data file
+#[derive(Copy, Clone, Debug)]
+struct Stored {
+    baserev: RevIdx,
+    chunk_len: u64,
+    text_len: u64,
+    chain: Chain,
+}
+
+/// `RevlogWriter` creates a new Mercurial revlog and appends revisions to it
+///
+/// The revlog is written in the RevlogNG format with generaldelta and with the data in a
+/// separate file. A revision is stored as a delta against its first parent if that is the
+/// revision added right before it, and as a full text otherwise or when the chain of deltas gets
+/// too long to be worth it.
+#[derive(Debug)]
+pub struct RevlogWriter {
+    idxpath: PathBuf,
+    datapath: PathBuf,
+    dataoff: u64,
+    nodeidx: HashMap<HgNodeHash, RevIdx>,
+    chains: Vec<Chain>,
+    last: Option<(RevIdx, Vec<u8>)>, // full text of the last revision, the next delta base
+}
+
+impl RevlogWriter {
+    /// Create an empty revlog with its index at `idxpath` and its data at `datapath`, replacing
+    /// the files if they exist already.
+    pub fn create<IP, DP>(idxpath: IP, datapath: DP) -> Result<Self>
+    where
+        IP: AsRef<Path>,
+        DP: AsRef<Path>,
+    {
+        let idxpath = idxpath.as_ref().to_path_buf();
+        let datapath = datapath.as_ref().to_path_buf();
+        for path in &[&idxpath, &datapath] {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).with_context(|_| format!("Can't create {:?}", dir))?;
+            }
+            File::create(path).with_context(|_| format!("Can't create {:?}", path))?;
+        }
+
+        Ok(RevlogWriter {
+            idxpath,
+            datapath,
+            dataoff: 0,
+            nodeidx: HashMap::new(),
+            chains: Vec::new(),
+            last: None,
+        })
+    }
+
+    /// Return the number of revisions in the revlog.
+    pub fn len(&self) -> usize {
+        self.chains.len()
+    }
+
+    /// Return `true` if a revision with the given nodeid was added.
+    pub fn contains(&self, nodeid: &HgNodeHash) -> bool {
+        self.nodeidx.contains_key(nodeid)
+    }
+
+    /// Return the ordinal index of an entry with the given nodeid.
+    pub fn get_idx_by_nodeid(&self, nodeid: &HgNodeHash) -> Result<RevIdx> {
+        match self.nodeidx.get(nodeid).cloned() {
+            Some(idx) => Ok(idx),
+            None => Err(ErrorKind::Revlog(format!("nodeid {} not found", nodeid)).into()),
+        }
+    }
+
+    /// Append the revision `nodeid` with the full text `text` and return its index. The parents
+    /// have to be in the revlog already, `linkrev` is the index of the changeset that introduced
+    /// the revision. Adding a revision that is in the revlog already does nothing.
+    pub fn add_revision(
+        &mut self,
+        nodeid: &HgNodeHash,
+        p1: Option<&HgNodeHash>,
+        p2: Option<&HgNodeHash>,
+        linkrev: RevIdx,
+        text: &[u8],
+    ) -> Result<RevIdx> {
+        if let Some(idx) = self.nodeidx.get(nodeid) {
+            return Ok(*idx);
+        }
+        let p1 = match p1 {
+            Some(p1) => Some(self.get_idx_by_nodeid(p1)?),
+            None => None,
+        };
+        let p2 = match p2 {
+            Some(p2) => Some(self.get_idx_by_nodeid(p2)?),
+            None => None,
+        };
+        let idx = RevIdx::from(self.chains.len());
+
+        let (baserev, chunk, chain) = match self.delta_against_p1(p1, text)? {
+            Some(delta) => delta,
+            None => {
+                let chunk = compress(text)?;
+                let chain = Chain {
+                    len: 1,
+                    size: chunk.len(),
+                };
+                (idx, chunk, chain)
+            }
+        };
+
+        append(&self.datapath, &chunk)?;
+        let stored = Stored {
+            baserev,
+            chunk_len: chunk.len() as u64,
+            text_len: text.len() as u64,
+            chain,
+        };
+        self.append_index(nodeid, p1, p2, linkrev, stored)?;
+        self.last = Some((idx, text.to_vec()));
+        Ok(idx)
+    }
+
+    /// Start appending the revision `nodeid`, whose full text is then passed piece by piece to
+    /// the returned `RevisionWriter` and which is added by `finish_revision`. The text is never
+    /// held in memory, so it is stored in full rather than as a delta. No other revision can be
+    /// added to the revlog until this one is finished.
+    pub fn start_revision(
+        &self,
+        nodeid: &HgNodeHash,
+        p1: Option<&HgNodeHash>,
+        p2: Option<&HgNodeHash>,
+        linkrev: RevIdx,
+    ) -> Result<RevisionWriter> {
+        if self.contains(nodeid) {
+            let msg = format!("nodeid {} is in the revlog already", nodeid);
+            return Err(ErrorKind::Revlog(msg).into());
+        }
+        let p1 = match p1 {
+            Some(p1) => Some(self.get_idx_by_nodeid(p1)?),
+            None => None,
+        };
+        let p2 = match p2 {
+            Some(p2) => Some(self.get_idx_by_nodeid(p2)?),
+            None => None,
+        };
+        let data = OpenOptions::new()
+            .append(true)
+            .open(&self.datapath)
+            .with_context(|_| format!("Can't open {:?}", self.datapath))?;
+
+        Ok(RevisionWriter {
+            nodeid: *nodeid,
+            p1,
+            p2,
+            linkrev,
+            dataoff: self.dataoff,
+            text_len: 0,
+            encoder: ZlibEncoder::new(data, Compression::default()),
+        })
+    }
+
+    /// Add the revision that was written with `revision` and return its index.
+    pub fn finish_revision(&mut self, revision: RevisionWriter) -> Result<RevIdx> {
+        if revision.dataoff != self.dataoff || self.contains(&revision.nodeid) {
+            let msg = format!("revision {} was written concurrently", revision.nodeid);
+            return Err(ErrorKind::Revlog(msg).into());
+        }
+        let data = revision
+            .encoder
+            .finish()
+            .with_context(|_| format!("Can't write to {:?}", self.datapath))?;
+        let chunk_len = data.metadata()?.len() - self.dataoff;
+
+        let idx = RevIdx::from(self.chains.len());
+        let stored = Stored {
+            baserev: idx,
+            chunk_len,
+            text_len: revision.text_len,
+            chain: Chain {
+                len: 1,
+                size: chunk_len as usize,
+            },
+        };
+        self.append_index(
+            &revision.nodeid,
+            revision.p1,
+            revision.p2,
+            revision.linkrev,
+            stored,
+        )?;
+        self.last = None;
+        Ok(idx)
+    }
+
+    // Append the index entry of a revision whose chunk was just appended to the data file
+    fn append_index(
+        &mut self,
+        nodeid: &HgNodeHash,
+        p1: Option<RevIdx>,
+        p2: Option<RevIdx>,
+        linkrev: RevIdx,
+        stored: Stored,
+    ) -> Result<RevIdx> {
+        // The index stores both lengths as 32 bit integers
+        if stored.chunk_len > u32::max_value() as u64 || stored.text_len > u32::max_value() as u64
+        {
+            let msg = format!("revision {} is too large for a revlog", nodeid);
+            return Err(ErrorKind::Revlog(msg).into());
+        }
+        let idx = RevIdx::from(self.chains.len());
+
+        let mut entry = Vec::with_capacity(parser::indexng_size());
+        entry.put_u64_be(self.dataoff << 16);
+        entry.put_u32_be(stored.chunk_len as u32);
+        entry.put_u32_be(stored.text_len as u32);
+        entry.put_u32_be(stored.baserev.into());
+        entry.put_u32_be(linkrev.into());
+        entry.put_u32_be(p1.map_or(!0, Into::into));
+        entry.put_u32_be(p2.map_or(!0, Into::into));
+        entry.put_slice(nodeid.as_bytes());
+        entry.put_slice(&[0; 12]);
+        if idx == RevIdx::zero() {
+            // The header overlaps the offset of the first entry, which is always 0
+            let mut header = Vec::with_capacity(4);
+            header.put_u16_be(Features::GENERAL_DELTA.bits());
+            header.put_u16_be(Version::RevlogNG as u16);
+            entry[..4].copy_from_slice(&header);
+        }
+        append(&self.idxpath, &entry)?;
+
+        self.dataoff += stored.chunk_len;
+        self.nodeidx.insert(*nodeid, idx);
+        self.chains.push(stored.chain);
+        Ok(idx)
+    }
+
+    // Compute the chunk that stores `text` as a delta against `p1`, if that is cheaper than
+    // storing it in full.
+    fn delta_against_p1(
+        &self,
+        p1: Option<RevIdx>,
+        text: &[u8],
+    ) -> Result<Option<(RevIdx, Vec<u8>, Chain)>> {
+        let (lastidx, lasttext) = match self.last {
+            Some((lastidx, ref lasttext)) if Some(lastidx) == p1 => (lastidx, lasttext),
+            _ => return Ok(None),
+        };
+        let base = self.chains[u32::from(lastidx) as usize];
+        if base.len >= MAX_CHAIN_LEN {
+            return Ok(None);
+        }
+
+        let chunk = compress(&compute_delta(lasttext, text))?;
+        let chain = Chain {
+            len: base.len + 1,
+            size: base.size + chunk.len(),
+        };
+        // Like Mercurial, don't read more than twice the size of the text to reconstruct it
+        if chain.size > 2 * text.len() {
+            Ok(None)
+        } else {
+            Ok(Some((lastidx, chunk, chain)))
+        }
+    }
+}
+
+/// The text of a revision that is being appended to a revlog, see
+/// `RevlogWriter::start_revision`
+pub struct RevisionWriter {
+    nodeid: HgNodeHash,
+    p1: Option<RevIdx>,
+    p2: Option<RevIdx>,
+    linkrev: RevIdx,
+    dataoff: u64,
+    text_len: u64,
+    encoder: ZlibEncoder<File>,
+}
+
+impl RevisionWriter {
+    /// Append `data` to the text of the revision.
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.encoder.write_all(data)?;
+        self.text_len += data.len() as u64;
+        Ok(())
+    }
+}
+
+fn append(path: &Path, data: &[u8]) -> Result<()> {
+    OpenOptions::new()
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(data))
+        .with_context(|_| format!("Can't write to {:?}", path))?;
+    Ok(())
+}
+
+// A delta that replaces everything between the common prefix and suffix of `base` and `text`
+fn compute_delta(base: &[u8], text: &[u8]) -> Vec<u8> {
+    let prefix = base.iter()
+        .zip(text.iter())
+        .take_while(|&(b, t)| b == t)
+        .count();
+    let suffix = base[prefix..]
+        .iter()
+        .rev()
+        .zip(text[prefix..].iter().rev())
+        .take_while(|&(b, t)| b == t)
+        .count();
+    let content = &text[prefix..text.len() - suffix];
+
+    let mut delta = Vec::with_capacity(12 + content.len());
+    delta.put_u32_be(prefix as u32);
+    delta.put_u32_be((base.len() - suffix) as u32);
+    delta.put_u32_be(content.len() as u32);
+    delta.put_slice(content);
+    delta
+}
+
+// Compress a chunk the way Mercurial does, so that the parser can tell from the first byte how
+// it is stored
+fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() >= MIN_COMPRESS_LEN {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        let compressed = encoder.finish()?;
+        if compressed.len() < data.len() {
+            return Ok(compressed);
+        }
+    }
+
+    if data.is_empty() || data[0] == b'\0' {
+        Ok(data.to_vec())
+    } else {
+        let mut chunk = Vec::with_capacity(data.len() + 1);
+        chunk.push(b'u');
+        chunk.extend_from_slice(data);
+        Ok(chunk)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bytes::Bytes;
+    use tempdir::TempDir;
+
+    use mercurial_types::HgBlobNode;
+    use revlog::Revlog;
+
+    fn add(
+        writer: &mut RevlogWriter,
+        text: &[u8],
+        p1: Option<&HgNodeHash>,
+        p2: Option<&HgNodeHash>,
+    ) -> HgNodeHash {
+        let nodeid = HgBlobNode::new(Bytes::from(text), p1, p2)
+            .nodeid()
+            .expect("no nodeid");
+        let linkrev = RevIdx::from(writer.len());
+        writer
+            .add_revision(&nodeid, p1, p2, linkrev, text)
+            .expect("failed to add revision");
+        nodeid
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let tmp = TempDir::new("revlog_writer").expect("failed to create temp dir");
+        let idxpath = tmp.path().join("store/data/file.i");
+        let datapath = tmp.path().join("store/data/file.d");
+        let mut writer = RevlogWriter::create(&idxpath, &datapath).expect("failed to create");
+
+        let long: Vec<u8> = (0..1000).flat_map(|i| format!("line {}\n", i).into_bytes()).collect();
+        let mut changed = long.clone();
+        changed[500] = b'x';
+
+        let texts: Vec<&[u8]> = vec![
+            &b""[..],
+            &b"\0binary"[..],
+            &b"short"[..],
+            &long[..],
+            &changed[..],
+            &long[..],
+        ];
+        let n0 = add(&mut writer, texts[0], None, None);
+        let n1 = add(&mut writer, texts[1], Some(&n0), None);
+        let n2 = add(&mut writer, texts[2], None, None);
+        let n3 = add(&mut writer, texts[3], Some(&n2), Some(&n1));
+        let n4 = add(&mut writer, texts[4], Some(&n3), None);
+        let n5 = add(&mut writer, texts[5], Some(&n3), None);
+        assert_eq!(writer.get_idx_by_nodeid(&n2).unwrap(), RevIdx::from(2u32));
+        assert!(writer.get_idx_by_nodeid(&n0).is_ok());
+
+        let revlog = Revlog::from_idx_with_data(&idxpath, Some(&datapath)).expect("failed to read");
+        for (idx, (text, nodeid)) in texts.iter().zip(vec![n0, n1, n2, n3, n4, n5]).enumerate() {
+            let idx = RevIdx::from(idx);
+            let node = revlog.get_rev(idx).expect("failed to get rev");
+            assert_eq!(node.as_blob().as_slice(), Some(*text));
+            assert_eq!(node.nodeid(), Some(nodeid));
+            assert_eq!(revlog.get_idx_by_nodeid(&nodeid).unwrap(), idx);
+        }
+
+        // The change in the middle of the long text is stored as a delta
+        let entry = revlog.get_entry(RevIdx::from(4u32)).unwrap();
+        assert_eq!(entry.baserev, Some(RevIdx::from(3u32)));
+        assert!(entry.compressed_len < 100);
+        // Not a child of the previous revision, so stored in full
+        let entry = revlog.get_entry(RevIdx::from(5u32)).unwrap();
+        assert_eq!(entry.baserev, Some(RevIdx::from(5u32)));
+    }
+
+    #[test]
+    fn test_write_streamed() {
+        let tmp = TempDir::new("revlog_writer").expect("failed to create temp dir");
+        let idxpath = tmp.path().join("a.i");
+        let datapath = tmp.path().join("a.d");
+        let mut writer = RevlogWriter::create(&idxpath, &datapath).expect("failed to create");
+
+        let n0 = add(&mut writer, b"first", None, None);
+        let text: Vec<u8> = (0..1000).flat_map(|i| format!("line {}\n", i).into_bytes()).collect();
+        let n1 = HgBlobNode::new(Bytes::from(&text[..]), Some(&n0), None)
+            .nodeid()
+            .expect("no nodeid");
+        let mut revision = writer
+            .start_revision(&n1, Some(&n0), None, RevIdx::from(1u32))
+            .expect("failed to start revision");
+        for piece in text.chunks(100) {
+            revision.write(piece).expect("failed to write");
+        }
+        writer
+            .finish_revision(revision)
+            .expect("failed to finish revision");
+        let n2 = add(&mut writer, b"last", Some(&n1), None);
+
+        let revlog = Revlog::from_idx_with_data(&idxpath, Some(&datapath)).expect("failed to read");
+        for (idx, (text, nodeid)) in vec![&b"first"[..], &text[..], &b"last"[..]]
+            .into_iter()
+            .zip(vec![n0, n1, n2])
+            .enumerate()
+        {
+            let node = revlog.get_rev(RevIdx::from(idx)).expect("failed to get rev");
+            assert_eq!(node.as_blob().as_slice(), Some(text));
+            assert_eq!(node.nodeid(), Some(nodeid));
+        }
+        // The streamed text is stored in full, and nothing is stored as a delta against it
+        let entry = revlog.get_entry(RevIdx::from(1u32)).unwrap();
+        assert_eq!(entry.baserev, Some(RevIdx::from(1u32)));
+        let entry = revlog.get_entry(RevIdx::from(2u32)).unwrap();
+        assert_eq!(entry.baserev, Some(RevIdx::from(2u32)));
+    }
+
+    #[test]
+    fn test_missing_parent() {
+        let tmp = TempDir::new("revlog_writer").expect("failed to create temp dir");
+        let mut writer =
+            RevlogWriter::create(tmp.path().join("a.i"), tmp.path().join("a.d")).unwrap();
+        let nodeid = HgNodeHash::from_bytes(&[1; 20]).unwrap();
+        let parent = HgNodeHash::from_bytes(&[2; 20]).unwrap();
+
+        assert!(
+            writer
+                .add_revision(&nodeid, Some(&parent), None, RevIdx::zero(), b"text")
+                .is_err()
+        );
+        assert_eq!(writer.len(), 0);
+    }
+}
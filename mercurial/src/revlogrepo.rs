@@ -28,7 +28,7 @@ use storage_types::Version;
 pub use changeset::RevlogChangeset;
 use errors::*;
 pub use manifest::RevlogManifest;
-use revlog::{Revlog, RevlogIter};
+use revlog::{RevIdx, Revlog, RevlogIter};
 
 const DEFAULT_LOGS_CAPACITY: usize = 1000000;
 
@@ -202,6 +202,31 @@ impl RevlogRepo {
         ChangesetStream::new(&self.changelog)
     }
 
+    /// Stream of the changesets starting at the revision `idx`, in revlog order
+    pub fn changesets_from(&self, idx: RevIdx) -> ChangesetStream {
+        let mut iter = (&self.changelog).into_iter();
+        iter.seek(idx);
+        ChangesetStream(iter)
+    }
+
+    /// Stream of the changesets with their revision indexes, from the tip down to revision 0
+    pub fn changesets_reversed(&self) -> BoxStream<(RevIdx, HgNodeHash), Error> {
+        let changelog = self.changelog.clone();
+        let tip = changelog.get_tip_idx();
+        stream::unfold(tip, move |idx| {
+            idx.map(|idx| {
+                let next = if idx == RevIdx::zero() {
+                    None
+                } else {
+                    Some(idx.pred())
+                };
+                changelog
+                    .get_entry(idx)
+                    .map(|entry| ((idx, entry.nodeid), next))
+            })
+        }).boxify()
+    }
+
     pub fn get_changeset(&self, changesetid: &HgChangesetId) -> BoxFuture<RevlogChangeset, Error> {
         // TODO: (jsgf) T17932873 distinguish between not existing vs some other error
         let nodeid = changesetid.clone().into_nodehash();
@@ -11,7 +11,6 @@
 #![feature(try_from)]
 
 extern crate bookmarks;
-#[cfg(test)]
 extern crate bytes;
 #[macro_use]
 extern crate failure_ext as failure;
@@ -22,6 +21,8 @@ extern crate maplit;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[cfg(test)]
+extern crate tempdir;
 extern crate toml;
 
 extern crate blobrepo;
@@ -35,6 +36,6 @@ extern crate vfs;
 pub mod errors;
 pub mod repoconfig;
 
-pub use repoconfig::{CacheWarmupParams, RepoConfigs};
+pub use repoconfig::{CacheWarmupParams, ChunkingParams, LfsParams, RepoConfigs};
 
 pub use errors::{Error, ErrorKind};
@@ -9,9 +9,10 @@
 
 use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::from_utf8;
 
+use bytes::Bytes;
 use failure::FutureFailureErrorExt;
 use futures::{future, Future, IntoFuture};
 
@@ -20,9 +21,9 @@ use bookmarks::Bookmark;
 use mercurial_types::{Changeset, MPath, MPathElement, Manifest};
 use mercurial_types::manifest::Content;
 use mercurial_types::nodehash::HgChangesetId;
-use mononoke_types::FileContents;
+use mononoke_types::{Chunker, FileContents};
 use toml;
-use vfs::{vfs_from_manifest, ManifestVfsDir, ManifestVfsFile, VfsDir, VfsFile, VfsNode, VfsWalker};
+use vfs::{vfs_from_directory, vfs_from_manifest, VfsDir, VfsFile, VfsNode, VfsWalker};
 
 use errors::*;
 
@@ -43,6 +44,12 @@ pub struct RepoConfig {
     pub bookmarks: Option<Vec<BookmarkParams>>,
     /// Configuration for hooks
     pub hooks: Option<Vec<HookParams>>,
+    /// Sizes of the cache of hook results. If not set then default sizes are used.
+    pub hook_manager_params: Option<HookManagerParams>,
+    /// Configuration for storing large file contents out of band
+    pub lfs: Option<LfsParams>,
+    /// Configuration for storing huge file contents as chunks
+    pub chunking: Option<ChunkingParams>,
 }
 
 /// Configuration of warming up the Mononoke cache. This warmup happens on startup
@@ -55,6 +62,30 @@ pub struct CacheWarmupParams {
     pub commit_limit: usize,
 }
 
+/// Configuration for storing large file contents out of band. If not set, all file contents are
+/// stored in the blobstore.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LfsParams {
+    /// Contents of files larger than this many bytes are stored in the LFS blobstore
+    pub threshold: u64,
+    /// Whether getfiles sends the pointers to contents stored in the LFS blobstore instead of
+    /// the contents, for clients with Mercurial's lfs extension that fetch them over HTTP
+    pub send_pointers: bool,
+}
+
+/// Configuration for splitting huge file contents into chunks, so that they can be streamed
+/// without being held in memory. If not set, file contents are stored as single blobs.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ChunkingParams {
+    /// Contents of files larger than this many bytes are stored as chunks
+    pub threshold: u64,
+    /// How the contents are split into chunks
+    pub chunker: Chunker,
+    /// Getfiles streams the contents of files larger than this many bytes uncompressed, instead
+    /// of compressing them in memory. If not set, the server picks the threshold.
+    pub stream_threshold: Option<u64>,
+}
+
 /// Configuration for a bookmark
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct BookmarkParams {
@@ -62,6 +93,14 @@ pub struct BookmarkParams {
     pub name: String,
     /// The hooks active for the bookmark
     pub hooks: Option<Vec<String>>,
+    /// Whether changesets reachable from the bookmark are public
+    pub publishing: bool,
+    /// Whether a push may only move the bookmark to a descendant of where it points
+    pub only_fast_forward: bool,
+    /// Whether a push may delete the bookmark
+    pub allow_delete: bool,
+    /// The users that may move the bookmark with a push. If not set then anyone may move it.
+    pub allowed_users: Option<Vec<String>>,
 }
 
 /// Configuration for a hook
@@ -69,8 +108,28 @@ pub struct BookmarkParams {
 pub struct HookParams {
     /// The name of the hook
     pub name: String,
-    /// The path to the hook
+    /// The path to the hook within the config repo
     pub path: String,
+    /// The code of the hook, read from `path` when the config repo is parsed
+    pub code: Option<String>,
+}
+
+/// Configuration of the cache of the results of running hooks on changesets
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HookManagerParams {
+    /// Maximum number of cached results
+    pub entrylimit: usize,
+    /// Maximum total size in bytes of the cached results
+    pub weightlimit: usize,
+}
+
+impl Default for HookManagerParams {
+    fn default() -> Self {
+        HookManagerParams {
+            entrylimit: 1024,
+            weightlimit: 1024 * 1024,
+        }
+    }
 }
 
 /// Types of repositories supported
@@ -145,6 +204,19 @@ impl RepoConfigs {
         )
     }
 
+    /// Read a directory of the local file system laid out like the metaconfig repo and
+    /// generate RepoConfigs based on it
+    pub fn read_config_dir<P: AsRef<Path>>(
+        path: P,
+    ) -> Box<Future<Item = Self, Error = Error> + Send> {
+        Box::new(
+            vfs_from_directory(path)
+                .into_future()
+                .from_err()
+                .and_then(|vfs| Self::read_vfs(vfs.into_node())),
+        )
+    }
+
     /// Read the given manifest of metaconfig repo and yield the RepoConfigs for it
     fn read_manifest<M>(manifest: &M) -> Box<Future<Item = Self, Error = Error> + Send>
     where
@@ -152,24 +224,41 @@ impl RepoConfigs {
     {
         Box::new(
             vfs_from_manifest(manifest)
-                .and_then(|vfs| {
-                    VfsWalker::new(vfs.into_node(), MPath::new(b"repos").unwrap()).walk()
-                })
                 .from_err()
-                .and_then(|repos_node| match repos_node {
+                .and_then(|vfs| Self::read_vfs(vfs.into_node())),
+        )
+    }
+
+    /// Read the RepoConfigs from the root of a Vfs laid out like the metaconfig repo
+    fn read_vfs<TDir, TFile>(
+        root: VfsNode<TDir, TFile>,
+    ) -> Box<Future<Item = Self, Error = Error> + Send>
+    where
+        TDir: VfsDir<TFile = TFile>,
+        TFile: VfsFile<TDir = TDir>,
+    {
+        Box::new(
+            VfsWalker::new(root.clone(), MPath::new(b"repos").unwrap())
+                .walk()
+                .map(move |repos_node| (root, repos_node))
+                .from_err()
+                .and_then(|(root, repos_node)| match repos_node {
                     VfsNode::File(_) => {
                         bail_err!(ErrorKind::InvalidFileStructure("expected directory".into()))
                     }
-                    VfsNode::Dir(dir) => Ok(dir),
+                    VfsNode::Dir(dir) => Ok((root, dir)),
                 })
-                .and_then(|repos_dir| {
+                .and_then(|(root, repos_dir)| {
                     let repopaths: Vec<_> = repos_dir.read().into_iter().cloned().collect();
                     let repos_node = repos_dir.into_node();
-                    future::join_all(
-                        repopaths
-                            .into_iter()
-                            .map(move |repopath| Self::read_repo(repos_node.clone(), repopath)),
-                    )
+                    future::join_all(repopaths.into_iter().map(move |repopath| {
+                        let root = root.clone();
+                        Self::read_repo(repos_node.clone(), repopath).and_then(
+                            move |(reponame, config)| {
+                                Self::read_hooks(root, config).map(move |config| (reponame, config))
+                            },
+                        )
+                    }))
                 })
                 .map(|repos| RepoConfigs {
                     metaconfig: MetaConfig {},
@@ -178,10 +267,14 @@ impl RepoConfigs {
         )
     }
 
-    fn read_repo(
-        dir: VfsNode<ManifestVfsDir, ManifestVfsFile>,
+    fn read_repo<TDir, TFile>(
+        dir: VfsNode<TDir, TFile>,
         path: MPathElement,
-    ) -> Box<Future<Item = (String, RepoConfig), Error = Error> + Send> {
+    ) -> Box<Future<Item = (String, RepoConfig), Error = Error> + Send>
+    where
+        TDir: VfsDir<TFile = TFile>,
+        TFile: VfsFile<TDir = TDir>,
+    {
         Box::new(
             from_utf8(path.as_bytes())
                 .map(ToOwned::to_owned)
@@ -190,28 +283,12 @@ impl RepoConfigs {
                 .and_then({
                     let path = path.clone();
                     move |reponame| {
-                        VfsWalker::new(dir, path.into_iter().cloned())
-                            .walk()
-                            .from_err()
-                            .and_then(|node| match node {
-                                VfsNode::File(file) => Ok(file),
-                                _ => Err(ErrorKind::InvalidFileStructure("expected file".into()).into()),
-                            })
-                            .and_then(|file| {
-                                file.read().map_err(|err| {
-                                    err.context("failed to read content of the file").into()
-                                })
-                            })
-                            .and_then(|content| match content {
-                                Content::File(FileContents::Bytes(bytes)) => Ok(bytes),
-                                _ => Err(ErrorKind::InvalidFileStructure("expected file".into()).into()),
-                            })
-                            .and_then(|bytes| {
-                                Ok((
-                                    reponame,
-                                    toml::from_slice::<RawRepoConfig>(bytes.as_ref())?.try_into()?,
-                                ))
-                            })
+                        Self::read_repo_file(dir, path).and_then(|bytes| {
+                            Ok((
+                                reponame,
+                                toml::from_slice::<RawRepoConfig>(bytes.as_ref())?.try_into()?,
+                            ))
+                        })
                     }
                 })
                 .map_err(move |err: Error| {
@@ -220,6 +297,103 @@ impl RepoConfigs {
                 }),
         )
     }
+
+    /// Read the config file of a repo, which is either `repos/<name>` or
+    /// `repos/<name>/server.toml`
+    fn read_repo_file<TDir, TFile>(
+        dir: VfsNode<TDir, TFile>,
+        path: MPathElement,
+    ) -> Box<Future<Item = Bytes, Error = Error> + Send>
+    where
+        TDir: VfsDir<TFile = TFile>,
+        TFile: VfsFile<TDir = TDir>,
+    {
+        Box::new(
+            VfsWalker::new(dir, vec![path])
+                .walk()
+                .from_err()
+                .and_then(|node| -> Box<Future<Item = Bytes, Error = Error> + Send> {
+                    match node {
+                        VfsNode::File(_) => Self::read_file(node, vec![]),
+                        VfsNode::Dir(_) => Box::new(
+                            MPathElement::new(b"server.toml".to_vec())
+                                .into_future()
+                                .and_then(move |server_toml| {
+                                    Self::read_file(node, vec![server_toml])
+                                }),
+                        ),
+                    }
+                }),
+        )
+    }
+
+    /// Fill in the code of the hooks of the given repo config. Paths of hooks are relative to the
+    /// root of the metaconfig repo.
+    fn read_hooks<TDir, TFile>(
+        root: VfsNode<TDir, TFile>,
+        mut config: RepoConfig,
+    ) -> Box<Future<Item = RepoConfig, Error = Error> + Send>
+    where
+        TDir: VfsDir<TFile = TFile>,
+        TFile: VfsFile<TDir = TDir>,
+    {
+        let hooks = match config.hooks.take() {
+            Some(hooks) => hooks,
+            None => return Box::new(future::ok(config)),
+        };
+
+        Box::new(
+            future::join_all(hooks.into_iter().map(move |hook| {
+                let path = hook.path.clone();
+                MPath::new(hook.path.as_bytes())
+                    .into_future()
+                    .and_then({
+                        let root = root.clone();
+                        move |hook_path| Self::read_file(root, hook_path.into_iter().collect())
+                    })
+                    .and_then(|bytes| Ok(String::from_utf8(bytes.to_vec())?))
+                    .map(move |code| HookParams {
+                        code: Some(code),
+                        ..hook
+                    })
+                    .map_err(move |err: Error| {
+                        err.context(format_err!("failed while reading hook: {:?}", path))
+                            .into()
+                    })
+            })).map(move |hooks| {
+                config.hooks = Some(hooks);
+                config
+            }),
+        )
+    }
+
+    /// Read the content of a file that is found by following the given path from the given node
+    fn read_file<TDir, TFile>(
+        node: VfsNode<TDir, TFile>,
+        path: Vec<MPathElement>,
+    ) -> Box<Future<Item = Bytes, Error = Error> + Send>
+    where
+        TDir: VfsDir<TFile = TFile>,
+        TFile: VfsFile<TDir = TDir>,
+    {
+        Box::new(
+            VfsWalker::new(node, path)
+                .walk()
+                .from_err()
+                .and_then(|node| match node {
+                    VfsNode::File(file) => Ok(file),
+                    _ => Err(ErrorKind::InvalidFileStructure("expected file".into()).into()),
+                })
+                .and_then(|file| {
+                    file.read()
+                        .map_err(|err| err.context("failed to read content of the file").into())
+                })
+                .and_then(|content| match content {
+                    Content::File(FileContents::Bytes(bytes)) => Ok(bytes),
+                    _ => Err(ErrorKind::InvalidFileStructure("expected file".into()).into()),
+                }),
+        )
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -242,6 +416,9 @@ struct RawRepoConfig {
     max_concurrent_requests_per_io_thread: Option<usize>,
     bookmarks: Option<Vec<RawBookmarkConfig>>,
     hooks: Option<Vec<RawHookConfig>>,
+    hook_manager_params: Option<RawHookManagerParams>,
+    lfs: Option<RawLfsConfig>,
+    chunking: Option<RawChunkingConfig>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -254,6 +431,10 @@ struct RawCacheWarmupConfig {
 struct RawBookmarkConfig {
     name: String,
     hooks: Option<Vec<RawBookmarkHook>>,
+    publishing: Option<bool>,
+    only_fast_forward: Option<bool>,
+    allow_delete: Option<bool>,
+    allowed_users: Option<Vec<String>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -267,6 +448,26 @@ struct RawHookConfig {
     path: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct RawHookManagerParams {
+    entrylimit: Option<usize>,
+    weightlimit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawLfsConfig {
+    threshold: u64,
+    send_pointers: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawChunkingConfig {
+    threshold: u64,
+    chunk_size: usize,
+    content_defined: Option<bool>,
+    stream_threshold: Option<u64>,
+}
+
 /// Types of repositories supported
 #[derive(Clone, Debug, Deserialize)]
 enum RawRepoType {
@@ -328,6 +529,10 @@ impl TryFrom<RawRepoConfig> for RepoConfig {
                             }
                             None => None,
                         },
+                        publishing: bm.publishing.unwrap_or(false),
+                        only_fast_forward: bm.only_fast_forward.unwrap_or(false),
+                        allow_delete: bm.allow_delete.unwrap_or(true),
+                        allowed_users: bm.allowed_users,
                     })
                     .collect(),
             ),
@@ -339,11 +544,41 @@ impl TryFrom<RawRepoConfig> for RepoConfig {
                     .map(|hook| HookParams {
                         name: hook.name,
                         path: hook.path,
+                        code: None,
                     })
                     .collect(),
             ),
             None => None,
         };
+        let hook_manager_params = this.hook_manager_params.map(|params| {
+            let default = HookManagerParams::default();
+            HookManagerParams {
+                entrylimit: params.entrylimit.unwrap_or(default.entrylimit),
+                weightlimit: params.weightlimit.unwrap_or(default.weightlimit),
+            }
+        });
+        let lfs = this.lfs.map(|lfs| LfsParams {
+            threshold: lfs.threshold,
+            send_pointers: lfs.send_pointers.unwrap_or(false),
+        });
+        let chunking = match this.chunking {
+            Some(chunking) => {
+                if chunking.chunk_size == 0 {
+                    bail_err!(ErrorKind::InvalidConfig("chunk_size must be positive".into()));
+                }
+                let chunker = if chunking.content_defined.unwrap_or(false) {
+                    Chunker::ContentDefined(chunking.chunk_size)
+                } else {
+                    Chunker::FixedSize(chunking.chunk_size)
+                };
+                Some(ChunkingParams {
+                    threshold: chunking.threshold,
+                    chunker,
+                    stream_threshold: chunking.stream_threshold,
+                })
+            }
+            None => None,
+        };
 
         Ok(RepoConfig {
             repotype,
@@ -353,6 +588,9 @@ impl TryFrom<RawRepoConfig> for RepoConfig {
             cache_warmup,
             bookmarks,
             hooks,
+            hook_manager_params,
+            lfs,
+            chunking,
         })
     }
 }
@@ -361,6 +599,11 @@ impl TryFrom<RawRepoConfig> for RepoConfig {
 mod test {
     use super::*;
 
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    use tempdir::TempDir;
+
     use mercurial_types::FileType;
     use mercurial_types_mocks::manifest::MockManifest;
 
@@ -377,19 +620,35 @@ mod test {
             commit_limit=100
             [[bookmarks]]
             name="bookmark_fbs1"
+            publishing=true
             [[bookmarks.hooks]]
             hook_name="hook_fbs1"
             [[bookmarks.hooks]]
             hook_name="hook_fbs2"
             [[bookmarks]]
             name="bookmark_fbs2"
+            only_fast_forward=true
+            allow_delete=false
+            allowed_users=["alice", "bob"]
             [[hooks]]
             name="hook_fbs1"
             path="blah/hooks/hook_fbs1.lua"
             [[hooks]]
             name="hook_fbs2"
             path="blah/hooks/hook_fbs2.lua"
+            [hook_manager_params]
+            entrylimit=2048
+            [lfs]
+            threshold=1000
+            send_pointers=true
+            [chunking]
+            threshold=100000
+            chunk_size=4096
+            content_defined=true
+            stream_threshold=1000000
         "#;
+        let hook1_content = "this is hook1";
+        let hook2_content = "this is hook2";
         let www_content = r#"
             path="/tmp/www"
             repotype="revlog"
@@ -401,6 +660,8 @@ mod test {
             "repos/fbsource" => (FileType::Regular, fbsource_content),
             "repos/www" => (FileType::Regular, www_content),
             "my_path/my_files" => (FileType::Regular, ""),
+            "blah/hooks/hook_fbs1.lua" => (FileType::Regular, hook1_content),
+            "blah/hooks/hook_fbs2.lua" => (FileType::Regular, hook2_content),
         };
         let root_manifest = MockManifest::from_paths(paths).expect("manifest is valid");
         let repoconfig = RepoConfigs::read_manifest(&root_manifest)
@@ -423,22 +684,45 @@ mod test {
                     BookmarkParams {
                         name: "bookmark_fbs1".to_string(),
                         hooks: Some(vec!["hook_fbs1".to_string(), "hook_fbs2".to_string()]),
+                        publishing: true,
+                        only_fast_forward: false,
+                        allow_delete: true,
+                        allowed_users: None,
                     },
                     BookmarkParams {
                         name: "bookmark_fbs2".to_string(),
                         hooks: None,
+                        publishing: false,
+                        only_fast_forward: true,
+                        allow_delete: false,
+                        allowed_users: Some(vec!["alice".to_string(), "bob".to_string()]),
                     },
                 ]),
                 hooks: Some(vec![
                     HookParams {
                         name: "hook_fbs1".to_string(),
                         path: "blah/hooks/hook_fbs1.lua".to_string(),
+                        code: Some("this is hook1".to_string()),
                     },
                     HookParams {
                         name: "hook_fbs2".to_string(),
                         path: "blah/hooks/hook_fbs2.lua".to_string(),
+                        code: Some("this is hook2".to_string()),
                     },
                 ]),
+                hook_manager_params: Some(HookManagerParams {
+                    entrylimit: 2048,
+                    weightlimit: 1024 * 1024,
+                }),
+                lfs: Some(LfsParams {
+                    threshold: 1000,
+                    send_pointers: true,
+                }),
+                chunking: Some(ChunkingParams {
+                    threshold: 100000,
+                    chunker: Chunker::ContentDefined(4096),
+                    stream_threshold: Some(1000000),
+                }),
             },
         );
         repos.insert(
@@ -451,6 +735,67 @@ mod test {
                 cache_warmup: None,
                 bookmarks: None,
                 hooks: None,
+                hook_manager_params: None,
+                lfs: None,
+                chunking: None,
+            },
+        );
+        assert_eq!(
+            repoconfig,
+            RepoConfigs {
+                metaconfig: MetaConfig {},
+                repos,
+            }
+        )
+    }
+
+    #[test]
+    fn test_read_config_dir() {
+        let www_content = r#"
+            path="/tmp/www"
+            repotype="revlog"
+            repoid=1
+            [[hooks]]
+            name="hook1"
+            path="hooks/hook1.lua"
+        "#;
+
+        let tmp = TempDir::new("metaconfig").expect("failed to create temp dir");
+        for (path, content) in vec![
+            ("repos/www/server.toml", www_content),
+            ("hooks/hook1.lua", "this is hook1"),
+        ] {
+            let path = tmp.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).expect("failed to create dir");
+            File::create(path)
+                .and_then(|mut file| file.write_all(content.as_bytes()))
+                .expect("failed to write file");
+        }
+
+        let repoconfig = RepoConfigs::read_config_dir(tmp.path())
+            .wait()
+            .expect("failed to read config from directory");
+
+        let mut repos = HashMap::new();
+        repos.insert(
+            "www".to_string(),
+            RepoConfig {
+                repotype: RepoType::Revlog("/tmp/www".into()),
+                generation_cache_size: 10 * 1024 * 1024,
+                repoid: 1,
+                scuba_table: None,
+                cache_warmup: None,
+                bookmarks: None,
+                hooks: Some(vec![
+                    HookParams {
+                        name: "hook1".to_string(),
+                        path: "hooks/hook1.lua".to_string(),
+                        code: Some("this is hook1".to_string()),
+                    },
+                ]),
+                hook_manager_params: None,
+                lfs: None,
+                chunking: None,
             },
         );
         assert_eq!(
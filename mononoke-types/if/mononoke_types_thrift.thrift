@@ -31,6 +31,9 @@
 // TODO (T26959816): add support to represent these as SmallVecs.
 typedef binary Blake2 (hs.newtype)
 
+// SHA-256 is only used to identify large file contents the way Git-LFS does.
+typedef binary Sha256 (hs.newtype)
+
 // Allow the hash type to change in the future.
 union IdType {
   1: Blake2 Blake2,
@@ -38,6 +41,7 @@ union IdType {
 
 typedef IdType ChangesetId (hs.newtype)
 typedef IdType ContentId (hs.newtype)
+typedef IdType ContentChunkId (hs.newtype)
 
 // mercurial_types defines Sha1, and it's most convenient to stick this in here.
 // This can be moved away in the future if necessary.
@@ -103,10 +107,44 @@ struct DateTime {
   2: required i32 tz_offset_secs,
 }
 
+// Contents of a large file that are stored out of band, by their SHA-256 hash,
+// the way a Git-LFS pointer file describes them.
+struct LfsPointer {
+  1: Sha256 sha256,
+  2: i64 size,
+}
+
+// A piece of the contents of a file that is stored chunked. Every chunk is a
+// separate blob, so that no single blob has to hold all of a huge file.
+union ContentChunk {
+  1: binary Bytes,
+}
+
+struct ChunkPointer {
+  1: ContentChunkId chunk_id,
+  // size is a u64 stored as an i64
+  2: i64 size,
+}
+
+// The index of the contents of a file that is stored as a list of chunks, in
+// the order in which they are concatenated.
+struct ChunkedFileContents {
+  1: list<ChunkPointer> chunks,
+}
+
 union FileContents {
   1: binary Bytes,
 }
 
+// Where the contents of a file are stored if they are too large for a
+// FileContents blob. This is stored separately, keyed by the ContentId of
+// the FileContents the bytes of the file would make, so that the ContentId
+// doesn't depend on how the contents are stored.
+union ExternalContents {
+  1: LfsPointer Lfs,
+  2: ChunkedFileContents Chunked,
+}
+
 enum FileType {
   Regular = 0,
   Executable = 1,
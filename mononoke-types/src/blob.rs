@@ -11,7 +11,7 @@ use bytes::Bytes;
 use asyncmemo::Weight;
 
 use errors::*;
-use typed_hash::{ChangesetId, ContentId, MononokeId};
+use typed_hash::{ChangesetId, ContentChunkId, ContentId, MononokeId};
 
 /// A serialized blob in memory.
 pub struct Blob<Id> {
@@ -40,6 +40,7 @@ impl<Id> Blob<Id> {
 
 pub type ChangesetBlob = Blob<ChangesetId>;
 pub type ContentBlob = Blob<ContentId>;
+pub type ContentChunkBlob = Blob<ContentChunkId>;
 
 /// A type representing bytes written to or read from a blobstore. The goal here is to ensure
 /// that only types that implement `From<BlobstoreBytes>` and `Into<BlobstoreBytes>` can be
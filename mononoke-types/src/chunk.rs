@@ -0,0 +1,394 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Contents of huge files, which are stored as a list of chunks.
+//!
+//! Every chunk is a separate blob keyed by its `ContentChunkId`, and the `ExternalContents` of
+//! the file only hold the index of the chunks. That way no blob has to hold all of the file, and
+//! the contents can be streamed one chunk at a time.
+
+use std::cmp;
+use std::fmt::{self, Debug};
+
+use bytes::Bytes;
+use failure::SyncFailure;
+use quickcheck::{empty_shrinker, single_shrinker, Arbitrary, Gen};
+
+use rust_thrift::compact_protocol;
+
+use blob::{Blob, BlobstoreBytes, BlobstoreValue, ContentChunkBlob};
+use errors::*;
+use thrift;
+use typed_hash::{ContentChunkId, ContentChunkIdContext};
+
+lazy_static! {
+    /// A random value for every byte, for the gear hash of content-defined chunking. The values
+    /// are generated from a fixed seed, as changing them would move the chunk boundaries.
+    static ref GEAR: Vec<u64> = {
+        // splitmix64
+        let mut state = 0u64;
+        (0..256)
+            .map(|_| {
+                state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                let mut z = state;
+                z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                z ^ (z >> 31)
+            })
+            .collect()
+    };
+}
+
+/// How the contents of a file are split into chunks.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Chunker {
+    /// Chunks of this many bytes, except for the last one.
+    FixedSize(usize),
+    /// Chunks whose boundaries depend on the contents around them, so that an edit in the
+    /// middle of a file only changes the chunks it touches. The chunks are roughly this many
+    /// bytes on average, and between a quarter and four times as large.
+    ContentDefined(usize),
+}
+
+impl Chunker {
+    /// Split `bytes` into chunks. The chunks share the memory of `bytes`.
+    pub fn split(&self, mut bytes: Bytes) -> Vec<Bytes> {
+        let mut chunks = vec![];
+        while !bytes.is_empty() {
+            let len = cmp::max(self.next_boundary(&bytes), 1);
+            chunks.push(bytes.split_to(len));
+        }
+        chunks
+    }
+
+    /// Split `bytes` into chunks, and return the index of the chunks along with the chunks
+    /// themselves, which have to be stored separately.
+    pub fn chunk(&self, bytes: Bytes) -> (ChunkedContents, Vec<ContentChunkBlob>) {
+        let mut pointers = vec![];
+        let mut blobs = vec![];
+        for chunk in self.split(bytes) {
+            let size = chunk.len() as u64;
+            let blob = ContentChunk::new(chunk).into_blob();
+            pointers.push(ChunkPointer::new(*blob.id(), size));
+            blobs.push(blob);
+        }
+        (ChunkedContents::new(pointers), blobs)
+    }
+
+    /// The length of the first chunk of `data`
+    fn next_boundary(&self, data: &[u8]) -> usize {
+        match *self {
+            Chunker::FixedSize(size) => cmp::min(size, data.len()),
+            Chunker::ContentDefined(average) => {
+                let min = average / 4;
+                let max = cmp::min(average.saturating_mul(4), data.len());
+                if data.len() <= min {
+                    return data.len();
+                }
+
+                // A boundary is after a byte at which the low bits of the gear hash are all
+                // zero. The hash only depends on the last 64 bytes, so the same boundaries are
+                // found no matter where the chunk started.
+                let mask = ((average - min) as u64).next_power_of_two() - 1;
+                let mut hash = 0u64;
+                for (i, byte) in data.iter().enumerate().take(max).skip(min) {
+                    hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+                    if hash & mask == 0 {
+                        return i + 1;
+                    }
+                }
+                max
+            }
+        }
+    }
+}
+
+/// A chunk of the contents of a file.
+#[derive(Clone, Eq, PartialEq)]
+pub struct ContentChunk(Bytes);
+
+impl ContentChunk {
+    pub fn new<B: Into<Bytes>>(bytes: B) -> Self {
+        ContentChunk(bytes.into())
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.0
+    }
+
+    pub(crate) fn from_thrift(chunk: thrift::ContentChunk) -> Result<Self> {
+        match chunk {
+            thrift::ContentChunk::Bytes(bytes) => Ok(ContentChunk(bytes.into())),
+            thrift::ContentChunk::UnknownField(x) => bail_err!(ErrorKind::InvalidThrift(
+                "ContentChunk".into(),
+                format!("unknown content chunk field: {}", x)
+            )),
+        }
+    }
+
+    pub(crate) fn into_thrift(self) -> thrift::ContentChunk {
+        // TODO (T26959816) -- allow Thrift to represent binary as Bytes
+        thrift::ContentChunk::Bytes(self.0.to_vec())
+    }
+}
+
+impl BlobstoreValue for ContentChunk {
+    type Key = ContentChunkId;
+
+    fn into_blob(self) -> ContentChunkBlob {
+        let thrift = self.into_thrift();
+        let data = compact_protocol::serialize(&thrift);
+        let mut context = ContentChunkIdContext::new();
+        context.update(&data);
+        let id = context.finish();
+        Blob::new(id, data)
+    }
+
+    fn from_blob(blob: Blob<Self::Key>) -> Result<Self> {
+        // TODO (T27336549) stop using SyncFailure once thrift is converted to failure
+        let thrift_tc = compact_protocol::deserialize(blob.data().as_ref())
+            .map_err(SyncFailure::new)
+            .context(ErrorKind::BlobDeserializeError("ContentChunk".into()))?;
+        Self::from_thrift(thrift_tc)
+    }
+}
+
+impl Debug for ContentChunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ContentChunk(length {})", self.0.len())
+    }
+}
+
+impl Arbitrary for ContentChunk {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        ContentChunk::new(Vec::arbitrary(g))
+    }
+
+    fn shrink(&self) -> Box<Iterator<Item = Self>> {
+        single_shrinker(ContentChunk::new(vec![]))
+    }
+}
+
+/// The id of a chunk, along with the size of the contents it holds.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct ChunkPointer {
+    chunk_id: ContentChunkId,
+    size: u64,
+}
+
+impl ChunkPointer {
+    pub fn new(chunk_id: ContentChunkId, size: u64) -> Self {
+        ChunkPointer { chunk_id, size }
+    }
+
+    pub fn chunk_id(&self) -> &ContentChunkId {
+        &self.chunk_id
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub(crate) fn from_thrift(pointer: thrift::ChunkPointer) -> Result<Self> {
+        if pointer.size < 0 {
+            bail_err!(ErrorKind::InvalidThrift(
+                "ChunkPointer".into(),
+                format!("negative size: {}", pointer.size)
+            ));
+        }
+        Ok(ChunkPointer {
+            chunk_id: ContentChunkId::from_thrift(pointer.chunk_id)?,
+            size: pointer.size as u64,
+        })
+    }
+
+    pub(crate) fn into_thrift(self) -> thrift::ChunkPointer {
+        thrift::ChunkPointer {
+            chunk_id: self.chunk_id.into_thrift(),
+            size: self.size as i64,
+        }
+    }
+}
+
+impl Arbitrary for ChunkPointer {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        // Sizes must fit in a Thrift i64, and their sum in a u64.
+        ChunkPointer::new(ContentChunkId::arbitrary(g), u64::from(u32::arbitrary(g)))
+    }
+
+    fn shrink(&self) -> Box<Iterator<Item = Self>> {
+        empty_shrinker()
+    }
+}
+
+/// The index of the contents of a file that are stored as a list of chunks.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ChunkedContents {
+    chunks: Vec<ChunkPointer>,
+}
+
+impl ChunkedContents {
+    pub fn new(chunks: Vec<ChunkPointer>) -> Self {
+        ChunkedContents { chunks }
+    }
+
+    /// The chunks, in the order in which they are concatenated.
+    pub fn chunks(&self) -> &[ChunkPointer] {
+        &self.chunks
+    }
+
+    pub fn into_chunks(self) -> Vec<ChunkPointer> {
+        self.chunks
+    }
+
+    /// The size of the contents, which is the sum of the sizes of the chunks.
+    pub fn size(&self) -> u64 {
+        self.chunks.iter().map(ChunkPointer::size).sum()
+    }
+
+    /// The index is stored as a blob of its own for contents that are not keyed by their
+    /// `ContentId`, like LFS contents.
+    pub fn from_blobstore_bytes(bytes: &BlobstoreBytes) -> Result<Self> {
+        // TODO (T27336549) stop using SyncFailure once thrift is converted to failure
+        let thrift_chunked = compact_protocol::deserialize(bytes.as_bytes().as_ref())
+            .map_err(SyncFailure::new)
+            .context(ErrorKind::BlobDeserializeError("ChunkedContents".into()))?;
+        Self::from_thrift(thrift_chunked)
+    }
+
+    pub fn into_blobstore_bytes(self) -> BlobstoreBytes {
+        BlobstoreBytes::from_bytes(compact_protocol::serialize(&self.into_thrift()))
+    }
+
+    pub(crate) fn from_thrift(chunked: thrift::ChunkedFileContents) -> Result<Self> {
+        let chunks = chunked
+            .chunks
+            .into_iter()
+            .map(ChunkPointer::from_thrift)
+            .collect::<Result<_>>()?;
+        Ok(ChunkedContents { chunks })
+    }
+
+    pub(crate) fn into_thrift(self) -> thrift::ChunkedFileContents {
+        thrift::ChunkedFileContents {
+            chunks: self.chunks
+                .into_iter()
+                .map(ChunkPointer::into_thrift)
+                .collect(),
+        }
+    }
+}
+
+impl Arbitrary for ChunkedContents {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        ChunkedContents::new(Vec::arbitrary(g))
+    }
+
+    fn shrink(&self) -> Box<Iterator<Item = Self>> {
+        empty_shrinker()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Pseudo-random data, so that content-defined chunks have a realistic size
+    fn random_data(len: usize) -> Vec<u8> {
+        // xorshift64
+        let mut x = 0x2545_f491_4f6c_dd1du64;
+        let mut data = Vec::with_capacity(len + 8);
+        while data.len() < len {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            data.extend((0..8).map(|i| (x >> (8 * i)) as u8));
+        }
+        data.truncate(len);
+        data
+    }
+
+    #[test]
+    fn fixed_size() {
+        let chunks = Chunker::FixedSize(4).split(Bytes::from("0123456789"));
+        assert_eq!(
+            chunks,
+            vec![Bytes::from("0123"), Bytes::from("4567"), Bytes::from("89")]
+        );
+        assert!(Chunker::FixedSize(4).split(Bytes::new()).is_empty());
+    }
+
+    #[test]
+    fn content_defined() {
+        let average = 8192;
+        let data = Bytes::from(random_data(1 << 20));
+        let chunks = Chunker::ContentDefined(average).split(data.clone());
+
+        let sizes: Vec<_> = chunks.iter().map(Bytes::len).collect();
+        assert!(sizes[..sizes.len() - 1].iter().all(|size| *size > average / 4));
+        assert!(sizes.iter().all(|size| *size <= average * 4));
+        assert_eq!(chunks.concat(), data.to_vec());
+
+        // An insertion only changes the chunk it is in
+        let mut edited = data[..10000].to_vec();
+        edited.extend_from_slice(&[b'x'; 100]);
+        edited.extend_from_slice(&data[10000..]);
+        let edited_chunks = Chunker::ContentDefined(average).split(Bytes::from(edited));
+        let new_chunks = edited_chunks
+            .iter()
+            .filter(|chunk| !chunks.contains(chunk))
+            .count();
+        assert!(new_chunks <= 2, "{} chunks changed", new_chunks);
+    }
+
+    #[test]
+    fn chunk() {
+        let (chunked, blobs) = Chunker::FixedSize(4).chunk(Bytes::from("0123456789"));
+        assert_eq!(chunked.size(), 10);
+        assert_eq!(chunked.chunks().len(), 3);
+        for (pointer, blob) in chunked.chunks().iter().zip(blobs) {
+            assert_eq!(pointer.chunk_id(), blob.id());
+            let chunk = ContentChunk::from_blob(blob).expect("valid chunk");
+            assert_eq!(pointer.size(), chunk.into_bytes().len() as u64);
+        }
+    }
+
+    quickcheck! {
+        fn split_roundtrip(data: Vec<u8>, size: usize) -> bool {
+            let size = size % 64 + 1;
+            let data = Bytes::from(data);
+            let fixed = Chunker::FixedSize(size).split(data.clone());
+            let content_defined = Chunker::ContentDefined(size).split(data.clone());
+            fixed.concat() == data.to_vec() && content_defined.concat() == data.to_vec()
+        }
+
+        fn chunk_blob_roundtrip(chunk: ContentChunk) -> bool {
+            let blob = chunk.clone().into_blob();
+            let chunk2 = ContentChunk::from_blob(blob)
+                .expect("blob roundtrips should always be valid");
+            chunk == chunk2
+        }
+
+        fn chunked_thrift_roundtrip(chunked: ChunkedContents) -> bool {
+            let chunked2 = ChunkedContents::from_thrift(chunked.clone().into_thrift())
+                .expect("thrift roundtrips should always be valid");
+            chunked == chunked2
+        }
+
+        fn chunked_blobstore_bytes_roundtrip(chunked: ChunkedContents) -> bool {
+            let bytes = chunked.clone().into_blobstore_bytes();
+            let chunked2 = ChunkedContents::from_blobstore_bytes(&bytes)
+                .expect("blobstore bytes roundtrips should always be valid");
+            chunked == chunked2
+        }
+    }
+
+    #[test]
+    fn bad_thrift() {
+        let thrift_chunk = thrift::ContentChunk::UnknownField(-1);
+        ContentChunk::from_thrift(thrift_chunk).expect_err("unexpected OK - unknown field");
+    }
+}
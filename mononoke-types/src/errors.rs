@@ -11,6 +11,7 @@ use MPath;
 #[derive(Debug, Fail)]
 pub enum ErrorKind {
     #[fail(display = "invalid blake2 input: {}", _0)] InvalidBlake2Input(String),
+    #[fail(display = "invalid sha256 input: {}", _0)] InvalidSha256Input(String),
     #[fail(display = "invalid path '{}': {}", _0, _1)] InvalidPath(String, String),
     #[fail(display = "invalid Mononoke path '{}': {}", _0, _1)] InvalidMPath(MPath, String),
     #[fail(display = "error while deserializing blob for '{}'", _0)] BlobDeserializeError(String),
@@ -19,6 +20,7 @@ pub enum ErrorKind {
     #[fail(display = "not path-prefix-free: path '{}' is a prefix of '{}'", _0, _1)]
     NotPathPrefixFree(MPath, MPath),
     #[fail(display = "invalid bonsai changeset: {}", _0)] InvalidBonsaiChangeset(String),
+    #[fail(display = "invalid Git-LFS pointer: {}", _0)] InvalidLfsPointer(String),
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -0,0 +1,154 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Where the contents of files that are too large for a `FileContents` blob are stored.
+//!
+//! Contents are always identified by the `ContentId` of the `FileContents` their bytes make, no
+//! matter how they are stored, so that content ids and everything that refers to them don't
+//! depend on the configuration of the repo. Large contents are stored in the LFS blobstore or as
+//! chunks instead of in a `FileContents` blob, and an `ExternalContents` blob keyed by their
+//! `ContentId` says where to find them.
+
+use std::fmt::{self, Debug};
+
+use failure::SyncFailure;
+use quickcheck::{empty_shrinker, Arbitrary, Gen};
+
+use rust_thrift::compact_protocol;
+
+use blob::BlobstoreBytes;
+use chunk::ChunkedContents;
+use errors::*;
+use lfs::LfsPointer;
+use thrift;
+use typed_hash::{ContentId, MononokeId};
+
+#[derive(Clone, Eq, PartialEq)]
+pub enum ExternalContents {
+    /// The contents are stored out of band in the LFS blobstore.
+    Lfs(LfsPointer),
+    /// The contents are stored as separate chunks.
+    Chunked(ChunkedContents),
+}
+
+impl ExternalContents {
+    /// The key of the blob that says where the contents with id `content_id` are stored, if
+    /// they are not stored in a `FileContents` blob.
+    pub fn blobstore_key(content_id: &ContentId) -> String {
+        format!("external.{}", content_id.blobstore_key())
+    }
+
+    /// The size of the contents, not of the pointer or of the index.
+    pub fn size(&self) -> u64 {
+        match *self {
+            ExternalContents::Lfs(ref pointer) => pointer.size(),
+            ExternalContents::Chunked(ref chunked) => chunked.size(),
+        }
+    }
+
+    pub fn from_blobstore_bytes(bytes: &BlobstoreBytes) -> Result<Self> {
+        // TODO (T27336549) stop using SyncFailure once thrift is converted to failure
+        let thrift_ec = compact_protocol::deserialize(bytes.as_bytes().as_ref())
+            .map_err(SyncFailure::new)
+            .context(ErrorKind::BlobDeserializeError("ExternalContents".into()))?;
+        Self::from_thrift(thrift_ec)
+    }
+
+    pub fn into_blobstore_bytes(self) -> BlobstoreBytes {
+        BlobstoreBytes::from_bytes(compact_protocol::serialize(&self.into_thrift()))
+    }
+
+    pub(crate) fn from_thrift(ec: thrift::ExternalContents) -> Result<Self> {
+        match ec {
+            thrift::ExternalContents::Lfs(lfs) => {
+                Ok(ExternalContents::Lfs(LfsPointer::from_thrift(lfs)?))
+            }
+            thrift::ExternalContents::Chunked(chunked) => Ok(ExternalContents::Chunked(
+                ChunkedContents::from_thrift(chunked)?,
+            )),
+            thrift::ExternalContents::UnknownField(x) => bail_err!(ErrorKind::InvalidThrift(
+                "ExternalContents".into(),
+                format!("unknown external contents field: {}", x)
+            )),
+        }
+    }
+
+    pub(crate) fn into_thrift(self) -> thrift::ExternalContents {
+        match self {
+            ExternalContents::Lfs(pointer) => thrift::ExternalContents::Lfs(pointer.into_thrift()),
+            ExternalContents::Chunked(chunked) => {
+                thrift::ExternalContents::Chunked(chunked.into_thrift())
+            }
+        }
+    }
+}
+
+impl Debug for ExternalContents {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExternalContents::Lfs(ref pointer) => write!(f, "ExternalContents::Lfs({:?})", pointer),
+            ExternalContents::Chunked(ref chunked) => write!(
+                f,
+                "ExternalContents::Chunked({} chunks, length {})",
+                chunked.chunks().len(),
+                chunked.size()
+            ),
+        }
+    }
+}
+
+impl Arbitrary for ExternalContents {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        if g.gen() {
+            ExternalContents::Lfs(LfsPointer::arbitrary(g))
+        } else {
+            ExternalContents::Chunked(ChunkedContents::arbitrary(g))
+        }
+    }
+
+    fn shrink(&self) -> Box<Iterator<Item = Self>> {
+        empty_shrinker()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use blob::BlobstoreValue;
+    use file_contents::FileContents;
+
+    quickcheck! {
+        fn thrift_roundtrip(ec: ExternalContents) -> bool {
+            let thrift_ec = ec.clone().into_thrift();
+            let ec2 = ExternalContents::from_thrift(thrift_ec)
+                .expect("thrift roundtrips should always be valid");
+            ec == ec2
+        }
+
+        fn blobstore_bytes_roundtrip(ec: ExternalContents) -> bool {
+            let bytes = ec.clone().into_blobstore_bytes();
+            let ec2 = ExternalContents::from_blobstore_bytes(&bytes)
+                .expect("blobstore bytes roundtrips should always be valid");
+            ec == ec2
+        }
+    }
+
+    #[test]
+    fn bad_thrift() {
+        let thrift_ec = thrift::ExternalContents::UnknownField(-1);
+        ExternalContents::from_thrift(thrift_ec).expect_err("unexpected OK - unknown field");
+    }
+
+    #[test]
+    fn blobstore_key() {
+        let content_id = *FileContents::new_bytes(&b"blob"[..]).into_blob().id();
+        assert_eq!(
+            ExternalContents::blobstore_key(&content_id),
+            format!("external.content.blake2.{}", content_id)
+        );
+    }
+}
@@ -53,6 +53,18 @@ impl FileContents {
         }
     }
 
+    /// The id these contents get once they are made into a blob. The bytes are hashed where
+    /// they are, along with the Thrift encoding around them, instead of being copied into a blob.
+    pub fn content_id(&self) -> ContentId {
+        match *self {
+            FileContents::Bytes(ref bytes) => {
+                let mut hasher = ContentIdHasher::new(bytes.len() as u64);
+                hasher.update(bytes);
+                hasher.finish()
+            }
+        }
+    }
+
     pub fn into_bytes(self) -> Bytes {
         match self {
             FileContents::Bytes(bytes) => bytes,
@@ -67,6 +79,39 @@ impl FileContents {
     }
 }
 
+/// Computes the id that contents of a known size get once they are made into a blob, from the
+/// pieces they are streamed in, so that they never have to be held in memory at once.
+pub struct ContentIdHasher(ContentIdContext);
+
+impl ContentIdHasher {
+    pub fn new(size: u64) -> Self {
+        let mut context = ContentIdContext::new();
+        // Compact protocol: the header of field 1 of type binary and the length of the bytes as
+        // a varint go before the bytes.
+        context.update(&[0x18u8]);
+        let mut len = size;
+        while len >= 0x80 {
+            context.update(&[(len as u8 & 0x7f) | 0x80]);
+            len >>= 7;
+        }
+        context.update(&[len as u8]);
+        ContentIdHasher(context)
+    }
+
+    pub fn update<T>(&mut self, data: T)
+    where
+        T: AsRef<[u8]>,
+    {
+        self.0.update(data)
+    }
+
+    pub fn finish(mut self) -> ContentId {
+        // The field stop that ends the union.
+        self.0.update(&[0x00u8]);
+        self.0.finish()
+    }
+}
+
 impl BlobstoreValue for FileContents {
     type Key = ContentId;
 
@@ -120,6 +165,18 @@ mod test {
             fc == fc2
         }
 
+        fn content_id_matches_blob(fc: FileContents) -> bool {
+            fc.content_id() == *fc.clone().into_blob().id()
+        }
+
+        fn content_id_hasher(v: Vec<u8>, split: usize) -> bool {
+            let split = if v.is_empty() { 0 } else { split % v.len() };
+            let mut hasher = ContentIdHasher::new(v.len() as u64);
+            hasher.update(&v[..split]);
+            hasher.update(&v[split..]);
+            hasher.finish() == FileContents::new_bytes(v).content_id()
+        }
+
         fn blob_roundtrip(cs: FileContents) -> bool {
             let blob = cs.clone().into_blob();
             let cs2 = FileContents::from_blob(blob)
@@ -10,6 +10,8 @@ use std::str::FromStr;
 use ascii::{AsciiStr, AsciiString};
 use blake2::Blake2b;
 use blake2::digest::{Input, VariableOutput};
+use sha2::Sha256 as Sha256Hasher;
+use sha2::digest::FixedOutput;
 use quickcheck::{empty_shrinker, Arbitrary, Gen};
 
 use errors::*;
@@ -181,6 +183,152 @@ impl Arbitrary for Blake2 {
     }
 }
 
+/// Raw SHA-256 hash.
+///
+/// Mononoke doesn't identify anything by SHA-256 hashes itself. They are used for large file
+/// contents that are stored out of band, since Git-LFS identifies those by their SHA-256 hash.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Serialize, Deserialize, HeapSizeOf)]
+pub struct Sha256([u8; 32]);
+
+impl Sha256 {
+    /// Construct a `Sha256` from an array of 32 bytes containing a
+    /// SHA-256 hash (ie, *not* a hash of the bytes).
+    pub fn from_bytes<B: AsRef<[u8]>>(bytes: B) -> Result<Self> {
+        let bytes = bytes.as_ref();
+        if bytes.len() != 32 {
+            bail_err!(ErrorKind::InvalidSha256Input(
+                "need exactly 32 bytes".into()
+            ));
+        }
+        let mut ret = Sha256([0; 32]);
+        ret.0.copy_from_slice(bytes);
+        Ok(ret)
+    }
+
+    /// Compute the SHA-256 hash of `data`.
+    pub fn from_data<T: AsRef<[u8]>>(data: T) -> Self {
+        let mut context = Sha256Context::new();
+        context.update(data);
+        context.finish()
+    }
+
+    #[inline]
+    pub(crate) fn from_thrift(b: thrift::Sha256) -> Result<Self> {
+        if b.0.len() != 32 {
+            bail_err!(ErrorKind::InvalidThrift(
+                "Sha256".into(),
+                format!("wrong length: expected 32, got {}", b.0.len())
+            ));
+        }
+        let mut arr = [0u8; 32];
+        arr.copy_from_slice(&b.0[..]);
+        Ok(Sha256(arr))
+    }
+
+    pub fn to_hex(&self) -> AsciiString {
+        let mut v = Vec::with_capacity(64);
+        for &byte in self.as_ref() {
+            v.push(HEX_CHARS[(byte >> 4) as usize]);
+            v.push(HEX_CHARS[(byte & 0xf) as usize]);
+        }
+
+        unsafe {
+            // A hex string is always a pure ASCII string.
+            AsciiString::from_ascii_unchecked(v)
+        }
+    }
+
+    pub(crate) fn into_thrift(self) -> thrift::Sha256 {
+        thrift::Sha256(self.0.to_vec())
+    }
+}
+
+/// Context for incrementally computing a `Sha256` hash.
+#[derive(Clone, Default)]
+pub struct Sha256Context(Sha256Hasher);
+
+impl Sha256Context {
+    /// Construct a `Sha256Context`
+    #[inline]
+    pub fn new() -> Self {
+        Sha256Context(Sha256Hasher::default())
+    }
+
+    #[inline]
+    pub fn update<T>(&mut self, data: T)
+    where
+        T: AsRef<[u8]>,
+    {
+        self.0.process(data.as_ref())
+    }
+
+    #[inline]
+    pub fn finish(self) -> Sha256 {
+        let mut ret = Sha256([0; 32]);
+        ret.0.copy_from_slice(self.0.fixed_result().as_slice());
+        ret
+    }
+}
+
+/// Get a reference to the underlying bytes of a `Sha256`
+impl AsRef<[u8]> for Sha256 {
+    fn as_ref(&self) -> &[u8] {
+        &self.0[..]
+    }
+}
+
+impl FromStr for Sha256 {
+    type Err = Error;
+
+    /// Git-LFS object ids are exactly 64 hex digits, so nothing else is accepted.
+    fn from_str(s: &str) -> Result<Self> {
+        if s.len() != 64 {
+            bail_err!(ErrorKind::InvalidSha256Input(
+                "need exactly 64 hex digits".into()
+            ));
+        }
+
+        let mut ret = Sha256([0; 32]);
+
+        for idx in 0..ret.0.len() {
+            ret.0[idx] = match s.get((idx * 2)..(idx * 2 + 2))
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+            {
+                Some(v) => v,
+                None => bail_err!(ErrorKind::InvalidSha256Input("bad digit".into())),
+            };
+        }
+
+        Ok(ret)
+    }
+}
+
+impl Display for Sha256 {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        Display::fmt(&self.to_hex(), fmt)
+    }
+}
+
+/// Custom `Debug` output for `Sha256` so it prints in hex.
+impl Debug for Sha256 {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Sha256({})", self)
+    }
+}
+
+impl Arbitrary for Sha256 {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let mut bytes = [0; 32];
+        g.fill_bytes(&mut bytes);
+        Sha256(bytes)
+    }
+
+    fn shrink(&self) -> Box<Iterator<Item = Self>> {
+        empty_shrinker()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -263,6 +411,26 @@ mod test {
             .expect_err("unexpected OK - badchar middle");
     }
 
+    #[test]
+    fn test_sha256() {
+        // The SHA-256 hash of no data.
+        let nil = Sha256::from_data(b"");
+        assert_eq!(
+            format!("{}", nil),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            nil,
+            Sha256::from_str("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+                .unwrap()
+        );
+        Sha256::from_str("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b85")
+            .expect_err("unexpected OK - trunc");
+        Sha256::from_str("x3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+            .expect_err("unexpected OK - badchar");
+        Sha256::from_thrift(thrift::Sha256(vec![0; 31])).expect_err("unexpected OK - too short");
+    }
+
     #[test]
     fn parse_thrift_bad() {
         Blake2::from_thrift(thrift::Blake2(vec![])).expect_err("unexpected OK - zero len");
@@ -293,5 +461,19 @@ mod test {
             let sh = Blake2::from_thrift(v).expect("converting a valid Thrift structure should always work");
             h == sh
         }
+
+        fn sha256_roundtrip(h: Sha256) -> bool {
+            let sh = Sha256::from_str(&h.to_string()).unwrap();
+            let th = Sha256::from_thrift(h.into_thrift()).unwrap();
+            h == sh && h == th
+        }
+
+        fn sha256_incremental(v: Vec<u8>, split: usize) -> bool {
+            let split = if v.is_empty() { 0 } else { split % v.len() };
+            let mut context = Sha256Context::new();
+            context.update(&v[..split]);
+            context.update(&v[split..]);
+            context.finish() == Sha256::from_data(&v)
+        }
     }
 }
@@ -0,0 +1,205 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Pointers to large file contents that are stored out of band, in the format of Git-LFS.
+//!
+//! See https://github.com/git-lfs/git-lfs/blob/master/docs/spec.md for the pointer file format.
+
+use std::str::{self, FromStr};
+
+use bytes::Bytes;
+use quickcheck::{empty_shrinker, Arbitrary, Gen};
+
+use errors::*;
+use hash::Sha256;
+use thrift;
+
+/// Every Git-LFS pointer file starts with this line.
+const VERSION_LINE: &str = "version https://git-lfs.github.com/spec/v1";
+const OID_PREFIX: &str = "sha256:";
+
+/// Pointer files are small, so anything larger is not parsed as one.
+pub const MAX_POINTER_SIZE: usize = 1024;
+
+/// A pointer to the contents of a large file, which are stored out of band by their SHA-256
+/// hash.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct LfsPointer {
+    sha256: Sha256,
+    size: u64,
+}
+
+impl LfsPointer {
+    pub fn new(sha256: Sha256, size: u64) -> Self {
+        LfsPointer { sha256, size }
+    }
+
+    /// Construct the pointer to `content`.
+    pub fn from_content<T: AsRef<[u8]>>(content: T) -> Self {
+        let content = content.as_ref();
+        LfsPointer {
+            sha256: Sha256::from_data(content),
+            size: content.len() as u64,
+        }
+    }
+
+    /// The SHA-256 hash of the contents, which is the Git-LFS object id.
+    pub fn sha256(&self) -> &Sha256 {
+        &self.sha256
+    }
+
+    /// The size of the contents, not of the pointer.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Parse a Git-LFS pointer file. Keys other than `oid` and `size` are ignored.
+    pub fn from_pointer_file(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() > MAX_POINTER_SIZE {
+            bail_err!(ErrorKind::InvalidLfsPointer("pointer file too large".into()));
+        }
+        let text = str::from_utf8(bytes)
+            .map_err(|_| ErrorKind::InvalidLfsPointer("pointer file is not utf-8".into()))?;
+
+        let mut lines = text.lines();
+        if lines.next() != Some(VERSION_LINE) {
+            bail_err!(ErrorKind::InvalidLfsPointer("unknown version".into()));
+        }
+
+        let mut sha256 = None;
+        let mut size = None;
+        for line in lines {
+            let mut parts = line.splitn(2, ' ');
+            match (parts.next(), parts.next()) {
+                (Some("oid"), Some(oid)) => {
+                    if !oid.starts_with(OID_PREFIX) {
+                        bail_err!(ErrorKind::InvalidLfsPointer(format!("bad oid {}", oid)));
+                    }
+                    sha256 = Some(Sha256::from_str(&oid[OID_PREFIX.len()..])?);
+                }
+                (Some("size"), Some(value)) => {
+                    size = Some(value.parse().map_err(|_| {
+                        ErrorKind::InvalidLfsPointer(format!("bad size {}", value))
+                    })?);
+                }
+                (Some(_), Some(_)) => {}
+                _ => bail_err!(ErrorKind::InvalidLfsPointer(format!("bad line {:?}", line))),
+            }
+        }
+
+        match (sha256, size) {
+            (Some(sha256), Some(size)) => Ok(LfsPointer { sha256, size }),
+            _ => bail_err!(ErrorKind::InvalidLfsPointer("oid or size missing".into())),
+        }
+    }
+
+    /// Serialize this as a Git-LFS pointer file.
+    pub fn to_pointer_file(&self) -> Bytes {
+        Bytes::from(format!(
+            "{}\noid {}{}\nsize {}\n",
+            VERSION_LINE,
+            OID_PREFIX,
+            self.sha256,
+            self.size
+        ))
+    }
+
+    pub(crate) fn from_thrift(lfs: thrift::LfsPointer) -> Result<Self> {
+        if lfs.size < 0 {
+            bail_err!(ErrorKind::InvalidThrift(
+                "LfsPointer".into(),
+                format!("negative size: {}", lfs.size)
+            ));
+        }
+        Ok(LfsPointer {
+            sha256: Sha256::from_thrift(lfs.sha256)?,
+            size: lfs.size as u64,
+        })
+    }
+
+    pub(crate) fn into_thrift(self) -> thrift::LfsPointer {
+        thrift::LfsPointer {
+            sha256: self.sha256.into_thrift(),
+            size: self.size as i64,
+        }
+    }
+}
+
+impl Arbitrary for LfsPointer {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        // Sizes must fit in a Thrift i64.
+        LfsPointer::new(Sha256::arbitrary(g), u64::arbitrary(g) >> 1)
+    }
+
+    fn shrink(&self) -> Box<Iterator<Item = Self>> {
+        empty_shrinker()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const POINTER: &str = concat!(
+        "version https://git-lfs.github.com/spec/v1\n",
+        "oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393\n",
+        "size 12345\n",
+    );
+
+    #[test]
+    fn parse_pointer_file() {
+        let pointer = LfsPointer::from_pointer_file(POINTER.as_bytes()).expect("valid pointer");
+        assert_eq!(pointer.size(), 12345);
+        assert_eq!(
+            pointer.sha256().to_string(),
+            "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393"
+        );
+        assert_eq!(pointer.to_pointer_file(), Bytes::from(POINTER));
+
+        let with_extension = POINTER.replace("size", "x-is-binary 0\nsize");
+        assert_eq!(
+            LfsPointer::from_pointer_file(with_extension.as_bytes()).expect("valid pointer"),
+            pointer
+        );
+    }
+
+    #[test]
+    fn parse_bad_pointer_file() {
+        LfsPointer::from_pointer_file(b"").expect_err("unexpected OK - empty");
+        LfsPointer::from_pointer_file(POINTER.replace("v1", "v2").as_bytes())
+            .expect_err("unexpected OK - bad version");
+        LfsPointer::from_pointer_file(POINTER.replace("sha256:", "sha1:").as_bytes())
+            .expect_err("unexpected OK - bad oid");
+        LfsPointer::from_pointer_file(POINTER.replace("12345", "-1").as_bytes())
+            .expect_err("unexpected OK - bad size");
+        LfsPointer::from_pointer_file(POINTER.replace("size 12345\n", "").as_bytes())
+            .expect_err("unexpected OK - no size");
+    }
+
+    #[test]
+    fn from_content() {
+        let pointer = LfsPointer::from_content(b"");
+        assert_eq!(pointer.size(), 0);
+        assert_eq!(
+            pointer.sha256().to_string(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    quickcheck! {
+        fn pointer_file_roundtrip(pointer: LfsPointer) -> bool {
+            let pointer2 = LfsPointer::from_pointer_file(&pointer.to_pointer_file())
+                .expect("pointer files roundtrips should always be valid");
+            pointer == pointer2
+        }
+
+        fn thrift_roundtrip(pointer: LfsPointer) -> bool {
+            let pointer2 = LfsPointer::from_thrift(pointer.into_thrift())
+                .expect("thrift roundtrips should always be valid");
+            pointer == pointer2
+        }
+    }
+}
@@ -19,6 +19,8 @@ extern crate blake2;
 extern crate bytes;
 extern crate chrono;
 #[macro_use]
+extern crate diesel;
+#[macro_use]
 extern crate failure_ext as failure;
 extern crate heapsize;
 #[macro_use]
@@ -33,6 +35,7 @@ extern crate quickcheck;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate sha2;
 
 extern crate rust_thrift;
 
@@ -40,21 +43,30 @@ extern crate mononoke_types_thrift;
 
 pub mod blob;
 pub mod bonsai_changeset;
+pub mod chunk;
 pub mod datetime;
 pub mod errors;
+pub mod external_contents;
 pub mod file_change;
 pub mod file_contents;
 pub mod hash;
+pub mod lfs;
 pub mod path;
+pub mod sql_types;
 pub mod typed_hash;
 
-pub use blob::{Blob, BlobstoreBytes, BlobstoreValue, ChangesetBlob, ContentBlob};
+pub use blob::{Blob, BlobstoreBytes, BlobstoreValue, ChangesetBlob, ContentBlob,
+               ContentChunkBlob};
 pub use bonsai_changeset::BonsaiChangeset;
+pub use chunk::{ChunkPointer, ChunkedContents, Chunker, ContentChunk};
 pub use datetime::DateTime;
+pub use external_contents::ExternalContents;
 pub use file_change::{FileChange, FileType};
-pub use file_contents::FileContents;
+pub use file_contents::{ContentIdHasher, FileContents};
+pub use hash::{Sha256, Sha256Context};
+pub use lfs::LfsPointer;
 pub use path::{MPath, MPathElement, RepoPath};
-pub use typed_hash::{ChangesetId, ContentId, MononokeId};
+pub use typed_hash::{ChangesetId, ContentChunkId, ContentId, MononokeId};
 
 mod thrift {
     pub use mononoke_types_thrift::*;
@@ -12,9 +12,11 @@ use quickcheck::{empty_shrinker, Arbitrary, Gen};
 
 use blob::BlobstoreValue;
 use bonsai_changeset::BonsaiChangeset;
+use chunk::ContentChunk;
 use errors::*;
 use file_contents::FileContents;
 use hash::{Blake2, Context};
+use sql_types::ChangesetIdSql;
 use thrift;
 
 // There is no NULL_HASH for typed hashes. Any places that need a null hash should use an
@@ -34,7 +36,8 @@ pub trait MononokeId: Copy + Send + 'static {
 
 /// An identifier for a changeset in Mononoke.
 #[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug, Hash)]
-#[derive(HeapSizeOf)]
+#[derive(HeapSizeOf, FromSqlRow, AsExpression)]
+#[sql_type = "ChangesetIdSql"]
 pub struct ChangesetId(Blake2);
 
 /// An identifier for file contents in Mononoke.
@@ -42,6 +45,11 @@ pub struct ChangesetId(Blake2);
 #[derive(HeapSizeOf)]
 pub struct ContentId(Blake2);
 
+/// An identifier for a chunk of file contents that are stored chunked.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug, Hash)]
+#[derive(HeapSizeOf)]
+pub struct ContentChunkId(Blake2);
+
 /// Implementations of typed hashes.
 macro_rules! impl_typed_hash {
     {
@@ -194,6 +202,13 @@ impl_typed_hash! {
     context_key => "content",
 }
 
+impl_typed_hash! {
+    hash_type => ContentChunkId,
+    value_type => ContentChunk,
+    context_type => ContentChunkIdContext,
+    context_key => "chunk",
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -212,6 +227,13 @@ mod test {
                 .expect("converting a valid Thrift structure should always work");
             h == sh
         }
+
+        fn contentchunkid_thrift_roundtrip(h: ContentChunkId) -> bool {
+            let v = h.into_thrift();
+            let sh = ContentChunkId::from_thrift(v)
+                .expect("converting a valid Thrift structure should always work");
+            h == sh
+        }
     }
 
     #[test]
@@ -223,5 +245,8 @@ mod test {
 
         let id = ContentId::new(Blake2::from_byte_array([1; 32]));
         assert_eq!(id.blobstore_key(), format!("content.blake2.{}", id));
+
+        let id = ContentChunkId::new(Blake2::from_byte_array([1; 32]));
+        assert_eq!(id.blobstore_key(), format!("chunk.blake2.{}", id));
     }
 }
@@ -0,0 +1,12 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+pub use failure::{Error, Result};
+
+#[derive(Debug, Eq, Fail, PartialEq)]
+pub enum ErrorKind {
+    #[fail(display = "Unknown phase number: {}", _0)] UnknownPhase(i32),
+}
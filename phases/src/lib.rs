@@ -0,0 +1,312 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Phases of changesets, i.e. whether they are public or draft.
+
+#![deny(warnings)]
+#![feature(never_type)]
+
+#[macro_use]
+extern crate diesel;
+#[macro_use]
+extern crate failure_ext as failure;
+extern crate futures;
+
+extern crate blobrepo;
+extern crate bookmarks;
+extern crate db;
+extern crate futures_ext;
+#[macro_use]
+extern crate lazy_static;
+extern crate mercurial_types;
+extern crate repoinfo;
+extern crate revset;
+extern crate skiplist;
+#[macro_use]
+extern crate stats;
+
+use std::fmt;
+use std::result;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use diesel::{replace_into, Connection, MysqlConnection, SqliteConnection};
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+
+use db::{get_connection_params, ConnectionParams, InstanceRequirement, ProxyRequirement};
+use futures_ext::{asynchronize, BoxFuture};
+use mercurial_types::{HgChangesetId, RepositoryId};
+use stats::Timeseries;
+
+mod errors;
+mod models;
+mod repo_phases;
+mod schema;
+mod wrappers;
+
+pub use errors::*;
+pub use repo_phases::RepoPhases;
+use models::PhaseRow;
+use schema::phases;
+
+define_stats! {
+    prefix = "mononoke.phases";
+    gets: timeseries(RATE, SUM),
+    gets_master: timeseries(RATE, SUM),
+    adds: timeseries(RATE, SUM),
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Phase {
+    Public,
+    Draft,
+}
+
+impl Phase {
+    /// Mercurial represents phases by numbers, both on the wire and in its own storage.
+    pub fn from_hg_number(number: i32) -> Result<Self> {
+        match number {
+            0 => Ok(Phase::Public),
+            1 => Ok(Phase::Draft),
+            _ => Err(ErrorKind::UnknownPhase(number).into()),
+        }
+    }
+
+    pub fn to_hg_number(&self) -> i32 {
+        match *self {
+            Phase::Public => 0,
+            Phase::Draft => 1,
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Phase::Public => write!(f, "public"),
+            Phase::Draft => write!(f, "draft"),
+        }
+    }
+}
+
+/// Interface to storage of the phases that are known for changesets. Changesets missing from
+/// the storage don't have a known phase, see `RepoPhases` for how it is derived for them.
+pub trait Phases: Send + Sync {
+    /// Record the phase of a changeset, replacing the phase stored before, if any.
+    fn add(
+        &self,
+        repo_id: RepositoryId,
+        cs_id: HgChangesetId,
+        phase: Phase,
+    ) -> BoxFuture<(), Error>;
+
+    /// Retrieve the stored phase of a changeset, if any.
+    fn get(&self, repo_id: RepositoryId, cs_id: HgChangesetId) -> BoxFuture<Option<Phase>, Error>;
+}
+
+#[derive(Clone)]
+pub struct SqlitePhases {
+    connection: Arc<Mutex<SqliteConnection>>,
+}
+
+impl SqlitePhases {
+    /// Open a SQLite database. This is synchronous because the SQLite backend hits local
+    /// disk or memory.
+    pub fn open<P: AsRef<str>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let conn = SqliteConnection::establish(path)?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    fn create_tables(&mut self) -> Result<()> {
+        let up_query = include_str!("../schemas/sqlite-phases.sql");
+
+        self.connection
+            .lock()
+            .expect("lock poisoned")
+            .batch_execute(&up_query)?;
+
+        Ok(())
+    }
+
+    /// Create a new SQLite database.
+    pub fn create<P: AsRef<str>>(path: P) -> Result<Self> {
+        let mut phases = Self::open(path)?;
+
+        phases.create_tables()?;
+
+        Ok(phases)
+    }
+
+    /// Open a SQLite database, and create the tables if they are missing
+    pub fn open_or_create<P: AsRef<str>>(path: P) -> Result<Self> {
+        let mut phases = Self::open(path)?;
+
+        let _ = phases.create_tables();
+
+        Ok(phases)
+    }
+
+    /// Create a new in-memory empty database. Great for tests.
+    pub fn in_memory() -> Result<Self> {
+        Self::create(":memory:")
+    }
+
+    fn get_conn(&self) -> result::Result<MutexGuard<SqliteConnection>, !> {
+        Ok(self.connection.lock().expect("lock poisoned"))
+    }
+
+    fn get_master_conn(&self) -> result::Result<MutexGuard<SqliteConnection>, !> {
+        Ok(self.connection.lock().expect("lock poisoned"))
+    }
+}
+
+#[derive(Clone)]
+pub struct MysqlPhases {
+    pool: Pool<ConnectionManager<MysqlConnection>>,
+    master_pool: Pool<ConnectionManager<MysqlConnection>>,
+}
+
+impl MysqlPhases {
+    pub fn open(db_address: &str) -> Result<Self> {
+        let local_connection_params = get_connection_params(
+            db_address.to_string(),
+            InstanceRequirement::Closest,
+            None,
+            Some(ProxyRequirement::Forbidden),
+        )?;
+
+        let master_connection_params = get_connection_params(
+            db_address.to_string(),
+            InstanceRequirement::Master,
+            None,
+            Some(ProxyRequirement::Forbidden),
+        )?;
+
+        Self::open_with_params(&local_connection_params, &master_connection_params)
+    }
+
+    fn open_with_params(
+        local_connection_params: &ConnectionParams,
+        master_connection_params: &ConnectionParams,
+    ) -> Result<Self> {
+        let local_url = local_connection_params.to_diesel_url()?;
+        let master_url = master_connection_params.to_diesel_url()?;
+
+        let pool = Pool::builder()
+            .max_size(10)
+            .min_idle(Some(1))
+            .build(ConnectionManager::new(local_url.clone()))?;
+        let master_pool = Pool::builder()
+            .max_size(1)
+            .min_idle(Some(1))
+            .build(ConnectionManager::new(master_url.clone()))?;
+        Ok(Self { pool, master_pool })
+    }
+
+    pub fn create_test_db<P: AsRef<str>>(prefix: P) -> Result<Self> {
+        let params = db::create_test_db(prefix)?;
+        Self::create(&params)
+    }
+
+    fn create(params: &ConnectionParams) -> Result<Self> {
+        let phases = Self::open_with_params(params, params)?;
+
+        let up_query = include_str!("../schemas/mysql-phases.sql");
+        phases.master_pool.get()?.batch_execute(&up_query)?;
+
+        Ok(phases)
+    }
+
+    fn get_conn(&self) -> Result<PooledConnection<ConnectionManager<MysqlConnection>>> {
+        self.pool.get().map_err(Error::from)
+    }
+
+    fn get_master_conn(&self) -> Result<PooledConnection<ConnectionManager<MysqlConnection>>> {
+        self.master_pool.get().map_err(Error::from)
+    }
+}
+
+/// Using a macro here is unfortunate, but it appears to be the only way to share this code
+/// between SQLite and MySQL.
+/// See https://github.com/diesel-rs/diesel/issues/882#issuecomment-300257476
+macro_rules! impl_phases {
+    ($struct: ty, $connection: ty) => {
+        impl Phases for $struct {
+            fn add(
+                &self,
+                repo_id: RepositoryId,
+                cs_id: HgChangesetId,
+                phase: Phase,
+            ) -> BoxFuture<(), Error> {
+                STATS::adds.add_value(1);
+                let db = self.clone();
+
+                asynchronize(move || {
+                    let connection = db.get_master_conn()?;
+                    let row = PhaseRow {
+                        repo_id,
+                        cs_id,
+                        phase: phase.to_hg_number(),
+                    };
+                    replace_into(phases::table)
+                        .values(&row)
+                        .execute(&*connection)?;
+                    Ok(())
+                })
+            }
+
+            fn get(
+                &self,
+                repo_id: RepositoryId,
+                cs_id: HgChangesetId,
+            ) -> BoxFuture<Option<Phase>, Error> {
+                STATS::gets.add_value(1);
+                let db = self.clone();
+
+                asynchronize(move || {
+                    let phase = {
+                        let connection = db.get_conn()?;
+                        Self::actual_get(&connection, repo_id, cs_id)?
+                    };
+
+                    if phase.is_none() {
+                        STATS::gets_master.add_value(1);
+                        let connection = db.get_master_conn()?;
+                        Self::actual_get(&connection, repo_id, cs_id)
+                    } else {
+                        Ok(phase)
+                    }
+                })
+            }
+        }
+
+        impl $struct {
+            fn actual_get(
+                connection: &$connection,
+                repo_id: RepositoryId,
+                cs_id: HgChangesetId,
+            ) -> Result<Option<Phase>> {
+                let row = phases::table
+                    .filter(phases::repo_id.eq(repo_id))
+                    .filter(phases::cs_id.eq(cs_id))
+                    .first::<PhaseRow>(connection)
+                    .optional()?;
+
+                match row {
+                    Some(row) => Ok(Some(Phase::from_hg_number(row.phase)?)),
+                    None => Ok(None),
+                }
+            }
+        }
+    }
+}
+
+impl_phases!(MysqlPhases, MysqlConnection);
+impl_phases!(SqlitePhases, SqliteConnection);
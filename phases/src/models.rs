@@ -0,0 +1,19 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use mercurial_types::{HgChangesetId, RepositoryId};
+
+use schema::phases;
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Queryable, Insertable)]
+#[table_name = "phases"]
+pub(crate) struct PhaseRow {
+    pub repo_id: RepositoryId,
+    pub cs_id: HgChangesetId,
+    /// Phase number as used by Mercurial, see `Phase::to_hg_number`
+    pub phase: i32,
+}
@@ -0,0 +1,348 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Phases of the changesets of a single repository. A changeset is public if it is marked public
+//! in the store, if it is an ancestor of such a changeset or if it is an ancestor of one of the
+//! publishing bookmarks, otherwise it is draft.
+
+use std::cmp;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+use futures::{future, Future, Stream};
+use futures::future::{loop_fn, Loop};
+
+use blobrepo::BlobRepo;
+use bookmarks::Bookmark;
+use futures_ext::{BoxFuture, BoxStream, FutureExt, StreamExt};
+use mercurial_types::{HgChangesetId, HgNodeHash, RepositoryId};
+use repoinfo::{Generation, RepoGenCache};
+use revset::{is_ancestor_indexed, DifferenceOfUnionsOfAncestorsNodeStream};
+use skiplist::SkiplistIndex;
+
+use {Phase, Phases};
+use errors::*;
+
+/// Changesets waiting to be visited, grouped by generation
+type Pending = BTreeMap<Generation, HashSet<HgNodeHash>>;
+
+/// The ancestors of the publishing bookmarks in decreasing generation order, and the first one
+/// that was taken from the stream but not compared with the visited changesets yet. They are
+/// only walked as far down as the changesets they are compared with.
+type PublicAncestors = (
+    BoxStream<(HgNodeHash, Generation), Error>,
+    Option<(HgNodeHash, Generation)>,
+);
+
+#[derive(Clone)]
+pub struct RepoPhases {
+    repo: Arc<BlobRepo>,
+    repo_generation: RepoGenCache,
+    index: SkiplistIndex,
+    store: Arc<Phases>,
+    repo_id: RepositoryId,
+    publishing_bookmarks: Vec<Bookmark>,
+}
+
+impl RepoPhases {
+    pub fn new(
+        repo: Arc<BlobRepo>,
+        repo_generation: RepoGenCache,
+        index: SkiplistIndex,
+        store: Arc<Phases>,
+        repo_id: RepositoryId,
+        publishing_bookmarks: Vec<Bookmark>,
+    ) -> Self {
+        RepoPhases {
+            repo,
+            repo_generation,
+            index,
+            store,
+            repo_id,
+            publishing_bookmarks,
+        }
+    }
+
+    /// Without publishing bookmarks every changeset is public, as in a plain Mercurial server.
+    pub fn has_publishing_bookmarks(&self) -> bool {
+        !self.publishing_bookmarks.is_empty()
+    }
+
+    /// Finds the phase of a changeset. This only reads, the phases it derives are not stored.
+    pub fn get_phase(&self, cs_id: HgChangesetId) -> BoxFuture<Phase, Error> {
+        if !self.has_publishing_bookmarks() {
+            return future::ok(Phase::Public).boxify();
+        }
+
+        let this = self.clone();
+        self.store
+            .get(self.repo_id, cs_id)
+            .and_then({
+                let this = this.clone();
+                move |phase| match phase {
+                    Some(Phase::Public) => future::ok(true).boxify(),
+                    _ => this.is_bookmark_ancestor(cs_id),
+                }
+            })
+            .and_then(move |public| {
+                if public {
+                    return future::ok(Phase::Public).boxify();
+                }
+                // It might still be an ancestor of a changeset marked public
+                this.find_drafts(vec![cs_id])
+                    .map(move |drafts| {
+                        if drafts.iter().any(|&(draft, _)| draft == cs_id) {
+                            Phase::Draft
+                        } else {
+                            Phase::Public
+                        }
+                    })
+                    .boxify()
+            })
+            .boxify()
+    }
+
+    /// Whether a changeset is an ancestor of one of the publishing bookmarks, looked up in the
+    /// skip-list index.
+    fn is_bookmark_ancestor(&self, cs_id: HgChangesetId) -> BoxFuture<bool, Error> {
+        let this = self.clone();
+        self.bookmark_heads()
+            .and_then(move |heads| {
+                let checks = heads.into_iter().map(|head| {
+                    is_ancestor_indexed(
+                        &this.index,
+                        &this.repo,
+                        this.repo_generation.clone(),
+                        cs_id.into_nodehash(),
+                        head,
+                    )
+                });
+                future::join_all(checks.collect::<Vec<_>>())
+            })
+            .map(|checks| checks.into_iter().any(|is_ancestor| is_ancestor))
+            .boxify()
+    }
+
+    /// The changesets the publishing bookmarks point to.
+    fn bookmark_heads(&self) -> BoxFuture<Vec<HgNodeHash>, Error> {
+        let bookmarks = self.publishing_bookmarks
+            .iter()
+            .map(|bookmark| self.repo.get_bookmark(bookmark))
+            .collect::<Vec<_>>();
+        future::join_all(bookmarks)
+            .map(|heads| {
+                heads
+                    .into_iter()
+                    .filter_map(|head| head)
+                    .map(|head| head.into_nodehash())
+                    .collect()
+            })
+            .boxify()
+    }
+
+    /// Mark a changeset as public, e.g. because a client pushed its phase.
+    pub fn mark_public(&self, cs_id: HgChangesetId) -> BoxFuture<(), Error> {
+        self.store.add(self.repo_id, cs_id, Phase::Public)
+    }
+
+    /// Find the roots of the draft changesets reachable from `heads`, which is what Mercurial
+    /// expects to find in the `phases` listkeys namespace.
+    pub fn get_draft_roots(
+        &self,
+        heads: Vec<HgChangesetId>,
+    ) -> BoxFuture<Vec<HgChangesetId>, Error> {
+        if !self.has_publishing_bookmarks() {
+            return future::ok(vec![]).boxify();
+        }
+
+        self.find_drafts(heads)
+            .map(|drafts| {
+                let draft_ids: HashSet<_> = drafts.iter().map(|&(cs_id, _)| cs_id).collect();
+                drafts
+                    .into_iter()
+                    .filter(|&(_, ref parents)| {
+                        parents.iter().all(|parent| !draft_ids.contains(parent))
+                    })
+                    .map(|(cs_id, _)| cs_id)
+                    .collect()
+            })
+            .boxify()
+    }
+
+    /// Find the draft ancestors of `heads` with their parents, in decreasing generation order.
+    ///
+    /// This is a single walk for all of `heads`, one generation at a time so that every
+    /// changeset is visited after all its children. It stops at the changesets marked public in
+    /// the store and at the ancestors of the publishing bookmarks. Both of their ancestors are
+    /// walked alongside, but only down to the generation of the lowest draft changeset.
+    fn find_drafts(
+        &self,
+        heads: Vec<HgChangesetId>,
+    ) -> BoxFuture<Vec<(HgChangesetId, Vec<HgChangesetId>)>, Error> {
+        let this = self.clone();
+        self.bookmark_heads()
+            .and_then(move |public_heads| {
+                let public_ancestors = DifferenceOfUnionsOfAncestorsNodeStream::new_union(
+                    &this.repo,
+                    this.repo_generation.clone(),
+                    public_heads,
+                ).and_then({
+                    let this = this.clone();
+                    move |node| this.with_generation(HgChangesetId::new(node))
+                })
+                    .map(|(cs_id, generation)| (cs_id.into_nodehash(), generation))
+                    .boxify();
+                let heads = future::join_all(heads.into_iter().map({
+                    let this = this.clone();
+                    move |head| this.with_generation(head)
+                }));
+
+                public_ancestors
+                    .into_future()
+                    .map_err(|(err, _)| err)
+                    .join(heads)
+                    .and_then(move |((next_public, public_ancestors), heads)| {
+                        let mut drafts_pending = Pending::new();
+                        add_pending(&mut drafts_pending, heads);
+                        let state = (
+                            drafts_pending,
+                            Pending::new(),
+                            (public_ancestors, next_public),
+                            vec![],
+                        );
+                        loop_fn(state, move |state| this.visit_generation(state))
+                    })
+            })
+            .boxify()
+    }
+
+    /// Visits the changesets of the highest pending generation, see `find_drafts`
+    fn visit_generation(
+        &self,
+        (mut drafts_pending, mut public_pending, public_ancestors, mut drafts): (
+            Pending,
+            Pending,
+            PublicAncestors,
+            Vec<(HgChangesetId, Vec<HgChangesetId>)>,
+        ),
+    ) -> BoxFuture<
+        Loop<
+            Vec<(HgChangesetId, Vec<HgChangesetId>)>,
+            (
+                Pending,
+                Pending,
+                PublicAncestors,
+                Vec<(HgChangesetId, Vec<HgChangesetId>)>,
+            ),
+        >,
+        Error,
+    > {
+        // Changesets marked public are walked too, as their ancestors might be reached by the
+        // draft walk further down
+        let highest_draft = drafts_pending.keys().next_back().cloned();
+        let highest_public = public_pending.keys().next_back().cloned();
+        let generation = match (highest_draft, highest_public) {
+            (None, _) => return future::ok(Loop::Break(drafts)).boxify(),
+            (Some(draft), None) => draft,
+            (Some(draft), Some(public)) => cmp::max(draft, public),
+        };
+        let candidates = drafts_pending.remove(&generation).unwrap_or_default();
+        let stored_public = public_pending.remove(&generation).unwrap_or_default();
+        let nodes: Vec<_> = candidates.union(&stored_public).cloned().collect();
+
+        let this = self.clone();
+        public_at_generation(public_ancestors, generation)
+            .and_then(move |(bookmark_public, public_ancestors)| {
+                let visits = nodes.into_iter().map(move |node| {
+                    let cs_id = HgChangesetId::new(node);
+                    let phase = if bookmark_public.contains(&node) {
+                        // Its ancestors are walked by the public ancestors stream
+                        return future::ok(None).boxify();
+                    } else if stored_public.contains(&node) {
+                        future::ok(Some(Phase::Public)).boxify()
+                    } else {
+                        this.store.get(this.repo_id, cs_id)
+                    };
+
+                    let this = this.clone();
+                    phase
+                        .and_then(move |phase| {
+                            this.repo
+                                .get_changeset_parents(&cs_id)
+                                .and_then({
+                                    let this = this.clone();
+                                    move |parents| {
+                                        future::join_all(
+                                            parents
+                                                .into_iter()
+                                                .map(move |parent| this.with_generation(parent)),
+                                        )
+                                    }
+                                })
+                                .map(move |parents| Some((cs_id, phase, parents)))
+                        })
+                        .boxify()
+                });
+
+                future::join_all(visits).map(move |visited| {
+                    for (cs_id, phase, parents) in visited.into_iter().filter_map(|v| v) {
+                        if phase == Some(Phase::Public) {
+                            add_pending(&mut public_pending, parents);
+                        } else {
+                            let parent_ids = parents.iter().map(|&(parent, _)| parent).collect();
+                            add_pending(&mut drafts_pending, parents);
+                            drafts.push((cs_id, parent_ids));
+                        }
+                    }
+                    Loop::Continue((drafts_pending, public_pending, public_ancestors, drafts))
+                })
+            })
+            .boxify()
+    }
+
+    fn with_generation(
+        &self,
+        cs_id: HgChangesetId,
+    ) -> impl Future<Item = (HgChangesetId, Generation), Error = Error> + Send {
+        self.repo_generation
+            .get(&self.repo, cs_id.into_nodehash())
+            .map(move |generation| (cs_id, generation))
+            .from_err()
+    }
+}
+
+fn add_pending(pending: &mut Pending, changesets: Vec<(HgChangesetId, Generation)>) {
+    for (cs_id, generation) in changesets {
+        pending
+            .entry(generation)
+            .or_insert_with(HashSet::new)
+            .insert(cs_id.into_nodehash());
+    }
+}
+
+/// Takes the ancestors of `generation` from `public_ancestors`, skipping those above it.
+fn public_at_generation(
+    public_ancestors: PublicAncestors,
+    generation: Generation,
+) -> BoxFuture<(HashSet<HgNodeHash>, PublicAncestors), Error> {
+    loop_fn(
+        (public_ancestors, HashSet::new()),
+        move |((ancestors, next), mut found)| match next {
+            Some((node, node_generation)) if node_generation >= generation => {
+                if node_generation == generation {
+                    found.insert(node);
+                }
+                ancestors
+                    .into_future()
+                    .map_err(|(err, _)| err)
+                    .map(move |(next, ancestors)| Loop::Continue(((ancestors, next), found)))
+                    .boxify()
+            }
+            next => future::ok(Loop::Break((found, (ancestors, next)))).boxify(),
+        },
+    ).boxify()
+}
+
@@ -0,0 +1,30 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Implementations for wrappers that enable dynamic dispatch. Add more as necessary.
+
+use std::sync::Arc;
+
+use futures_ext::BoxFuture;
+use mercurial_types::{HgChangesetId, RepositoryId};
+
+use {Phase, Phases};
+use errors::*;
+
+impl Phases for Arc<Phases> {
+    fn add(
+        &self,
+        repo_id: RepositoryId,
+        cs_id: HgChangesetId,
+        phase: Phase,
+    ) -> BoxFuture<(), Error> {
+        (**self).add(repo_id, cs_id, phase)
+    }
+
+    fn get(&self, repo_id: RepositoryId, cs_id: HgChangesetId) -> BoxFuture<Option<Phase>, Error> {
+        (**self).get(repo_id, cs_id)
+    }
+}
@@ -0,0 +1,275 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Tests for the phases store and for deriving phases from publishing bookmarks.
+
+#![deny(warnings)]
+
+extern crate async_unit;
+extern crate futures;
+
+extern crate bookmarks;
+extern crate linear;
+extern crate mercurial_types;
+extern crate mercurial_types_mocks;
+extern crate phases;
+extern crate repoinfo;
+extern crate skiplist;
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use futures::Future;
+
+use bookmarks::{Bookmark, BookmarkUpdateReason};
+use mercurial_types::{HgChangesetId, RepositoryId};
+use mercurial_types_mocks::nodehash::{ONES_CSID, TWOS_CSID};
+use mercurial_types_mocks::repo::{REPO_ONE, REPO_ZERO};
+use phases::{MysqlPhases, Phase, Phases, RepoPhases, SqlitePhases};
+use repoinfo::RepoGenCache;
+use skiplist::SkiplistIndex;
+
+fn add_and_get<P: Phases>(phases: P) {
+    phases
+        .add(REPO_ZERO, ONES_CSID, Phase::Public)
+        .wait()
+        .expect("Adding new phase failed");
+
+    let result = phases
+        .get(REPO_ZERO, ONES_CSID)
+        .wait()
+        .expect("Get failed");
+    assert_eq!(result, Some(Phase::Public));
+
+    // Phases from other repos are not returned
+    let result = phases
+        .get(REPO_ONE, ONES_CSID)
+        .wait()
+        .expect("Get failed");
+    assert_eq!(result, None);
+}
+
+fn missing<P: Phases>(phases: P) {
+    let result = phases
+        .get(REPO_ZERO, TWOS_CSID)
+        .wait()
+        .expect("Failed to fetch missing phase (should succeed with None instead)");
+    assert_eq!(result, None);
+}
+
+fn replace<P: Phases>(phases: P) {
+    phases
+        .add(REPO_ZERO, ONES_CSID, Phase::Draft)
+        .wait()
+        .expect("Adding new phase failed");
+    phases
+        .add(REPO_ZERO, ONES_CSID, Phase::Public)
+        .wait()
+        .expect("Replacing phase failed");
+
+    let result = phases
+        .get(REPO_ZERO, ONES_CSID)
+        .wait()
+        .expect("Get failed");
+    assert_eq!(result, Some(Phase::Public));
+}
+
+macro_rules! phases_test_impl {
+    ($mod_name: ident => {
+        new: $new_cb: expr,
+    }) => {
+        mod $mod_name {
+            use super::*;
+
+            #[test]
+            fn test_add_and_get() {
+                async_unit::tokio_unit_test(|| {
+                    add_and_get($new_cb());
+                });
+            }
+
+            #[test]
+            fn test_missing() {
+                async_unit::tokio_unit_test(|| {
+                    missing($new_cb());
+                });
+            }
+
+            #[test]
+            fn test_replace() {
+                async_unit::tokio_unit_test(|| {
+                    replace($new_cb());
+                });
+            }
+        }
+    }
+}
+
+phases_test_impl! {
+    sqlite_test => {
+        new: new_sqlite,
+    }
+}
+
+phases_test_impl! {
+    sqlite_arced_test => {
+        new: new_sqlite_arced,
+    }
+}
+
+phases_test_impl! {
+    mysql_test => {
+        new: new_mysql,
+    }
+}
+
+fn new_sqlite() -> SqlitePhases {
+    SqlitePhases::in_memory().expect("Creating an in-memory SQLite database failed")
+}
+
+fn new_sqlite_arced() -> Arc<Phases> {
+    Arc::new(new_sqlite())
+}
+
+fn new_mysql() -> MysqlPhases {
+    MysqlPhases::create_test_db("phases_test").expect("Failed to create test database")
+}
+
+fn cs_id(hash: &str) -> HgChangesetId {
+    HgChangesetId::from_str(hash).expect("Invalid changeset id")
+}
+
+fn linear_repo_phases() -> RepoPhases {
+    let repo = Arc::new(linear::getrepo(None));
+    let master = Bookmark::new("master").unwrap();
+
+    let mut txn = repo.update_bookmark_transaction(BookmarkUpdateReason::TestMove, "test");
+    txn.create(&master, &cs_id("cb15ca4a43a59acff5388cea9648c162afde8372"))
+        .unwrap();
+    txn.commit().wait().expect("Bookmark creation failed");
+
+    RepoPhases::new(
+        repo.clone(),
+        RepoGenCache::new(10),
+        SkiplistIndex::new(repo, 100000),
+        Arc::new(new_sqlite()),
+        RepositoryId::new(1),
+        vec![master],
+    )
+}
+
+#[test]
+fn test_ancestors_of_publishing_bookmarks_are_public() {
+    async_unit::tokio_unit_test(|| {
+        let phases = linear_repo_phases();
+
+        for (hash, expected) in vec![
+            ("2d7d4ba9ce0a6ffd222de7785b249ead9c51c536", Phase::Public),
+            ("cb15ca4a43a59acff5388cea9648c162afde8372", Phase::Public),
+            ("eed3a8c0ec67b6a6fe2eb3543334df3f0b4f202b", Phase::Draft),
+            ("a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157", Phase::Draft),
+        ] {
+            let phase = phases
+                .get_phase(cs_id(hash))
+                .wait()
+                .expect("Getting phase failed");
+            assert_eq!(phase, expected, "wrong phase for {}", hash);
+        }
+    });
+}
+
+#[test]
+fn test_draft_roots() {
+    async_unit::tokio_unit_test(|| {
+        let phases = linear_repo_phases();
+
+        let roots = phases
+            .get_draft_roots(vec![cs_id("a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157")])
+            .wait()
+            .expect("Getting draft roots failed");
+        assert_eq!(roots, vec![cs_id("eed3a8c0ec67b6a6fe2eb3543334df3f0b4f202b")]);
+    });
+}
+
+#[test]
+fn test_marked_public() {
+    async_unit::tokio_unit_test(|| {
+        let phases = linear_repo_phases();
+        let tip = cs_id("a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157");
+
+        phases.mark_public(tip).wait().expect("Marking public failed");
+        let phase = phases.get_phase(tip).wait().expect("Getting phase failed");
+        assert_eq!(phase, Phase::Public);
+    });
+}
+
+#[test]
+fn test_draft_roots_of_several_heads() {
+    async_unit::tokio_unit_test(|| {
+        let phases = linear_repo_phases();
+
+        let roots = phases
+            .get_draft_roots(vec![
+                cs_id("a5ffa77602a066db7d5cfb9fb5823a0895717c5a"),
+                cs_id("0ed509bf086fadcb8a8a5384dc3b550729b0fc17"),
+                cs_id("cb15ca4a43a59acff5388cea9648c162afde8372"),
+            ])
+            .wait()
+            .expect("Getting draft roots failed");
+        assert_eq!(roots, vec![cs_id("eed3a8c0ec67b6a6fe2eb3543334df3f0b4f202b")]);
+    });
+}
+
+#[test]
+fn test_draft_roots_below_marked_public() {
+    async_unit::tokio_unit_test(|| {
+        let phases = linear_repo_phases();
+
+        // The ancestors of a changeset marked public are public, even if they are not
+        // ancestors of a publishing bookmark
+        phases
+            .mark_public(cs_id("a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157"))
+            .wait()
+            .expect("Marking public failed");
+
+        let roots = phases
+            .get_draft_roots(vec![cs_id("a5ffa77602a066db7d5cfb9fb5823a0895717c5a")])
+            .wait()
+            .expect("Getting draft roots failed");
+        assert_eq!(roots, vec![cs_id("3c15267ebf11807f3d772eb891272b911ec68759")]);
+    });
+}
+
+#[test]
+fn test_get_phase_does_not_write() {
+    async_unit::tokio_unit_test(|| {
+        let repo = Arc::new(linear::getrepo(None));
+        let master = Bookmark::new("master").unwrap();
+        let public = cs_id("cb15ca4a43a59acff5388cea9648c162afde8372");
+
+        let mut txn = repo.update_bookmark_transaction(BookmarkUpdateReason::TestMove, "test");
+        txn.create(&master, &public).unwrap();
+        txn.commit().wait().expect("Bookmark creation failed");
+
+        let store = Arc::new(new_sqlite());
+        let phases = RepoPhases::new(
+            repo.clone(),
+            RepoGenCache::new(10),
+            SkiplistIndex::new(repo, 100000),
+            store.clone(),
+            RepositoryId::new(1),
+            vec![master],
+        );
+
+        let phase = phases.get_phase(public).wait().expect("Getting phase failed");
+        assert_eq!(phase, Phase::Public);
+        let stored = store
+            .get(RepositoryId::new(1), public)
+            .wait()
+            .expect("Reading the store failed");
+        assert_eq!(stored, None);
+    });
+}
@@ -20,10 +20,12 @@ use blobrepo::BlobRepo;
 use mercurial_types::{Changeset, HgNodeHash};
 use mercurial_types::nodehash::HgChangesetId;
 use repoinfo::{Generation, RepoGenCache};
+use skiplist::SkiplistIndex;
 
 use IntersectNodeStream;
 use NodeStream;
 use errors::*;
+use unless_not_indexed;
 
 pub struct AncestorsNodeStream {
     repo: Arc<BlobRepo>,
@@ -157,6 +159,41 @@ where
     Box::new(common_ancestors(repo, repo_generation, nodes).take(1))
 }
 
+/// Like `common_ancestors`, but the common ancestors are looked up in a skip-list index. Fails with
+/// `skiplist::ErrorKind::NotIndexed` if the changesets are not indexed yet.
+pub fn common_ancestors_indexed<I>(index: &SkiplistIndex, nodes: I) -> Box<NodeStream>
+where
+    I: IntoIterator<Item = HgNodeHash>,
+{
+    index.common_ancestors(nodes.into_iter().collect())
+}
+
+/// Like `greatest_common_ancestor`, but looks the common ancestor up in a skip-list index rather
+/// than walking the ancestors of every node, unless the changesets are not indexed yet.
+pub fn greatest_common_ancestor_indexed<I>(
+    index: &SkiplistIndex,
+    repo: &Arc<BlobRepo>,
+    repo_generation: RepoGenCache,
+    nodes: I,
+) -> Box<NodeStream>
+where
+    I: IntoIterator<Item = HgNodeHash>,
+{
+    let nodes: Vec<_> = nodes.into_iter().collect();
+    let repo = repo.clone();
+    Box::new(
+        index
+            .greatest_common_ancestor(nodes.clone())
+            .then(move |node| {
+                unless_not_indexed(node).map(|node| match node {
+                    Some(node) => iter_ok::<_, Error>(node).boxed(),
+                    None => greatest_common_ancestor(&repo, repo_generation, nodes),
+                })
+            })
+            .flatten_stream(),
+    )
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -13,4 +13,8 @@ pub enum ErrorKind {
     #[fail(display = "repo error checking for node: {}", _0)] RepoError(HgNodeHash),
     #[fail(display = "could not fetch node generation")] GenerationFetchFailed,
     #[fail(display = "failed to fetch parent nodes")] ParentsFetchFailed,
+    #[fail(display = "invalid revset: {}", _0)] RevsetParseError(String),
+    #[fail(display = "unknown revision {}", _0)] UnknownRevision(String),
+    #[fail(display = "ambiguous revision {}", _0)] AmbiguousRevision(String),
+    #[fail(display = "bookmark {} does not exist", _0)] UnknownBookmark(String),
 }
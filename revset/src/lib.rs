@@ -6,6 +6,7 @@
 
 extern crate asyncmemo;
 extern crate blobrepo;
+extern crate bookmarks;
 #[macro_use]
 extern crate failure_ext as failure;
 #[macro_use]
@@ -15,6 +16,7 @@ extern crate futures_ext;
 extern crate maplit;
 extern crate mercurial_types;
 extern crate repoinfo;
+extern crate skiplist;
 
 use futures::stream::Stream;
 use mercurial_types::HgNodeHash;
@@ -38,17 +40,36 @@ pub use errors::{Error, ErrorKind};
 
 pub type NodeStream = Stream<Item = HgNodeHash, Error = errors::Error> + Send + 'static;
 
+/// Turns the error of a skip-list index query into `None` if the changesets are not indexed yet,
+/// in which case the history has to be walked instead.
+fn unless_not_indexed<T>(result: errors::Result<T>) -> errors::Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(err) => match err.downcast::<skiplist::ErrorKind>() {
+            Ok(skiplist::ErrorKind::NotIndexed(_)) => Ok(None),
+            Ok(err) => Err(err.into()),
+            Err(err) => Err(err),
+        },
+    }
+}
+
 mod validation;
 pub use validation::ValidateNodeStream;
 
 mod ancestors;
-pub use ancestors::{common_ancestors, greatest_common_ancestor, AncestorsNodeStream};
+pub use ancestors::{common_ancestors, common_ancestors_indexed, greatest_common_ancestor,
+                    greatest_common_ancestor_indexed, AncestorsNodeStream};
 
 mod ancestorscombinators;
 pub use ancestorscombinators::DifferenceOfUnionsOfAncestorsNodeStream;
 
 mod range;
-pub use range::RangeNodeStream;
+pub use range::{is_ancestor_indexed, range_indexed, RangeNodeStream};
+
+mod revsetlang;
+pub use revsetlang::Revset;
+
+mod query;
 
 pub use test::*;
 #[cfg(test)]
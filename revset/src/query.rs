@@ -0,0 +1,516 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+// Evaluation of parsed revsets into node streams. Every part of a revset produces its changesets
+// in decreasing order of generation numbers, which is what the set operations need. The output
+// of the whole revset is additionally sorted by hash within each generation, so it doesn't depend
+// on the iteration order of the hash sets in the streams.
+
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use futures::{future, stream, Future, Stream};
+use futures::future::{loop_fn, Loop};
+use futures_ext::{BoxFuture, FutureExt, StreamExt};
+
+use blobrepo::BlobRepo;
+use bookmarks::Bookmark;
+use mercurial_types::{Changeset, HgChangesetId, HgChangesetIdPrefix, HgNodeHash};
+use repoinfo::RepoGenCache;
+use skiplist::SkiplistIndex;
+
+use IntersectNodeStream;
+use NodeStream;
+use SetDifferenceNodeStream;
+use UnionNodeStream;
+use ancestorscombinators::DifferenceOfUnionsOfAncestorsNodeStream;
+use errors::*;
+use range::range_indexed;
+use revsetlang::Revset;
+
+const FETCH_CONCURRENCY: usize = 100;
+
+impl Revset {
+    /// Evaluate the revset, producing its changesets in decreasing order of generation numbers
+    /// and, within a generation, in increasing order of hashes.
+    ///
+    /// `not x` and `author(string)` on their own range over all the changesets reachable from
+    /// the heads of the repo.
+    ///
+    /// Ranges are looked up in `index`.
+    pub fn evaluate(
+        &self,
+        repo: &Arc<BlobRepo>,
+        repo_generation: RepoGenCache,
+        index: &SkiplistIndex,
+    ) -> Box<NodeStream> {
+        let nodes = self.evaluate_unsorted(repo, repo_generation.clone(), index);
+        sort_generations(repo.clone(), repo_generation, nodes)
+    }
+
+    /// Whether evaluating the revset ranges over all the changesets reachable from the heads of
+    /// the repo, because of a `not x` or an `author(string)` that is not intersected with another
+    /// set.
+    pub fn scans_all_changesets(&self) -> bool {
+        match *self {
+            Revset::Symbol(_) | Revset::Bookmark(_) => false,
+            Revset::Not(_) | Revset::Author(_) => true,
+            Revset::Ancestors(ref set) | Revset::Heads(ref set) => set.scans_all_changesets(),
+            Revset::Range(ref lhs, ref rhs)
+            | Revset::Only(ref lhs, ref rhs)
+            | Revset::Or(ref lhs, ref rhs) => {
+                lhs.scans_all_changesets() || rhs.scans_all_changesets()
+            }
+            Revset::And(ref lhs, ref rhs) => match (&**lhs, &**rhs) {
+                (set, &Revset::Not(ref excluded)) | (&Revset::Not(ref excluded), set) => {
+                    set.scans_all_changesets() || excluded.scans_all_changesets()
+                }
+                (set, &Revset::Author(_)) | (&Revset::Author(_), set) => {
+                    set.scans_all_changesets()
+                }
+                (lhs, rhs) => lhs.scans_all_changesets() || rhs.scans_all_changesets(),
+            },
+        }
+    }
+
+    fn evaluate_unsorted(
+        &self,
+        repo: &Arc<BlobRepo>,
+        repo_generation: RepoGenCache,
+        index: &SkiplistIndex,
+    ) -> Box<NodeStream> {
+        match *self {
+            Revset::Symbol(ref symbol) => Box::new(
+                resolve_symbol(repo.clone(), symbol.clone())
+                    .map(|node| stream::iter_ok::<_, Error>(vec![node]))
+                    .flatten_stream(),
+            ),
+            Revset::Ancestors(ref set) => {
+                let nodes =
+                    collect_nodes(set.evaluate_unsorted(repo, repo_generation.clone(), index));
+                let repo = repo.clone();
+                Box::new(
+                    nodes
+                        .map(move |nodes| {
+                            DifferenceOfUnionsOfAncestorsNodeStream::new_union(
+                                &repo,
+                                repo_generation,
+                                nodes,
+                            )
+                        })
+                        .flatten_stream(),
+                )
+            }
+            Revset::Range(ref start, ref end) => {
+                let starts =
+                    collect_nodes(start.evaluate_unsorted(repo, repo_generation.clone(), index));
+                let ends =
+                    collect_nodes(end.evaluate_unsorted(repo, repo_generation.clone(), index));
+                let repo = repo.clone();
+                let index = index.clone();
+                Box::new(
+                    starts
+                        .join(ends)
+                        .map(move |(starts, ends)| {
+                            let mut ranges = vec![];
+                            for start in starts {
+                                for end in ends.iter() {
+                                    ranges.push(range_indexed(
+                                        &index,
+                                        &repo,
+                                        repo_generation.clone(),
+                                        start,
+                                        *end,
+                                    ));
+                                }
+                            }
+                            UnionNodeStream::new(&repo, repo_generation, ranges).boxed()
+                        })
+                        .flatten_stream(),
+                )
+            }
+            Revset::Only(ref include, ref exclude) => {
+                let includes =
+                    collect_nodes(include.evaluate_unsorted(repo, repo_generation.clone(), index));
+                let excludes =
+                    collect_nodes(exclude.evaluate_unsorted(repo, repo_generation.clone(), index));
+                let repo = repo.clone();
+                Box::new(
+                    includes
+                        .join(excludes)
+                        .map(move |(includes, excludes)| {
+                            DifferenceOfUnionsOfAncestorsNodeStream::new_with_excludes(
+                                &repo,
+                                repo_generation,
+                                includes,
+                                excludes,
+                            )
+                        })
+                        .flatten_stream(),
+                )
+            }
+            Revset::And(ref lhs, ref rhs) => match (&**lhs, &**rhs) {
+                // Intersections with complements and authors don't need to go through all the
+                // changesets of the repo
+                (set, &Revset::Not(ref excluded)) | (&Revset::Not(ref excluded), set) => {
+                    SetDifferenceNodeStream::new(
+                        repo,
+                        repo_generation.clone(),
+                        set.evaluate_unsorted(repo, repo_generation.clone(), index),
+                        excluded.evaluate_unsorted(repo, repo_generation, index),
+                    ).boxed()
+                }
+                (set, &Revset::Author(ref author)) | (&Revset::Author(ref author), set) => {
+                    filter_author(repo, set.evaluate_unsorted(repo, repo_generation, index), author)
+                }
+                (lhs, rhs) => {
+                    let inputs = vec![
+                        lhs.evaluate_unsorted(repo, repo_generation.clone(), index),
+                        rhs.evaluate_unsorted(repo, repo_generation.clone(), index),
+                    ];
+                    IntersectNodeStream::new(repo, repo_generation, inputs).boxed()
+                }
+            },
+            Revset::Or(ref lhs, ref rhs) => {
+                let inputs = vec![
+                    lhs.evaluate_unsorted(repo, repo_generation.clone(), index),
+                    rhs.evaluate_unsorted(repo, repo_generation.clone(), index),
+                ];
+                UnionNodeStream::new(repo, repo_generation, inputs).boxed()
+            }
+            Revset::Not(ref set) => SetDifferenceNodeStream::new(
+                repo,
+                repo_generation.clone(),
+                all_changesets(repo, repo_generation.clone()),
+                set.evaluate_unsorted(repo, repo_generation, index),
+            ).boxed(),
+            Revset::Heads(ref set) => {
+                heads(repo, set.evaluate_unsorted(repo, repo_generation, index))
+            }
+            Revset::Bookmark(Some(ref name)) => {
+                let name = name.clone();
+                let bookmark = match Bookmark::new(&name) {
+                    Ok(bookmark) => repo.get_bookmark(&bookmark),
+                    Err(_) => future::ok(None).boxify(),
+                };
+                Box::new(
+                    bookmark
+                        .and_then(move |node| node.ok_or(ErrorKind::UnknownBookmark(name).into()))
+                        .map(|node| stream::iter_ok::<_, Error>(vec![node.into_nodehash()]))
+                        .flatten_stream(),
+                )
+            }
+            Revset::Bookmark(None) => {
+                let nodes = repo.get_bookmarks()
+                    .map(|(_, node)| node.into_nodehash())
+                    .collect();
+                sort_by_generation(repo.clone(), repo_generation, nodes.boxify())
+            }
+            Revset::Author(ref author) => {
+                filter_author(repo, all_changesets(repo, repo_generation), author)
+            }
+        }
+    }
+}
+
+fn collect_nodes(nodes: Box<NodeStream>) -> BoxFuture<Vec<HgNodeHash>, Error> {
+    nodes.collect().boxify()
+}
+
+/// Resolve a full hash, a bookmark name or a unique hash prefix, in this order, like Mercurial.
+fn resolve_symbol(repo: Arc<BlobRepo>, symbol: String) -> BoxFuture<HgNodeHash, Error> {
+    if let Ok(node) = HgNodeHash::from_str(&symbol) {
+        return repo.changeset_exists(&HgChangesetId::new(node))
+            .and_then(move |exists| {
+                if exists {
+                    Ok(node)
+                } else {
+                    Err(ErrorKind::UnknownRevision(symbol).into())
+                }
+            })
+            .boxify();
+    }
+
+    let bookmark = match Bookmark::new(&symbol) {
+        Ok(bookmark) => repo.get_bookmark(&bookmark),
+        Err(_) => future::ok(None).boxify(),
+    };
+    bookmark
+        .and_then(move |node| {
+            if let Some(node) = node {
+                return future::ok(node.into_nodehash()).boxify();
+            }
+            let prefix = match HgChangesetIdPrefix::from_str(&symbol) {
+                Ok(prefix) => prefix,
+                Err(_) => return future::err(ErrorKind::UnknownRevision(symbol).into()).boxify(),
+            };
+            repo.get_changesets_by_prefix(prefix, 2)
+                .and_then(move |candidates| match candidates.len() {
+                    0 => Err(ErrorKind::UnknownRevision(symbol).into()),
+                    1 => Ok(candidates[0].into_nodehash()),
+                    _ => Err(ErrorKind::AmbiguousRevision(symbol).into()),
+                })
+                .boxify()
+        })
+        .boxify()
+}
+
+/// All the changesets reachable from the heads of the repo.
+fn all_changesets(repo: &Arc<BlobRepo>, repo_generation: RepoGenCache) -> Box<NodeStream> {
+    let heads = repo.get_heads().collect();
+    let repo = repo.clone();
+    Box::new(
+        heads
+            .map(move |heads| {
+                DifferenceOfUnionsOfAncestorsNodeStream::new_union(&repo, repo_generation, heads)
+            })
+            .flatten_stream(),
+    )
+}
+
+/// The changesets of a set that are not parents of other changesets of the set.
+fn heads(repo: &Arc<BlobRepo>, set: Box<NodeStream>) -> Box<NodeStream> {
+    let repo = repo.clone();
+    let nodes = collect_nodes(set).and_then(move |nodes| {
+        stream::iter_ok::<_, Error>(nodes.clone())
+            .map(move |node| repo.get_changeset_parents(&HgChangesetId::new(node)))
+            .buffered(FETCH_CONCURRENCY)
+            .concat2()
+            .map(move |parents| {
+                let parents: HashSet<_> = parents
+                    .into_iter()
+                    .map(|parent| parent.into_nodehash())
+                    .collect();
+                let heads: Vec<_> = nodes
+                    .into_iter()
+                    .filter(|node| !parents.contains(node))
+                    .collect();
+                stream::iter_ok::<_, Error>(heads)
+            })
+    });
+    Box::new(nodes.flatten_stream())
+}
+
+/// The changesets of a set whose author contains `author`, ignoring case like Mercurial.
+fn filter_author(repo: &Arc<BlobRepo>, set: Box<NodeStream>, author: &str) -> Box<NodeStream> {
+    let repo = repo.clone();
+    let author = author.to_lowercase();
+    let changesets = set.map(move |node| {
+        repo.get_changeset_by_changesetid(&HgChangesetId::new(node))
+            .map(move |changeset| (node, changeset))
+    });
+    Box::new(
+        changesets
+            .buffered(FETCH_CONCURRENCY)
+            .filter_map(move |(node, changeset)| {
+                let user = String::from_utf8_lossy(changeset.user()).to_lowercase();
+                if user.contains(&author) {
+                    Some(node)
+                } else {
+                    None
+                }
+            }),
+    )
+}
+
+/// Turn changesets in any order into a stream in decreasing order of generation numbers.
+fn sort_by_generation(
+    repo: Arc<BlobRepo>,
+    repo_generation: RepoGenCache,
+    nodes: BoxFuture<Vec<HgNodeHash>, Error>,
+) -> Box<NodeStream> {
+    let nodes = nodes.and_then(move |nodes| {
+        let nodes: HashSet<_> = nodes.into_iter().collect();
+        let generations = nodes
+            .into_iter()
+            .map(|node| repo_generation.get(&repo, node).map(move |gen| (gen, node)))
+            .collect::<Vec<_>>();
+        future::join_all(generations).map(|mut nodes| {
+            nodes.sort_by_key(|&(gen, node)| (Reverse(gen), node));
+            stream::iter_ok::<_, Error>(nodes.into_iter().map(|(_, node)| node))
+        })
+    });
+    Box::new(nodes.flatten_stream())
+}
+
+/// Sort the changesets of each generation of a stream in decreasing order of generation numbers
+/// by hash.
+fn sort_generations(
+    repo: Arc<BlobRepo>,
+    repo_generation: RepoGenCache,
+    nodes: Box<NodeStream>,
+) -> Box<NodeStream> {
+    let nodes = nodes
+        .and_then(move |node| repo_generation.get(&repo, node).map(move |gen| (gen, node)))
+        .boxify();
+
+    // Each step collects one generation, and keeps the first changeset of the next one
+    let generations = stream::unfold(Some((nodes, None)), |state| {
+        state.map(|(nodes, first)| {
+            let generation: Vec<_> = first.into_iter().collect();
+            loop_fn((nodes, generation), |(nodes, mut generation)| {
+                nodes
+                    .into_future()
+                    .map_err(|(err, _)| err)
+                    .map(move |(next, nodes)| match next {
+                        Some((gen, node))
+                            if generation.first().map_or(true, |&(first, _)| first == gen) =>
+                        {
+                            generation.push((gen, node));
+                            Loop::Continue((nodes, generation))
+                        }
+                        next => Loop::Break((nodes, generation, next)),
+                    })
+            }).map(|(nodes, generation, next)| {
+                let mut generation: Vec<_> = generation.into_iter().map(|(_, node)| node).collect();
+                generation.sort();
+                (generation, next.map(|next| (nodes, Some(next))))
+            })
+        })
+    });
+
+    Box::new(
+        generations
+            .map(|generation| stream::iter_ok::<_, Error>(generation))
+            .flatten(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_unit;
+    use linear;
+    use merge_uneven;
+    use tests::string_to_nodehash;
+
+    fn query(repo: &Arc<BlobRepo>, revset: &str) -> Vec<HgNodeHash> {
+        Revset::parse(revset)
+            .expect("parsing failed")
+            .evaluate(
+                repo,
+                RepoGenCache::new(10),
+                &SkiplistIndex::new(repo.clone(), 100000),
+            )
+            .collect()
+            .wait()
+            .expect("evaluation failed")
+    }
+
+    fn query_error(repo: &Arc<BlobRepo>, revset: &str) -> ErrorKind {
+        Revset::parse(revset)
+            .expect("parsing failed")
+            .evaluate(
+                repo,
+                RepoGenCache::new(10),
+                &SkiplistIndex::new(repo.clone(), 100000),
+            )
+            .collect()
+            .wait()
+            .expect_err("evaluation succeeded")
+            .downcast::<ErrorKind>()
+            .expect("unexpected error")
+    }
+
+    #[test]
+    fn linear_queries() {
+        async_unit::tokio_unit_test(|| {
+            let repo = Arc::new(linear::getrepo(None));
+
+            assert_eq!(
+                query(&repo, "::d0a361e9022d226ae52f689667bd7d212a19cfe0"),
+                vec![
+                    string_to_nodehash("d0a361e9022d226ae52f689667bd7d212a19cfe0"),
+                    string_to_nodehash("607314ef579bd2407752361ba1b0c1729d08b281"),
+                    string_to_nodehash("3e0e761030db6e479a7fb58b12881883f9f8c63f"),
+                    string_to_nodehash("2d7d4ba9ce0a6ffd222de7785b249ead9c51c536"),
+                ]
+            );
+            // Prefixes and bookmarks
+            assert_eq!(
+                query(&repo, "607314::head-a5ffa77602a066db7d5cfb9fb5823a0895717c5a % a9473b"),
+                vec![
+                    string_to_nodehash("a5ffa77602a066db7d5cfb9fb5823a0895717c5a"),
+                    string_to_nodehash("3c15267ebf11807f3d772eb891272b911ec68759"),
+                ]
+            );
+            assert_eq!(
+                query(&repo, "ancestors(cb15ca) and not ::607314"),
+                vec![
+                    string_to_nodehash("cb15ca4a43a59acff5388cea9648c162afde8372"),
+                    string_to_nodehash("d0a361e9022d226ae52f689667bd7d212a19cfe0"),
+                ]
+            );
+            assert_eq!(
+                query(&repo, "bookmark()"),
+                vec![string_to_nodehash("a5ffa77602a066db7d5cfb9fb5823a0895717c5a")]
+            );
+            assert_eq!(query(&repo, "not ::bookmark()"), vec![]);
+            assert_eq!(query(&repo, "author(JSGF)").len(), 10);
+            assert_eq!(query(&repo, "::607314 and author(nobody)"), vec![]);
+
+            match query_error(&repo, "bookmark(nonexistent)") {
+                ErrorKind::UnknownBookmark(_) => {}
+                err => panic!("unexpected error {:?}", err),
+            }
+            match query_error(&repo, "1234567890123456789012345678901234567890") {
+                ErrorKind::UnknownRevision(_) => {}
+                err => panic!("unexpected error {:?}", err),
+            }
+            match query_error(&repo, "unknown") {
+                ErrorKind::UnknownRevision(_) => {}
+                err => panic!("unexpected error {:?}", err),
+            }
+        });
+    }
+
+    #[test]
+    fn full_scans() {
+        for (revset, expected) in vec![
+            ("::a % b", false),
+            ("::a and not b", false),
+            ("author(x) & (a or b)", false),
+            ("not a", true),
+            ("author(x)", true),
+            ("a or not b", true),
+            ("::a % not b", true),
+            ("not a and not b", true),
+        ] {
+            assert_eq!(
+                Revset::parse(revset).unwrap().scans_all_changesets(),
+                expected,
+                "{}",
+                revset
+            );
+        }
+    }
+
+    #[test]
+    fn merge_queries() {
+        async_unit::tokio_unit_test(|| {
+            let repo = Arc::new(merge_uneven::getrepo(None));
+
+            assert_eq!(
+                query(&repo, "heads(::3cda5c78 or ::d7542c9d)"),
+                vec![
+                    string_to_nodehash("3cda5c78aa35f0f5b09780d971197b51cad4613a"),
+                    string_to_nodehash("d7542c9db7f4c77dab4b315edd328edf1514952f"),
+                ]
+            );
+            assert_eq!(
+                query(&repo, "15c40d0a::75742e6f and ::16839021"),
+                vec![
+                    string_to_nodehash("16839021e338500b3cf7c9b871c8a07351697d68"),
+                    string_to_nodehash("1d8a907f7b4bf50c6a09c16361e2205047ecc5e5"),
+                    string_to_nodehash("3cda5c78aa35f0f5b09780d971197b51cad4613a"),
+                    string_to_nodehash("15c40d0abc36d47fb51c8eaec51ac7aad31f669c"),
+                ]
+            );
+        });
+    }
+}
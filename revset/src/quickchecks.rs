@@ -6,7 +6,9 @@
 
 use async_unit;
 use failure::Error;
+use futures::Future;
 use futures::executor::spawn;
+use futures::stream::Stream;
 use futures_ext::{BoxStream, StreamExt};
 use quickcheck::{quickcheck, Arbitrary, Gen};
 use quickcheck::rand::{thread_rng, Rng, distributions::{Sample, range::Range}};
@@ -17,6 +19,7 @@ use std::sync::Arc;
 use blobrepo::BlobRepo;
 use mercurial_types::HgNodeHash;
 use repoinfo::RepoGenCache;
+use skiplist::SkiplistIndex;
 
 use branch_even;
 use branch_uneven;
@@ -28,9 +31,11 @@ use unshared_merge_even;
 use unshared_merge_uneven;
 
 use NodeStream;
-use ancestors::AncestorsNodeStream;
+use ancestors::{common_ancestors, common_ancestors_indexed, greatest_common_ancestor_indexed,
+                AncestorsNodeStream};
 use ancestorscombinators::DifferenceOfUnionsOfAncestorsNodeStream;
 use intersectnodestream::IntersectNodeStream;
+use range::{range_indexed, RangeNodeStream};
 use setdifferencenodestream::SetDifferenceNodeStream;
 use singlenodehash::SingleNodeHash;
 use unionnodestream::UnionNodeStream;
@@ -430,3 +435,99 @@ ancestors_check!(ancestors_check_merge_even, merge_even);
 ancestors_check!(ancestors_check_merge_uneven, merge_uneven);
 ancestors_check!(ancestors_check_unshared_merge_even, unshared_merge_even);
 ancestors_check!(ancestors_check_unshared_merge_uneven, unshared_merge_uneven);
+
+fn collect_hashes(stream: Box<NodeStream>) -> HashSet<HgNodeHash> {
+    stream.collect().wait().expect("unexpected error").into_iter().collect()
+}
+
+// The skip-list index must agree with the revsets that walk the history: every pair of changesets
+// is checked, as the fixtures are small.
+macro_rules! skiplist_check {
+    ($test_name:ident, $repo:ident) => {
+        #[test]
+        fn $test_name() {
+            async_unit::tokio_unit_test(|| {
+                let repo = Arc::new($repo::getrepo(None));
+                let repo_generation = RepoGenCache::new(10);
+                let index = SkiplistIndex::new(repo.clone(), 100000);
+                let all_changesets = get_changesets_from_repo(&*repo);
+
+                for &end in all_changesets.iter() {
+                    let ancestors = collect_hashes(
+                        AncestorsNodeStream::new(&repo, repo_generation.clone(), end).boxed()
+                    );
+
+                    for &start in all_changesets.iter() {
+                        let is_ancestor = index
+                            .is_ancestor(start, end)
+                            .wait()
+                            .expect("unexpected error");
+                        assert_eq!(
+                            is_ancestor,
+                            ancestors.contains(&start),
+                            "is_ancestor is wrong for {} {}",
+                            start,
+                            end
+                        );
+
+                        let expected = RangeNodeStream::new(
+                            &repo, repo_generation.clone(), start, end
+                        );
+                        let actual =
+                            range_indexed(&index, &repo, repo_generation.clone(), start, end);
+                        assert!(
+                            match_streams(expected.boxify(), actual.boxify()),
+                            "ranges do not match for {} {}",
+                            start,
+                            end
+                        );
+
+                        // Several common ancestors may have the greatest generation number, so
+                        // check that the one found is one of them
+                        let common = collect_hashes(
+                            common_ancestors(&repo, repo_generation.clone(), vec![start, end])
+                        );
+                        assert_eq!(
+                            collect_hashes(common_ancestors_indexed(&index, vec![start, end])),
+                            common,
+                            "common ancestors do not match for {} {}",
+                            start,
+                            end
+                        );
+                        let greatest = collect_hashes(greatest_common_ancestor_indexed(
+                            &index,
+                            &repo,
+                            repo_generation.clone(),
+                            vec![start, end],
+                        ));
+                        let generation = |node| {
+                            repo_generation
+                                .get(&repo, node)
+                                .wait()
+                                .expect("unexpected error")
+                        };
+                        match greatest.iter().next() {
+                            Some(&node) => {
+                                assert!(common.contains(&node));
+                                assert!(common.iter().all(|&other| {
+                                    generation(other) <= generation(node)
+                                }));
+                            }
+                            None => assert!(common.is_empty()),
+                        }
+                        assert!(greatest.len() <= 1);
+                    }
+                }
+            })
+        }
+    }
+}
+
+skiplist_check!(skiplist_check_branch_even, branch_even);
+skiplist_check!(skiplist_check_branch_uneven, branch_uneven);
+skiplist_check!(skiplist_check_branch_wide, branch_wide);
+skiplist_check!(skiplist_check_linear, linear);
+skiplist_check!(skiplist_check_merge_even, merge_even);
+skiplist_check!(skiplist_check_merge_uneven, merge_uneven);
+skiplist_check!(skiplist_check_unshared_merge_even, unshared_merge_even);
+skiplist_check!(skiplist_check_unshared_merge_uneven, unshared_merge_uneven);
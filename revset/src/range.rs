@@ -11,16 +11,19 @@ use std::mem::replace;
 use std::sync::Arc;
 
 use futures::{Async, Poll};
-use futures::future::Future;
+use futures::future::{self, Future};
 use futures::stream::{self, iter_ok, Stream};
+use futures_ext::{BoxFuture, FutureExt};
 
 use blobrepo::BlobRepo;
 use mercurial_types::{Changeset, HgNodeHash};
 use mercurial_types::nodehash::HgChangesetId;
 use repoinfo::{Generation, RepoGenCache};
+use skiplist::SkiplistIndex;
 
 use NodeStream;
 use errors::*;
+use unless_not_indexed;
 
 #[derive(Clone, Copy, Eq, Hash, Ord, PartialEq, PartialOrd)]
 struct HashGen {
@@ -228,6 +231,54 @@ impl Stream for RangeNodeStream {
     }
 }
 
+/// Like `RangeNodeStream`, but the range is found with a skip-list index, which only looks at
+/// the changesets in the range and the parents of the merges in it. The history is walked with
+/// `RangeNodeStream` if the changesets are not indexed yet.
+pub fn range_indexed(
+    index: &SkiplistIndex,
+    repo: &Arc<BlobRepo>,
+    repo_generation: RepoGenCache,
+    start: HgNodeHash,
+    end: HgNodeHash,
+) -> Box<NodeStream> {
+    let repo = repo.clone();
+    Box::new(
+        index
+            .range(start, end)
+            .then(move |range| {
+                unless_not_indexed(range).map(|range| match range {
+                    Some(nodes) => iter_ok::<_, Error>(nodes).boxed(),
+                    None => RangeNodeStream::new(&repo, repo_generation, start, end).boxed(),
+                })
+            })
+            .flatten_stream(),
+    )
+}
+
+/// Whether `ancestor` is an ancestor of `descendant`, looked up in a skip-list index. The history
+/// is walked with `RangeNodeStream` if the changesets are not indexed yet.
+pub fn is_ancestor_indexed(
+    index: &SkiplistIndex,
+    repo: &Arc<BlobRepo>,
+    repo_generation: RepoGenCache,
+    ancestor: HgNodeHash,
+    descendant: HgNodeHash,
+) -> BoxFuture<bool, Error> {
+    let repo = repo.clone();
+    index
+        .is_ancestor(ancestor, descendant)
+        .then(move |is_ancestor| match unless_not_indexed(is_ancestor) {
+            Ok(Some(is_ancestor)) => future::ok(is_ancestor).boxify(),
+            Ok(None) => RangeNodeStream::new(&repo, repo_generation, ancestor, descendant)
+                .take(1)
+                .collect()
+                .map(|range| !range.is_empty())
+                .boxify(),
+            Err(err) => future::err(err).boxify(),
+        })
+        .boxify()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -0,0 +1,397 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+// Parser for a subset of the Mercurial revset language. From the loosest to the tightest binding,
+// the operators are:
+//
+//   x or y, x | y     union
+//   x and y, x & y    intersection
+//   x % y             ancestors of x that are not ancestors of y
+//   not x, !x         changesets that are not in x
+//   x::y              descendants of x that are ancestors of y
+//   ::x               ancestors of x
+//
+// plus the functions ancestors(set), heads(set), bookmark([name]) and author(string). A symbol is
+// a bookmark name, a changeset hash or a unique prefix of one. Symbols and strings may be quoted
+// with ' or ", in which case \ escapes the next character.
+
+use std::fmt;
+use std::iter::Peekable;
+use std::str::{CharIndices, FromStr};
+
+use errors::*;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Revset {
+    Symbol(String),
+    Ancestors(Box<Revset>),
+    Range(Box<Revset>, Box<Revset>),
+    Only(Box<Revset>, Box<Revset>),
+    And(Box<Revset>, Box<Revset>),
+    Or(Box<Revset>, Box<Revset>),
+    Not(Box<Revset>),
+    Heads(Box<Revset>),
+    Bookmark(Option<String>),
+    Author(String),
+}
+
+impl Revset {
+    pub fn parse(query: &str) -> Result<Self> {
+        let tokens = tokenize(query)?;
+        if tokens.len() > MAX_TOKENS {
+            return Err(parse_error(format!(
+                "revset is longer than {} tokens",
+                MAX_TOKENS
+            )));
+        }
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            depth: 0,
+        };
+        let revset = parser.parse_expr(0)?;
+        match parser.next() {
+            None => Ok(revset),
+            Some(token) => Err(parse_error(format!("unexpected {}", token))),
+        }
+    }
+}
+
+impl FromStr for Revset {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Revset::parse(s)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Token {
+    Symbol(String),
+    String(String),
+    DoubleColon,
+    Percent,
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Symbol(ref symbol) => write!(fmt, "symbol {}", symbol),
+            Token::String(ref string) => write!(fmt, "string {:?}", string),
+            Token::DoubleColon => write!(fmt, "'::'"),
+            Token::Percent => write!(fmt, "'%'"),
+            Token::And => write!(fmt, "'and'"),
+            Token::Or => write!(fmt, "'or'"),
+            Token::Not => write!(fmt, "'not'"),
+            Token::LParen => write!(fmt, "'('"),
+            Token::RParen => write!(fmt, "')'"),
+        }
+    }
+}
+
+fn parse_error(msg: String) -> Error {
+    ErrorKind::RevsetParseError(msg).into()
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '.' || c == '/' || !c.is_ascii()
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars: Peekable<CharIndices> = query.char_indices().peekable();
+
+    while let Some((pos, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '%' => Token::Percent,
+            '&' => Token::And,
+            '|' => Token::Or,
+            '!' => Token::Not,
+            ':' => match chars.next() {
+                Some((_, ':')) => Token::DoubleColon,
+                _ => return Err(parse_error(format!("unexpected ':' at {}", pos))),
+            },
+            '\'' | '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '\\')) => if let Some((_, escaped)) = chars.next() {
+                            string.push(escaped);
+                        },
+                        Some((_, end)) if end == c => {
+                            tokens.push(Token::String(string));
+                            break;
+                        }
+                        Some((_, other)) => string.push(other),
+                        None => {
+                            return Err(parse_error(format!("unterminated string at {}", pos)))
+                        }
+                    }
+                }
+                continue;
+            }
+            c if is_symbol_char(c) => {
+                let mut end = pos + c.len_utf8();
+                while let Some(&(next_pos, next)) = chars.peek() {
+                    if !is_symbol_char(next) {
+                        break;
+                    }
+                    end = next_pos + next.len_utf8();
+                    chars.next();
+                }
+                match &query[pos..end] {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    symbol => Token::Symbol(symbol.to_string()),
+                }
+            }
+            c => return Err(parse_error(format!("unexpected {:?} at {}", c, pos))),
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+// Binding powers of the operators, the higher the tighter
+const OR_BP: u8 = 4;
+const AND_BP: u8 = 5;
+const NOT_BP: u8 = 10;
+const RANGE_BP: u8 = 17;
+
+// Limits on the size of revsets, as parsing and evaluating them recurses into the subexpressions
+const MAX_TOKENS: usize = 1000;
+const MAX_DEPTH: usize = 64;
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        match self.next() {
+            Some(ref token) if *token == expected => Ok(()),
+            Some(token) => Err(parse_error(format!("expected {}, got {}", expected, token))),
+            None => Err(parse_error(format!("expected {}", expected))),
+        }
+    }
+
+    /// Parse an expression whose operators bind tighter than `min_bp`
+    fn parse_expr(&mut self, min_bp: u8) -> Result<Revset> {
+        if self.depth == MAX_DEPTH {
+            return Err(parse_error(format!(
+                "revset is nested deeper than {} levels",
+                MAX_DEPTH
+            )));
+        }
+        self.depth += 1;
+        let revset = self.parse_nested_expr(min_bp);
+        self.depth -= 1;
+        revset
+    }
+
+    fn parse_nested_expr(&mut self, min_bp: u8) -> Result<Revset> {
+        let mut lhs = match self.next() {
+            Some(Token::Symbol(name)) => if self.peek() == Some(&Token::LParen) {
+                self.next();
+                self.parse_function(name)?
+            } else {
+                Revset::Symbol(name)
+            },
+            Some(Token::String(string)) => Revset::Symbol(string),
+            Some(Token::DoubleColon) => Revset::Ancestors(Box::new(self.parse_expr(RANGE_BP)?)),
+            Some(Token::Not) => Revset::Not(Box::new(self.parse_expr(NOT_BP)?)),
+            Some(Token::LParen) => {
+                let revset = self.parse_expr(0)?;
+                self.expect(Token::RParen)?;
+                revset
+            }
+            Some(token) => return Err(parse_error(format!("unexpected {}", token))),
+            None => return Err(parse_error("unexpected end of revset".to_string())),
+        };
+
+        loop {
+            let bp = match self.peek() {
+                Some(&Token::DoubleColon) => RANGE_BP,
+                Some(&Token::And) | Some(&Token::Percent) => AND_BP,
+                Some(&Token::Or) => OR_BP,
+                _ => break,
+            };
+            if bp <= min_bp {
+                break;
+            }
+
+            let op = self.next();
+            match self.peek() {
+                None | Some(&Token::RParen) if op == Some(Token::DoubleColon) => {
+                    return Err(parse_error("descendants (x::) are not supported".to_string()))
+                }
+                _ => {}
+            }
+            let rhs = Box::new(self.parse_expr(bp)?);
+            let lhs_box = Box::new(lhs);
+            lhs = match op {
+                Some(Token::DoubleColon) => Revset::Range(lhs_box, rhs),
+                Some(Token::And) => Revset::And(lhs_box, rhs),
+                Some(Token::Percent) => Revset::Only(lhs_box, rhs),
+                Some(Token::Or) => Revset::Or(lhs_box, rhs),
+                _ => unreachable!(),
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    /// Parse the arguments of a function after its opening parenthesis
+    fn parse_function(&mut self, name: String) -> Result<Revset> {
+        let arg = if self.peek() == Some(&Token::RParen) {
+            None
+        } else {
+            Some(self.parse_expr(0)?)
+        };
+        self.expect(Token::RParen)?;
+
+        match (name.as_str(), arg) {
+            ("ancestors", Some(set)) => Ok(Revset::Ancestors(Box::new(set))),
+            ("heads", Some(set)) => Ok(Revset::Heads(Box::new(set))),
+            ("bookmark", None) => Ok(Revset::Bookmark(None)),
+            ("bookmark", Some(Revset::Symbol(name))) => Ok(Revset::Bookmark(Some(name))),
+            ("author", Some(Revset::Symbol(string))) => Ok(Revset::Author(string)),
+            ("ancestors", None) | ("heads", None) => {
+                Err(parse_error(format!("{}() takes a revset", name)))
+            }
+            ("bookmark", Some(_)) | ("author", _) => {
+                Err(parse_error(format!("{}() takes a string", name)))
+            }
+            _ => Err(parse_error(format!("unknown function {}()", name))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn symbol(name: &str) -> Box<Revset> {
+        Box::new(Revset::Symbol(name.to_string()))
+    }
+
+    #[test]
+    fn parse_symbols() {
+        assert_eq!(Revset::parse("master").unwrap(), *symbol("master"));
+        assert_eq!(
+            Revset::parse("  remote/my-feature_1.0 ").unwrap(),
+            *symbol("remote/my-feature_1.0")
+        );
+        assert_eq!(Revset::parse("'with space'").unwrap(), *symbol("with space"));
+        assert_eq!(Revset::parse(r#""a\"b""#).unwrap(), *symbol("a\"b"));
+    }
+
+    #[test]
+    fn parse_precedence() {
+        assert_eq!(
+            Revset::parse("a or b and not c").unwrap(),
+            Revset::Or(
+                symbol("a"),
+                Box::new(Revset::And(symbol("b"), Box::new(Revset::Not(symbol("c"))))),
+            )
+        );
+        assert_eq!(
+            Revset::parse("(a | b) & !c").unwrap(),
+            Revset::And(
+                Box::new(Revset::Or(symbol("a"), symbol("b"))),
+                Box::new(Revset::Not(symbol("c"))),
+            )
+        );
+        assert_eq!(
+            Revset::parse("a % b and c").unwrap(),
+            Revset::And(Box::new(Revset::Only(symbol("a"), symbol("b"))), symbol("c"))
+        );
+        assert_eq!(
+            Revset::parse("not a::b").unwrap(),
+            Revset::Not(Box::new(Revset::Range(symbol("a"), symbol("b"))))
+        );
+        assert_eq!(
+            Revset::parse("::a or b").unwrap(),
+            Revset::Or(Box::new(Revset::Ancestors(symbol("a"))), symbol("b"))
+        );
+    }
+
+    #[test]
+    fn parse_functions() {
+        assert_eq!(
+            Revset::parse("heads(ancestors(a))").unwrap(),
+            Revset::Heads(Box::new(Revset::Ancestors(symbol("a"))))
+        );
+        assert_eq!(Revset::parse("bookmark()").unwrap(), Revset::Bookmark(None));
+        assert_eq!(
+            Revset::parse("bookmark(master)").unwrap(),
+            Revset::Bookmark(Some("master".to_string()))
+        );
+        assert_eq!(
+            Revset::parse("author('Jane Doe')").unwrap(),
+            Revset::Author("Jane Doe".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_nested() {
+        let depth = MAX_DEPTH - 1;
+        let query = format!("{}a{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(Revset::parse(&query).unwrap(), *symbol("a"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for query in &[
+            "",
+            "a and",
+            "(a",
+            "a)",
+            "a::",
+            "a : b",
+            "'a",
+            "foo(a)",
+            "heads()",
+            "author(a or b)",
+            "a b",
+            "a $ b",
+            "(".repeat(MAX_TOKENS).as_str(),
+            format!("{}a{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH)).as_str(),
+            format!("{}a", "not ".repeat(MAX_DEPTH)).as_str(),
+            vec!["a"; MAX_TOKENS].join(" or ").as_str(),
+        ] {
+            match Revset::parse(query) {
+                Err(err) => match err.downcast::<ErrorKind>() {
+                    Ok(ErrorKind::RevsetParseError(_)) => {}
+                    other => panic!("unexpected error for {:?}: {:?}", query, other),
+                },
+                Ok(revset) => panic!("{:?} parsed as {:?}", query, revset),
+            }
+        }
+    }
+}
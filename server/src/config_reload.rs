@@ -0,0 +1,382 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Reloading of the repo configs while the server is running. The config repo bookmark or the
+//! config directory is polled and the new configs are sent to the listener threads of the repos,
+//! which apply the changes that can be applied to a live repo and report the others as needing a
+//! restart.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use failure::SlogKVError;
+use futures::{Future, Sink};
+use futures::sync::mpsc;
+use slog::Logger;
+
+use blobrepo::BlobRepo;
+use bookmarks::Bookmark;
+use mercurial_types::HgChangesetId;
+use metaconfig::RepoConfigs;
+use metaconfig::repoconfig::RepoConfig;
+
+use errors::*;
+use repo::MononokeRepo;
+
+/// A difference between two configs of a repo
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConfigChange {
+    pub description: String,
+    /// The change only takes effect once the server is restarted
+    pub needs_restart: bool,
+}
+
+/// Where the configs are polled from
+pub enum ConfigSource {
+    /// A bookmark of the config repo, and the changeset the current configs were read from
+    Repo {
+        repo: BlobRepo,
+        bookmark: Bookmark,
+        current: HgChangesetId,
+    },
+    /// A directory laid out like the config repo, and the current configs read from it
+    Dir {
+        path: PathBuf,
+        current: HashMap<String, RepoConfig>,
+    },
+}
+
+impl ConfigSource {
+    /// Reads the configs again, returns None if they didn't change since the last time
+    fn poll(&mut self, logger: &Logger) -> Result<Option<RepoConfigs>> {
+        match *self {
+            ConfigSource::Repo {
+                ref repo,
+                ref bookmark,
+                ref mut current,
+            } => {
+                let changesetid = match repo.get_bookmark(bookmark)
+                    .wait()
+                    .context("Failed to read config repository bookmark")?
+                {
+                    Some(changesetid) => changesetid,
+                    None => {
+                        warn!(logger, "Config repository bookmark {} not found", bookmark);
+                        return Ok(None);
+                    }
+                };
+                if changesetid == *current {
+                    return Ok(None);
+                }
+
+                info!(logger, "Reloading config from commit: {}", changesetid);
+                let configs = RepoConfigs::read_config_repo(repo.clone(), changesetid).wait()?;
+                *current = changesetid;
+                Ok(Some(configs))
+            }
+            ConfigSource::Dir {
+                ref path,
+                ref mut current,
+            } => {
+                let configs = RepoConfigs::read_config_dir(path).wait()?;
+                if configs.repos == *current {
+                    return Ok(None);
+                }
+
+                info!(logger, "Reloading config from directory: {}", path.display());
+                *current = configs.repos.clone();
+                Ok(Some(configs))
+            }
+        }
+    }
+}
+
+/// Polls `source` every `interval` and sends the new repo configs to the listener threads
+/// whenever they change.
+pub fn config_poller(
+    logger: Logger,
+    mut source: ConfigSource,
+    interval: Duration,
+    repo_senders: HashMap<String, mpsc::Sender<RepoConfig>>,
+) -> ! {
+    loop {
+        thread::sleep(interval);
+
+        let configs = match source.poll(&logger) {
+            Ok(Some(configs)) => configs,
+            Ok(None) => continue,
+            Err(err) => {
+                error!(logger, "Failed to read config"; SlogKVError(err));
+                continue;
+            }
+        };
+
+        for reponame in repo_senders.keys() {
+            if !configs.repos.contains_key(reponame) {
+                warn!(
+                    logger,
+                    "Repo {} was removed from the config, it is served until a restart", reponame
+                );
+            }
+        }
+        for (reponame, config) in configs.repos {
+            match repo_senders.get(&reponame) {
+                Some(sender) => if let Err(err) = sender.clone().send(config).wait() {
+                    error!(logger, "Failed to send config to repo {}: {}", reponame, err);
+                },
+                None => warn!(
+                    logger,
+                    "Repo {} was added to the config, it is served after a restart", reponame
+                ),
+            }
+        }
+    }
+}
+
+/// Applies `new` to `repo`, whose current config is `old`, and logs what changed. Returns the
+/// config the repo runs with afterwards, which keeps the old values of the fields that need a
+/// restart, so that their changes are reported again until the server is restarted.
+pub fn apply_config(
+    logger: &Logger,
+    repo: &MononokeRepo,
+    old: RepoConfig,
+    new: RepoConfig,
+) -> RepoConfig {
+    let changes = diff_repo_configs(&old, &new);
+    for change in &changes {
+        if change.needs_restart {
+            warn!(logger, "Config change needs a restart: {}", change.description);
+        } else {
+            info!(logger, "Config change: {}", change.description);
+        }
+    }
+
+    if changes.iter().all(|change| change.needs_restart) {
+        return old;
+    }
+    let new = live_config(&old, new);
+    match repo.reload_config(&new) {
+        Ok(()) => new,
+        Err(err) => {
+            error!(logger, "Failed to apply the config changes"; SlogKVError(err));
+            old
+        }
+    }
+}
+
+/// The config a live repo runs with once `new` is applied to it: the fields that can be changed
+/// on a live repo come from `new`, the others keep their values from `old`.
+pub fn live_config(old: &RepoConfig, new: RepoConfig) -> RepoConfig {
+    RepoConfig {
+        cache_warmup: new.cache_warmup,
+        bookmarks: new.bookmarks,
+        hooks: new.hooks,
+        hook_manager_params: new.hook_manager_params,
+        ..old.clone()
+    }
+}
+
+/// Lists the differences between two configs of a repo. The hooks and the size of their cache, the
+/// bookmarks and the cache warmup can be changed on a live repo, everything else needs a restart.
+pub fn diff_repo_configs(old: &RepoConfig, new: &RepoConfig) -> Vec<ConfigChange> {
+    let mut changes = vec![];
+
+    diff_field(&mut changes, "repotype", &old.repotype, &new.repotype, true);
+    diff_field(
+        &mut changes,
+        "generation_cache_size",
+        &old.generation_cache_size,
+        &new.generation_cache_size,
+        true,
+    );
+    diff_field(&mut changes, "repoid", &old.repoid, &new.repoid, true);
+    diff_field(&mut changes, "scuba_table", &old.scuba_table, &new.scuba_table, true);
+    diff_field(&mut changes, "cache_warmup", &old.cache_warmup, &new.cache_warmup, false);
+    diff_field(
+        &mut changes,
+        "hook_manager_params",
+        &old.hook_manager_params,
+        &new.hook_manager_params,
+        false,
+    );
+    diff_field(&mut changes, "lfs", &old.lfs, &new.lfs, true);
+    diff_field(&mut changes, "chunking", &old.chunking, &new.chunking, true);
+
+    diff_named(
+        &mut changes,
+        "bookmark",
+        old.bookmarks.as_ref().map_or(&[][..], Vec::as_slice),
+        new.bookmarks.as_ref().map_or(&[][..], Vec::as_slice),
+        |bookmark| &bookmark.name,
+        |bookmark| format!("{:?}", bookmark),
+    );
+    // The code of the hooks is left out of the log, it is only reported as changed
+    diff_named(
+        &mut changes,
+        "hook",
+        old.hooks.as_ref().map_or(&[][..], Vec::as_slice),
+        new.hooks.as_ref().map_or(&[][..], Vec::as_slice),
+        |hook| &hook.name,
+        |hook| format!("at {}", hook.path),
+    );
+
+    changes
+}
+
+fn diff_field<T: Debug + PartialEq>(
+    changes: &mut Vec<ConfigChange>,
+    name: &str,
+    old: &T,
+    new: &T,
+    needs_restart: bool,
+) {
+    if old != new {
+        changes.push(ConfigChange {
+            description: format!("{} changed from {:?} to {:?}", name, old, new),
+            needs_restart,
+        });
+    }
+}
+
+/// Diffs two lists of items identified by their names, all of which can be changed live
+fn diff_named<T, N, D>(
+    changes: &mut Vec<ConfigChange>,
+    kind: &str,
+    old: &[T],
+    new: &[T],
+    name: N,
+    describe: D,
+) where
+    T: PartialEq,
+    N: Fn(&T) -> &String,
+    D: Fn(&T) -> String,
+{
+    let old: HashMap<_, _> = old.iter().map(|item| (name(item), item)).collect();
+    let new: HashMap<_, _> = new.iter().map(|item| (name(item), item)).collect();
+
+    let mut names: Vec<_> = old.keys().chain(new.keys()).cloned().collect();
+    names.sort();
+    names.dedup();
+
+    for item_name in names {
+        let description = match (old.get(item_name), new.get(item_name)) {
+            (Some(old), Some(new)) if old == new => continue,
+            (Some(old), Some(new)) => {
+                let (old_description, new_description) = (describe(old), describe(new));
+                if old_description == new_description {
+                    format!("{} {} changed", kind, item_name)
+                } else {
+                    format!(
+                        "{} {} changed from {} to {}",
+                        kind, item_name, old_description, new_description
+                    )
+                }
+            }
+            (Some(old), None) => format!("{} {} removed, was {}", kind, item_name, describe(old)),
+            (None, Some(new)) => format!("{} {} added, {}", kind, item_name, describe(new)),
+            (None, None) => unreachable!(),
+        };
+        changes.push(ConfigChange {
+            description,
+            needs_restart: false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use metaconfig::repoconfig::{BookmarkParams, HookParams, RepoType};
+
+    fn config() -> RepoConfig {
+        RepoConfig {
+            repotype: RepoType::BlobRocks(PathBuf::from("/tmp/repo")),
+            generation_cache_size: 1024,
+            repoid: 0,
+            scuba_table: None,
+            cache_warmup: None,
+            lfs: None,
+            chunking: None,
+            bookmarks: Some(vec![
+                BookmarkParams {
+                    name: "master".to_string(),
+                    hooks: None,
+                    publishing: true,
+                    only_fast_forward: false,
+                    allow_delete: true,
+                    allowed_users: None,
+                },
+            ]),
+            hooks: Some(vec![
+                HookParams {
+                    name: "hook1".to_string(),
+                    path: "hooks/hook1.lua".to_string(),
+                    code: Some("hook1 code".to_string()),
+                },
+            ]),
+            hook_manager_params: None,
+        }
+    }
+
+    #[test]
+    fn test_diff_no_changes() {
+        assert_eq!(diff_repo_configs(&config(), &config()), vec![]);
+    }
+
+    #[test]
+    fn test_diff_changes() {
+        let mut new = config();
+        new.generation_cache_size = 2048;
+        new.bookmarks.as_mut().unwrap()[0].only_fast_forward = true;
+        new.hooks.as_mut().unwrap()[0].code = Some("new code".to_string());
+        new.hooks.as_mut().unwrap().push(HookParams {
+            name: "hook2".to_string(),
+            path: "hooks/hook2.lua".to_string(),
+            code: None,
+        });
+
+        let changes = diff_repo_configs(&config(), &new);
+        assert_eq!(changes.len(), 4);
+        assert_eq!(
+            changes[0],
+            ConfigChange {
+                description: "generation_cache_size changed from 1024 to 2048".to_string(),
+                needs_restart: true,
+            }
+        );
+        assert!(
+            changes[1]
+                .description
+                .starts_with("bookmark master changed from")
+        );
+        assert_eq!(changes[2].description, "hook hook1 changed");
+        assert_eq!(changes[3].description, "hook hook2 added, at hooks/hook2.lua");
+        assert!(changes[1..].iter().all(|change| !change.needs_restart));
+    }
+
+    #[test]
+    fn test_live_config_keeps_restart_fields() {
+        let mut new = config();
+        new.generation_cache_size = 2048;
+        new.hooks = None;
+
+        let live = live_config(&config(), new);
+        assert_eq!(live.generation_cache_size, 1024);
+        assert_eq!(live.hooks, None);
+
+        // The change that needs a restart is still reported by the next diff
+        let mut next = config();
+        next.generation_cache_size = 2048;
+        let changes = diff_repo_configs(&live, &next);
+        assert!(changes.iter().any(|change| change.needs_restart));
+    }
+}
@@ -6,7 +6,7 @@
 
 pub use failure::{Error, Result, ResultExt};
 
-use mercurial_types::RepoPath;
+use mercurial_types::{HgNodeHash, RepoPath};
 
 #[derive(Debug, Fail)]
 pub enum ErrorKind {
@@ -16,4 +16,9 @@ pub enum ErrorKind {
     #[fail(display = "connection does not start with preamble")] NoConnectionPreamble,
     #[fail(display = "connection error while reading preamble")] ConnectionError,
     #[fail(display = "incorrect reponame: {}", _0)] IncorrectRepoName(String),
+    #[fail(display = "no streaming clone snapshot available")] NoStreamingCloneSnapshot,
+    #[fail(display = "streaming clones are only served to remotefilelog clients")]
+    StreamingCloneNeedsRemotefilelog,
+    #[fail(display = "file {} of {} bytes is too large to be sent by getfiles", _0, _1)]
+    FileTooLargeForGetfiles(HgNodeHash, u64),
 }
@@ -48,6 +48,7 @@ extern crate bytes;
 extern crate cache_warmup;
 extern crate filenodes;
 extern crate hgproto;
+extern crate hooks;
 extern crate manifold_thrift;
 #[cfg(test)]
 extern crate many_files_dirs;
@@ -57,19 +58,23 @@ extern crate mercurial_types;
 #[cfg(test)]
 extern crate mercurial_types_mocks;
 extern crate metaconfig;
+extern crate phases;
 extern crate pylz4;
 extern crate repoinfo;
 extern crate revset;
 extern crate scuba_ext;
 extern crate services;
+extern crate skiplist;
 extern crate sshrelay;
 extern crate stats;
+extern crate streaming_clone;
 extern crate time_ext;
 #[macro_use]
 extern crate tracing;
 extern crate tracing_fb303;
 extern crate upload_trace;
 
+mod config_reload;
 mod errors;
 mod listener;
 mod monitoring;
@@ -96,7 +101,7 @@ use futures_stats::Timed;
 use tokio::util::FutureExt as TokioFutureExt;
 
 use bytes::Bytes;
-use clap::{App, ArgMatches};
+use clap::{App, ArgGroup, ArgMatches};
 
 use dns_lookup::getnameinfo;
 
@@ -110,13 +115,15 @@ use tracing::TraceContext;
 
 use blobrepo::BlobRepo;
 use hgproto::{sshproto, HgProtoHandler};
-use mercurial_types::RepositoryId;
+use mercurial_types::{HgChangesetId, RepositoryId};
 use metaconfig::RepoConfigs;
 use metaconfig::repoconfig::RepoConfig;
 
 use errors::*;
 
+use config_reload::ConfigSource;
 use listener::{ssh_server_mux, Stdio};
+use sshrelay::Preamble;
 use monitoring::{ReadyHandle, ReadyState, ReadyStateBuilder};
 
 struct SenderBytesWrite {
@@ -154,19 +161,28 @@ fn setup_app<'a, 'b>() -> App<'a, 'b> {
         .about("serve repos")
         .args_from_usage(
             r#"
-            <crpath>      -P, --configrepo_path [PATH]           'path to the config repo in rocksdb form'
+            [crpath]      -P, --configrepo_path [PATH]           'path to the config repo in rocksdb form'
+
+                          --config-dir [PATH]                    'directory laid out like the config repo, used instead of it'
 
             -C, --configrepo_hash [HASH]                         'config repo commit hash'
 
-            <crbook>      -C, --configrepo_book [BOOK]           'config repo bookmark'
+            [crbook]      -C, --configrepo_book [BOOK]           'config repo bookmark'
 
                           --listening-host-port <PATH>           'tcp address to listen to in format `host:port`'
 
             -p, --thrift_port [PORT] 'if provided the thrift server will start on this port'
 
+                          --config-poll-interval [SECONDS]       'poll the configs every SECONDS and apply changes'
+
             -d, --debug                                          'print debug level output'
         "#,
         )
+        .group(
+            ArgGroup::with_name("config")
+                .args(&["crpath", "config-dir"])
+                .required(true),
+        )
 }
 
 fn setup_logger<'a>(matches: &ArgMatches<'a>) -> Logger {
@@ -204,15 +220,21 @@ fn setup_scuba_logger(scuba_table: Option<String>, reponame: String) -> ScubaSam
     scuba_logger
 }
 
-fn get_config<'a>(logger: &Logger, matches: &ArgMatches<'a>) -> Result<RepoConfigs> {
+fn open_config_repo<'a>(logger: &Logger, matches: &ArgMatches<'a>) -> Result<BlobRepo> {
     // TODO: This needs to cope with blob repos, too
     let crpath = PathBuf::from(matches.value_of("crpath").unwrap());
-    let config_repo = BlobRepo::new_rocksdb(
+    BlobRepo::new_rocksdb(
         logger.new(o!["repo" => "Config repo"]),
         &crpath,
         RepositoryId::new(0),
-    )?;
+    )
+}
 
+fn get_config<'a>(
+    logger: &Logger,
+    config_repo: BlobRepo,
+    matches: &ArgMatches<'a>,
+) -> Result<(RepoConfigs, HgChangesetId)> {
     let changesetid = match matches.value_of("crbook") {
         Some(book) => {
             let book = bookmarks::Bookmark::new(book).expect("book must be ascii");
@@ -221,7 +243,7 @@ fn get_config<'a>(logger: &Logger, matches: &ArgMatches<'a>) -> Result<RepoConfi
                 .wait()?
                 .expect("bookmark not found")
         }
-        None => mercurial_types::nodehash::HgChangesetId::from_str(
+        None => HgChangesetId::from_str(
             matches
                 .value_of("crhash")
                 .expect("crhash and crbook are not specified"),
@@ -233,16 +255,21 @@ fn get_config<'a>(logger: &Logger, matches: &ArgMatches<'a>) -> Result<RepoConfi
         "Config repository will be read from commit: {}", changesetid
     );
 
-    RepoConfigs::read_config_repo(config_repo, changesetid)
+    let config = RepoConfigs::read_config_repo(config_repo, changesetid)
         .from_err()
-        .wait()
+        .wait()?;
+    Ok((config, changesetid))
 }
 
 fn start_repo_listeners<I>(
     repos: I,
     root_log: &Logger,
     sockname: &str,
-) -> Result<(Vec<JoinHandle<!>>, ReadyState)>
+) -> Result<(
+    Vec<JoinHandle<!>>,
+    ReadyState,
+    HashMap<String, mpsc::Sender<RepoConfig>>,
+)>
 where
     I: IntoIterator<Item = (String, RepoConfig)>,
 {
@@ -253,6 +280,7 @@ where
 
     let sockname = String::from(sockname);
     let mut repo_senders = HashMap::new();
+    let mut config_senders = HashMap::new();
     let mut ready = ReadyStateBuilder::new();
 
     let mut handles: Vec<_> = repos
@@ -265,13 +293,24 @@ where
             // the sender. However each clone creates one more entry in the channel.
             let (sender, receiver) = mpsc::channel(1);
             repo_senders.insert(reponame.clone(), sender);
+            let (config_sender, config_receiver) = mpsc::channel(1);
+            config_senders.insert(reponame.clone(), config_sender);
             // start a thread for each repo to own the reactor and start listening for
             // connections and detach it
             thread::Builder::new()
                 .name(format!("listener_{:?}", config.repotype))
                 .spawn({
                     let root_log = root_log.clone();
-                    move || repo_listen(reponame, config, root_log, ready_handle, receiver)
+                    move || {
+                        repo_listen(
+                            reponame,
+                            config,
+                            root_log,
+                            ready_handle,
+                            receiver,
+                            config_receiver,
+                        )
+                    }
                 })
                 .map_err(Error::from)
         })
@@ -298,6 +337,7 @@ where
     Ok((
         handles.into_iter().filter_map(Result::ok).collect(),
         ready.freeze(),
+        config_senders,
     ))
 }
 
@@ -370,6 +410,19 @@ fn connection_acceptor(
     unreachable!();
 }
 
+/// Identity of a session: its uuid, followed by what the client reported about itself in the
+/// preamble, e.g. "<uuid> host=devvm1 user=alice"
+fn session_identity(session_uuid: &uuid::Uuid, preamble: &Preamble) -> String {
+    let mut misc: Vec<_> = preamble
+        .misc
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    misc.sort();
+    misc.insert(0, format!("{}", session_uuid));
+    misc.join(" ")
+}
+
 // Listener thread for a specific repo
 fn repo_listen(
     reponame: String,
@@ -377,6 +430,7 @@ fn repo_listen(
     root_log: Logger,
     ready_handle: ReadyHandle,
     input_stream: mpsc::Receiver<(Stdio, SocketAddr)>,
+    config_updates: mpsc::Receiver<RepoConfig>,
 ) -> ! {
     let mut core = tokio_core::reactor::Core::new().expect("failed to create tokio core");
 
@@ -384,9 +438,8 @@ fn repo_listen(
 
     let repo = repo::MononokeRepo::new(
         root_log.new(o!("repo" => reponame.clone())),
-        &config.repotype,
-        config.generation_cache_size,
-        RepositoryId::new(config.repoid),
+        reponame.clone(),
+        &config,
     ).expect(&format!("failed to initialize repo {}", reponame));
 
     let listen_log = root_log.new(o!("repo" => repo.path().clone()));
@@ -394,17 +447,45 @@ fn repo_listen(
     let handle = core.handle();
     let repo = Arc::new(repo);
 
-    let initial_warmup =
-        cache_warmup::cache_warmup(repo.blobrepo(), config.cache_warmup, listen_log.clone())
-            .map_err({
-                let listen_log = listen_log.clone();
-                move |err| {
-                    error!(listen_log, "failed to warmup cache: {}", err);
-                    ()
-                }
-            });
+    let initial_warmup = cache_warmup::cache_warmup(
+        repo.blobrepo(),
+        config.cache_warmup.clone(),
+        listen_log.clone(),
+    ).map_err({
+        let listen_log = listen_log.clone();
+        move |err| {
+            error!(listen_log, "failed to warmup cache: {}", err);
+            ()
+        }
+    });
     let initial_warmup = ready_handle.wait_for(initial_warmup);
 
+    // Apply the configs sent by the config poller, warming up the cache again if its target
+    // has changed
+    let config_reload = config_updates
+        .fold(config, {
+            let repo = repo.clone();
+            let listen_log = listen_log.clone();
+            let handle = handle.clone();
+            move |old, new| {
+                let old_cache_warmup = old.cache_warmup.clone();
+                let config = config_reload::apply_config(&listen_log, &repo, old, new);
+                if config.cache_warmup != old_cache_warmup {
+                    let warmup = cache_warmup::cache_warmup(
+                        repo.blobrepo(),
+                        config.cache_warmup.clone(),
+                        listen_log.clone(),
+                    ).map_err({
+                        let listen_log = listen_log.clone();
+                        move |err| error!(listen_log, "failed to warmup cache: {}", err)
+                    });
+                    handle.spawn(warmup);
+                }
+                Ok::<_, ()>(config)
+            }
+        })
+        .map(|_| ());
+
     let server = input_stream.for_each(move |(stdio, addr)| {
         // Have a connection. Extract std{in,out,err} streams for socket
         let Stdio {
@@ -415,6 +496,8 @@ fn repo_listen(
         } = stdio;
 
         let session_uuid = uuid::Uuid::new_v4();
+        let session = session_identity(&session_uuid, &preamble);
+        let user = preamble.misc.get("user").cloned();
         let wireproto_calls = Arc::new(Mutex::new(Vec::new()));
         let trace = TraceContext::new(session_uuid, Instant::now());
 
@@ -452,7 +535,14 @@ fn repo_listen(
         // Construct a hg protocol handler
         let proto_handler = HgProtoHandler::new(
             stdin,
-            repo::RepoClient::new(repo.clone(), conn_log.clone(), scuba_logger.clone(), trace),
+            repo::RepoClient::new(
+                repo.clone(),
+                conn_log.clone(),
+                scuba_logger.clone(),
+                trace,
+                session,
+                user,
+            ),
             sshproto::HgSshCommandDecode,
             sshproto::HgSshCommandEncode,
             &conn_log,
@@ -528,7 +618,7 @@ fn repo_listen(
         Ok(())
     });
 
-    let server = server.join(initial_warmup);
+    let server = server.join(initial_warmup).join(config_reload);
     core.run(server)
         .expect("failure while running listener on tokio core");
 
@@ -546,8 +636,19 @@ fn main() {
 
         let stats_aggregation = monitoring::start_stats()?;
 
-        let config = get_config(root_log, &matches)?;
-        let (repo_listeners, ready) = start_repo_listeners(
+        let (config, config_repo) = match matches.value_of("config-dir") {
+            Some(config_dir) => {
+                info!(root_log, "Config will be read from directory: {}", config_dir);
+                (RepoConfigs::read_config_dir(config_dir).wait()?, None)
+            }
+            None => {
+                let config_repo = open_config_repo(root_log, &matches)?;
+                let (config, changesetid) = get_config(root_log, config_repo.clone(), &matches)?;
+                (config, Some((config_repo, changesetid)))
+            }
+        };
+        let current_repos = config.repos.clone();
+        let (repo_listeners, ready, config_senders) = start_repo_listeners(
             config.repos.into_iter(),
             root_log,
             matches
@@ -555,6 +656,38 @@ fn main() {
                 .expect("listening path must be specified"),
         )?;
 
+        let maybe_config_poller = match matches.value_of("config-poll-interval") {
+            None => None,
+            Some(interval) => {
+                let interval = Duration::from_secs(interval.parse()?);
+                let source = match (
+                    matches.value_of("config-dir"),
+                    matches.value_of("crbook"),
+                    config_repo,
+                ) {
+                    (Some(config_dir), _, _) => ConfigSource::Dir {
+                        path: PathBuf::from(config_dir),
+                        current: current_repos,
+                    },
+                    (None, Some(book), Some((repo, current))) => ConfigSource::Repo {
+                        repo,
+                        bookmark: bookmarks::Bookmark::new(book)?,
+                        current,
+                    },
+                    _ => bail_err!(ErrorKind::Initialization(
+                        "config polling needs a config repo bookmark or a config directory",
+                    )),
+                };
+                let root_log = root_log.clone();
+                let handle = thread::Builder::new()
+                    .name("config_poller".to_string())
+                    .spawn(move || {
+                        config_reload::config_poller(root_log, source, interval, config_senders)
+                    })?;
+                Some(handle)
+            }
+        };
+
         tracing_fb303::register();
 
         let maybe_thrift = match monitoring::start_thrift_service(&root_log, &matches, ready) {
@@ -565,6 +698,7 @@ fn main() {
         for handle in vec![stats_aggregation]
             .into_iter()
             .chain(maybe_thrift.into_iter())
+            .chain(maybe_config_poller.into_iter())
             .chain(repo_listeners.into_iter())
         {
             let thread_name = handle.thread().name().unwrap_or("unknown").to_owned();
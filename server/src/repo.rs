@@ -13,8 +13,8 @@ use std::iter::FromIterator;
 use std::mem;
 use std::path::Path;
 use std::str::FromStr;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
 use bytes::{BufMut, Bytes, BytesMut};
 use failure::err_msg;
@@ -32,16 +32,23 @@ use slog::Logger;
 use tracing::{TraceContext, Traced};
 
 use blobrepo::BlobChangeset;
-use bundle2_resolver;
+use bookmarks::{Bookmark, BookmarkPrefix};
+use bundle2_resolver::{self, BookmarkPolicies, BookmarkPolicy};
 use filenodes::FilenodeInfo;
+use hooks::{BlobRepoChangesetStore, HookManager};
+use hooks::hook_loader::load_hooks;
 use mercurial::{self, RevlogChangeset};
 use mercurial_bundles::{create_bundle_stream, parts, Bundle2EncodeBuilder, Bundle2Item};
-use mercurial_types::{percent_encode, Changeset, Entry, HgBlobNode, HgChangesetId, HgManifestId,
-                      HgNodeHash, HgParents, MPath, RepoPath, RepositoryId, Type, NULL_HASH};
+use mercurial_bundles::part_encode::PartEncodeBuilder;
+use mercurial_types::{percent_encode, Changeset, Entry, HgBlobNode, HgChangesetId,
+                      HgChangesetIdPrefix, HgManifestId, HgNodeHash, HgParents, MPath, RepoPath,
+                      RepositoryId, Type, NULL_HASH};
 use mercurial_types::manifest_utils::{and_pruner_combinator, changed_entry_stream,
                                       changed_entry_stream_with_pruner, file_pruner,
                                       visited_pruner, ChangedEntry, EntryStatus};
-use metaconfig::repoconfig::RepoType;
+use metaconfig::repoconfig::{RepoConfig, RepoType};
+use phases::{MysqlPhases, Phase, Phases, RepoPhases, SqlitePhases};
+use streaming_clone::{Snapshot, StreamingClone};
 
 use hgproto::{self, GetbundleArgs, GettreepackArgs, HgCommandRes, HgCommands};
 
@@ -51,13 +58,33 @@ use errors::*;
 
 use repoinfo::RepoGenCache;
 use revset::DifferenceOfUnionsOfAncestorsNodeStream;
+use skiplist::SkiplistIndex;
 
 const METAKEYFLAG: &str = "f";
 const METAKEYSIZE: &str = "s";
+// Mercurial's REVIDX_EXTSTORED flag, which marks the contents of a file as a pointer to contents
+// that are stored elsewhere, like the pointers of the lfs extension
+const EXTSTORED_FLAG: u16 = 1 << 13;
 const MAX_NODES_TO_LOG: usize = 5;
+const MAX_LOOKUP_CANDIDATES: usize = 10;
+const DEFAULT_BRANCH: &str = "default";
+// Getfiles streams the contents of larger files uncompressed, see stream_remotefilelog_blob. The
+// chunking config of a repo can override this.
+const MAX_COMPRESSED_FILE_SIZE: u64 = 16 * 1024 * 1024;
+// How long the streaming clone snapshot is cached for, i.e. how long it takes for a new snapshot
+// to be served
+const SNAPSHOT_CACHE_SECS: u64 = 60;
+// Size in bytes of the in-memory cache of the skip-list index
+const SKIPLIST_CACHE_SIZE: usize = 100 * 1024 * 1024;
+// Bundle capability that remotefilelog clients send with getbundle
+const REMOTEFILELOG_BUNDLECAP: &[u8] = b"remotefilelog";
+
+/// Heads of every named branch
+type Branchmap = HashMap<String, HashSet<HgNodeHash>>;
 
 mod ops {
     pub const HELLO: &str = "hello";
+    pub const BRANCHMAP: &str = "branchmap";
     pub const UNBUNDLE: &str = "unbundle";
     pub const HEADS: &str = "heads";
     pub const LOOKUP: &str = "lookup";
@@ -66,6 +93,7 @@ mod ops {
     pub const GETBUNDLE: &str = "getbundle";
     pub const GETTREEPACK: &str = "gettreepack";
     pub const GETFILES: &str = "getfiles";
+    pub const STREAM_OUT: &str = "stream_out";
 }
 
 struct LogNormalGenerator {
@@ -75,6 +103,7 @@ struct LogNormalGenerator {
 
 pub trait OpenableRepoType {
     fn open(&self, logger: Logger, repoid: RepositoryId) -> Result<BlobRepo>;
+    fn open_phases(&self) -> Result<Arc<Phases>>;
     fn path(&self) -> &Path;
 }
 
@@ -153,6 +182,21 @@ impl OpenableRepoType for RepoType {
         Ok(ret)
     }
 
+    fn open_phases(&self) -> Result<Arc<Phases>> {
+        use hgproto::ErrorKind;
+        use metaconfig::repoconfig::RepoType::*;
+
+        let ret: Arc<Phases> = match *self {
+            Revlog(_) => Err(ErrorKind::CantServeRevlogRepo)?,
+            BlobRocks(ref path) | TestBlobDelayRocks(ref path, ..) => Arc::new(
+                SqlitePhases::open_or_create(path.join("phases").to_string_lossy())?,
+            ),
+            BlobManifold { ref db_address, .. } => Arc::new(MysqlPhases::open(&db_address)?),
+        };
+
+        Ok(ret)
+    }
+
     fn path(&self) -> &Path {
         use metaconfig::repoconfig::RepoType::*;
 
@@ -177,9 +221,197 @@ fn format_utf8_bytes_list(mut entries: Vec<Bytes>) -> String {
         .join(" ")
 }
 
+fn lookup_full_hash(repo: Arc<BlobRepo>, key: &str) -> BoxFuture<Option<HgChangesetId>, Error> {
+    match HgChangesetId::from_str(key) {
+        Ok(csid) => repo.changeset_exists(&csid)
+            .map(move |exists| if exists { Some(csid) } else { None })
+            .boxify(),
+        Err(_) => future::ok(None).boxify(),
+    }
+}
+
+/// Looks up a public bookmark first, then a scratch bookmark with the same name
+fn lookup_bookmark(repo: Arc<BlobRepo>, key: &str) -> BoxFuture<Option<HgChangesetId>, Error> {
+    match Bookmark::new(key) {
+        Ok(bookmark) => repo.get_bookmark(&bookmark)
+            .and_then(move |found| match found {
+                Some(csid) => future::ok(Some(csid)).boxify(),
+                None => repo.get_scratch_bookmark(&bookmark),
+            })
+            .boxify(),
+        Err(_) => future::ok(None).boxify(),
+    }
+}
+
+/// A `listkeyspatterns` pattern is either an exact bookmark name or a prefix followed by `*`,
+/// like in the infinitepush extension
+fn bookmark_matches_pattern(name: &str, pattern: &str) -> bool {
+    if pattern.ends_with('*') {
+        name.starts_with(&pattern[..pattern.len() - 1])
+    } else {
+        name == pattern
+    }
+}
+
+fn get_scratch_bookmarks_by_pattern(
+    repo: Arc<BlobRepo>,
+    pattern: &str,
+) -> BoxStream<(Bookmark, HgChangesetId), Error> {
+    if pattern.ends_with('*') {
+        let prefix = try_boxstream!(BookmarkPrefix::new(&pattern[..pattern.len() - 1]));
+        repo.get_scratch_bookmarks_by_prefix(&prefix)
+    } else {
+        let bookmark = try_boxstream!(Bookmark::new(pattern));
+        repo.get_scratch_bookmark(&bookmark)
+            .map(move |found| stream::iter_ok(found.map(|csid| (bookmark, csid))))
+            .flatten_stream()
+            .boxify()
+    }
+}
+
+/// Computes the heads of the named branches among the ancestors of `heads` that are not
+/// ancestors of `excludes`. A changeset is a branch head if none of its children is on the same
+/// branch. Also returns the branches of the children of the excluded changesets that are parents
+/// of the visited ones.
+fn compute_branchmap(
+    repo: Arc<BlobRepo>,
+    repo_generation: RepoGenCache,
+    heads: Vec<HgNodeHash>,
+    excludes: Vec<HgNodeHash>,
+) -> BoxFuture<(Branchmap, HashMap<HgNodeHash, HashSet<String>>), Error> {
+    // Ancestors come in decreasing generation order, so every changeset is seen after all its
+    // children and it is known whether one of them is on the same branch
+    DifferenceOfUnionsOfAncestorsNodeStream::new_with_excludes(
+        &repo,
+        repo_generation,
+        heads,
+        excludes,
+    ).map({
+        let repo = repo.clone();
+        move |node| {
+            repo.get_changeset_by_changesetid(&HgChangesetId::new(node))
+                .map(move |cs| (node, cs))
+        }
+    })
+        .buffered(100)
+        .fold(
+            (Branchmap::new(), HashMap::new()),
+            |(mut branchmap, mut children_branches), (node, cs)| {
+                let branch = match cs.extra().get(&b"branch"[..]) {
+                    Some(branch) => String::from_utf8_lossy(branch).into_owned(),
+                    None => DEFAULT_BRANCH.to_string(),
+                };
+
+                let children_branches_of_node: HashSet<String> =
+                    children_branches.remove(&node).unwrap_or_default();
+                for parent in cs.parents().into_iter() {
+                    children_branches
+                        .entry(parent)
+                        .or_insert_with(HashSet::new)
+                        .insert(branch.clone());
+                }
+
+                if !children_branches_of_node.contains(&branch) {
+                    branchmap
+                        .entry(branch)
+                        .or_insert_with(HashSet::new)
+                        .insert(node);
+                }
+
+                Ok::<_, Error>((branchmap, children_branches))
+            },
+        )
+        .boxify()
+}
+
+/// Updates `old`, the branchmap of the ancestors of `old_heads`, to the branchmap of the
+/// ancestors of `heads` by only walking the changesets that are new since. Returns None if some
+/// of `old_heads` are not ancestors of `heads` anymore, e.g. because a bookmark was moved
+/// backwards, in which case the branchmap has to be computed from scratch.
+fn update_branchmap(
+    repo: Arc<BlobRepo>,
+    repo_generation: RepoGenCache,
+    old_heads: Vec<HgNodeHash>,
+    old: Arc<Branchmap>,
+    heads: Vec<HgNodeHash>,
+) -> BoxFuture<Option<Branchmap>, Error> {
+    compute_branchmap(repo, repo_generation, heads.clone(), old_heads.clone())
+        .map(move |(mut branchmap, children_branches)| {
+            // An old head is still an ancestor if it is a head or a parent of a new changeset
+            let all_reachable = old_heads
+                .iter()
+                .all(|head| heads.contains(head) || children_branches.contains_key(head));
+            if !all_reachable {
+                return None;
+            }
+
+            // The old branch heads stay heads unless a new child is on the same branch
+            for (branch, branch_heads) in old.iter() {
+                for head in branch_heads {
+                    let has_child_on_branch = children_branches
+                        .get(head)
+                        .map_or(false, |branches| branches.contains(branch));
+                    if !has_child_on_branch {
+                        branchmap
+                            .entry(branch.clone())
+                            .or_insert_with(HashSet::new)
+                            .insert(*head);
+                    }
+                }
+            }
+            Some(branchmap)
+        })
+        .boxify()
+}
+
+fn lookup_prefix(repo: Arc<BlobRepo>, key: String) -> BoxFuture<Bytes, Error> {
+    let prefix = match HgChangesetIdPrefix::from_str(&key) {
+        Ok(prefix) => prefix,
+        Err(_) => return future::ok(lookup_failure(format!("{} not found", key))).boxify(),
+    };
+
+    // Fetch one more candidate than we report to find out whether the list was truncated
+    repo.get_changesets_by_prefix(prefix, MAX_LOOKUP_CANDIDATES + 1)
+        .map(move |mut candidates| match candidates.len() {
+            0 => lookup_failure(format!("{} not found", key)),
+            1 => lookup_success(candidates[0]),
+            _ => {
+                let truncated = candidates.len() > MAX_LOOKUP_CANDIDATES;
+                candidates.truncate(MAX_LOOKUP_CANDIDATES);
+                let candidates =
+                    format_nodes_list(candidates.into_iter().map(|c| c.into_nodehash()).collect());
+                lookup_failure(format!(
+                    "ambiguous identifier {}, candidates: {}{}",
+                    key,
+                    candidates,
+                    if truncated { " ..." } else { "" },
+                ))
+            }
+        })
+        .boxify()
+}
+
+fn lookup_success(csid: HgChangesetId) -> Bytes {
+    generate_lookup_resp_buf(true, csid.to_hex().as_bytes())
+}
+
+fn lookup_failure(err_msg: String) -> Bytes {
+    generate_lookup_resp_buf(false, err_msg.as_bytes())
+}
+
+fn generate_lookup_resp_buf(success: bool, message: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(message.len() + 3);
+    buf.put(if success { b'1' } else { b'0' });
+    buf.put(b' ');
+    buf.extend_from_slice(message);
+    buf.put(b'\n');
+    buf.freeze()
+}
+
 fn wireprotocaps() -> Vec<String> {
     vec![
         "lookup".to_string(),
+        "branchmap".to_string(),
         "known".to_string(),
         "getbundle".to_string(),
         "unbundle=HG10GZ,HG10BZ,HG10UN".to_string(),
@@ -189,44 +421,29 @@ fn wireprotocaps() -> Vec<String> {
     ]
 }
 
-fn bundle2caps() -> String {
-    let caps = vec![
+fn bundle2caps(streaming_clone: bool) -> String {
+    let mut caps = vec![
         ("HG20", vec![]),
-        // Note that "listkeys" is *NOT* returned as a bundle2 capability; that's because there's
-        // a race that can happen. Here's how:
-        // 1. The client does discovery to figure out which heads are missing.
-        // 2. At this point, a frequently updated bookmark (say "master") moves forward.
-        // 3. The client requests the heads discovered in step 1 + the latest value of master.
-        // 4. The server returns changesets up to those heads, plus the latest version of master.
-        //
-        // master doesn't point to a commit that will exist on the client at the end of the pull,
-        // so the client ignores it.
+        // Advertising "listkeys" makes the client fetch bookmarks as part of getbundle, i.e.
+        // after discovery. If a frequently updated bookmark (say "master") moved between
+        // discovery and getbundle, the client would get a version of master that points to a
+        // commit that won't exist on the client at the end of the pull, and it would ignore it.
         //
-        // The workaround here is to force bookmarks to be sent before discovery happens. Disabling
-        // the listkeys capabilities causes the Mercurial client to do that.
-        //
-        // A better fix might be to snapshot and maintain the bookmark state on the server at the
-        // start of discovery.
-        //
-        // The best fix here would be to change the protocol to represent bookmark pulls
-        // atomically.
-        //
-        // Some other notes:
-        // * Stock Mercurial doesn't appear to have this problem. @rain1 hasn't verified why, but
-        //   believes it's because bookmarks get loaded up into memory before discovery and then
-        //   don't get reloaded for the duration of the process. (In Mononoke, this is the
-        //   "snapshot and maintain the bookmark state" approach mentioned above.)
-        // * There's no similar race with pushes updating bookmarks, so "pushkey" is still sent
-        //   as a capability.
-        // * To repro the race, run test-bookmark-race.t with the following line enabled.
-
-        // ("listkeys", vec![]),
+        // To avoid this race, every session serves heads, listkeys and getbundle from a single
+        // snapshot of the bookmarks, taken the first time they are requested. (This is also what
+        // stock Mercurial effectively does, since it loads bookmarks into memory once per
+        // process.) test-bookmark-race.t covers this.
+        ("listkeys", vec![]),
         ("changegroup", vec!["02"]),
         ("b2x:infinitepush", vec![]),
         ("b2x:infinitepushscratchbookmarks", vec![]),
+        ("b2x:rebase", vec![]),
         ("pushkey", vec![]),
         ("treemanifestserver", vec!["True"]),
     ];
+    if streaming_clone {
+        caps.push(("stream", vec!["v2"]));
+    }
 
     let mut encodedcaps = vec![];
 
@@ -245,24 +462,173 @@ fn bundle2caps() -> String {
 
 pub struct MononokeRepo {
     path: String,
+    reponame: String,
+    repoid: RepositoryId,
     blobrepo: Arc<BlobRepo>,
     repo_generation: RepoGenCache,
+    skiplist_index: SkiplistIndex,
+    phases_store: Arc<Phases>,
+    // The parts of the repo that are derived from its config, replaced when it is reloaded
+    live_config: RwLock<LiveConfig>,
+    streaming_clone: StreamingClone,
+    // Getfiles streams the contents of files larger than this uncompressed
+    stream_threshold: u64,
+    // Getfiles sends the pointers to contents stored in the LFS blobstore instead of them
+    send_lfs_pointers: bool,
+    // The branchmap and the heads it was computed from, so that only the changesets added since
+    // are walked to update it
+    branchmap_cache: Arc<Mutex<Option<(Vec<HgNodeHash>, Arc<Branchmap>)>>>,
+    // The streaming clone snapshot and when it was fetched, so that it isn't fetched for every
+    // hello
+    snapshot_cache: Arc<Mutex<Option<(Instant, Option<Snapshot>)>>>,
 }
 
-impl MononokeRepo {
-    pub fn new(
-        logger: Logger,
-        repo: &RepoType,
-        cache_size: usize,
+#[derive(Clone)]
+struct LiveConfig {
+    hook_manager: Arc<HookManager>,
+    phases: RepoPhases,
+    bookmark_policies: BookmarkPolicies,
+}
+
+impl LiveConfig {
+    fn new(
+        reponame: String,
         repoid: RepositoryId,
+        blobrepo: &Arc<BlobRepo>,
+        repo_generation: &RepoGenCache,
+        skiplist_index: &SkiplistIndex,
+        phases_store: &Arc<Phases>,
+        config: &RepoConfig,
     ) -> Result<Self> {
+        let store = BlobRepoChangesetStore::new((**blobrepo).clone());
+        let hook_manager_params = config.hook_manager_params.clone().unwrap_or_default();
+        let mut hook_manager = HookManager::new(
+            reponame,
+            Box::new(store),
+            hook_manager_params.entrylimit,
+            hook_manager_params.weightlimit,
+        );
+        load_hooks(&mut hook_manager, config.clone())?;
+
+        let publishing_bookmarks = config
+            .bookmarks
+            .iter()
+            .flat_map(|bookmarks| bookmarks.iter())
+            .filter(|bookmark| bookmark.publishing)
+            .map(|bookmark| Bookmark::new(&bookmark.name))
+            .collect::<Result<Vec<_>>>()?;
+        let phases = RepoPhases::new(
+            blobrepo.clone(),
+            repo_generation.clone(),
+            skiplist_index.clone(),
+            phases_store.clone(),
+            repoid,
+            publishing_bookmarks,
+        );
+
+        let bookmark_policies = config
+            .bookmarks
+            .iter()
+            .flat_map(|bookmarks| bookmarks.iter())
+            .map(|bookmark| {
+                let policy = BookmarkPolicy {
+                    only_fast_forward: bookmark.only_fast_forward,
+                    allow_delete: bookmark.allow_delete,
+                    allowed_users: bookmark.allowed_users.clone(),
+                };
+                Bookmark::new(&bookmark.name).map(|name| (name, policy))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+        let bookmark_policies = BookmarkPolicies::new(
+            blobrepo.clone(),
+            repo_generation.clone(),
+            skiplist_index.clone(),
+            bookmark_policies,
+        );
+
+        Ok(LiveConfig {
+            hook_manager: Arc::new(hook_manager),
+            phases,
+            bookmark_policies,
+        })
+    }
+}
+
+impl MononokeRepo {
+    pub fn new(logger: Logger, reponame: String, config: &RepoConfig) -> Result<Self> {
+        let repo = &config.repotype;
+        let repoid = RepositoryId::new(config.repoid);
+        let mut blobrepo = repo.open(logger, repoid)?;
+        blobrepo.set_lfs_threshold(config.lfs.as_ref().map(|lfs| lfs.threshold));
+        blobrepo.set_chunking(
+            config
+                .chunking
+                .as_ref()
+                .map(|chunking| (chunking.threshold, chunking.chunker)),
+        );
+        let blobrepo = Arc::new(blobrepo);
+        let repo_generation = RepoGenCache::new(config.generation_cache_size);
+        let skiplist_index = SkiplistIndex::new(blobrepo.clone(), SKIPLIST_CACHE_SIZE);
+        let phases_store = repo.open_phases()?;
+
+        let live_config = LiveConfig::new(
+            reponame.clone(),
+            repoid,
+            &blobrepo,
+            &repo_generation,
+            &skiplist_index,
+            &phases_store,
+            config,
+        )?;
+
+        let streaming_clone = StreamingClone::new(Arc::new(blobrepo.get_blobstore()));
+        let stream_threshold = config
+            .chunking
+            .as_ref()
+            .and_then(|chunking| chunking.stream_threshold)
+            .unwrap_or(MAX_COMPRESSED_FILE_SIZE);
+        let send_lfs_pointers = config
+            .lfs
+            .as_ref()
+            .map_or(false, |lfs| lfs.send_pointers);
+
         Ok(MononokeRepo {
             path: format!("{}", repo.path().to_owned().display()),
-            blobrepo: Arc::new(repo.open(logger, repoid)?),
-            repo_generation: RepoGenCache::new(cache_size),
+            reponame,
+            repoid,
+            blobrepo,
+            repo_generation,
+            skiplist_index,
+            phases_store,
+            live_config: RwLock::new(live_config),
+            streaming_clone,
+            stream_threshold,
+            send_lfs_pointers,
+            branchmap_cache: Arc::new(Mutex::new(None)),
+            snapshot_cache: Arc::new(Mutex::new(None)),
         })
     }
 
+    /// Replaces the hooks, the publishing bookmarks and the bookmark policies with the ones of
+    /// `config`. The sessions that are already pushing keep using the previous ones.
+    pub fn reload_config(&self, config: &RepoConfig) -> Result<()> {
+        let live_config = LiveConfig::new(
+            self.reponame.clone(),
+            self.repoid,
+            &self.blobrepo,
+            &self.repo_generation,
+            &self.skiplist_index,
+            &self.phases_store,
+            config,
+        )?;
+        *self.live_config.write().expect("lock poisoned") = live_config;
+        Ok(())
+    }
+
+    fn live_config(&self) -> LiveConfig {
+        self.live_config.read().expect("lock poisoned").clone()
+    }
+
     pub fn path(&self) -> &String {
         &self.path
     }
@@ -270,6 +636,69 @@ impl MononokeRepo {
     pub fn blobrepo(&self) -> Arc<BlobRepo> {
         self.blobrepo.clone()
     }
+
+    /// Returns the branchmap of the ancestors of `heads`, which must be sorted. The result is
+    /// cached, and when the heads change only the changesets that are new since are walked.
+    fn get_branchmap(&self, heads: Vec<HgNodeHash>) -> BoxFuture<Arc<Branchmap>, Error> {
+        let cached = self.branchmap_cache.lock().expect("lock poisoned").clone();
+        let (repo, repo_generation) = (self.blobrepo.clone(), self.repo_generation.clone());
+
+        let branchmap = match cached {
+            Some((cached_heads, branchmap)) => {
+                if cached_heads == heads {
+                    return future::ok(branchmap).boxify();
+                }
+                update_branchmap(
+                    repo.clone(),
+                    repo_generation.clone(),
+                    cached_heads,
+                    branchmap,
+                    heads.clone(),
+                ).boxify()
+            }
+            None => future::ok(None).boxify(),
+        };
+
+        let branchmap_cache = self.branchmap_cache.clone();
+        branchmap
+            .and_then({
+                let heads = heads.clone();
+                move |branchmap| match branchmap {
+                    Some(branchmap) => future::ok(branchmap).boxify(),
+                    None => compute_branchmap(repo, repo_generation, heads, vec![])
+                        .map(|(branchmap, _)| branchmap)
+                        .boxify(),
+                }
+            })
+            .map(move |branchmap| {
+                let branchmap = Arc::new(branchmap);
+                *branchmap_cache.lock().expect("lock poisoned") = Some((heads, branchmap.clone()));
+                branchmap
+            })
+            .boxify()
+    }
+
+    /// Returns the streaming clone snapshot, if there is one. The result is cached for
+    /// `SNAPSHOT_CACHE_SECS`.
+    fn get_snapshot(&self) -> BoxFuture<Option<Snapshot>, Error> {
+        if let Some((ref fetched, ref snapshot)) =
+            *self.snapshot_cache.lock().expect("lock poisoned")
+        {
+            if fetched.elapsed() < Duration::from_secs(SNAPSHOT_CACHE_SECS) {
+                return future::ok(snapshot.clone()).boxify();
+            }
+        }
+
+        let snapshot_cache = self.snapshot_cache.clone();
+        self.streaming_clone
+            .get_snapshot()
+            .map(move |snapshot| {
+                *snapshot_cache.lock().expect("lock poisoned") =
+                    Some((Instant::now(), snapshot.clone()));
+                snapshot
+            })
+            .boxify()
+    }
 }
 
 impl Debug for MononokeRepo {
@@ -284,6 +713,13 @@ pub struct RepoClient {
     logger: Logger,
     scuba_logger: ScubaSampleBuilder,
     trace: TraceContext,
+    // Identity of the session, recorded in the update logs of the bookmarks it moves
+    session: String,
+    // User the ssh relay authenticated the client as, checked against the users allowed to move
+    // a bookmark
+    user: Option<String>,
+    // Bookmarks as seen by this session, see bundle2caps() for why they are snapshotted
+    bookmarks_snapshot: Arc<Mutex<Option<Arc<Vec<(Bookmark, HgChangesetId)>>>>>,
 }
 
 impl RepoClient {
@@ -292,12 +728,17 @@ impl RepoClient {
         logger: Logger,
         scuba_logger: ScubaSampleBuilder,
         trace: TraceContext,
+        session: String,
+        user: Option<String>,
     ) -> Self {
         RepoClient {
             repo,
             logger,
             scuba_logger,
             trace,
+            session,
+            user,
+            bookmarks_snapshot: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -319,7 +760,36 @@ impl RepoClient {
         scuba_logger
     }
 
-    fn create_bundle(&self, args: GetbundleArgs) -> hgproto::Result<HgCommandRes<Bytes>> {
+    /// Returns the bookmarks snapshot of this session, taking it if that hasn't happened yet
+    fn get_bookmarks_snapshot(&self) -> BoxFuture<Arc<Vec<(Bookmark, HgChangesetId)>>, Error> {
+        if let Some(ref snapshot) = *self.bookmarks_snapshot.lock().expect("lock poisoned") {
+            return future::ok(snapshot.clone()).boxify();
+        }
+
+        let bookmarks_snapshot = self.bookmarks_snapshot.clone();
+        self.repo
+            .blobrepo
+            .get_bookmarks()
+            .collect()
+            .map(move |bookmarks| {
+                let mut snapshot = bookmarks_snapshot.lock().expect("lock poisoned");
+                // Another command of this session might have taken the snapshot in the meantime,
+                // the first one wins.
+                snapshot.get_or_insert_with(|| Arc::new(bookmarks)).clone()
+            })
+            .boxify()
+    }
+
+    /// Drops the bookmarks snapshot, so that the next command sees the current bookmarks
+    fn invalidate_bookmarks_snapshot(&self) {
+        *self.bookmarks_snapshot.lock().expect("lock poisoned") = None;
+    }
+
+    fn create_bundle(
+        &self,
+        args: GetbundleArgs,
+        snapshot: Option<Snapshot>,
+    ) -> hgproto::Result<HgCommandRes<Bytes>> {
         let writer = Cursor::new(Vec::new());
         let mut bundle = Bundle2EncodeBuilder::new(writer);
         // Mercurial currently hangs while trying to read compressed bundles over the wire:
@@ -327,12 +797,44 @@ impl RepoClient {
         // TODO: possibly enable compression support once this is fixed.
         bundle.set_compressor_type(None);
 
+        match snapshot {
+            Some(snapshot) => bundle.add_part(self.stream2_part(snapshot)?),
+            None => bundle.add_part(self.changegroup_part(args.heads, args.common)?),
+        };
+
+        // TODO: generalize this to other listkey types
+        // (note: just calling &b"bookmarks"[..] doesn't work because https://fburl.com/0p0sq6kp)
+        if args.listkeys.contains(&b"bookmarks".to_vec()) {
+            let items = self.get_bookmarks_snapshot()
+                .map(|bookmarks| stream::iter_ok((*bookmarks).clone()))
+                .flatten_stream()
+                .map(|(name, cs)| {
+                    let hash: Vec<u8> = cs.into_nodehash().to_hex().into();
+                    (name.to_string(), hash)
+                });
+            bundle.add_part(parts::listkey_part("bookmarks", items)?);
+        }
+        // TODO(stash): handle includepattern= and excludepattern=
+
+        let encode_fut = bundle.build();
+
+        Ok(encode_fut
+            .map(|cursor| Bytes::from(cursor.into_inner()))
+            .from_err()
+            .boxify())
+    }
+
+    fn changegroup_part(
+        &self,
+        heads: Vec<HgNodeHash>,
+        common: Vec<HgNodeHash>,
+    ) -> hgproto::Result<PartEncodeBuilder> {
         let repo_generation = &self.repo.repo_generation;
         let blobrepo = &self.repo.blobrepo;
 
-        let common_heads: HashSet<_> = HashSet::from_iter(args.common.iter());
+        let common_heads: HashSet<_> = HashSet::from_iter(common.iter());
 
-        let heads: Vec<_> = args.heads
+        let heads: Vec<_> = heads
             .iter()
             .filter(|head| !common_heads.contains(head))
             .cloned()
@@ -343,7 +845,7 @@ impl RepoClient {
             debug!(self.logger, "{}", head);
         }
 
-        let excludes: Vec<_> = args.common
+        let excludes: Vec<_> = common
             .iter()
             .map(|node| node.clone().into_option())
             .filter_map(|maybe_node| maybe_node)
@@ -391,29 +893,24 @@ impl RepoClient {
                 ))
             });
 
-        bundle.add_part(parts::changegroup_part(changelogentries)?);
-
-        // XXX Note that listkeys is NOT returned as a bundle2 capability -- see comment in
-        // bundle2caps() for why.
-
-        // TODO: generalize this to other listkey types
-        // (note: just calling &b"bookmarks"[..] doesn't work because https://fburl.com/0p0sq6kp)
-        if args.listkeys.contains(&b"bookmarks".to_vec()) {
-            let blobrepo = self.repo.blobrepo.clone();
-            let items = blobrepo.get_bookmarks().map(|(name, cs)| {
-                let hash: Vec<u8> = cs.into_nodehash().to_hex().into();
-                (name.to_string(), hash)
-            });
-            bundle.add_part(parts::listkey_part("bookmarks", items)?);
-        }
-        // TODO(stash): handle includepattern= and excludepattern=
+        Ok(parts::changegroup_part(changelogentries)?)
+    }
 
-        let encode_fut = bundle.build();
+    /// Builds the part of a streaming clone, which sends the revlogs of the snapshot instead of
+    /// a changegroup
+    fn stream2_part(&self, snapshot: Snapshot) -> hgproto::Result<PartEncodeBuilder> {
+        info!(self.logger, "streaming {} files", snapshot.files.len());
+
+        let filecount = snapshot.files.len();
+        let bytecount = snapshot.bytecount();
+        let streaming_clone = self.repo.streaming_clone.clone();
+        let files = stream::iter_ok::<_, Error>(snapshot.files).map(move |file| {
+            let content = streaming_clone.stream_file(&file);
+            (file.name, file.size, content)
+        });
 
-        Ok(encode_fut
-            .map(|cursor| Bytes::from(cursor.into_inner()))
-            .from_err()
-            .boxify())
+        let requirements = snapshot.client_requirements();
+        Ok(parts::stream2_part(&requirements, filecount, bytecount, files)?)
     }
 
     fn gettreepack_untimed(&self, params: GettreepackArgs) -> BoxStream<Bytes, Error> {
@@ -573,50 +1070,56 @@ impl HgCommands for RepoClient {
         let mut scuba_logger = self.scuba_logger(ops::HEADS, None);
         let trace = self.trace.clone();
 
-        self.repo
-            .blobrepo
-            .get_heads()
-            .collect()
-            .map(|v| v.into_iter().collect())
-            .from_err()
+        self.get_bookmarks_snapshot()
+            .map(|bookmarks| bookmarks.iter().map(|&(_, cs)| cs.into_nodehash()).collect())
             .inspect(move |resp| debug!(logger, "heads response: {:?}", resp))
             .timed(move |stats, _| scuba_logger.add_stats(&stats).log_with_trace(&trace))
             .boxify()
     }
 
+    // @wireprotocommand('branchmap')
+    fn branchmap(&self) -> HgCommandRes<Branchmap> {
+        let mut scuba_logger = self.scuba_logger(ops::BRANCHMAP, None);
+        let trace = self.trace.clone();
+        let repo = self.repo.clone();
+
+        // Serve the branches of the same heads as the heads command
+        self.get_bookmarks_snapshot()
+            .and_then(move |bookmarks| {
+                let mut heads: Vec<_> = bookmarks
+                    .iter()
+                    .map(|&(_, cs)| cs.into_nodehash())
+                    .collect();
+                heads.sort();
+                heads.dedup();
+                repo.get_branchmap(heads)
+            })
+            .map(|branchmap| (*branchmap).clone())
+            .timed(move |stats, _| scuba_logger.add_stats(&stats).log_with_trace(&trace))
+            .boxify()
+    }
+
     // @wireprotocommand('lookup', 'key')
     fn lookup(&self, key: String) -> HgCommandRes<Bytes> {
         info!(self.logger, "lookup: {:?}", key);
-        // TODO(stash): T25928839 lookup should support bookmarks and prefixes too
         let repo = self.repo.blobrepo.clone();
         let mut scuba_logger = self.scuba_logger(ops::LOOKUP, None);
         let trace = self.trace.clone();
 
-        HgNodeHash::from_str(&key)
-            .into_future()
-            .and_then(move |node| {
-                let csid = HgChangesetId::new(node);
-                repo.changeset_exists(&csid)
-                    .map(move |exists| (node, exists))
-            })
-            .and_then(|(node, exists)| {
-                if exists {
-                    let mut buf = BytesMut::with_capacity(node.to_hex().len() + 3);
-                    buf.put(b'1');
-                    buf.put(b' ');
-                    buf.extend_from_slice(node.to_hex().as_bytes());
-                    buf.put(b'\n');
-                    Ok(buf.freeze())
-                } else {
-                    let err_msg = format!("{} not found", node);
-                    let mut buf = BytesMut::with_capacity(err_msg.len() + 3);
-                    buf.put(b'0');
-                    buf.put(b' ');
-                    buf.extend_from_slice(err_msg.as_bytes());
-                    buf.put(b'\n');
-                    Ok(buf.freeze())
+        // Same order as in Mercurial: full hash, then bookmark, then hash prefix
+        lookup_full_hash(repo.clone(), &key)
+            .and_then({
+                let repo = repo.clone();
+                let key = key.clone();
+                move |found| match found {
+                    Some(csid) => future::ok(Some(csid)).boxify(),
+                    None => lookup_bookmark(repo, &key),
                 }
             })
+            .and_then(move |found| match found {
+                Some(csid) => future::ok(lookup_success(csid)).boxify(),
+                None => lookup_prefix(repo, key),
+            })
             .timed(move |stats, _| scuba_logger.add_stats(&stats).log_with_trace(&trace))
             .boxify()
     }
@@ -648,10 +1151,32 @@ impl HgCommands for RepoClient {
         let mut scuba_logger = self.scuba_logger(ops::GETBUNDLE, None);
         let trace = self.trace.clone();
 
-        match self.create_bundle(args) {
-            Ok(res) => res,
-            Err(err) => Err(err).into_future().boxify(),
-        }.timed(move |stats, _| scuba_logger.add_stats(&stats).log_with_trace(&trace))
+        let remotefilelog = args.bundlecaps
+            .iter()
+            .any(|cap| cap.as_slice() == REMOTEFILELOG_BUNDLECAP);
+
+        let snapshot = if !args.stream {
+            future::ok(None).boxify()
+        } else if !remotefilelog {
+            // Snapshots have no filelogs, see Snapshot::client_requirements()
+            future::err(ErrorKind::StreamingCloneNeedsRemotefilelog.into()).boxify()
+        } else {
+            self.repo
+                .get_snapshot()
+                .and_then(|snapshot| match snapshot {
+                    Some(snapshot) => Ok(Some(snapshot)),
+                    None => Err(ErrorKind::NoStreamingCloneSnapshot.into()),
+                })
+                .boxify()
+        };
+
+        let client = self.clone();
+        snapshot
+            .and_then(move |snapshot| match client.create_bundle(args, snapshot) {
+                Ok(res) => res,
+                Err(err) => Err(err).into_future().boxify(),
+            })
+            .timed(move |stats, _| scuba_logger.add_stats(&stats).log_with_trace(&trace))
             .boxify()
     }
 
@@ -659,15 +1184,26 @@ impl HgCommands for RepoClient {
     fn hello(&self) -> HgCommandRes<HashMap<String, Vec<String>>> {
         info!(self.logger, "Hello -> capabilities");
 
-        let mut res = HashMap::new();
-        let mut caps = wireprotocaps();
-        caps.push(format!("bundle2={}", bundle2caps()));
-        res.insert("capabilities".to_string(), caps);
-
         let mut scuba_logger = self.scuba_logger(ops::HELLO, None);
         let trace = self.trace.clone();
 
-        future::ok(res)
+        // Streaming clones are only advertised once there is a snapshot to stream. Clients that
+        // ask for one fall back to a regular clone otherwise, and so do clients that don't
+        // support remotefilelog, which is one of the advertised requirements.
+        self.repo
+            .get_snapshot()
+            .map(|snapshot| {
+                let mut caps = wireprotocaps();
+                if let Some(ref snapshot) = snapshot {
+                    let requirements = snapshot.client_requirements();
+                    caps.push(format!("streamreqs={}", requirements.join(",")));
+                }
+                caps.push(format!("bundle2={}", bundle2caps(snapshot.is_some())));
+
+                let mut res = HashMap::new();
+                res.insert("capabilities".to_string(), caps);
+                res
+            })
             .timed(move |stats, _| scuba_logger.add_stats(&stats).log_with_trace(&trace))
             .boxify()
     }
@@ -675,21 +1211,30 @@ impl HgCommands for RepoClient {
     // @wireprotocommand('listkeys', 'namespace')
     fn listkeys(&self, namespace: String) -> HgCommandRes<HashMap<Vec<u8>, Vec<u8>>> {
         if namespace == "bookmarks" {
-            self.repo
-                .blobrepo
-                .get_bookmarks()
-                .map(|(name, cs)| {
-                    let hash: Vec<u8> = cs.into_nodehash().to_hex().into();
-                    (name, hash)
-                })
-                .collect()
+            self.get_bookmarks_snapshot()
                 .map(|bookmarks| {
-                    let bookiter = bookmarks
-                        .into_iter()
-                        .map(|(name, value)| (Vec::from(name.to_string()), value));
+                    let bookiter = bookmarks.iter().map(|&(ref name, cs)| {
+                        let hash: Vec<u8> = cs.into_nodehash().to_hex().into();
+                        (Vec::from(name.to_string()), hash)
+                    });
                     HashMap::from_iter(bookiter)
                 })
                 .boxify()
+        } else if namespace == "phases" {
+            // Mercurial only needs the roots of the draft changesets, everything else is public
+            let phases = self.repo.live_config().phases;
+            self.get_bookmarks_snapshot()
+                .and_then(move |bookmarks| {
+                    phases.get_draft_roots(bookmarks.iter().map(|&(_, cs)| cs).collect())
+                })
+                .map(|roots| {
+                    let rootiter = roots.into_iter().map(|cs| {
+                        let hash: Vec<u8> = cs.into_nodehash().to_hex().into();
+                        (hash, Vec::from(Phase::Draft.to_hg_number().to_string()))
+                    });
+                    HashMap::from_iter(rootiter)
+                })
+                .boxify()
         } else {
             info!(
                 self.get_logger(),
@@ -700,6 +1245,58 @@ impl HgCommands for RepoClient {
         }
     }
 
+    // @wireprotocommand('listkeyspatterns', 'namespace patterns')
+    fn listkeyspatterns(
+        &self,
+        namespace: String,
+        patterns: Vec<String>,
+    ) -> HgCommandRes<HashMap<Vec<u8>, Vec<u8>>> {
+        if namespace != "bookmarks" {
+            info!(
+                self.get_logger(),
+                "unsupported listkeyspatterns namespace: {}",
+                namespace
+            );
+            return future::ok(HashMap::new()).boxify();
+        }
+
+        info!(self.logger, "listkeyspatterns: {:?}", patterns);
+
+        // Public bookmarks come from the session snapshot, scratch bookmarks are always fetched
+        let public = self.get_bookmarks_snapshot().map({
+            let patterns = patterns.clone();
+            move |bookmarks| {
+                bookmarks
+                    .iter()
+                    .filter(|&&(ref name, _)| {
+                        let name = name.to_string();
+                        patterns
+                            .iter()
+                            .any(|pattern| bookmark_matches_pattern(&name, pattern))
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>()
+            }
+        });
+
+        let blobrepo = self.repo.blobrepo.clone();
+        let scratch = stream::iter_ok::<_, Error>(patterns)
+            .map(move |pattern| get_scratch_bookmarks_by_pattern(blobrepo.clone(), &pattern))
+            .flatten()
+            .collect();
+
+        public
+            .join(scratch)
+            .map(|(public, scratch)| {
+                let bookiter = public.into_iter().chain(scratch).map(|(name, cs)| {
+                    let hash: Vec<u8> = cs.into_nodehash().to_hex().into();
+                    (Vec::from(name.to_string()), hash)
+                });
+                HashMap::from_iter(bookiter)
+            })
+            .boxify()
+    }
+
     // @wireprotocommand('unbundle')
     fn unbundle(
         &self,
@@ -709,13 +1306,26 @@ impl HgCommands for RepoClient {
         let mut scuba_logger = self.scuba_logger(ops::UNBUNDLE, None);
         let trace = self.trace.clone();
 
+        let live_config = self.repo.live_config();
         let res = bundle2_resolver::resolve(
             self.repo.blobrepo.clone(),
             self.logger.new(o!("command" => "unbundle")),
             scuba_logger.clone(),
+            live_config.hook_manager,
+            live_config.phases,
+            live_config.bookmark_policies,
+            self.session.clone(),
+            self.user.clone(),
             heads,
             stream,
-        );
+        ).then({
+            // The push might have moved bookmarks, later commands of this session should see that
+            let client = self.clone();
+            move |res| {
+                client.invalidate_bookmarks_snapshot();
+                res
+            }
+        });
 
         res.traced(&trace, "unbundle", trace_args!())
             .timed(move |stats, _| scuba_logger.add_stats(&stats).log_with_trace(&trace))
@@ -755,18 +1365,55 @@ impl HgCommands for RepoClient {
 
                 trace!(logger, "get file request: {:?} {}", path, node);
                 let repo = this.repo.clone();
-                create_remotefilelog_blob(repo.blobrepo.clone(), node, path.clone(), trace.clone())
-                    .traced(
-                        &trace,
-                        "getfile",
-                        trace_args!("node" => format!("{}", node), "path" => format!("{}", path)),
-                    )
-                    .timed({
-                        let trace = trace.clone();
-                        move |stats, _| scuba_logger.add_stats(&stats).log_with_trace(&trace)
-                    })
+                let blob = create_remotefilelog_blob(
+                    repo.blobrepo.clone(),
+                    node,
+                    path.clone(),
+                    repo.stream_threshold,
+                    repo.send_lfs_pointers,
+                    trace.clone(),
+                );
+                blob.traced(
+                    &trace,
+                    "getfile",
+                    trace_args!("node" => format!("{}", node), "path" => format!("{}", path)),
+                ).timed({
+                    let trace = trace.clone();
+                    move |stats, _| scuba_logger.add_stats(&stats).log_with_trace(&trace)
+                })
             })
             .buffered(getfiles_buffer_size)
+            .flatten()
+            .boxify()
+    }
+
+    // @wireprotocommand('stream_out')
+    fn stream_out(&self) -> BoxStream<Bytes, Error> {
+        info!(self.logger, "stream_out");
+
+        let mut scuba_logger = self.scuba_logger(ops::STREAM_OUT, None);
+        let trace = self.trace.clone();
+        let streaming_clone = self.repo.streaming_clone.clone();
+
+        self.repo
+            .get_snapshot()
+            .map(move |snapshot| match snapshot {
+                // Tells the client that the operation is forbidden
+                None => stream::once(Ok(Bytes::from(&b"1\n"[..]))).boxify(),
+                Some(snapshot) => {
+                    let header = format!("0\n{} {}\n", snapshot.files.len(), snapshot.bytecount());
+                    let files = stream::iter_ok::<_, Error>(snapshot.files)
+                        .map(move |file| {
+                            let header = format!("{}\0{}\n", file.name, file.size);
+                            stream::once(Ok(Bytes::from(header)))
+                                .chain(streaming_clone.stream_file(&file))
+                        })
+                        .flatten();
+                    stream::once(Ok(Bytes::from(header))).chain(files).boxify()
+                }
+            })
+            .flatten_stream()
+            .timed(move |stats, _| scuba_logger.add_stats(&stats).log_with_trace(&trace))
             .boxify()
     }
 }
@@ -983,39 +1630,25 @@ fn get_file_history(
 }
 
 /// Remotefilelog blob consists of file content in `node` revision and all the history
-/// of the file up to `node`
+/// of the file up to `node`. Getfiles sends it lz4 compressed and prefixed with its length.
+///
+/// If `send_lfs_pointers` is set, the content of files stored in the LFS blobstore is the
+/// pointer to them instead, flagged so that clients with the lfs extension fetch what it points
+/// to themselves.
 fn create_remotefilelog_blob(
     repo: Arc<BlobRepo>,
     node: HgNodeHash,
     path: MPath,
+    stream_threshold: u64,
+    send_lfs_pointers: bool,
     trace: TraceContext,
-) -> BoxFuture<Bytes, Error> {
-    // raw_content includes copy information
-    let raw_content_bytes = repo.get_file_content(&node)
-        .and_then(move |raw_content| {
-            let raw_content = raw_content.into_bytes();
-            // requires digit counting to know for sure, use reasonable approximation
-            let approximate_header_size = 12;
-            let mut writer = Cursor::new(Vec::with_capacity(
-                approximate_header_size + raw_content.len(),
-            ));
-
-            // Write header
-            // TODO(stash): support LFS files using METAKEYFLAG
-            let res = write!(
-                writer,
-                "v1\n{}{}\n{}{}\0",
-                METAKEYSIZE,
-                raw_content.len(),
-                METAKEYFLAG,
-                0,
-            );
-
-            res.and_then(|_| writer.write_all(&raw_content))
-                .map_err(Error::from)
-                .map(|_| writer.into_inner())
-        })
-        .traced(&trace, "fetching remotefilelog content", trace_args!());
+) -> BoxFuture<BoxStream<Bytes, Error>, Error> {
+    let file_size = repo.get_file_size(&node);
+    let lfs_pointer = if send_lfs_pointers {
+        repo.get_hg_lfs_pointer(&node)
+    } else {
+        future::ok(None).boxify()
+    };
 
     // Do bulk prefetch of the filenodes first. That saves lots of db roundtrips.
     // Prefetched filenodes are used as a cache. If filenode is not in the cache, then it will
@@ -1030,6 +1663,7 @@ fn create_remotefilelog_blob(
 
     let file_history_bytes = prefetched_filenodes
         .and_then({
+            let repo = repo.clone();
             let node = node.clone();
             let trace = trace.clone();
             move |prefetched_filenodes| {
@@ -1073,13 +1707,89 @@ fn create_remotefilelog_blob(
         })
         .traced(&trace, "fetching file history", trace_args!());
 
-    raw_content_bytes
-        .join(file_history_bytes)
-        .map(|(mut raw_content, file_history)| {
-            raw_content.extend(file_history);
-            raw_content
+    file_size
+        .join3(file_history_bytes, lfs_pointer)
+        .and_then(move |(size, file_history, lfs_pointer)| {
+            if let Some(lfs_pointer) = lfs_pointer {
+                let header = format!(
+                    "v1\n{}{}\n{}{}\0",
+                    METAKEYSIZE,
+                    lfs_pointer.len(),
+                    METAKEYFLAG,
+                    EXTSTORED_FLAG
+                );
+                return compress_remotefilelog_content(header, &lfs_pointer, file_history)
+                    .into_future()
+                    .boxify();
+            }
+
+            // Write header
+            let header = format!("v1\n{}{}\n{}{}\0", METAKEYSIZE, size, METAKEYFLAG, 0);
+            if size > stream_threshold {
+                stream_remotefilelog_blob(&repo, node, header, size, file_history)
+                    .into_future()
+                    .boxify()
+            } else {
+                compress_remotefilelog_blob(&repo, node, header, file_history, trace)
+            }
+        })
+        .boxify()
+}
+
+fn compress_remotefilelog_blob(
+    repo: &BlobRepo,
+    node: HgNodeHash,
+    header: String,
+    file_history: Vec<u8>,
+    trace: TraceContext,
+) -> BoxFuture<BoxStream<Bytes, Error>, Error> {
+    repo.get_file_content(&node)
+        .traced(&trace, "fetching remotefilelog content", trace_args!())
+        .and_then(move |raw_content| {
+            compress_remotefilelog_content(header, &raw_content.into_bytes(), file_history)
         })
-        .and_then(|content| pylz4::compress(&content))
-        .map(|bytes| Bytes::from(bytes))
         .boxify()
 }
+
+fn compress_remotefilelog_content(
+    header: String,
+    content: &[u8],
+    file_history: Vec<u8>,
+) -> Result<BoxStream<Bytes, Error>> {
+    let mut blob = Vec::with_capacity(header.len() + content.len() + file_history.len());
+    blob.extend_from_slice(header.as_bytes());
+    blob.extend_from_slice(content);
+    blob.extend_from_slice(&file_history);
+    let compressed = pylz4::compress(&blob)?;
+
+    let mut framed = format!("{}\n", compressed.len()).into_bytes();
+    framed.extend_from_slice(&compressed);
+    Ok(stream::once(Ok(Bytes::from(framed))).boxify())
+}
+
+/// Huge contents are not compressed, as that would need all of them in memory. They are sent
+/// as an uncompressed lz4 block instead, which only needs its size to be known upfront.
+///
+/// An lz4 block can't be larger than `pylz4::MAX_INPUT_SIZE`, just under 2 GiB, and remotefilelog
+/// blobs are single lz4 blocks, so larger files can't be sent at all and fail the request.
+fn stream_remotefilelog_blob(
+    repo: &BlobRepo,
+    node: HgNodeHash,
+    header: String,
+    size: u64,
+    file_history: Vec<u8>,
+) -> Result<BoxStream<Bytes, Error>> {
+    let blob_size = header.len() as u64 + size + file_history.len() as u64;
+    if blob_size > pylz4::MAX_INPUT_SIZE as u64 {
+        bail_err!(ErrorKind::FileTooLargeForGetfiles(node, size));
+    }
+    let lz4_header = pylz4::uncompressed_header(blob_size as usize)?;
+
+    let mut framed = format!("{}\n", lz4_header.len() as u64 + blob_size).into_bytes();
+    framed.extend_from_slice(&lz4_header);
+    framed.extend_from_slice(header.as_bytes());
+    Ok(stream::once(Ok(Bytes::from(framed)))
+        .chain(repo.get_file_content_stream(&node))
+        .chain(stream::once(Ok(Bytes::from(file_history))))
+        .boxify())
+}
@@ -0,0 +1,16 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+pub use failure::{Error, Result};
+
+use mercurial_types::HgChangesetId;
+
+#[derive(Debug, Eq, Fail, PartialEq)]
+pub enum ErrorKind {
+    #[fail(display = "Generation number of {} not found", _0)] GenerationMissing(HgChangesetId),
+    #[fail(display = "{} is too far from the indexed changesets, the index needs a backfill", _0)]
+    NotIndexed(HgChangesetId),
+}
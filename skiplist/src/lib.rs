@@ -0,0 +1,647 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Skip-list index of the commit graph, to answer ancestry queries without walking the history
+//! one changeset at a time.
+//!
+//! Every changeset has a `SkiplistNode` with its generation number, its parents and, if it has a
+//! single parent, skip edges to the ancestors 1, 2, 4, ... changesets away along the first-parent
+//! chain. An edge never skips over a merge, so the ancestors of a changeset are the changesets
+//! between it and the target of an edge plus the ancestors of the target. On linear history
+//! `is_ancestor` then needs a logarithmic number of node lookups.
+//!
+//! The node of a changeset never changes, so nodes are computed the first time they are needed,
+//! stored in the blobstore and cached in memory. Computing the node of a changeset needs the
+//! nodes of its first-parent ancestors, so only changesets close to indexed ones are computed on
+//! demand. The rest of the history is indexed offline with `SkiplistIndex::backfill`.
+
+#![deny(warnings)]
+
+extern crate bincode;
+extern crate bytes;
+#[macro_use]
+extern crate failure_ext as failure;
+extern crate futures;
+#[macro_use]
+extern crate serde_derive;
+
+extern crate asyncmemo;
+extern crate blobrepo;
+extern crate blobstore;
+#[macro_use]
+extern crate futures_ext;
+extern crate mercurial_types;
+extern crate mononoke_types;
+
+use std::cmp;
+use std::collections::{BinaryHeap, HashSet};
+use std::mem;
+use std::sync::Arc;
+use std::usize;
+
+use bytes::Bytes;
+use futures::{future, stream, Future, Stream};
+use futures::future::{loop_fn, Loop};
+
+use asyncmemo::{Asyncmemo, Filler, Weight};
+use blobrepo::BlobRepo;
+use blobstore::Blobstore;
+use futures_ext::{BoxFuture, BoxStream, FutureExt, StreamExt};
+use mercurial_types::{HgChangesetId, HgNodeHash};
+use mononoke_types::BlobstoreBytes;
+
+mod errors;
+
+pub use errors::*;
+
+/// Position of a changeset in the commit graph.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SkiplistNode {
+    /// Generation number of the changeset, 1 for a root.
+    pub generation: u64,
+    /// Parents of the changeset with their generation numbers.
+    pub parents: Vec<(HgNodeHash, u64)>,
+    /// For a changeset with a single parent, the ancestors 2^i changesets away along the
+    /// first-parent chain with their generation numbers, up to the first merge or root. Empty
+    /// for merges and roots.
+    pub skip_edges: Vec<(HgNodeHash, u64)>,
+}
+
+impl SkiplistNode {
+    /// Changesets to visit next when looking for ancestors with a generation number of at least
+    /// `min_generation`: the target of the longest skip edge that doesn't go below it, or the
+    /// parents of a merge.
+    fn next_hops(&self, min_generation: u64) -> Vec<(HgNodeHash, u64)> {
+        if self.skip_edges.is_empty() {
+            self.parents
+                .iter()
+                .filter(|&&(_, gen)| gen >= min_generation)
+                .cloned()
+                .collect()
+        } else {
+            self.skip_edges
+                .iter()
+                .rev()
+                .find(|&&(_, gen)| gen >= min_generation)
+                .cloned()
+                .into_iter()
+                .collect()
+        }
+    }
+}
+
+impl Weight for SkiplistNode {
+    fn get_weight(&self) -> usize {
+        mem::size_of::<Self>()
+            + (self.parents.len() + self.skip_edges.len()) * mem::size_of::<(HgNodeHash, u64)>()
+    }
+}
+
+/// How many changesets the index computes the nodes of to find the node of a changeset.
+const DEFAULT_FILL_LIMIT: usize = 100;
+
+/// Skip-list index of the changesets of a repo.
+pub struct SkiplistIndex {
+    cache: Asyncmemo<NodeFiller>,
+}
+
+impl Clone for SkiplistIndex {
+    fn clone(&self) -> Self {
+        Self {
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl SkiplistIndex {
+    /// Construct a new `SkiplistIndex` of `repo`, caching up to `sizelimit` bytes of nodes.
+    /// Queries involving changesets that are more than a few changesets away from the indexed
+    /// ones fail with `ErrorKind::NotIndexed`.
+    pub fn new(repo: Arc<BlobRepo>, sizelimit: usize) -> Self {
+        Self::with_fill_limit(repo, sizelimit, DEFAULT_FILL_LIMIT)
+    }
+
+    /// Like `new`, but computes the nodes of up to `fill_limit` changesets to find the node of
+    /// a changeset.
+    pub fn with_fill_limit(repo: Arc<BlobRepo>, sizelimit: usize, fill_limit: usize) -> Self {
+        SkiplistIndex {
+            cache: Asyncmemo::with_limits(
+                NodeFiller { repo, fill_limit },
+                usize::MAX,
+                sizelimit,
+            ),
+        }
+    }
+
+    /// Index all the ancestors of `heads`, however far they are from the indexed changesets.
+    pub fn backfill(
+        repo: Arc<BlobRepo>,
+        sizelimit: usize,
+        heads: Vec<HgNodeHash>,
+    ) -> BoxFuture<(), Error> {
+        let this = Self::with_fill_limit(repo, sizelimit, usize::MAX);
+
+        // Getting the node of a changeset indexes its first-parent chain down to a merge or a
+        // root, the chains of the parents of which are indexed next
+        loop_fn(
+            (heads, HashSet::new()),
+            move |(mut pending, mut visited): (Vec<_>, HashSet<_>)| {
+                let head = match pending.pop() {
+                    Some(head) => head,
+                    None => return future::ok(Loop::Break(())).boxify(),
+                };
+                this.chain_bottom(head)
+                    .map(move |(bottom, node)| {
+                        if visited.insert(bottom) {
+                            pending.extend(node.parents.into_iter().map(|(parent, _)| parent));
+                        }
+                        Loop::Continue((pending, visited))
+                    })
+                    .boxify()
+            },
+        ).boxify()
+    }
+
+    /// Get the node of a changeset, computing and storing it and the nodes of its first-parent
+    /// ancestors if needed.
+    pub fn get_node(&self, node: HgNodeHash) -> BoxFuture<SkiplistNode, Error> {
+        self.cache.get(HgChangesetId::new(node)).boxify()
+    }
+
+    /// The first merge or root along the first-parent chain of a changeset, which may be the
+    /// changeset itself.
+    fn chain_bottom(&self, node: HgNodeHash) -> BoxFuture<(HgNodeHash, SkiplistNode), Error> {
+        let this = self.clone();
+        loop_fn(node, move |hash| {
+            this.get_node(hash).map(move |node| {
+                match node.skip_edges.last().cloned() {
+                    Some((target, _)) => Loop::Continue(target),
+                    None => Loop::Break((hash, node)),
+                }
+            })
+        }).boxify()
+    }
+
+    /// Whether `ancestor` is an ancestor of `descendant`. Every changeset is its own ancestor.
+    pub fn is_ancestor(
+        &self,
+        ancestor: HgNodeHash,
+        descendant: HgNodeHash,
+    ) -> BoxFuture<bool, Error> {
+        let this = self.clone();
+        self.get_node(ancestor)
+            .and_then(move |target| {
+                let min_generation = target.generation;
+                loop_fn(
+                    (vec![descendant], HashSet::new()),
+                    move |(frontier, mut visited)| {
+                        if frontier.contains(&ancestor) {
+                            return future::ok(Loop::Break(true)).boxify();
+                        }
+                        if frontier.is_empty() {
+                            return future::ok(Loop::Break(false)).boxify();
+                        }
+
+                        let nodes = frontier
+                            .into_iter()
+                            .map(|node| this.get_node(node))
+                            .collect::<Vec<_>>();
+                        future::join_all(nodes)
+                            .map(move |nodes| {
+                                let mut next = vec![];
+                                for node in nodes {
+                                    for (hop, gen) in node.next_hops(min_generation) {
+                                        // Other changesets with the same generation number
+                                        // can't lead to the ancestor
+                                        if (gen > min_generation || hop == ancestor)
+                                            && visited.insert(hop)
+                                        {
+                                            next.push(hop);
+                                        }
+                                    }
+                                }
+                                Loop::Continue((next, visited))
+                            })
+                            .boxify()
+                    },
+                )
+            })
+            .boxify()
+    }
+
+    /// The common ancestor of `nodes` with the highest generation number, if they have any. If
+    /// several have the same generation number, any one of them.
+    pub fn greatest_common_ancestor(
+        &self,
+        nodes: Vec<HgNodeHash>,
+    ) -> BoxFuture<Option<HgNodeHash>, Error> {
+        let mut nodes = nodes.into_iter();
+        let first = match nodes.next() {
+            Some(first) => first,
+            None => return future::ok(None).boxify(),
+        };
+        let others: Arc<Vec<_>> = Arc::new(nodes.collect());
+
+        // Ancestors of the first node are visited in decreasing order of generation numbers, so
+        // the first common ancestor found is the greatest one. Nodes that are not common
+        // ancestors are skipped over with `skip_to_common_ancestor`.
+        let this = self.clone();
+        self.get_node(first)
+            .and_then(move |node| {
+                let mut candidates = BinaryHeap::new();
+                candidates.push((node.generation, first));
+                loop_fn(
+                    (candidates, HashSet::new()),
+                    move |(mut candidates, mut visited)| {
+                        let candidate = loop {
+                            match candidates.pop() {
+                                Some((_, candidate)) => if visited.insert(candidate) {
+                                    break candidate;
+                                },
+                                None => return future::ok(Loop::Break(None)).boxify(),
+                            }
+                        };
+
+                        let this = this.clone();
+                        let others = others.clone();
+                        this.is_common_ancestor(candidate, others.clone())
+                            .and_then(move |common| {
+                                if common {
+                                    return future::ok(Loop::Break(Some(candidate))).boxify();
+                                }
+                                this.skip_to_common_ancestor(candidate, others)
+                                    .map(move |next| {
+                                        for (node, gen) in next {
+                                            candidates.push((gen, node));
+                                        }
+                                        Loop::Continue((candidates, visited))
+                                    })
+                                    .boxify()
+                            })
+                            .boxify()
+                    },
+                )
+            })
+            .boxify()
+    }
+
+    /// All the common ancestors of `nodes`, in decreasing order of generation numbers.
+    pub fn common_ancestors(&self, nodes: Vec<HgNodeHash>) -> BoxStream<HgNodeHash, Error> {
+        let mut nodes = nodes.into_iter();
+        let first = match nodes.next() {
+            Some(first) => first,
+            None => return stream::empty().boxify(),
+        };
+        let others: Arc<Vec<_>> = Arc::new(nodes.collect());
+
+        // Like `greatest_common_ancestor`, but the search goes on after a common ancestor is
+        // found. The ancestors of a common ancestor are common ancestors, so they are marked as
+        // such and output without checking them.
+        let this = self.clone();
+        self.get_node(first)
+            .map(move |node| {
+                let mut candidates = BinaryHeap::new();
+                candidates.push((node.generation, false, first));
+                stream::unfold((candidates, HashSet::new()), move |(candidates, visited)| {
+                    if candidates.is_empty() {
+                        return None;
+                    }
+                    Some(this.next_common_ancestor(candidates, visited, others.clone()))
+                })
+            })
+            .flatten_stream()
+            .filter_map(|node| node)
+            .boxify()
+    }
+
+    /// Changesets that are descendants of `start` and ancestors of `end`, both included, in
+    /// decreasing order of generation numbers.
+    pub fn range(&self, start: HgNodeHash, end: HgNodeHash) -> BoxFuture<Vec<HgNodeHash>, Error> {
+        let this = self.clone();
+        self.is_ancestor(start, end)
+            .join(self.get_node(start))
+            .and_then(move |(in_range, start_node)| {
+                if !in_range {
+                    return future::ok(vec![]).boxify();
+                }
+
+                let min_generation = start_node.generation;
+                let mut visited = HashSet::new();
+                visited.insert(end);
+                loop_fn(
+                    (vec![end], visited, vec![]),
+                    move |(frontier, mut visited, mut output)| {
+                        if frontier.is_empty() {
+                            output.sort_by(|a, b| b.cmp(a));
+                            let output = output.into_iter().map(|(_, node)| node).collect();
+                            return future::ok(Loop::Break(output)).boxify();
+                        }
+
+                        let this = this.clone();
+                        let nodes = frontier
+                            .into_iter()
+                            .map(|hash| this.get_node(hash).map(move |node| (hash, node)))
+                            .collect::<Vec<_>>();
+                        future::join_all(nodes)
+                            .and_then(move |nodes| {
+                                let mut next = vec![];
+                                let mut merge_parents = vec![];
+                                for (hash, node) in nodes {
+                                    output.push((node.generation, hash));
+                                    if hash == start {
+                                        continue;
+                                    }
+                                    if node.parents.len() == 1 {
+                                        // `start` is an ancestor of this changeset, so it is
+                                        // an ancestor of its only parent
+                                        let (parent, _) = node.parents[0];
+                                        if visited.insert(parent) {
+                                            next.push(parent);
+                                        }
+                                    } else {
+                                        for (parent, gen) in node.parents {
+                                            if gen >= min_generation && visited.insert(parent) {
+                                                merge_parents.push(parent);
+                                            }
+                                        }
+                                    }
+                                }
+
+                                let checks = merge_parents
+                                    .into_iter()
+                                    .map(|parent| {
+                                        this.is_ancestor(start, parent)
+                                            .map(move |in_range| (parent, in_range))
+                                    })
+                                    .collect::<Vec<_>>();
+                                future::join_all(checks).map(move |checks| {
+                                    next.extend(
+                                        checks
+                                            .into_iter()
+                                            .filter(|&(_, in_range)| in_range)
+                                            .map(|(parent, _)| parent),
+                                    );
+                                    Loop::Continue((next, visited, output))
+                                })
+                            })
+                            .boxify()
+                    },
+                )
+            })
+            .boxify()
+    }
+
+    /// Pops candidates until a common ancestor of `others` is found, see `common_ancestors`.
+    fn next_common_ancestor(
+        &self,
+        candidates: BinaryHeap<(u64, bool, HgNodeHash)>,
+        visited: HashSet<HgNodeHash>,
+        others: Arc<Vec<HgNodeHash>>,
+    ) -> BoxFuture<
+        (
+            Option<HgNodeHash>,
+            (BinaryHeap<(u64, bool, HgNodeHash)>, HashSet<HgNodeHash>),
+        ),
+        Error,
+    > {
+        let this = self.clone();
+        loop_fn(
+            (candidates, visited),
+            move |(mut candidates, mut visited)| {
+                let (known_common, candidate) = loop {
+                    match candidates.pop() {
+                        Some((_, known_common, candidate)) => if visited.insert(candidate) {
+                            break (known_common, candidate);
+                        },
+                        None => {
+                            return future::ok(Loop::Break((None, (candidates, visited)))).boxify()
+                        }
+                    }
+                };
+
+                let common = if known_common {
+                    future::ok(true).boxify()
+                } else {
+                    this.is_common_ancestor(candidate, others.clone())
+                };
+                let this = this.clone();
+                let others = others.clone();
+                common
+                    .and_then(move |common| {
+                        if common {
+                            this.get_node(candidate)
+                                .map(move |node| {
+                                    for (parent, gen) in node.parents {
+                                        candidates.push((gen, true, parent));
+                                    }
+                                    Loop::Break((Some(candidate), (candidates, visited)))
+                                })
+                                .boxify()
+                        } else {
+                            this.skip_to_common_ancestor(candidate, others)
+                                .map(move |next| {
+                                    for (node, gen) in next {
+                                        candidates.push((gen, false, node));
+                                    }
+                                    Loop::Continue((candidates, visited))
+                                })
+                                .boxify()
+                        }
+                    })
+                    .boxify()
+            },
+        ).boxify()
+    }
+
+    fn is_common_ancestor(
+        &self,
+        node: HgNodeHash,
+        others: Arc<Vec<HgNodeHash>>,
+    ) -> BoxFuture<bool, Error> {
+        let checks = others
+            .iter()
+            .map(|other| self.is_ancestor(node, *other))
+            .collect::<Vec<_>>();
+        future::join_all(checks)
+            .map(|checks| checks.into_iter().all(|check| check))
+            .boxify()
+    }
+
+    /// Given a changeset that is not a common ancestor, find the changesets to look at next: the
+    /// first-parent chain below it is skipped over as long as it isn't a common ancestor.
+    ///
+    /// Common ancestors of `others` are closed under taking ancestors, so this is a binary
+    /// search over the skip edges. After following edge i, the edges of the target from i up
+    /// lead to common ancestors, so only the shorter ones are tried.
+    fn skip_to_common_ancestor(
+        &self,
+        node: HgNodeHash,
+        others: Arc<Vec<HgNodeHash>>,
+    ) -> BoxFuture<Vec<(HgNodeHash, u64)>, Error> {
+        let this = self.clone();
+        loop_fn((node, usize::MAX), move |(current, max_level)| {
+            let this = this.clone();
+            let others = others.clone();
+            this.get_node(current).and_then(move |node| {
+                if node.skip_edges.is_empty() {
+                    return future::ok(Loop::Break(node.parents)).boxify();
+                }
+
+                let levels = cmp::min(node.skip_edges.len(), max_level);
+                let edges = node.skip_edges.clone();
+                loop_fn(levels, move |level| {
+                    if level == 0 {
+                        return future::ok(Loop::Break(None)).boxify();
+                    }
+                    let (target, _) = edges[level - 1];
+                    this.is_common_ancestor(target, others.clone())
+                        .map(move |common| {
+                            if common {
+                                Loop::Continue(level - 1)
+                            } else {
+                                Loop::Break(Some(level - 1))
+                            }
+                        })
+                        .boxify()
+                }).map(move |level| match level {
+                    Some(level) => Loop::Continue((node.skip_edges[level].0, level)),
+                    None => Loop::Break(vec![node.skip_edges[0]]),
+                })
+                    .boxify()
+            })
+        }).boxify()
+    }
+}
+
+fn node_key(cs: &HgChangesetId) -> String {
+    format!("skiplist.hgchangeset.sha1.{}", cs)
+}
+
+fn load_node(repo: &BlobRepo, cs: &HgChangesetId) -> BoxFuture<Option<SkiplistNode>, Error> {
+    repo.get_blobstore()
+        .get(node_key(cs))
+        .and_then(|blob| match blob {
+            Some(blob) => Ok(Some(bincode::deserialize(blob.as_bytes().as_ref())?)),
+            None => Ok(None),
+        })
+        .boxify()
+}
+
+fn store_node(repo: &BlobRepo, cs: &HgChangesetId, node: &SkiplistNode) -> BoxFuture<(), Error> {
+    let blob = try_boxfuture!(bincode::serialize(node));
+    repo.get_blobstore()
+        .put(node_key(cs), BlobstoreBytes::from_bytes(Bytes::from(blob)))
+        .boxify()
+}
+
+fn get_generation(repo: &BlobRepo, cs: HgChangesetId) -> BoxFuture<u64, Error> {
+    repo.get_generation_number(&cs)
+        .and_then(move |gen| gen.ok_or_else(|| ErrorKind::GenerationMissing(cs).into()))
+        .boxify()
+}
+
+/// Node of a root or a merge, which doesn't depend on the nodes of its parents.
+fn unlinked_node(repo: &BlobRepo, parents: Vec<HgChangesetId>) -> BoxFuture<SkiplistNode, Error> {
+    let parents = parents
+        .into_iter()
+        .map(|parent| get_generation(repo, parent).map(move |gen| (parent.into_nodehash(), gen)))
+        .collect::<Vec<_>>();
+    future::join_all(parents)
+        .map(|parents| SkiplistNode {
+            generation: parents.iter().map(|&(_, gen)| gen).max().unwrap_or(0) + 1,
+            parents,
+            skip_edges: vec![],
+        })
+        .boxify()
+}
+
+/// Node of a changeset with a single parent. The skip edge 2^(i+1) changesets away is the edge
+/// 2^i changesets away of the target of the previous edge.
+fn linked_node(
+    cache: &Asyncmemo<NodeFiller>,
+    parent_hash: HgNodeHash,
+    parent: SkiplistNode,
+) -> BoxFuture<SkiplistNode, Error> {
+    let generation = parent.generation + 1;
+    let first_edge = (parent_hash, parent.generation);
+    let cache = cache.clone();
+    loop_fn((vec![first_edge], parent), move |(mut edges, target)| {
+        match target.skip_edges.get(edges.len() - 1).cloned() {
+            Some(edge) => {
+                edges.push(edge);
+                cache
+                    .get(HgChangesetId::new(edge.0))
+                    .map(move |target| Loop::Continue((edges, target)))
+                    .boxify()
+            }
+            None => future::ok(Loop::Break(edges)).boxify(),
+        }
+    }).map(move |skip_edges| SkiplistNode {
+        generation,
+        parents: vec![first_edge],
+        skip_edges,
+    })
+        .boxify()
+}
+
+struct NodeFiller {
+    repo: Arc<BlobRepo>,
+    fill_limit: usize,
+}
+
+impl Filler for NodeFiller {
+    type Key = HgChangesetId;
+    type Value = BoxFuture<SkiplistNode, Error>;
+
+    fn fill(&self, cache: &Asyncmemo<Self>, cs: &HgChangesetId) -> Self::Value {
+        // Walk down the first-parent chain until a changeset whose node is stored already, or a
+        // root or merge. The nodes of the changesets above it are then computed bottom up, and
+        // each is stored before the next one, whose skip edges may lead to it, is computed.
+        let start = *cs;
+        let fill_limit = self.fill_limit;
+        let base = loop_fn((*cs, vec![]), {
+            let repo = self.repo.clone();
+            move |(cs, mut chain)| {
+                let repo = repo.clone();
+                load_node(&repo, &cs).and_then(move |node| match node {
+                    Some(node) => future::ok(Loop::Break((cs, node, chain))).boxify(),
+                    None if chain.len() >= fill_limit => {
+                        future::err(ErrorKind::NotIndexed(start).into()).boxify()
+                    }
+                    None => repo.get_changeset_parents(&cs)
+                        .and_then(move |parents| {
+                            if parents.len() == 1 {
+                                chain.push(cs);
+                                return future::ok(Loop::Continue((parents[0], chain))).boxify();
+                            }
+                            unlinked_node(&repo, parents)
+                                .and_then(move |node| {
+                                    store_node(&repo, &cs, &node)
+                                        .map(move |()| Loop::Break((cs, node, chain)))
+                                })
+                                .boxify()
+                        })
+                        .boxify(),
+                })
+            }
+        });
+
+        let repo = self.repo.clone();
+        let cache = cache.clone();
+        base.and_then(move |(base_cs, base, chain)| {
+            stream::iter_ok::<_, Error>(chain.into_iter().rev()).fold(
+                (base_cs, base),
+                move |(parent_cs, parent), cs| {
+                    let repo = repo.clone();
+                    linked_node(&cache, parent_cs.into_nodehash(), parent).and_then(move |node| {
+                        store_node(&repo, &cs, &node).map(move |()| (cs, node))
+                    })
+                },
+            )
+        }).map(|(_, node)| node)
+            .boxify()
+    }
+}
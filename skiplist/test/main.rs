@@ -0,0 +1,236 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Tests for the skip-list index of the commit graph.
+
+#![deny(warnings)]
+
+extern crate async_unit;
+extern crate futures;
+
+extern crate blobstore;
+extern crate linear;
+extern crate mercurial_types;
+extern crate merge_uneven;
+extern crate skiplist;
+
+use std::sync::Arc;
+
+use futures::{Future, Stream};
+
+use blobstore::Blobstore;
+use mercurial_types::HgNodeHash;
+use skiplist::{ErrorKind, SkiplistIndex, SkiplistNode};
+
+fn string_to_nodehash(hash: &'static str) -> HgNodeHash {
+    HgNodeHash::from_static_str(hash).expect("Can't turn string to HgNodeHash")
+}
+
+#[test]
+fn linear_skip_edges() {
+    async_unit::tokio_unit_test(|| {
+        let repo = Arc::new(linear::getrepo(None));
+        let index = SkiplistIndex::new(repo.clone(), 100000);
+
+        let node = index
+            .get_node(string_to_nodehash("a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157"))
+            .wait()
+            .expect("Getting node failed");
+        let parent = string_to_nodehash("0ed509bf086fadcb8a8a5384dc3b550729b0fc17");
+        assert_eq!(
+            node,
+            SkiplistNode {
+                generation: 8,
+                parents: vec![(parent, 7)],
+                skip_edges: vec![
+                    (parent, 7),
+                    (
+                        string_to_nodehash("eed3a8c0ec67b6a6fe2eb3543334df3f0b4f202b"),
+                        6,
+                    ),
+                    (
+                        string_to_nodehash("d0a361e9022d226ae52f689667bd7d212a19cfe0"),
+                        4,
+                    ),
+                ],
+            }
+        );
+
+        // The nodes of the ancestors were stored too, and are used by a new index
+        let stored = repo.get_blobstore()
+            .get(format!("skiplist.hgchangeset.sha1.{}", parent))
+            .wait()
+            .expect("Getting stored node failed");
+        assert!(stored.is_some());
+        let index = SkiplistIndex::new(repo, 100000);
+        let node = index
+            .get_node(parent)
+            .wait()
+            .expect("Getting node failed");
+        assert_eq!(node.generation, 7);
+        assert_eq!(node.skip_edges.len(), 3);
+    });
+}
+
+#[test]
+fn linear_fill_limit() {
+    async_unit::tokio_unit_test(|| {
+        let repo = Arc::new(linear::getrepo(None));
+        let head = string_to_nodehash("a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157");
+
+        // The node of the head needs the nodes of all its ancestors, and none of them is stored
+        let index = SkiplistIndex::with_fill_limit(repo.clone(), 100000, 2);
+        let err = index
+            .get_node(head)
+            .wait()
+            .expect_err("getting an unindexed node succeeded");
+        match err.downcast::<ErrorKind>() {
+            Ok(ErrorKind::NotIndexed(_)) => {}
+            err => panic!("unexpected error {:?}", err),
+        }
+
+        SkiplistIndex::backfill(repo.clone(), 100000, vec![head])
+            .wait()
+            .expect("backfill failed");
+        let index = SkiplistIndex::with_fill_limit(repo, 100000, 0);
+        let node = index.get_node(head).wait().expect("Getting node failed");
+        assert_eq!(node.generation, 8);
+    });
+}
+
+#[test]
+fn linear_is_ancestor() {
+    async_unit::tokio_unit_test(|| {
+        let repo = Arc::new(linear::getrepo(None));
+        let index = SkiplistIndex::new(repo, 100000);
+        let root = string_to_nodehash("2d7d4ba9ce0a6ffd222de7785b249ead9c51c536");
+        let middle = string_to_nodehash("d0a361e9022d226ae52f689667bd7d212a19cfe0");
+        let head = string_to_nodehash("a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157");
+
+        let is_ancestor = |ancestor, descendant| {
+            index
+                .is_ancestor(ancestor, descendant)
+                .wait()
+                .expect("is_ancestor failed")
+        };
+        assert!(is_ancestor(root, head));
+        assert!(is_ancestor(middle, head));
+        assert!(is_ancestor(root, middle));
+        assert!(is_ancestor(head, head));
+        assert!(!is_ancestor(head, middle));
+        assert!(!is_ancestor(middle, root));
+    });
+}
+
+#[test]
+fn merge_is_ancestor() {
+    async_unit::tokio_unit_test(|| {
+        let repo = Arc::new(merge_uneven::getrepo(None));
+        let index = SkiplistIndex::new(repo, 100000);
+        let merge = string_to_nodehash("75742e6fc286a359b39a89fdfa437cc7e2a0e1ce");
+        let branch = string_to_nodehash("3cda5c78aa35f0f5b09780d971197b51cad4613a");
+        let other_branch = string_to_nodehash("4f7f3fd428bec1a48f9314414b063c706d9c1aed");
+
+        let is_ancestor = |ancestor, descendant| {
+            index
+                .is_ancestor(ancestor, descendant)
+                .wait()
+                .expect("is_ancestor failed")
+        };
+        assert!(is_ancestor(branch, merge));
+        assert!(is_ancestor(other_branch, merge));
+        assert!(!is_ancestor(branch, other_branch));
+        assert!(!is_ancestor(other_branch, branch));
+    });
+}
+
+#[test]
+fn merge_greatest_common_ancestor() {
+    async_unit::tokio_unit_test(|| {
+        let repo = Arc::new(merge_uneven::getrepo(None));
+        let index = SkiplistIndex::new(repo, 100000);
+
+        let gca = index
+            .greatest_common_ancestor(vec![
+                string_to_nodehash("4f7f3fd428bec1a48f9314414b063c706d9c1aed"),
+                string_to_nodehash("3cda5c78aa35f0f5b09780d971197b51cad4613a"),
+            ])
+            .wait()
+            .expect("greatest_common_ancestor failed");
+        assert_eq!(
+            gca,
+            Some(string_to_nodehash("15c40d0abc36d47fb51c8eaec51ac7aad31f669c"))
+        );
+
+        let gca = index
+            .greatest_common_ancestor(vec![])
+            .wait()
+            .expect("greatest_common_ancestor failed");
+        assert_eq!(gca, None);
+    });
+}
+
+#[test]
+fn merge_common_ancestors() {
+    async_unit::tokio_unit_test(|| {
+        let repo = Arc::new(merge_uneven::getrepo(None));
+        let index = SkiplistIndex::new(repo, 100000);
+
+        let common = index
+            .common_ancestors(vec![
+                string_to_nodehash("75742e6fc286a359b39a89fdfa437cc7e2a0e1ce"),
+                string_to_nodehash("16839021e338500b3cf7c9b871c8a07351697d68"),
+            ])
+            .collect()
+            .wait()
+            .expect("common_ancestors failed");
+        assert_eq!(
+            common,
+            vec![
+                string_to_nodehash("16839021e338500b3cf7c9b871c8a07351697d68"),
+                string_to_nodehash("1d8a907f7b4bf50c6a09c16361e2205047ecc5e5"),
+                string_to_nodehash("3cda5c78aa35f0f5b09780d971197b51cad4613a"),
+                string_to_nodehash("15c40d0abc36d47fb51c8eaec51ac7aad31f669c"),
+            ]
+        );
+    });
+}
+
+#[test]
+fn linear_range() {
+    async_unit::tokio_unit_test(|| {
+        let repo = Arc::new(linear::getrepo(None));
+        let index = SkiplistIndex::new(repo, 100000);
+
+        let range = index
+            .range(
+                string_to_nodehash("d0a361e9022d226ae52f689667bd7d212a19cfe0"),
+                string_to_nodehash("a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157"),
+            )
+            .wait()
+            .expect("range failed");
+        assert_eq!(
+            range,
+            vec![
+                string_to_nodehash("a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157"),
+                string_to_nodehash("0ed509bf086fadcb8a8a5384dc3b550729b0fc17"),
+                string_to_nodehash("eed3a8c0ec67b6a6fe2eb3543334df3f0b4f202b"),
+                string_to_nodehash("cb15ca4a43a59acff5388cea9648c162afde8372"),
+                string_to_nodehash("d0a361e9022d226ae52f689667bd7d212a19cfe0"),
+            ]
+        );
+
+        // The start is not an ancestor of the end
+        let range = index
+            .range(
+                string_to_nodehash("a9473beb2eb03ddb1cccc3fbaeb8a4820f9cd157"),
+                string_to_nodehash("d0a361e9022d226ae52f689667bd7d212a19cfe0"),
+            )
+            .wait()
+            .expect("range failed");
+        assert!(range.is_empty());
+    });
+}
@@ -0,0 +1,20 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::path::PathBuf;
+
+pub use failure::{Error, Result};
+
+#[derive(Debug, Eq, Fail, PartialEq)]
+pub enum ErrorKind {
+    #[fail(display = "Repository requirement {} is not supported by streaming clone", _0)]
+    UnsupportedRequirement(String),
+    #[fail(display = "Repository requirement {} is needed for streaming clone", _0)]
+    MissingRequirement(String),
+    #[fail(display = "No changelog found in {:?}", _0)] MissingChangelog(PathBuf),
+    #[fail(display = "Streaming clone chunk {} is missing from the blobstore", _0)]
+    MissingChunk(String),
+}
@@ -0,0 +1,377 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+//! Snapshots of the changelog and manifest revlogs of a Mercurial repository, used to serve
+//! streaming clones (`stream_out` and bundle2 `stream2` parts) without generating changegroups.
+//!
+//! The revlogs are split into chunks that are stored in the blobstore under their SHA-1, and a
+//! single snapshot blob lists the chunks of every file. Revlogs are append-only, so when the
+//! snapshot is updated only the chunks at the end of the files are new and need to be uploaded.
+
+#![deny(warnings)]
+
+extern crate bincode;
+extern crate bytes;
+#[macro_use]
+extern crate failure_ext as failure;
+extern crate futures;
+#[macro_use]
+extern crate serde_derive;
+
+extern crate blobstore;
+#[macro_use]
+extern crate futures_ext;
+extern crate mercurial_types;
+extern crate mononoke_types;
+
+#[cfg(test)]
+extern crate tempdir;
+
+use std::cmp;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::{future, stream, Future, IntoFuture, Stream};
+
+use blobstore::Blobstore;
+use futures_ext::{BoxFuture, BoxStream, FutureExt, StreamExt};
+use mercurial_types::hash::Context;
+use mononoke_types::BlobstoreBytes;
+
+mod errors;
+
+pub use errors::*;
+
+/// Requirements of the revlogs in a snapshot. The revlogs are sent to the client as they are, so
+/// the client has to support exactly these, which is what the `streamreqs` capability tells it.
+const STREAM_REQUIREMENTS: &[&str] = &["generaldelta", "revlogv1"];
+/// Requirements that only affect how the files of the store are named, which doesn't matter for
+/// the changelog and manifests.
+const IGNORED_REQUIREMENTS: &[&str] = &["dotencode", "fncache", "store"];
+/// Requirement that is added to those of the revlogs when a snapshot is served. Snapshots have no
+/// filelogs, so only clients that fetch files with remotefilelog can use them. Other clients
+/// don't support this requirement and fall back to a regular clone.
+const SHALLOW_REQUIREMENT: &str = "remotefilelog";
+/// Revlogs in a snapshot, in the order in which they are read. The changelog is read first, so
+/// every manifest it refers to is already in the manifest revlog when that is read.
+const REVLOGS: &[&str] = &["00changelog", "00manifest", "00manifesttree"];
+
+const SNAPSHOT_KEY: &str = "streaming_clone.snapshot";
+const CHUNK_SIZE: u64 = 10 * 1024 * 1024;
+const UPLOAD_CONCURRENCY: usize = 10;
+
+/// A file of the store, e.g. `00changelog.i`, split into chunks.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotFile {
+    /// Path of the file relative to the store, which is also its name on the wire.
+    pub name: String,
+    pub size: u64,
+    /// Blobstore keys of the chunks of the file, in order.
+    pub chunks: Vec<String>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub requirements: Vec<String>,
+    pub files: Vec<SnapshotFile>,
+}
+
+impl Snapshot {
+    /// Total size of the files, as announced to the client before they are sent.
+    pub fn bytecount(&self) -> u64 {
+        self.files.iter().map(|file| file.size).sum()
+    }
+
+    /// Requirements a client must support to clone from the snapshot, as advertised in
+    /// `streamreqs` and sent in `stream2` parts.
+    pub fn client_requirements(&self) -> Vec<String> {
+        let mut requirements = self.requirements.clone();
+        requirements.push(SHALLOW_REQUIREMENT.to_string());
+        requirements.sort();
+        requirements
+    }
+}
+
+#[derive(Clone)]
+pub struct StreamingClone {
+    blobstore: Arc<Blobstore>,
+}
+
+impl StreamingClone {
+    pub fn new(blobstore: Arc<Blobstore>) -> Self {
+        StreamingClone { blobstore }
+    }
+
+    /// Fetch the latest snapshot, if one was ever created.
+    pub fn get_snapshot(&self) -> BoxFuture<Option<Snapshot>, Error> {
+        self.blobstore
+            .get(SNAPSHOT_KEY.to_string())
+            .and_then(|blob| match blob {
+                Some(blob) => Ok(Some(bincode::deserialize(blob.as_bytes().as_ref())?)),
+                None => Ok(None),
+            })
+            .boxify()
+    }
+
+    /// Stream the content of a file of a snapshot, one chunk at a time.
+    pub fn stream_file(&self, file: &SnapshotFile) -> BoxStream<Bytes, Error> {
+        let blobstore = self.blobstore.clone();
+
+        stream::iter_ok::<_, Error>(file.chunks.clone())
+            .and_then(move |key| {
+                blobstore.get(key.clone()).and_then(move |blob| match blob {
+                    Some(blob) => Ok(blob.into_bytes()),
+                    None => Err(ErrorKind::MissingChunk(key).into()),
+                })
+            })
+            .boxify()
+    }
+
+    /// Snapshot the revlogs of the Mercurial repository at `repo_path` (the `.hg` directory)
+    /// and make the snapshot the one that is served. Chunks that are already in the blobstore
+    /// aren't uploaded again.
+    ///
+    /// The repository must not be written to while this is running, e.g. snapshot a backup or
+    /// a mirror that is paused for the duration.
+    pub fn update_snapshot<P: AsRef<Path>>(&self, repo_path: P) -> BoxFuture<Snapshot, Error> {
+        let repo_path = repo_path.as_ref();
+        let requirements = try_boxfuture!(read_requirements(repo_path));
+        let store_path = repo_path.join("store");
+
+        if !store_path.join("00changelog.i").exists() {
+            return future::err(ErrorKind::MissingChangelog(store_path).into()).boxify();
+        }
+
+        let names = REVLOGS.iter().flat_map(|revlog| {
+            // The index is read before the data, so the data covers every revision in the index
+            vec![format!("{}.i", revlog), format!("{}.d", revlog)]
+        });
+        let files: Vec<_> = names
+            .filter(|name| store_path.join(name).exists())
+            .collect();
+
+        let this = self.clone();
+        stream::iter_ok::<_, Error>(files)
+            .and_then(move |name| this.upload_file(store_path.join(&name), name))
+            .collect()
+            .and_then({
+                let blobstore = self.blobstore.clone();
+                move |files| {
+                    let snapshot = Snapshot {
+                        requirements,
+                        files,
+                    };
+                    // Only written once all the chunks are uploaded, so readers never see a
+                    // snapshot with missing chunks
+                    bincode::serialize(&snapshot)
+                        .map_err(Error::from)
+                        .into_future()
+                        .and_then(move |blob| {
+                            let blob = BlobstoreBytes::from_bytes(Bytes::from(blob));
+                            blobstore.put(SNAPSHOT_KEY.to_string(), blob)
+                        })
+                        .map(move |()| snapshot)
+                }
+            })
+            .boxify()
+    }
+
+    fn upload_file(&self, path: PathBuf, name: String) -> BoxFuture<SnapshotFile, Error> {
+        let size = try_boxfuture!(fs::metadata(&path)).len();
+        let blobstore = self.blobstore.clone();
+
+        read_chunks(path, size)
+            .map(move |chunk| upload_chunk(blobstore.clone(), chunk))
+            .buffered(UPLOAD_CONCURRENCY)
+            .collect()
+            .map(move |chunks| SnapshotFile { name, size, chunks })
+            .boxify()
+    }
+}
+
+/// Read `.hg/requires` and check that the revlogs can be streamed to clients that support
+/// `STREAM_REQUIREMENTS`.
+fn read_requirements(repo_path: &Path) -> Result<Vec<String>> {
+    let file = File::open(repo_path.join("requires"))?;
+    let mut requirements = vec![];
+    for line in BufReader::new(file).lines() {
+        let requirement = line?;
+        if STREAM_REQUIREMENTS.contains(&requirement.as_str()) {
+            requirements.push(requirement);
+        } else if !IGNORED_REQUIREMENTS.contains(&requirement.as_str()) {
+            bail_err!(ErrorKind::UnsupportedRequirement(requirement));
+        }
+    }
+
+    for requirement in STREAM_REQUIREMENTS {
+        if !requirements.iter().any(|req| req == *requirement) {
+            bail_err!(ErrorKind::MissingRequirement(requirement.to_string()));
+        }
+    }
+
+    requirements.sort();
+    Ok(requirements)
+}
+
+/// Read the first `size` bytes of a file in chunks of `CHUNK_SIZE`. Anything appended to the file
+/// after its size was taken is left out.
+fn read_chunks(path: PathBuf, size: u64) -> BoxStream<Bytes, Error> {
+    let file = try_boxstream!(File::open(path));
+
+    stream::unfold((file, size), |(mut file, remaining)| {
+        if remaining == 0 {
+            return None;
+        }
+
+        let mut chunk = vec![0; cmp::min(remaining, CHUNK_SIZE) as usize];
+        let res = match file.read_exact(&mut chunk) {
+            Ok(()) => {
+                let remaining = remaining - chunk.len() as u64;
+                Ok((Bytes::from(chunk), (file, remaining)))
+            }
+            Err(err) => Err(Error::from(err)),
+        };
+        Some(res)
+    }).boxify()
+}
+
+/// Store a chunk under its SHA-1, unless it is already there, and return its key.
+fn upload_chunk(blobstore: Arc<Blobstore>, chunk: Bytes) -> BoxFuture<String, Error> {
+    let mut context = Context::new();
+    context.update(&chunk);
+    let key = format!("streaming_clone.chunk.sha1.{}", context.finish().to_hex());
+
+    blobstore
+        .is_present(key.clone())
+        .and_then(move |present| {
+            if present {
+                future::ok(key).boxify()
+            } else {
+                blobstore
+                    .put(key.clone(), BlobstoreBytes::from_bytes(chunk))
+                    .map(move |()| key)
+                    .boxify()
+            }
+        })
+        .boxify()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Write;
+
+    use blobstore::EagerMemblob;
+    use tempdir::TempDir;
+
+    fn write_file(path: PathBuf, content: &[u8]) {
+        File::create(path)
+            .and_then(|mut file| file.write_all(content))
+            .expect("cannot write file");
+    }
+
+    fn create_repo(changelog: &[u8]) -> TempDir {
+        let dir = TempDir::new("streaming_clone").expect("cannot create temp dir");
+        fs::create_dir(dir.path().join("store")).unwrap();
+        write_file(
+            dir.path().join("requires"),
+            b"dotencode\nfncache\ngeneraldelta\nrevlogv1\nstore\n",
+        );
+        write_file(dir.path().join("store").join("00changelog.i"), changelog);
+        write_file(dir.path().join("store").join("00manifest.i"), b"manifest");
+        dir
+    }
+
+    fn streamed(streaming_clone: &StreamingClone, file: &SnapshotFile) -> Vec<u8> {
+        let chunks = streaming_clone.stream_file(file).collect().wait().unwrap();
+        chunks.concat()
+    }
+
+    #[test]
+    fn test_update_and_stream() {
+        let repo = create_repo(b"changelog");
+        let streaming_clone = StreamingClone::new(Arc::new(EagerMemblob::new()));
+
+        assert_eq!(streaming_clone.get_snapshot().wait().unwrap(), None);
+
+        let snapshot = streaming_clone.update_snapshot(repo.path()).wait().unwrap();
+        assert_eq!(
+            snapshot.requirements,
+            vec!["generaldelta".to_string(), "revlogv1".to_string()]
+        );
+        let names: Vec<_> = snapshot.files.iter().map(|file| file.name.as_str()).collect();
+        assert_eq!(names, vec!["00changelog.i", "00manifest.i"]);
+        assert_eq!(snapshot.bytecount(), 17);
+        assert_eq!(
+            snapshot.client_requirements(),
+            vec![
+                "generaldelta".to_string(),
+                "remotefilelog".to_string(),
+                "revlogv1".to_string(),
+            ]
+        );
+
+        let stored = streaming_clone.get_snapshot().wait().unwrap();
+        assert_eq!(stored, Some(snapshot.clone()));
+        assert_eq!(streamed(&streaming_clone, &snapshot.files[0]), b"changelog");
+        assert_eq!(streamed(&streaming_clone, &snapshot.files[1]), b"manifest");
+    }
+
+    #[test]
+    fn test_update_appended() {
+        let repo = create_repo(b"changelog");
+        let streaming_clone = StreamingClone::new(Arc::new(EagerMemblob::new()));
+        let first = streaming_clone.update_snapshot(repo.path()).wait().unwrap();
+
+        write_file(
+            repo.path().join("store").join("00changelog.i"),
+            b"changelog, appended",
+        );
+        let second = streaming_clone.update_snapshot(repo.path()).wait().unwrap();
+
+        assert_eq!(second.files[0].size, 19);
+        assert_eq!(
+            streamed(&streaming_clone, &second.files[0]),
+            b"changelog, appended"
+        );
+        // The manifest didn't change, so it still refers to the same chunk
+        assert_eq!(first.files[1], second.files[1]);
+    }
+
+    #[test]
+    fn test_unsupported_requirement() {
+        let repo = create_repo(b"changelog");
+        write_file(repo.path().join("requires"), b"lz4revlog\nrevlogv1\nstore\n");
+        let streaming_clone = StreamingClone::new(Arc::new(EagerMemblob::new()));
+
+        let err = streaming_clone
+            .update_snapshot(repo.path())
+            .wait()
+            .expect_err("lz4revlog should not be streamable");
+        match err.downcast::<ErrorKind>() {
+            Ok(ErrorKind::UnsupportedRequirement(requirement)) => {
+                assert_eq!(requirement, "lz4revlog")
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_missing_chunk() {
+        let streaming_clone = StreamingClone::new(Arc::new(EagerMemblob::new()));
+        let file = SnapshotFile {
+            name: "00changelog.i".to_string(),
+            size: 1,
+            chunks: vec!["streaming_clone.chunk.sha1.missing".to_string()],
+        };
+
+        assert!(streaming_clone.stream_file(&file).collect().wait().is_err());
+    }
+}
@@ -41,6 +41,7 @@ if __name__ == '__main__':
 
 #![deny(warnings)]
 
+extern crate bonsai_hg_mapping;
 extern crate bookmarks;
 extern crate changesets;
 extern crate dbbookmarks;
@@ -58,9 +59,10 @@ extern crate slog;
 use std::str::FromStr;
 
 use ascii::AsciiString;
+use bonsai_hg_mapping::SqliteBonsaiHgMapping;
 use bookmarks::{Bookmark, Bookmarks};
 use changesets::{Changesets, ChangesetInsert, SqliteChangesets};
-use dbbookmarks::SqliteDbBookmarks;
+use dbbookmarks::{SqliteDbBookmarks, SqliteDbScratchBookmarks};
 use dieselfilenodes::SqliteFilenodes;
 use mercurial_types::{HgChangesetId, HgNodeHash, RepositoryId};
 use mononoke_types::BlobstoreBytes;
@@ -73,11 +75,16 @@ use std::sync::Arc;
 pub fn getrepo(logger: Option<Logger>) -> BlobRepo {
     let bookmarks = Arc::new(SqliteDbBookmarks::in_memory()
         .expect("cannot create in-memory bookmarks table"));
+    let scratch_bookmarks = Arc::new(SqliteDbScratchBookmarks::in_memory()
+        .expect("cannot create in-memory scratch bookmarks table"));
     let blobs = Arc::new(EagerMemblob::new());
+    let lfs_blobs = Arc::new(EagerMemblob::new());
     let filenodes = Arc::new(SqliteFilenodes::in_memory()
         .expect("cannot create in-memory filenodes"));
     let changesets = Arc::new(SqliteChangesets::in_memory()
         .expect("cannot create in-memory changeset table"));
+    let bonsai_hg_mapping = Arc::new(SqliteBonsaiHgMapping::in_memory()
+        .expect("cannot create in-memory bonsai hg mapping table"));
     let repo_id = RepositoryId::new(1);
     let mut book_txn = bookmarks.create_transaction(&repo_id);
 
@@ -161,7 +168,17 @@ pub fn getrepo(logger: Option<Logger>) -> BlobRepo {
             """
     book_txn.commit().wait().expect("Bookmark heads creation failed");
     let logger = logger.unwrap_or(Logger::root(Discard {}.ignore_res(), o!()));
-    BlobRepo::new(logger, bookmarks, blobs, filenodes, changesets, repo_id)
+    BlobRepo::new(
+        logger,
+        bookmarks,
+        scratch_bookmarks,
+        blobs,
+        lfs_blobs,
+        filenodes,
+        changesets,
+        bonsai_hg_mapping,
+        repo_id,
+    )
 }
 """
         )
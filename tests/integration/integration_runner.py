@@ -23,7 +23,9 @@ from .third_party import hg_run_tests
 
 TESTDIR_PATH = 'scm/mononoke/tests/integration'
 
+MONONOKE_BLOBEXPORT_TARGET = '//scm/mononoke:blobexport'
 MONONOKE_BLOBIMPORT_TARGET = '//scm/mononoke:blobimport'
+MONONOKE_GITIMPORT_TARGET = '//scm/mononoke:gitimport'
 MONONOKE_APISERVER_TARGET = '//scm/mononoke/apiserver:apiserver'
 MONONOKE_EDEN_SERVER_TARGET = '//scm/mononoke/eden_server:eden_server'
 DUMMYSSH_TARGET = '//scm/mononoke/tests/integration:dummyssh'
@@ -112,7 +114,9 @@ def run(
         output = None
     _fp, xunit_output = tempfile.mkstemp(dir=output)
 
+    add_to_environ('MONONOKE_BLOBEXPORT', MONONOKE_BLOBEXPORT_TARGET)
     add_to_environ('MONONOKE_BLOBIMPORT', MONONOKE_BLOBIMPORT_TARGET)
+    add_to_environ('MONONOKE_GITIMPORT', MONONOKE_GITIMPORT_TARGET)
     add_to_environ(
         'DUMMYSSH', DUMMYSSH_TARGET, pathutils.BuildRuleTypes.PYTHON_BINARY
     )
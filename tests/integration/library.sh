@@ -23,6 +23,15 @@ function mononoke {
   echo $! >> "$DAEMON_PIDS"
 }
 
+# Start Mononoke reading the repo configs from the working copy of the config repo, instead of
+# from its RocksDb version
+function mononoke_config_dir {
+  export MONONOKE_SOCKET
+  MONONOKE_SOCKET=$(get_free_socket)
+  "$MONONOKE_SERVER" "$@" --debug --listening-host-port 127.0.0.1:"$MONONOKE_SOCKET" --config-dir "$TESTTMP/mononoke-config" >> "$TESTTMP/mononoke.out" 2>&1 &
+  echo $! >> "$DAEMON_PIDS"
+}
+
 # Wait until a Mononoke server is available for this repo.
 function wait_for_mononoke {
   local attempts=150
@@ -53,6 +62,21 @@ function wait_for_mononoke_cache_warmup {
   fi
 }
 
+# Wait until Mononoke logs a line matching the given pattern
+function wait_for_mononoke_log {
+  local attempts=150
+  for _ in $(seq 1 $attempts); do
+    grep -q "$1" "$TESTTMP/mononoke.out" && break
+    sleep 0.1
+  done
+
+  if ! grep -q "$1" "$TESTTMP/mononoke.out"; then
+    echo "Mononoke did not log $1" >&2
+    cat "$TESTTMP/mononoke.out"
+    exit 1
+  fi
+}
+
 function setup_common_config {
     setup_config_repo
   cat >> "$HGRCPATH" <<EOF
@@ -93,6 +117,35 @@ bookmark="$CACHE_WARMUP_BOOKMARK"
 CONFIG
   fi
 
+  if [[ -v LFS_THRESHOLD ]]; then
+    cat >> repos/repo <<CONFIG
+[lfs]
+threshold=$LFS_THRESHOLD
+CONFIG
+    if [[ -v LFS_SEND_POINTERS ]]; then
+      echo "send_pointers=true" >> repos/repo
+    fi
+  fi
+
+  if [[ -v CHUNKING_THRESHOLD ]]; then
+    cat >> repos/repo <<CONFIG
+[chunking]
+threshold=$CHUNKING_THRESHOLD
+chunk_size=${CHUNK_SIZE:-4}
+CONFIG
+    if [[ -v STREAM_THRESHOLD ]]; then
+      echo "stream_threshold=$STREAM_THRESHOLD" >> repos/repo
+    fi
+  fi
+
+  if [[ -v PUBLISHING_BOOKMARK ]]; then
+    cat >> repos/repo <<CONFIG
+[[bookmarks]]
+name="$PUBLISHING_BOOKMARK"
+publishing=true
+CONFIG
+  fi
+
   hg add -q repos
   hg ci -ma
   hg backfilltree
@@ -110,6 +163,16 @@ function blobimport {
   $MONONOKE_BLOBIMPORT --repo_id 0 --blobstore rocksdb "$@" >> "$TESTTMP/blobimport.out" 2>&1
 }
 
+function gitimport {
+  reponame=$2
+  mkdir -p "$reponame"
+  $MONONOKE_GITIMPORT --repo_id 0 --blobstore rocksdb "$@" >> "$TESTTMP/gitimport.out" 2>&1
+}
+
+function blobexport {
+  $MONONOKE_BLOBEXPORT --repo_id 0 --blobstore rocksdb "$@" >> "$TESTTMP/blobexport.out" 2>&1
+}
+
 function apiserver {
   $MONONOKE_APISERVER --config-path "$TESTTMP/mononoke-config-rocks" --config-bookmark "local_master"  "$@" >> "$TESTTMP/apiserver.out" 2>&1 &
   echo $! >> "$DAEMON_PIDS"
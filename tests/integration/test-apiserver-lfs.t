@@ -0,0 +1,69 @@
+  $ CACHEDIR=$PWD/cachepath
+  $ . $TESTDIR/library.sh
+
+setup config repo
+  $ setup_common_config
+  $ cd $TESTTMP
+
+setup testing repo for mononoke
+  $ hg init repo-hg
+  $ cd repo-hg
+  $ setup_hg_server
+  $ hg debugdrawdag <<EOF
+  > A
+  > EOF
+  $ hg bookmark master_bookmark -r tip
+
+import testing repo to mononoke
+  $ cd ..
+  $ blobimport repo-hg/.hg repo
+
+starts api server
+  $ apiserver -p 0
+
+  $ for i in $(seq 1 40); do
+  > PORT=$(cat $TESTTMP/apiserver.out | grep "Listening to" | grep -Pzo "(\\d+)\$") && break
+  > sleep 0.1
+  > done
+
+  $ OID=372920552fbf4fa7c97393b749bf061342eb1c1d13d743aca3f499794b7ef9b2
+  $ printf "large file contents\n" > large
+  $ printf "not what was promised\n" > other
+
+an object that isn't stored can't be downloaded, but can be uploaded
+  $ curl -X POST -H "Content-Type: application/vnd.git-lfs+json" http://127.0.0.1:$PORT/repo/lfs/objects/batch -d "{\"operation\":\"download\",\"objects\":[{\"oid\":\"$OID\",\"size\":20}]}" 2> /dev/null
+  {"transfer":"basic","objects":[{"oid":"372920552fbf4fa7c97393b749bf061342eb1c1d13d743aca3f499794b7ef9b2","size":20,"error":{"code":404,"message":"object 372920552fbf4fa7c97393b749bf061342eb1c1d13d743aca3f499794b7ef9b2 not found"}}]} (no-eol)
+
+  $ curl -X POST -H "Content-Type: application/vnd.git-lfs+json" http://127.0.0.1:$PORT/repo/lfs/objects/batch -d "{\"operation\":\"upload\",\"objects\":[{\"oid\":\"$OID\",\"size\":20}]}" 2> /dev/null
+  {"transfer":"basic","objects":[{"oid":"372920552fbf4fa7c97393b749bf061342eb1c1d13d743aca3f499794b7ef9b2","size":20,"actions":{"upload":{"href":"http://127.0.0.1:*/repo/lfs/upload/372920552fbf4fa7c97393b749bf061342eb1c1d13d743aca3f499794b7ef9b2"}}}]} (no-eol) (glob)
+
+  $ curl -i http://127.0.0.1:$PORT/repo/lfs/download/$OID 2> /dev/null | grep 404
+  HTTP/1.1 404 Not Found\r (esc)
+
+contents that don't match the oid are rejected
+  $ curl -i -X PUT --data-binary @other http://127.0.0.1:$PORT/repo/lfs/upload/$OID 2> /dev/null | grep 400
+  HTTP/1.1 400 Bad Request\r (esc)
+
+  $ curl -i -X PUT --data-binary @large http://127.0.0.1:$PORT/repo/lfs/upload/invalid 2> /dev/null | grep 400
+  HTTP/1.1 400 Bad Request\r (esc)
+
+upload the object
+  $ curl -i -X PUT --data-binary @large http://127.0.0.1:$PORT/repo/lfs/upload/$OID 2> /dev/null | grep 200
+  HTTP/1.1 200 OK\r (esc)
+
+a stored object doesn't have to be uploaded again, and can be downloaded
+  $ curl -X POST -H "Content-Type: application/vnd.git-lfs+json" http://127.0.0.1:$PORT/repo/lfs/objects/batch -d "{\"operation\":\"upload\",\"objects\":[{\"oid\":\"$OID\",\"size\":20}]}" 2> /dev/null
+  {"transfer":"basic","objects":[{"oid":"372920552fbf4fa7c97393b749bf061342eb1c1d13d743aca3f499794b7ef9b2","size":20}]} (no-eol)
+
+  $ curl -X POST -H "Content-Type: application/vnd.git-lfs+json" http://127.0.0.1:$PORT/repo/lfs/objects/batch -d "{\"operation\":\"download\",\"objects\":[{\"oid\":\"$OID\",\"size\":20}]}" 2> /dev/null
+  {"transfer":"basic","objects":[{"oid":"372920552fbf4fa7c97393b749bf061342eb1c1d13d743aca3f499794b7ef9b2","size":20,"actions":{"download":{"href":"http://127.0.0.1:*/repo/lfs/download/372920552fbf4fa7c97393b749bf061342eb1c1d13d743aca3f499794b7ef9b2"}}}]} (no-eol) (glob)
+
+  $ curl http://127.0.0.1:$PORT/repo/lfs/download/$OID 2> /dev/null
+  large file contents
+
+invalid requests
+  $ curl -X POST -H "Content-Type: application/vnd.git-lfs+json" http://127.0.0.1:$PORT/repo/lfs/objects/batch -d "{\"operation\":\"download\",\"objects\":[{\"oid\":\"invalid\",\"size\":20}]}" 2> /dev/null
+  {"transfer":"basic","objects":[{"oid":"invalid","size":20,"error":{"code":422,"message":"invalid oid invalid"}}]} (no-eol)
+
+  $ curl -i -X POST -H "Content-Type: application/vnd.git-lfs+json" http://127.0.0.1:$PORT/repo/lfs/objects/batch -d "{\"operation\":\"verify\",\"objects\":[]}" 2> /dev/null | grep 400
+  HTTP/1.1 400 Bad Request\r (esc)
@@ -16,6 +16,7 @@ setup testing repo for mononoke
   > |
   > A
   > EOF
+  $ hg bookmark master_bookmark -r tip
 
 import testing repo to mononoke
   $ cd ..
@@ -29,8 +30,67 @@ starts api server
   > sleep 0.1
   > done
 
-  $ curl http://127.0.0.1:$PORT/repo/blob/test 2> /dev/null
-  success! (no-eol)
+get the raw content of a file
+  $ curl http://127.0.0.1:$PORT/repo/raw/26805aba1e600a82e93661149f2313866a221a7b/B 2> /dev/null
+  B (no-eol)
+
+  $ curl -i http://127.0.0.1:$PORT/repo/raw/426bada5c67598ca65036d57d9e4b64b0c1ce7a0/B 2> /dev/null | grep 404
+  HTTP/1.1 404 Not Found\r (esc)
+
+  $ curl -i http://127.0.0.1:$PORT/repo/raw/invalidhash/B 2> /dev/null | grep 400
+  HTTP/1.1 400 Bad Request\r (esc)
+
+  $ curl -i http://127.0.0.1:$PORT/repo/raw/26805aba1e600a82e93661149f2313866a221a7b/ 2> /dev/null | grep 400
+  HTTP/1.1 400 Bad Request\r (esc)
+
+get the content of a file by its filenode hash
+  $ curl http://127.0.0.1:$PORT/repo/blob/005d992c5dcf32993668f7cede29d296c494a5d9 2> /dev/null
+  A (no-eol)
+
+  $ curl -i http://127.0.0.1:$PORT/repo/blob/0000000000000000000000000000000000000001 2> /dev/null | grep 404
+  HTTP/1.1 404 Not Found\r (esc)
+
+  $ curl -i http://127.0.0.1:$PORT/repo/blob/test 2> /dev/null | grep 400
+  HTTP/1.1 400 Bad Request\r (esc)
+
+list a directory
+  $ curl http://127.0.0.1:$PORT/repo/tree/26805aba1e600a82e93661149f2313866a221a7b/ 2> /dev/null
+  [{"name":"A","type":"file","hash":"005d992c5dcf32993668f7cede29d296c494a5d9"},{"name":"B","type":"file","hash":"35e7525ce3a48913275d7061dd9a867ffef1e34d"},{"name":"C","type":"file","hash":"a2e456504a5e61f763f1a0b36a6c247c7541b2b3"}] (no-eol)
+
+  $ curl -i http://127.0.0.1:$PORT/repo/tree/26805aba1e600a82e93661149f2313866a221a7b/A 2> /dev/null | grep 400
+  HTTP/1.1 400 Bad Request\r (esc)
+
+get changeset metadata
+  $ curl http://127.0.0.1:$PORT/repo/changeset/26805aba1e600a82e93661149f2313866a221a7b 2> /dev/null
+  {"hash":"26805aba1e600a82e93661149f2313866a221a7b","parents":["112478962961147124edd43549aedd1a335e44bf"],"manifest":"7c9b4fd8b49377e2fead2e9610bb8db910a98c53","author":"test","timestamp":0,"tz_offset":0,"comment":"C","files":["C"]} (no-eol)
+
+  $ curl -i http://127.0.0.1:$PORT/repo/changeset/0000000000000000000000000000000000000001 2> /dev/null | grep 404
+  HTTP/1.1 404 Not Found\r (esc)
+
+list bookmarks
+  $ curl http://127.0.0.1:$PORT/repo/bookmarks 2> /dev/null
+  [{"name":"master_bookmark","changeset":"26805aba1e600a82e93661149f2313866a221a7b"}] (no-eol)
+
+get the history of a file
+  $ curl http://127.0.0.1:$PORT/repo/history/B 2> /dev/null
+  [{"filenode":"35e7525ce3a48913275d7061dd9a867ffef1e34d","p1":null,"p2":null,"linknode":"112478962961147124edd43549aedd1a335e44bf","copyfrom":null}] (no-eol)
+
+  $ curl -i http://127.0.0.1:$PORT/repo/history/D 2> /dev/null | grep 404
+  HTTP/1.1 404 Not Found\r (esc)
+
+query a revset
+  $ curl http://127.0.0.1:$PORT/repo/revset/112478::master_bookmark 2> /dev/null
+  ["26805aba1e600a82e93661149f2313866a221a7b","112478962961147124edd43549aedd1a335e44bf"] (no-eol)
+
+  $ curl -i http://127.0.0.1:$PORT/repo/revset/not\(master_bookmark\) 2> /dev/null | grep 400
+  HTTP/1.1 400 Bad Request\r (esc)
+
+  $ curl -i http://127.0.0.1:$PORT/repo/revset/$(printf '(%.0s' $(seq 1 100))A 2> /dev/null | grep 400
+  HTTP/1.1 400 Bad Request\r (esc)
+
+unknown repos and routes
+  $ curl -i http://127.0.0.1:$PORT/sup/bookmarks 2> /dev/null | grep 404
+  HTTP/1.1 404 Not Found\r (esc)
 
   $ curl -i http://127.0.0.1:$PORT//blob/test 2> /dev/null | grep 404
   HTTP/1.1 404 Not Found\r (esc)
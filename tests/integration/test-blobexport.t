@@ -0,0 +1,66 @@
+  $ . $TESTDIR/library.sh
+
+setup configuration
+  $ setup_common_config
+
+setup repo
+
+  $ hginit_treemanifest repo-hg
+  $ cd repo-hg
+  $ mkdir dir
+  $ echo "a" > a
+  $ echo "b" > dir/b
+  $ hg addremove && hg ci -q -ma
+  adding a
+  adding dir/b
+  $ echo "a2" > a
+  $ hg cp dir/b dir/c
+  $ hg ci -q -mb
+  $ hg bookmark master_bookmark -r tip
+  $ hg log -T '{node} {bookmarks}\n' > $TESTTMP/original.log
+  $ cd $TESTTMP
+
+blobimport and export it again
+
+  $ blobimport repo-hg/.hg repo
+  $ blobexport repo-export repo
+  $ hg verify -R repo-export
+  checking changesets
+  checking manifests
+  crosschecking files in changesets and manifests
+  checking files
+  3 files, 2 changesets, 4 total revisions
+  $ hg files -R repo-export -r master_bookmark
+  a
+  dir/b
+  dir/c
+
+the tree manifests are exported next to the flat ones
+
+  $ ls repo-export/.hg/store repo-export/.hg/store/meta/dir
+  repo-export/.hg/store:
+  00changelog.d
+  00changelog.i
+  00manifest.d
+  00manifest.i
+  00manifesttree.d
+  00manifesttree.i
+  data
+  fncache
+  meta
+  
+  repo-export/.hg/store/meta/dir:
+  00manifest.d
+  00manifest.i
+  $ hg log -R repo-export -T '{node} {bookmarks}\n' > $TESTTMP/exported.log
+  $ diff original.log exported.log
+  $ hg cat -R repo-export -r master_bookmark a dir/c
+  a2
+  b
+
+the exported repo imports to the same hashes
+
+  $ blobimport repo-export/.hg repo-reimport
+  $ blobexport repo-reexport repo-reimport
+  $ hg log -R repo-reexport -T '{node} {bookmarks}\n' > $TESTTMP/reexported.log
+  $ diff original.log reexported.log
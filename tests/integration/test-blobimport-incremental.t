@@ -0,0 +1,63 @@
+  $ . $TESTDIR/library.sh
+
+setup configuration
+  $ setup_common_config
+  $ cd $TESTTMP
+
+setup repo with a bookmark that will move and one that will be deleted
+
+  $ hginit_treemanifest repo-hg
+  $ cd repo-hg
+  $ echo a > a && hg add a && hg ci -m a
+  $ echo b > b && hg add b && hg ci -m b
+  $ hg bookmark master_bookmark -r tip
+  $ hg bookmark old_bookmark -r 0
+
+blobimport
+
+  $ cd $TESTTMP
+  $ blobimport repo-hg/.hg repo
+  $ grep -c "setting bookmark" $TESTTMP/blobimport.out
+  2
+
+add a commit, move a bookmark and delete the other one
+
+  $ cd repo-hg
+  $ echo c > c && hg add c && hg ci -m c
+  $ hg bookmark -f master_bookmark -r tip
+  $ hg bookmark -d old_bookmark
+  $ cd $TESTTMP
+
+An incremental import moves the bookmark, but doesn't delete the other one unless asked to
+
+  $ rm $TESTTMP/blobimport.out
+  $ blobimport repo-hg/.hg repo --incremental
+  $ grep -c "setting bookmark" $TESTTMP/blobimport.out
+  1
+  $ grep -c "is not in the revlog repo, but is not deleted without --delete-bookmarks" $TESTTMP/blobimport.out
+  1
+  $ grep -c "deleting bookmark" $TESTTMP/blobimport.out
+  [1]
+
+  $ rm $TESTTMP/blobimport.out
+  $ blobimport repo-hg/.hg repo --incremental --delete-bookmarks
+  $ grep -c "setting bookmark" $TESTTMP/blobimport.out
+  [1]
+  $ grep -c "deleting bookmark" $TESTTMP/blobimport.out
+  1
+
+start mononoke and check the bookmarks and the new commit
+
+  $ mononoke
+  $ wait_for_mononoke $TESTTMP/repo
+  $ hgmn debugpushkey ssh://user@dummy/repo bookmarks > bookmarks
+  $ cut -f1 bookmarks
+  master_bookmark
+  $ grep -c "$(hg log -R repo-hg -r master_bookmark -T '{node}')" bookmarks
+  1
+
+  $ hgclone_treemanifest ssh://user@dummy/repo-hg repo-pull --noupdate -r 0
+  $ cd repo-pull
+  $ hgmn pull -q
+  $ hg log -r master_bookmark -T '{desc}\n'
+  c
@@ -0,0 +1,87 @@
+  $ . $TESTDIR/library.sh
+
+setup configuration
+  $ setup_common_config
+  $ cd $TESTTMP
+  $ cat >> mononoke-config/repos/repo <<EOF
+  > [[bookmarks]]
+  > name="master_bookmark"
+  > only_fast_forward=true
+  > [[bookmarks]]
+  > name="release"
+  > allow_delete=false
+  > [[bookmarks]]
+  > name="protected"
+  > allowed_users=["someone_else"]
+  > [[bookmarks]]
+  > name="mine"
+  > allowed_users=["$(id -un)"]
+  > EOF
+
+setup repo
+
+  $ hginit_treemanifest repo-hg
+  $ cd repo-hg
+  $ echo a > a && hg add a && hg ci -m a
+  $ echo b > b && hg add b && hg ci -m b
+  $ hg bookmark master_bookmark -r tip
+  $ hg bookmark release -r tip
+  $ hg bookmark protected -r tip
+  $ hg bookmark mine -r tip
+
+blobimport
+
+  $ cd $TESTTMP
+  $ blobimport repo-hg/.hg repo
+
+  $ hgclone_treemanifest ssh://user@dummy/repo-hg repo-push
+  $ cd repo-push
+  $ enableextension remotenames
+
+start mononoke
+
+  $ mononoke_config_dir
+  $ wait_for_mononoke $TESTTMP/repo
+
+Fast-forwarding master_bookmark is fine
+  $ hg up -q master_bookmark
+  $ echo c > c && hg add c && hg ci -m c
+  $ hgmn push -q -r . --to master_bookmark
+
+Moving it anywhere else is rejected
+  $ hgmn push -q -r 0 --to master_bookmark --force > /dev/null 2>&1 || true
+  $ grep -c "Bookmark master_bookmark can only be fast-forwarded" $TESTTMP/mononoke.out
+  1
+
+Deleting release is rejected
+  $ hgmn push -q --delete release > /dev/null 2>&1 || true
+  $ grep -c "Bookmark release can't be deleted" $TESTTMP/mononoke.out
+  1
+
+Only the allowed users can move protected and mine
+  $ hgmn push -q -r . --to protected > /dev/null 2>&1 || true
+  $ grep -c "$(id -un) is not allowed to move bookmark protected" $TESTTMP/mononoke.out
+  1
+  $ hgmn push -q -r . --to mine
+
+None of the rejected pushes moved their bookmarks
+  $ hgmn pull -q
+  $ hg log -T '{desc} {remotenames}\n'
+  c default/master_bookmark default/mine
+  b default/protected default/release
+  a
+
+Pushrebasing onto protected is rejected before anything is rebased
+  $ cd $TESTTMP
+  $ hgclone_treemanifest ssh://user@dummy/repo-hg repo-pushrebase
+  $ cd repo-pushrebase
+  $ enableextension pushrebase
+  $ enableextension remotenames
+  $ echo d > d && hg add d && hg ci -m d
+  $ hgmn push -q -r . --to protected > /dev/null 2>&1
+  [255]
+  $ grep -c "$(id -un) is not allowed to move bookmark protected" $TESTTMP/mononoke.out
+  2
+  $ grep -c "pushrebased" $TESTTMP/mononoke.out
+  0
+  [1]
@@ -0,0 +1,58 @@
+  $ . $TESTDIR/library.sh
+
+setup configuration
+  $ setup_common_config
+  $ cd $TESTTMP
+
+setup repo with two named branches
+
+  $ hginit_treemanifest repo-hg
+  $ cd repo-hg
+  $ echo a > a && hg add a && hg ci -m a
+  $ hg branch -q stable
+  $ echo b > b && hg add b && hg ci -m b
+  $ hg up -q default
+  $ echo c > c && hg add c && hg ci -m c
+
+create bookmarks on the heads of both branches
+
+  $ hg bookmark master_bookmark -r 2
+  $ hg bookmark stable_bookmark -r 1
+
+  $ cd $TESTTMP
+
+blobimport
+
+  $ blobimport repo-hg/.hg repo
+
+start mononoke
+
+  $ mononoke
+  $ wait_for_mononoke $TESTTMP/repo
+
+Clone with stock hg, i.e. without remotefilelog and treemanifest. Cloning a single branch
+needs the branchmap of the server.
+  $ hgmn clone -q --noupdate --config extensions.remotefilelog=! ssh://user@dummy/repo repo-stock -b stable
+  $ hg log -R repo-stock -T '{desc} {branch}\n'
+  b stable
+  a default
+
+Clone everything
+  $ hgmn clone -q --noupdate --config extensions.remotefilelog=! ssh://user@dummy/repo repo-stock-all
+  $ hg log -R repo-stock-all -r 'head()' -T '{desc} {branch}\n' | sort
+  b stable
+  c default
+
+Push a new head of stable, the cached branchmap is updated with it
+  $ hgclone_treemanifest ssh://user@dummy/repo-hg repo-push --noupdate
+  $ cd repo-push
+  $ enableextension remotenames
+  $ hg up -q stable_bookmark
+  $ echo d > d && hg add d && hg ci -m d
+  $ hgmn push -q -r . --to stable_bookmark
+  $ cd $TESTTMP
+  $ hgmn clone -q --noupdate --config extensions.remotefilelog=! ssh://user@dummy/repo repo-stock-stable -b stable
+  $ hg log -R repo-stock-stable -T '{desc} {branch}\n'
+  d stable
+  b stable
+  a default
@@ -0,0 +1,52 @@
+  $ . $TESTDIR/library.sh
+
+setup configuration
+  $ setup_common_config
+  $ cd $TESTTMP
+
+setup repo
+
+  $ hginit_treemanifest repo-hg
+  $ cd repo-hg
+  $ echo a > a && hg add a && hg ci -m a
+  $ hg bookmark master_bookmark -r tip
+
+blobimport
+
+  $ cd $TESTTMP
+  $ blobimport repo-hg/.hg repo
+
+start mononoke with the working copy of the config repo instead of its RocksDb version
+
+  $ mononoke_config_dir
+  $ wait_for_mononoke $TESTTMP/repo
+  $ grep -c "Config will be read from directory: $TESTTMP/mononoke-config" $TESTTMP/mononoke.out
+  1
+
+  $ hgclone_treemanifest ssh://user@dummy/repo-hg repo-pull --noupdate
+  $ cd repo-pull
+  $ hgmn pull -q
+  $ hgmn up -q master_bookmark
+  $ cat a
+  a
+  $ cd $TESTTMP
+
+A missing config directory is a fatal error
+  $ "$MONONOKE_SERVER" --listening-host-port 127.0.0.1:0 --config-dir "$TESTTMP/missing" > missing.out 2>&1
+  [1]
+  $ grep -c "Server fatal error" missing.out
+  1
+
+So is a malformed repo config
+  $ mkdir -p bad-config/repos
+  $ echo "not toml" > bad-config/repos/repo
+  $ "$MONONOKE_SERVER" --listening-host-port 127.0.0.1:0 --config-dir "$TESTTMP/bad-config" > bad.out 2>&1
+  [1]
+  $ grep -c "failed while parsing file" bad.out
+  1
+
+The config directory can't be used together with the config repo
+  $ "$MONONOKE_SERVER" --listening-host-port 127.0.0.1:0 --config-dir "$TESTTMP/mononoke-config" -P "$TESTTMP/mononoke-config-rocks" > both.out 2>&1
+  [1]
+  $ grep -c "cannot be used with" both.out
+  1
@@ -0,0 +1,75 @@
+  $ . $TESTDIR/library.sh
+
+setup configuration
+  $ setup_common_config
+  $ cd $TESTTMP
+
+setup repo
+
+  $ hginit_treemanifest repo-hg
+  $ cd repo-hg
+  $ echo a > a && hg add a && hg ci -m a
+  $ hg bookmark master_bookmark -r tip
+
+blobimport
+
+  $ cd $TESTTMP
+  $ blobimport repo-hg/.hg repo
+
+  $ hgclone_treemanifest ssh://user@dummy/repo-hg repo-push
+  $ cd repo-push
+  $ enableextension remotenames
+
+start mononoke, polling the config directory
+
+  $ mononoke_config_dir --config-poll-interval 1
+  $ wait_for_mononoke $TESTTMP/repo
+
+Without hooks or policies, anything can be pushed
+  $ echo bad > bad && hg add bad && hg ci -m bad
+  $ hgmn push -q -r . --to master_bookmark
+
+Add a hook and make the bookmark fast-forward only while the server is running
+  $ mkdir $TESTTMP/mononoke-config/hooks
+  $ cat > $TESTTMP/mononoke-config/hooks/no_bad_files.lua <<EOF
+  > hook = function (info, files)
+  >   for _, file in ipairs(files) do
+  >     if file == "bad" then
+  >       return false
+  >     end
+  >   end
+  >   return true
+  > end
+  > EOF
+  $ cat >> $TESTTMP/mononoke-config/repos/repo <<EOF
+  > [[bookmarks]]
+  > name="master_bookmark"
+  > only_fast_forward=true
+  > [[bookmarks.hooks]]
+  > hook_name="no_bad_files"
+  > [[hooks]]
+  > name="no_bad_files"
+  > path="hooks/no_bad_files.lua"
+  > EOF
+  $ wait_for_mononoke_log "Config change: hook no_bad_files added"
+  $ grep -c "Config change: bookmark master_bookmark added" $TESTTMP/mononoke.out
+  1
+
+The hook now rejects changes to the bad file
+  $ echo worse > bad && hg ci -m worse
+  $ hgmn push -r . --to master_bookmark 2>&1 | grep -e "^remote: hook" -e "^abort"
+  remote: hook no_bad_files rejected changeset * for bookmark master_bookmark: short desc (glob)
+  abort: push failed on remote
+
+The bookmark can't be moved backwards anymore
+  $ hgmn push -q -r 0 --to master_bookmark --force > /dev/null 2>&1 || true
+  $ grep -c "Bookmark master_bookmark can only be fast-forwarded" $TESTTMP/mononoke.out
+  1
+
+Changes that need a restart are reported until the server is restarted
+  $ sed -i 's/^repoid=0$/repoid=0\ngeneration_cache_size=2048/' $TESTTMP/mononoke-config/repos/repo
+  $ wait_for_mononoke_log "Config change needs a restart: generation_cache_size"
+  $ sed -i 's/^only_fast_forward=true$/only_fast_forward=false/' $TESTTMP/mononoke-config/repos/repo
+  $ wait_for_mononoke_log "Config change: bookmark master_bookmark changed"
+  $ grep -c "Config change needs a restart: generation_cache_size" $TESTTMP/mononoke.out
+  2
@@ -0,0 +1,45 @@
+  $ . $TESTDIR/library.sh
+
+setup configuration
+  $ setup_common_config
+
+setup git repo
+
+  $ export GIT_AUTHOR_NAME=test GIT_AUTHOR_EMAIL=test@example.com
+  $ export GIT_COMMITTER_NAME=test GIT_COMMITTER_EMAIL=test@example.com
+  $ export GIT_AUTHOR_DATE="1000000000 +0000" GIT_COMMITTER_DATE="1000000000 +0000"
+  $ git init -q repo-git
+  $ cd repo-git
+  $ git symbolic-ref HEAD refs/heads/master
+  $ mkdir dir
+  $ echo "a" > a
+  $ echo "b" > dir/b
+  $ git add a dir/b && git commit -q -m a
+  $ echo "a2" > a
+  $ git mv dir/b dir/c
+  $ git commit -q -am b
+  $ cd $TESTTMP
+
+gitimport it and export it to Mercurial
+
+  $ gitimport repo-git/.git repo
+  $ blobexport repo-export repo
+  $ hg verify -R repo-export
+  checking changesets
+  checking manifests
+  checking directory manifests
+  crosschecking files in changesets and manifests
+  checking files
+  3 files, 2 changesets, 4 total revisions
+  $ hg log -R repo-export -T '{desc} {author} {files} {file_copies} {bookmarks}\n'
+  b test <test@example.com> a dir/b dir/c dir/c (dir/b) master
+  a test <test@example.com> a dir/b  
+  $ hg cat -R repo-export -r master a dir/c
+  a2
+  b
+
+importing again leaves the repo unchanged
+
+  $ gitimport repo-git/.git repo
+  $ grep "imported 0 commits" $TESTTMP/gitimport.out | wc -l
+  1
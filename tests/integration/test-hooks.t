@@ -0,0 +1,75 @@
+  $ . $TESTDIR/library.sh
+
+setup configuration
+  $ setup_common_config
+  $ cd $TESTTMP
+
+setup a hook that rejects changesets touching files named "bad" on master_bookmark
+
+  $ mkdir mononoke-config/hooks
+  $ cat > mononoke-config/hooks/no_bad_files.lua <<EOF
+  > hook = function (info, files)
+  >   for _, file in ipairs(files) do
+  >     if file == "bad" then
+  >       return false
+  >     end
+  >   end
+  >   return true
+  > end
+  > EOF
+  $ cat >> mononoke-config/repos/repo <<EOF
+  > [hook_manager_params]
+  > entrylimit=100
+  > [[bookmarks]]
+  > name="master_bookmark"
+  > [[bookmarks.hooks]]
+  > hook_name="no_bad_files"
+  > [[hooks]]
+  > name="no_bad_files"
+  > path="hooks/no_bad_files.lua"
+  > EOF
+
+setup repo
+
+  $ hginit_treemanifest repo-hg
+  $ cd repo-hg
+  $ echo a > a && hg add a && hg ci -m a
+  $ hg bookmark master_bookmark -r tip
+
+blobimport
+
+  $ cd $TESTTMP
+  $ blobimport repo-hg/.hg repo
+
+  $ hgclone_treemanifest ssh://user@dummy/repo-hg repo-push
+  $ cd repo-push
+  $ enableextension remotenames
+
+start mononoke
+
+  $ mononoke_config_dir
+  $ wait_for_mononoke $TESTTMP/repo
+
+A changeset the hook accepts is pushed
+  $ echo good > good && hg add good && hg ci -m good
+  $ hgmn push -q -r . --to master_bookmark
+
+A changeset the hook rejects aborts the push, telling the client which hook rejected what
+  $ echo bad > bad && hg add bad && hg ci -m bad
+  $ hgmn push -r . --to master_bookmark
+  pushing rev * to destination ssh://user@dummy/repo bookmark master_bookmark (glob)
+  searching for changes
+  remote: hook no_bad_files rejected changeset * for bookmark master_bookmark: short desc (glob)
+  remote: (long desc)
+  abort: push failed on remote
+  [255]
+
+Other bookmarks have no hooks
+  $ hgmn push -q -r . --to other_bookmark --create
+
+The rejected changeset did not move master_bookmark
+  $ hgmn pull -q
+  $ hg log -r default/master_bookmark -T '{desc}\n'
+  good
+  $ hg log -r default/other_bookmark -T '{desc}\n'
+  bad
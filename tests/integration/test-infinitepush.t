@@ -51,7 +51,7 @@ Do infinitepush (aka commit cloud) push
   sending hello command
   sending between command
   remote: * (glob)
-  remote: capabilities: lookup known getbundle unbundle=HG10GZ,HG10BZ,HG10UN gettreepack remotefilelog pushkey bundle2=* (glob)
+  remote: capabilities: lookup branchmap known getbundle unbundle=HG10GZ,HG10BZ,HG10UN gettreepack remotefilelog pushkey bundle2=* (glob)
   remote: 1
   query 1; heads
   sending batch command
@@ -104,7 +104,7 @@ Pushbackup also works
   sending hello command
   sending between command
   remote: * (glob)
-  remote: capabilities: lookup known getbundle unbundle=HG10GZ,HG10BZ,HG10UN gettreepack remotefilelog pushkey bundle2=* (glob)
+  remote: capabilities: lookup branchmap known getbundle unbundle=HG10GZ,HG10BZ,HG10UN gettreepack remotefilelog pushkey bundle2=* (glob)
   remote: 1
   2 changesets found
   list of changesets:
@@ -147,7 +147,7 @@ Pushbackup that pushes only bookmarks
   sending hello command
   sending between command
   remote: * (glob)
-  remote: capabilities: lookup known getbundle unbundle=HG10GZ,HG10BZ,HG10UN gettreepack remotefilelog pushkey bundle2=* (glob)
+  remote: capabilities: lookup branchmap known getbundle unbundle=HG10GZ,HG10BZ,HG10UN gettreepack remotefilelog pushkey bundle2=* (glob)
   remote: 1
   sending unbundle command
   bundle2-output-bundle: "HG20", (1 params) 2 parts total
@@ -157,3 +157,22 @@ Pushbackup that pushes only bookmarks
   heads added: 
   heads removed: 
   finished in * seconds (glob)
+
+Push to a scratch bookmark and pull it by name
+  $ cat >> .hg/hgrc <<EOF
+  > branchpattern=re:scratch/.+
+  > EOF
+  $ echo scratch > scratchfile && hg addremove -q && hg ci -q -m scratch
+  $ hgmn push -q ssh://user@dummy/repo -r . --to scratch/mybook --create
+
+  $ cd ../repo-pull
+  $ cat >> .hg/hgrc <<EOF
+  > [extensions]
+  > infinitepush=
+  > [infinitepush]
+  > server=False
+  > branchpattern=re:scratch/.+
+  > EOF
+  $ hgmn pull -q -B scratch/mybook
+  $ hg log -r scratch/mybook -T '{desc}\n'
+  scratch
@@ -0,0 +1,69 @@
+  $ . $TESTDIR/library.sh
+
+setup configuration, contents of files larger than 10 bytes are stored in the LFS blobstore, and
+getfiles sends the pointers to them instead of them
+
+  $ export LFS_THRESHOLD=10
+  $ export LFS_SEND_POINTERS=1
+  $ setup_common_config
+
+  $ cd $TESTTMP
+
+init repo-hg
+
+  $ hginit_treemanifest repo-hg
+
+setup repo2 and repo3
+
+  $ hgclone_treemanifest ssh://user@dummy/repo-hg repo2
+  $ hgclone_treemanifest ssh://user@dummy/repo-hg repo3
+
+setup repo-hg
+
+  $ cd repo-hg
+  $ echo small > small
+  $ hg add small
+  $ hg ci -msmall
+  $ hg bookmark master_bookmark -r tip
+
+blobimport and start mononoke
+
+  $ cd $TESTTMP
+  $ blobimport repo-hg/.hg repo
+  $ mononoke
+  $ wait_for_mononoke $TESTTMP/repo
+
+push a large file from repo2, which doesn't have the lfs extension
+
+  $ cd $TESTTMP/repo2
+  $ hgmn pull -q
+  $ hgmn update -q -r master_bookmark
+  $ printf "large file contents\n" > large
+  $ hg add large
+  $ hg ci -mlarge
+  $ hgmn push -q
+
+repo3 has the lfs extension, and gets the pointer to the large file
+
+  $ cd $TESTTMP/repo3
+  $ enableextension lfs
+  $ hgmn pull -q
+  $ hgmn prefetch -r master_bookmark
+
+stop mononoke, as the api server opens the same blobstore, and start the api server
+
+  $ killdaemons.py $DAEMON_PIDS
+  $ apiserver -p 0
+  $ for i in $(seq 1 40); do
+  > PORT=$(cat $TESTTMP/apiserver.out | grep "Listening to" | grep -Pzo "(\\d+)\$") && break
+  > sleep 0.1
+  > done
+
+the contents the pointer points to are fetched from the api server
+
+  $ hgmn update -q -r master_bookmark --config lfs.url=http://127.0.0.1:$PORT/repo/lfs
+  $ cat small large
+  small
+  large file contents
+  $ ls .hg/store/lfs/objects/37
+  2920552fbf4fa7c97393b749bf061342eb1c1d13d743aca3f499794b7ef9b2
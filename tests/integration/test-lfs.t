@@ -0,0 +1,56 @@
+  $ . $TESTDIR/library.sh
+
+setup configuration, contents of files larger than 10 bytes are stored in the LFS blobstore
+
+  $ export LFS_THRESHOLD=10
+  $ setup_common_config
+
+  $ cd $TESTTMP
+
+init repo-hg
+
+  $ hginit_treemanifest repo-hg
+
+setup repo2 and repo3
+
+  $ hgclone_treemanifest ssh://user@dummy/repo-hg repo2
+  $ hgclone_treemanifest ssh://user@dummy/repo-hg repo3
+
+setup repo-hg
+
+  $ cd repo-hg
+  $ echo small > small
+  $ hg add small
+  $ hg ci -msmall
+  $ hg bookmark master_bookmark -r tip
+
+blobimport and start mononoke
+
+  $ cd $TESTTMP
+  $ blobimport repo-hg/.hg repo
+  $ mononoke
+  $ wait_for_mononoke $TESTTMP/repo
+
+push a small and a large file from repo2
+
+  $ cd $TESTTMP/repo2
+  $ hgmn pull -q
+  $ hgmn update -q -r master_bookmark
+  $ echo small2 > small2
+  $ printf "large file contents\n" > large
+  $ hg add small2 large
+  $ hg ci -mlarge
+  $ hgmn push -q
+
+pull on repo3, the large file is read back from the LFS blobstore
+
+  $ cd $TESTTMP/repo3
+  $ hgmn pull -q
+  $ hgmn log -T '{desc}\n'
+  large
+  small
+  $ hgmn update -q -r master_bookmark
+  $ cat small small2 large
+  small
+  small2
+  large file contents
@@ -0,0 +1,40 @@
+  $ . $TESTDIR/library.sh
+
+setup configuration
+  $ export PUBLISHING_BOOKMARK="master_bookmark"
+  $ setup_common_config
+  $ cd $TESTTMP
+
+setup repo
+
+  $ hginit_treemanifest repo-hg
+  $ cd repo-hg
+  $ echo a > a && hg add a && hg ci -m a
+  $ echo b > b && hg add b && hg ci -m b
+
+create bookmarks, only the first commit is reachable from the publishing one
+
+  $ hg bookmark master_bookmark -r 0
+  $ hg bookmark feature_bookmark -r 1
+
+  $ cd $TESTTMP
+
+setup repo-pull with just the public commit
+
+  $ hgclone_treemanifest ssh://user@dummy/repo-hg repo-pull --noupdate -r master_bookmark
+
+blobimport
+
+  $ blobimport repo-hg/.hg repo
+
+start mononoke
+
+  $ mononoke
+  $ wait_for_mononoke $TESTTMP/repo
+
+Commits that are not ancestors of the publishing bookmark are pulled as draft
+  $ cd repo-pull
+  $ hgmn pull -q
+  $ hg log -T '{desc} {phase}\n'
+  b draft
+  a public
@@ -231,7 +231,7 @@ push to Mononoke
   sending hello command
   sending between command
   remote: * (glob)
-  remote: capabilities: lookup known getbundle unbundle=HG10GZ,HG10BZ,HG10UN gettreepack remotefilelog pushkey bundle2=* (glob)
+  remote: capabilities: lookup branchmap known getbundle unbundle=HG10GZ,HG10BZ,HG10UN gettreepack remotefilelog pushkey bundle2=* (glob)
   remote: 1
   query 1; heads
   sending batch command
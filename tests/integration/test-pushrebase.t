@@ -0,0 +1,78 @@
+  $ . $TESTDIR/library.sh
+
+setup configuration
+
+  $ setup_common_config
+  $ cd $TESTTMP
+
+setup repo
+
+  $ hginit_treemanifest repo-hg
+  $ cd repo-hg
+  $ echo a > a && hg add a && hg ci -m a
+  $ hg bookmark master_bookmark -r tip
+
+blobimport
+
+  $ cd $TESTTMP
+  $ blobimport repo-hg/.hg repo
+
+setup two repos to push from, both based on the same commit, and one to pull into
+
+  $ hgclone_treemanifest ssh://user@dummy/repo-hg repo-push1
+  $ hgclone_treemanifest ssh://user@dummy/repo-hg repo-push2
+  $ hgclone_treemanifest ssh://user@dummy/repo-hg repo-pull
+  $ for repo in repo-push1 repo-push2 repo-pull; do
+  >   cd $TESTTMP/$repo
+  >   enableextension pushrebase
+  >   enableextension remotenames
+  > done
+
+start mononoke
+
+  $ cd $TESTTMP
+  $ mononoke
+  $ wait_for_mononoke $TESTTMP/repo
+
+The first push is based on the bookmark, so nothing is rebased
+  $ cd $TESTTMP/repo-push1
+  $ echo b > b && hg add b && hg ci -m b
+  $ hgmn push -q -r . --to master_bookmark
+
+The second push is based on an older commit, and doesn't touch the files of the first one, so
+it's rebased on top of it
+  $ cd $TESTTMP/repo-push2
+  $ echo c > c && hg add c && hg ci -m c
+  $ hgmn push -q -r . --to master_bookmark
+
+  $ cd $TESTTMP/repo-pull
+  $ hgmn pull -q
+  $ hgmn up -q default/master_bookmark
+  $ hg log -G -T '{desc}\n'
+  @  c
+  |
+  o  b
+  |
+  o  a
+
+  $ hg log -r default/master_bookmark -T '{extras}\n' | grep -o 'rebase_source=[0-9a-f]*' | wc -l | tr -d ' '
+  1
+  $ cat a b c
+  a
+  b
+  c
+
+The third push modifies a file that was added on the server after its base, so it conflicts
+  $ cd $TESTTMP/repo-push2
+  $ hg up -q 0
+  $ echo conflict > b && hg add b && hg ci -q -m conflict
+  $ hgmn push -q -r . --to master_bookmark > /dev/null 2>&1
+  [255]
+  $ grep -o 'Pushrebase conflicts, files modified on the server: .*"b")\]' $TESTTMP/mononoke.out | wc -l | tr -d ' '
+  1
+
+The bookmark didn't move
+  $ cd $TESTTMP/repo-pull
+  $ hgmn pull -q
+  $ hg log -r default/master_bookmark -T '{desc}\n'
+  c
@@ -0,0 +1,61 @@
+  $ . $TESTDIR/library.sh
+
+setup configuration
+  $ setup_common_config
+  $ cd $TESTTMP
+
+setup repo
+
+  $ hginit_treemanifest repo-hg
+  $ cd repo-hg
+  $ echo a > a && hg add a && hg ci -m a
+  $ echo b > b && hg add b && hg ci -m b
+  $ hg bookmark master_bookmark -r tip
+
+  $ cd $TESTTMP
+
+blobimport and snapshot the revlogs
+
+  $ blobimport repo-hg/.hg repo --streaming-clone
+  $ grep -c "updated the streaming clone snapshot" $TESTTMP/blobimport.out
+  1
+
+start mononoke
+
+  $ mononoke
+  $ wait_for_mononoke $TESTTMP/repo
+
+The snapshot has no filelogs, so stock clients don't stream it
+  $ hgmn clone --uncompressed --noupdate --config extensions.remotefilelog=! ssh://user@dummy/repo repo-stock 2>&1 | grep -v "^(see"
+  warning: stream clone requested but client is missing requirements: remotefilelog
+  requesting all changes
+  adding changesets
+  adding manifests
+  adding file changes
+  added 2 changesets with 2 changes to 2 files
+  $ hg log -R repo-stock -T '{desc}\n'
+  b
+  a
+  $ grep -c "stream_out" $TESTTMP/mononoke.out
+  [1]
+
+Stream clone with stream_out
+  $ hgmn clone -q --shallow --uncompressed --noupdate ssh://user@dummy/repo repo-stream
+  $ hg log -R repo-stream -T '{desc}\n'
+  b
+  a
+  $ grep -q remotefilelog repo-stream/.hg/requires
+  $ grep -c "stream_out" $TESTTMP/mononoke.out
+  1
+  $ hgmn up -q -R repo-stream master_bookmark
+  $ cat repo-stream/a repo-stream/b
+  a
+  b
+
+Stream clone with getbundle stream=1
+  $ hgmn clone -q --shallow --uncompressed --noupdate --config experimental.bundle2.stream=True ssh://user@dummy/repo repo-stream2
+  $ hg log -R repo-stream2 -T '{desc}\n'
+  b
+  a
+  $ grep -c "streaming [0-9]* files" $TESTTMP/mononoke.out
+  1
@@ -33,4 +33,7 @@ pub enum ErrorKind {
     /// One of the paths in entries listed by manifest contained an invalid (f.e. empty) Path
     #[fail(display = "manifest contained an invalid path: {}", _0)]
     ManifestInvalidPath(String),
+    /// A path on the local file system that can't be represented in the Vfs
+    #[fail(display = "invalid path on the file system: {}", _0)]
+    FsInvalidPath(String),
 }
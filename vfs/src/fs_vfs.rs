@@ -0,0 +1,223 @@
+// Copyright (c) 2018-present, Facebook, Inc.
+// All Rights Reserved.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2 or any later version.
+
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::{future, Future};
+
+use mercurial_types::manifest::Content;
+use mononoke_types::FileContents;
+use mononoke_types::path::{MPathElement, DOT, DOTDOT};
+
+use node::{VfsDir, VfsFile, VfsNode};
+use tree::{TNodeId, Tree, TreeValue, INCONSISTENCY, ROOT_ID};
+
+use errors::*;
+
+/// For a given directory on the local file system return a VfsDir representing it. The directory
+/// is listed recursively when this is called, the content of the files is read when requested.
+pub fn vfs_from_directory<P: AsRef<Path>>(path: P) -> Result<FsVfsDir> {
+    let mut path_tree = Tree::new();
+    insert_directory(&mut path_tree, &mut vec![], path.as_ref())?;
+    Ok(FsVfsDir {
+        root: Arc::new(FsVfsRoot { path_tree }),
+        nodeid: ROOT_ID,
+    })
+}
+
+fn insert_directory(
+    path_tree: &mut Tree<MPathElement, PathBuf>,
+    prefix: &mut Vec<MPathElement>,
+    dir: &Path,
+) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = match path.file_name() {
+            Some(name) => MPathElement::new(name.as_bytes().to_vec())?,
+            None => bail_err!(ErrorKind::FsInvalidPath(format!("{}", path.display()))),
+        };
+        if path.is_dir() {
+            prefix.push(name);
+            insert_directory(path_tree, prefix, &path)?;
+            prefix.pop();
+        } else {
+            path_tree.insert(prefix.iter().cloned(), name, path)?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+struct FsVfsRoot {
+    path_tree: Tree<MPathElement, PathBuf>,
+}
+
+impl FsVfsRoot {
+    fn get_node(this: &Arc<Self>, nodeid: TNodeId) -> VfsNode<FsVfsDir, FsVfsFile> {
+        match this.path_tree.get_value(nodeid).expect(INCONSISTENCY) {
+            &TreeValue::Leaf(_) => VfsNode::File(FsVfsFile {
+                root: this.clone(),
+                nodeid,
+            }),
+            &TreeValue::Node(_) => VfsNode::Dir(FsVfsDir {
+                root: this.clone(),
+                nodeid,
+            }),
+        }
+    }
+}
+
+/// Structure implementing the VfsDir interface that represents a dir on the local file system
+#[derive(Debug)]
+pub struct FsVfsDir {
+    root: Arc<FsVfsRoot>,
+    nodeid: TNodeId,
+}
+
+impl Clone for FsVfsDir {
+    fn clone(&self) -> Self {
+        FsVfsDir {
+            root: self.root.clone(),
+            nodeid: self.nodeid,
+        }
+    }
+}
+
+impl VfsDir for FsVfsDir {
+    type TFile = FsVfsFile;
+
+    fn read(&self) -> Vec<&MPathElement> {
+        self.root
+            .path_tree
+            .get_value(self.nodeid)
+            .expect(INCONSISTENCY)
+            .get_node()
+            .expect("Expected an internal node, not a leaf")
+            .keys()
+            .collect()
+    }
+
+    fn step(&self, path: &MPathElement) -> Option<VfsNode<Self, Self::TFile>> {
+        if path == &*DOT {
+            return Some(VfsNode::Dir(self.clone()));
+        }
+
+        let tree = &self.root.path_tree;
+        let nodeid = if path == &*DOTDOT {
+            tree.get_parent(self.nodeid)
+        } else {
+            tree.get_child(self.nodeid, path)
+        };
+        nodeid.map(|nodeid| FsVfsRoot::get_node(&self.root, nodeid))
+    }
+}
+
+/// Structure implementing the VfsFile interface that represents a file on the local file system
+#[derive(Debug)]
+pub struct FsVfsFile {
+    root: Arc<FsVfsRoot>,
+    nodeid: TNodeId,
+}
+
+impl Clone for FsVfsFile {
+    fn clone(&self) -> Self {
+        FsVfsFile {
+            root: self.root.clone(),
+            nodeid: self.nodeid,
+        }
+    }
+}
+
+impl VfsFile for FsVfsFile {
+    type TDir = FsVfsDir;
+
+    fn read(&self) -> Box<Future<Item = Content, Error = Error> + Send> {
+        let path = self.root
+            .path_tree
+            .get_value(self.nodeid)
+            .expect(INCONSISTENCY)
+            .get_leaf()
+            .expect("Expected a leaf, not an internal node");
+        let content = fs::read(path)
+            .map(|content| Content::File(FileContents::Bytes(Bytes::from(content))))
+            .map_err(Error::from);
+        Box::new(future::result(content))
+    }
+
+    fn parent_dir(&self) -> Self::TDir {
+        let parentid = self.root
+            .path_tree
+            .get_parent(self.nodeid)
+            .expect("No parent node found for a file");
+        match FsVfsRoot::get_node(&self.root, parentid) {
+            VfsNode::Dir(vfs) => vfs,
+            _ => panic!("Parent of a file is not a dir"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test::*;
+
+    use std::fs::File;
+    use std::io::Write;
+
+    use tempdir::TempDir;
+
+    use mercurial_types::MPath;
+    use node::VfsWalker;
+
+    fn example_dir() -> TempDir {
+        let tmp = TempDir::new("fs_vfs").expect("failed to create temp dir");
+        for (path, content) in vec![("a/b", "b"), ("a/ab", "ab"), ("c/d/e", "e"), ("f", "f")] {
+            let path = tmp.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).expect("failed to create dir");
+            File::create(path)
+                .and_then(|mut file| file.write_all(content.as_bytes()))
+                .expect("failed to write file");
+        }
+        tmp
+    }
+
+    #[test]
+    fn test_dir() {
+        let tmp = example_dir();
+        let vfs = vfs_from_directory(tmp.path()).expect("failed to get vfs");
+        cmp(vfs.read(), vec!["a", "c", "f"]);
+
+        let dir_a = match vfs.step(&pel("a")).unwrap() {
+            VfsNode::Dir(dir) => dir,
+            _ => panic!("Expected dir, found file"),
+        };
+        cmp(dir_a.read(), vec!["ab", "b"]);
+    }
+
+    #[test]
+    fn test_read_file() {
+        let tmp = example_dir();
+        let vfs = VfsNode::Dir(vfs_from_directory(tmp.path()).expect("failed to get vfs"));
+
+        let file = match VfsWalker::new(vfs, MPath::new("c/d/e").unwrap())
+            .walk()
+            .wait()
+            .unwrap()
+        {
+            VfsNode::File(file) => file,
+            _ => panic!("Expected file, found dir"),
+        };
+        match file.read().wait().unwrap() {
+            Content::File(FileContents::Bytes(bytes)) => assert_eq!(bytes, Bytes::from("e")),
+            _ => panic!("Expected the content of a file"),
+        }
+        cmp(file.parent_dir().read(), vec!["e"]);
+    }
+}
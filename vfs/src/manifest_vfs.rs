@@ -15,12 +15,10 @@ use mercurial_types::manifest_utils::recursive_entry_stream;
 use mononoke_types::path::{MPath, MPathElement, DOT, DOTDOT};
 
 use node::{VfsDir, VfsFile, VfsNode};
-use tree::{TNodeId, Tree, TreeValue, ROOT_ID};
+use tree::{TNodeId, Tree, TreeValue, INCONSISTENCY, ROOT_ID};
 
 use errors::*;
 
-const INCONCISTENCY: &str = "Internal inconsitency in Tree detected, a nodeid is missing";
-
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 struct TEntryId(usize);
 
@@ -63,7 +61,7 @@ struct ManifestVfsRoot {
 
 impl ManifestVfsRoot {
     fn get_node(this: &Arc<Self>, nodeid: TNodeId) -> VfsNode<ManifestVfsDir, ManifestVfsFile> {
-        match this.path_tree.get_value(nodeid).expect(INCONCISTENCY) {
+        match this.path_tree.get_value(nodeid).expect(INCONSISTENCY) {
             &TreeValue::Leaf(_) => VfsNode::File(ManifestVfsFile {
                 root: this.clone(),
                 nodeid,
@@ -110,7 +108,7 @@ impl VfsDir for ManifestVfsDir {
         self.root
             .path_tree
             .get_value(self.nodeid)
-            .expect(INCONCISTENCY)
+            .expect(INCONSISTENCY)
             .get_node()
             .expect("Expected an internal node, not a leaf")
             .keys()
@@ -155,7 +153,7 @@ impl VfsFile for ManifestVfsFile {
         let &TEntryId(entryid) = self.root
             .path_tree
             .get_value(self.nodeid)
-            .expect(INCONCISTENCY)
+            .expect(INCONSISTENCY)
             .get_leaf()
             .expect("Expected a leaf, not an internal node");
         self.root
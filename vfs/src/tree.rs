@@ -24,6 +24,9 @@ use itertools::Itertools;
 pub struct TNodeId(usize);
 pub static ROOT_ID: TNodeId = TNodeId(0);
 
+/// The message of the panics on nodeids that are missing from a tree
+pub const INCONSISTENCY: &str = "Internal inconsistency in Tree detected, a nodeid is missing";
+
 pub enum TreeValue<K, V> {
     Leaf(V),
     Node(HashMap<K, TNodeId>),